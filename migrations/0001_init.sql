@@ -33,6 +33,94 @@ CREATE TABLE IF NOT EXISTS camera_state (
   FOREIGN KEY(camera_id) REFERENCES cameras(id) ON DELETE CASCADE
 );
 
+----------------------------------------------------------------------
+-- Runtime sink on/off overrides (set via the control API).
+-- No row -> the `enabled` flag from config.toml applies.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS sink_overrides (
+  camera_id      INTEGER NOT NULL,
+  sink_id        INTEGER NOT NULL,
+  enabled        INTEGER NOT NULL,   -- 0=false, 1=true
+  updated_at_utc INTEGER NOT NULL,
+  PRIMARY KEY (camera_id, sink_id),
+  FOREIGN KEY(camera_id) REFERENCES cameras(id) ON DELETE CASCADE
+);
+
+----------------------------------------------------------------------
+-- Camera events (G-sensor, motion, manual, object detection).
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS events (
+  id         INTEGER PRIMARY KEY AUTOINCREMENT,
+  camera_id  INTEGER NOT NULL,
+  kind       TEXT    NOT NULL,   -- "gsensor", "motion", "manual", "object"
+  label      TEXT,               -- detected class for "object" events ("person", "car")
+  score      REAL,               -- detector confidence, 0..1
+  at_utc     INTEGER NOT NULL,
+  FOREIGN KEY(camera_id) REFERENCES cameras(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_events_camera_time
+  ON events(camera_id, at_utc);
+
+----------------------------------------------------------------------
+-- Incidents: an event on one camera of a `[[clips.groups]]` group cut
+-- into clips of the same window on every camera of the group, saved
+-- together in `dir`.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS incidents (
+  id         INTEGER PRIMARY KEY AUTOINCREMENT,
+  group_name TEXT    NOT NULL,
+  camera_id  INTEGER,            -- camera whose event started it
+  kind       TEXT    NOT NULL,   -- kind of that event
+  at_utc     INTEGER NOT NULL,
+  dir        TEXT    NOT NULL,   -- incident folder under recording_save_dir
+  FOREIGN KEY(camera_id) REFERENCES cameras(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_incidents_time
+  ON incidents(at_utc);
+
+CREATE TABLE IF NOT EXISTS incident_clips (
+  incident_id INTEGER NOT NULL,
+  camera_id   INTEGER NOT NULL,
+  path        TEXT    NOT NULL,
+  from_utc    INTEGER NOT NULL,
+  to_utc      INTEGER NOT NULL,
+  PRIMARY KEY (incident_id, camera_id),
+  FOREIGN KEY(incident_id) REFERENCES incidents(id) ON DELETE CASCADE,
+  FOREIGN KEY(camera_id) REFERENCES cameras(id) ON DELETE CASCADE
+);
+
+----------------------------------------------------------------------
+-- Audit log of control-plane commands (who did what, when, and how it went).
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS audit_log (
+  id         INTEGER PRIMARY KEY AUTOINCREMENT,
+  at_utc     INTEGER NOT NULL,
+  actor      TEXT    NOT NULL,   -- token name, "socket", "local", ...
+  action     TEXT    NOT NULL,   -- e.g. "set_sink_enabled"
+  target     TEXT,               -- e.g. "cam_front/sink 2"
+  ok         INTEGER NOT NULL,   -- 0=false, 1=true
+  result     TEXT                -- error message when ok = 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_time
+  ON audit_log(at_utc);
+
+----------------------------------------------------------------------
+-- Runtime settings (KV), changed through the control API and kept
+-- across restarts. Values are text; callers parse them.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS settings (
+  key            TEXT PRIMARY KEY,   -- e.g. "overlay.enabled", "motion.sensitivity"
+  value          TEXT    NOT NULL,
+  updated_at_utc INTEGER NOT NULL
+);
 
 ----------------------------------------------------------------------
 -- Generic counters (KV) for other global integer settings if needed.
@@ -106,6 +194,7 @@ CREATE TABLE IF NOT EXISTS saved_trips (
 CREATE TABLE IF NOT EXISTS segments (
   id              INTEGER PRIMARY KEY,
   camera_id       INTEGER NOT NULL,
+  sink_id         INTEGER NOT NULL DEFAULT 0,
   segment_index   INTEGER NOT NULL,    -- ring index at creation
   segment_gen     INTEGER NOT NULL,    -- generation at creation
   absolute_index  INTEGER NOT NULL,    -- copy of camera_state.absolute_segments
@@ -117,6 +206,11 @@ CREATE TABLE IF NOT EXISTS segments (
   height          INTEGER,
   fps             REAL,
   bytes           INTEGER,
+  clock_offset_ms INTEGER,             -- largest clock correction covering it (see clock_corrections)
+  thumbnail_path  TEXT,                -- JPEG of the first frame, NULL until the backfill worker made it
+  checksum        TEXT,                -- CRC-32 of the file (hex), NULL until the backfill worker read it
+  restarted       INTEGER NOT NULL DEFAULT 0,  -- 1: first segment after the pipeline (re)started
+  disk            TEXT,                -- mount point, for an NvrTs sink spread over disks (rel_path is then absolute)
 
   FOREIGN KEY(camera_id) REFERENCES cameras(id) ON DELETE CASCADE
 );
@@ -126,3 +220,153 @@ CREATE INDEX IF NOT EXISTS idx_segments_camera_time
 
 CREATE INDEX IF NOT EXISTS idx_segments_camera_abs
   ON segments(camera_id, absolute_index);
+
+----------------------------------------------------------------------
+-- Read-only sinks holding imported footage (e.g. the old single-camera
+-- output_N.ts layout). Their files are listed in `segments` under the
+-- same (camera_id, sink_id); nothing records into them.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS historical_sinks (
+  camera_id       INTEGER NOT NULL,
+  sink_id         INTEGER NOT NULL,
+  source_dir      TEXT    NOT NULL,
+  imported_at_utc INTEGER NOT NULL,
+  PRIMARY KEY (camera_id, sink_id),
+  FOREIGN KEY(camera_id) REFERENCES cameras(id) ON DELETE CASCADE
+);
+
+----------------------------------------------------------------------
+-- Ring segments saved from being overwritten ("save this moment", see
+-- protect.rs): a hard link or copy of each under the save dir. segment_id
+-- stops matching once the ring rewrites the file; the saved file stays.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS protected_segments (
+  id            INTEGER PRIMARY KEY AUTOINCREMENT,
+  camera_id     INTEGER NOT NULL,
+  sink_id       INTEGER NOT NULL,
+  segment_id    INTEGER NOT NULL UNIQUE,  -- segments.id when it was saved
+  start_utc     INTEGER NOT NULL,
+  end_utc       INTEGER NOT NULL,
+  source_path   TEXT    NOT NULL,         -- ring file, relative to the recording root
+  saved_path    TEXT    NOT NULL,         -- the link or copy
+  kind          TEXT    NOT NULL,         -- event kind it was saved for
+  saved_at_utc  INTEGER NOT NULL,
+  FOREIGN KEY(camera_id) REFERENCES cameras(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_protected_segments_camera_time
+  ON protected_segments(camera_id, start_utc);
+
+----------------------------------------------------------------------
+-- Catalogued segments whose footage is gone, and why. A stretch with a
+-- tombstone was recorded and aged out; one without was never recorded.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS segment_tombstones (
+  id             INTEGER PRIMARY KEY AUTOINCREMENT,
+  camera_id      INTEGER NOT NULL,
+  sink_id        INTEGER NOT NULL,
+  segment_index  INTEGER NOT NULL,
+  segment_gen    INTEGER NOT NULL,
+  start_utc      INTEGER NOT NULL,
+  end_utc        INTEGER NOT NULL,
+  rel_path       TEXT    NOT NULL,
+  reason         TEXT    NOT NULL,   -- "overwritten" (the ring came round) or "janitor"
+  deleted_at_utc INTEGER NOT NULL,
+  FOREIGN KEY(camera_id) REFERENCES cameras(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_segment_tombstones_camera_time
+  ON segment_tombstones(camera_id, start_utc, end_utc);
+
+----------------------------------------------------------------------
+-- Wall clock steps (NTP/GPS setting the clock after boot). Timestamps
+-- from since_utc to at_utc were stamped with the old clock and are off
+-- by up to offset_ms.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS clock_corrections (
+  id         INTEGER PRIMARY KEY AUTOINCREMENT,
+  at_utc     INTEGER NOT NULL,   -- on the old clock
+  offset_ms  INTEGER NOT NULL,   -- new minus old clock
+  since_utc  INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_clock_corrections_time
+  ON clock_corrections(since_utc, at_utc);
+
+----------------------------------------------------------------------
+-- GPS fixes of the vehicle (not per camera), for tracks and exports.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS gps_points (
+  id          INTEGER PRIMARY KEY AUTOINCREMENT,
+  at_utc      INTEGER NOT NULL,
+  lat         REAL    NOT NULL,   -- WGS84 degrees
+  lon         REAL    NOT NULL,
+  alt_m       REAL,
+  speed_mps   REAL,
+  heading_deg REAL
+);
+
+CREATE INDEX IF NOT EXISTS idx_gps_points_time
+  ON gps_points(at_utc);
+
+----------------------------------------------------------------------
+-- Vehicle telemetry (OBD-II), on the segments' clock.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS telemetry (
+  id           INTEGER PRIMARY KEY AUTOINCREMENT,
+  at_utc       INTEGER NOT NULL,
+  source       TEXT    NOT NULL DEFAULT 'obd',
+  speed_kmh    REAL,
+  rpm          REAL,
+  throttle_pct REAL
+);
+
+CREATE INDEX IF NOT EXISTS idx_telemetry_time
+  ON telemetry(at_utc);
+
+----------------------------------------------------------------------
+-- S.M.A.R.T. samples of the recording drives ([smart]), for trends.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS drive_health (
+  id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+  at_utc              INTEGER NOT NULL,
+  device              TEXT    NOT NULL,   -- e.g. /dev/sda
+  passed              INTEGER,            -- overall self-assessment; NULL if not reported
+  temperature_c       REAL,
+  reallocated_sectors INTEGER,            -- NVMe: media errors
+  pending_sectors     INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_drive_health_device
+  ON drive_health(device, at_utc);
+
+----------------------------------------------------------------------
+-- [[rules]] state, so restarts keep cooldowns and armed/disarmed.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS rule_state (
+  name           TEXT PRIMARY KEY,     -- [[rules]] name
+  armed          INTEGER NOT NULL DEFAULT 1,
+  fire_count     INTEGER NOT NULL DEFAULT 0,
+  last_fired_utc INTEGER,
+  fired_on       TEXT                  -- local day a time trigger last fired, YYYY-MM-DD
+);
+
+----------------------------------------------------------------------
+-- [[modes]] switches (home/away/night), newest last; the last one is
+-- restored on startup.
+----------------------------------------------------------------------
+
+CREATE TABLE IF NOT EXISTS mode_history (
+  id     INTEGER PRIMARY KEY AUTOINCREMENT,
+  at_utc INTEGER NOT NULL,
+  mode   TEXT    NOT NULL,
+  actor  TEXT    NOT NULL          -- who switched: token name, "mqtt", "rule:<name>"
+);
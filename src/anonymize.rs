@@ -0,0 +1,161 @@
+//! Face/plate blurring for exports that get shared publicly (`anonymize` feature).
+//!
+//! Every decoded frame is scaled down to the model's input size, run through a
+//! YOLO model trained on the labels to hide (faces, licence plates), and each
+//! box it finds is pixelated in place before the frame is re-encoded. The
+//! model comes from `[export.anonymize]`; which labels to hide is picked per
+//! export with `export --blur`.
+
+use anyhow::Result;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AnonymizeConfig {
+    /// YOLOv8-style ONNX model, output `[1, 4 + classes, anchors]`
+    pub model_path: String,
+    /// Class names in model output order; unset = COCO
+    pub labels: Option<Vec<String>>,
+    #[serde(default = "default_min_score")]
+    pub min_score: f32,
+    /// Square model input, frames are scaled to this
+    #[serde(default = "default_input_size")]
+    pub input_size: u32,
+    /// Mosaic block size in pixels of the exported frame
+    #[serde(default = "default_block_px")]
+    pub block_px: u32,
+}
+
+fn default_min_score() -> f32 {
+    // missing a face is worse than blurring a bit of background
+    0.3
+}
+
+fn default_input_size() -> u32 {
+    640
+}
+
+fn default_block_px() -> u32 {
+    16
+}
+
+/// One export's blur pass: the configured model and the labels to hide.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlurPass {
+    pub cfg: AnonymizeConfig,
+    pub labels: Vec<String>,
+}
+
+/// Nearest-neighbour scale of a packed RGB frame to `size`x`size`, rows unpadded.
+/// The aspect ratio isn't kept, so normalized boxes map straight back.
+pub fn sample_square(rgb: &[u8], width: usize, height: usize, stride: usize, size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(size * size * 3);
+    for y in 0..size {
+        let row = (y * height / size) * stride;
+        for x in 0..size {
+            let at = row + (x * width / size) * 3;
+            out.extend_from_slice(&rgb[at..at + 3]);
+        }
+    }
+    out
+}
+
+/// Replace the normalized `bbox` (x0, y0, x1, y1) of a packed RGB frame with
+/// `block`-pixel squares of one colour each, the top-left pixel of the square.
+pub fn pixelate(rgb: &mut [u8], width: usize, height: usize, stride: usize, bbox: [f32; 4], block: usize) {
+    let block = block.max(1);
+    let to_px = |v: f32, max: usize| ((v.clamp(0.0, 1.0) * max as f32) as usize).min(max);
+    let (x0, x1) = (to_px(bbox[0], width), to_px(bbox[2], width));
+    let (y0, y1) = (to_px(bbox[1], height), to_px(bbox[3], height));
+    for by in (y0..y1).step_by(block) {
+        for bx in (x0..x1).step_by(block) {
+            let at = by * stride + bx * 3;
+            let colour = [rgb[at], rgb[at + 1], rgb[at + 2]];
+            for y in by..(by + block).min(y1) {
+                for x in bx..(bx + block).min(x1) {
+                    let at = y * stride + x * 3;
+                    rgb[at..at + 3].copy_from_slice(&colour);
+                }
+            }
+        }
+    }
+}
+
+/// Blur every frame passing through `element` (an `identity` on RGB caps).
+#[cfg(feature = "anonymize")]
+pub fn attach(element: &gstreamer::Element, pass: &BlurPass) -> Result<()> {
+    use anyhow::Context;
+    use gstreamer as gst;
+    use gstreamer::prelude::*;
+    use tracing::{info, warn};
+
+    use crate::detection::{COCO_LABELS, YoloModel, decode_yolo};
+
+    let model = YoloModel::load(&pass.cfg.model_path, pass.cfg.input_size)?;
+    let labels = match &pass.cfg.labels {
+        Some(labels) => labels.clone(),
+        None => COCO_LABELS.iter().map(|l| l.to_string()).collect(),
+    };
+    if let Some(unknown) = pass.labels.iter().find(|l| !labels.contains(l)) {
+        anyhow::bail!("{} has no '{}' class, it knows {:?}", pass.cfg.model_path, unknown, labels);
+    }
+    info!("Blurring {:?} with {}", pass.labels, pass.cfg.model_path);
+
+    let pass = pass.clone();
+    let size = pass.cfg.input_size as usize;
+    element
+        .static_pad("sink")
+        .context("Blur element has no sink pad")?
+        .add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+            let dims = pad.current_caps().and_then(|caps| {
+                let structure = caps.structure(0)?;
+                Some((structure.get::<i32>("width").ok()?, structure.get::<i32>("height").ok()?))
+            });
+            let (Some((width, height)), Some(gst::PadProbeData::Buffer(buffer))) = (dims, info.data.as_mut()) else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let (width, height) = (width as usize, height as usize);
+            let Ok(mut map) = buffer.make_mut().map_writable() else {
+                return gst::PadProbeReturn::Ok;
+            };
+            let stride = map.len() / height.max(1);
+            let input = sample_square(&map, width, height, stride, size);
+            match model.run(&input, size * 3) {
+                Ok(output) => {
+                    for detection in decode_yolo(&output, &labels, size as u32, &pass.labels, pass.cfg.min_score) {
+                        pixelate(&mut map, width, height, stride, detection.bbox, pass.cfg.block_px as usize);
+                    }
+                }
+                Err(e) => warn!("Blur model failed on a frame: {:#}", e),
+            }
+            gst::PadProbeReturn::Ok
+        });
+    Ok(())
+}
+
+#[cfg(not(feature = "anonymize"))]
+pub fn attach(_element: &gstreamer::Element, _pass: &BlurPass) -> Result<()> {
+    anyhow::bail!("--blur needs dashcam_rs built with the `anonymize` feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixelates_only_inside_the_box() {
+        // 4x2 frame, rows padded to 12 bytes, every pixel a different grey
+        let (width, height, stride) = (4, 2, 12);
+        let mut rgb: Vec<u8> = (0..stride * height).map(|i| (i / 3) as u8).collect();
+        let original = rgb.clone();
+
+        pixelate(&mut rgb, width, height, stride, [0.5, 0.0, 1.0, 1.0], 2);
+        assert_eq!(rgb[..6], original[..6]);
+        assert_eq!(rgb[6..12], [2, 2, 2, 2, 2, 2]);
+        assert_eq!(rgb[18..24], [2, 2, 2, 2, 2, 2]);
+        assert_eq!(rgb[12..18], original[12..18]);
+
+        let square = sample_square(&original, width, height, stride, 2);
+        assert_eq!(square, vec![0, 0, 0, 2, 2, 2, 4, 4, 4, 6, 6, 6]);
+    }
+}
@@ -0,0 +1,222 @@
+//! Backfill of derived segment artifacts (`[backfill]` in config.toml).
+//!
+//! Every catalogued segment gets a small JPEG thumbnail of its first frame
+//! and a CRC-32 of the file, stored in the nullable `thumbnail_path` and
+//! `checksum` columns of `segments`. Rows from before the columns existed,
+//! or whose worker died with the process, are found by those NULLs and
+//! filled in here, one segment at a time with a pause in between so the
+//! recording pipelines keep the CPU and the disk.
+
+use anyhow::{Context, Result, anyhow, bail};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+use tracing::{debug, info};
+
+use crate::db::db::PendingSegment;
+use crate::db::db_worker::DBMessage;
+use crate::worker::Worker;
+
+const THUMBNAIL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Segments asked from the DB at a time
+const BATCH: i64 = 20;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackfillConfig {
+    /// Seconds between passes over the catalogue
+    pub interval_sec: u64,
+    /// Pause after each segment
+    pub pause_ms: u64,
+    pub thumbnails: bool,
+    pub checksums: bool,
+    pub thumbnail_width: u32,
+}
+
+impl Default for BackfillConfig {
+    fn default() -> Self {
+        Self {
+            interval_sec: 600,
+            pause_ms: 1000,
+            thumbnails: true,
+            checksums: true,
+            thumbnail_width: 320,
+        }
+    }
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// CRC-32 of `crc`'s data followed by `bytes`; start from 0.
+pub fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in bytes {
+        crc = CRC32_TABLE[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// CRC-32 (IEEE, as in zip and gzip) of everything `reader` yields.
+pub fn crc32(mut reader: impl Read) -> std::io::Result<u32> {
+    let mut crc = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(crc);
+        }
+        crc = crc32_update(crc, &buf[..n]);
+    }
+}
+
+/// `checksum` column value for `file`: its CRC-32 as 8 hex digits.
+pub fn checksum_file(file: &Path) -> Result<String> {
+    let f = File::open(file).with_context(|| format!("Can't open {}", file.display()))?;
+    let crc = crc32(f).with_context(|| format!("Can't read {}", file.display()))?;
+    Ok(format!("{:08x}", crc))
+}
+
+/// Write a `width` pixel wide JPEG of the first frame of `file` to `location`.
+pub fn make_thumbnail(file: &Path, location: &Path, width: u32) -> Result<()> {
+    gst::init()?;
+    if let Some(dir) = location.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let line = format!(
+        "filesrc location=\"{}\" ! decodebin ! videoconvert ! videoscale ! \
+         video/x-raw,width={},pixel-aspect-ratio=1/1 ! jpegenc snapshot=true ! filesink location=\"{}\"",
+        file.display(),
+        width,
+        location.display()
+    );
+    let pipeline = gst::parse::launch(&line).with_context(|| format!("Failed to build thumbnail pipeline '{}'", line))?;
+    let bus = pipeline.bus().context("Thumbnail pipeline has no bus")?;
+
+    pipeline.set_state(gst::State::Playing)?;
+    let msg = bus.timed_pop_filtered(
+        gst::ClockTime::from_mseconds(THUMBNAIL_TIMEOUT.as_millis() as u64),
+        &[gst::MessageType::Eos, gst::MessageType::Error],
+    );
+    pipeline.set_state(gst::State::Null)?;
+
+    match msg.as_ref().map(|m| m.view()) {
+        Some(gst::MessageView::Eos(..)) => Ok(()),
+        Some(gst::MessageView::Error(err)) => Err(anyhow!("Thumbnail failed: {} ({:?})", err.error(), err.debug())),
+        _ => bail!("Thumbnail timed out after {:?}", THUMBNAIL_TIMEOUT),
+    }
+}
+
+/// Fills in missing thumbnails and checksums, a pass every `interval_sec`.
+/// Thumbnails go to `<thumbnail_dir>/<segment id>.jpg`.
+pub fn spawn_backfill(
+    cfg: &BackfillConfig,
+    recording_root: PathBuf,
+    thumbnail_dir: PathBuf,
+    db_sender: Arc<Sender<DBMessage>>,
+) -> Worker {
+    let cfg = cfg.clone();
+    Worker::spawn("backfill", Duration::from_secs(cfg.interval_sec), move |stop| {
+        // walk the catalogue by id so segments that fail are passed over until the next pass
+        let (mut after_id, mut done, mut failed) = (0, 0, 0);
+        'batches: loop {
+            let (tx, rx) = mpsc::channel();
+            let request = DBMessage::GetSegmentsMissingDerived {
+                after_id,
+                limit: BATCH,
+                thumbnails: cfg.thumbnails,
+                checksums: cfg.checksums,
+                reply: tx,
+            };
+            if db_sender.send(request).is_err() {
+                stop.store(true, Ordering::SeqCst);
+                return;
+            }
+            let Ok(Some(pending)) = rx.recv() else {
+                break;
+            };
+            if pending.is_empty() {
+                break;
+            }
+            for segment in pending {
+                if stop.load(Ordering::SeqCst) {
+                    break 'batches;
+                }
+                after_id = segment.id;
+                let (thumbnail_path, checksum) = derive(&cfg, &segment, &recording_root, &thumbnail_dir);
+                if thumbnail_path.is_none() && checksum.is_none() {
+                    failed += 1;
+                } else {
+                    done += 1;
+                    let _ = db_sender.send(DBMessage::SetSegmentDerived { id: segment.id, thumbnail_path, checksum });
+                }
+                std::thread::sleep(Duration::from_millis(cfg.pause_ms));
+            }
+        }
+        if done + failed > 0 {
+            info!("Backfilled thumbnails/checksums of {} segments, {} failed", done, failed);
+        }
+    })
+}
+
+/// Whichever of the missing thumbnail and checksum could be made.
+fn derive(
+    cfg: &BackfillConfig,
+    segment: &PendingSegment,
+    recording_root: &Path,
+    thumbnail_dir: &Path,
+) -> (Option<String>, Option<String>) {
+    let file = recording_root.join(&segment.rel_path);
+    let checksum = segment
+        .needs_checksum
+        .then(|| checksum_file(&file))
+        .and_then(|r| r.map_err(|e| debug!("No checksum for segment {}: {:#}", segment.id, e)).ok());
+    let thumbnail_path = segment
+        .needs_thumbnail
+        .then(|| {
+            let location = thumbnail_dir.join(format!("{}.jpg", segment.id));
+            make_thumbnail(&file, &location, cfg.thumbnail_width).map(|_| location.to_string_lossy().to_string())
+        })
+        .and_then(|r| r.map_err(|e| debug!("No thumbnail for segment {}: {:#}", segment.id, e)).ok());
+    (thumbnail_path, checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_are_crc32() {
+        assert_eq!(crc32(&b"123456789"[..]).unwrap(), 0xCBF4_3926);
+        assert_eq!(crc32(&b""[..]).unwrap(), 0);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("output_0.ts");
+        std::fs::write(&file, vec![0x47u8; 200_000]).unwrap();
+        let expected = format!("{:08x}", crc32(&vec![0x47u8; 200_000][..]).unwrap());
+        assert_eq!(checksum_file(&file).unwrap(), expected);
+        assert!(checksum_file(&tmp.path().join("missing.ts")).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+//! Token-bucket rate limits (`[bandwidth]` in config.toml) for traffic leaving the
+//! device: HTTP downloads and HLS today, uploads through the same `RateLimits`.
+//! Keeps remote viewing over a vehicle LTE link from starving everything else.
+
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Largest write done before checking the buckets again.
+const CHUNK: usize = 16 * 1024;
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BandwidthConfig {
+    /// Shared by every download/upload. Unset = unlimited.
+    pub global_kbps: Option<u64>,
+    /// Per HTTP connection / upload. Unset = unlimited.
+    pub per_connection_kbps: Option<u64>,
+}
+
+fn bytes_per_sec(kbps: u64) -> f64 {
+    kbps as f64 * 1000.0 / 8.0
+}
+
+/// Allows short bursts of up to one second's worth, then `rate` bytes/s.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: f64) -> Self {
+        Self {
+            rate: bytes_per_sec,
+            tokens: bytes_per_sec,
+            last: Instant::now(),
+        }
+    }
+
+    /// Take `n` bytes worth of tokens (going into debt if needed) and return how
+    /// long the caller should wait before sending them.
+    pub fn reserve(&mut self, n: usize) -> Duration {
+        self.reserve_at(n, Instant::now())
+    }
+
+    pub fn reserve_at(&mut self, n: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.tokens -= n as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Limits built once from config and shared by all connections.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimits {
+    global: Option<Arc<Mutex<TokenBucket>>>,
+    per_connection: Option<f64>,
+}
+
+impl RateLimits {
+    pub fn from_config(cfg: &BandwidthConfig) -> Self {
+        Self {
+            global: cfg
+                .global_kbps
+                .map(|kbps| Arc::new(Mutex::new(TokenBucket::new(bytes_per_sec(kbps))))),
+            per_connection: cfg.per_connection_kbps.map(bytes_per_sec),
+        }
+    }
+
+    /// Wrap one connection's writer.
+    pub fn writer<W: Write>(&self, inner: W) -> ThrottledWriter<W> {
+        ThrottledWriter {
+            inner,
+            global: self.global.clone(),
+            own: self.per_connection.map(TokenBucket::new),
+        }
+    }
+}
+
+pub struct ThrottledWriter<W: Write> {
+    inner: W,
+    global: Option<Arc<Mutex<TokenBucket>>>,
+    own: Option<TokenBucket>,
+}
+
+impl<W: Write> Write for ThrottledWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let buf = &buf[..buf.len().min(CHUNK)];
+        let own_wait = self.own.as_mut().map_or(Duration::ZERO, |b| b.reserve(buf.len()));
+        let global_wait = self
+            .global
+            .as_ref()
+            .map_or(Duration::ZERO, |b| b.lock().unwrap().reserve(buf.len()));
+        let wait = own_wait.max(global_wait);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_burst_then_paces() {
+        let t0 = Instant::now();
+        let mut bucket = TokenBucket::new(1000.0);
+        bucket.last = t0;
+
+        assert_eq!(bucket.reserve_at(1000, t0), Duration::ZERO, "one second of burst");
+        assert_eq!(bucket.reserve_at(500, t0), Duration::from_millis(500));
+        // half a second later the debt is paid off
+        assert_eq!(bucket.reserve_at(0, t0 + Duration::from_millis(500)), Duration::ZERO);
+        // idle time doesn't bank more than the burst
+        assert_eq!(bucket.reserve_at(2000, t0 + Duration::from_secs(60)), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn unlimited_by_default() {
+        let limits = RateLimits::from_config(&BandwidthConfig::default());
+        let mut out = limits.writer(Vec::new());
+        out.write_all(&[0u8; 100_000]).unwrap();
+        assert_eq!(out.inner.len(), 100_000);
+    }
+}
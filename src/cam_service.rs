@@ -1,24 +1,262 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, bail};
 use regex::Regex;
-use crate::db::db::{DashcamDb };
-use crate::db::db_worker::{DBMessage,DBWorker,start_db_worker};
+use crate::db::db::{AuditRow, EventRow, GpsPoint, IncidentRow, RuleStateRow};
+use crate::db::db_worker::{DBMessage,DBWorker,SettingChange,start_db_worker};
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{Sender, channel};
+use std::sync::mpsc::{self, Receiver, Sender, channel};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
-use tracing::{error, info};
+use std::time::{Duration, Instant};
+use tracing::{error, info, trace, warn};
 
-use crate::config::AppConfig;
-use crate::recording_pipeline::RecordingPipeline;
+use crate::bandwidth::RateLimits;
+use crate::clips::{ClipCamera, ClipQueue, spawn_clips};
+use crate::live::LiveView;
+use crate::modes::{self, ModeStatus};
+use crate::rules::{self, RuleInputs, RuleQueue};
+use crate::config::{AppConfig, RecordMode, SinkConfig};
+use crate::control::{
+    BundleInfo, CameraStatus, ControlCommand, ControlReply, ProtectInfo, ReloadReport, SegmentStatus, ServiceStatus,
+};
+use crate::crash;
+use crate::health::{HealthReport, HealthState, SubsystemHealth, camera_health};
+use crate::incident_bundle::write_bundle;
+use crate::playlist::{self, RewindStart};
+use crate::protect::{self, ProtectRequest};
+use crate::track::{self, TrackFormat, TrackRange};
+use crate::detection::{DetectionGate, spawn_detector};
+use crate::motion::spawn_motion;
+use crate::events::{CameraEvent, EventFilter, EventKind};
+use crate::power_loss::{PowerBudget, PowerLossConfig, run_shutdown_command, sync_filesystems};
+use crate::roi::Roi;
+use crate::speed_events::SpeedRules;
+use crate::smart::{DriveHealth, SmartWorker};
+use crate::gps_overlay::{LatestFix, spawn_gps_overlay};
+use crate::standby::{PowerState, spawn_standby};
+use crate::start_retry::spawn_start_retry;
+use crate::storage_wear::StorageWear;
+use crate::time_sync::spawn_time_sync;
+use crate::upload::SnapshotUploader;
+use crate::usb_sync::spawn_usb_sync;
+use crate::backfill::spawn_backfill;
+use crate::obd::spawn_obd;
+use crate::janitor::{ring_cameras, spawn_janitor};
+use crate::storage_manager::spawn_storage_manager;
+use crate::tiering::{TieredCamera, spawn_tiering};
+use crate::rollup::{RolledCamera, spawn_rollup};
+use crate::validation::{ValidatedCamera, spawn_validation};
+use crate::worker::Worker;
+use crate::pipeline_sinks::frame_tap_sink::{FrameTapConfig, FrameTarget};
+use crate::pipeline_sinks::staging::{Staging, is_network_fs};
+use crate::recording_pipeline::{EncodingChange, RecordingPipeline};
 use crate::recording_pipeline_factory::build_pipelines_from_config;
 
+const HEALTH_DB_TIMEOUT: Duration = Duration::from_secs(2);
+
 pub struct CamService {
     pub pipelines: Vec<Arc<Mutex<RecordingPipeline>>>,
     pub running: Arc<AtomicBool>,
     pub db_worker_handle: Option<JoinHandle<()>>,
     pub db_sender: Arc<Sender<DBMessage>>,
-    pub app_config: AppConfig
+    pub app_config: AppConfig,
+    pub detection_gates: HashMap<String, Arc<DetectionGate>>,
+    pub detector_handles: Vec<JoinHandle<()>>,
+    pub event_filter: Arc<Mutex<EventFilter>>,
+    /// Some while in standby
+    standby: Mutex<Option<Worker>>,
+    /// Some while a camera that didn't come up with the others is being retried
+    start_retry: Arc<Mutex<Option<Worker>>>,
+    /// Some while running with `[validation]` configured
+    validator: Option<Worker>,
+    /// Some while running with `[rollup]` configured
+    rollup: Option<Worker>,
+    /// Some while running with `[janitor]` configured
+    janitor: Option<Worker>,
+    /// Some while running with `[tiering]` configured
+    tiering: Option<Worker>,
+    /// Some while running with `[storage_manager]` configured
+    storage_manager: Option<Worker>,
+    /// Some while running with `[backfill]` configured
+    backfill: Option<Worker>,
+    /// Some while running with `[obd]` configured
+    obd: Option<Worker>,
+    /// Some while running with `[usb_sync]` configured
+    usb_sync: Option<Worker>,
+    /// Some while running with `[smart]` configured
+    smart: Option<SmartWorker>,
+    /// Some while running with a camera on `overlay_gps`
+    gps_overlay: Option<Worker>,
+    /// Last `[gps]` fix, for the overlays; set by `gps_fix`
+    latest_fix: LatestFix,
+    /// Incidents whose bundle is being written, so a repeated request can't race it
+    bundling: Arc<Mutex<HashSet<i64>>>,
+    /// Some while running
+    time_sync: Option<Worker>,
+    /// Some with `[clips]` configured, until the service is killed
+    clips: Option<Worker>,
+    routing: EventRouting,
+    /// Read end of `routing.rules` until `take_rule_inputs`, with `[[rules]]` configured
+    rule_inputs: Option<RuleInputs>,
+    /// Some with `[speed_events]` configured; fed by `gps_fix`
+    speed_rules: Mutex<Option<SpeedRules>>,
+    /// Current `[[modes]]` mode, Some with modes configured
+    mode: Mutex<Option<String>>,
+}
+
+/// Where admitted events go besides the DB and the sinks; cheap to clone into
+/// event callbacks.
+#[derive(Clone, Default)]
+struct EventRouting {
+    clips: ClipQueue,
+    /// Advertised primary stream, switched by `[live]` events
+    live: LiveView,
+    rules: RuleQueue,
+}
+
+/// Record an event and hand it to the camera's sinks, unless the cooldown/hysteresis
+/// policy for its kind swallows it. Returns whether it went through.
+fn raise_event(
+    pipeline: &Mutex<RecordingPipeline>,
+    db_sender: &Sender<DBMessage>,
+    event_filter: &Mutex<EventFilter>,
+    routing: &EventRouting,
+    event: CameraEvent,
+) -> bool {
+    let mut pipeline = pipeline.lock().unwrap();
+    if !event_filter.lock().unwrap().admit(pipeline.camera_key(), &event) {
+        trace!("Camera '{}' {} event debounced", pipeline.camera_key(), event.kind);
+        return false;
+    }
+    let _ = db_sender.send(DBMessage::InsertEvent {
+        camera_key: pipeline.camera_key().to_string(),
+        event: event.clone(),
+    });
+    pipeline.notify_event(&event);
+    routing.clips.submit(pipeline.camera_key(), &event);
+    routing.live.on_event(&event);
+    routing.rules.submit(pipeline.camera_key(), &event);
+    true
+}
+
+fn start_pipeline_with_timeout(idx: usize, pipeline_arc: &Mutex<RecordingPipeline>, timeout: Duration) {
+    let (camera_key, waiter) = {
+        let mut pipeline = pipeline_arc.lock().unwrap();
+        if pipeline.is_running() {
+            info!("Pipeline #{} already running, skipping start", idx);
+            return;
+        }
+        if pipeline.is_private() {
+            info!("Camera '{}' is in privacy mode, not starting it", pipeline.camera_key());
+            crash::note_pipeline_state(pipeline.camera_key(), "privacy");
+            return;
+        }
+        if pipeline.is_off_in_mode() {
+            info!("Camera '{}' doesn't record in the current mode, not starting it", pipeline.camera_key());
+            crash::note_pipeline_state(pipeline.camera_key(), "off in mode");
+            return;
+        }
+        if pipeline.is_halted() {
+            info!("Camera '{}' was stopped over the control API, not starting it", pipeline.camera_key());
+            crash::note_pipeline_state(pipeline.camera_key(), "stopped");
+            return;
+        }
+        info!("Starting pipeline #{} ('{}')", idx, pipeline.camera_key());
+        if let Err(e) = pipeline.begin_start() {
+            error!("Failed to start pipeline #{}: {:#}", idx, e);
+            crash::note_pipeline_state(pipeline.camera_key(), "failed to start");
+            return;
+        }
+        (pipeline.camera_key().to_string(), pipeline.start_waiter())
+    };
+
+    // Wait without holding the lock, so the control API isn't blocked meanwhile
+    let started = Instant::now();
+    match waiter.wait(timeout) {
+        Ok(state) => {
+            info!(
+                "Camera '{}' {} after {:.1}s",
+                camera_key,
+                state.as_str(),
+                started.elapsed().as_secs_f64()
+            );
+            crash::note_pipeline_state(&camera_key, "running");
+        }
+        Err(e) => {
+            warn!("{:#}; continuing startup without it", e);
+            crash::note_pipeline_state(&camera_key, "starting");
+        }
+    }
+}
+
+/// What a power-loss shutdown needs, cloned out of the service when the watcher
+/// is spawned so it runs without the service lock, which whoever holds it may
+/// keep past the power budget.
+pub struct PowerLossShutdown {
+    cfg: PowerLossConfig,
+    pipelines: Vec<Arc<Mutex<RecordingPipeline>>>,
+    running: Arc<AtomicBool>,
+    start_retry: Arc<Mutex<Option<Worker>>>,
+    db_sender: Arc<Sender<DBMessage>>,
+}
+
+impl PowerLossShutdown {
+    pub fn config(&self) -> &PowerLossConfig {
+        &self.cfg
+    }
+
+    /// Close the segments, stop the cameras, checkpoint the DB and sync, each cut
+    /// short to stay within the power budget counted from `lost_at`, then run the
+    /// shutdown command.
+    pub fn run(self, lost_at: Instant) {
+        let budget = PowerBudget::starting(lost_at, Duration::from_millis(self.cfg.budget_ms));
+        self.running.store(false, Ordering::SeqCst);
+
+        // EOS finishes the files; a stuck pipeline (or a lock held elsewhere) mustn't eat the whole budget
+        let stop_window = budget.stage(Instant::now(), 0.6);
+        let (tx, rx) = mpsc::channel();
+        let (pipelines, start_retry) = (self.pipelines.clone(), self.start_retry.clone());
+        std::thread::spawn(move || {
+            if let Some(worker) = start_retry.lock().unwrap().take() {
+                worker.stop();
+            }
+            for pipeline in &pipelines {
+                let _ = pipeline.lock().unwrap().split_segments();
+            }
+            for pipeline in &pipelines {
+                let mut pipeline = pipeline.lock().unwrap();
+                if pipeline.is_started() {
+                    let _ = pipeline.stop_pipeline();
+                }
+                crash::note_pipeline_state(pipeline.camera_key(), "power loss");
+            }
+            let _ = tx.send(());
+        });
+        if rx.recv_timeout(stop_window).is_err() {
+            warn!("Pipelines didn't stop within {}ms, moving on", stop_window.as_millis());
+        }
+
+        let (tx, rx) = mpsc::channel();
+        if self.db_sender.send(DBMessage::Checkpoint { reply: tx }).is_ok()
+            && rx.recv_timeout(budget.stage(Instant::now(), 0.5)).is_err()
+        {
+            warn!("DB checkpoint didn't finish in time, moving on");
+        }
+
+        // sync(1) can block on a slow card; the shutdown command still goes out in time
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            sync_filesystems();
+            let _ = tx.send(());
+        });
+        if rx.recv_timeout(budget.stage(Instant::now(), 0.8)).is_err() {
+            warn!("sync didn't finish in time, moving on");
+        }
+        info!("Power-loss shutdown ready {}ms after the signal", lost_at.elapsed().as_millis());
+        run_shutdown_command(&self.cfg);
+    }
 }
 
 impl CamService {
@@ -41,15 +279,51 @@ impl CamService {
         let pipelines: Vec<Arc<Mutex<RecordingPipeline>>> =
             pipeline_vec.into_iter().map(|p| Arc::new(Mutex::new(p))).collect();
 
-        let service = CamService {
+        // Before the detectors, which keep a copy of the queue
+        let (rule_queue, rule_inputs) = if cfg.rules.is_empty() {
+            (RuleQueue::default(), None)
+        } else {
+            let (queue, inputs) = RuleQueue::channel();
+            (queue, Some(inputs))
+        };
+
+        let mut service = CamService {
             pipelines,
             running: Arc::new(AtomicBool::new(false)),
             db_worker_handle: Some(dbhandle),
             db_sender: dbsender,
-            app_config: cfg
+            detection_gates: HashMap::new(),
+            detector_handles: Vec::new(),
+            event_filter: Arc::new(Mutex::new(EventFilter::new(cfg.events.clone()))),
+            standby: Mutex::new(None),
+            start_retry: Arc::new(Mutex::new(None)),
+            validator: None,
+            rollup: None,
+            janitor: None,
+            tiering: None,
+            storage_manager: None,
+            backfill: None,
+            obd: None,
+            usb_sync: None,
+            smart: None,
+            gps_overlay: None,
+            latest_fix: LatestFix::default(),
+            bundling: Arc::new(Mutex::new(HashSet::new())),
+            time_sync: None,
+            clips: None,
+            routing: EventRouting { live: LiveView::new(cfg.live.as_ref()), rules: rule_queue, ..Default::default() },
+            rule_inputs,
+            speed_rules: Mutex::new(cfg.speed_events.as_ref().map(SpeedRules::new)),
+            mode: Mutex::new(None),
+            app_config: cfg,
         };
 
         service.prep_dir_for_service()?;
+        service.restore_mode()?;
+        service.start_clips();
+        service.export_roi_masks()?;
+        service.start_detectors()?;
+        service.start_motion()?;
 
         Ok(service)
     }
@@ -62,19 +336,26 @@ impl CamService {
         );
 
         self.running.store(true, Ordering::SeqCst);
-
-        for (idx, pipeline_arc) in self.pipelines.iter().enumerate() {
-            let mut pipeline = pipeline_arc.lock().unwrap();
-            if pipeline.is_running() {
-                info!("Pipeline #{} already running, skipping start", idx);
-                continue;
-            }
-            info!("Starting pipeline #{}", idx);
-            if let Err(e) = pipeline.start_pipeline() {
-                error!("Failed to start pipeline #{}: {:#}", idx, e);
+        if let Some(wear) = self.storage_wear() {
+            let health = wear.health(&self.app_config.storage_wear);
+            if health.state != HealthState::Ok {
+                warn!("Recording storage {}: {}", wear.root, health.detail);
             }
         }
-
+        self.start_pipelines();
+        self.start_validator();
+        self.start_rollup();
+        self.start_janitor();
+        self.start_tiering();
+        self.start_storage_manager();
+        self.start_backfill();
+        self.start_obd();
+        self.start_usb_sync();
+        self.start_smart();
+        self.start_gps_overlay();
+        if self.time_sync.is_none() {
+            self.time_sync = Some(spawn_time_sync(&self.app_config.time_sync, self.db_sender.clone()));
+        }
         Ok(())
     }
 
@@ -83,31 +364,1362 @@ impl CamService {
         info!("Killing CamService main loop");
         self.running.store(false, Ordering::SeqCst);
 
+        if let Some(worker) = self.standby.lock().unwrap().take() {
+            worker.stop();
+        }
+        if let Some(validator) = self.validator.take() {
+            validator.stop();
+        }
+        if let Some(rollup) = self.rollup.take() {
+            rollup.stop();
+        }
+        if let Some(janitor) = self.janitor.take() {
+            janitor.stop();
+        }
+        if let Some(tiering) = self.tiering.take() {
+            tiering.stop();
+        }
+        if let Some(storage_manager) = self.storage_manager.take() {
+            storage_manager.stop();
+        }
+        if let Some(backfill) = self.backfill.take() {
+            backfill.stop();
+        }
+        if let Some(obd) = self.obd.take() {
+            obd.stop();
+        }
+        if let Some(usb_sync) = self.usb_sync.take() {
+            usb_sync.stop();
+        }
+        if let Some(smart) = self.smart.take() {
+            smart.stop();
+        }
+        if let Some(overlay) = self.gps_overlay.take() {
+            overlay.stop();
+        }
+        if let Some(time_sync) = self.time_sync.take() {
+            time_sync.stop();
+        }
+        if let Some(clips) = self.clips.take() {
+            clips.stop();
+        }
+        self.stop_pipelines("stopped");
+
+        info!(
+            "Killed CamService at {}",
+            chrono::Local::now().format("%m-%d-%Y %H:%M:%S")
+        );
+        Ok(())
+    }
+
+    pub fn status(&self) -> ServiceStatus {
+        ServiceStatus {
+            power_state: self.power_state().as_str(),
+            cameras: self
+                .pipelines
+                .iter()
+                .map(|p| {
+                    let p = p.lock().unwrap();
+                    CameraStatus {
+                        key: p.camera_key().to_string(),
+                        running: p.is_running(),
+                        state: p.start_state().as_str().to_string(),
+                        source: if p.is_on_backup() { "backup" } else { "primary" },
+                        privacy: p.is_private(),
+                        off_in_mode: p.is_off_in_mode(),
+                        segments: p
+                            .current_segments()
+                            .into_iter()
+                            .map(|(sink_id, info)| SegmentStatus {
+                                sink_id,
+                                path: info.path,
+                                index: info.index,
+                                started_at: info.started_at.to_rfc3339(),
+                            })
+                            .collect(),
+                        throughput: p.throughput(),
+                    }
+                })
+                .collect(),
+            crash_reports: crash::list_reports(&crash::crash_dir(&self.app_config.main_dir())),
+            live: self.routing.live.status(),
+            mode: self.mode.lock().unwrap().clone(),
+            storage_wear: self.storage_wear(),
+            drives: self.smart.as_ref().map(SmartWorker::drives).unwrap_or_default(),
+        }
+    }
+
+    /// Segment bytes written under the recording root against `[storage_wear]`;
+    /// `None` if the DB doesn't answer.
+    fn storage_wear(&self) -> Option<StorageWear> {
+        let root = self.app_config.recording_root();
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetBytesWritten { storage_root: root.clone(), reply: tx }).ok()?;
+        let bytes = rx.recv_timeout(HEALTH_DB_TIMEOUT).ok()??;
+        Some(StorageWear::new(&root, bytes.max(0) as u64, &self.app_config.storage_wear))
+    }
+
+    /// Liveness of the DB worker, the recording disk and each camera, for `/healthz`.
+    pub fn health(&self) -> HealthReport {
+        let mut subsystems = Vec::new();
+
+        let (tx, rx) = mpsc::channel();
+        let db = match self.db_sender.send(DBMessage::Ping { reply: tx }) {
+            Err(_) => SubsystemHealth::new("db", HealthState::Failing, "worker has stopped"),
+            Ok(()) => match rx.recv_timeout(HEALTH_DB_TIMEOUT) {
+                Ok(()) => SubsystemHealth::new("db", HealthState::Ok, "responding"),
+                Err(_) => SubsystemHealth::new(
+                    "db",
+                    HealthState::Failing,
+                    format!("no reply within {}s", HEALTH_DB_TIMEOUT.as_secs()),
+                ),
+            },
+        };
+        subsystems.push(db);
+
+        let probe = PathBuf::from(self.app_config.recording_root()).join(".healthz");
+        subsystems.push(match fs::write(&probe, b"ok").and_then(|_| fs::remove_file(&probe)) {
+            Ok(()) => SubsystemHealth::new("storage", HealthState::Ok, "writable"),
+            Err(e) => SubsystemHealth::new("storage", HealthState::Failing, format!("can't write: {}", e)),
+        });
+        if let Some(wear) = self.storage_wear() {
+            subsystems.push(wear.health(&self.app_config.storage_wear));
+        }
+        if let Some(smart) = &self.smart {
+            subsystems.extend(smart.drives().iter().map(DriveHealth::health));
+        }
+
+        let standby = self.power_state() == PowerState::Standby;
+        for pipeline in &self.pipelines {
+            let p = pipeline.lock().unwrap();
+            if standby || p.is_held() {
+                let state = if standby {
+                    "standby"
+                } else if p.is_private() {
+                    "privacy"
+                } else if p.is_off_in_mode() {
+                    "off in mode"
+                } else {
+                    "stopped by operator"
+                };
+                subsystems.push(SubsystemHealth::new(format!("camera:{}", p.camera_key()), HealthState::Ok, state));
+                continue;
+            }
+            let newest = p.current_segments().into_iter().map(|(_, info)| info.started_at).max();
+            let age = newest.and_then(|at| (chrono::Local::now() - at).to_std().ok());
+            let state = p.start_state();
+            subsystems.push(camera_health(
+                p.camera_key(),
+                state.as_str(),
+                state.is_playing(),
+                age,
+                p.config().segment_length(),
+            ));
+            subsystems.extend(p.throughput().iter().map(|sink| sink.health(p.camera_key())));
+        }
+        HealthReport::from_subsystems(subsystems)
+    }
+
+    /// Start every pipeline on its own thread and wait (up to `camera_start_timeout`
+    /// each, concurrently) for them to reach PLAYING. A camera that is still
+    /// connecting after that keeps trying in the background; the others are
+    /// already recording. Cameras that failed outright are retried per their
+    /// source's `retry_interval_sec`; ones with a backup source stay watched for failover.
+    fn start_pipelines(&self) {
+        let timeout = self.app_config.camera_start_timeout();
+        std::thread::scope(|scope| {
+            for (idx, pipeline_arc) in self.pipelines.iter().enumerate() {
+                scope.spawn(move || start_pipeline_with_timeout(idx, pipeline_arc, timeout));
+            }
+        });
+
+        let watched: Vec<_> = self
+            .pipelines
+            .iter()
+            .filter(|p| {
+                let p = p.lock().unwrap();
+                !p.start_state().is_playing() || p.has_backup_source()
+            })
+            .cloned()
+            .collect();
+        let mut start_retry = self.start_retry.lock().unwrap();
+        if let Some(worker) = start_retry.take() {
+            worker.stop();
+        }
+        if !watched.is_empty() {
+            let pipelines = self.pipelines.clone();
+            let db_sender = self.db_sender.clone();
+            let event_filter = self.event_filter.clone();
+            let routing = self.routing.clone();
+            *start_retry = Some(spawn_start_retry(watched, move |camera_key, event| {
+                if let Some(pipeline) = pipelines.iter().find(|p| p.lock().unwrap().camera_key() == camera_key) {
+                    raise_event(pipeline, &db_sender, &event_filter, &routing, event);
+                }
+            }));
+        }
+    }
+
+    /// `state` is what crash reports should show for the stopped cameras.
+    fn stop_pipelines(&self, state: &str) {
+        if let Some(worker) = self.start_retry.lock().unwrap().take() {
+            worker.stop();
+        }
         for (idx, pipeline_arc) in self.pipelines.iter().enumerate() {
             let mut pipeline = pipeline_arc.lock().unwrap();
-            if pipeline.is_running() {
+            // also reaps pipelines that already stopped on an error
+            if pipeline.is_started() {
                 info!("Stopping pipeline #{}", idx);
                 if let Err(e) = pipeline.stop_pipeline() {
                     error!("Error stopping pipeline #{}: {:#}", idx, e);
                 }
             }
+            crash::note_pipeline_state(pipeline.camera_key(), state);
+        }
+    }
+
+    pub fn power_state(&self) -> PowerState {
+        if self.standby.lock().unwrap().is_some() {
+            PowerState::Standby
+        } else {
+            PowerState::Active
+        }
+    }
+
+    /// Stop recording, checkpoint the DB and only take periodic snapshots until woken.
+    pub fn enter_standby(&self) -> Result<PowerState> {
+        let mut standby = self.standby.lock().unwrap();
+        if standby.is_some() {
+            return Ok(PowerState::Standby);
+        }
+        info!("Entering standby");
+        self.stop_pipelines("standby");
+
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::Checkpoint { reply: tx })?;
+        rx.recv()?;
+
+        let recording_root = PathBuf::from(self.app_config.recording_root());
+        let cameras = self
+            .app_config
+            .cameras
+            .iter()
+            .filter(|c| c.enabled)
+            .map(|c| (c.key.clone(), c.source.clone(), recording_root.join(&c.key)))
+            .collect();
+        let uploader = self
+            .app_config
+            .snapshot_upload
+            .clone()
+            .map(|cfg| SnapshotUploader::new(cfg, RateLimits::from_config(&self.app_config.bandwidth)));
+        *standby = Some(spawn_standby(&self.app_config.standby, cameras, uploader));
+        Ok(PowerState::Standby)
+    }
+
+    /// Back to recording. Pipelines restart right away.
+    pub fn exit_standby(&self) -> Result<PowerState> {
+        let Some(worker) = self.standby.lock().unwrap().take() else {
+            return Ok(PowerState::Active);
+        };
+        info!("Leaving standby");
+        worker.stop();
+        self.start_pipelines();
+        Ok(PowerState::Active)
+    }
+
+    /// Privacy switch of `camera_key` flipped: stop the camera or start it again
+    /// (unless in standby), and record the transition as a `privacy` event.
+    pub fn set_privacy(&self, camera_key: &str, private: bool) -> Result<()> {
+        let (idx, pipeline_arc) = self
+            .pipelines
+            .iter()
+            .enumerate()
+            .find(|(_, p)| p.lock().unwrap().camera_key() == camera_key)
+            .with_context(|| format!("No pipeline for camera '{}'", camera_key))?;
+        {
+            let mut pipeline = pipeline_arc.lock().unwrap();
+            if pipeline.is_private() == private {
+                return Ok(());
+            }
+            pipeline.set_private(private)?;
+            if private {
+                info!("Camera '{}' in privacy mode, recording stopped", camera_key);
+                crash::note_pipeline_state(camera_key, "privacy");
+            }
+        }
+        let event = CameraEvent {
+            label: Some(if private { "on" } else { "off" }.to_string()),
+            ..CameraEvent::now(EventKind::Privacy)
+        };
+        raise_event(pipeline_arc, &self.db_sender, &self.event_filter, &self.routing, event);
+
+        if !private && self.running.load(Ordering::SeqCst) && self.power_state() == PowerState::Active {
+            info!("Camera '{}' out of privacy mode, recording again", camera_key);
+            start_pipeline_with_timeout(idx, pipeline_arc, self.app_config.camera_start_timeout());
+        }
+        Ok(())
+    }
+
+    /// Everything `[power_loss]` needs to shut down, for the watcher to hold
+    /// on to; `None` without `[power_loss]`.
+    pub fn power_loss_shutdown(&self) -> Option<PowerLossShutdown> {
+        Some(PowerLossShutdown {
+            cfg: self.app_config.power_loss.clone()?,
+            pipelines: self.pipelines.clone(),
+            running: self.running.clone(),
+            start_retry: self.start_retry.clone(),
+            db_sender: self.db_sender.clone(),
+        })
+    }
+
+    /// Ignition line from the vehicle: off enters standby, on leaves it.
+    pub fn set_ignition(&self, on: bool) -> Result<PowerState> {
+        if on { self.exit_standby() } else { self.enter_standby() }
+    }
+
+    /// Events for the `[[rules]]` dispatcher; `None` without rules or once taken.
+    pub fn take_rule_inputs(&mut self) -> Option<RuleInputs> {
+        self.rule_inputs.take()
+    }
+
+    /// `[[rules]]` with their stored state, in config order.
+    pub fn rules(&self) -> Result<Vec<RuleStateRow>> {
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetRuleStates { reply: tx })?;
+        let stored = rx.recv()?.context("Failed to load rule state")?;
+        Ok(self
+            .app_config
+            .rules
+            .iter()
+            .map(|rule| {
+                stored
+                    .iter()
+                    .find(|s| s.name == rule.name)
+                    .cloned()
+                    .unwrap_or_else(|| rules::initial_state(rule))
+            })
+            .collect())
+    }
+
+    /// Control API: arm or disarm a rule; it stays so across restarts.
+    pub fn set_rule_armed(&self, name: &str, armed: bool) -> Result<()> {
+        if !self.app_config.rules.iter().any(|r| r.name == name) {
+            bail!("No rule named '{}'", name);
+        }
+        if !self.routing.rules.set_armed(name, armed) {
+            bail!("The rule dispatcher isn't running");
+        }
+        Ok(())
+    }
+
+    /// Back in the mode last switched to, before the cameras start. Rules keep
+    /// their stored armed state, which the last switch already set.
+    fn restore_mode(&self) -> Result<()> {
+        if self.app_config.modes.is_empty() {
+            return Ok(());
+        }
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetModeHistory { limit: 1, reply: tx })?;
+        let last = rx.recv()?.context("Failed to load mode history")?;
+        let Some(mode) = modes::initial_mode(&self.app_config.modes, last.first().map(|row| row.mode.as_str())) else {
+            return Ok(());
+        };
+        info!("Starting in mode '{}'", mode.name);
+        for pipeline in &self.pipelines {
+            let mut pipeline = pipeline.lock().unwrap();
+            let off = !mode.records(pipeline.camera_key());
+            pipeline.set_off_in_mode(off)?;
+            let encoding = mode.encoding_for(pipeline.default_encoding());
+            pipeline.set_encoding(encoding)?;
+        }
+        *self.mode.lock().unwrap() = Some(mode.name.clone());
+        Ok(())
+    }
+
+    /// Control API: switch to mode `name`, stopping the cameras it doesn't record
+    /// on, starting the others (at the mode's encoding) and arming/disarming
+    /// rules. Logged with `actor`.
+    /// Switching to the current mode changes nothing, so a retained MQTT message
+    /// doesn't undo rules armed by hand since.
+    pub fn set_mode(&self, name: &str, actor: &str) -> Result<()> {
+        let mode = self
+            .app_config
+            .modes
+            .iter()
+            .find(|m| m.name == name)
+            .with_context(|| format!("No mode named '{}'", name))?;
+        let mut current = self.mode.lock().unwrap();
+        if current.as_deref() == Some(name) {
+            return Ok(());
+        }
+        info!("Switching to mode '{}' (by '{}')", name, actor);
+
+        let active = self.running.load(Ordering::SeqCst) && self.power_state() == PowerState::Active;
+        let timeout = self.app_config.camera_start_timeout();
+        for (idx, pipeline_arc) in self.pipelines.iter().enumerate() {
+            let start = {
+                let mut pipeline = pipeline_arc.lock().unwrap();
+                let encoding = mode.encoding_for(pipeline.default_encoding());
+                let rebuilt = match pipeline.set_encoding(encoding) {
+                    Ok(EncodingChange::Rebuilt { was_running }) => was_running,
+                    Ok(_) => false,
+                    Err(e) => {
+                        warn!("Camera '{}' couldn't switch encoding: {:#}", pipeline.camera_key(), e);
+                        false
+                    }
+                };
+                let off = !mode.records(pipeline.camera_key());
+                let was_off = pipeline.is_off_in_mode();
+                pipeline.set_off_in_mode(off)?;
+                if off && !was_off {
+                    info!("Camera '{}' doesn't record in mode '{}', stopped", pipeline.camera_key(), name);
+                    crash::note_pipeline_state(pipeline.camera_key(), "off in mode");
+                }
+                !off && active && (was_off || rebuilt)
+            };
+            if start {
+                start_pipeline_with_timeout(idx, pipeline_arc, timeout);
+            }
+        }
+        for rule in &self.app_config.rules {
+            if let Some(armed) = mode.arms(&rule.name) {
+                self.routing.rules.set_armed(&rule.name, armed);
+            }
         }
 
+        let _ = self.db_sender.send(DBMessage::InsertModeChange { mode: name.to_string(), actor: actor.to_string() });
+        *current = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Control API: current mode and the last switches.
+    pub fn mode_status(&self) -> Result<ModeStatus> {
+        let current = self.mode.lock().unwrap().clone().context("No [[modes]] configured")?;
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetModeHistory { limit: 20, reply: tx })?;
+        Ok(ModeStatus {
+            current,
+            modes: self.app_config.modes.iter().map(|m| m.name.clone()).collect(),
+            history: rx.recv()?.context("Failed to load mode history")?,
+        })
+    }
+
+    /// Control API: stop `camera_key` and keep it stopped (no start retries,
+    /// standby exits or mode switches restart it) until `start_camera`.
+    pub fn stop_camera(&self, camera_key: &str) -> Result<()> {
+        let pipeline_arc = self.pipeline(camera_key)?;
+        let mut pipeline = pipeline_arc.lock().unwrap();
+        pipeline.set_halted(true)?;
+        info!("Camera '{}' stopped over the control API", camera_key);
+        crash::note_pipeline_state(camera_key, "stopped");
+        Ok(())
+    }
+
+    /// Control API: undo `stop_camera` and start the camera, unless the service
+    /// is in standby or something else keeps it stopped.
+    pub fn start_camera(&self, camera_key: &str) -> Result<()> {
+        let (idx, pipeline_arc) = self
+            .pipelines
+            .iter()
+            .enumerate()
+            .find(|(_, p)| p.lock().unwrap().camera_key() == camera_key)
+            .with_context(|| format!("No pipeline for camera '{}'", camera_key))?;
+        {
+            let mut pipeline = pipeline_arc.lock().unwrap();
+            pipeline.set_halted(false)?;
+            if pipeline.is_private() {
+                bail!("Camera '{}' is in privacy mode", camera_key);
+            }
+            if pipeline.is_off_in_mode() {
+                bail!("Camera '{}' doesn't record in the current mode", camera_key);
+            }
+        }
+        if !self.running.load(Ordering::SeqCst) || self.power_state() != PowerState::Active {
+            bail!("Not recording right now (standby or shutting down); '{}' starts with the others", camera_key);
+        }
+        start_pipeline_with_timeout(idx, pipeline_arc, self.app_config.camera_start_timeout());
+        Ok(())
+    }
+
+    /// Re-read config (control socket `reload-config`): `[events]`, `[standby]`,
+    /// `[snapshot_upload]` and `[speed_events]` take effect now, other changed
+    /// sections are only reported, since the cameras and workers built from
+    /// them keep running as they are. Audited like a control command.
+    pub fn reload_config(&mut self, actor: &str, cfg: AppConfig) -> Result<ReloadReport> {
+        let result = self.apply_reloaded_config(cfg);
+        let target = result.as_ref().ok().map(|report| format!("applied {:?}", report.applied));
+        self.record_audit(actor, "reload_config", target, result.as_ref().err().map(|e| format!("{:#}", e)));
+        result
+    }
+
+    fn apply_reloaded_config(&mut self, cfg: AppConfig) -> Result<ReloadReport> {
+        if self.app_config.simulation.is_some() {
+            bail!("Can't reload the config in simulation mode");
+        }
+        // Not every section is PartialEq; their Debug output is as good for this
+        let changed = |old: &dyn std::fmt::Debug, new: &dyn std::fmt::Debug| format!("{:?}", old) != format!("{:?}", new);
+        let old = &self.app_config;
+        let mut report = ReloadReport::default();
+
+        // a HashMap, whose Debug order varies
+        if old.events != cfg.events {
+            self.event_filter.lock().unwrap().set_policies(cfg.events.clone());
+            report.applied.push("events");
+        }
+        if changed(&old.standby, &cfg.standby) {
+            report.applied.push("standby");
+        }
+        if changed(&old.snapshot_upload, &cfg.snapshot_upload) {
+            report.applied.push("snapshot_upload");
+        }
+        if changed(&old.speed_events, &cfg.speed_events) {
+            *self.speed_rules.lock().unwrap() = cfg.speed_events.as_ref().map(SpeedRules::new);
+            report.applied.push("speed_events");
+        }
+        if old.storage_wear != cfg.storage_wear {
+            report.applied.push("storage_wear");
+        }
+
+        let fixed: &[(&'static str, &dyn std::fmt::Debug, &dyn std::fmt::Debug)] = &[
+            ("global", &old.global, &cfg.global),
+            ("profiles", &old.profiles, &cfg.profiles),
+            ("cameras", &old.cameras, &cfg.cameras),
+            ("http", &old.http, &cfg.http),
+            ("mdns", &old.mdns, &cfg.mdns),
+            ("bandwidth", &old.bandwidth, &cfg.bandwidth),
+            ("validation", &old.validation, &cfg.validation),
+            ("rollup", &old.rollup, &cfg.rollup),
+            ("clips", &old.clips, &cfg.clips),
+            ("gsensor", &old.gsensor, &cfg.gsensor),
+            ("gps", &old.gps, &cfg.gps),
+            ("obd", &old.obd, &cfg.obd),
+            ("live", &old.live, &cfg.live),
+            ("rules", &old.rules, &cfg.rules),
+            ("modes", &old.modes, &cfg.modes),
+            ("mode_mqtt", &old.mode_mqtt, &cfg.mode_mqtt),
+            ("janitor", &old.janitor, &cfg.janitor),
+            ("tiering", &old.tiering, &cfg.tiering),
+            ("storage_manager", &old.storage_manager, &cfg.storage_manager),
+            ("backfill", &old.backfill, &cfg.backfill),
+            ("usb_sync", &old.usb_sync, &cfg.usb_sync),
+            ("export", &old.export, &cfg.export),
+            ("time_sync", &old.time_sync, &cfg.time_sync),
+            ("throughput", &old.throughput, &cfg.throughput),
+            ("smart", &old.smart, &cfg.smart),
+            ("staging", &old.staging, &cfg.staging),
+            ("power_loss", &old.power_loss, &cfg.power_loss),
+            ("status_mqtt", &old.status_mqtt, &cfg.status_mqtt),
+        ];
+        report.restart_needed = fixed
+            .iter()
+            .filter(|(_, old, new)| changed(*old, *new))
+            .map(|(section, _, _)| *section)
+            .collect();
+
+        let AppConfig { events, standby, snapshot_upload, speed_events, storage_wear, .. } = cfg;
+        self.app_config.events = events;
+        self.app_config.standby = standby;
+        self.app_config.snapshot_upload = snapshot_upload;
+        self.app_config.speed_events = speed_events;
+        self.app_config.storage_wear = storage_wear;
         info!(
-            "Killed CamService at {}",
-            chrono::Local::now().format("%m-%d-%Y %H:%M:%S")
+            "Config reloaded; applied: {:?}, needs a restart: {:?}",
+            report.applied, report.restart_needed
+        );
+        Ok(report)
+    }
+
+    fn pipeline(&self, camera_key: &str) -> Result<&Arc<Mutex<RecordingPipeline>>> {
+        self.pipelines
+            .iter()
+            .find(|p| p.lock().unwrap().camera_key() == camera_key)
+            .with_context(|| format!("No pipeline for camera '{}'", camera_key))
+    }
+
+    /// Control API: cut a `[clips]` clip of `camera_key` around now.
+    pub fn save_clip(&self, camera_key: &str, kind: EventKind) -> Result<()> {
+        self.camera_id(camera_key)?;
+        if !self.routing.clips.cut(camera_key, &CameraEvent::now(kind)) {
+            bail!("Clips are off ([clips] isn't configured)");
+        }
+        Ok(())
+    }
+
+    /// Control API entry point: run `command` on behalf of `actor` and record it,
+    /// with its outcome, in the audit log.
+    pub fn execute(&self, actor: &str, command: ControlCommand) -> Result<ControlReply> {
+        let action = command.action();
+        let target = command.target();
+
+        let result = match command {
+            ControlCommand::SetSinkEnabled { camera_key, sink_id, enabled } => self
+                .set_sink_enabled(&camera_key, sink_id, enabled)
+                .map(|_| ControlReply::Done),
+            ControlCommand::TriggerEvent { camera_key, kind } => self
+                .trigger_event(&camera_key, kind)
+                .map(ControlReply::EventRaised),
+            ControlCommand::RecentEvents { camera_key, limit } => self
+                .recent_events(&camera_key, limit)
+                .map(ControlReply::Events),
+            ControlCommand::AuditLog { limit } => self.recent_audit(limit).map(ControlReply::Audit),
+            ControlCommand::SetStandby { standby: true } => self.enter_standby().map(ControlReply::PowerState),
+            ControlCommand::SetStandby { standby: false } => self.exit_standby().map(ControlReply::PowerState),
+            ControlCommand::SetSetting { key, value } => self.set_setting(&key, &value).map(|_| ControlReply::Done),
+            ControlCommand::GetSetting { key } => self
+                .get_setting(&key)
+                .map(|value| ControlReply::Setting { key, value }),
+            ControlCommand::SplitSegments { camera_key } => self
+                .split_segments(&camera_key)
+                .map(|_| ControlReply::Done),
+            ControlCommand::SaveClip { camera_key, kind } => self
+                .save_clip(&camera_key, kind)
+                .map(|_| ControlReply::Done),
+            ControlCommand::SetPrivacy { camera_key, private } => self
+                .set_privacy(&camera_key, private)
+                .map(|_| ControlReply::Done),
+            ControlCommand::StopCamera { camera_key } => self.stop_camera(&camera_key).map(|_| ControlReply::Done),
+            ControlCommand::StartCamera { camera_key } => self.start_camera(&camera_key).map(|_| ControlReply::Done),
+            ControlCommand::Rules => self.rules().map(ControlReply::Rules),
+            ControlCommand::SetRuleArmed { name, armed } => self
+                .set_rule_armed(&name, armed)
+                .map(|_| ControlReply::Done),
+            ControlCommand::Mode => self.mode_status().map(ControlReply::Mode),
+            ControlCommand::SetMode { name } => self.set_mode(&name, actor).map(|_| ControlReply::Done),
+            ControlCommand::RecentIncidents { limit } => self.recent_incidents(limit).map(ControlReply::Incidents),
+            ControlCommand::BundleIncident { incident_id } => self.bundle_incident(incident_id).map(ControlReply::Bundle),
+            ControlCommand::Track { range, format } => self
+                .track(range, format)
+                .map(|body| ControlReply::Track { format, body }),
+            ControlCommand::ProtectSegments { camera_key, kind, before_sec } => self
+                .protect_segments(&camera_key, kind, before_sec)
+                .map(ControlReply::Protecting),
+            ControlCommand::Playlist { camera_key, from_utc, to_utc } => self
+                .playlist(&camera_key, from_utc, to_utc)
+                .map(ControlReply::Playlist),
+            ControlCommand::Rewind { camera_key, start } => self.rewind(&camera_key, start).map(ControlReply::Playlist),
+            ControlCommand::Status => Ok(ControlReply::Status(self.status())),
+        };
+
+        if let Err(e) = &result {
+            error!("Control command '{}' by '{}' failed: {:#}", action, actor, e);
+        }
+        self.record_audit(actor, action, target, result.as_ref().err().map(|e| format!("{:#}", e)));
+        result
+    }
+
+    /// Audit a command that was refused before it ran (e.g. the token's role is too low).
+    pub fn audit_denied(&self, actor: &str, command: &ControlCommand, reason: &str) {
+        info!("Denied '{}' for '{}': {}", command.action(), actor, reason);
+        self.record_audit(actor, command.action(), command.target(), Some(reason.to_string()));
+    }
+
+    fn record_audit(&self, actor: &str, action: &str, target: Option<String>, error: Option<String>) {
+        let _ = self.db_sender.send(DBMessage::InsertAudit {
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target,
+            ok: error.is_none(),
+            result: error,
+        });
+    }
+
+    /// Control API: switch a sink on/off while running and persist the choice in the DB,
+    /// so it wins over the `enabled` flag in config.toml on the next start.
+    pub fn set_sink_enabled(&self, camera_key: &str, sink_id: i64, enabled: bool) -> Result<()> {
+        self.pipeline(camera_key)?.lock().unwrap().set_sink_enabled(sink_id, enabled)?;
+
+        let camera_id = self.camera_id(camera_key)?;
+        self.db_sender.send(DBMessage::SetSinkEnabled {
+            camera_id,
+            sink_id,
+            enabled,
+        })?;
+        Ok(())
+    }
+
+    /// Control API: finish the camera's current files now, e.g. so an incident can
+    /// be pulled off the card without waiting out the segment.
+    pub fn split_segments(&self, camera_key: &str) -> Result<()> {
+        self.pipeline(camera_key)?.lock().unwrap().split_segments()
+    }
+
+    /// Most recent events for a camera, newest first.
+    pub fn recent_events(&self, camera_key: &str, limit: i64) -> Result<Vec<EventRow>> {
+        let camera_id = self.camera_id(camera_key)?;
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetRecentEvents { camera_id, limit, reply: tx })?;
+        Ok(rx.recv()?)
+    }
+
+    /// Most recent control-plane commands, newest first.
+    pub fn recent_audit(&self, limit: i64) -> Result<Vec<AuditRow>> {
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetRecentAudit { limit, reply: tx })?;
+        Ok(rx.recv()?)
+    }
+
+    /// Most recent group incidents, newest first.
+    pub fn recent_incidents(&self, limit: i64) -> Result<Vec<IncidentRow>> {
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetRecentIncidents { limit, reply: tx })?;
+        Ok(rx.recv()?)
+    }
+
+    /// Control API ("save this moment"): keep the camera's last `before_sec` of
+    /// ring segments, and the one being written, from being overwritten.
+    pub fn protect_segments(&self, camera_key: &str, kind: EventKind, before_sec: u64) -> Result<ProtectInfo> {
+        self.protect_window(camera_key, kind, before_sec, 0)
+    }
+
+    /// `protect_segments` of a window that reaches `after_sec` past now; the
+    /// segments are saved once it's over.
+    fn protect_window(
+        &self,
+        camera_key: &str,
+        kind: EventKind,
+        before_sec: u64,
+        after_sec: u64,
+    ) -> Result<ProtectInfo> {
+        let camera_id = self.camera_id(camera_key)?;
+        let pipeline = self.pipeline(camera_key)?.clone();
+        let now = chrono::Local::now();
+        let save_dir = PathBuf::from(&self.app_config.profile().recording_save_dir);
+        let request = ProtectRequest {
+            camera_id,
+            from_utc: now.timestamp() - before_sec as i64,
+            to_utc: now.timestamp() + after_sec as i64,
+            kind,
+            recording_root: PathBuf::from(self.app_config.recording_root()),
+            dir: protect::protected_dir(&save_dir, camera_key, kind, now),
+        };
+        let info = ProtectInfo {
+            dir: request.dir.to_string_lossy().to_string(),
+            from_utc: request.from_utc,
+            to_utc: request.to_utc,
+        };
+        let db_sender = self.db_sender.clone();
+        let camera_key = camera_key.to_string();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_secs(after_sec));
+            // close the segment being written, so it's catalogued and saved too
+            if let Err(e) = pipeline.lock().unwrap().split_segments() {
+                trace!("Camera '{}' not recording, saving what's catalogued: {:#}", camera_key, e);
+            }
+            std::thread::sleep(protect::CATALOG_DELAY);
+            // the DB worker only finds the segments; linking (or copying) them happens here
+            let (tx, rx) = mpsc::channel();
+            let (camera_id, from_utc, to_utc) = (request.camera_id, request.from_utc, request.to_utc);
+            if db_sender.send(DBMessage::GetProtectPlan { camera_id, from_utc, to_utc, reply: tx }).is_err() {
+                return;
+            }
+            let Ok(Some(plan)) = rx.recv() else {
+                warn!("Camera '{}': saving segments failed", camera_key);
+                return;
+            };
+            match protect::save_segments(&request, plan) {
+                Ok((summary, saved)) => {
+                    if summary.linked + summary.copied + summary.already == 0 {
+                        warn!("Camera '{}': no recordings to save around {}", camera_key, now.format("%H:%M:%S"));
+                    }
+                    let saved_at_utc = chrono::Utc::now().timestamp();
+                    let _ = db_sender.send(DBMessage::InsertProtectedSegments { camera_id, kind, saved, saved_at_utc });
+                }
+                Err(e) => warn!("Camera '{}': saving segments failed: {:#}", camera_key, e),
+            }
+        });
+        Ok(info)
+    }
+
+    /// Control API: zip an incident's clips, GPS track and timeline next to its folder.
+    /// The zip is written on its own thread, so the service isn't held up meanwhile;
+    /// asking again before it's done is refused.
+    pub fn bundle_incident(&self, incident_id: i64) -> Result<BundleInfo> {
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetIncidentRecord { incident_id, reply: tx })?;
+        let record = rx.recv()?.with_context(|| format!("No incident {}", incident_id))?;
+        if record.clips.is_empty() {
+            bail!("Incident {} has no finished clips yet", incident_id);
+        }
+        let output = record.default_output();
+        let recording_root = PathBuf::from(self.app_config.recording_root());
+        let info = BundleInfo {
+            path: output.display().to_string(),
+            download: output.strip_prefix(&recording_root).ok().map(|rel| format!("/files/{}", rel.display())),
+        };
+        if !self.bundling.lock().unwrap().insert(incident_id) {
+            bail!("Incident {} is already being bundled", incident_id);
+        }
+        let bundling = self.bundling.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = write_bundle(&record, &output) {
+                error!("Incident {} bundle failed: {:#}", incident_id, e);
+            }
+            bundling.lock().unwrap().remove(&incident_id);
+        });
+        Ok(info)
+    }
+
+    /// GPS track of a trip or time range as a GPX or GeoJSON document.
+    pub fn track(&self, range: TrackRange, format: TrackFormat) -> Result<String> {
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetTrack { range, reply: tx })?;
+        let points = rx.recv()?.with_context(|| format!("No {}", range.name()))?;
+        Ok(track::render(format, range, &points))
+    }
+
+    /// HLS playlist of a camera's catalogued segments overlapping a time range,
+    /// with discontinuities marked (see `playlist`).
+    pub fn playlist(&self, camera_key: &str, from_utc: i64, to_utc: i64) -> Result<String> {
+        let camera_id = self.camera_id(camera_key)?;
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetSegmentsBetween { camera_id, from_utc, to_utc, reply: tx })?;
+        let rows = rx.recv()?.context("Failed to look up segments")?;
+        if rows.is_empty() {
+            bail!("No recordings of {} in that range", camera_key);
+        }
+        Ok(playlist::archive_playlist(&rows))
+    }
+
+    /// Rewind of a camera's live view: a master playlist that pins "`minutes`
+    /// ago" to a time, or the growing playlist from that time.
+    pub fn rewind(&self, camera_key: &str, start: RewindStart) -> Result<String> {
+        let camera_id = self.camera_id(camera_key)?;
+        let to_utc = chrono::Utc::now().timestamp();
+        let from_utc = match start {
+            RewindStart::MinutesAgo(minutes) => to_utc - minutes as i64 * 60,
+            RewindStart::At(from_utc) => from_utc,
+        };
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetSegmentsBetween { camera_id, from_utc, to_utc, reply: tx })?;
+        let rows = rx.recv()?.context("Failed to look up segments")?;
+        if rows.is_empty() {
+            bail!("No recordings of {} since {}", camera_key, from_utc);
+        }
+        Ok(match start {
+            RewindStart::MinutesAgo(_) => playlist::rewind_master(camera_key, from_utc, &rows),
+            RewindStart::At(_) => {
+                let segment_sec =
+                    self.pipeline(camera_key)?.lock().unwrap().config().segment_length().as_secs_f64().ceil() as u64;
+                playlist::rewind_playlist(&rows, segment_sec)
+            }
+        })
+    }
+
+    /// Store a runtime setting in the DB; `subscribe_settings` receivers see the change.
+    pub fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        if key.is_empty() {
+            anyhow::bail!("Setting key must not be empty");
+        }
+        self.db_sender.send(DBMessage::SetSetting {
+            key: key.to_string(),
+            value: value.to_string(),
+        })?;
+        Ok(())
+    }
+
+    pub fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetSetting { key: key.to_string(), reply: tx })?;
+        Ok(rx.recv()?)
+    }
+
+    /// Changes to runtime settings from now on (read current values with `get_setting`).
+    pub fn subscribe_settings(&self) -> Result<Receiver<SettingChange>> {
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::SubscribeSettings { subscriber: tx })?;
+        Ok(rx)
+    }
+
+    fn camera_id(&self, camera_key: &str) -> Result<i64> {
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetCameraIdByKey {
+            camera_key: camera_key.to_string(),
+            reply: tx,
+        })?;
+        rx.recv()?
+            .ok_or_else(|| anyhow!("No camera_id for camera '{}'", camera_key))
+    }
+
+    /// Library API: get decoded frames from a camera for an external analyzer.
+    /// Call between `CamService::new` and `main_loop`.
+    pub fn add_frame_tap(&self, camera_key: &str, tap_config: FrameTapConfig, target: FrameTarget) -> Result<i64> {
+        let pipeline_arc = self
+            .pipelines
+            .iter()
+            .find(|p| p.lock().unwrap().camera_key() == camera_key)
+            .with_context(|| format!("No pipeline for camera '{}'", camera_key))?;
+        pipeline_arc.lock().unwrap().add_frame_tap(tap_config, target)
+    }
+
+    /// Raise an event on one camera. G-sensor and motion sources call this too.
+    /// Returns false if the event was debounced (see `[events]` in config.toml).
+    /// Events listed in `[standby] wake_on` leave standby first.
+    pub fn trigger_event(&self, camera_key: &str, kind: EventKind) -> Result<bool> {
+        self.raise(camera_key, CameraEvent::now(kind))
+    }
+
+    /// `trigger_event` with the details of `event`.
+    fn raise(&self, camera_key: &str, event: CameraEvent) -> Result<bool> {
+        if self.app_config.standby.wake_on.contains(&event.kind) && self.power_state() == PowerState::Standby {
+            info!("{} event on '{}' wakes from standby", event.kind, camera_key);
+            self.exit_standby()?;
+        }
+        let pipeline_arc = self
+            .pipelines
+            .iter()
+            .find(|p| p.lock().unwrap().camera_key() == camera_key)
+            .with_context(|| format!("No running pipeline for camera '{}'", camera_key))?;
+        Ok(raise_event(pipeline_arc, &self.db_sender, &self.event_filter, &self.routing, event))
+    }
+
+    /// The G-sensor felt an impact of `g`: a `gsensor` event on the `[gsensor]`
+    /// cameras (all of them when none are listed), and their footage around it saved.
+    pub fn impact(&self, g: f64) {
+        let Some(cfg) = &self.app_config.gsensor else {
+            return;
+        };
+        let keys: Vec<String> = self.pipelines.iter().map(|p| p.lock().unwrap().camera_key().to_string()).collect();
+        for key in keys.iter().filter(|k| cfg.cameras.is_empty() || cfg.cameras.contains(k)) {
+            let event = CameraEvent { score: Some(g as f32), ..CameraEvent::now(EventKind::GSensor) };
+            if let Err(e) = self.raise(key, event) {
+                warn!("Camera '{}' gsensor event: {:#}", key, e);
+            }
+            match self.protect_window(key, EventKind::GSensor, cfg.before_sec, cfg.after_sec) {
+                Ok(info) => info!("Camera '{}': saving the impact to {} in {}s", key, info.dir, cfg.after_sec),
+                Err(e) => warn!("Camera '{}': can't save the impact: {:#}", key, e),
+            }
+        }
+    }
+
+    /// A GPS fix came in: show it on the `overlay_gps` cameras and raise the
+    /// `[speed_events]` it triggers on the configured cameras (all of them when
+    /// none are listed).
+    pub fn gps_fix(&self, point: &GpsPoint) {
+        self.latest_fix.set(point);
+        let kinds = match self.speed_rules.lock().unwrap().as_mut() {
+            Some(rules) => rules.feed(point),
+            None => return,
+        };
+        let Some(cfg) = &self.app_config.speed_events else {
+            return;
+        };
+        for kind in kinds {
+            trace!("GPS fix at {} triggers a {} event", point.at_utc, kind);
+            let keys: Vec<String> = self.pipelines.iter().map(|p| p.lock().unwrap().camera_key().to_string()).collect();
+            for key in keys.iter().filter(|k| cfg.cameras.is_empty() || cfg.cameras.contains(k)) {
+                if let Err(e) = self.trigger_event(key, kind) {
+                    warn!("Camera '{}' {} event: {:#}", key, kind, e);
+                }
+            }
+        }
+    }
+
+    /// Segment validator over every camera, raising `corrupt` events through the
+    /// usual filter so they land in the DB and reach the sinks.
+    fn start_validator(&mut self) {
+        let Some(cfg) = &self.app_config.validation else {
+            return;
+        };
+        if self.validator.is_some() {
+            return;
+        }
+        let cameras = self
+            .pipelines
+            .iter()
+            .map(|p| {
+                let pipeline = p.lock().unwrap();
+                ValidatedCamera {
+                    key: pipeline.camera_key().to_string(),
+                    dir: PathBuf::from(&pipeline.config().recording_dir),
+                    segment_duration: pipeline.config().segment_length(),
+                }
+            })
+            .collect();
+        let pipelines = self.pipelines.clone();
+        let db_sender = self.db_sender.clone();
+        let event_filter = self.event_filter.clone();
+        let routing = self.routing.clone();
+        info!("Starting segment validator (every {}s)", cfg.interval_sec);
+        self.validator = Some(spawn_validation(cfg, cameras, move |camera_key, event| {
+            error!("Camera '{}' is producing corrupt segments", camera_key);
+            if let Some(pipeline) = pipelines.iter().find(|p| p.lock().unwrap().camera_key() == camera_key) {
+                raise_event(pipeline, &db_sender, &event_filter, &routing, event);
+            }
+        }));
+    }
+
+    fn start_rollup(&mut self) {
+        let Some(cfg) = &self.app_config.rollup else {
+            return;
+        };
+        if self.rollup.is_some() {
+            return;
+        }
+        let cameras: Vec<RolledCamera> = self
+            .app_config
+            .cameras
+            .iter()
+            .filter(|c| c.enabled && cfg.includes(&c.key))
+            .filter(|c| c.sinks.iter().any(|s| matches!(s, SinkConfig::DashcamTs { .. })))
+            .filter_map(|c| {
+                let pipeline = self.pipelines.iter().find(|p| p.lock().unwrap().camera_key() == c.key)?;
+                Some(RolledCamera {
+                    key: c.key.clone(),
+                    ring_dir: PathBuf::from(&pipeline.lock().unwrap().config().recording_dir),
+                    codec: c.source.codec,
+                })
+            })
+            .collect();
+        if cameras.is_empty() {
+            warn!("[rollup] is set but no camera has a dashcamts ring to roll up");
+            return;
+        }
+        info!("Starting daily rollup into {} at {}", cfg.archive_dir, cfg.run_at);
+        self.rollup = Some(spawn_rollup(cfg, cameras));
+    }
+
+    fn start_janitor(&mut self) {
+        let Some(cfg) = &self.app_config.janitor else {
+            return;
+        };
+        if self.janitor.is_some() {
+            return;
+        }
+        let cameras = ring_cameras(&self.app_config, |key| self.camera_id(key).ok());
+        if cameras.is_empty() {
+            warn!("[janitor] is set but no camera has a dashcamts ring to clean up");
+            return;
+        }
+        info!(
+            "Starting ring janitor every {}s{}",
+            cfg.interval_sec,
+            if cfg.dry_run { " (dry run)" } else { "" }
         );
+        self.janitor = Some(spawn_janitor(
+            cfg,
+            cameras,
+            PathBuf::from(self.app_config.recording_root()),
+            self.db_sender.clone(),
+        ));
+    }
+
+    fn start_tiering(&mut self) {
+        let Some(cfg) = &self.app_config.tiering else {
+            return;
+        };
+        if self.tiering.is_some() {
+            return;
+        }
+        let recording_root = PathBuf::from(self.app_config.recording_root());
+        let profile = self.app_config.profile();
+        let cameras: Vec<TieredCamera> = self
+            .app_config
+            .cameras
+            .iter()
+            .filter(|c| c.enabled && cfg.includes(&c.key))
+            .filter_map(|c| {
+                let mut dirs = Vec::new();
+                for sink in &c.sinks {
+                    if let SinkConfig::NvrTs { disks, .. } = sink {
+                        dirs.push(recording_root.join(&c.key));
+                        let mounts = disks.iter().flat_map(|d| &d.mounts);
+                        dirs.extend(mounts.map(|mount| PathBuf::from(mount).join(&c.key)));
+                    }
+                }
+                dirs.sort();
+                dirs.dedup();
+                let width = c.video_width.unwrap_or(profile.video_width as i64);
+                let height = c.video_height.unwrap_or(profile.video_height as i64);
+                (!dirs.is_empty()).then(|| TieredCamera {
+                    key: c.key.clone(),
+                    dirs,
+                    codec: c.source.codec,
+                    width: cfg.width,
+                    // even, as the encoders want
+                    height: (cfg.width as i64 * height / width.max(1)) as u32 & !1,
+                })
+            })
+            .collect();
+        if cameras.is_empty() {
+            warn!("[tiering] is set but no camera has an nvrts sink to re-encode");
+            return;
+        }
+        info!(
+            "Re-encoding nvrts segments older than {} days at {} px wide, {} kbit/s",
+            cfg.after_days, cfg.width, cfg.bitrate_kbps
+        );
+        self.tiering = Some(spawn_tiering(cfg, cameras, recording_root, self.db_sender.clone()));
+    }
+
+    fn start_storage_manager(&mut self) {
+        let Some(cfg) = &self.app_config.storage_manager else {
+            return;
+        };
+        if self.storage_manager.is_some() {
+            return;
+        }
+        let recording_root = PathBuf::from(self.app_config.recording_root());
+        info!(
+            "Deleting the oldest segments when {} has less than {} MB free, up to {} MB",
+            recording_root.display(),
+            cfg.low_water_mb,
+            cfg.high_water_mb
+        );
+        self.storage_manager = Some(spawn_storage_manager(cfg, recording_root, self.db_sender.clone()));
+    }
+
+    fn start_backfill(&mut self) {
+        let Some(cfg) = &self.app_config.backfill else {
+            return;
+        };
+        if self.backfill.is_some() {
+            return;
+        }
+        if !cfg.thumbnails && !cfg.checksums {
+            warn!("[backfill] is set but both thumbnails and checksums are off");
+            return;
+        }
+        info!("Backfilling segment thumbnails/checksums every {}s", cfg.interval_sec);
+        self.backfill = Some(spawn_backfill(
+            cfg,
+            PathBuf::from(self.app_config.recording_root()),
+            PathBuf::from(self.app_config.main_dir()).join("thumbnails"),
+            self.db_sender.clone(),
+        ));
+    }
+
+    fn start_obd(&mut self) {
+        let Some(cfg) = &self.app_config.obd else {
+            return;
+        };
+        if self.obd.is_some() {
+            return;
+        }
+        info!("Polling OBD-II adapter on {} every {}ms", cfg.device, cfg.poll_ms);
+        self.obd = Some(spawn_obd(cfg, self.db_sender.clone()));
+    }
+
+    fn start_usb_sync(&mut self) {
+        let Some(cfg) = &self.app_config.usb_sync else {
+            return;
+        };
+        if self.usb_sync.is_some() {
+            return;
+        }
+        let save_dir = PathBuf::from(&self.app_config.profile().recording_save_dir);
+        info!("Copying {} to USB sticks mounted under {}", save_dir.display(), cfg.mount_root);
+        self.usb_sync = Some(spawn_usb_sync(cfg, save_dir));
+    }
+
+    fn start_smart(&mut self) {
+        let Some(cfg) = &self.app_config.smart else {
+            return;
+        };
+        if self.smart.is_some() {
+            return;
+        }
+        info!("Polling the health of {} every {}s", cfg.devices.join(", "), cfg.interval_sec);
+        self.smart = Some(SmartWorker::spawn(cfg, self.db_sender.clone()));
+    }
+
+    /// Overlay worker over the cameras with `overlay_gps`.
+    fn start_gps_overlay(&mut self) {
+        if self.gps_overlay.is_some() {
+            return;
+        }
+        let keys: Vec<&str> =
+            self.app_config.cameras.iter().filter(|c| c.enabled && c.overlay_gps).map(|c| c.key.as_str()).collect();
+        let pipelines: Vec<_> =
+            self.pipelines.iter().filter(|p| keys.contains(&p.lock().unwrap().camera_key())).cloned().collect();
+        if pipelines.is_empty() {
+            return;
+        }
+        info!("Showing GPS speed and position on {}", keys.join(", "));
+        self.gps_overlay = Some(spawn_gps_overlay(self.latest_fix.clone(), pipelines));
+    }
+
+    /// Clip worker over the cameras with a `dashcamts` ring. Runs from construction
+    /// on, so events raised by the detectors are covered too.
+    fn start_clips(&mut self) {
+        let Some(cfg) = &self.app_config.clips else {
+            return;
+        };
+        let cameras: Vec<ClipCamera> = self
+            .app_config
+            .cameras
+            .iter()
+            .filter(|c| c.enabled && c.sinks.iter().any(|s| matches!(s, SinkConfig::DashcamTs { .. })))
+            .filter_map(|c| {
+                let pipeline = self.pipelines.iter().find(|p| p.lock().unwrap().camera_key() == c.key)?;
+                let pipeline = pipeline.lock().unwrap();
+                Some(ClipCamera {
+                    key: c.key.clone(),
+                    ring_dir: PathBuf::from(&pipeline.config().recording_dir),
+                    segment_duration: pipeline.config().segment_length(),
+                })
+            })
+            .collect();
+        if cameras.is_empty() {
+            warn!("[clips] is set but no camera has a dashcamts ring to cut clips from");
+            return;
+        }
+        let save_dir = PathBuf::from(&self.app_config.profile().recording_save_dir);
+        info!("Cutting {}s+{}s clips of events into {}", cfg.pre_roll_sec, cfg.post_roll_sec, save_dir.display());
+        let (worker, queue) = spawn_clips(cfg, save_dir, cameras, self.db_sender.clone());
+        self.clips = Some(worker);
+        self.routing.clips = queue;
+    }
+
+    /// Confirmed-detection gate for a camera with `[cameras.detection]`.
+    pub fn detection_gate(&self, camera_key: &str) -> Option<Arc<DetectionGate>> {
+        self.detection_gates.get(camera_key).cloned()
+    }
+
+    /// Log each camera's effective ROI and write it to `<recording_root>/<key>/roi_mask.pgm`
+    /// at the camera's resolution, for the UI to overlay.
+    fn export_roi_masks(&self) -> Result<()> {
+        let profile = self.app_config.profile();
+        for cam in &self.app_config.cameras {
+            let width = cam.video_width.unwrap_or(profile.video_width as i64) as u32;
+            let height = cam.video_height.unwrap_or(profile.video_height as i64) as u32;
+            let dir = PathBuf::from(self.app_config.recording_root()).join(&cam.key);
+            Roi::from_config(&cam.roi).log_and_export(&cam.key, &dir, width, height)?;
+        }
+        Ok(())
+    }
+
+    /// Frame tap + detector thread for every camera with `[cameras.detection]`.
+    fn start_detectors(&mut self) -> Result<()> {
+        for cam in &self.app_config.cameras {
+            let Some(detection) = cam.detection.clone() else {
+                continue;
+            };
+            let Some(pipeline_arc) = self
+                .pipelines
+                .iter()
+                .find(|p| p.lock().unwrap().camera_key() == cam.key)
+                .cloned()
+            else {
+                continue;
+            };
+
+            let (target, frames) = FrameTarget::channel(1);
+            let tap_config = FrameTapConfig {
+                max_fps: detection.fps,
+                width: Some(detection.input_size as i32),
+                height: Some(detection.input_size as i32),
+            };
+            pipeline_arc.lock().unwrap().add_frame_tap(tap_config, target)?;
+
+            let gate = Arc::new(DetectionGate::default());
+            let db_sender = self.db_sender.clone();
+            let event_gate = gate.clone();
+            let event_filter = self.event_filter.clone();
+            let routing = self.routing.clone();
+            let roi = Roi::from_config(&cam.roi);
+            let handle = spawn_detector(cam.key.clone(), detection, roi, frames, move |event| {
+                event_gate.confirm();
+                raise_event(&pipeline_arc, &db_sender, &event_filter, &routing, event);
+            })?;
+
+            self.detection_gates.insert(cam.key.clone(), gate);
+            self.detector_handles.push(handle);
+        }
+        Ok(())
+    }
+
+    /// Frame tap + frame-differencing thread for every camera with `[cameras.motion]`.
+    /// With `record_mode = "motion"`, the camera's segment file sinks get a pre-roll
+    /// and are triggered by motion (and, in `raise_event`, by every other event).
+    fn start_motion(&mut self) -> Result<()> {
+        let profile = self.app_config.profile();
+        for cam in &self.app_config.cameras {
+            let Some(motion) = cam.motion.clone() else {
+                continue;
+            };
+            let Some(pipeline_arc) = self
+                .pipelines
+                .iter()
+                .find(|p| p.lock().unwrap().camera_key() == cam.key)
+                .cloned()
+            else {
+                continue;
+            };
+
+            let width = cam.video_width.unwrap_or(profile.video_width as i64);
+            let height = cam.video_height.unwrap_or(profile.video_height as i64);
+            let (target, frames) = FrameTarget::channel(1);
+            let tap_config = FrameTapConfig {
+                max_fps: motion.fps,
+                width: Some(motion.width as i32),
+                // even, as most raw formats want
+                height: Some((motion.width as i64 * height / width.max(1)) as i32 & !1),
+            };
+
+            let triggered = {
+                let mut pipeline = pipeline_arc.lock().unwrap();
+                pipeline.add_frame_tap(tap_config, target)?;
+                let triggered: Vec<i64> = cam
+                    .sinks
+                    .iter()
+                    .filter(|s| cam.record_mode == RecordMode::Motion && s.muxer().is_some())
+                    .map(SinkConfig::sink_id)
+                    .collect();
+                for sink_id in &triggered {
+                    pipeline.set_sink_triggered(
+                        *sink_id,
+                        Duration::from_secs(motion.pre_roll_sec),
+                        Duration::from_secs(motion.post_roll_sec),
+                    )?;
+                }
+                triggered
+            };
+            if !triggered.is_empty() {
+                info!(
+                    "Camera '{}' records sinks {:?} only on motion ({} s pre-roll, {} s post-roll)",
+                    cam.key, triggered, motion.pre_roll_sec, motion.post_roll_sec
+                );
+            }
+
+            let db_sender = self.db_sender.clone();
+            let event_filter = self.event_filter.clone();
+            let routing = self.routing.clone();
+            let event_pipeline = pipeline_arc.clone();
+            let on_motion = move |area: f32| {
+                // raw motion keeps the sinks armed, also while its events are debounced
+                event_pipeline.lock().unwrap().trigger(Instant::now());
+                let event = CameraEvent { score: Some(area), ..CameraEvent::now(EventKind::Motion) };
+                raise_event(&event_pipeline, &db_sender, &event_filter, &routing, event);
+            };
+            // a pipeline busy starting or stopping gets its post-roll checked on the next frame or tick
+            let on_tick = move || {
+                if let Ok(mut pipeline) = pipeline_arc.try_lock() {
+                    pipeline.expire_triggers(Instant::now());
+                }
+            };
+            let roi = Roi::from_config(&cam.roi);
+            let handle = spawn_motion(cam.key.clone(), motion, roi, frames, on_motion, on_tick);
+            self.detector_handles.push(handle);
+        }
         Ok(())
     }
 
     fn prep_dir_for_service(&self) -> Result<()> {
         // Create directories
-        fs::create_dir_all(&self.app_config.global.recording_root)?;
+        let recording_root = self.app_config.recording_root();
+        fs::create_dir_all(&recording_root)?;
+
+        // Segments a crash left in the staging dir
+        if let Some(staging) = &self.app_config.staging {
+            fs::create_dir_all(&staging.dir)?;
+            Staging { dir: PathBuf::from(&staging.dir), recording_root: PathBuf::from(&recording_root) }.move_leftovers();
+        } else if is_network_fs(std::path::Path::new(&recording_root)) {
+            warn!("{} is a network share; without [staging] a dropped connection stops recording", recording_root);
+        }
 
         // Delete any segment*.ts or livestream.m3u8
         let segment_regex = Regex::new(r"segment\d*\.ts")?;
 
-        for entry in fs::read_dir(&self.app_config.global.recording_root)? {
+        for entry in fs::read_dir(&recording_root)? {
             let entry = entry?;
             if entry.file_type()?.is_dir() {
                 for dir in fs::read_dir(entry.path())? {
@@ -0,0 +1,431 @@
+//! Automatic event clips (`[clips]` in config.toml).
+//!
+//! An event of one of the configured kinds (G-sensor by default) is turned into
+//! a finished MP4 with pre- and post-roll in
+//! `<recording_save_dir>/<camera>/<YYYY-MM-DD>/<HH-MM-SS>_<kind>.mp4`, so an
+//! impact can be handed over without digging through the ring. The worker waits
+//! until the ring segment holding the end of the post-roll has been closed, then
+//! remuxes the covering segments with the exporter.
+//!
+//! Cameras listed together in a `[[clips.groups]]` entry (front, rear, cabin)
+//! are cut as one incident: an event on any of them clips the same window on
+//! all of them into
+//! `<recording_save_dir>/incidents/<YYYY-MM-DD>/<HH-MM-SS>_<kind>_<group>/<camera>.mp4`,
+//! and the clips are filed in the DB under a shared incident id.
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::time::{Duration, SystemTime};
+use tracing::{info, trace, warn};
+
+use crate::db::db_worker::DBMessage;
+use crate::events::{CameraEvent, EventKind};
+use crate::export::export_segments;
+use crate::legacy_import::{LegacySegment, scan_legacy_dir};
+use crate::worker::Worker;
+
+/// Give up waiting for the post-roll segment this long after it should have closed
+const CLOSE_GRACE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClipConfig {
+    /// Event kinds that produce a clip
+    pub kinds: Vec<EventKind>,
+    pub pre_roll_sec: u64,
+    pub post_roll_sec: u64,
+    /// Cameras whose clips are cut together, see the module docs
+    pub groups: Vec<ClipGroup>,
+}
+
+/// `[[clips.groups]]`: cameras that record one incident together.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ClipGroup {
+    /// Part of the incident folder's name
+    pub name: String,
+    /// Camera keys
+    pub cameras: Vec<String>,
+}
+
+impl ClipConfig {
+    /// Group `camera_key` belongs to, if any.
+    pub fn group_of(&self, camera_key: &str) -> Option<&ClipGroup> {
+        self.groups.iter().find(|g| g.cameras.iter().any(|c| c == camera_key))
+    }
+
+    /// Groups need a name usable as a path component, known cameras, and no
+    /// camera may be in two of them.
+    pub fn groups_valid(&self, camera_keys: &[&str]) -> bool {
+        self.groups.iter().enumerate().all(|(i, group)| {
+            !group.name.is_empty()
+                && !group.name.contains('/')
+                && !group.cameras.is_empty()
+                && self.groups[..i].iter().all(|g| g.name != group.name)
+                && group.cameras.iter().enumerate().all(|(j, key)| {
+                    camera_keys.contains(&key.as_str())
+                        && !group.cameras[..j].contains(key)
+                        && self.groups[..i].iter().all(|g| !g.cameras.contains(key))
+                })
+        })
+    }
+}
+
+impl Default for ClipConfig {
+    fn default() -> Self {
+        Self {
+            kinds: vec![EventKind::GSensor],
+            pre_roll_sec: 20,
+            post_roll_sec: 10,
+            groups: Vec::new(),
+        }
+    }
+}
+
+/// One camera's view for the worker.
+#[derive(Debug, Clone)]
+pub struct ClipCamera {
+    pub key: String,
+    /// Where the camera's `dashcamts` ring lives
+    pub ring_dir: PathBuf,
+    pub segment_duration: Duration,
+}
+
+/// Shared by the clips of one group incident.
+#[derive(Debug, Clone, PartialEq)]
+struct PendingIncident {
+    /// `None` if the DB couldn't record it; the clips are still cut
+    id: Option<i64>,
+    dir: PathBuf,
+}
+
+/// A clip being waited for. Events that land inside it extend it instead of
+/// starting another one.
+#[derive(Debug, Clone)]
+struct PendingClip {
+    camera_key: String,
+    event: CameraEvent,
+    from: SystemTime,
+    to: SystemTime,
+    incident: Option<PendingIncident>,
+}
+
+/// `<save_dir>/<camera>/<YYYY-MM-DD>/<HH-MM-SS>_<kind>.mp4`
+pub fn clip_path(save_dir: &Path, camera_key: &str, event: &CameraEvent) -> PathBuf {
+    save_dir
+        .join(camera_key)
+        .join(event.at.format("%Y-%m-%d").to_string())
+        .join(format!("{}_{}.mp4", event.at.format("%H-%M-%S"), event.kind))
+}
+
+/// `<save_dir>/incidents/<YYYY-MM-DD>/<HH-MM-SS>_<kind>_<group>`, holding `<camera>.mp4` per camera
+pub fn incident_dir(save_dir: &Path, group: &str, event: &CameraEvent) -> PathBuf {
+    save_dir
+        .join("incidents")
+        .join(event.at.format("%Y-%m-%d").to_string())
+        .join(format!("{}_{}_{}", event.at.format("%H-%M-%S"), event.kind, group))
+}
+
+/// Ring segments covering `[from, to)`, oldest first, or `None` while the segment
+/// holding `to` is still being written. The newest segment counts as open unless
+/// `include_open`. A segment ends at its mtime and starts where the one before it
+/// ended (the first one `segment_duration` earlier).
+pub fn select_clip_segments(
+    segments: &[LegacySegment],
+    from: SystemTime,
+    to: SystemTime,
+    segment_duration: Duration,
+    include_open: bool,
+) -> Option<Vec<PathBuf>> {
+    let closed = if include_open { segments } else { &segments[..segments.len().saturating_sub(1)] };
+    if !include_open && !closed.iter().any(|s| s.modified >= to) {
+        return None;
+    }
+    let mut start = closed.first().map(|s| s.modified - segment_duration);
+    let mut files = Vec::new();
+    for segment in closed {
+        if segment.modified > from && start.is_some_and(|start| start < to) {
+            files.push(segment.path.clone());
+        }
+        start = Some(segment.modified);
+    }
+    Some(files)
+}
+
+/// Handle for raising clips; cheap to clone into event callbacks. Does nothing
+/// without `[clips]`.
+#[derive(Clone, Default)]
+pub struct ClipQueue {
+    sender: Option<Sender<(String, CameraEvent)>>,
+    kinds: Vec<EventKind>,
+}
+
+impl ClipQueue {
+    /// Clip `event` if its kind is one of `[clips] kinds`.
+    pub fn submit(&self, camera_key: &str, event: &CameraEvent) {
+        if self.kinds.contains(&event.kind) {
+            self.cut(camera_key, event);
+        }
+    }
+
+    /// Clip `event` whatever its kind; false without `[clips]`.
+    pub fn cut(&self, camera_key: &str, event: &CameraEvent) -> bool {
+        match &self.sender {
+            Some(sender) => sender.send((camera_key.to_string(), event.clone())).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Clip thread. Group incidents and their clips are recorded through `db_sender`.
+/// Clips still waiting for their post-roll when it's stopped are dropped.
+pub fn spawn_clips(
+    cfg: &ClipConfig,
+    save_dir: PathBuf,
+    cameras: Vec<ClipCamera>,
+    db_sender: Arc<Sender<DBMessage>>,
+) -> (Worker, ClipQueue) {
+    let (sender, receiver) = channel();
+    let queue = ClipQueue { sender: Some(sender), kinds: cfg.kinds.clone() };
+    let cfg = cfg.clone();
+    let mut pending: Vec<PendingClip> = Vec::new();
+    let worker = Worker::spawn("clips", Duration::ZERO, move |stop| {
+        tick(&mut pending, &cfg, &save_dir, &cameras, &db_sender, &receiver, stop)
+    });
+    (worker, queue)
+}
+
+/// Add `event` of `camera_key` to `pending`. Landing inside a pending clip
+/// extends it, and with it the rest of its incident so the windows stay
+/// aligned. Otherwise it starts a clip, or for a grouped camera one clip per
+/// group camera over the same window; `new_incident` records that incident
+/// and returns its id.
+fn add_event(
+    pending: &mut Vec<PendingClip>,
+    cfg: &ClipConfig,
+    save_dir: &Path,
+    camera_key: &str,
+    event: CameraEvent,
+    new_incident: impl FnOnce(&ClipGroup, &CameraEvent, &Path) -> Option<i64>,
+) {
+    let at = SystemTime::from(event.at);
+    let from = at - Duration::from_secs(cfg.pre_roll_sec);
+    let to = at + Duration::from_secs(cfg.post_roll_sec);
+
+    if let Some(i) = pending
+        .iter()
+        .position(|c| c.camera_key == camera_key && (c.from..=c.to).contains(&at))
+    {
+        trace!("Camera '{}' {} event extends the pending clip", camera_key, event.kind);
+        match pending[i].incident.clone() {
+            Some(incident) => pending
+                .iter_mut()
+                .filter(|c| c.incident.as_ref() == Some(&incident))
+                .for_each(|c| c.to = c.to.max(to)),
+            None => pending[i].to = pending[i].to.max(to),
+        }
+        return;
+    }
+
+    let Some(group) = cfg.group_of(camera_key) else {
+        pending.push(PendingClip { camera_key: camera_key.to_string(), event, from, to, incident: None });
+        return;
+    };
+    let dir = incident_dir(save_dir, &group.name, &event);
+    let incident = PendingIncident { id: new_incident(group, &event, &dir), dir };
+    info!(
+        "Camera '{}' {} event starts an incident of group '{}' ({} cameras)",
+        camera_key,
+        event.kind,
+        group.name,
+        group.cameras.len()
+    );
+    for key in &group.cameras {
+        pending.push(PendingClip {
+            camera_key: key.clone(),
+            event: event.clone(),
+            from,
+            to,
+            incident: Some(incident.clone()),
+        });
+    }
+}
+
+/// Take in the next event, if one comes within half a second, then cut the clips that are due.
+fn tick(
+    pending: &mut Vec<PendingClip>,
+    cfg: &ClipConfig,
+    save_dir: &Path,
+    cameras: &[ClipCamera],
+    db_sender: &Sender<DBMessage>,
+    receiver: &Receiver<(String, CameraEvent)>,
+    stop: &AtomicBool,
+) {
+    match receiver.recv_timeout(Duration::from_millis(500)) {
+        Ok((camera_key, event)) => {
+            add_event(pending, cfg, save_dir, &camera_key, event, |group, event, dir| {
+                let (reply, answer) = channel();
+                let _ = db_sender.send(DBMessage::InsertIncident {
+                    group_name: group.name.clone(),
+                    camera_key: camera_key.clone(),
+                    event: event.clone(),
+                    dir: dir.to_string_lossy().to_string(),
+                    reply,
+                });
+                answer.recv().ok().flatten()
+            });
+        }
+        Err(RecvTimeoutError::Timeout) => {}
+        Err(RecvTimeoutError::Disconnected) => {
+            stop.store(true, Ordering::SeqCst);
+            return;
+        }
+    }
+
+    let now = SystemTime::now();
+    pending.retain(|clip| {
+        if now < clip.to {
+            return true;
+        }
+        let Some(cam) = cameras.iter().find(|c| c.key == clip.camera_key) else {
+            warn!("Camera '{}' has no dashcamts ring, no clip for its {} event", clip.camera_key, clip.event.kind);
+            return false;
+        };
+        let overdue = now >= clip.to + cam.segment_duration + CLOSE_GRACE;
+        let segments = match scan_legacy_dir(&cam.ring_dir) {
+            Ok(segments) => segments,
+            Err(e) => {
+                warn!("Camera '{}': can't list the ring for a clip: {:#}", cam.key, e);
+                return !overdue;
+            }
+        };
+        let Some(files) = select_clip_segments(&segments, clip.from, clip.to, cam.segment_duration, overdue) else {
+            return true;
+        };
+        match assemble(save_dir, clip, &files) {
+            Ok(output) => {
+                if let Some(incident_id) = clip.incident.as_ref().and_then(|i| i.id) {
+                    let _ = db_sender.send(DBMessage::InsertIncidentClip {
+                        incident_id,
+                        camera_key: clip.camera_key.clone(),
+                        path: output.to_string_lossy().to_string(),
+                        from_utc: DateTime::<Local>::from(clip.from).timestamp(),
+                        to_utc: DateTime::<Local>::from(clip.to).timestamp(),
+                    });
+                }
+            }
+            Err(e) => warn!("Camera '{}' clip of {} event failed: {:#}", cam.key, clip.event.kind, e),
+        }
+        false
+    });
+}
+
+/// Cut `clip` from `files`; returns where it went.
+fn assemble(save_dir: &Path, clip: &PendingClip, files: &[PathBuf]) -> Result<PathBuf> {
+    if files.is_empty() {
+        bail!("the ring no longer holds any of it");
+    }
+    let output = match &clip.incident {
+        Some(incident) => incident.dir.join(format!("{}.mp4", clip.camera_key)),
+        None => clip_path(save_dir, &clip.camera_key, &clip.event),
+    };
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let partial = output.with_extension("mp4.part");
+    export_segments(files, &partial)?;
+    fs::rename(&partial, &output)?;
+    info!(
+        "Camera '{}' {} clip from {} ({} segments) -> {}",
+        clip.camera_key,
+        clip.event.kind,
+        DateTime::<Local>::from(clip.from).format("%H:%M:%S"),
+        files.len(),
+        output.display()
+    );
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn waits_for_the_post_roll_segment_to_close() {
+        let t0: SystemTime = Local.with_ymd_and_hms(2026, 3, 1, 14, 0, 0).unwrap().into();
+        let secs = |s| t0 + Duration::from_secs(s);
+        let segment = |index: i64, end| LegacySegment {
+            path: PathBuf::from(format!("0/output_{}.ts", index)),
+            index,
+            modified: secs(end),
+            bytes: 1,
+        };
+        let two = Duration::from_secs(2);
+        // segments end every 2s; output_5 is still being written
+        let mut segments: Vec<_> = (0..6).map(|i| segment(i, 2 * (i as u64 + 1))).collect();
+
+        assert_eq!(select_clip_segments(&segments, secs(3), secs(11), two, false), None);
+        segments.push(segment(6, 14));
+        let files = select_clip_segments(&segments, secs(3), secs(11), two, false).unwrap();
+        let names: Vec<_> = files.iter().map(|f| f.file_name().unwrap().to_string_lossy().to_string()).collect();
+        assert_eq!(names, vec!["output_1.ts", "output_2.ts", "output_3.ts", "output_4.ts", "output_5.ts"]);
+
+        // gave up waiting: whatever there is, open segment included
+        let files = select_clip_segments(&segments[..5], secs(3), secs(20), two, true).unwrap();
+        assert_eq!(files.len(), 4);
+
+        let event = CameraEvent {
+            at: Local.with_ymd_and_hms(2026, 3, 1, 14, 0, 5).unwrap(),
+            ..CameraEvent::now(EventKind::GSensor)
+        };
+        assert_eq!(
+            clip_path(Path::new("/save"), "front", &event),
+            PathBuf::from("/save/front/2026-03-01/14-00-05_gsensor.mp4")
+        );
+    }
+
+    #[test]
+    fn grouped_event_clips_every_camera_of_the_group_over_one_window() {
+        let cfg = ClipConfig {
+            groups: vec![ClipGroup { name: "car".into(), cameras: vec!["front".into(), "rear".into(), "cabin".into()] }],
+            ..ClipConfig::default()
+        };
+        let event_at = |s| CameraEvent {
+            at: Local.with_ymd_and_hms(2026, 3, 1, 14, 0, s).unwrap(),
+            ..CameraEvent::now(EventKind::GSensor)
+        };
+        let save = Path::new("/save");
+        let mut pending = Vec::new();
+        let mut incidents = 0;
+
+        add_event(&mut pending, &cfg, save, "rear", event_at(5), |group, _, dir| {
+            assert_eq!(group.name, "car");
+            assert_eq!(dir, Path::new("/save/incidents/2026-03-01/14-00-05_gsensor_car"));
+            incidents += 1;
+            Some(7)
+        });
+        assert_eq!(pending.iter().map(|c| c.camera_key.as_str()).collect::<Vec<_>>(), vec!["front", "rear", "cabin"]);
+        assert!(pending.iter().all(|c| c.incident.as_ref().and_then(|i| i.id) == Some(7)));
+
+        // a later hit on another group camera extends the whole incident
+        add_event(&mut pending, &cfg, save, "cabin", event_at(12), |_, _, _| {
+            incidents += 1;
+            None
+        });
+        assert_eq!(incidents, 1);
+        let t5 = SystemTime::from(event_at(5).at);
+        assert!(pending.iter().all(|c| c.from == t5 - Duration::from_secs(20) && c.to == t5 + Duration::from_secs(17)));
+
+        // ungrouped cameras keep their own clip
+        add_event(&mut pending, &cfg, save, "side", event_at(6), |_, _, _| unreachable!());
+        assert_eq!(pending.len(), 4);
+        assert!(pending[3].incident.is_none());
+    }
+}
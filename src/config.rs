@@ -1,33 +1,290 @@
+use anyhow::{Context, Result, bail, ensure};
 use serde::Deserialize;
+use tracing::warn;
+
+use crate::bandwidth::BandwidthConfig;
+use crate::clips::ClipConfig;
+use crate::control::Role;
+use crate::events::EventsConfig;
+use crate::backfill::BackfillConfig;
+use crate::janitor::JanitorConfig;
+use crate::speed_events::SpeedEventsConfig;
+use crate::obd::ObdConfig;
+use crate::live::LiveConfig;
+use crate::rules::{RuleAction, RuleConfig};
+use crate::modes::{ModeConfig, ModeMqttConfig};
+use crate::export::ExportConfig;
+use crate::gps::GpsConfig;
+use crate::gsensor::GSensorConfig;
+use crate::motion::MotionConfig;
+use crate::mqtt::StatusMqttConfig;
+use crate::pipeline_sinks::disk_span::DisksConfig;
+use crate::pipeline_sinks::segmented_file_sink::{ContainerFormat, MuxerConfig};
+use crate::pipeline_sinks::staging::StagingConfig;
+use crate::pipeline_sinks::throughput::ThroughputConfig;
+use crate::pipeline_sinks::write_mode::WriteMode;
+use crate::pipeline_sources::audio_source::AudioConfig;
+use crate::pipeline_sources::processing::is_known_colorimetry;
+use crate::pipeline_sources::v4l2_pipeline_source::describe_video_devices;
+use crate::power_loss::PowerLossConfig;
+use crate::privacy::PrivacySwitchConfig;
+use crate::profiles::{Profile, ProfileName, Profiles, ProfilesConfig};
+use crate::roi::RoiConfig;
+use crate::rollup::RollupConfig;
+use crate::simulation::Simulation;
+use crate::smart::SmartConfig;
+use crate::standby::StandbyConfig;
+use crate::storage_wear::StorageWearConfig;
+use crate::storage_manager::StorageManagerConfig;
+use crate::tiering::TieringConfig;
+use crate::time_sync::TimeSyncConfig;
+use crate::upload::{SnapshotUploadConfig, check_endpoint};
+use crate::usb_sync::UsbSyncConfig;
+use crate::validation::ValidationConfig;
 
 #[derive(Debug, Deserialize)]
 pub struct AppConfig {
+    #[serde(default)]
     pub global: GlobalConfig,
+    #[serde(default)]
+    pub profiles: ProfilesConfig,
+    /// `[events.<kind>]` cooldown/hysteresis overrides
+    #[serde(default)]
+    pub events: EventsConfig,
+    /// `[http]`: control API server, off when absent
+    pub http: Option<HttpConfig>,
+    /// `[mdns]`: announce the API/RTSP endpoints on the LAN (`mdns` feature)
+    pub mdns: Option<MdnsConfig>,
+    /// `[bandwidth]`: caps on downloads/HLS/uploads
+    #[serde(default)]
+    pub bandwidth: BandwidthConfig,
+    /// `[standby]`: low-power mode settings
+    #[serde(default)]
+    pub standby: StandbyConfig,
+    /// `[snapshot_upload]`: send standby snapshots to a remote endpoint, off when absent
+    pub snapshot_upload: Option<SnapshotUploadConfig>,
+    /// `[validation]`: periodic decode check of recent segments, off when absent
+    pub validation: Option<ValidationConfig>,
+    /// `[rollup]`: daily remux of the ring into hour files on secondary storage, off when absent
+    pub rollup: Option<RollupConfig>,
+    /// `[clips]`: finished MP4s of G-sensor (or other) events, off when absent
+    pub clips: Option<ClipConfig>,
+    /// `[speed_events]`: speeding and hard-brake events from GPS, off when absent
+    pub speed_events: Option<SpeedEventsConfig>,
+    /// `[gsensor]`: impacts on an accelerometer raise events and save footage, off when absent
+    pub gsensor: Option<GSensorConfig>,
+    /// `[gps]`: fixes from gpsd or an NMEA receiver into `gps_points`, off when absent
+    pub gps: Option<GpsConfig>,
+    /// `[obd]`: speed/RPM/throttle from an ELM327 OBD-II adapter, off when absent
+    pub obd: Option<ObdConfig>,
+    /// `[live]`: switch the advertised live camera on reverse events, off when absent
+    pub live: Option<LiveConfig>,
+    /// `[[rules]]`: event → action rules, see `rules`
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    /// `[[modes]]`: home/away/night style modes, see `modes`; the first is the default
+    #[serde(default)]
+    pub modes: Vec<ModeConfig>,
+    /// `[mode_mqtt]`: switch modes from MQTT messages, off when absent
+    pub mode_mqtt: Option<ModeMqttConfig>,
+    /// `[janitor]`: periodic removal of ring files the ring no longer reaches, off when absent
+    pub janitor: Option<JanitorConfig>,
+    /// `[tiering]`: re-encode old `nvrts` segments smaller, off when absent
+    pub tiering: Option<TieringConfig>,
+    /// `[storage_manager]`: delete the oldest segments when the recording root runs low on space, off when absent
+    pub storage_manager: Option<StorageManagerConfig>,
+    /// `[backfill]`: thumbnails and checksums for catalogued segments lacking them, off when absent
+    pub backfill: Option<BackfillConfig>,
+    /// `[usb_sync]`: copy the save directory to USB sticks when plugged in, off when absent
+    pub usb_sync: Option<UsbSyncConfig>,
+    /// `[export]`: watermarking of exported clips
+    #[serde(default)]
+    pub export: ExportConfig,
+    /// `[time_sync]`: clock step detection, always on
+    #[serde(default)]
+    pub time_sync: TimeSyncConfig,
+    /// `[storage_wear]`: SD card endurance rating for the wear estimate
+    #[serde(default)]
+    pub storage_wear: StorageWearConfig,
+    /// `[throughput]`: when a file sink writing slower than the encoder counts as a failing card
+    #[serde(default)]
+    pub throughput: ThroughputConfig,
+    /// `[smart]`: poll the recording drives' S.M.A.R.T. health, off when absent
+    pub smart: Option<SmartConfig>,
+    /// `[staging]`: write segments to a tmpfs and move them to the recording root, off when absent
+    pub staging: Option<StagingConfig>,
+    /// `[power_loss]`: UPS HAT signal that closes segments and halts
+    pub power_loss: Option<PowerLossConfig>,
+    /// `[status_mqtt]`: retained state topics (privacy switches)
+    pub status_mqtt: Option<StatusMqttConfig>,
+    /// Set by `--simulate`, never read from config.toml
+    #[serde(skip)]
+    pub simulation: Option<Simulation>,
     pub cameras: Vec<CameraConfig>,
 }
 
-#[derive(Debug, Deserialize)]
+impl AppConfig {
+    /// The active profile (`global.profile`) with its `[profiles.<name>]` overrides applied.
+    pub fn profile(&self) -> Profile {
+        Profiles::from_config(&self.profiles)
+            .get(self.global.profile)
+            .clone()
+    }
+
+    pub fn main_dir(&self) -> String {
+        self.global.main_dir.clone().unwrap_or_else(|| self.profile().main_dir)
+    }
+
+    pub fn recording_root(&self) -> String {
+        self.global
+            .recording_root
+            .clone()
+            .unwrap_or_else(|| self.profile().recording_dir)
+    }
+
+    pub fn db_path(&self) -> String {
+        self.global.db_path.clone().unwrap_or_else(|| self.profile().db_path)
+    }
+
+    pub fn camera_start_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.global.camera_start_timeout_sec.unwrap_or(20))
+    }
+}
+
+/// Every field has a default, so `[global]` can be omitted entirely.
+/// Unset paths come from the active profile; `schema_path = None` means
+/// the schema embedded in the binary is used.
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
 pub struct GlobalConfig {
-    pub main_dir: String,
-    pub recording_root: String,
-    pub db_path: String,
-    pub schema_path: String,
-    pub log_level: Option<String>
+    pub profile: ProfileName,
+    pub main_dir: Option<String>,
+    pub recording_root: Option<String>,
+    pub db_path: Option<String>,
+    pub schema_path: Option<String>,
+    pub log_level: Option<String>,
+    /// How long one camera may take to reach PLAYING before startup moves on without it
+    pub camera_start_timeout_sec: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct HttpConfig {
+    #[serde(default = "default_http_bind")]
+    pub bind: String,
+    #[serde(default)]
+    pub auth: HttpAuthConfig,
+    /// `[http.tls]`: serve HTTPS instead of HTTP (`tls` feature)
+    pub tls: Option<HttpTlsConfig>,
+}
+
+/// PEM `cert_path`/`key_path`, or `self_signed = true` to generate a certificate
+/// on first start (under `<main_dir>/tls/` when the paths are unset).
+#[derive(Debug, Deserialize, Clone)]
+pub struct HttpTlsConfig {
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+    #[serde(default)]
+    pub self_signed: bool,
+    /// Subject alt names for the generated certificate
+    #[serde(default = "default_tls_names")]
+    pub names: Vec<String>,
+}
+
+/// `[http.auth]`. With no tokens the API is open (and says so in the log).
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HttpAuthConfig {
+    #[serde(default)]
+    pub tokens: Vec<ApiToken>,
+}
+
+/// `[[http.auth.tokens]]`
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiToken {
+    /// Shows up as the actor in the audit log, e.g. "hallway-tablet"
+    pub name: String,
+    /// Sent as `Authorization: Bearer <token>`
+    pub token: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MdnsConfig {
+    /// Name shown in service browsers
+    #[serde(default = "default_mdns_instance")]
+    pub instance: String,
+    #[serde(default = "default_mdns_hostname")]
+    pub hostname: String,
+    /// Port of the RTSP server re-streaming the cameras (at `/<camera key>`).
+    /// Unset = no `_rtsp._tcp` entries.
+    pub rtsp_port: Option<u16>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct CameraConfig {
+    #[serde(default = "default_camera_key")]
     pub key: String,
+    #[serde(default = "default_camera_name")]
     pub name: String,
+    #[serde(default = "default_true")]
     pub enabled: bool,
+    #[serde(default = "default_camera_role")]
     pub role: CameraRole,
+    /// `"motion"`: segment file sinks only record around `[cameras.motion]` events; `role = "nvr"` only
+    #[serde(default)]
+    pub record_mode: RecordMode,
 
+    // None -> falls back to the active profile
     pub video_width: Option<i64>,
     pub video_height: Option<i64>,
     pub video_framerate: Option<i64>,
 
     pub source: SourceConfig,
+    /// `[cameras.failover]`: backup source for when `source` keeps failing
+    pub failover: Option<FailoverConfig>,
+    /// Deinterlace/crop/colorimetry on raw frames before encoding
+    #[serde(default)]
+    pub processing: ProcessingConfig,
+    /// Burn the current `[gps]` speed and position into the frames; needs raw frames
+    #[serde(default)]
+    pub overlay_gps: bool,
+    /// Bitrate, preset, tune and keyframe interval of the encoder, for raw sources
+    #[serde(default)]
+    pub encoding: CameraEncoding,
+    #[serde(default = "default_sinks")]
     pub sinks: Vec<SinkConfig>,
+    /// Microphone muxed into the file and HLS sinks; no audio when absent
+    pub audio: Option<AudioConfig>,
+
+    /// `[cameras.detection]`: object detection on a low-rate frame tap (`detection` feature)
+    pub detection: Option<DetectionConfig>,
+    /// `[cameras.motion]`: frame-differencing motion events, and what `record_mode = "motion"` records on
+    pub motion: Option<MotionConfig>,
+
+    /// `[[cameras.roi]]`: where motion/detection should look; none = full frame
+    #[serde(default)]
+    pub roi: Vec<RoiConfig>,
+
+    /// `[cameras.privacy_switch]`: GPIO input that stops this camera while on
+    pub privacy_switch: Option<PrivacySwitchConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DetectionConfig {
+    /// YOLOv8-style ONNX model, output `[1, 4 + classes, anchors]`
+    pub model_path: String,
+    /// Class names in model output order; unset = COCO
+    pub labels: Option<Vec<String>>,
+    /// Labels that raise events
+    #[serde(default = "default_detect_labels")]
+    pub detect: Vec<String>,
+    #[serde(default = "default_min_score")]
+    pub min_score: f32,
+    /// Frames per second fed to the model
+    #[serde(default = "default_detection_fps")]
+    pub fps: u32,
+    /// Square model input, frames are scaled to this
+    #[serde(default = "default_input_size")]
+    pub input_size: u32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -38,11 +295,223 @@ pub enum CameraRole {
     Preview,
 }
 
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordMode {
+    #[default]
+    Continuous,
+    /// Armed by motion, from `pre_roll_sec` before it until `post_roll_sec` after
+    Motion,
+}
+
 #[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
 pub struct SourceConfig {
     pub kind: SourceKind,
     pub rtsp_url: Option<String>,
     pub device: Option<String>,
+    /// Source already delivers `codec`: parse and tee it as-is, no decode/encode.
+    #[serde(default)]
+    pub passthrough: bool,
+    /// Codec on the tee. Without passthrough, the source encodes to this.
+    #[serde(default)]
+    pub codec: VideoCodec,
+    /// Encoder element for raw frames; the codec's software encoder when unset
+    #[serde(default)]
+    pub encoder: Option<EncoderKind>,
+    /// How long a start waits for the device to show up (slow USB hubs, libcamera enumeration)
+    #[serde(default = "default_startup_timeout_sec")]
+    pub startup_timeout_sec: u64,
+    /// A camera that didn't come up with the service is retried this often in the
+    /// background, until it plays. 0 gives up after the first attempt.
+    #[serde(default = "default_retry_interval_sec")]
+    pub retry_interval_sec: u64,
+}
+
+impl SourceConfig {
+    /// "v4l2 /dev/video0", "rtsp rtsp://cam/sub", "libcamera"
+    pub fn describe(&self) -> String {
+        let kind = match self.kind {
+            SourceKind::Libcamera => "libcamera",
+            SourceKind::Rtsp => "rtsp",
+            SourceKind::V4l2 => "v4l2",
+        };
+        match self.device.as_ref().or(self.rtsp_url.as_ref()) {
+            Some(target) => format!("{} {}", kind, target),
+            None => kind.to_string(),
+        }
+    }
+
+    /// Frames arrive encoded: passthrough, and RTSP cameras (which always encode).
+    pub fn delivers_encoded(&self) -> bool {
+        self.passthrough || self.kind == SourceKind::Rtsp
+    }
+}
+
+/// Hot-standby source: after `after_failures` failed starts of the primary in a
+/// row, the pipeline is rebuilt on `source` and stays there until restarted.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct FailoverConfig {
+    /// Must put the same codec on the tee as the primary (the sinks are built for it)
+    pub source: SourceConfig,
+    #[serde(default = "default_failover_after")]
+    pub after_failures: u32,
+}
+
+fn default_failover_after() -> u32 {
+    3
+}
+
+/// Fixes for legacy and analog cameras, applied to raw frames in the source chain
+/// ahead of the encoder. Not available with `passthrough` (nothing is decoded).
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessingConfig {
+    /// Analog-to-USB dongles deliver interlaced 480i/576i
+    #[serde(default)]
+    pub deinterlace: bool,
+    /// Colorimetry the encoder gets ("bt709", "bt601", ...); videoconvert converts to it
+    pub colorimetry: Option<String>,
+    /// Pixels cut off each edge, e.g. the black borders and noisy lines of capture dongles
+    pub crop: Option<CropConfig>,
+}
+
+impl ProcessingConfig {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// `encoding = { ... }` of a camera: how a raw source's encoder is set up.
+/// `[[modes]]` encodings start from this bitrate.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraEncoding {
+    /// 2000 when unset
+    pub bitrate_kbps: Option<u32>,
+    /// x264enc/x265enc `speed-preset`; "ultrafast" when unset
+    pub speed_preset: Option<String>,
+    /// x264enc/x265enc `tune`; "zerolatency" when unset
+    pub tune: Option<String>,
+    /// Frames from one keyframe to the next; one a second when unset
+    pub keyframe_interval: Option<u32>,
+}
+
+const SPEED_PRESETS: [&str; 10] =
+    ["ultrafast", "superfast", "veryfast", "faster", "fast", "medium", "slow", "slower", "veryslow", "placebo"];
+
+impl CameraEncoding {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Why `encoder` can't take these settings, if it can't. GStreamer panics
+    /// on a preset or tune it doesn't know, so they're checked up front.
+    pub fn problem(&self, encoder: Option<EncoderKind>) -> Option<String> {
+        if self.bitrate_kbps == Some(0) || self.keyframe_interval == Some(0) {
+            return Some("bitrate_kbps and keyframe_interval must be at least 1".to_string());
+        }
+        let tunes: &[&str] = match encoder {
+            Some(EncoderKind::X264enc) => &["zerolatency", "fastdecode", "stillimage"],
+            Some(EncoderKind::X265enc) => &["zerolatency", "fastdecode", "psnr", "ssim", "grain", "animation"],
+            _ if self.speed_preset.is_some() || self.tune.is_some() => {
+                let factory = encoder.map_or("the AV1 encoder", |e| e.factory());
+                return Some(format!("speed_preset and tune are x264enc/x265enc settings, not {}'s", factory));
+            }
+            _ => return None,
+        };
+        if let Some(preset) = &self.speed_preset
+            && !SPEED_PRESETS.contains(&preset.as_str())
+        {
+            return Some(format!("unknown speed_preset '{}' (use one of {})", preset, SPEED_PRESETS.join(", ")));
+        }
+        // x264enc's tune is a set of flags, "zerolatency+fastdecode"
+        if let Some(tune) = &self.tune {
+            let known = match encoder {
+                Some(EncoderKind::X264enc) => tune.split('+').all(|t| tunes.contains(&t)),
+                _ => tunes.contains(&tune.as_str()),
+            };
+            if !known {
+                return Some(format!("unknown tune '{}' (use one of {})", tune, tunes.join(", ")));
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CropConfig {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+fn default_startup_timeout_sec() -> u64 {
+    10
+}
+
+fn default_retry_interval_sec() -> u64 {
+    5
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    #[default]
+    H264,
+    H265,
+    /// Needs the `av1` feature. Can't go in TS, so pair it with `mp4` sinks.
+    Av1,
+}
+
+/// `encoder = "..."`: which element encodes raw frames. Each takes the same
+/// settings (bitrate, keyframe interval) under its own property names.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EncoderKind {
+    /// Software, libx264. Maxes out a Pi 4 at 1080p30.
+    X264enc,
+    /// V4L2 memory-to-memory, e.g. the Raspberry Pi's hardware encoder
+    V4l2h264enc,
+    /// VA-API, Intel/AMD GPUs
+    Vaapih264enc,
+    /// Software, libx265. About half the bitrate of x264 for the same picture, at several times the CPU
+    X265enc,
+    /// V4L2 memory-to-memory HEVC (Rockchip, Amlogic; the Pi has none)
+    V4l2h265enc,
+    /// VA-API HEVC
+    Vaapih265enc,
+}
+
+impl EncoderKind {
+    /// What raw frames are encoded with when `encoder` is unset.
+    pub fn software(codec: VideoCodec) -> Option<Self> {
+        match codec {
+            VideoCodec::H264 => Some(EncoderKind::X264enc),
+            VideoCodec::H265 => Some(EncoderKind::X265enc),
+            VideoCodec::Av1 => None,
+        }
+    }
+
+    pub fn factory(&self) -> &'static str {
+        match self {
+            EncoderKind::X264enc => "x264enc",
+            EncoderKind::V4l2h264enc => "v4l2h264enc",
+            EncoderKind::Vaapih264enc => "vaapih264enc",
+            EncoderKind::X265enc => "x265enc",
+            EncoderKind::V4l2h265enc => "v4l2h265enc",
+            EncoderKind::Vaapih265enc => "vaapih265enc",
+        }
+    }
+
+    pub fn codec(&self) -> VideoCodec {
+        match self {
+            EncoderKind::X264enc | EncoderKind::V4l2h264enc | EncoderKind::Vaapih264enc => VideoCodec::H264,
+            EncoderKind::X265enc | EncoderKind::V4l2h265enc | EncoderKind::Vaapih265enc => VideoCodec::H265,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq, Clone)]
@@ -53,35 +522,530 @@ pub enum SourceKind {
     V4l2,
 }
 
+/// `max_segments` / `segment_duration_sec` left unset fall back to the active profile.
 #[derive(Debug, Deserialize)]
 #[serde(tag = "kind", rename_all = "lowercase")]
 pub enum SinkConfig {
-    DashcamTs { max_segments: i64, segment_duration_sec: u64 , sink_id: i64},
-    NvrTs { segment_duration_sec: u64 , sink_id: i64},
-    Hls { segment_duration_sec: u64 , sink_id: i64},
+    DashcamTs {
+        max_segments: Option<i64>,
+        segment_duration_sec: Option<u64>,
+        sink_id: i64,
+        #[serde(default = "default_true")]
+        enabled: bool,
+        #[serde(default)]
+        muxer: MuxerConfig,
+        #[serde(default)]
+        write_mode: WriteMode,
+        /// `g<generation>_s<index>.ts` instead of `output_<index>.ts`, so a file name
+        /// (and a link to it) stays with one recording
+        #[serde(default)]
+        generation_names: bool,
+    },
+    NvrTs {
+        segment_duration_sec: Option<u64>,
+        sink_id: i64,
+        #[serde(default = "default_true")]
+        enabled: bool,
+        #[serde(default)]
+        muxer: MuxerConfig,
+        #[serde(default)]
+        write_mode: WriteMode,
+        /// Spread segments over these disks instead of the recording root
+        disks: Option<DisksConfig>,
+    },
+    Hls {
+        segment_duration_sec: Option<u64>,
+        sink_id: i64,
+        #[serde(default = "default_true")]
+        enabled: bool,
+        /// Decode and re-encode to H.264 when the source delivers something else (e.g. H.265 passthrough).
+        #[serde(default)]
+        transcode: bool,
+    },
+    /// Like NvrTs, but fragmented into .mp4 files
+    Mp4 {
+        segment_duration_sec: Option<u64>,
+        sink_id: i64,
+        #[serde(default = "default_true")]
+        enabled: bool,
+        #[serde(default)]
+        muxer: MuxerConfig,
+        #[serde(default)]
+        write_mode: WriteMode,
+    },
+    /// Like NvrTs, but .mkv files tagged with the camera and chaptered at events
+    Mkv {
+        segment_duration_sec: Option<u64>,
+        sink_id: i64,
+        #[serde(default = "default_true")]
+        enabled: bool,
+        #[serde(default)]
+        muxer: MuxerConfig,
+        #[serde(default)]
+        write_mode: WriteMode,
+    },
+    /// One JPEG every `interval_sec` seconds
+    Timelapse {
+        #[serde(default = "default_timelapse_interval")]
+        interval_sec: u64,
+        sink_id: i64,
+        #[serde(default = "default_true")]
+        enabled: bool,
+    },
+    /// On events, `frames` JPEGs from the last `window_sec` seconds
+    Burst {
+        #[serde(default = "default_burst_frames")]
+        frames: u32,
+        #[serde(default = "default_burst_window")]
+        window_sec: u64,
+        sink_id: i64,
+        #[serde(default = "default_true")]
+        enabled: bool,
+    },
+}
+
+impl SinkConfig {
+    pub fn sink_id(&self) -> i64 {
+        match self {
+            SinkConfig::DashcamTs { sink_id, .. }
+            | SinkConfig::NvrTs { sink_id, .. }
+            | SinkConfig::Hls { sink_id, .. }
+            | SinkConfig::Mp4 { sink_id, .. }
+            | SinkConfig::Mkv { sink_id, .. }
+            | SinkConfig::Timelapse { sink_id, .. }
+            | SinkConfig::Burst { sink_id, .. } => *sink_id,
+        }
+    }
+
+    /// Enabled state from config. A runtime override stored in the DB wins over this.
+    pub fn enabled(&self) -> bool {
+        match self {
+            SinkConfig::DashcamTs { enabled, .. }
+            | SinkConfig::NvrTs { enabled, .. }
+            | SinkConfig::Hls { enabled, .. }
+            | SinkConfig::Mp4 { enabled, .. }
+            | SinkConfig::Mkv { enabled, .. }
+            | SinkConfig::Timelapse { enabled, .. }
+            | SinkConfig::Burst { enabled, .. } => *enabled,
+        }
+    }
+
+    pub fn segment_duration_sec(&self) -> Option<u64> {
+        match self {
+            SinkConfig::DashcamTs { segment_duration_sec, .. }
+            | SinkConfig::NvrTs { segment_duration_sec, .. }
+            | SinkConfig::Hls { segment_duration_sec, .. }
+            | SinkConfig::Mp4 { segment_duration_sec, .. }
+            | SinkConfig::Mkv { segment_duration_sec, .. } => *segment_duration_sec,
+            SinkConfig::Timelapse { .. } | SinkConfig::Burst { .. } => None,
+        }
+    }
+
+    /// Container and muxer settings, for sinks that write segment files.
+    pub fn muxer(&self) -> Option<(ContainerFormat, &MuxerConfig)> {
+        match self {
+            SinkConfig::DashcamTs { muxer, .. } | SinkConfig::NvrTs { muxer, .. } => Some((ContainerFormat::Ts, muxer)),
+            SinkConfig::Mp4 { muxer, .. } => Some((ContainerFormat::Mp4, muxer)),
+            SinkConfig::Mkv { muxer, .. } => Some((ContainerFormat::Mkv, muxer)),
+            SinkConfig::Hls { .. } | SinkConfig::Timelapse { .. } | SinkConfig::Burst { .. } => None,
+        }
+    }
+
+    /// How segment files are written (`buffered`, `drop_cache` or `sync`); sinks
+    /// without segment files always buffer.
+    pub fn write_mode(&self) -> WriteMode {
+        match self {
+            SinkConfig::DashcamTs { write_mode, .. }
+            | SinkConfig::NvrTs { write_mode, .. }
+            | SinkConfig::Mp4 { write_mode, .. }
+            | SinkConfig::Mkv { write_mode, .. } => *write_mode,
+            SinkConfig::Hls { .. } | SinkConfig::Timelapse { .. } | SinkConfig::Burst { .. } => WriteMode::Buffered,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////
+// serde defaults
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_timelapse_interval() -> u64 {
+    60
+}
+
+fn default_burst_frames() -> u32 {
+    5
 }
 
+fn default_burst_window() -> u64 {
+    2
+}
+
+fn default_detect_labels() -> Vec<String> {
+    vec!["person".to_string(), "car".to_string()]
+}
+
+fn default_min_score() -> f32 {
+    0.5
+}
+
+fn default_detection_fps() -> u32 {
+    1
+}
+
+fn default_input_size() -> u32 {
+    640
+}
+
+fn default_http_bind() -> String {
+    "0.0.0.0:8080".to_string()
+}
+
+fn default_tls_names() -> Vec<String> {
+    vec!["dashcam.local".to_string(), "localhost".to_string()]
+}
+
+fn default_mdns_instance() -> String {
+    "Dashcam".to_string()
+}
+
+fn default_mdns_hostname() -> String {
+    "dashcam.local.".to_string()
+}
+
+fn default_camera_key() -> String {
+    "dashcam".to_string()
+}
+
+fn default_camera_name() -> String {
+    "Dashcam".to_string()
+}
+
+fn default_camera_role() -> CameraRole {
+    CameraRole::Dashcam
+}
+
+/// A camera without any `[[cameras.sinks]]` records into a single ring buffer.
+fn default_sinks() -> Vec<SinkConfig> {
+    vec![SinkConfig::DashcamTs {
+        max_segments: None,
+        segment_duration_sec: None,
+        sink_id: 0,
+        enabled: true,
+        muxer: MuxerConfig::default(),
+        write_mode: WriteMode::default(),
+        generation_names: false,
+    }]
+}
+
+/// Checks on a camera's primary or backup source.
+fn verify_source(
+    camera_key: &str,
+    source: &SourceConfig,
+    processing: &ProcessingConfig,
+    encoding: &CameraEncoding,
+) -> Result<()> {
+    // Rtsp type needs rtsp url
+    if source.kind == SourceKind::Rtsp && source.rtsp_url.is_none() {
+        bail!("Camera '{}': an rtsp source needs rtsp_url", camera_key);
+    }
+    // V4L2 needs a device, by absolute path ("dev/video0" is an easy typo)
+    if source.kind == SourceKind::V4l2 {
+        let Some(device) = &source.device else {
+            bail!("Camera '{}': a v4l2 source needs device; {}", camera_key, describe_video_devices());
+        };
+        if !std::path::Path::new(device).is_absolute() {
+            bail!(
+                "Camera '{}': device '{}' must be an absolute path like /dev/video0; {}",
+                camera_key,
+                device,
+                describe_video_devices()
+            );
+        }
+        // may still show up (slow USB hub); startup waits and retries
+        if !std::path::Path::new(device).exists() {
+            warn!("Camera '{}': {} doesn't exist (yet); {}", camera_key, device, describe_video_devices());
+        }
+    }
+    // libcamera only hands out raw frames
+    if source.kind == SourceKind::Libcamera && source.passthrough {
+        bail!("Camera '{}': a libcamera source can't be passthrough", camera_key);
+    }
+    // Processing works on raw frames
+    if !processing.is_empty() && source.delivers_encoded() {
+        bail!("Camera '{}': [cameras.processing] needs a source that delivers raw frames", camera_key);
+    }
+    // An encoder only encodes raw frames, and only to its own codec
+    if let Some(encoder) = source.encoder
+        && (source.delivers_encoded() || encoder.codec() != source.codec)
+    {
+        bail!("Camera '{}': encoder {:?} needs raw frames and codec = {:?}", camera_key, encoder, encoder.codec());
+    }
+    // Encoder settings must suit the encoder (a backup that delivers encoded has none)
+    if !source.delivers_encoded()
+        && let Some(problem) = encoding.problem(source.encoder.or(EncoderKind::software(source.codec)))
+    {
+        bail!("Camera '{}': {}", camera_key, problem);
+    }
+    Ok(())
+}
 
-pub fn verify_app_config(app_config: &AppConfig) -> bool {
+/// Rejects configs that can't run, naming the first problem found.
+pub fn verify_app_config(app_config: &AppConfig) -> Result<()> {
     let mut checklist : Vec<SourceConfig> = vec![];
+    let mut keys: Vec<&str> = vec![];
     for camera_config in app_config.cameras.iter() {
         let camera_source = &camera_config.source;
         // Can't have 2 cameras with the same source
-        for item in checklist.iter() {
-            if *item == *camera_source {
-                return false;
+        if checklist.contains(camera_source) {
+            bail!("Camera '{}': another camera already records this source", camera_config.key);
+        }
+        // Can't have 2 cameras with the same key (easy to hit when relying on the default key)
+        ensure!(!keys.contains(&camera_config.key.as_str()), "More than one camera with key '{}'", camera_config.key);
+        verify_source(&camera_config.key, camera_source, &camera_config.processing, &camera_config.encoding)?;
+        // Encoder settings need a source that encodes
+        if !camera_config.encoding.is_empty() && camera_source.delivers_encoded() {
+            bail!("Camera '{}': [cameras.encoding] needs a source that delivers raw frames", camera_config.key);
+        }
+        // The backup feeds the same sinks, and is only switched to by the start retries
+        if let Some(failover) = &camera_config.failover {
+            if failover.source == *camera_source
+                || failover.source.codec != camera_source.codec
+                || camera_source.retry_interval_sec == 0
+                || failover.after_failures == 0
+            {
+                bail!(
+                    "Camera '{}': [cameras.failover] needs a different source of the same codec, \
+                     after_failures > 0 and retry_interval_sec > 0 on the primary",
+                    camera_config.key
+                );
             }
+            verify_source(&camera_config.key, &failover.source, &camera_config.processing, &camera_config.encoding)
+                .context("In [cameras.failover]")?;
+        }
+        // The overlay is drawn on raw frames, from [gps] fixes
+        if camera_config.overlay_gps
+            && (app_config.gps.is_none()
+                || camera_source.delivers_encoded()
+                || camera_config.failover.as_ref().is_some_and(|f| f.source.delivers_encoded()))
+        {
+            bail!("Camera '{}': overlay_gps needs [gps] and a source that delivers raw frames", camera_config.key);
+        }
+        // Audio codec must suit the sinks it's muxed into
+        if let Some(audio) = &camera_config.audio {
+            let hls = camera_config.sinks.iter().any(|s| matches!(s, SinkConfig::Hls { .. }));
+            if let Some(problem) = audio.problem(hls) {
+                bail!("Camera '{}': {}", camera_config.key, problem);
+            }
+        }
+        // Colorimetry must be one GStreamer knows
+        if let Some(colorimetry) = &camera_config.processing.colorimetry
+            && !is_known_colorimetry(colorimetry)
+        {
+            bail!("Camera '{}': unknown colorimetry '{}'", camera_config.key, colorimetry);
         }
-        // Rtsp type needs rtsp url
-        if camera_source.kind == SourceKind::Rtsp && camera_source.rtsp_url == None {
-            return false;
+        // AV1 encoder lookup is compiled out without the feature
+        if camera_source.codec == VideoCodec::Av1 && !camera_source.delivers_encoded() && !cfg!(feature = "av1") {
+            bail!("Camera '{}': encoding AV1 needs the av1 feature", camera_config.key);
         }
-        // V4L2 needs a device
-        if camera_source.kind == SourceKind::V4l2 && camera_source.device == None {
-            return false;
+        // ROI polygons need 3+ points inside the frame
+        if !camera_config.roi.iter().all(RoiConfig::is_valid) {
+            bail!("Camera '{}': every [[cameras.roi]] needs 3+ points inside the frame", camera_config.key);
+        }
+        // Detector is compiled out without the feature
+        if camera_config.detection.is_some() && !cfg!(feature = "detection") {
+            bail!("Camera '{}': [cameras.detection] needs the detection feature", camera_config.key);
+        }
+        if camera_config.motion.as_ref().is_some_and(|m| !m.is_valid()) {
+            bail!("Camera '{}': invalid [cameras.motion]", camera_config.key);
+        }
+        // Dashcams always record; only NVR cameras wait for motion
+        if camera_config.record_mode == RecordMode::Motion
+            && (camera_config.motion.is_none() || !matches!(camera_config.role, CameraRole::Nvr))
+        {
+            bail!(
+                "Camera '{}': record_mode = \"motion\" needs role = \"nvr\" and a [cameras.motion] section",
+                camera_config.key
+            );
+        }
+        // Muxer must fit the container, and tuning only applies to mpegtsmux
+        for (i, sink) in camera_config.sinks.iter().enumerate() {
+            // sink_id keys the segment rows and the persisted on/off switch
+            if camera_config.sinks[..i].iter().any(|s| s.sink_id() == sink.sink_id()) {
+                bail!("Camera '{}': more than one sink with sink_id={}", camera_config.key, sink.sink_id());
+            }
+            if let Some((container, muxer)) = sink.muxer()
+                && let Some(problem) = muxer.problem(container)
+            {
+                bail!("Camera '{}' sink_id={}: {}", camera_config.key, sink.sink_id(), problem);
+            }
+            if let SinkConfig::NvrTs { disks: Some(disks), .. } = sink
+                && let Some(problem) = disks.problem()
+            {
+                bail!("Camera '{}' sink_id={}: {}", camera_config.key, sink.sink_id(), problem);
+            }
         }
         checklist.push(camera_source.clone());
+        keys.push(&camera_config.key);
     }
 
-    true
-}
\ No newline at end of file
+    // API tokens need a name and a secret, and neither may repeat
+    if let Some(http) = &app_config.http {
+        let tokens = &http.auth.tokens;
+        for (i, token) in tokens.iter().enumerate() {
+            if token.name.is_empty() || token.token.is_empty() {
+                bail!("Every [[http.auth.tokens]] needs a name and a token");
+            }
+            if tokens[..i].iter().any(|t| t.name == token.name || t.token == token.token) {
+                bail!("API token '{}' repeats another token's name or secret", token.name);
+            }
+        }
+        // TLS needs both PEM paths or a self-signed bootstrap, and the feature
+        if let Some(tls) = &http.tls {
+            let has_paths = tls.cert_path.is_some() && tls.key_path.is_some();
+            if tls.cert_path.is_some() != tls.key_path.is_some() || !(has_paths || tls.self_signed) {
+                bail!("[http.tls] needs both cert_path and key_path, or self_signed = true");
+            }
+            ensure!(cfg!(feature = "tls"), "[http.tls] needs the tls feature");
+        }
+    }
+
+    // mDNS responder is compiled out without the feature
+    ensure!(app_config.mdns.is_none() || cfg!(feature = "mdns"), "[mdns] needs the mdns feature");
+
+    if let Some(validation) = &app_config.validation
+        && (validation.sample == 0 || !(0.0..=1.0).contains(&validation.min_duration_ratio))
+    {
+        bail!("[validation] needs sample > 0 and min_duration_ratio within 0..=1");
+    }
+
+    // Rollup needs somewhere to go, a valid time and cameras that exist
+    if let Some(rollup) = &app_config.rollup {
+        if rollup.archive_dir.is_empty() || rollup.run_at_time().is_none() {
+            bail!("[rollup] needs an archive_dir and a run_at like \"03:30\"");
+        }
+        if let Some(key) = rollup.cameras.iter().find(|key| !app_config.cameras.iter().any(|c| &c.key == *key)) {
+            bail!("[rollup] names unknown camera '{}'", key);
+        }
+    }
+
+    // A clip needs some footage around the event, and groups cameras that exist
+    if let Some(clips) = &app_config.clips
+        && (clips.kinds.is_empty() || clips.pre_roll_sec + clips.post_roll_sec == 0 || !clips.groups_valid(&keys))
+    {
+        bail!("[clips] needs kinds, some pre/post roll and groups of known cameras");
+    }
+
+    if let Some(upload) = &app_config.snapshot_upload {
+        check_endpoint(&upload.url, upload.token.as_deref()).context("In [snapshot_upload]")?;
+        if upload.mqtt.as_ref().is_some_and(|m| m.broker.is_empty()) {
+            bail!("[snapshot_upload.mqtt] needs a broker");
+        }
+    }
+
+    if app_config.speed_events.as_ref().is_some_and(|s| !s.is_valid(&keys)) {
+        bail!("Invalid [speed_events]");
+    }
+
+    if app_config.gsensor.as_ref().is_some_and(|g| !g.is_valid(&keys)) {
+        bail!("Invalid [gsensor]");
+    }
+
+    // Rule names identify them in the audit log
+    for (i, rule) in app_config.rules.iter().enumerate() {
+        ensure!(rule.is_valid(&keys), "Invalid [[rules]] entry '{}'", rule.name);
+        ensure!(!app_config.rules[..i].iter().any(|r| r.name == rule.name), "More than one rule named '{}'", rule.name);
+    }
+
+    // Modes are switched to by name, and `mode` actions must name one
+    let rule_names: Vec<&str> = app_config.rules.iter().map(|r| r.name.as_str()).collect();
+    for (i, mode) in app_config.modes.iter().enumerate() {
+        ensure!(mode.is_valid(&keys, &rule_names), "Invalid [[modes]] entry '{}'", mode.name);
+        ensure!(!app_config.modes[..i].iter().any(|m| m.name == mode.name), "More than one mode named '{}'", mode.name);
+    }
+    for rule in &app_config.rules {
+        for action in &rule.actions {
+            if let RuleAction::Mode { name } = action
+                && !app_config.modes.iter().any(|m| &m.name == name)
+            {
+                bail!("Rule '{}' switches to unknown mode '{}'", rule.name, name);
+            }
+        }
+    }
+    ensure!(app_config.mode_mqtt.is_none() || !app_config.modes.is_empty(), "[mode_mqtt] needs some [[modes]]");
+
+    if app_config.live.as_ref().is_some_and(|l| !l.is_valid(&keys)) {
+        bail!("Invalid [live]");
+    }
+
+    if app_config.gps.as_ref().is_some_and(|g| !g.is_valid()) {
+        bail!("Invalid [gps]");
+    }
+
+    if app_config.obd.as_ref().is_some_and(|o| !o.is_valid()) {
+        bail!("Invalid [obd]");
+    }
+
+    if app_config.janitor.as_ref().is_some_and(|j| j.interval_sec == 0) {
+        bail!("[janitor] needs interval_sec > 0");
+    }
+
+    if app_config.tiering.as_ref().is_some_and(|t| !t.is_valid()) {
+        bail!("Invalid [tiering]");
+    }
+
+    if app_config.storage_manager.as_ref().is_some_and(|s| !s.is_valid()) {
+        bail!("Invalid [storage_manager]");
+    }
+
+    if app_config.backfill.as_ref().is_some_and(|b| b.interval_sec == 0 || b.thumbnail_width == 0) {
+        bail!("[backfill] needs interval_sec and thumbnail_width > 0");
+    }
+
+    if app_config.power_loss.as_ref().is_some_and(|p| !p.is_valid()) {
+        bail!("Invalid [power_loss]");
+    }
+
+    if app_config.status_mqtt.as_ref().is_some_and(|m| m.broker.is_empty() || m.topic_prefix.is_empty()) {
+        bail!("[status_mqtt] needs a broker and a topic_prefix");
+    }
+
+    if let Some(usb_sync) = &app_config.usb_sync
+        && (usb_sync.mount_root.is_empty() || usb_sync.folder.is_empty())
+    {
+        bail!("[usb_sync] needs a mount_root and a folder");
+    }
+
+    // Staged segments mirror the recording root, so they can't live inside it
+    if let Some(staging) = &app_config.staging
+        && (!std::path::Path::new(&staging.dir).is_absolute()
+            || std::path::Path::new(&staging.dir).starts_with(app_config.recording_root())
+            || staging.retry_sec == 0
+            || staging.spool_mb == 0)
+    {
+        bail!("[staging] needs an absolute dir outside the recording root, and retry_sec and spool_mb > 0");
+    }
+
+    // A share of the bitrate, held for some time
+    if !(0.0..=1.0).contains(&app_config.throughput.min_ratio) || app_config.throughput.sustained_sec == 0 {
+        bail!("[throughput] needs min_ratio within 0..=1 and sustained_sec > 0");
+    }
+
+    if app_config.smart.as_ref().is_some_and(|smart| !smart.is_valid()) {
+        bail!("Invalid [smart]");
+    }
+
+    // A blank serial would make the watermark useless as evidence
+    if app_config.export.device_serial.as_ref().is_some_and(|s| s.trim().is_empty()) {
+        bail!("[export] device_serial must not be blank");
+    }
+    // Blur model runner is compiled out without the feature
+    ensure!(
+        app_config.export.anonymize.is_none() || cfg!(feature = "anonymize"),
+        "[export.anonymize] needs the anonymize feature"
+    );
+
+    Ok(())
+}
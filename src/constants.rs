@@ -1,32 +1,11 @@
+use crate::profiles::ProfileName;
 
-// DEBUG
+// Profile used when config.toml doesn't set `global.profile`.
+// The values themselves live in profiles.rs and can be switched at runtime.
 #[cfg(debug_assertions)]
-pub const VIDEO_DURATION: u64 = 2;
-#[cfg(debug_assertions)]
-pub const VIDEO_WIDTH: i32 = 640;
-#[cfg(debug_assertions)]
-pub const VIDEO_HEIGHT: i32 = 480;
-#[cfg(debug_assertions)]
-pub const VIDEO_FRAMERATE: i32 = 10;
-#[cfg(debug_assertions)]
-pub const RECORDING_DIR: &str = "./recordings/";
-#[cfg(debug_assertions)]
-pub const RECORDING_SAVE_DIR: &str = "./recordings/save/";
-#[cfg(debug_assertions)]
-pub const SEGMENTS_TO_KEEP: i64 = 86400 / 2 * 2; // 2 days worth
-
-// RELEASE
+pub const DEFAULT_PROFILE: ProfileName = ProfileName::Dev;
 #[cfg(not(debug_assertions))]
-pub const VIDEO_DURATION: u64 = 2;
-#[cfg(not(debug_assertions))]
-pub const VIDEO_WIDTH: i32 = 1920;
-#[cfg(not(debug_assertions))]
-pub const VIDEO_HEIGHT: i32 = 1080;
-#[cfg(not(debug_assertions))]
-pub const VIDEO_FRAMERATE: i32 = 30;
-#[cfg(not(debug_assertions))]
-pub const RECORDING_DIR: &str = "/var/lib/dashcam/recordings/";
-#[cfg(not(debug_assertions))]
-pub const RECORDING_SAVE_DIR: &str = "/var/lib/dashcam/recordings/save/";
-#[cfg(not(debug_assertions))]
-pub const SEGMENTS_TO_KEEP: i64 = 86400 / 2 * 2; // 2 days worth
+pub const DEFAULT_PROFILE: ProfileName = ProfileName::Production;
+
+// Unix socket the control server listens on (see control_socket.rs).
+pub const SOCKET_PATH: &str = "/var/lib/dashcam/control.sock";
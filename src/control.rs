@@ -0,0 +1,323 @@
+//! Control-plane commands. Transports (HTTP, control socket) parse requests into a
+//! `ControlCommand` and run it through `CamService::execute`, which audits every call.
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::db::{AuditRow, EventRow, IncidentRow, RuleStateRow};
+use crate::events::EventKind;
+use crate::live::LiveStatus;
+use crate::modes::ModeStatus;
+use crate::playlist::RewindStart;
+use crate::pipeline_sinks::throughput::SinkThroughput;
+use crate::standby::PowerState;
+use crate::smart::DriveHealth;
+use crate::storage_wear::StorageWear;
+use crate::track::{TrackFormat, TrackRange};
+
+/// Token scopes, each including the ones below it.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Live view, recent events
+    Viewer,
+    /// Sinks on/off, manual events
+    Operator,
+    /// Everything, including the audit log
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    SetSinkEnabled {
+        camera_key: String,
+        sink_id: i64,
+        enabled: bool,
+    },
+    TriggerEvent {
+        camera_key: String,
+        kind: EventKind,
+    },
+    RecentEvents {
+        camera_key: String,
+        limit: i64,
+    },
+    AuditLog {
+        limit: i64,
+    },
+    /// Enter (true) or leave (false) low-power standby
+    SetStandby {
+        standby: bool,
+    },
+    /// Runtime setting, persisted in the DB (e.g. `overlay.enabled`)
+    SetSetting {
+        key: String,
+        value: String,
+    },
+    GetSetting {
+        key: String,
+    },
+    /// Close the camera's current segments now (splitmuxsink `split-now`)
+    SplitSegments {
+        camera_key: String,
+    },
+    /// Cut a `[clips]` clip around now, filed under `kind`
+    SaveClip {
+        camera_key: String,
+        kind: EventKind,
+    },
+    /// Stop (true) or resume (false) recording, like the privacy switch
+    SetPrivacy {
+        camera_key: String,
+        private: bool,
+    },
+    /// Stop the camera's pipeline; nothing restarts it until `StartCamera`
+    StopCamera {
+        camera_key: String,
+    },
+    StartCamera {
+        camera_key: String,
+    },
+    RecentIncidents {
+        limit: i64,
+    },
+    /// Zip an incident next to its folder (see `incident_bundle`)
+    BundleIncident {
+        incident_id: i64,
+    },
+    /// `[[rules]]` with their stored state
+    Rules,
+    SetRuleArmed {
+        name: String,
+        armed: bool,
+    },
+    /// Current `[[modes]]` mode and its recent switches
+    Mode,
+    SetMode {
+        name: String,
+    },
+    /// GPS track of a trip or time range
+    Track {
+        range: TrackRange,
+        format: TrackFormat,
+    },
+    /// Save the camera's last `before_sec` of ring segments from being
+    /// overwritten (see `protect`), filed under `kind`
+    ProtectSegments {
+        camera_key: String,
+        kind: EventKind,
+        before_sec: u64,
+    },
+    /// HLS playlist of a camera's recordings in a time range
+    Playlist {
+        camera_key: String,
+        from_utc: i64,
+        to_utc: i64,
+    },
+    /// HLS playlist of a camera from some time back up to live (see `playlist`)
+    Rewind {
+        camera_key: String,
+        start: RewindStart,
+    },
+    Status,
+}
+
+impl ControlCommand {
+    /// Name recorded in `audit_log.action`.
+    pub fn action(&self) -> &'static str {
+        match self {
+            ControlCommand::SetSinkEnabled { .. } => "set_sink_enabled",
+            ControlCommand::TriggerEvent { .. } => "trigger_event",
+            ControlCommand::RecentEvents { .. } => "recent_events",
+            ControlCommand::AuditLog { .. } => "audit_log",
+            ControlCommand::SetStandby { .. } => "set_standby",
+            ControlCommand::SetSetting { .. } => "set_setting",
+            ControlCommand::GetSetting { .. } => "get_setting",
+            ControlCommand::SplitSegments { .. } => "split_segments",
+            ControlCommand::SaveClip { .. } => "save_clip",
+            ControlCommand::SetPrivacy { .. } => "set_privacy",
+            ControlCommand::StopCamera { .. } => "stop_camera",
+            ControlCommand::StartCamera { .. } => "start_camera",
+            ControlCommand::RecentIncidents { .. } => "recent_incidents",
+            ControlCommand::BundleIncident { .. } => "bundle_incident",
+            ControlCommand::Rules => "rules",
+            ControlCommand::SetRuleArmed { .. } => "set_rule_armed",
+            ControlCommand::Mode => "mode",
+            ControlCommand::SetMode { .. } => "set_mode",
+            ControlCommand::Track { .. } => "track",
+            ControlCommand::ProtectSegments { .. } => "protect_segments",
+            ControlCommand::Playlist { .. } => "playlist",
+            ControlCommand::Rewind { .. } => "rewind",
+            ControlCommand::Status => "status",
+        }
+    }
+
+    /// Lowest role allowed to run the command.
+    pub fn required_role(&self) -> Role {
+        match self {
+            ControlCommand::RecentEvents { .. }
+            | ControlCommand::GetSetting { .. }
+            | ControlCommand::RecentIncidents { .. }
+            | ControlCommand::Track { .. }
+            | ControlCommand::Playlist { .. }
+            | ControlCommand::Rewind { .. }
+            | ControlCommand::Rules
+            | ControlCommand::Mode
+            | ControlCommand::Status => Role::Viewer,
+            ControlCommand::SetSinkEnabled { .. }
+            | ControlCommand::TriggerEvent { .. }
+            | ControlCommand::SetStandby { .. }
+            | ControlCommand::SetSetting { .. }
+            | ControlCommand::SplitSegments { .. }
+            | ControlCommand::SaveClip { .. }
+            | ControlCommand::ProtectSegments { .. }
+            | ControlCommand::SetPrivacy { .. }
+            | ControlCommand::StopCamera { .. }
+            | ControlCommand::StartCamera { .. }
+            | ControlCommand::SetRuleArmed { .. }
+            | ControlCommand::SetMode { .. }
+            | ControlCommand::BundleIncident { .. } => Role::Operator,
+            ControlCommand::AuditLog { .. } => Role::Admin,
+        }
+    }
+
+    /// What the command acted on, recorded in `audit_log.target`.
+    pub fn target(&self) -> Option<String> {
+        match self {
+            ControlCommand::SetSinkEnabled { camera_key, sink_id, enabled } => {
+                Some(format!("{}/sink {} enabled={}", camera_key, sink_id, enabled))
+            }
+            ControlCommand::TriggerEvent { camera_key, kind } => Some(format!("{}/{}", camera_key, kind)),
+            ControlCommand::RecentEvents { camera_key, .. } => Some(camera_key.clone()),
+            ControlCommand::AuditLog { .. }
+            | ControlCommand::RecentIncidents { .. }
+            | ControlCommand::Rules
+            | ControlCommand::Mode
+            | ControlCommand::Status => None,
+            ControlCommand::SetRuleArmed { name, armed } => Some(format!("rule {} armed={}", name, armed)),
+            ControlCommand::SetMode { name } => Some(format!("mode {}", name)),
+            ControlCommand::SetStandby { standby } => Some(format!("standby={}", standby)),
+            ControlCommand::SetSetting { key, value } => Some(format!("{}={}", key, value)),
+            ControlCommand::GetSetting { key } => Some(key.clone()),
+            ControlCommand::SplitSegments { camera_key }
+            | ControlCommand::StopCamera { camera_key }
+            | ControlCommand::StartCamera { camera_key } => Some(camera_key.clone()),
+            ControlCommand::SaveClip { camera_key, kind } => Some(format!("{}/{}", camera_key, kind)),
+            ControlCommand::ProtectSegments { camera_key, kind, before_sec } => {
+                Some(format!("{}/{} last {}s", camera_key, kind, before_sec))
+            }
+            ControlCommand::SetPrivacy { camera_key, private } => Some(format!("{} private={}", camera_key, private)),
+            ControlCommand::BundleIncident { incident_id } => Some(format!("incident {}", incident_id)),
+            ControlCommand::Track { range, .. } => Some(range.name()),
+            ControlCommand::Playlist { camera_key, from_utc, to_utc } => {
+                Some(format!("{} {}..{}", camera_key, from_utc, to_utc))
+            }
+            ControlCommand::Rewind { camera_key, start: RewindStart::MinutesAgo(minutes) } => {
+                Some(format!("{} {} min ago..", camera_key, minutes))
+            }
+            ControlCommand::Rewind { camera_key, start: RewindStart::At(from_utc) } => {
+                Some(format!("{} {}..", camera_key, from_utc))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlReply {
+    Done,
+    PowerState(PowerState),
+    /// false if the event was debounced
+    EventRaised(bool),
+    Events(Vec<EventRow>),
+    Audit(Vec<AuditRow>),
+    /// None if the setting was never set
+    Setting { key: String, value: Option<String> },
+    Incidents(Vec<IncidentRow>),
+    Rules(Vec<RuleStateRow>),
+    Mode(ModeStatus),
+    Bundle(BundleInfo),
+    Protecting(ProtectInfo),
+    /// GPX or GeoJSON document, sent as is rather than wrapped in JSON
+    Track { format: TrackFormat, body: String },
+    /// M3U8 document, sent as is
+    Playlist(String),
+    Status(ServiceStatus),
+}
+
+/// Where an incident bundle goes. It's zipped in the background and only
+/// appears under `path` once it's complete.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BundleInfo {
+    pub path: String,
+    /// `/files/...` URL when the bundle lies under the recording root
+    pub download: Option<String>,
+}
+
+/// Where a `ProtectSegments` request saves to. The segments follow a few
+/// seconds later, once the one being written has been closed and catalogued.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProtectInfo {
+    pub dir: String,
+    pub from_utc: i64,
+    pub to_utc: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CameraStatus {
+    pub key: String,
+    pub running: bool,
+    /// "playing", "playing (live)", "starting", "timed out", "stopped" or the error
+    pub state: String,
+    /// "primary", or "backup" after a failover
+    pub source: &'static str,
+    /// Privacy switch on: not recording on purpose
+    pub privacy: bool,
+    /// Not recording in the current `[[modes]]` mode
+    pub off_in_mode: bool,
+    /// Files being written right now, one per recording sink
+    pub segments: Vec<SegmentStatus>,
+    /// Write throughput of each file sink, if the source encodes
+    pub throughput: Vec<SinkThroughput>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SegmentStatus {
+    pub sink_id: i64,
+    pub path: String,
+    pub index: u64,
+    /// RFC 3339, local time
+    pub started_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServiceStatus {
+    pub power_state: &'static str,
+    pub cameras: Vec<CameraStatus>,
+    /// Reports in `<main_dir>/crash/` (panics, unclean shutdowns); delete them to clear
+    pub crash_reports: Vec<String>,
+    /// Camera to show full screen, with `[live]` configured
+    pub live: Option<LiveStatus>,
+    /// With `[[modes]]` configured
+    pub mode: Option<String>,
+    /// Estimated wear of the recording card; `None` if the DB didn't answer
+    pub storage_wear: Option<StorageWear>,
+    /// Newest sample of each `[smart]` drive
+    pub drives: Vec<DriveHealth>,
+}
+
+/// Outcome of re-reading config.toml: sections now in effect, and changed
+/// sections that only take effect after a restart.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReloadReport {
+    pub applied: Vec<&'static str>,
+    pub restart_needed: Vec<&'static str>,
+}
@@ -0,0 +1,171 @@
+//! Control server on a Unix domain socket (`SOCKET_PATH`), for local tools and
+//! scripts. No tokens: access is whoever may open the socket (mode 0660).
+//!
+//! One JSON request per line, one JSON reply per line, as many as the client
+//! likes on one connection:
+//! - `{"cmd": "status"}`
+//! - `{"cmd": "stop-camera", "camera": "front"}`, `{"cmd": "start-camera", ...}`
+//! - `{"cmd": "save-clip", "camera": "front"}`, optionally with `"kind": "gsensor"`
+//! - `{"cmd": "protect", "camera": "front"}`: keep the ring segments of the last
+//!   minute (or `"before_sec"`) from being overwritten, optionally with `"kind"`
+//! - `{"cmd": "reload-config"}`
+//!
+//! Replies are `{"ok": true, "reply": ...}`, with the same JSON the HTTP API
+//! returns, or `{"ok": false, "error": "..."}`. Everything runs as actor `socket`
+//! and is audited.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tracing::{info, trace, warn};
+
+use crate::cam_service::CamService;
+use crate::config::AppConfig;
+use crate::control::ControlCommand;
+use crate::events::EventKind;
+use crate::http::server::reply_json;
+use crate::protect::DEFAULT_BEFORE_SEC;
+
+const ACTOR: &str = "socket";
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(tag = "cmd", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum SocketRequest {
+    Status,
+    StopCamera { camera: String },
+    StartCamera { camera: String },
+    SaveClip { camera: String, kind: Option<EventKind> },
+    Protect { camera: String, kind: Option<EventKind>, before_sec: Option<u64> },
+    ReloadConfig,
+}
+
+impl SocketRequest {
+    /// The control command to run; `None` for `reload-config`, which isn't one.
+    pub fn command(&self) -> Option<ControlCommand> {
+        Some(match self {
+            SocketRequest::Status => ControlCommand::Status,
+            SocketRequest::StopCamera { camera } => ControlCommand::StopCamera { camera_key: camera.clone() },
+            SocketRequest::StartCamera { camera } => ControlCommand::StartCamera { camera_key: camera.clone() },
+            SocketRequest::SaveClip { camera, kind } => ControlCommand::SaveClip {
+                camera_key: camera.clone(),
+                kind: kind.unwrap_or(EventKind::Manual),
+            },
+            SocketRequest::Protect { camera, kind, before_sec } => ControlCommand::ProtectSegments {
+                camera_key: camera.clone(),
+                kind: kind.unwrap_or(EventKind::Manual),
+                before_sec: before_sec.unwrap_or(DEFAULT_BEFORE_SEC),
+            },
+            SocketRequest::ReloadConfig => return None,
+        })
+    }
+}
+
+/// Reply line for one request line.
+fn handle_line<F>(line: &str, service: &Mutex<CamService>, load_config: &F) -> serde_json::Value
+where
+    F: Fn() -> Result<AppConfig>,
+{
+    let request: SocketRequest = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return json!({ "ok": false, "error": format!("Bad request: {}", e) }),
+    };
+    let result = match request.command() {
+        Some(command) => service.lock().unwrap().execute(ACTOR, command).map(reply_json),
+        None => load_config()
+            .and_then(|cfg| service.lock().unwrap().reload_config(ACTOR, cfg))
+            .map(|report| json!(report)),
+    };
+    match result {
+        Ok(reply) => json!({ "ok": true, "reply": reply }),
+        Err(e) => json!({ "ok": false, "error": format!("{:#}", e) }),
+    }
+}
+
+fn handle_connection<F>(stream: UnixStream, service: &Mutex<CamService>, load_config: &F) -> Result<()>
+where
+    F: Fn() -> Result<AppConfig>,
+{
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        trace!("Control socket: {}", line);
+        let reply = handle_line(&line, service, load_config);
+        writer.write_all(format!("{}\n", reply).as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Listen on `path` (a stale socket file from an unclean exit is replaced) and
+/// serve each connection on its own thread. `load_config` reads and verifies
+/// config.toml for `reload-config`.
+pub fn spawn_control_socket<F>(path: &Path, service: Arc<Mutex<CamService>>, load_config: F) -> Result<JoinHandle<()>>
+where
+    F: Fn() -> Result<AppConfig> + Send + Sync + 'static,
+{
+    if path.exists() {
+        std::fs::remove_file(path).with_context(|| format!("Can't remove stale socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path).with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    info!("Control socket listening on {}", path.display());
+
+    let load_config = Arc::new(load_config);
+    Ok(std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Control socket accept failed: {}", e);
+                    continue;
+                }
+            };
+            let service = service.clone();
+            let load_config = load_config.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &service, load_config.as_ref()) {
+                    trace!("Control socket connection error: {:#}", e);
+                }
+            });
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_map_to_commands() {
+        let parse = |line: &str| serde_json::from_str::<SocketRequest>(line).unwrap();
+        assert_eq!(parse(r#"{"cmd": "status"}"#).command(), Some(ControlCommand::Status));
+        assert_eq!(
+            parse(r#"{"cmd": "stop-camera", "camera": "cab"}"#).command(),
+            Some(ControlCommand::StopCamera { camera_key: "cab".to_string() })
+        );
+        assert_eq!(
+            parse(r#"{"cmd": "save-clip", "camera": "front"}"#).command(),
+            Some(ControlCommand::SaveClip { camera_key: "front".to_string(), kind: EventKind::Manual })
+        );
+        assert_eq!(
+            parse(r#"{"cmd": "save-clip", "camera": "front", "kind": "gsensor"}"#).command(),
+            Some(ControlCommand::SaveClip { camera_key: "front".to_string(), kind: EventKind::GSensor })
+        );
+        assert_eq!(
+            parse(r#"{"cmd": "protect", "camera": "front", "before_sec": 120}"#).command(),
+            Some(ControlCommand::ProtectSegments { camera_key: "front".to_string(), kind: EventKind::Manual, before_sec: 120 })
+        );
+        assert_eq!(parse(r#"{"cmd": "reload-config"}"#).command(), None);
+
+        assert!(serde_json::from_str::<SocketRequest>(r#"{"cmd": "start-camera"}"#).is_err(), "needs a camera");
+        assert!(serde_json::from_str::<SocketRequest>(r#"{"cmd": "reboot"}"#).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Forensics for unattended devices: a panic hook that writes a crash report
+//! (backtrace, last log lines, pipeline states), and a marker file that tells the
+//! next start the previous run never shut down cleanly (power cut, OOM kill, ...).
+//!
+//! Everything lives in `<main_dir>/crash/`; reports stay there until deleted,
+//! and the status API lists them.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tracing::{error, warn};
+
+const MARKER: &str = "running.marker";
+
+/// Per-camera state as last reported, for reports written without access to CamService.
+fn states() -> &'static Mutex<BTreeMap<String, String>> {
+    static STATES: OnceLock<Mutex<BTreeMap<String, String>>> = OnceLock::new();
+    STATES.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+/// When this run started, for the marker.
+static START: OnceLock<String> = OnceLock::new();
+
+fn crash_dir_slot() -> &'static OnceLock<PathBuf> {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    &DIR
+}
+
+pub fn crash_dir(main_dir: &str) -> PathBuf {
+    Path::new(main_dir).join("crash")
+}
+
+fn states_text() -> String {
+    match states().try_lock() {
+        Ok(states) if states.is_empty() => "(none reported)\n".to_string(),
+        Ok(states) => states.iter().map(|(k, v)| format!("{}: {}\n", k, v)).collect(),
+        Err(_) => "(unavailable)\n".to_string(),
+    }
+}
+
+fn marker_text() -> String {
+    format!(
+        "pid: {}\nstarted: {}\nupdated: {}\n\n[pipelines]\n{}",
+        std::process::id(),
+        START.get().map(String::as_str).unwrap_or("?"),
+        chrono::Local::now().to_rfc3339(),
+        states_text()
+    )
+}
+
+/// Remember a camera's state (e.g. "running", "stopped", "standby") for reports,
+/// and refresh the marker so an unclean shutdown shows the last known state.
+pub fn note_pipeline_state(camera_key: &str, state: &str) {
+    if let Ok(mut states) = states().lock() {
+        states.insert(camera_key.to_string(), state.to_string());
+    }
+    if let Some(dir) = crash_dir_slot().get() {
+        let _ = fs::write(dir.join(MARKER), marker_text());
+    }
+}
+
+fn report_path(dir: &Path, kind: &str) -> PathBuf {
+    dir.join(format!("crash_{}_{}.txt", chrono::Local::now().format("%Y-%m-%d_%H-%M-%S"), kind))
+}
+
+/// If a marker from the previous run is still there, turn it into a report.
+pub fn check_previous_run(dir: &Path) -> Result<Option<PathBuf>> {
+    let marker = dir.join(MARKER);
+    let Ok(last_state) = fs::read_to_string(&marker) else {
+        return Ok(None);
+    };
+    let report = report_path(dir, "unclean");
+    fs::write(
+        &report,
+        format!("Previous run did not shut down cleanly.\nLast known state:\n{}", last_state),
+    )
+    .with_context(|| format!("Failed to write {}", report.display()))?;
+    fs::remove_file(&marker)?;
+    Ok(Some(report))
+}
+
+/// Write a panic report. Returns where it went.
+pub fn write_panic_report(dir: &Path, panic: &str, backtrace: &str) -> Result<PathBuf> {
+    let mut text = String::new();
+    let _ = writeln!(text, "Panic: {}\nAt: {}\n", panic, chrono::Local::now().to_rfc3339());
+    let _ = writeln!(text, "[pipelines]\n{}", states_text());
+    let _ = writeln!(text, "[backtrace]\n{}\n", backtrace);
+    let _ = writeln!(text, "[log]");
+    for line in crate::log::recent_lines() {
+        let _ = writeln!(text, "{}", line);
+    }
+    let report = report_path(dir, "panic");
+    fs::write(&report, text)?;
+    Ok(report)
+}
+
+/// Reports waiting in `dir`, oldest first.
+pub fn list_reports(dir: &Path) -> Vec<String> {
+    let mut reports: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .filter(|name| name.starts_with("crash_"))
+                .collect()
+        })
+        .unwrap_or_default();
+    reports.sort();
+    reports
+}
+
+/// Call once at startup: reports an unclean previous run, writes this run's
+/// marker and installs the panic hook. Returns the unclean-shutdown report, if any.
+pub fn install(main_dir: &str) -> Result<Option<PathBuf>> {
+    let dir = crash_dir(main_dir);
+    fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let unclean = check_previous_run(&dir)?;
+    if let Some(report) = &unclean {
+        warn!("Previous run ended without a clean shutdown, see {}", report.display());
+    }
+
+    let _ = START.set(chrono::Local::now().to_rfc3339());
+    let _ = crash_dir_slot().set(dir.clone());
+    fs::write(dir.join(MARKER), marker_text())?;
+
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let backtrace = std::backtrace::Backtrace::force_capture().to_string();
+        match write_panic_report(&dir, &info.to_string(), &backtrace) {
+            Ok(report) => error!("Panic, crash report written to {}", report.display()),
+            Err(e) => error!("Panic, and writing the crash report failed: {:#}", e),
+        }
+        previous_hook(info);
+    }));
+    Ok(unclean)
+}
+
+/// Call on a clean exit so the next start doesn't report a crash.
+pub fn mark_clean_shutdown() {
+    if let Some(dir) = crash_dir_slot().get() {
+        let _ = fs::remove_file(dir.join(MARKER));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leftover_marker_becomes_unclean_report() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert_eq!(check_previous_run(dir.path()).unwrap(), None);
+
+        fs::write(dir.path().join(MARKER), "pid: 42\n[pipelines]\nfront: running\n").unwrap();
+        let report = check_previous_run(dir.path()).unwrap().unwrap();
+
+        let text = fs::read_to_string(&report).unwrap();
+        assert!(text.contains("did not shut down cleanly"));
+        assert!(text.contains("front: running"));
+        assert!(!dir.path().join(MARKER).exists());
+        assert_eq!(list_reports(dir.path()).len(), 1);
+    }
+
+    #[test]
+    fn panic_report_has_sections() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let report = write_panic_report(dir.path(), "boom", "frame 0").unwrap();
+        let text = fs::read_to_string(report).unwrap();
+        assert!(text.starts_with("Panic: boom"));
+        assert!(text.contains("[backtrace]\nframe 0"));
+        assert!(text.contains("[log]"));
+    }
+}
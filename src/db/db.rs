@@ -1,15 +1,295 @@
 use crate::config::{AppConfig, CameraConfig, SinkConfig};
+use crate::events::{CameraEvent, EventKind};
+use crate::pipeline_sinks::segment_counters::SegmentCounters;
+use crate::storage_wear;
+use crate::time_sync::ClockCorrection;
 
 use anyhow::{Context, Result};
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+/// Schema compiled into the binary, used when `global.schema_path` is not set.
+pub const EMBEDDED_SCHEMA_SQL: &str = include_str!("../../migrations/0001_init.sql");
+
 pub struct DashcamDb {
     pub conn: Connection,
 }
 
+/// Row of the `events` table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EventRow {
+    pub id: i64,
+    pub kind: String,
+    pub label: Option<String>,
+    pub score: Option<f64>,
+    pub at_utc: i64,
+}
+
+/// Row of the `incidents` table, with the key of the camera that started it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IncidentRow {
+    pub id: i64,
+    pub group_name: String,
+    pub camera_key: Option<String>,
+    pub kind: String,
+    pub at_utc: i64,
+    pub dir: String,
+}
+
+/// Row of the `incident_clips` table, with the camera's key.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IncidentClipRow {
+    pub camera_key: String,
+    pub path: String,
+    pub from_utc: i64,
+    pub to_utc: i64,
+}
+
+/// Row of the `gps_points` table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GpsPoint {
+    pub at_utc: i64,
+    pub lat: f64,
+    pub lon: f64,
+    pub alt_m: Option<f64>,
+    pub speed_mps: Option<f64>,
+    pub heading_deg: Option<f64>,
+}
+
+/// Row of the `rule_state` table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleStateRow {
+    pub name: String,
+    pub armed: bool,
+    pub fire_count: i64,
+    pub last_fired_utc: Option<i64>,
+    pub fired_on: Option<String>,
+}
+
+/// Row of the `mode_history` table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModeChangeRow {
+    pub at_utc: i64,
+    pub mode: String,
+    pub actor: String,
+}
+
+/// Row of the `telemetry` table (OBD-II); PIDs the car didn't answer are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TelemetrySample {
+    pub at_utc: i64,
+    pub speed_kmh: Option<f64>,
+    pub rpm: Option<f64>,
+    pub throttle_pct: Option<f64>,
+}
+
+/// Row of the `drive_health` table, see `smart`; what the drive didn't report is `None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DriveSample {
+    pub device: String,
+    pub at_utc: i64,
+    pub passed: Option<bool>,
+    pub temperature_c: Option<f64>,
+    pub reallocated_sectors: Option<i64>,
+    pub pending_sectors: Option<i64>,
+}
+
+/// New row for the `segments` catalog.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentRecord {
+    pub camera_id: i64,
+    pub sink_id: i64,
+    pub segment_index: i64,
+    pub segment_gen: i64,
+    pub absolute_index: i64,
+    pub start_utc: i64,
+    pub end_utc: i64,
+    /// Relative to the recording root (absolute for files outside it)
+    pub rel_path: String,
+    /// Mount point of the disk it's on, for an NvrTs sink spread over `disks`
+    pub disk: Option<String>,
+    pub stream: SegmentStream,
+    pub bytes: Option<i64>,
+    /// First segment after the pipeline (re)started: its timestamps don't carry
+    /// on from the segment before
+    pub restarted: bool,
+}
+
+/// What's in a segment file, as far as players and muxers care: a run of
+/// segments can only be played or joined as one stream while this stays the
+/// same. `None` where unknown (imported footage).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SegmentStream {
+    /// "H264", "H265" or "AV1"
+    pub codec: Option<String>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub fps: Option<f64>,
+}
+
+impl SegmentStream {
+    /// Whether `next` can follow this segment in one stream. What either side
+    /// doesn't know is taken to match.
+    pub fn continues_into(&self, next: &SegmentStream) -> bool {
+        fn same<T: PartialEq>(a: &Option<T>, b: &Option<T>) -> bool {
+            a.is_none() || b.is_none() || a == b
+        }
+        let same_fps = match (self.fps, next.fps) {
+            (Some(a), Some(b)) => (a - b).abs() < 0.01,
+            _ => true,
+        };
+        same(&self.codec, &next.codec) && same(&self.width, &next.width) && same(&self.height, &next.height) && same_fps
+    }
+
+    /// Take what `other` knows and this doesn't.
+    fn fill_from(&mut self, other: &SegmentStream) {
+        self.codec = self.codec.take().or_else(|| other.codec.clone());
+        self.width = self.width.or(other.width);
+        self.height = self.height.or(other.height);
+        self.fps = self.fps.or(other.fps);
+    }
+}
+
+/// Split time-ordered `segments` where the stream changes, so each run can be
+/// played or exported as one. Segments with an unknown stream stay in the run
+/// before them.
+pub fn stream_runs<T>(segments: &[T], stream: impl Fn(&T) -> &SegmentStream) -> Vec<&[T]> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    // what the current run is known to be so far
+    let mut run_stream = SegmentStream::default();
+    for (i, segment) in segments.iter().enumerate() {
+        let next = stream(segment);
+        if !run_stream.continues_into(next) {
+            runs.push(&segments[start..i]);
+            start = i;
+            run_stream = SegmentStream::default();
+        }
+        run_stream.fill_from(next);
+    }
+    if start < segments.len() {
+        runs.push(&segments[start..]);
+    }
+    runs
+}
+
+/// A catalogued segment, as found by time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SegmentRow {
+    pub id: i64,
+    pub sink_id: i64,
+    pub start_utc: i64,
+    pub end_utc: i64,
+    /// Segments the camera recorded before this one, see `camera_state.absolute_segments`
+    pub absolute_index: i64,
+    /// Relative to the recording root (absolute for files outside it)
+    pub rel_path: String,
+    pub stream: SegmentStream,
+    pub bytes: Option<i64>,
+    pub restarted: bool,
+    /// Largest clock correction covering it, see `insert_clock_correction`
+    pub clock_offset_ms: Option<i64>,
+}
+
+/// The rows of the sink with the most of `rows`, oldest first. A camera
+/// recording to several sinks has every stretch catalogued once per sink; ties
+/// go to the lower sink id.
+pub fn busiest_sink(rows: &[SegmentRow]) -> Vec<&SegmentRow> {
+    let count = |sink_id| rows.iter().filter(|r| r.sink_id == sink_id).count();
+    let Some(sink_id) = rows.iter().map(|r| r.sink_id).max_by_key(|&id| (count(id), std::cmp::Reverse(id))) else {
+        return Vec::new();
+    };
+    let mut rows: Vec<&SegmentRow> = rows.iter().filter(|r| r.sink_id == sink_id).collect();
+    rows.sort_by_key(|r| r.start_utc);
+    rows
+}
+
+/// Why catalogued footage went away, stored in `segment_tombstones.reason`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletionReason {
+    /// The ring came back round to the file's slot
+    Overwritten,
+    /// Removed by the ring janitor, out of reach of a shrunk ring
+    Janitor,
+    /// Pruned by the storage manager to get free space back
+    LowSpace,
+}
+
+impl DeletionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeletionReason::Overwritten => "overwritten",
+            DeletionReason::Janitor => "janitor",
+            DeletionReason::LowSpace => "low_space",
+        }
+    }
+}
+
+/// A segment that was recorded and later deleted.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TombstoneRow {
+    pub sink_id: i64,
+    pub segment_index: i64,
+    pub segment_gen: i64,
+    pub start_utc: i64,
+    pub end_utc: i64,
+    pub rel_path: String,
+    pub reason: String,
+    pub deleted_at_utc: i64,
+}
+
+/// A catalogued segment still missing a thumbnail or checksum.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingSegment {
+    pub id: i64,
+    /// Relative to the recording root (absolute for files outside it)
+    pub rel_path: String,
+    pub needs_thumbnail: bool,
+    pub needs_checksum: bool,
+}
+
+/// A catalogued segment the storage manager may delete.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrunableSegment {
+    pub camera_id: i64,
+    /// Relative to the recording root
+    pub rel_path: String,
+    pub end_utc: i64,
+}
+
+/// What a sink starts from: its ring index and any runtime enable override.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SinkState {
+    pub sink_id: i64,
+    pub segment_index: i64,
+    pub generation: i64,
+    pub absolute_segments: i64,
+    pub enabled_override: Option<bool>,
+}
+
+/// A camera's id plus the starting state of the sinks asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraState {
+    pub camera_key: String,
+    pub camera_id: i64,
+    pub sinks: Vec<SinkState>,
+}
+
+/// Row of the `audit_log` table.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditRow {
+    pub id: i64,
+    pub at_utc: i64,
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub ok: bool,
+    pub result: Option<String>,
+}
+
 impl DashcamDb {
     ////////////////////////////////////////////////////////////////////////////////
     // Setup / initialization
@@ -19,11 +299,11 @@ impl DashcamDb {
     ///
     /// - ensure DB directory exists
     /// - open DB
-    /// - run schema from `global.schema_path`
+    /// - run schema from `global.schema_path` (or the embedded schema if unset)
     /// - insert/update cameras from config (key, name, rtsp_url)
     /// - ensure `camera_state` rows exist for each camera
     pub fn setup_from_config(cfg: &AppConfig) -> Result<Self> {
-        let db_path = PathBuf::from(&cfg.global.db_path);
+        let db_path = PathBuf::from(cfg.db_path());
         if let Some(parent) = db_path.parent() {
             fs::create_dir_all(parent)
                 .with_context(|| format!("Failed to create DB directory {:?}", parent))?;
@@ -32,8 +312,11 @@ impl DashcamDb {
         let db = Self::open(&db_path)
             .with_context(|| format!("Failed to open DB at {:?}", db_path))?;
 
-        let schema_sql = fs::read_to_string(&cfg.global.schema_path)
-            .with_context(|| format!("Failed to read schema file {}", cfg.global.schema_path))?;
+        let schema_sql = match &cfg.global.schema_path {
+            Some(schema_path) => fs::read_to_string(schema_path)
+                .with_context(|| format!("Failed to read schema file {}", schema_path))?,
+            None => EMBEDDED_SCHEMA_SQL.to_string(),
+        };
 
         db.run_schema(&schema_sql)
             .context("Failed to run schema.sql")?;
@@ -68,16 +351,34 @@ impl DashcamDb {
 
     pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
         let conn = Connection::open(path)?;
-        conn.pragma_update(None, "journal_mode", &"WAL")?;
-        conn.pragma_update(None, "synchronous", &"NORMAL")?;
-        conn.pragma_update(None, "foreign_keys", &"ON")?;
-        conn.pragma_update(None, "temp_store", &"MEMORY")?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.pragma_update(None, "temp_store", "MEMORY")?;
         conn.busy_timeout(Duration::from_millis(100))?;
         Ok(Self { conn })
     }
 
     pub fn run_schema(&self, schema_sql: &str) -> rusqlite::Result<()> {
         self.conn.execute_batch(schema_sql)?;
+        // Columns added after a table first shipped; CREATE TABLE IF NOT EXISTS won't add them
+        self.ensure_column("segments", "sink_id", "INTEGER NOT NULL DEFAULT 0")?;
+        self.ensure_column("segments", "clock_offset_ms", "INTEGER")?;
+        self.ensure_column("segments", "thumbnail_path", "TEXT")?;
+        self.ensure_column("segments", "checksum", "TEXT")?;
+        self.ensure_column("segments", "restarted", "INTEGER NOT NULL DEFAULT 0")?;
+        self.ensure_column("segments", "disk", "TEXT")?;
+        Ok(())
+    }
+
+    /// Add `column` to `table` if the table exists without it.
+    fn ensure_column(&self, table: &str, column: &str, decl: &str) -> rusqlite::Result<()> {
+        let mut stmt = self.conn.prepare(&format!("PRAGMA table_info({});", table))?;
+        let columns: Vec<String> = stmt.query_map([], |r| r.get(1))?.collect::<rusqlite::Result<_>>()?;
+        if !columns.is_empty() && !columns.iter().any(|c| c == column) {
+            self.conn
+                .execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, decl))?;
+        }
         Ok(())
     }
 
@@ -148,6 +449,25 @@ impl DashcamDb {
         )
     }
 
+    /// camera_id, ring index and enable override of each of `sink_ids`, for building pipelines.
+    /// A sink without a `camera_state` row starts at zero.
+    pub fn get_camera_state(&self, camera_key: &str, sink_ids: &[i64]) -> rusqlite::Result<CameraState> {
+        let camera_id = self.get_camera_id_by_key(camera_key)?;
+        let sinks = sink_ids
+            .iter()
+            .map(|&sink_id| {
+                Ok(SinkState {
+                    sink_id,
+                    segment_index: self.get_segment_index(camera_id, sink_id).optional()?.unwrap_or(0),
+                    generation: self.get_segment_generation(camera_id, sink_id).optional()?.unwrap_or(0),
+                    absolute_segments: self.get_absolute_segments(camera_id, sink_id).optional()?.unwrap_or(0),
+                    enabled_override: self.get_sink_enabled(camera_id, sink_id)?,
+                })
+            })
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(CameraState { camera_key: camera_key.to_string(), camera_id, sinks })
+    }
+
     ////////////////////////////////////////////////////////////////////////////////
     // Segment counters API (ID-based, hot path)
     ////////////////////////////////////////////////////////////////////////////////
@@ -260,54 +580,23 @@ impl DashcamDb {
             |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
         )?;
 
-        // If DB already matches, nothing to do.
-        if new_segment_index == cur_idx {
-            tx.commit()?;
-            return Ok(());
-        }
-
-        let max = max_segments;
-        let wrapped = new_segment_index < cur_idx;
-
-        // How far did we advance around the ring?
-        let diff = if wrapped {
-            (max - cur_idx) + new_segment_index
-        } else {
-            new_segment_index - cur_idx
-        };
-
-        // Update segment_index
-        tx.execute(
-            "UPDATE camera_state
-             SET segment_index = ?1
-             WHERE camera_id = ?2 AND sink_id = ?3;",
-            rusqlite::params![new_segment_index, camera_id, sink_id],
-        )?;
-
-        // Bump generation on wrap
-        if wrapped {
+        let current = SegmentCounters { index: cur_idx, generation: cur_gen, absolute: cur_abs };
+        let next = current.advanced(new_segment_index, max_segments);
+        if next != current {
             tx.execute(
                 "UPDATE camera_state
-                 SET segment_generation = ?1
-                 WHERE camera_id = ?2 AND sink_id = ?3;",
-                rusqlite::params![cur_gen + 1, camera_id, sink_id],
+                 SET segment_index = ?1, segment_generation = ?2, absolute_segments = ?3
+                 WHERE camera_id = ?4 AND sink_id = ?5;",
+                rusqlite::params![next.index, next.generation, next.absolute, camera_id, sink_id],
             )?;
         }
 
-        // absolute_segments always increases by `diff`
-        tx.execute(
-            "UPDATE camera_state
-             SET absolute_segments = ?1
-             WHERE camera_id = ?2 AND sink_id = ?3;",
-            rusqlite::params![cur_abs + diff, camera_id, sink_id],
-        )?;
-
         tx.commit()?;
         Ok(())
     }
 
 
-    /// Increment ring index and bump generation/absolute atomically
+    /// Increment ring index and bump generation/absolute in one statement
     /// for the given (camera_id, sink_id). Returns new ring index.
     pub fn increment_segment_index(
         &self,
@@ -315,47 +604,864 @@ impl DashcamDb {
         sink_id: i64,
         max_segments: i64,
     ) -> rusqlite::Result<i64> {
+        // right-hand sides all see the row as it was before the update
+        self.conn.query_row(
+            "UPDATE camera_state
+             SET segment_index      = CASE WHEN segment_index + 1 >= ?1 THEN 0 ELSE segment_index + 1 END,
+                 segment_generation = segment_generation + (segment_index + 1 >= ?1),
+                 absolute_segments  = absolute_segments + 1
+             WHERE camera_id = ?2 AND sink_id = ?3
+             RETURNING segment_index;",
+            params![max_segments, camera_id, sink_id],
+            |r| r.get(0),
+        )
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Sink overrides (runtime enable/disable)
+    ////////////////////////////////////////////////////////////////////////////////
+
+    /// Persist a runtime enable/disable for a sink so it survives restarts.
+    pub fn set_sink_enabled(
+        &self,
+        camera_id: i64,
+        sink_id: i64,
+        enabled: bool,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO sink_overrides (camera_id, sink_id, enabled, updated_at_utc)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(camera_id, sink_id) DO UPDATE SET
+                enabled        = excluded.enabled,
+                updated_at_utc = excluded.updated_at_utc;",
+            params![camera_id, sink_id, enabled, chrono::Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    /// Runtime override for a sink, or None if config.toml should decide.
+    pub fn get_sink_enabled(
+        &self,
+        camera_id: i64,
+        sink_id: i64,
+    ) -> rusqlite::Result<Option<bool>> {
+        self.conn
+            .query_row(
+                "SELECT enabled
+                 FROM sink_overrides
+                 WHERE camera_id = ?1 AND sink_id = ?2;",
+                params![camera_id, sink_id],
+                |r| r.get(0),
+            )
+            .optional()
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Events
+    ////////////////////////////////////////////////////////////////////////////////
+
+    /// Record an event for a camera (by key). Returns the new event id.
+    pub fn insert_event(&self, camera_key: &str, event: &CameraEvent) -> rusqlite::Result<i64> {
+        self.conn.execute(
+            "INSERT INTO events (camera_id, kind, label, score, at_utc)
+             VALUES ((SELECT id FROM cameras WHERE key = ?1), ?2, ?3, ?4, ?5);",
+            params![
+                camera_key,
+                event.kind.as_str(),
+                event.label,
+                event.score,
+                event.at.timestamp()
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Most recent events for a camera, newest first.
+    pub fn get_recent_events(&self, camera_id: i64, limit: i64) -> rusqlite::Result<Vec<EventRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, kind, label, score, at_utc
+             FROM events
+             WHERE camera_id = ?1
+             ORDER BY at_utc DESC, id DESC
+             LIMIT ?2;",
+        )?;
+        let rows = stmt.query_map(params![camera_id, limit], |r| {
+            Ok(EventRow {
+                id: r.get(0)?,
+                kind: r.get(1)?,
+                label: r.get(2)?,
+                score: r.get(3)?,
+                at_utc: r.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// A camera's events with `from_utc <= at_utc < to_utc`, oldest first.
+    pub fn get_events_between(&self, camera_id: i64, from_utc: i64, to_utc: i64) -> rusqlite::Result<Vec<EventRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, kind, label, score, at_utc
+             FROM events
+             WHERE camera_id = ?1 AND at_utc >= ?2 AND at_utc < ?3
+             ORDER BY at_utc, id;",
+        )?;
+        let rows = stmt.query_map(params![camera_id, from_utc, to_utc], |r| {
+            Ok(EventRow {
+                id: r.get(0)?,
+                kind: r.get(1)?,
+                label: r.get(2)?,
+                score: r.get(3)?,
+                at_utc: r.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Record an incident of camera group `group_name`, started by `event` on
+    /// `camera_key`. Returns the incident id its clips are filed under.
+    pub fn insert_incident(
+        &self,
+        group_name: &str,
+        camera_key: &str,
+        event: &CameraEvent,
+        dir: &str,
+    ) -> rusqlite::Result<i64> {
+        self.conn.execute(
+            "INSERT INTO incidents (group_name, camera_id, kind, at_utc, dir)
+             VALUES (?1, (SELECT id FROM cameras WHERE key = ?2), ?3, ?4, ?5);",
+            params![group_name, camera_key, event.kind.as_str(), event.at.timestamp(), dir],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Record one camera's finished clip of an incident.
+    pub fn insert_incident_clip(
+        &self,
+        incident_id: i64,
+        camera_key: &str,
+        path: &str,
+        from_utc: i64,
+        to_utc: i64,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO incident_clips (incident_id, camera_id, path, from_utc, to_utc)
+             VALUES (?1, (SELECT id FROM cameras WHERE key = ?2), ?3, ?4, ?5);",
+            params![incident_id, camera_key, path, from_utc, to_utc],
+        )?;
+        Ok(())
+    }
+
+    /// One incident, `None` if there is no such id.
+    pub fn get_incident(&self, incident_id: i64) -> rusqlite::Result<Option<IncidentRow>> {
+        self.conn
+            .query_row(
+                "SELECT i.id, i.group_name, c.key, i.kind, i.at_utc, i.dir
+                 FROM incidents i
+                 LEFT JOIN cameras c ON c.id = i.camera_id
+                 WHERE i.id = ?1;",
+                params![incident_id],
+                incident_row,
+            )
+            .optional()
+    }
+
+    /// Most recent incidents, newest first.
+    pub fn get_recent_incidents(&self, limit: i64) -> rusqlite::Result<Vec<IncidentRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT i.id, i.group_name, c.key, i.kind, i.at_utc, i.dir
+             FROM incidents i
+             LEFT JOIN cameras c ON c.id = i.camera_id
+             ORDER BY i.at_utc DESC, i.id DESC
+             LIMIT ?1;",
+        )?;
+        let rows = stmt.query_map(params![limit], incident_row)?;
+        rows.collect()
+    }
+
+    /// Clips of an incident, by camera key.
+    pub fn get_incident_clips(&self, incident_id: i64) -> rusqlite::Result<Vec<IncidentClipRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT c.key, ic.path, ic.from_utc, ic.to_utc
+             FROM incident_clips ic
+             JOIN cameras c ON c.id = ic.camera_id
+             WHERE ic.incident_id = ?1
+             ORDER BY c.key;",
+        )?;
+        let rows = stmt.query_map(params![incident_id], |r| {
+            Ok(IncidentClipRow {
+                camera_key: r.get(0)?,
+                path: r.get(1)?,
+                from_utc: r.get(2)?,
+                to_utc: r.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Flush the WAL into the main database file and truncate it, so nothing is
+    /// pending if power goes away while idle.
+    pub fn checkpoint(&self) -> rusqlite::Result<()> {
+        self.conn
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE);", [], |_| Ok(()))
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Audit log
+    ////////////////////////////////////////////////////////////////////////////////
+
+    /// Record one control-plane command. `result` is the error message for failures.
+    pub fn insert_audit(
+        &self,
+        actor: &str,
+        action: &str,
+        target: Option<&str>,
+        ok: bool,
+        result: Option<&str>,
+    ) -> rusqlite::Result<i64> {
+        self.conn.execute(
+            "INSERT INTO audit_log (at_utc, actor, action, target, ok, result)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+            params![chrono::Utc::now().timestamp(), actor, action, target, ok, result],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Most recent audit entries across all cameras, newest first.
+    pub fn get_recent_audit(&self, limit: i64) -> rusqlite::Result<Vec<AuditRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, at_utc, actor, action, target, ok, result
+             FROM audit_log
+             ORDER BY at_utc DESC, id DESC
+             LIMIT ?1;",
+        )?;
+        let rows = stmt.query_map(params![limit], |r| {
+            Ok(AuditRow {
+                id: r.get(0)?,
+                at_utc: r.get(1)?,
+                actor: r.get(2)?,
+                action: r.get(3)?,
+                target: r.get(4)?,
+                ok: r.get(5)?,
+                result: r.get(6)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Segment catalog
+    ////////////////////////////////////////////////////////////////////////////////
+
+    /// Add a segment unless the same file is already listed for that sink.
+    /// Returns whether a row was inserted.
+    pub fn insert_segment(&self, segment: &SegmentRecord) -> rusqlite::Result<bool> {
+        let exists: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT id FROM segments WHERE camera_id = ?1 AND sink_id = ?2 AND rel_path = ?3;",
+                params![segment.camera_id, segment.sink_id, segment.rel_path],
+                |r| r.get(0),
+            )
+            .optional()?;
+        if exists.is_some() {
+            return Ok(false);
+        }
+        self.conn.execute(
+            "INSERT INTO segments (camera_id, sink_id, segment_index, segment_gen, absolute_index,
+                                   start_utc, end_utc, rel_path, codec, width, height, fps, bytes, restarted, disk)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15);",
+            params![
+                segment.camera_id,
+                segment.sink_id,
+                segment.segment_index,
+                segment.segment_gen,
+                segment.absolute_index,
+                segment.start_utc,
+                segment.end_utc,
+                segment.rel_path,
+                segment.stream.codec,
+                segment.stream.width,
+                segment.stream.height,
+                segment.stream.fps,
+                segment.bytes,
+                segment.restarted,
+                segment.disk
+            ],
+        )?;
+        Ok(true)
+    }
+
+    /// Catalog a segment a sink just finished. A ring sink writes the same files
+    /// over and over, so the row of an earlier file at that path is replaced, as
+    /// is that of an earlier generation's file in the same slot (whose name, with
+    /// `generation_names`, differs and which the sink has just removed).
+    /// Replaced rows of other recordings leave a tombstone.
+    pub fn record_segment(&self, segment: &SegmentRecord) -> rusqlite::Result<()> {
         let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO segment_tombstones (camera_id, sink_id, segment_index, segment_gen, start_utc, end_utc,
+                                             rel_path, reason, deleted_at_utc)
+             SELECT camera_id, sink_id, segment_index, segment_gen, start_utc, end_utc, rel_path, ?7, ?8
+             FROM segments
+             WHERE camera_id = ?1 AND sink_id = ?2 AND (rel_path = ?3 OR (segment_index = ?4 AND segment_gen < ?5))
+               AND absolute_index <> ?6;",
+            params![
+                segment.camera_id,
+                segment.sink_id,
+                segment.rel_path,
+                segment.segment_index,
+                segment.segment_gen,
+                segment.absolute_index,
+                DeletionReason::Overwritten.as_str(),
+                segment.start_utc
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM segments
+             WHERE camera_id = ?1 AND sink_id = ?2 AND (rel_path = ?3 OR (segment_index = ?4 AND segment_gen < ?5));",
+            params![segment.camera_id, segment.sink_id, segment.rel_path, segment.segment_index, segment.segment_gen],
+        )?;
+        self.insert_segment(segment)?;
+        tx.commit()
+    }
 
-        let (cur_idx, cur_gen, cur_abs): (i64, i64, i64) = tx.query_row(
-            "SELECT segment_index, segment_generation, absolute_segments
-             FROM camera_state
-             WHERE camera_id = ?1 AND sink_id = ?2;",
-            params![camera_id, sink_id],
-            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+    /// A camera's segments overlapping `[from_utc, to_utc]`, oldest first.
+    pub fn segments_between(&self, camera_id: i64, from_utc: i64, to_utc: i64) -> rusqlite::Result<Vec<SegmentRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, sink_id, start_utc, end_utc, rel_path, bytes, codec, width, height, fps,
+                    restarted, clock_offset_ms, absolute_index
+             FROM segments
+             WHERE camera_id = ?1 AND start_utc <= ?3 AND end_utc >= ?2
+             ORDER BY start_utc, sink_id;",
         )?;
+        let rows = stmt.query_map(params![camera_id, from_utc, to_utc], |r| {
+            Ok(SegmentRow {
+                id: r.get(0)?,
+                sink_id: r.get(1)?,
+                start_utc: r.get(2)?,
+                end_utc: r.get(3)?,
+                absolute_index: r.get(12)?,
+                rel_path: r.get(4)?,
+                bytes: r.get(5)?,
+                stream: SegmentStream { codec: r.get(6)?, width: r.get(7)?, height: r.get(8)?, fps: r.get(9)? },
+                restarted: r.get(10)?,
+                clock_offset_ms: r.get(11)?,
+            })
+        })?;
+        rows.collect()
+    }
 
-        let (next_idx, next_gen, wrapped) = if cur_idx + 1 >= max_segments {
-            (0, cur_gen + 1, true)
-        } else {
-            (cur_idx + 1, cur_gen, false)
-        };
+    /// Whether the segment (this recording of its file) has been saved already.
+    pub fn is_segment_protected(&self, segment_id: i64) -> rusqlite::Result<bool> {
+        self.conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM protected_segments WHERE segment_id = ?1);",
+            params![segment_id],
+            |r| r.get(0),
+        )
+    }
+
+    /// Record that `segment` was saved to `saved_path` for a `kind` event.
+    pub fn insert_protected_segment(
+        &self,
+        camera_id: i64,
+        segment: &SegmentRow,
+        saved_path: &str,
+        kind: EventKind,
+        saved_at_utc: i64,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO protected_segments (camera_id, sink_id, segment_id, start_utc, end_utc,
+                                             source_path, saved_path, kind, saved_at_utc)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9);",
+            params![
+                camera_id,
+                segment.sink_id,
+                segment.id,
+                segment.start_utc,
+                segment.end_utc,
+                segment.rel_path,
+                saved_path,
+                kind.as_str(),
+                saved_at_utc
+            ],
+        )?;
+        Ok(())
+    }
 
+    ////////////////////////////////////////////////////////////////////////////////
+    // Clock corrections
+    ////////////////////////////////////////////////////////////////////////////////
+
+    /// Store a clock step and mark the segments stamped with the old clock
+    /// (started between `since_utc` and `at_utc`) with its size.
+    pub fn insert_clock_correction(&self, correction: &ClockCorrection) -> rusqlite::Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
         tx.execute(
-            "UPDATE camera_state
-             SET segment_index = ?1
-             WHERE camera_id = ?2 AND sink_id = ?3;",
-            params![next_idx, camera_id, sink_id],
+            "INSERT INTO clock_corrections (at_utc, offset_ms, since_utc) VALUES (?1, ?2, ?3);",
+            params![correction.at_utc, correction.offset_ms, correction.since_utc],
         )?;
+        let annotated = tx.execute(
+            "UPDATE segments
+             SET clock_offset_ms = MAX(COALESCE(clock_offset_ms, 0), ?1)
+             WHERE start_utc >= ?2 AND start_utc <= ?3;",
+            params![correction.offset_ms.abs(), correction.since_utc, correction.at_utc],
+        )?;
+        tx.commit()?;
+        Ok(annotated)
+    }
+
+    /// Largest clock correction (ms, absolute) covering any part of `[from_utc, to_utc]`,
+    /// `None` if the clock wasn't corrected during that time.
+    pub fn clock_accuracy_between(&self, from_utc: i64, to_utc: i64) -> rusqlite::Result<Option<i64>> {
+        self.conn.query_row(
+            "SELECT MAX(ABS(offset_ms)) FROM clock_corrections WHERE since_utc <= ?2 AND at_utc >= ?1;",
+            params![from_utc, to_utc],
+            |r| r.get(0),
+        )
+    }
+
+    pub fn count_segments(&self, camera_id: i64, sink_id: i64) -> rusqlite::Result<i64> {
+        self.conn.query_row(
+            "SELECT COUNT(*) FROM segments WHERE camera_id = ?1 AND sink_id = ?2;",
+            params![camera_id, sink_id],
+            |r| r.get(0),
+        )
+    }
 
-        if wrapped {
+    /// Stream of the catalogued file at `rel_path` (the newest row if a ring
+    /// wrote it more than once), `None` if it isn't catalogued.
+    pub fn segment_stream(&self, rel_path: &str) -> rusqlite::Result<Option<SegmentStream>> {
+        self.conn
+            .query_row(
+                "SELECT codec, width, height, fps FROM segments WHERE rel_path = ?1 ORDER BY id DESC LIMIT 1;",
+                params![rel_path],
+                |r| Ok(SegmentStream { codec: r.get(0)?, width: r.get(1)?, height: r.get(2)?, fps: r.get(3)? }),
+            )
+            .optional()
+    }
+
+    /// Drop the catalogue rows of a camera's files at `rel_paths`, which were
+    /// just deleted, leaving a tombstone for each. Returns how many rows went.
+    pub fn forget_segments(
+        &self,
+        camera_id: i64,
+        rel_paths: &[String],
+        reason: DeletionReason,
+        deleted_at_utc: i64,
+    ) -> rusqlite::Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut forgotten = 0;
+        for rel_path in rel_paths {
             tx.execute(
-                "UPDATE camera_state
-                 SET segment_generation = ?1
-                 WHERE camera_id = ?2 AND sink_id = ?3;",
-                params![next_gen, camera_id, sink_id],
+                "INSERT INTO segment_tombstones (camera_id, sink_id, segment_index, segment_gen, start_utc, end_utc,
+                                                 rel_path, reason, deleted_at_utc)
+                 SELECT camera_id, sink_id, segment_index, segment_gen, start_utc, end_utc, rel_path, ?3, ?4
+                 FROM segments WHERE camera_id = ?1 AND rel_path = ?2;",
+                params![camera_id, rel_path, reason.as_str(), deleted_at_utc],
+            )?;
+            forgotten += tx.execute(
+                "DELETE FROM segments WHERE camera_id = ?1 AND rel_path = ?2;",
+                params![camera_id, rel_path],
             )?;
         }
+        tx.commit()?;
+        Ok(forgotten)
+    }
 
-        tx.execute(
-            "UPDATE camera_state
-             SET absolute_segments = ?1
-             WHERE camera_id = ?2 AND sink_id = ?3;",
-            params![cur_abs + 1, camera_id, sink_id],
+    /// Tombstones of a camera's footage that covered part of `[from_utc, to_utc]`, oldest first.
+    pub fn tombstones_between(&self, camera_id: i64, from_utc: i64, to_utc: i64) -> rusqlite::Result<Vec<TombstoneRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT sink_id, segment_index, segment_gen, start_utc, end_utc, rel_path, reason, deleted_at_utc
+             FROM segment_tombstones
+             WHERE camera_id = ?1 AND start_utc <= ?3 AND end_utc >= ?2
+             ORDER BY start_utc, sink_id;",
         )?;
+        let rows = stmt.query_map(params![camera_id, from_utc, to_utc], |r| {
+            Ok(TombstoneRow {
+                sink_id: r.get(0)?,
+                segment_index: r.get(1)?,
+                segment_gen: r.get(2)?,
+                start_utc: r.get(3)?,
+                end_utc: r.get(4)?,
+                rel_path: r.get(5)?,
+                reason: r.get(6)?,
+                deleted_at_utc: r.get(7)?,
+            })
+        })?;
+        rows.collect()
+    }
 
-        tx.commit()?;
-        Ok(next_idx)
+    /// Catalogued imported footage of a camera (files of its `historical_sinks`),
+    /// relative to the recording root. Recording sinks' own files aren't listed.
+    pub fn segment_rel_paths(&self, camera_id: i64) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT rel_path FROM segments
+             WHERE camera_id = ?1
+               AND sink_id IN (SELECT sink_id FROM historical_sinks WHERE camera_id = ?1);",
+        )?;
+        let rows = stmt.query_map(params![camera_id], |r| r.get(0))?;
+        rows.collect()
+    }
+
+    /// The `limit` oldest segments (by end time, any camera) under the recording
+    /// root that no event saved. Imported footage, files on spanned disks and the
+    /// ring slots a sink is writing or opens next aren't listed.
+    pub fn oldest_prunable_segments(&self, limit: i64) -> rusqlite::Result<Vec<PrunableSegment>> {
+        // `camera_state.segment_index` is the slot after the open one; right after
+        // a wrap the open slot is the last, whose row is the only one two laps old
+        let mut stmt = self.conn.prepare(
+            "SELECT s.camera_id, s.rel_path, s.end_utc
+             FROM segments s
+             WHERE s.disk IS NULL
+               AND NOT EXISTS (SELECT 1 FROM protected_segments p WHERE p.segment_id = s.id)
+               AND s.sink_id NOT IN (SELECT sink_id FROM historical_sinks h WHERE h.camera_id = s.camera_id)
+               AND NOT EXISTS (
+                   SELECT 1 FROM camera_state c
+                   WHERE c.camera_id = s.camera_id AND c.sink_id = s.sink_id
+                     AND (s.segment_index IN (c.segment_index - 1, c.segment_index)
+                          OR (c.segment_index = 0 AND s.segment_gen < c.segment_generation - 1)))
+             ORDER BY s.end_utc, s.id
+             LIMIT ?1;",
+        )?;
+        let rows = stmt.query_map(params![limit], |r| {
+            Ok(PrunableSegment { camera_id: r.get(0)?, rel_path: r.get(1)?, end_utc: r.get(2)? })
+        })?;
+        rows.collect()
+    }
+
+    /// Up to `limit` segments after `after_id` (by id) lacking a thumbnail
+    /// (if `thumbnails`) or a checksum (if `checksums`).
+    pub fn segments_missing_derived(
+        &self,
+        after_id: i64,
+        limit: i64,
+        thumbnails: bool,
+        checksums: bool,
+    ) -> rusqlite::Result<Vec<PendingSegment>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, rel_path, thumbnail_path IS NULL, checksum IS NULL
+             FROM segments
+             WHERE id > ?1 AND ((?3 AND thumbnail_path IS NULL) OR (?4 AND checksum IS NULL))
+             ORDER BY id
+             LIMIT ?2;",
+        )?;
+        let rows = stmt.query_map(params![after_id, limit, thumbnails, checksums], |r| {
+            Ok(PendingSegment {
+                id: r.get(0)?,
+                rel_path: r.get(1)?,
+                needs_thumbnail: thumbnails && r.get::<_, bool>(2)?,
+                needs_checksum: checksums && r.get::<_, bool>(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Store whichever of a segment's thumbnail and checksum are given, keeping the other.
+    pub fn set_segment_derived(
+        &self,
+        id: i64,
+        thumbnail_path: Option<&str>,
+        checksum: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE segments
+             SET thumbnail_path = COALESCE(?2, thumbnail_path),
+                 checksum       = COALESCE(?3, checksum)
+             WHERE id = ?1;",
+            params![id, thumbnail_path, checksum],
+        )?;
+        Ok(())
+    }
+
+    /// The file at `rel_path` was re-encoded in place (`tiering`): new size and
+    /// resolution, and its checksum is gone until the backfill reads it again.
+    /// Returns how many rows it was catalogued in.
+    pub fn set_segment_tiered(&self, rel_path: &str, width: i64, height: i64, bytes: i64) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "UPDATE segments
+             SET width = ?2, height = ?3, bytes = ?4, checksum = NULL
+             WHERE rel_path = ?1;",
+            params![rel_path, width, height, bytes],
+        )
+    }
+
+    /// Start and end (`None` while open) of a trip, `None` if there is no such trip.
+    pub fn get_trip_span(&self, trip_id: i64) -> rusqlite::Result<Option<(i64, Option<i64>)>> {
+        self.conn
+            .query_row(
+                "SELECT start_time_utc, end_time_utc FROM trips WHERE id = ?1;",
+                params![trip_id],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .optional()
+    }
+
+    /// Record one GPS fix.
+    pub fn insert_gps_point(&self, point: &GpsPoint) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO gps_points (at_utc, lat, lon, alt_m, speed_mps, heading_deg)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+            params![point.at_utc, point.lat, point.lon, point.alt_m, point.speed_mps, point.heading_deg],
+        )?;
+        Ok(())
+    }
+
+    /// GPS fixes with `from_utc <= at_utc < to_utc`, oldest first.
+    pub fn gps_points_between(&self, from_utc: i64, to_utc: i64) -> rusqlite::Result<Vec<GpsPoint>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT at_utc, lat, lon, alt_m, speed_mps, heading_deg
+             FROM gps_points
+             WHERE at_utc >= ?1 AND at_utc < ?2
+             ORDER BY at_utc, id;",
+        )?;
+        let rows = stmt.query_map(params![from_utc, to_utc], |r| {
+            Ok(GpsPoint {
+                at_utc: r.get(0)?,
+                lat: r.get(1)?,
+                lon: r.get(2)?,
+                alt_m: r.get(3)?,
+                speed_mps: r.get(4)?,
+                heading_deg: r.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// GPS fixes taken while segment `segment_id` was written, oldest first.
+    pub fn gps_points_for_segment(&self, segment_id: i64) -> rusqlite::Result<Vec<GpsPoint>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT g.at_utc, g.lat, g.lon, g.alt_m, g.speed_mps, g.heading_deg
+             FROM gps_points g
+             JOIN segments s ON s.id = ?1
+             WHERE g.at_utc >= s.start_utc AND g.at_utc <= s.end_utc
+             ORDER BY g.at_utc, g.id;",
+        )?;
+        let rows = stmt.query_map(params![segment_id], |r| {
+            Ok(GpsPoint {
+                at_utc: r.get(0)?,
+                lat: r.get(1)?,
+                lon: r.get(2)?,
+                alt_m: r.get(3)?,
+                speed_mps: r.get(4)?,
+                heading_deg: r.get(5)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn get_rule_states(&self) -> rusqlite::Result<Vec<RuleStateRow>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, armed, fire_count, last_fired_utc, fired_on FROM rule_state ORDER BY name;")?;
+        let rows = stmt.query_map([], |r| {
+            Ok(RuleStateRow {
+                name: r.get(0)?,
+                armed: r.get(1)?,
+                fire_count: r.get(2)?,
+                last_fired_utc: r.get(3)?,
+                fired_on: r.get(4)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn save_rule_state(&self, state: &RuleStateRow) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO rule_state (name, armed, fire_count, last_fired_utc, fired_on)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(name) DO UPDATE SET
+                armed          = excluded.armed,
+                fire_count     = excluded.fire_count,
+                last_fired_utc = excluded.last_fired_utc,
+                fired_on       = excluded.fired_on;",
+            params![state.name, state.armed, state.fire_count, state.last_fired_utc, state.fired_on],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_mode_change(&self, mode: &str, actor: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO mode_history (at_utc, mode, actor) VALUES (?1, ?2, ?3);",
+            params![chrono::Utc::now().timestamp(), mode, actor],
+        )?;
+        Ok(())
+    }
+
+    /// Most recent mode switches, newest first.
+    pub fn get_mode_history(&self, limit: i64) -> rusqlite::Result<Vec<ModeChangeRow>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT at_utc, mode, actor
+             FROM mode_history
+             ORDER BY id DESC
+             LIMIT ?1;",
+        )?;
+        let rows = stmt.query_map(params![limit], |r| {
+            Ok(ModeChangeRow {
+                at_utc: r.get(0)?,
+                mode: r.get(1)?,
+                actor: r.get(2)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Record one OBD-II sample.
+    pub fn insert_telemetry(&self, sample: &TelemetrySample) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO telemetry (at_utc, source, speed_kmh, rpm, throttle_pct)
+             VALUES (?1, 'obd', ?2, ?3, ?4);",
+            params![sample.at_utc, sample.speed_kmh, sample.rpm, sample.throttle_pct],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_drive_sample(&self, sample: &DriveSample) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO drive_health (at_utc, device, passed, temperature_c, reallocated_sectors, pending_sectors)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+            params![
+                sample.at_utc,
+                sample.device,
+                sample.passed,
+                sample.temperature_c,
+                sample.reallocated_sectors,
+                sample.pending_sectors
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Oldest sample of `device` taken at or after `since_utc`.
+    pub fn drive_sample_since(&self, device: &str, since_utc: i64) -> rusqlite::Result<Option<DriveSample>> {
+        self.conn
+            .query_row(
+                "SELECT device, at_utc, passed, temperature_c, reallocated_sectors, pending_sectors
+                 FROM drive_health WHERE device = ?1 AND at_utc >= ?2
+                 ORDER BY at_utc, id LIMIT 1;",
+                params![device, since_utc],
+                |r| {
+                    Ok(DriveSample {
+                        device: r.get(0)?,
+                        at_utc: r.get(1)?,
+                        passed: r.get(2)?,
+                        temperature_c: r.get(3)?,
+                        reallocated_sectors: r.get(4)?,
+                        pending_sectors: r.get(5)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    /// Telemetry recorded while segment `segment_id` was written, oldest first.
+    pub fn telemetry_for_segment(&self, segment_id: i64) -> rusqlite::Result<Vec<TelemetrySample>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT t.at_utc, t.speed_kmh, t.rpm, t.throttle_pct
+             FROM telemetry t
+             JOIN segments s ON s.id = ?1
+             WHERE t.at_utc >= s.start_utc AND t.at_utc <= s.end_utc
+             ORDER BY t.at_utc, t.id;",
+        )?;
+        let rows = stmt.query_map(params![segment_id], |r| {
+            Ok(TelemetrySample {
+                at_utc: r.get(0)?,
+                speed_kmh: r.get(1)?,
+                rpm: r.get(2)?,
+                throttle_pct: r.get(3)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Vehicle speed in km/h at `at_utc`: the latest GPS fix with a speed in the
+    /// `max_age_sec` before it, else the latest OBD sample (no fix in a tunnel).
+    pub fn speed_at(&self, at_utc: i64, max_age_sec: i64) -> rusqlite::Result<Option<f64>> {
+        let gps: Option<Option<f64>> = self
+            .conn
+            .query_row(
+                "SELECT speed_mps * 3.6 FROM gps_points
+                 WHERE speed_mps IS NOT NULL AND at_utc <= ?1 AND at_utc >= ?2
+                 ORDER BY at_utc DESC, id DESC LIMIT 1;",
+                params![at_utc, at_utc - max_age_sec],
+                |r| r.get(0),
+            )
+            .optional()?;
+        if let Some(speed) = gps.flatten() {
+            return Ok(Some(speed));
+        }
+        let obd: Option<Option<f64>> = self
+            .conn
+            .query_row(
+                "SELECT speed_kmh FROM telemetry
+                 WHERE speed_kmh IS NOT NULL AND at_utc <= ?1 AND at_utc >= ?2
+                 ORDER BY at_utc DESC, id DESC LIMIT 1;",
+                params![at_utc, at_utc - max_age_sec],
+                |r| r.get(0),
+            )
+            .optional()?;
+        Ok(obd.flatten())
+    }
+
+    /// Record (or refresh) a read-only sink holding imported footage.
+    pub fn register_historical_sink(&self, camera_id: i64, sink_id: i64, source_dir: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO historical_sinks (camera_id, sink_id, source_dir, imported_at_utc)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(camera_id, sink_id) DO UPDATE SET
+                source_dir      = excluded.source_dir,
+                imported_at_utc = excluded.imported_at_utc;",
+            params![camera_id, sink_id, source_dir, chrono::Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Runtime settings
+    ////////////////////////////////////////////////////////////////////////////////
+
+    /// Store a runtime setting. Returns true if the value actually changed.
+    pub fn set_setting(&self, key: &str, value: &str) -> rusqlite::Result<bool> {
+        if self.get_setting(key)?.as_deref() == Some(value) {
+            return Ok(false);
+        }
+        self.conn.execute(
+            "INSERT INTO settings (key, value, updated_at_utc)
+             VALUES (?1, ?2, ?3)
+             ON CONFLICT(key) DO UPDATE SET
+                value          = excluded.value,
+                updated_at_utc = excluded.updated_at_utc;",
+            params![key, value, chrono::Utc::now().timestamp()],
+        )?;
+        Ok(true)
+    }
+
+    pub fn get_setting(&self, key: &str) -> rusqlite::Result<Option<String>> {
+        self.conn
+            .query_row("SELECT value FROM settings WHERE key = ?1;", params![key], |r| r.get(0))
+            .optional()
+    }
+
+    /// Add a finished segment's size to what has been written under `storage_root`.
+    pub fn add_bytes_written(&self, storage_root: &str, bytes: i64) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT INTO counters (name, value) VALUES (?1, ?2)
+             ON CONFLICT(name) DO UPDATE SET value = value + excluded.value;",
+            params![storage_wear::counter_name(storage_root), bytes],
+        )?;
+        Ok(())
+    }
+
+    /// Segment bytes written under `storage_root` so far, see `storage_wear`.
+    pub fn bytes_written(&self, storage_root: &str) -> rusqlite::Result<i64> {
+        Ok(self
+            .conn
+            .query_row(
+                "SELECT value FROM counters WHERE name = ?1;",
+                params![storage_wear::counter_name(storage_root)],
+                |r| r.get(0),
+            )
+            .optional()?
+            .unwrap_or(0))
     }
 
     ////////////////////////////////////////////////////////////////////////////////
@@ -389,3 +1495,14 @@ impl DashcamDb {
         Ok(())
     }
 }
+
+fn incident_row(r: &rusqlite::Row<'_>) -> rusqlite::Result<IncidentRow> {
+    Ok(IncidentRow {
+        id: r.get(0)?,
+        group_name: r.get(1)?,
+        camera_key: r.get(2)?,
+        kind: r.get(3)?,
+        at_utc: r.get(4)?,
+        dir: r.get(5)?,
+    })
+}
@@ -0,0 +1,92 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+
+use crate::config::{AppConfig, CameraConfig, SinkConfig};
+use crate::db::db::CameraState;
+use crate::db::db_worker::DBMessage;
+use crate::pipeline_sinks::segment_counters::SegmentCounters;
+
+/// Read-through cache of what the factory asks the DB worker while building
+/// pipelines: camera ids, ring indices and sink enable overrides. `prefetch`
+/// loads every camera of the config in one round-trip; a camera it missed is
+/// fetched on first use.
+pub struct DbFacade {
+    db_sender: Arc<Sender<DBMessage>>,
+    cache: Mutex<HashMap<String, CameraState>>,
+}
+
+/// What a sink that talks to the DB gets instead of a bare sender.
+#[derive(Clone)]
+pub struct SinkDb {
+    pub camera_id: i64,
+    pub sink_id: i64,
+    /// Ring counters to continue from
+    pub counters: SegmentCounters,
+    pub db_sender: Arc<Sender<DBMessage>>,
+}
+
+impl DbFacade {
+    pub fn new(db_sender: Arc<Sender<DBMessage>>) -> Self {
+        Self { db_sender, cache: Mutex::new(HashMap::new()) }
+    }
+
+    /// Load the enabled cameras of `cfg` with one DB message.
+    pub fn prefetch(&self, cfg: &AppConfig) -> Result<()> {
+        let cameras: Vec<&CameraConfig> = cfg.cameras.iter().filter(|c| c.enabled).collect();
+        self.fetch(&cameras)
+    }
+
+    fn fetch(&self, cameras: &[&CameraConfig]) -> Result<()> {
+        let request = cameras
+            .iter()
+            .map(|c| (c.key.clone(), c.sinks.iter().map(|s| s.sink_id()).collect()))
+            .collect();
+        let (tx, rx) = mpsc::channel();
+        self.db_sender.send(DBMessage::GetCameraStates { cameras: request, reply: tx })?;
+        let states = rx
+            .recv()
+            .map_err(|e| anyhow!("DBWorker channel closed while loading camera state: {}", e))?;
+
+        let mut cache = self.cache.lock().unwrap();
+        for state in states {
+            cache.insert(state.camera_key.clone(), state);
+        }
+        Ok(())
+    }
+
+    /// The camera's DB state, from the cache if it's there.
+    pub fn camera(&self, cam: &CameraConfig) -> Result<CameraState> {
+        if let Some(state) = self.cache.lock().unwrap().get(&cam.key) {
+            return Ok(state.clone());
+        }
+        self.fetch(&[cam])?;
+        self.cache
+            .lock()
+            .unwrap()
+            .get(&cam.key)
+            .cloned()
+            .ok_or_else(|| anyhow!("DBWorker could not find camera_id for key '{}'", cam.key))
+    }
+
+    pub fn camera_id(&self, cam: &CameraConfig) -> Result<i64> {
+        Ok(self.camera(cam)?.camera_id)
+    }
+
+    /// Effective enabled state for a sink: a runtime override in the DB wins over config.toml.
+    pub fn sink_enabled(&self, cam: &CameraConfig, sink_cfg: &SinkConfig) -> Result<bool> {
+        let state = self.camera(cam)?;
+        let enabled_override = state.sinks.iter().find(|s| s.sink_id == sink_cfg.sink_id()).and_then(|s| s.enabled_override);
+        Ok(enabled_override.unwrap_or(sink_cfg.enabled()))
+    }
+
+    /// Handle for a sink that keeps its own counters in the DB.
+    pub fn sink(&self, cam: &CameraConfig, sink_id: i64) -> Result<SinkDb> {
+        let state = self.camera(cam)?;
+        let counters = state.sinks.iter().find(|s| s.sink_id == sink_id).map_or_else(SegmentCounters::default, |s| {
+            SegmentCounters { index: s.segment_index, generation: s.generation, absolute: s.absolute_segments }
+        });
+        Ok(SinkDb { camera_id: state.camera_id, sink_id, counters, db_sender: self.db_sender.clone() })
+    }
+}
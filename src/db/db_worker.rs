@@ -1,24 +1,24 @@
 use anyhow::Result;
 use std::{
-    sync::mpsc::{self, Receiver, Sender},
+    sync::mpsc::{Receiver, Sender},
     thread::JoinHandle,
+    time::Instant,
 };
 use tracing::{error, info, trace};
 
-use crate::{config::AppConfig, db::db::{self, DashcamDb}};
+use crate::{config::AppConfig, db::db::{self, AuditRow, CameraState, DashcamDb, IncidentRow, PendingSegment, PrunableSegment, SegmentRow}, events::{CameraEvent, EventKind}, incident_bundle::{self, IncidentRecord}, metrics, protect::{self, ProtectPlan}, time_sync::ClockCorrection, track::{self, TrackRange}};
 // use crate::db::{self, DashcamDb};
 
 pub enum DBMessage {
     SegmentUpdate {
+        /// For the commit latency metric
+        camera_key: String,
         camera_id: i64,
         sink_id: i64,
         segment_index: i64,
         max_segments: i64,
-    },
-    GetSegmentIndex {
-        camera_id: i64,
-        sink_id:i64,
-        reply: Sender<i64>,
+        /// When the segment rolled over
+        queued_at: Instant,
     },
     ClampSegmentIndex {
         camera_id: i64,
@@ -30,11 +30,224 @@ pub enum DBMessage {
         camera_key: String,
         reply: Sender<Option<i64>>,
     },
+    /// Everything the factory needs, for many cameras in one round-trip:
+    /// (camera key, sink ids). Cameras the DB doesn't know are left out of the reply.
+    GetCameraStates {
+        cameras: Vec<(String, Vec<i64>)>,
+        reply: Sender<Vec<CameraState>>,
+    },
+
+    SetSinkEnabled {
+        camera_id: i64,
+        sink_id: i64,
+        enabled: bool,
+    },
+
+    InsertEvent {
+        camera_key: String,
+        event: CameraEvent,
+    },
+    GetRecentEvents {
+        camera_id: i64,
+        limit: i64,
+        reply: Sender<Vec<db::EventRow>>,
+    },
+    /// New incident of a camera group; replies with its id, `None` if the insert failed
+    InsertIncident {
+        group_name: String,
+        camera_key: String,
+        event: CameraEvent,
+        dir: String,
+        reply: Sender<Option<i64>>,
+    },
+    InsertIncidentClip {
+        incident_id: i64,
+        camera_key: String,
+        path: String,
+        from_utc: i64,
+        to_utc: i64,
+    },
+    /// Everything for an incident's bundle, `None` if it doesn't exist or the query failed
+    GetIncidentRecord {
+        incident_id: i64,
+        reply: Sender<Option<IncidentRecord>>,
+    },
+    GetRecentIncidents {
+        limit: i64,
+        reply: Sender<Vec<IncidentRow>>,
+    },
+    /// `[gps]` fix
+    InsertGpsPoint {
+        point: db::GpsPoint,
+    },
+    /// GPS fixes of a trip or time range, `None` if the trip doesn't exist or the query failed
+    GetTrack {
+        range: TrackRange,
+        reply: Sender<Option<Vec<db::GpsPoint>>>,
+    },
+
+    /// A file sink finished a segment, see `DashcamDb::record_segment`
+    RecordSegment {
+        segment: db::SegmentRecord,
+    },
+    /// A camera's segments overlapping a time range, `None` if the query failed
+    GetSegmentsBetween {
+        camera_id: i64,
+        from_utc: i64,
+        to_utc: i64,
+        reply: Sender<Option<Vec<db::SegmentRow>>>,
+    },
+
+    /// Segment bytes written under a storage root, `None` if the query failed
+    GetBytesWritten {
+        storage_root: String,
+        reply: Sender<Option<i64>>,
+    },
+
+    /// Unsaved segments of a window, see `protect::plan_window`; the caller links them
+    GetProtectPlan {
+        camera_id: i64,
+        from_utc: i64,
+        to_utc: i64,
+        reply: Sender<Option<ProtectPlan>>,
+    },
+    /// Segments `protect::save_segments` saved, as (segment, saved path)
+    InsertProtectedSegments {
+        camera_id: i64,
+        kind: EventKind,
+        saved: Vec<(SegmentRow, String)>,
+        saved_at_utc: i64,
+    },
+
+    /// `segments.rel_path` of a camera's imported footage, `None` if the query failed
+    GetSegmentPaths {
+        camera_id: i64,
+        reply: Sender<Option<Vec<String>>>,
+    },
+
+    /// Oldest segments the storage manager may delete, see `DashcamDb::oldest_prunable_segments`
+    GetPrunableSegments {
+        limit: i64,
+        reply: Sender<Option<Vec<PrunableSegment>>>,
+    },
+
+    /// Segments still lacking derived artifacts, see `DashcamDb::segments_missing_derived`
+    GetSegmentsMissingDerived {
+        after_id: i64,
+        limit: i64,
+        thumbnails: bool,
+        checksums: bool,
+        reply: Sender<Option<Vec<PendingSegment>>>,
+    },
+    SetSegmentDerived {
+        id: i64,
+        thumbnail_path: Option<String>,
+        checksum: Option<String>,
+    },
+
+    /// File re-encoded in place; see `DashcamDb::set_segment_tiered`
+    SetSegmentTiered {
+        rel_path: String,
+        width: i64,
+        height: i64,
+        bytes: i64,
+    },
+
+    /// Files at `rel_paths` were deleted; see `DashcamDb::forget_segments`
+    ForgetSegments {
+        camera_id: i64,
+        rel_paths: Vec<String>,
+        reason: db::DeletionReason,
+    },
+
+    InsertTelemetry {
+        sample: db::TelemetrySample,
+    },
+
+    /// `[smart]` sample of a recording drive
+    InsertDriveSample {
+        sample: db::DriveSample,
+    },
+    /// Oldest sample of `device` since `since_utc`, to compare the newest against
+    GetDriveBaseline {
+        device: String,
+        since_utc: i64,
+        reply: Sender<Option<db::DriveSample>>,
+    },
+
+    /// Stored `[[rules]]` state, `None` if the query failed
+    GetRuleStates {
+        reply: Sender<Option<Vec<db::RuleStateRow>>>,
+    },
+    SaveRuleState {
+        state: db::RuleStateRow,
+    },
+
+    InsertModeChange {
+        mode: String,
+        actor: String,
+    },
+    /// Newest first, `None` if the query failed
+    GetModeHistory {
+        limit: i64,
+        reply: Sender<Option<Vec<db::ModeChangeRow>>>,
+    },
+
+    InsertAudit {
+        actor: String,
+        action: String,
+        target: Option<String>,
+        ok: bool,
+        result: Option<String>,
+    },
+    GetRecentAudit {
+        limit: i64,
+        reply: Sender<Vec<AuditRow>>,
+    },
+
+    /// Liveness probe for `/healthz`; answered right away
+    Ping {
+        reply: Sender<()>,
+    },
+
+    /// Wall clock step noticed by the time-sync watcher
+    ClockCorrection {
+        correction: ClockCorrection,
+    },
+
+    /// WAL checkpoint; replies once done
+    Checkpoint {
+        reply: Sender<()>,
+    },
+
+    /// Runtime setting; subscribers hear about it if the value changed
+    SetSetting {
+        key: String,
+        value: String,
+    },
+    GetSetting {
+        key: String,
+        reply: Sender<Option<String>>,
+    },
+    /// Get a `SettingChange` for every later change, until the receiver is dropped
+    SubscribeSettings {
+        subscriber: Sender<SettingChange>,
+    },
+}
+
+/// Sent to settings subscribers after a value changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingChange {
+    pub key: String,
+    pub value: String,
 }
 
 pub struct DBWorker {
     pub recvr: Receiver<DBMessage>,
     pub dbconn: DashcamDb,
+    /// Where recorded segments count towards, see `storage_wear`
+    pub recording_root: String,
+    pub setting_subscribers: Vec<Sender<SettingChange>>,
 }
 
 impl DBWorker {
@@ -46,21 +259,23 @@ impl DBWorker {
     pub fn new(recvr: Receiver<DBMessage>, cfg: &AppConfig) -> Result<Self> {
         let dbconn = db::DashcamDb::setup_from_config(cfg)?;
 
-        Ok(DBWorker { recvr, dbconn })
+        Ok(DBWorker { recvr, dbconn, recording_root: cfg.recording_root(), setting_subscribers: Vec::new() })
     }
 }
 
-pub fn start_db_worker(dbworker: DBWorker) -> JoinHandle<()> {
-    let thread = std::thread::spawn(move || {
+pub fn start_db_worker(mut dbworker: DBWorker) -> JoinHandle<()> {
+    std::thread::spawn(move || {
         while let Ok(db_message) = dbworker.recvr.recv() {
 
             match db_message {
 
                 DBMessage::SegmentUpdate {
+                    camera_key,
                     camera_id,
                     sink_id,
                     segment_index,
                     max_segments,
+                    queued_at,
                 } => {
                     trace!(
                         "DB Worker received SegmentUpdate: camera_id={}, segment_index={}, max_segments={}",
@@ -79,23 +294,13 @@ pub fn start_db_worker(dbworker: DBWorker) -> JoinHandle<()> {
                         )
                     {
                         error!("DB Worker failed to update segment counters: {:#}", e);
+                    } else {
+                        let sink = sink_id.to_string();
+                        let labels = [("camera", camera_key.as_str()), ("sink", sink.as_str())];
+                        metrics::observe(metrics::FRAGMENT_COMMIT, &labels, queued_at.elapsed());
                     }
                 },
 
-                DBMessage::GetSegmentIndex { camera_id,  sink_id, reply } => {
-                    let segment_index = match dbworker.dbconn.get_segment_index(camera_id, sink_id) {
-                        Ok(val) => val,
-                        Err(e) => {
-                            error!(
-                                "DB Worker failed to get segment index for camera_id={}: {:#}",
-                                camera_id, e
-                            );
-                            0
-                        }
-                    };
-                    let _ = reply.send(segment_index);
-                },
-
                 DBMessage::ClampSegmentIndex {
                     camera_id,
                     sink_id,
@@ -126,12 +331,294 @@ pub fn start_db_worker(dbworker: DBWorker) -> JoinHandle<()> {
                         }
                     };
                     let _ = reply.send(id);
+                },
+
+                DBMessage::GetCameraStates { cameras, reply } => {
+                    let states = cameras
+                        .iter()
+                        .filter_map(|(camera_key, sink_ids)| {
+                            dbworker
+                                .dbconn
+                                .get_camera_state(camera_key, sink_ids)
+                                .map_err(|e| error!("DB Worker failed to load camera '{}': {:#}", camera_key, e))
+                                .ok()
+                        })
+                        .collect();
+                    let _ = reply.send(states);
+                },
+
+                DBMessage::SetSinkEnabled { camera_id, sink_id, enabled } => {
+                    info!(
+                        "DB Worker setting sink enabled={} for camera_id={} sink_id={}",
+                        enabled, camera_id, sink_id
+                    );
+                    if let Err(e) = dbworker.dbconn.set_sink_enabled(camera_id, sink_id, enabled) {
+                        error!("DB Worker failed to set sink override: {:#}", e);
+                    }
+                },
+
+                DBMessage::InsertEvent { camera_key, event } => {
+                    trace!("DB Worker recording {} event for camera '{}'", event.kind, camera_key);
+                    if let Err(e) = dbworker.dbconn.insert_event(&camera_key, &event) {
+                        error!("DB Worker failed to record event for camera '{}': {:#}", camera_key, e);
+                    }
+                },
+
+                DBMessage::GetRecentEvents { camera_id, limit, reply } => {
+                    let events = dbworker.dbconn.get_recent_events(camera_id, limit).unwrap_or_else(|e| {
+                        error!("DB Worker failed to get events for camera_id={}: {:#}", camera_id, e);
+                        Vec::new()
+                    });
+                    let _ = reply.send(events);
+                },
+
+                DBMessage::InsertIncident { group_name, camera_key, event, dir, reply } => {
+                    trace!("DB Worker recording incident of group '{}' ({} on '{}')", group_name, event.kind, camera_key);
+                    let id = dbworker.dbconn.insert_incident(&group_name, &camera_key, &event, &dir).map_err(|e| {
+                        error!("DB Worker failed to record incident of group '{}': {:#}", group_name, e);
+                    });
+                    let _ = reply.send(id.ok());
+                },
+
+                DBMessage::InsertIncidentClip { incident_id, camera_key, path, from_utc, to_utc } => {
+                    trace!("DB Worker filing clip of camera '{}' under incident {}", camera_key, incident_id);
+                    if let Err(e) = dbworker.dbconn.insert_incident_clip(incident_id, &camera_key, &path, from_utc, to_utc) {
+                        error!("DB Worker failed to record clip of incident {}: {:#}", incident_id, e);
+                    }
+                },
+
+                DBMessage::GetIncidentRecord { incident_id, reply } => {
+                    let record = incident_bundle::load_incident(&dbworker.dbconn, incident_id).unwrap_or_else(|e| {
+                        error!("DB Worker failed to load incident {}: {:#}", incident_id, e);
+                        None
+                    });
+                    let _ = reply.send(record);
+                },
+
+                DBMessage::GetRecentIncidents { limit, reply } => {
+                    let incidents = dbworker.dbconn.get_recent_incidents(limit).unwrap_or_else(|e| {
+                        error!("DB Worker failed to list incidents: {:#}", e);
+                        Vec::new()
+                    });
+                    let _ = reply.send(incidents);
+                },
+
+                DBMessage::InsertGpsPoint { point } => {
+                    if let Err(e) = dbworker.dbconn.insert_gps_point(&point) {
+                        error!("DB Worker failed to store a GPS fix: {:#}", e);
+                    }
+                },
+
+                DBMessage::GetTrack { range, reply } => {
+                    let points = track::load_track(&dbworker.dbconn, range).unwrap_or_else(|e| {
+                        error!("DB Worker failed to load the track of {}: {:#}", range.name(), e);
+                        None
+                    });
+                    let _ = reply.send(points);
+                },
+
+                DBMessage::RecordSegment { segment } => {
+                    trace!("DB Worker cataloguing segment {}", segment.rel_path);
+                    if let Err(e) = dbworker.dbconn.record_segment(&segment) {
+                        error!("DB Worker failed to catalog segment {}: {:#}", segment.rel_path, e);
+                    }
+                    if let Some(bytes) = segment.bytes
+                        && let Err(e) = dbworker.dbconn.add_bytes_written(&dbworker.recording_root, bytes)
+                    {
+                        error!("DB Worker failed to count bytes written under {}: {:#}", dbworker.recording_root, e);
+                    }
+                },
+
+                DBMessage::GetSegmentsBetween { camera_id, from_utc, to_utc, reply } => {
+                    let segments = dbworker.dbconn.segments_between(camera_id, from_utc, to_utc).map_err(|e| {
+                        error!("DB Worker failed to look up segments for camera_id={}: {:#}", camera_id, e);
+                    });
+                    let _ = reply.send(segments.ok());
+                },
+
+                DBMessage::GetBytesWritten { storage_root, reply } => {
+                    let bytes = dbworker.dbconn.bytes_written(&storage_root).map_err(|e| {
+                        error!("DB Worker failed to read bytes written under {}: {:#}", storage_root, e);
+                    });
+                    let _ = reply.send(bytes.ok());
+                },
+
+                DBMessage::GetProtectPlan { camera_id, from_utc, to_utc, reply } => {
+                    let plan = protect::plan_window(&dbworker.dbconn, camera_id, from_utc, to_utc).map_err(|e| {
+                        error!("DB Worker failed to list segments to save for camera_id={}: {:#}", camera_id, e);
+                    });
+                    let _ = reply.send(plan.ok());
+                },
+
+                DBMessage::InsertProtectedSegments { camera_id, kind, saved, saved_at_utc } => {
+                    trace!("DB Worker listing {} saved segments of camera_id={}", saved.len(), camera_id);
+                    if let Err(e) = protect::record_saved(&dbworker.dbconn, camera_id, kind, &saved, saved_at_utc) {
+                        error!("DB Worker failed to list saved segments of camera_id={}: {:#}", camera_id, e);
+                    }
+                },
+
+                DBMessage::GetSegmentPaths { camera_id, reply } => {
+                    let paths = dbworker.dbconn.segment_rel_paths(camera_id).map_err(|e| {
+                        error!("DB Worker failed to list segments for camera_id={}: {:#}", camera_id, e);
+                    });
+                    let _ = reply.send(paths.ok());
+                },
+
+                DBMessage::GetPrunableSegments { limit, reply } => {
+                    let segments = dbworker.dbconn.oldest_prunable_segments(limit).map_err(|e| {
+                        error!("DB Worker failed to list prunable segments: {:#}", e);
+                    });
+                    let _ = reply.send(segments.ok());
+                },
+
+                DBMessage::GetSegmentsMissingDerived { after_id, limit, thumbnails, checksums, reply } => {
+                    let pending = dbworker
+                        .dbconn
+                        .segments_missing_derived(after_id, limit, thumbnails, checksums)
+                        .map_err(|e| {
+                            error!("DB Worker failed to list segments to backfill: {:#}", e);
+                        });
+                    let _ = reply.send(pending.ok());
+                },
+
+                DBMessage::SetSegmentDerived { id, thumbnail_path, checksum } => {
+                    trace!("DB Worker storing derived artifacts of segment {}", id);
+                    if let Err(e) = dbworker.dbconn.set_segment_derived(id, thumbnail_path.as_deref(), checksum.as_deref()) {
+                        error!("DB Worker failed to update segment {}: {:#}", id, e);
+                    }
+                },
+
+                DBMessage::SetSegmentTiered { rel_path, width, height, bytes } => {
+                    trace!("DB Worker storing re-encoded {}", rel_path);
+                    if let Err(e) = dbworker.dbconn.set_segment_tiered(&rel_path, width, height, bytes) {
+                        error!("DB Worker failed to update {}: {:#}", rel_path, e);
+                    }
+                },
+
+                DBMessage::ForgetSegments { camera_id, rel_paths, reason } => {
+                    let now = chrono::Utc::now().timestamp();
+                    if let Err(e) = dbworker.dbconn.forget_segments(camera_id, &rel_paths, reason, now) {
+                        error!("DB Worker failed to forget {} segments of camera_id={}: {:#}", rel_paths.len(), camera_id, e);
+                    }
+                },
+
+                DBMessage::InsertTelemetry { sample } => {
+                    if let Err(e) = dbworker.dbconn.insert_telemetry(&sample) {
+                        error!("DB Worker failed to store OBD telemetry: {:#}", e);
+                    }
+                },
+
+                DBMessage::InsertDriveSample { sample } => {
+                    if let Err(e) = dbworker.dbconn.insert_drive_sample(&sample) {
+                        error!("DB Worker failed to store the health of {}: {:#}", sample.device, e);
+                    }
+                },
+
+                DBMessage::GetDriveBaseline { device, since_utc, reply } => {
+                    let baseline = dbworker.dbconn.drive_sample_since(&device, since_utc).unwrap_or_else(|e| {
+                        error!("DB Worker failed to read the health history of {}: {:#}", device, e);
+                        None
+                    });
+                    let _ = reply.send(baseline);
+                },
+
+                DBMessage::GetRuleStates { reply } => {
+                    let states = dbworker.dbconn.get_rule_states().map_err(|e| {
+                        error!("DB Worker failed to load rule state: {:#}", e);
+                    });
+                    let _ = reply.send(states.ok());
+                },
+
+                DBMessage::SaveRuleState { state } => {
+                    if let Err(e) = dbworker.dbconn.save_rule_state(&state) {
+                        error!("DB Worker failed to store state of rule '{}': {:#}", state.name, e);
+                    }
+                },
+
+                DBMessage::InsertModeChange { mode, actor } => {
+                    if let Err(e) = dbworker.dbconn.insert_mode_change(&mode, &actor) {
+                        error!("DB Worker failed to log the switch to mode '{}': {:#}", mode, e);
+                    }
+                },
+
+                DBMessage::GetModeHistory { limit, reply } => {
+                    let history = dbworker.dbconn.get_mode_history(limit).map_err(|e| {
+                        error!("DB Worker failed to load mode history: {:#}", e);
+                    });
+                    let _ = reply.send(history.ok());
+                },
+
+                DBMessage::InsertAudit { actor, action, target, ok, result } => {
+                    trace!("DB Worker auditing '{}' by '{}' (ok={})", action, actor, ok);
+                    if let Err(e) = dbworker.dbconn.insert_audit(
+                        &actor,
+                        &action,
+                        target.as_deref(),
+                        ok,
+                        result.as_deref(),
+                    ) {
+                        error!("DB Worker failed to write audit entry for '{}': {:#}", action, e);
+                    }
+                },
+
+                DBMessage::GetRecentAudit { limit, reply } => {
+                    let entries = dbworker.dbconn.get_recent_audit(limit).unwrap_or_else(|e| {
+                        error!("DB Worker failed to read audit log: {:#}", e);
+                        Vec::new()
+                    });
+                    let _ = reply.send(entries);
+                },
+
+                DBMessage::Ping { reply } => {
+                    let _ = reply.send(());
+                },
+
+                DBMessage::ClockCorrection { correction } => {
+                    match dbworker.dbconn.insert_clock_correction(&correction) {
+                        Ok(annotated) => info!(
+                            "DB Worker recorded a {}ms clock correction ({} segments annotated)",
+                            correction.offset_ms, annotated
+                        ),
+                        Err(e) => error!("DB Worker failed to record clock correction: {}", e),
+                    }
+                },
+
+                DBMessage::Checkpoint { reply } => {
+                    info!("DB Worker checkpointing WAL");
+                    if let Err(e) = dbworker.dbconn.checkpoint() {
+                        error!("DB Worker failed to checkpoint: {:#}", e);
+                    }
+                    let _ = reply.send(());
+                },
+
+                DBMessage::SetSetting { key, value } => {
+                    match dbworker.dbconn.set_setting(&key, &value) {
+                        Ok(true) => {
+                            info!("DB Worker setting '{}' = '{}'", key, value);
+                            let change = SettingChange { key, value };
+                            dbworker
+                                .setting_subscribers
+                                .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+                        }
+                        Ok(false) => trace!("DB Worker setting '{}' unchanged", key),
+                        Err(e) => error!("DB Worker failed to store setting '{}': {:#}", key, e),
+                    }
+                },
+
+                DBMessage::GetSetting { key, reply } => {
+                    let value = dbworker.dbconn.get_setting(&key).unwrap_or_else(|e| {
+                        error!("DB Worker failed to read setting '{}': {:#}", key, e);
+                        None
+                    });
+                    let _ = reply.send(value);
+                },
+
+                DBMessage::SubscribeSettings { subscriber } => {
+                    dbworker.setting_subscribers.push(subscriber);
                 }
             }
 
         }
         trace!("DB Worker channel closed. Exiting DB worker thread.");
-    });
-
-    thread
+    })
 }
@@ -1,2 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod db;
-pub mod db_worker;
\ No newline at end of file
+pub mod db_facade;
+pub mod db_worker;
@@ -0,0 +1,291 @@
+//! Object detection on frame-tap frames (YOLOv8-style ONNX models).
+//!
+//! The model runner needs the `detection` feature (tract); output decoding and
+//! the motion gate are always built so the rest of the tree doesn't care.
+
+use anyhow::Result;
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::config::DetectionConfig;
+use crate::events::CameraEvent;
+use crate::pipeline_sinks::frame_tap_sink::Frame;
+use crate::roi::Roi;
+
+/// COCO class names, in the order stock YOLO exports emit them.
+pub const COCO_LABELS: [&str; 80] = [
+    "person", "bicycle", "car", "motorcycle", "airplane", "bus", "train", "truck", "boat",
+    "traffic light", "fire hydrant", "stop sign", "parking meter", "bench", "bird", "cat", "dog",
+    "horse", "sheep", "cow", "elephant", "bear", "zebra", "giraffe", "backpack", "umbrella",
+    "handbag", "tie", "suitcase", "frisbee", "skis", "snowboard", "sports ball", "kite",
+    "baseball bat", "baseball glove", "skateboard", "surfboard", "tennis racket", "bottle",
+    "wine glass", "cup", "fork", "knife", "spoon", "bowl", "banana", "apple", "sandwich", "orange",
+    "broccoli", "carrot", "hot dog", "pizza", "donut", "cake", "chair", "couch", "potted plant",
+    "bed", "dining table", "toilet", "tv", "laptop", "mouse", "remote", "keyboard", "cell phone",
+    "microwave", "oven", "toaster", "sink", "refrigerator", "book", "clock", "vase", "scissors",
+    "teddy bear", "hair drier", "toothbrush",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    pub label: String,
+    pub score: f32,
+    /// Normalized (0..1) box: x0, y0, x1, y1
+    pub bbox: [f32; 4],
+}
+
+/// Decode a YOLOv8 output tensor laid out as `[4 + classes, anchors]`
+/// (cx, cy, w, h in input pixels, then one score per class).
+/// Keeps detections of `wanted` labels scoring at least `min_score`.
+pub fn decode_yolo(
+    output: &[f32],
+    labels: &[String],
+    input_size: u32,
+    wanted: &[String],
+    min_score: f32,
+) -> Vec<Detection> {
+    let rows = 4 + labels.len();
+    if labels.is_empty() || !output.len().is_multiple_of(rows) {
+        return Vec::new();
+    }
+    let anchors = output.len() / rows;
+    let at = |row: usize, anchor: usize| output[row * anchors + anchor];
+    let size = input_size as f32;
+
+    let mut detections = Vec::new();
+    for anchor in 0..anchors {
+        let (class, score) = (0..labels.len())
+            .map(|c| (c, at(4 + c, anchor)))
+            .fold((0, f32::MIN), |best, cur| if cur.1 > best.1 { cur } else { best });
+
+        if score < min_score || !wanted.contains(&labels[class]) {
+            continue;
+        }
+
+        let (cx, cy, w, h) = (at(0, anchor), at(1, anchor), at(2, anchor), at(3, anchor));
+        detections.push(Detection {
+            label: labels[class].clone(),
+            score,
+            bbox: [
+                ((cx - w / 2.0) / size).clamp(0.0, 1.0),
+                ((cy - h / 2.0) / size).clamp(0.0, 1.0),
+                ((cx + w / 2.0) / size).clamp(0.0, 1.0),
+                ((cy + h / 2.0) / size).clamp(0.0, 1.0),
+            ],
+        });
+    }
+    detections
+}
+
+/// Best detection per label, so one frame with five people is one "person" event.
+pub fn best_per_label(detections: Vec<Detection>) -> Vec<Detection> {
+    let mut best: Vec<Detection> = Vec::new();
+    for detection in detections {
+        match best.iter_mut().find(|d| d.label == detection.label) {
+            Some(existing) if existing.score >= detection.score => {}
+            Some(existing) => *existing = detection,
+            None => best.push(detection),
+        }
+    }
+    best
+}
+
+/// Drop detections whose bottom-center (where a person/car meets the ground)
+/// is outside the camera's ROI.
+pub fn filter_by_roi(detections: Vec<Detection>, roi: &Roi) -> Vec<Detection> {
+    detections
+        .into_iter()
+        .filter(|d| roi.contains((d.bbox[0] + d.bbox[2]) / 2.0, d.bbox[3]))
+        .collect()
+}
+
+/// Lets motion recording require a recent confirmed detection, so tree shadows
+/// and headlight sweeps don't start recordings on their own.
+#[derive(Debug, Default)]
+pub struct DetectionGate {
+    last_confirmed: Mutex<Option<Instant>>,
+}
+
+impl DetectionGate {
+    pub fn confirm(&self) {
+        *self.last_confirmed.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// True if the detector confirmed something within `window`.
+    pub fn is_open(&self, window: Duration) -> bool {
+        self.last_confirmed
+            .lock()
+            .unwrap()
+            .is_some_and(|at| at.elapsed() <= window)
+    }
+}
+
+/// Labels to decode with: the configured list, or COCO.
+pub fn labels_for(cfg: &DetectionConfig) -> Vec<String> {
+    match &cfg.labels {
+        Some(labels) => labels.clone(),
+        None => COCO_LABELS.iter().map(|l| l.to_string()).collect(),
+    }
+}
+
+/// A loaded YOLO model taking `input_size`x`input_size` RGB frames.
+#[cfg(feature = "detection")]
+pub struct YoloModel {
+    model: tract_onnx::prelude::TypedRunnableModel<tract_onnx::prelude::TypedModel>,
+    size: usize,
+}
+
+#[cfg(feature = "detection")]
+impl YoloModel {
+    pub fn load(model_path: &str, input_size: u32) -> Result<Self> {
+        use anyhow::Context;
+        use tract_onnx::prelude::*;
+
+        let size = input_size as usize;
+        let model = tract_onnx::onnx()
+            .model_for_path(model_path)
+            .with_context(|| format!("Failed to load ONNX model {}", model_path))?
+            .with_input_fact(0, f32::fact([1, 3, size, size]).into())?
+            .into_optimized()?
+            .into_runnable()?;
+        Ok(Self { model, size })
+    }
+
+    /// Raw output tensor for one packed RGB frame of the model's input size.
+    pub fn run(&self, rgb: &[u8], stride: usize) -> Result<Vec<f32>> {
+        use tract_onnx::prelude::*;
+
+        let size = self.size;
+        let input: Tensor = tract_ndarray::Array4::from_shape_fn((1, 3, size, size), |(_, c, y, x)| {
+            rgb[y * stride + x * 3 + c] as f32 / 255.0
+        })
+        .into();
+        let output = self.model.run(tvec!(input.into()))?;
+        let view = output[0].to_array_view::<f32>()?;
+        Ok(view.iter().copied().collect())
+    }
+}
+
+/// Run the model on every frame from `frames` (the tap caps the rate) and call
+/// `on_event` once per wanted label seen inside `roi`. Exits when the tap goes away.
+#[cfg(feature = "detection")]
+pub fn spawn_detector<F>(
+    camera_key: String,
+    cfg: DetectionConfig,
+    roi: Roi,
+    frames: Receiver<Frame>,
+    on_event: F,
+) -> Result<JoinHandle<()>>
+where
+    F: Fn(CameraEvent) + Send + 'static,
+{
+    use tracing::{info, warn};
+
+    let size = cfg.input_size as usize;
+    let model = YoloModel::load(&cfg.model_path, cfg.input_size)?;
+    let labels = labels_for(&cfg);
+
+    info!(
+        "Object detection on camera '{}' with {} (looking for {:?})",
+        camera_key, cfg.model_path, cfg.detect
+    );
+
+    Ok(std::thread::spawn(move || {
+        while let Ok(frame) = frames.recv() {
+            if frame.width as usize != size || frame.height as usize != size {
+                warn!("Detector got {}x{} frame, expected {}x{}", frame.width, frame.height, size, size);
+                continue;
+            }
+            let values = match model.run(&frame.data, frame.stride) {
+                Ok(values) => values,
+                Err(e) => {
+                    warn!("Detector failed on camera '{}': {:#}", camera_key, e);
+                    continue;
+                }
+            };
+
+            let detections = decode_yolo(&values, &labels, cfg.input_size, &cfg.detect, cfg.min_score);
+            for detection in best_per_label(filter_by_roi(detections, &roi)) {
+                on_event(CameraEvent::object(&detection.label, detection.score));
+            }
+        }
+        info!("Detector for camera '{}' exiting", camera_key);
+    }))
+}
+
+#[cfg(not(feature = "detection"))]
+pub fn spawn_detector<F>(
+    camera_key: String,
+    _cfg: DetectionConfig,
+    _roi: Roi,
+    _frames: Receiver<Frame>,
+    _on_event: F,
+) -> Result<JoinHandle<()>>
+where
+    F: Fn(CameraEvent) + Send + 'static,
+{
+    anyhow::bail!(
+        "Camera '{}' has [cameras.detection] but dashcam_rs was built without the `detection` feature",
+        camera_key
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels() -> Vec<String> {
+        vec!["person".to_string(), "car".to_string(), "dog".to_string()]
+    }
+
+    /// Build a [7, anchors] tensor from per-anchor (cx, cy, w, h, scores...)
+    fn tensor(anchors: &[[f32; 7]]) -> Vec<f32> {
+        (0..7).flat_map(|row| anchors.iter().map(move |a| a[row])).collect()
+    }
+
+    #[test]
+    fn decode_keeps_wanted_labels_over_threshold() {
+        let output = tensor(&[
+            [320.0, 320.0, 64.0, 128.0, 0.9, 0.1, 0.0], // person
+            [100.0, 100.0, 20.0, 20.0, 0.1, 0.3, 0.0],  // car, too weak
+            [50.0, 50.0, 10.0, 10.0, 0.0, 0.0, 0.95],   // dog, not wanted
+        ]);
+        let wanted = vec!["person".to_string(), "car".to_string()];
+
+        let detections = decode_yolo(&output, &labels(), 640, &wanted, 0.5);
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].label, "person");
+        assert_eq!(detections[0].bbox, [0.45, 0.4, 0.55, 0.6]);
+    }
+
+    #[test]
+    fn best_per_label_collapses_duplicates() {
+        let d = |label: &str, score| Detection { label: label.to_string(), score, bbox: [0.0; 4] };
+        let best = best_per_label(vec![d("person", 0.6), d("car", 0.7), d("person", 0.8)]);
+        assert_eq!(best, vec![d("person", 0.8), d("car", 0.7)]);
+    }
+
+    #[test]
+    fn roi_filters_on_bottom_center() {
+        use crate::roi::{RoiConfig, RoiMode};
+        // only the bottom half is watched
+        let roi = Roi::from_config(&[RoiConfig {
+            name: "driveway".to_string(),
+            mode: RoiMode::Include,
+            points: vec![[0.0, 0.5], [1.0, 0.5], [1.0, 1.0], [0.0, 1.0]],
+        }]);
+        let d = |bbox| Detection { label: "person".to_string(), score: 0.9, bbox };
+        let kept = filter_by_roi(vec![d([0.4, 0.1, 0.5, 0.4]), d([0.4, 0.3, 0.5, 0.7])], &roi);
+        assert_eq!(kept, vec![d([0.4, 0.3, 0.5, 0.7])]);
+    }
+
+    #[test]
+    fn gate_closes_after_window() {
+        let gate = DetectionGate::default();
+        assert!(!gate.is_open(Duration::from_secs(10)));
+        gate.confirm();
+        assert!(gate.is_open(Duration::from_secs(10)));
+    }
+}
@@ -0,0 +1,239 @@
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// What raised a camera event.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    /// Accelerometer spike (hard braking, impact, parked bump)
+    GSensor,
+    Motion,
+    /// User asked for it (button, control API)
+    Manual,
+    /// Object detector saw one of the configured labels
+    Object,
+    /// Recent segments stopped decoding or came out short (see `validation`)
+    Corrupt,
+    /// Camera switched to its backup source; the label names it
+    Failover,
+    /// Privacy switch flipped; the label is "on" or "off"
+    Privacy,
+    /// GPS speed over `[speed_events] over_kmh`
+    Speeding,
+    /// GPS speed dropped faster than `[speed_events] hard_brake_mps2`
+    #[serde(rename = "hard_brake")]
+    HardBrake,
+    /// Car in reverse gear; the label is "on"/"off" when the source reports both
+    Reverse,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::GSensor => "gsensor",
+            EventKind::Motion => "motion",
+            EventKind::Manual => "manual",
+            EventKind::Object => "object",
+            EventKind::Corrupt => "corrupt",
+            EventKind::Failover => "failover",
+            EventKind::Privacy => "privacy",
+            EventKind::Speeding => "speeding",
+            EventKind::HardBrake => "hard_brake",
+            EventKind::Reverse => "reverse",
+        }
+    }
+}
+
+impl std::fmt::Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Delivered to every sink of the camera via `PipelineSink::on_event`.
+#[derive(Debug, Clone)]
+pub struct CameraEvent {
+    pub kind: EventKind,
+    pub at: DateTime<Local>,
+    /// What was detected, for `Object` events
+    pub label: Option<String>,
+    pub score: Option<f32>,
+}
+
+impl CameraEvent {
+    pub fn now(kind: EventKind) -> Self {
+        Self {
+            kind,
+            at: Local::now(),
+            label: None,
+            score: None,
+        }
+    }
+
+    pub fn object(label: &str, score: f32) -> Self {
+        Self {
+            label: Some(label.to_string()),
+            score: Some(score),
+            ..Self::now(EventKind::Object)
+        }
+    }
+}
+
+/// Debounce settings for one event kind (`[events.<kind>]` in config.toml).
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventPolicy {
+    /// Minimum time between two recorded events of this kind (per camera and label)
+    pub cooldown_sec: u64,
+    /// Hits needed in a row before the first event fires (e.g. detector frames)
+    pub consecutive: u32,
+    /// A longer pause between hits than this starts the count over
+    pub max_gap_sec: u64,
+}
+
+impl Default for EventPolicy {
+    /// Every hit is an event.
+    fn default() -> Self {
+        Self {
+            cooldown_sec: 0,
+            consecutive: 1,
+            max_gap_sec: 5,
+        }
+    }
+}
+
+impl EventPolicy {
+    /// Built-in policy when config.toml doesn't set one for `kind`.
+    pub fn default_for(kind: EventKind) -> Self {
+        match kind {
+            // Someone walking past is seen in many frames; one row per pass is enough
+            EventKind::Object => Self { cooldown_sec: 30, consecutive: 2, ..Self::default() },
+            EventKind::Motion => Self { cooldown_sec: 10, ..Self::default() },
+            // One bump rattles the sensor for a moment
+            EventKind::GSensor => Self { cooldown_sec: 5, ..Self::default() },
+            EventKind::Manual => Self::default(),
+            // The validator only reports the change to corrupt; this guards against flapping
+            EventKind::Corrupt => Self { cooldown_sec: 3600, ..Self::default() },
+            // Happens once per run at most
+            EventKind::Failover => Self::default(),
+            // Already debounced at the switch
+            EventKind::Privacy => Self::default(),
+            // A fix every second while over the limit; a few in a row rule out GPS jumps
+            EventKind::Speeding => Self { cooldown_sec: 60, consecutive: 3, ..Self::default() },
+            EventKind::HardBrake => Self { cooldown_sec: 5, ..Self::default() },
+            // Every pulse keeps the live view switched
+            EventKind::Reverse => Self::default(),
+        }
+    }
+}
+
+/// `[events]` table: policies per kind, unset kinds use `EventPolicy::default_for`.
+pub type EventsConfig = HashMap<EventKind, EventPolicy>;
+
+#[derive(Debug, Default)]
+struct Streak {
+    hits: u32,
+    last_hit: Option<Instant>,
+    last_emitted: Option<Instant>,
+}
+
+/// Applies cooldown + hysteresis to raw events before they're recorded/published.
+/// Keyed by camera, kind and label, so a car doesn't suppress a person.
+#[derive(Debug, Default)]
+pub struct EventFilter {
+    policies: EventsConfig,
+    streaks: HashMap<(String, EventKind, Option<String>), Streak>,
+}
+
+impl EventFilter {
+    pub fn new(policies: EventsConfig) -> Self {
+        Self {
+            policies,
+            streaks: HashMap::new(),
+        }
+    }
+
+    /// New `[events]` policies (config reload); running streaks are kept.
+    pub fn set_policies(&mut self, policies: EventsConfig) {
+        self.policies = policies;
+    }
+
+    pub fn policy(&self, kind: EventKind) -> EventPolicy {
+        self.policies
+            .get(&kind)
+            .copied()
+            .unwrap_or_else(|| EventPolicy::default_for(kind))
+    }
+
+    /// Whether `event` should become a real event.
+    pub fn admit(&mut self, camera_key: &str, event: &CameraEvent) -> bool {
+        self.admit_at(camera_key, event, Instant::now())
+    }
+
+    pub fn admit_at(&mut self, camera_key: &str, event: &CameraEvent, now: Instant) -> bool {
+        let policy = self.policy(event.kind);
+        let streak = self
+            .streaks
+            .entry((camera_key.to_string(), event.kind, event.label.clone()))
+            .or_default();
+
+        let continues = streak
+            .last_hit
+            .is_some_and(|at| now.duration_since(at) <= Duration::from_secs(policy.max_gap_sec));
+        streak.hits = if continues { streak.hits + 1 } else { 1 };
+        streak.last_hit = Some(now);
+
+        if streak.hits < policy.consecutive.max(1) {
+            return false;
+        }
+        let cooled_down = streak
+            .last_emitted
+            .is_none_or(|at| now.duration_since(at) >= Duration::from_secs(policy.cooldown_sec));
+        if cooled_down {
+            streak.last_emitted = Some(now);
+        }
+        cooled_down
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_needs_consecutive_hits_then_cools_down() {
+        let mut filter = EventFilter::new(EventsConfig::new());
+        let t0 = Instant::now();
+        let person = CameraEvent::object("person", 0.9);
+        let at = |s| t0 + Duration::from_secs(s);
+
+        assert!(!filter.admit_at("cam", &person, at(0)), "one frame isn't enough");
+        assert!(filter.admit_at("cam", &person, at(1)));
+        // still walking past: swallowed by the 30s cooldown
+        for s in 2..20 {
+            assert!(!filter.admit_at("cam", &person, at(s)));
+        }
+        // a car is tracked separately
+        let car = CameraEvent::object("car", 0.9);
+        assert!(!filter.admit_at("cam", &car, at(20)));
+        assert!(filter.admit_at("cam", &car, at(21)));
+        // after a long gap the streak starts over
+        assert!(!filter.admit_at("cam", &person, at(60)));
+        assert!(filter.admit_at("cam", &person, at(61)));
+    }
+
+    #[test]
+    fn configured_policy_overrides_default() {
+        let mut policies = EventsConfig::new();
+        policies.insert(EventKind::Manual, EventPolicy { cooldown_sec: 10, ..EventPolicy::default() });
+        let mut filter = EventFilter::new(policies);
+        let t0 = Instant::now();
+        let manual = CameraEvent::now(EventKind::Manual);
+
+        assert!(filter.admit_at("cam", &manual, t0));
+        assert!(!filter.admit_at("cam", &manual, t0 + Duration::from_secs(5)));
+        assert!(filter.admit_at("cam", &manual, t0 + Duration::from_secs(10)));
+    }
+}
@@ -0,0 +1,654 @@
+//! Export recorded segments as one MP4, without decoding.
+//!
+//! `splitmuxsrc` plays the segments back as one continuous stream (it offsets
+//! each part's timestamps), `parsebin` only parses, and `mp4mux` writes a
+//! fragmented, streamable MP4. Gluing `.ts` files together byte by byte instead
+//! leaves timestamp jumps and broken continuity counters at every boundary,
+//! which players show as glitches or refuse to seek across.
+//!
+//! Events inside the exported range can be added as chapters: embedded for
+//! `.mkv` output, as a WebVTT chapters file next to `.mp4` output (mp4mux
+//! can't write chapters).
+//!
+//! `[export]` can watermark exports with the device serial, camera and export
+//! time for chain of custody: in the file's tags, and/or burnt into the
+//! picture, which means decoding and re-encoding instead of a plain remux.
+//!
+//! A hyperlapse export (`speed`) also re-encodes: `videorate` speeds the
+//! timestamps up and drops the frames that don't fit the output frame rate.
+//! Chapters move with the picture, so each one still lands on its event.
+//!
+//! Whether recorded sound may be handed over differs by jurisdiction, so audio
+//! can be muted (a silent track of the same length) or stripped on export.
+//! Faces and plates can be blurred too (see `anonymize`), which re-encodes.
+//!
+//! A time range of a camera can be exported by looking its segments up in the
+//! `segments` catalog (`range_segments`) instead of naming the files.
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use serde::Deserialize;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::anonymize::{self, AnonymizeConfig, BlurPass};
+use crate::db::db::{EventRow, SegmentRow, SegmentStream, busiest_sink, stream_runs};
+use crate::pipeline_sinks::mkv_metadata::{ChapterMarker, chapter_toc};
+
+/// Export throughput at or above this share of a plain file copy counts as IO-bound
+pub const IO_BOUND_RATIO: f64 = 0.75;
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(3600);
+/// mp4mux fragment length; also how much a cut-off export loses at most
+const FRAGMENT_MS: u32 = 1000;
+/// Frame rate of hyperlapse exports, whatever the source ran at
+pub const HYPERLAPSE_FPS: u32 = 30;
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportConfig {
+    /// Stamped on watermarked exports; the hostname when unset
+    pub device_serial: Option<String>,
+    /// Burn the watermark into the picture (re-encodes with x264enc)
+    pub overlay: bool,
+    /// Write the watermark into the file's title/comment/date tags
+    pub metadata: bool,
+    /// What happens to audio tracks unless `export --audio` says otherwise
+    pub audio: ExportAudio,
+    /// `[export.anonymize]`: model for `export --blur`
+    pub anonymize: Option<AnonymizeConfig>,
+}
+
+/// Audio handling on export; the video is never touched by it.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportAudio {
+    #[default]
+    Keep,
+    /// Silence, re-encoded with opusenc, so players still show the track
+    Mute,
+    /// No audio track in the export at all
+    Strip,
+}
+
+impl ExportAudio {
+    /// From an `export --audio` argument.
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg {
+            "keep" => Some(ExportAudio::Keep),
+            "mute" => Some(ExportAudio::Mute),
+            "strip" => Some(ExportAudio::Strip),
+            _ => None,
+        }
+    }
+}
+
+impl ExportConfig {
+    pub fn watermarks(&self) -> bool {
+        self.overlay || self.metadata
+    }
+
+    pub fn device_serial(&self) -> String {
+        self.device_serial.clone().unwrap_or_else(|| {
+            fs::read_to_string("/etc/hostname")
+                .map(|h| h.trim().to_string())
+                .ok()
+                .filter(|h| !h.is_empty())
+                .unwrap_or_else(|| "unknown".to_string())
+        })
+    }
+
+    /// Watermark for an export of `camera` made now, `None` when watermarking is off.
+    pub fn watermark(&self, camera: &str) -> Option<Watermark> {
+        self.watermarks().then(|| Watermark {
+            device_serial: self.device_serial(),
+            camera: camera.to_string(),
+            exported_at: Local::now(),
+            overlay: self.overlay,
+            metadata: self.metadata,
+            clock_accuracy_ms: None,
+        })
+    }
+}
+
+/// Who exported what, and when.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    pub device_serial: String,
+    pub camera: String,
+    pub exported_at: DateTime<Local>,
+    pub overlay: bool,
+    pub metadata: bool,
+    /// Largest clock correction during the footage (`clock_corrections` in the DB)
+    pub clock_accuracy_ms: Option<i64>,
+}
+
+impl Watermark {
+    pub fn text(&self) -> String {
+        let mut text = format!(
+            "{} / {} / exported {}",
+            self.device_serial,
+            self.camera,
+            self.exported_at.format("%Y-%m-%d %H:%M:%S %:z")
+        );
+        if let Some(ms) = self.clock_accuracy_ms {
+            text.push_str(&format!(" / {}", clock_accuracy_text(ms)));
+        }
+        text
+    }
+
+    fn tags(&self) -> gst::TagList {
+        let mut tags = gst::TagList::new();
+        {
+            let tags = tags.get_mut().unwrap();
+            tags.add::<gst::tags::Title>(&self.camera.as_str(), gst::TagMergeMode::Replace);
+            tags.add::<gst::tags::Comment>(&self.text().as_str(), gst::TagMergeMode::Replace);
+            tags.add::<gst::tags::DeviceModel>(&self.device_serial.as_str(), gst::TagMergeMode::Replace);
+            if let Ok(at) = gst::DateTime::from_unix_epoch_utc(self.exported_at.timestamp()) {
+                tags.add::<gst::tags::DateTime>(&at, gst::TagMergeMode::Replace);
+            }
+        }
+        tags
+    }
+}
+
+/// "timestamps accurate to ±2.5s" for a clock correction of `ms` either way.
+pub fn clock_accuracy_text(ms: i64) -> String {
+    format!("timestamps accurate to ±{:.1}s", ms.unsigned_abs() as f64 / 1000.0)
+}
+
+/// Extras for `export_with`.
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions<'a> {
+    pub chapters: &'a [ChapterMarker],
+    /// Length of the export, where the last chapter ends
+    pub total: Duration,
+    pub watermark: Option<&'a Watermark>,
+    /// Hyperlapse: play back this many times faster than real time
+    pub speed: Option<u32>,
+    pub audio: ExportAudio,
+    pub blur: Option<&'a BlurPass>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throughput {
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    pub fn mb_per_sec(&self) -> f64 {
+        self.bytes as f64 / (1024.0 * 1024.0) / self.elapsed.as_secs_f64().max(1e-6)
+    }
+}
+
+fn is_mkv(output: &Path) -> bool {
+    output.extension().is_some_and(|e| e == "mkv")
+}
+
+/// `gst-launch` line for an export; Matroska for `.mkv` output, MP4 otherwise.
+/// The part list is handed to `splitmuxsrc` through its format-location signal,
+/// so no glob pattern is needed. `overlay` text is burnt in and `speed` makes a
+/// hyperlapse; either re-encodes, as does `blur`, which leaves an `identity`
+/// named `blur` on RGB frames for `anonymize::attach`. Video and audio are
+/// linked from splitmuxsrc's `video_0` and `audio_0` pads by name; a hyperlapse
+/// has no audio, as it would run at the real-time speed.
+pub fn export_description(
+    output: &Path,
+    overlay: Option<&str>,
+    speed: Option<u32>,
+    audio: ExportAudio,
+    blur: bool,
+) -> String {
+    let mut filters = Vec::new();
+    if let Some(speed) = speed {
+        filters.push(format!("videorate rate={} ! video/x-raw,framerate={}/1", speed, HYPERLAPSE_FPS));
+    }
+    // before the overlay, so the watermark isn't mistaken for a plate
+    if blur {
+        filters.push("video/x-raw,format=RGB ! identity name=blur ! videoconvert".to_string());
+    }
+    if let Some(text) = overlay {
+        filters.push(format!(
+            "textoverlay text=\"{}\" valignment=bottom halignment=left shaded-background=true font-desc=\"Sans 12\"",
+            text.replace(['"', '\\'], "")
+        ));
+    }
+    let video = if filters.is_empty() {
+        "parsebin".to_string()
+    } else {
+        format!(
+            "parsebin ! decodebin ! videoconvert ! {} ! videoconvert ! x264enc speed-preset=veryfast ! h264parse",
+            filters.join(" ! ")
+        )
+    };
+    let muxer = if is_mkv(output) {
+        "matroskamux name=mux".to_string()
+    } else {
+        format!("mp4mux name=mux fragment-duration={} streamable=true", FRAGMENT_MS)
+    };
+    let audio = match audio {
+        _ if speed.is_some() => " src.audio_0 ! queue ! fakesink sync=false",
+        ExportAudio::Keep => " src.audio_0 ! queue ! parsebin ! mux.",
+        ExportAudio::Mute => {
+            " src.audio_0 ! queue ! decodebin ! audioconvert ! volume mute=true ! audioconvert ! opusenc ! opusparse ! mux."
+        }
+        // splitmuxsrc stops on a not-linked pad, so the track goes nowhere instead
+        ExportAudio::Strip => " src.audio_0 ! queue ! fakesink sync=false",
+    };
+    format!(
+        "splitmuxsrc name=src src.video_0 ! {} ! {} ! filesink location=\"{}\"{}",
+        video,
+        muxer,
+        output.display(),
+        audio
+    )
+}
+
+/// When a segment started recording: from the name for `<date>/<HH-MM-SS>.<ext>`
+/// files, else its mtime (when it was closed) minus the segment length.
+pub fn segment_start(path: &Path, segment_duration: Duration) -> Option<DateTime<Local>> {
+    let date = path.parent()?.file_name()?.to_str()?;
+    let time = path.file_stem()?.to_str()?;
+    if let Ok(naive) = NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H-%M-%S")
+        && let Some(start) = Local.from_local_datetime(&naive).earliest()
+    {
+        return Some(start);
+    }
+    let modified = DateTime::<Local>::from(fs::metadata(path).ok()?.modified().ok()?);
+    Some(modified - chrono::Duration::from_std(segment_duration).ok()?)
+}
+
+/// Chapters for the events that fall in `[start_utc, end_utc)` of an export.
+/// Manual events are the user's bookmarks; `corrupt` isn't something to jump to.
+pub fn event_chapters(events: &[EventRow], start_utc: i64, end_utc: i64) -> Vec<ChapterMarker> {
+    events
+        .iter()
+        .filter(|e| (start_utc..end_utc).contains(&e.at_utc) && e.kind != "corrupt")
+        .map(|e| ChapterMarker {
+            offset: Duration::from_secs((e.at_utc - start_utc) as u64),
+            title: match (e.kind.as_str(), &e.label) {
+                ("manual", _) => "bookmark".to_string(),
+                (kind, Some(label)) => format!("{}: {}", kind, label),
+                (kind, None) => kind.to_string(),
+            },
+        })
+        .collect()
+}
+
+/// Chapters of a real-time export moved to where their events play in a
+/// `speed`x hyperlapse, titled with the real-time offset they came from.
+pub fn hyperlapse_chapters(chapters: &[ChapterMarker], speed: u32) -> Vec<ChapterMarker> {
+    chapters
+        .iter()
+        .map(|c| {
+            let secs = c.offset.as_secs();
+            ChapterMarker {
+                offset: c.offset / speed.max(1),
+                title: format!("{} ({:02}:{:02}:{:02})", c.title, secs / 3600, secs / 60 % 60, secs % 60),
+            }
+        })
+        .collect()
+}
+
+/// WebVTT chapter track; each chapter lasts until the next, the last until `total`.
+pub fn chapters_vtt(chapters: &[ChapterMarker], total: Duration) -> String {
+    let stamp = |d: Duration| {
+        let ms = d.as_millis();
+        format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+    };
+    let mut vtt = String::from("WEBVTT\n");
+    for (i, chapter) in chapters.iter().enumerate() {
+        let end = chapters.get(i + 1).map_or(total, |next| next.offset).max(chapter.offset);
+        vtt.push_str(&format!(
+            "\n{}\n{} --> {}\n{}\n",
+            i + 1,
+            stamp(chapter.offset),
+            stamp(end),
+            chapter.title
+        ));
+    }
+    vtt
+}
+
+/// `files` split where their catalogued stream (codec, size, frame rate)
+/// changes, one stream per run, since a single MP4/MKV can't switch midway.
+/// `streams[i]` belongs to `files[i]`; unknown streams stay in the run before them.
+pub fn split_by_stream<'a>(files: &'a [PathBuf], streams: &[SegmentStream]) -> Vec<&'a [PathBuf]> {
+    let mut start = 0;
+    stream_runs(streams, |s| s)
+        .into_iter()
+        .map(|run| {
+            let files = &files[start..start + run.len()];
+            start += run.len();
+            files
+        })
+        .collect()
+}
+
+/// Files of the catalogued `rows` (from `segments_between`), in playback
+/// order, with their streams, from one sink (see `busiest_sink`). Files gone
+/// since (the ring wrapped) are left out.
+pub fn range_segments(rows: &[SegmentRow], recording_root: &Path) -> (Vec<PathBuf>, Vec<SegmentStream>) {
+    busiest_sink(rows)
+        .into_iter()
+        .map(|r| (recording_root.join(&r.rel_path), r.stream.clone()))
+        .filter(|(path, _)| path.exists())
+        .unzip()
+}
+
+/// Output for run `n` (from 0) of a split export: `trip.mp4`, `trip_2.mp4`, ...
+pub fn run_output(output: &Path, n: usize) -> PathBuf {
+    if n == 0 {
+        return output.to_path_buf();
+    }
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, n + 1, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, n + 1),
+    };
+    output.with_file_name(name)
+}
+
+/// Remux `files` (in playback order) into `output`. Returns bytes read and time taken.
+pub fn export_segments(files: &[PathBuf], output: &Path) -> Result<Throughput> {
+    export_with(files, output, &ExportOptions::default())
+}
+
+/// Like `export_segments`, with chapters embedded (`.mkv`) or written to
+/// `<output>.chapters.vtt` (`.mp4`), and the watermark applied.
+pub fn export_with(files: &[PathBuf], output: &Path, options: &ExportOptions) -> Result<Throughput> {
+    if files.is_empty() {
+        bail!("No segments to export");
+    }
+    if options.speed == Some(0) {
+        bail!("Hyperlapse speed must be at least 1");
+    }
+    let (chapters, total) = match options.speed {
+        Some(speed) => (hyperlapse_chapters(options.chapters, speed), options.total / speed),
+        None => (options.chapters.to_vec(), options.total),
+    };
+    gst::init()?;
+    let bytes = files.iter().map(|f| fs::metadata(f).map(|m| m.len()).unwrap_or(0)).sum();
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let pipeline = gst::parse::launch(&export_description(
+        output,
+        options.watermark.filter(|w| w.overlay).map(|w| w.text()).as_deref(),
+        options.speed,
+        options.audio,
+        options.blur.is_some(),
+    ))?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Not a pipeline"))?;
+    let parts: Vec<String> = files.iter().map(|f| f.to_string_lossy().to_string()).collect();
+    pipeline
+        .by_name("src")
+        .context("No splitmuxsrc")?
+        .connect("format-location", false, move |_| Some(parts.to_value()));
+    if let Some(pass) = options.blur {
+        anonymize::attach(&pipeline.by_name("blur").context("No blur element")?, pass)?;
+    }
+    let muxer = pipeline.by_name("mux").context("No muxer")?;
+    if !chapters.is_empty() && is_mkv(output) {
+        muxer
+            .dynamic_cast_ref::<gst::TocSetter>()
+            .context("Muxer can't take chapters")?
+            .set_toc(Some(&chapter_toc(&chapters)));
+    }
+    if let Some(watermark) = options.watermark.filter(|w| w.metadata) {
+        muxer
+            .dynamic_cast_ref::<gst::TagSetter>()
+            .context("Muxer can't take tags")?
+            .merge_tags(&watermark.tags(), gst::TagMergeMode::Replace);
+    }
+    let bus = pipeline.bus().context("No bus")?;
+
+    let started = Instant::now();
+    pipeline.set_state(gst::State::Playing)?;
+    let msg = bus.timed_pop_filtered(
+        gst::ClockTime::from_seconds(EXPORT_TIMEOUT.as_secs()),
+        &[gst::MessageType::Eos, gst::MessageType::Error],
+    );
+    pipeline.set_state(gst::State::Null)?;
+    match msg.as_ref().map(|m| m.view()) {
+        Some(gst::MessageView::Eos(..)) => {}
+        Some(gst::MessageView::Error(err)) => bail!("Export to {} failed: {}", output.display(), err.error()),
+        _ => bail!("Export to {} timed out", output.display()),
+    }
+    if !chapters.is_empty() && !is_mkv(output) {
+        let vtt_path = PathBuf::from(format!("{}.chapters.vtt", output.display()));
+        fs::write(&vtt_path, chapters_vtt(&chapters, total))
+            .with_context(|| format!("Failed to write {}", vtt_path.display()))?;
+    }
+    let throughput = Throughput { bytes, elapsed: started.elapsed() };
+    info!(
+        "Exported {} segments to {} ({:.1} MB/s)",
+        files.len(),
+        output.display(),
+        throughput.mb_per_sec()
+    );
+    Ok(throughput)
+}
+
+/// Export speed next to a plain copy of the same bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportBenchmark {
+    pub copy: Throughput,
+    pub export: Throughput,
+}
+
+impl ExportBenchmark {
+    pub fn ratio(&self) -> f64 {
+        self.export.mb_per_sec() / self.copy.mb_per_sec().max(1e-6)
+    }
+
+    /// Whether the export keeps up with the disk, i.e. remuxing isn't the bottleneck.
+    pub fn io_bound(&self) -> bool {
+        self.ratio() >= IO_BOUND_RATIO
+    }
+}
+
+impl fmt::Display for ExportBenchmark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "copy:   {:8.1} MB/s", self.copy.mb_per_sec())?;
+        writeln!(f, "export: {:8.1} MB/s", self.export.mb_per_sec())?;
+        write!(
+            f,
+            "{} export runs at {:.0}% of copy speed",
+            if self.io_bound() { "IO-BOUND" } else { "CPU-BOUND" },
+            self.ratio() * 100.0
+        )
+    }
+}
+
+/// Time a byte-for-byte copy of `files` and an export of them into `scratch_dir`.
+/// Everything is read once beforehand so both runs see the same page cache.
+pub fn benchmark_export(files: &[PathBuf], scratch_dir: &Path) -> Result<ExportBenchmark> {
+    fs::create_dir_all(scratch_dir)?;
+    for file in files {
+        io::copy(&mut File::open(file)?, &mut io::sink())?;
+    }
+
+    let copy_path = scratch_dir.join("bench_copy.ts");
+    let started = Instant::now();
+    let mut out = File::create(&copy_path)?;
+    let mut bytes = 0;
+    for file in files {
+        bytes += io::copy(&mut File::open(file)?, &mut out)?;
+    }
+    out.sync_all()?;
+    let copy = Throughput { bytes, elapsed: started.elapsed() };
+
+    let export_path = scratch_dir.join("bench_export.mp4");
+    let started = Instant::now();
+    let exported = export_segments(files, &export_path)?;
+    File::open(&export_path)?.sync_all()?;
+    let export = Throughput { bytes: exported.bytes, elapsed: started.elapsed() };
+
+    let _ = fs::remove_file(copy_path);
+    let _ = fs::remove_file(export_path);
+    Ok(ExportBenchmark { copy, export })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_split_where_the_stream_changes() {
+        let files: Vec<PathBuf> = (0..5).map(|i| PathBuf::from(format!("{}.ts", i))).collect();
+        let stream = |width, fps| SegmentStream {
+            codec: Some("H264".to_string()),
+            width: Some(width),
+            height: Some(1080),
+            fps: Some(fps),
+        };
+        let driving = stream(1920, 30.0);
+        let parked = stream(1920, 5.0);
+        let streams = [driving.clone(), driving.clone(), SegmentStream::default(), parked, driving];
+
+        let runs = split_by_stream(&files, &streams);
+        assert_eq!(runs, vec![&files[0..3], &files[3..4], &files[4..5]]);
+        assert_eq!(split_by_stream(&files[..2], &streams[..2]).len(), 1);
+
+        assert_eq!(run_output(Path::new("/tmp/trip.mp4"), 0), PathBuf::from("/tmp/trip.mp4"));
+        assert_eq!(run_output(Path::new("/tmp/trip.mp4"), 2), PathBuf::from("/tmp/trip_3.mp4"));
+    }
+
+    #[test]
+    fn benchmark_compares_export_with_copy_speed() {
+        let mb = |mb: u64, ms: u64| Throughput { bytes: mb * 1024 * 1024, elapsed: Duration::from_millis(ms) };
+        let bench = ExportBenchmark { copy: mb(100, 1000), export: mb(100, 1100) };
+        assert!((bench.copy.mb_per_sec() - 100.0).abs() < 1e-9);
+        assert!(bench.io_bound());
+        assert!(!ExportBenchmark { export: mb(100, 4000), ..bench }.io_bound());
+        assert!(export_description(Path::new("/x/out.mp4"), None, None, ExportAudio::Keep, false).contains("splitmuxsrc name=src src.video_0 ! parsebin ! mp4mux"));
+        assert!(export_description(Path::new("/x/out.mkv"), None, None, ExportAudio::Keep, false).contains("parsebin ! matroskamux"));
+    }
+
+    #[test]
+    fn watermark_names_device_camera_and_time() {
+        let cfg = ExportConfig { device_serial: Some("DC-0042".to_string()), overlay: true, metadata: false, ..Default::default() };
+        assert!(ExportConfig::default().watermark("front").is_none());
+        let mut watermark = cfg.watermark("front").unwrap();
+        watermark.exported_at = Local.with_ymd_and_hms(2026, 3, 1, 14, 5, 30).unwrap();
+        let text = watermark.text();
+        assert!(text.starts_with("DC-0042 / front / exported 2026-03-01 14:05:30"), "{}", text);
+
+        let line = export_description(Path::new("/x/out.mp4"), Some(&text), None, ExportAudio::Keep, false);
+        assert!(line.contains("parsebin ! decodebin ! videoconvert ! textoverlay text=\"DC-0042 / front"));
+        assert!(line.contains("x264enc speed-preset=veryfast ! h264parse ! mp4mux"));
+    }
+
+    #[test]
+    fn events_in_range_become_chapters() {
+        let event = |kind: &str, label: Option<&str>, at_utc| EventRow {
+            id: 0,
+            kind: kind.to_string(),
+            label: label.map(str::to_string),
+            score: None,
+            at_utc,
+        };
+        let events = vec![
+            event("motion", None, 990),
+            event("gsensor", None, 1010),
+            event("manual", None, 1065),
+            event("corrupt", None, 1070),
+            event("object", Some("person"), 1090),
+            event("motion", None, 1120),
+        ];
+        let chapters = event_chapters(&events, 1000, 1120);
+        let titles: Vec<&str> = chapters.iter().map(|c| c.title.as_str()).collect();
+        assert_eq!(titles, vec!["gsensor", "bookmark", "object: person"]);
+        assert_eq!(chapters[1].offset, Duration::from_secs(65));
+
+        let vtt = chapters_vtt(&chapters, Duration::from_secs(120));
+        assert!(vtt.starts_with("WEBVTT\n"));
+        assert!(vtt.contains("\n2\n00:01:05.000 --> 00:01:30.000\nbookmark\n"));
+        assert!(vtt.contains("00:01:30.000 --> 00:02:00.000\nobject: person"));
+    }
+
+    #[test]
+    fn audio_is_muted_or_stripped_apart_from_the_video() {
+        // an unlinked audio_0 would stop splitmuxsrc, so even kept audio gets a branch
+        let keep = export_description(Path::new("/x/out.mp4"), None, None, ExportAudio::Keep, false);
+        assert!(keep.ends_with("src.audio_0 ! queue ! parsebin ! mux."), "{}", keep);
+        let muted = export_description(Path::new("/x/out.mkv"), None, None, ExportAudio::Mute, false);
+        assert!(muted.starts_with("splitmuxsrc name=src src.video_0 ! parsebin ! matroskamux"), "{}", muted);
+        assert!(muted.ends_with("src.audio_0 ! queue ! decodebin ! audioconvert ! volume mute=true ! audioconvert ! opusenc ! opusparse ! mux."));
+        let stripped = export_description(Path::new("/x/out.mp4"), None, None, ExportAudio::Strip, false);
+        assert!(stripped.ends_with("src.audio_0 ! queue ! fakesink sync=false"));
+        assert!(!stripped.contains("mux."));
+        assert_eq!(ExportAudio::from_arg("strip"), Some(ExportAudio::Strip));
+        assert_eq!(ExportAudio::from_arg("loud"), None);
+    }
+
+    #[test]
+    fn hyperlapse_drops_frames_and_keeps_chapters_on_their_events() {
+        let line = export_description(Path::new("/x/out.mp4"), None, Some(288), ExportAudio::Keep, false);
+        assert!(line.contains("decodebin ! videoconvert ! videorate rate=288 ! video/x-raw,framerate=30/1 ! videoconvert ! x264enc"));
+        assert!(line.ends_with("src.audio_0 ! queue ! fakesink sync=false"), "{}", line);
+        let line = export_description(Path::new("/x/out.mp4"), Some("DC-0042"), Some(60), ExportAudio::Keep, false);
+        assert!(line.contains("framerate=30/1 ! textoverlay text=\"DC-0042\""), "{}", line);
+
+        let chapters = [ChapterMarker { offset: Duration::from_secs(8 * 3600 + 24 * 60), title: "gsensor".to_string() }];
+        let moved = hyperlapse_chapters(&chapters, 288);
+        assert_eq!(moved[0].offset, Duration::from_secs(105));
+        assert_eq!(moved[0].title, "gsensor (08:24:00)");
+
+        let line = export_description(Path::new("/x/out.mp4"), Some("DC-0042"), Some(60), ExportAudio::Keep, true);
+        assert!(line.contains("framerate=30/1 ! video/x-raw,format=RGB ! identity name=blur ! videoconvert ! textoverlay"));
+    }
+
+    #[test]
+    fn range_exports_take_one_sink_and_skip_missing_files() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let row = |sink_id, start_utc, rel_path: &str| SegmentRow {
+            id: 0,
+            sink_id,
+            start_utc,
+            end_utc: start_utc + 60,
+            absolute_index: 0,
+            rel_path: rel_path.to_string(),
+            stream: SegmentStream::default(),
+            bytes: None,
+            restarted: false,
+            clock_offset_ms: None,
+        };
+        for name in ["a1.ts", "a2.ts", "b1.ts", "b2.ts", "b3.ts"] {
+            fs::write(tmp.path().join(name), b"").unwrap();
+        }
+        let rows = vec![
+            row(0, 1_000, "a1.ts"),
+            row(1, 1_000, "b1.ts"),
+            row(0, 1_060, "a2.ts"),
+            row(1, 1_120, "b3.ts"),
+            row(1, 1_060, "b2.ts"),
+            row(0, 1_120, "a3.ts"),
+        ];
+        let (files, streams) = range_segments(&rows, tmp.path());
+        let names: Vec<_> = files.iter().map(|f| f.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, vec!["a1.ts", "a2.ts"], "a tie goes to the lower sink; a3.ts is gone");
+        assert_eq!(streams.len(), 2);
+
+        let (files, _) = range_segments(&rows[1..], tmp.path());
+        let names: Vec<_> = files.iter().map(|f| f.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, vec!["b1.ts", "b2.ts", "b3.ts"]);
+        assert!(range_segments(&[], tmp.path()).0.is_empty());
+    }
+
+    #[test]
+    fn segment_start_comes_from_nvr_names() {
+        let start = segment_start(Path::new("/r/cam/2026-03-01/14-05-30.mp4"), Duration::from_secs(60)).unwrap();
+        assert_eq!(start.format("%Y-%m-%d %H:%M:%S").to_string(), "2026-03-01 14:05:30");
+        assert_eq!(segment_start(Path::new("/nonexistent/output_1.ts"), Duration::from_secs(60)), None);
+    }
+}
@@ -0,0 +1,85 @@
+//! sysfs GPIO inputs (`/sys/class/gpio/gpio<N>/value`), for dash switches and
+//! UPS HAT signals. A pin is exported as an input first if needed.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+fn value_path(gpio: u32) -> PathBuf {
+    PathBuf::from(format!("/sys/class/gpio/gpio{}/value", gpio))
+}
+
+/// Export `gpio` as an input unless that's been done already.
+pub fn export_input(gpio: u32) -> Result<()> {
+    if !value_path(gpio).exists() {
+        fs::write("/sys/class/gpio/export", gpio.to_string()).with_context(|| format!("Can't export GPIO {}", gpio))?;
+    }
+    let direction = format!("/sys/class/gpio/gpio{}/direction", gpio);
+    fs::write(&direction, "in").with_context(|| format!("Can't make GPIO {} an input", gpio))
+}
+
+/// Whether the input is active: high, or low with `active_low`.
+pub fn read_input(gpio: u32, active_low: bool) -> Result<bool> {
+    let value = fs::read_to_string(value_path(gpio)).with_context(|| format!("Can't read GPIO {}", gpio))?;
+    Ok((value.trim() == "1") != active_low)
+}
+
+/// Turns raw switch readings into state changes, ignoring contact bounce.
+#[derive(Debug, Clone)]
+pub struct SwitchDebounce {
+    hold: Duration,
+    state: Option<bool>,
+    pending: Option<(bool, Instant)>,
+}
+
+impl SwitchDebounce {
+    pub fn new(hold: Duration) -> Self {
+        Self { hold, state: None, pending: None }
+    }
+
+    /// Feed one reading; returns the new state once a change has held for `hold`.
+    /// The very first reading counts right away.
+    pub fn update(&mut self, raw: bool, now: Instant) -> Option<bool> {
+        if self.state.is_none() {
+            self.state = Some(raw);
+            return Some(raw);
+        }
+        if self.state == Some(raw) {
+            self.pending = None;
+            return None;
+        }
+        match self.pending {
+            Some((level, since)) if level == raw && now.duration_since(since) >= self.hold => {
+                self.state = Some(raw);
+                self.pending = None;
+                Some(raw)
+            }
+            Some((level, _)) if level == raw => None,
+            _ => {
+                self.pending = Some((raw, now));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debounce_waits_for_the_level_to_hold() {
+        let t0 = Instant::now();
+        let ms = |ms| t0 + Duration::from_millis(ms);
+        let mut debounce = SwitchDebounce::new(Duration::from_millis(100));
+        assert_eq!(debounce.update(false, ms(0)), Some(false));
+        assert_eq!(debounce.update(true, ms(10)), None);
+        // bounced back before the hold time
+        assert_eq!(debounce.update(false, ms(60)), None);
+        assert_eq!(debounce.update(true, ms(70)), None);
+        assert_eq!(debounce.update(true, ms(150)), None);
+        assert_eq!(debounce.update(true, ms(170)), Some(true));
+        assert_eq!(debounce.update(true, ms(500)), None);
+    }
+}
@@ -0,0 +1,288 @@
+//! GPS fixes (`[gps]` in config.toml), from gpsd or straight from a receiver.
+//!
+//! By default gpsd is asked to stream its reports (`?WATCH`) and each TPV
+//! report with a 2D/3D fix becomes a fix. With `device` set, the receiver's
+//! NMEA sentences are read from the serial port instead (set up with `stty`
+//! like `[obd]`): RMC gives position, speed and course, GGA the altitude.
+//!
+//! At most one fix per `interval_ms` goes to `gps_points`, stamped with the
+//! same clock as the segments, so a segment's fixes are the rows inside its
+//! start/end (`DashcamDb::gps_points_for_segment`). Each stored fix is also
+//! handed to `CamService::gps_fix` for `[speed_events]`. The source is reopened
+//! after a few seconds when it fails or goes quiet.
+
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::db::db::GpsPoint;
+use crate::db::db_worker::DBMessage;
+use crate::worker::{Worker, sleep_unless_stopped};
+
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+const REOPEN_DELAY: Duration = Duration::from_secs(5);
+/// Receivers and gpsd report at least once a second, fix or not
+const SILENCE_TIMEOUT: Duration = Duration::from_secs(10);
+/// No fix for this long after having one is a lost fix
+const FIX_TIMEOUT: Duration = Duration::from_secs(5);
+const KNOT_MPS: f64 = 1852.0 / 3600.0;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GpsConfig {
+    /// gpsd to connect to, `host:port`
+    pub gpsd: String,
+    /// Serial device of an NMEA receiver, read instead of gpsd when set
+    pub device: Option<String>,
+    /// Only for `device`
+    pub baud: u32,
+    /// Fixes are stored at most this often
+    pub interval_ms: u64,
+}
+
+impl Default for GpsConfig {
+    fn default() -> Self {
+        Self {
+            gpsd: "127.0.0.1:2947".to_string(),
+            device: None,
+            baud: 9600,
+            interval_ms: 1000,
+        }
+    }
+}
+
+impl GpsConfig {
+    pub fn is_valid(&self) -> bool {
+        let source = match &self.device {
+            Some(device) => device.starts_with('/') && self.baud > 0,
+            None => !self.gpsd.is_empty(),
+        };
+        source && self.interval_ms > 0
+    }
+
+    /// The device or gpsd address, for the log.
+    pub fn source(&self) -> &str {
+        self.device.as_deref().unwrap_or(&self.gpsd)
+    }
+}
+
+/// A fix out of a gpsd JSON report: TPV reports with `mode` 2 (2D) or 3 (3D).
+/// Altitude is only taken from a 3D fix.
+pub fn parse_gpsd_report(line: &str, at_utc: i64) -> Option<GpsPoint> {
+    let report: Value = serde_json::from_str(line).ok()?;
+    let mode = report["mode"].as_i64().unwrap_or(0);
+    if report["class"] != "TPV" || mode < 2 {
+        return None;
+    }
+    let alt_m = if mode == 3 { report["altMSL"].as_f64().or_else(|| report["alt"].as_f64()) } else { None };
+    Some(GpsPoint {
+        at_utc,
+        lat: report["lat"].as_f64()?,
+        lon: report["lon"].as_f64()?,
+        alt_m,
+        speed_mps: report["speed"].as_f64(),
+        heading_deg: report["track"].as_f64(),
+    })
+}
+
+/// NMEA 0183 sentences to fixes. Any talker (`GP`, `GN`, `GL`, ...) is taken;
+/// the altitude of the latest GGA is carried into the next RMC.
+#[derive(Debug, Default)]
+pub struct NmeaParser {
+    alt_m: Option<f64>,
+}
+
+impl NmeaParser {
+    /// A fix for a valid RMC sentence, `None` for anything else.
+    pub fn feed(&mut self, line: &str, at_utc: i64) -> Option<GpsPoint> {
+        let fields: Vec<&str> = nmea_body(line.trim())?.split(',').collect();
+        let field = |i: usize| fields.get(i).copied().filter(|f| !f.is_empty());
+        match fields[0].get(2..)? {
+            "GGA" => {
+                // fix quality 0 is no fix
+                let fixed = field(6).is_some_and(|quality| quality != "0");
+                self.alt_m = if fixed { field(9).and_then(|alt| alt.parse().ok()) } else { None };
+                None
+            }
+            "RMC" if field(2) == Some("A") => Some(GpsPoint {
+                at_utc,
+                lat: nmea_coordinate(field(3)?, field(4)?)?,
+                lon: nmea_coordinate(field(5)?, field(6)?)?,
+                alt_m: self.alt_m,
+                speed_mps: field(7).and_then(|knots| knots.parse::<f64>().ok()).map(|knots| knots * KNOT_MPS),
+                heading_deg: field(8).and_then(|course| course.parse().ok()),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// What's between `$` and `*`, if the checksum (when there is one) matches.
+fn nmea_body(line: &str) -> Option<&str> {
+    let line = line.strip_prefix('$')?;
+    let Some((body, checksum)) = line.split_once('*') else {
+        return Some(line);
+    };
+    let expected = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+    (body.bytes().fold(0, |sum, b| sum ^ b) == expected).then_some(body)
+}
+
+/// `ddmm.mmmm` (`dddmm.mmmm` for longitude) and a hemisphere to degrees.
+fn nmea_coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let raw: f64 = value.parse().ok()?;
+    let degrees = (raw / 100.0).trunc() + (raw % 100.0) / 60.0;
+    match hemisphere {
+        "N" | "E" => Some(degrees),
+        "S" | "W" => Some(-degrees),
+        _ => None,
+    }
+}
+
+/// An open gpsd connection or serial port.
+struct GpsSource {
+    lines: Box<dyn BufRead + Send>,
+    /// Some for a serial NMEA receiver
+    nmea: Option<NmeaParser>,
+}
+
+impl GpsSource {
+    fn open(cfg: &GpsConfig) -> Result<Self> {
+        let Some(device) = &cfg.device else {
+            let stream = TcpStream::connect(cfg.gpsd.as_str()).with_context(|| format!("Can't reach gpsd at {}", cfg.gpsd))?;
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+            (&stream).write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")?;
+            return Ok(Self { lines: Box::new(BufReader::new(stream)), nmea: None });
+        };
+        let status = Command::new("stty")
+            .args(["-F", device, &cfg.baud.to_string(), "raw", "-echo", "min", "0", "time", "10"])
+            .status()
+            .context("Can't run stty")?;
+        if !status.success() {
+            bail!("stty failed on {}", device);
+        }
+        let port = File::open(device).with_context(|| format!("Can't open {}", device))?;
+        Ok(Self { lines: Box::new(BufReader::new(port)), nmea: Some(NmeaParser::default()) })
+    }
+
+    fn parse(&mut self, line: &str, at_utc: i64) -> Option<GpsPoint> {
+        match &mut self.nmea {
+            Some(nmea) => nmea.feed(line, at_utc),
+            None => parse_gpsd_report(line, at_utc),
+        }
+    }
+}
+
+/// Reads fixes until stopped, stores them through `db_sender` and hands each
+/// stored one to `on_fix`.
+pub fn spawn_gps<F>(cfg: &GpsConfig, db_sender: Arc<Sender<DBMessage>>, on_fix: F) -> Worker
+where
+    F: Fn(&GpsPoint) + Send + 'static,
+{
+    let cfg = cfg.clone();
+    let interval = Duration::from_millis(cfg.interval_ms);
+    let mut source: Option<GpsSource> = None;
+    let mut line = String::new();
+    let mut last_line = Instant::now();
+    let mut last_fix: Option<Instant> = None;
+    let mut last_stored: Option<Instant> = None;
+
+    Worker::spawn("gps", Duration::ZERO, move |stop| {
+        let Some(gps) = source.as_mut() else {
+            match GpsSource::open(&cfg) {
+                Ok(opened) => {
+                    info!("Reading GPS fixes from {}", cfg.source());
+                    source = Some(opened);
+                    line.clear();
+                    last_line = Instant::now();
+                }
+                Err(e) => {
+                    debug!("GPS on {}: {:#}", cfg.source(), e);
+                    sleep_unless_stopped(REOPEN_DELAY, stop);
+                }
+            }
+            return;
+        };
+
+        match gps.lines.read_line(&mut line) {
+            Ok(_) if line.ends_with('\n') => {
+                last_line = Instant::now();
+                let fix = gps.parse(&line, Utc::now().timestamp());
+                line.clear();
+                if let Some(point) = fix {
+                    if last_fix.is_none() {
+                        info!("GPS has a fix at {:.5}, {:.5}", point.lat, point.lon);
+                    }
+                    last_fix = Some(Instant::now());
+                    if last_stored.is_none_or(|at| at.elapsed() >= interval) {
+                        last_stored = Some(Instant::now());
+                        let _ = db_sender.send(DBMessage::InsertGpsPoint { point: point.clone() });
+                        on_fix(&point);
+                    }
+                }
+            }
+            // the serial port's read timed out, or half a line so far
+            Ok(_) => std::thread::sleep(Duration::from_millis(100)),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+            // noise at the wrong baud rate
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => line.clear(),
+            Err(e) => {
+                warn!("GPS on {} failed: {:#}", cfg.source(), e);
+                source = None;
+                return;
+            }
+        }
+
+        if last_fix.is_some_and(|at| at.elapsed() > FIX_TIMEOUT) {
+            info!("GPS lost its fix");
+            last_fix = None;
+        }
+        if last_line.elapsed() > SILENCE_TIMEOUT {
+            warn!("GPS on {} went quiet, reopening", cfg.source());
+            source = None;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nmea_and_gpsd_fixes() {
+        let mut nmea = NmeaParser::default();
+        let gga = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n";
+        assert_eq!(nmea.feed(gga, 100), None);
+        let fix = nmea.feed("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A\r\n", 100).unwrap();
+        assert!((fix.lat - 48.1173).abs() < 1e-6 && (fix.lon - 11.516_667).abs() < 1e-6);
+        assert_eq!((fix.alt_m, fix.heading_deg), (Some(545.4), Some(84.4)));
+        assert!((fix.speed_mps.unwrap() - 11.523_56).abs() < 1e-3);
+
+        assert_eq!(nmea.feed("$GNRMC,090000.00,V,,,,,,,170426,,,N*6C", 101), None, "no fix");
+        assert_eq!(
+            nmea.feed("$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6B", 102),
+            None,
+            "bad checksum"
+        );
+        let south_west = nmea.feed("$GPRMC,000000,A,3345.000,S,07030.000,W,,,010126,,", 103).unwrap();
+        assert_eq!((south_west.lat, south_west.lon, south_west.speed_mps), (-33.75, -70.5, None));
+
+        let tpv = r#"{"class":"TPV","mode":3,"lat":52.52,"lon":13.405,"altMSL":34.0,"speed":13.9,"track":271.0}"#;
+        assert_eq!(
+            parse_gpsd_report(tpv, 200),
+            Some(GpsPoint { at_utc: 200, lat: 52.52, lon: 13.405, alt_m: Some(34.0), speed_mps: Some(13.9), heading_deg: Some(271.0) })
+        );
+        let flat = parse_gpsd_report(r#"{"class":"TPV","mode":2,"lat":52.52,"lon":13.405,"alt":34.0}"#, 200).unwrap();
+        assert_eq!(flat.alt_m, None, "2D fixes have no usable altitude");
+        assert_eq!(parse_gpsd_report(r#"{"class":"TPV","mode":1}"#, 200), None);
+        assert_eq!(parse_gpsd_report(r#"{"class":"SKY","satellites":[]}"#, 200), None);
+    }
+}
@@ -0,0 +1,89 @@
+//! `overlay_gps = true` on a camera: its speed and position burnt into the
+//! frames. The source chain carries a textoverlay for it (see `processing`);
+//! this worker rewrites the overlay's text every second from the latest
+//! `[gps]` fix, and blanks it once that fix is stale so a lost fix doesn't
+//! leave a frozen speed on the video.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::db::db::GpsPoint;
+use crate::recording_pipeline::RecordingPipeline;
+use crate::worker::Worker;
+
+/// How often the overlays are rewritten
+const TICK: Duration = Duration::from_secs(1);
+/// A fix older than this is no longer shown
+const STALE_AFTER: Duration = Duration::from_secs(5);
+
+/// What the overlay shows for `point`, e.g. `87 km/h  48.11730°N 11.51667°E`.
+pub fn overlay_text(point: &GpsPoint) -> String {
+    let position = format!(
+        "{:.5}°{} {:.5}°{}",
+        point.lat.abs(),
+        if point.lat < 0.0 { 'S' } else { 'N' },
+        point.lon.abs(),
+        if point.lon < 0.0 { 'W' } else { 'E' }
+    );
+    match point.speed_mps {
+        Some(mps) => format!("{:.0} km/h  {}", mps * 3.6, position),
+        None => position,
+    }
+}
+
+/// The last fix handed to the service, shared with the overlay worker.
+#[derive(Clone, Default)]
+pub struct LatestFix(Arc<Mutex<Option<(Instant, GpsPoint)>>>);
+
+impl LatestFix {
+    pub fn set(&self, point: &GpsPoint) {
+        *self.0.lock().unwrap() = Some((Instant::now(), point.clone()));
+    }
+
+    /// Overlay text for the fix, empty once it's stale.
+    pub fn text(&self) -> String {
+        match &*self.0.lock().unwrap() {
+            Some((at, point)) if at.elapsed() <= STALE_AFTER => overlay_text(point),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Keeps the overlays of the given pipelines current.
+pub fn spawn_gps_overlay(latest: LatestFix, pipelines: Vec<Arc<Mutex<RecordingPipeline>>>) -> Worker {
+    Worker::spawn("gps_overlay", TICK, move |_| {
+        let text = latest.text();
+        // a pipeline busy starting or stopping gets the next tick's text
+        for pipeline in &pipelines {
+            if let Ok(pipeline) = pipeline.try_lock() {
+                pipeline.set_gps_overlay(&text);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shows_speed_and_hemispheres_until_the_fix_is_stale() {
+        let point = GpsPoint {
+            at_utc: 0,
+            lat: -33.868_82,
+            lon: 151.209_3,
+            alt_m: None,
+            speed_mps: Some(25.0),
+            heading_deg: None,
+        };
+        assert_eq!(overlay_text(&point), "90 km/h  33.86882°S 151.20930°E");
+        assert_eq!(overlay_text(&GpsPoint { speed_mps: None, lon: -0.5, ..point.clone() }), "33.86882°S 0.50000°W");
+
+        let latest = LatestFix::default();
+        assert_eq!(latest.text(), "");
+        latest.set(&point);
+        assert!(latest.text().starts_with("90 km/h"));
+        *latest.0.lock().unwrap() = Some((Instant::now() - STALE_AFTER - Duration::from_secs(1), point));
+        assert_eq!(latest.text(), "");
+    }
+}
@@ -0,0 +1,275 @@
+//! Crash detection from an accelerometer (`[gsensor]` in config.toml).
+//!
+//! Accelerometers with a kernel driver (most I2C/SPI parts: LIS3DH, MPU6050,
+//! ADXL345 with its overlay) are read from their IIO sysfs directory, each
+//! axis as `(in_accel_<axis>_raw + offset) * scale` in m/s². Without a driver,
+//! an ADXL345 is read straight off the I2C bus with `i2c_bus` set.
+//!
+//! The sensor is sampled `sample_hz` times a second. Gravity is tracked as a
+//! slow average of the samples, so the mounting angle and hills don't matter,
+//! and a sample further than `threshold_g` from it is an impact. Each impact
+//! becomes a `gsensor` event on the `[gsensor]` cameras, and their segments
+//! from `before_sec` before to `after_sec` after it are saved like a `protect`
+//! request (`CamService::impact`). Impacts during the `after_sec` that follow
+//! belong to the same crash and are ignored.
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::worker::{Worker, sleep_unless_stopped};
+
+const REOPEN_DELAY: Duration = Duration::from_secs(5);
+/// Impacts closer together than this are one, whatever `after_sec` is
+const MIN_QUIET: Duration = Duration::from_secs(2);
+/// Share of each sample that goes into the gravity estimate
+const GRAVITY_SMOOTHING: f64 = 0.02;
+const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// `ioctl` that sets the address of the I2C device talked to
+const I2C_SLAVE: libc::c_ulong = 0x0703;
+/// ADXL345 registers
+const ADXL345_DEVID: u8 = 0x00;
+const ADXL345_BW_RATE: u8 = 0x2C;
+const ADXL345_POWER_CTL: u8 = 0x2D;
+const ADXL345_DATA_FORMAT: u8 = 0x31;
+const ADXL345_DATAX0: u8 = 0x32;
+/// g per bit in full-resolution mode
+const ADXL345_SCALE_G: f64 = 0.0039;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GSensorConfig {
+    /// IIO sysfs directory of the accelerometer
+    pub iio_device: String,
+    /// I2C bus of an ADXL345 without a driver, read instead of `iio_device` when set
+    pub i2c_bus: Option<String>,
+    /// Only for `i2c_bus`; 0x1D when the ADXL345's SDO pin is high
+    pub i2c_address: u16,
+    pub sample_hz: u32,
+    /// Acceleration beyond gravity that counts as an impact
+    pub threshold_g: f64,
+    /// Saved footage before and after an impact
+    pub before_sec: u64,
+    pub after_sec: u64,
+    /// Cameras the event is raised and footage saved on, every camera when empty
+    pub cameras: Vec<String>,
+}
+
+impl Default for GSensorConfig {
+    fn default() -> Self {
+        Self {
+            iio_device: "/sys/bus/iio/devices/iio:device0".to_string(),
+            i2c_bus: None,
+            i2c_address: 0x53,
+            sample_hz: 50,
+            threshold_g: 2.0,
+            before_sec: 20,
+            after_sec: 10,
+            cameras: Vec::new(),
+        }
+    }
+}
+
+impl GSensorConfig {
+    /// Needs an absolute device, a sample rate, a positive threshold and known cameras.
+    pub fn is_valid(&self, camera_keys: &[&str]) -> bool {
+        let source = match &self.i2c_bus {
+            Some(bus) => bus.starts_with('/') && self.i2c_address <= 0x7F,
+            None => self.iio_device.starts_with('/'),
+        };
+        source
+            && (1..=1000).contains(&self.sample_hz)
+            && self.threshold_g > 0.0
+            && self.cameras.iter().all(|key| camera_keys.contains(&key.as_str()))
+    }
+
+    /// The I2C bus or IIO device, for the log.
+    pub fn source(&self) -> &str {
+        self.i2c_bus.as_deref().unwrap_or(&self.iio_device)
+    }
+}
+
+/// Finds impacts in a stream of samples.
+#[derive(Debug, Clone)]
+pub struct ImpactDetector {
+    threshold_g: f64,
+    quiet: Duration,
+    /// Slow average of the samples, m/s²
+    gravity: Option<[f64; 3]>,
+    quiet_until: Option<Instant>,
+}
+
+impl ImpactDetector {
+    pub fn new(threshold_g: f64, quiet: Duration) -> Self {
+        Self { threshold_g, quiet: quiet.max(MIN_QUIET), gravity: None, quiet_until: None }
+    }
+
+    /// The g beyond gravity if `accel` (m/s²) is an impact.
+    pub fn feed(&mut self, accel: [f64; 3], now: Instant) -> Option<f64> {
+        let gravity = self.gravity.get_or_insert(accel);
+        let dynamic: f64 = (0..3).map(|i| (accel[i] - gravity[i]).powi(2)).sum::<f64>().sqrt() / STANDARD_GRAVITY;
+        for (g, a) in gravity.iter_mut().zip(accel) {
+            *g += (a - *g) * GRAVITY_SMOOTHING;
+        }
+        if dynamic < self.threshold_g || self.quiet_until.is_some_and(|until| now < until) {
+            return None;
+        }
+        self.quiet_until = Some(now + self.quiet);
+        Some(dynamic)
+    }
+}
+
+/// An open accelerometer.
+enum Accelerometer {
+    Iio { dir: PathBuf },
+    Adxl345 { bus: File },
+}
+
+impl Accelerometer {
+    fn open(cfg: &GSensorConfig) -> Result<Self> {
+        let Some(bus) = &cfg.i2c_bus else {
+            let dir = PathBuf::from(&cfg.iio_device);
+            let mut accel = Self::Iio { dir };
+            accel.read().with_context(|| format!("{} isn't an accelerometer", cfg.iio_device))?;
+            return Ok(accel);
+        };
+        let mut bus =
+            OpenOptions::new().read(true).write(true).open(bus).with_context(|| format!("Can't open {}", bus))?;
+        // SAFETY: I2C_SLAVE takes the address by value
+        if unsafe { libc::ioctl(bus.as_raw_fd(), I2C_SLAVE as _, libc::c_ulong::from(cfg.i2c_address)) } < 0 {
+            bail!("No I2C device at {:#04x}: {}", cfg.i2c_address, std::io::Error::last_os_error());
+        }
+        let mut id = [0u8];
+        bus.write_all(&[ADXL345_DEVID])?;
+        bus.read_exact(&mut id)?;
+        if id[0] != 0xE5 {
+            bail!("Device at {:#04x} isn't an ADXL345 (id {:#04x})", cfg.i2c_address, id[0]);
+        }
+        // full resolution at ±16 g, 100 Hz output, measuring
+        bus.write_all(&[ADXL345_DATA_FORMAT, 0x0B])?;
+        bus.write_all(&[ADXL345_BW_RATE, 0x0A])?;
+        bus.write_all(&[ADXL345_POWER_CTL, 0x08])?;
+        Ok(Self::Adxl345 { bus })
+    }
+
+    /// Acceleration on x, y and z in m/s².
+    fn read(&mut self) -> Result<[f64; 3]> {
+        match self {
+            Self::Iio { dir } => {
+                let value =
+                    |name: &str| -> Option<f64> { fs::read_to_string(dir.join(name)).ok()?.trim().parse().ok() };
+                let mut accel = [0.0; 3];
+                for (i, axis) in ["x", "y", "z"].iter().enumerate() {
+                    let raw = value(&format!("in_accel_{}_raw", axis)).with_context(|| format!("No {} axis", axis))?;
+                    let scale = value(&format!("in_accel_{}_scale", axis))
+                        .or_else(|| value("in_accel_scale"))
+                        .context("No accelerometer scale")?;
+                    let offset = value(&format!("in_accel_{}_offset", axis)).or_else(|| value("in_accel_offset"));
+                    accel[i] = (raw + offset.unwrap_or(0.0)) * scale;
+                }
+                Ok(accel)
+            }
+            Self::Adxl345 { bus } => {
+                let mut data = [0u8; 6];
+                bus.write_all(&[ADXL345_DATAX0])?;
+                bus.read_exact(&mut data)?;
+                Ok(adxl345_accel(&data))
+            }
+        }
+    }
+}
+
+/// The ADXL345's DATAX0..DATAZ1 registers in m/s².
+fn adxl345_accel(data: &[u8; 6]) -> [f64; 3] {
+    let axis =
+        |i: usize| f64::from(i16::from_le_bytes([data[2 * i], data[2 * i + 1]])) * ADXL345_SCALE_G * STANDARD_GRAVITY;
+    [axis(0), axis(1), axis(2)]
+}
+
+/// Samples the accelerometer at `sample_hz` until stopped and hands the g of
+/// each impact to `on_impact`.
+pub fn spawn_gsensor<F>(cfg: &GSensorConfig, on_impact: F) -> Worker
+where
+    F: Fn(f64) + Send + 'static,
+{
+    let cfg = cfg.clone();
+    let period = Duration::from_secs(1) / cfg.sample_hz.max(1);
+    let mut detector = ImpactDetector::new(cfg.threshold_g, Duration::from_secs(cfg.after_sec));
+    let mut sensor: Option<Accelerometer> = None;
+
+    Worker::spawn("gsensor", period, move |stop| {
+        let Some(accel) = sensor.as_mut() else {
+            match Accelerometer::open(&cfg) {
+                Ok(opened) => {
+                    info!("Reading the G-sensor on {}", cfg.source());
+                    sensor = Some(opened);
+                }
+                Err(e) => {
+                    debug!("G-sensor on {}: {:#}", cfg.source(), e);
+                    sleep_unless_stopped(REOPEN_DELAY, stop);
+                }
+            }
+            return;
+        };
+
+        match accel.read() {
+            Ok(sample) => {
+                if let Some(g) = detector.feed(sample, Instant::now()) {
+                    warn!("G-sensor impact of {:.1} g", g);
+                    on_impact(g);
+                }
+            }
+            Err(e) => {
+                warn!("G-sensor on {} failed: {:#}", cfg.source(), e);
+                sensor = None;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_impact_stands_out_from_gravity_at_any_angle() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut detector = ImpactDetector::new(2.0, Duration::from_secs(10));
+        // mounted tilted: gravity split over y and z
+        let tilted = [0.0, 6.93, 6.93];
+        for ms in 0..100 {
+            assert_eq!(detector.feed(tilted, at(ms * 20)), None);
+        }
+        assert_eq!(detector.feed([0.0, 6.93 + 9.8, 6.93], at(2000)), None, "1 g bump");
+        let g = detector.feed([-29.4, 6.93, 6.93], at(2020)).unwrap();
+        assert!((g - 3.0).abs() < 0.1, "{}", g);
+        assert_eq!(detector.feed([29.4, 6.93, 6.93], at(3000)), None, "same crash");
+        assert!(detector.feed([0.0, 6.93, 6.93 + 40.0], at(12_100)).is_some());
+
+        // 1 g on z, little-endian 16-bit counts
+        let one_g = adxl345_accel(&[0, 0, 0, 0, 0x00, 0x01]);
+        assert!((one_g[2] - 256.0 * 0.0039 * STANDARD_GRAVITY).abs() < 1e-9 && one_g[0] == 0.0);
+    }
+
+    #[test]
+    fn reads_iio_axes_with_a_shared_scale() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        for (name, value) in [("in_accel_x_raw", "-12\n"), ("in_accel_y_raw", "0\n"), ("in_accel_z_raw", "1024\n")] {
+            fs::write(tmp.path().join(name), value).unwrap();
+        }
+        fs::write(tmp.path().join("in_accel_scale"), "0.009576\n").unwrap();
+        let mut accel = Accelerometer::Iio { dir: tmp.path().to_path_buf() };
+        let [x, y, z] = accel.read().unwrap();
+        assert!((x + 0.114912).abs() < 1e-9 && y == 0.0 && (z - 9.805824).abs() < 1e-9);
+
+        fs::remove_file(tmp.path().join("in_accel_scale")).unwrap();
+        assert!(accel.read().is_err());
+    }
+}
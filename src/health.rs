@@ -0,0 +1,115 @@
+//! `GET /healthz`: one word for orchestrators and uptime monitors, plus a line
+//! per subsystem for whoever gets paged. Unlike `/api/status` it needs no token
+//! and doesn't list files.
+
+use serde::Serialize;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    Ok,
+    /// Still recording, but something needs a look
+    Degraded,
+    /// Not recording (or not keeping what it records)
+    Failing,
+}
+
+impl HealthState {
+    /// Status code for the endpoint: monitors treat anything but 2xx as down.
+    pub fn http_status(&self) -> u16 {
+        match self {
+            HealthState::Ok | HealthState::Degraded => 200,
+            HealthState::Failing => 503,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubsystemHealth {
+    /// "db", "storage", "camera:<key>"
+    pub name: String,
+    pub state: HealthState,
+    pub detail: String,
+}
+
+impl SubsystemHealth {
+    pub fn new(name: impl Into<String>, state: HealthState, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), state, detail: detail.into() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub status: HealthState,
+    pub subsystems: Vec<SubsystemHealth>,
+}
+
+impl HealthReport {
+    /// Failing if the DB or storage is, or no camera is recording; otherwise the
+    /// worst camera counts as degraded at most, since the others still record.
+    pub fn from_subsystems(subsystems: Vec<SubsystemHealth>) -> Self {
+        let (cameras, core): (Vec<_>, Vec<_>) = subsystems.iter().partition(|s| s.name.starts_with("camera:"));
+        let core_worst = core.iter().map(|s| s.state).max().unwrap_or(HealthState::Ok);
+        let cameras_worst = cameras.iter().map(|s| s.state).max().unwrap_or(HealthState::Ok);
+        let all_cameras_failing = !cameras.is_empty() && cameras.iter().all(|s| s.state == HealthState::Failing);
+
+        let status = if all_cameras_failing {
+            HealthState::Failing
+        } else {
+            core_worst.max(cameras_worst.min(HealthState::Degraded))
+        };
+        Self { status, subsystems }
+    }
+}
+
+/// Camera health from its start state and how long ago its newest segment was opened.
+/// A segment older than two segment lengths plus slack means writes have stalled.
+pub fn camera_health(
+    key: &str,
+    state: &str,
+    playing: bool,
+    newest_segment_age: Option<Duration>,
+    segment_duration: Duration,
+) -> SubsystemHealth {
+    let name = format!("camera:{}", key);
+    if !playing {
+        let health = if state == "starting" { HealthState::Degraded } else { HealthState::Failing };
+        return SubsystemHealth::new(name, health, state);
+    }
+    match newest_segment_age {
+        Some(age) if age > segment_duration * 2 + Duration::from_secs(10) => SubsystemHealth::new(
+            name,
+            HealthState::Degraded,
+            format!("{}, but no new segment for {}s", state, age.as_secs()),
+        ),
+        _ => SubsystemHealth::new(name, HealthState::Ok, state),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_bad_camera_degrades_all_bad_fails() {
+        let two = Duration::from_secs(2);
+        let ok = camera_health("front", "playing", true, Some(Duration::from_secs(1)), two);
+        let stalled = camera_health("rear", "playing", true, Some(Duration::from_secs(30)), two);
+        let dead = camera_health("rear", "Internal data stream error", false, None, two);
+        let db = SubsystemHealth::new("db", HealthState::Ok, "responding");
+        assert_eq!(stalled.state, HealthState::Degraded);
+
+        let report = |subsystems: Vec<&SubsystemHealth>| {
+            HealthReport::from_subsystems(subsystems.into_iter().cloned().collect()).status
+        };
+        assert_eq!(report(vec![&db, &ok]), HealthState::Ok);
+        assert_eq!(report(vec![&db, &ok, &stalled]), HealthState::Degraded);
+        assert_eq!(report(vec![&db, &ok, &dead]), HealthState::Degraded);
+        assert_eq!(report(vec![&db, &dead]), HealthState::Failing);
+
+        let db_down = SubsystemHealth::new("db", HealthState::Failing, "no reply");
+        assert_eq!(report(vec![&db_down, &ok]), HealthState::Failing);
+        assert_eq!(HealthState::Failing.http_status(), 503);
+    }
+}
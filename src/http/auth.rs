@@ -0,0 +1,96 @@
+//! Bearer-token auth for the HTTP control API (`[http.auth]` in config.toml).
+
+use crate::config::{ApiToken, HttpAuthConfig};
+use crate::control::Role;
+
+/// Who a request runs as: recorded in the audit log and checked against
+/// `ControlCommand::required_role`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenAuth {
+    tokens: Vec<ApiToken>,
+}
+
+impl TokenAuth {
+    pub fn new(cfg: &HttpAuthConfig) -> Self {
+        Self {
+            tokens: cfg.tokens.clone(),
+        }
+    }
+
+    /// No tokens configured: anyone who can reach the port is admin.
+    pub fn is_open(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Resolve an `Authorization` header value. None = reject with 401.
+    pub fn authenticate(&self, authorization: Option<&str>) -> Option<Caller> {
+        if self.is_open() {
+            return Some(Caller {
+                name: "anonymous".to_string(),
+                role: Role::Admin,
+            });
+        }
+        let presented = authorization?.strip_prefix("Bearer ")?.trim();
+        self.tokens
+            .iter()
+            .find(|t| constant_time_eq(t.token.as_bytes(), presented.as_bytes()))
+            .map(|t| Caller {
+                name: t.name.clone(),
+                role: t.role,
+            })
+    }
+}
+
+/// Compare without bailing out at the first differing byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> TokenAuth {
+        let token = |name: &str, token: &str, role| ApiToken {
+            name: name.to_string(),
+            token: token.to_string(),
+            role,
+        };
+        TokenAuth::new(&HttpAuthConfig {
+            tokens: vec![token("tablet", "view-secret", Role::Viewer), token("me", "admin-secret", Role::Admin)],
+        })
+    }
+
+    #[test]
+    fn bearer_token_maps_to_caller() {
+        let auth = auth();
+        assert_eq!(
+            auth.authenticate(Some("Bearer view-secret")),
+            Some(Caller { name: "tablet".to_string(), role: Role::Viewer })
+        );
+        assert_eq!(auth.authenticate(Some("Bearer admin-secret")).unwrap().role, Role::Admin);
+        assert_eq!(auth.authenticate(Some("Bearer nope")), None);
+        assert_eq!(auth.authenticate(Some("view-secret")), None);
+        assert_eq!(auth.authenticate(None), None);
+    }
+
+    #[test]
+    fn roles_include_lower_ones() {
+        assert!(Role::Admin >= Role::Operator);
+        assert!(Role::Operator >= Role::Viewer);
+        assert!(Role::Viewer < Role::Operator);
+    }
+
+    #[test]
+    fn no_tokens_means_open_api() {
+        let auth = TokenAuth::new(&HttpAuthConfig::default());
+        assert!(auth.is_open());
+        assert_eq!(auth.authenticate(None).unwrap().role, Role::Admin);
+    }
+}
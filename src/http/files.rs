@@ -0,0 +1,194 @@
+//! Read-only downloads from the recording root (TS segments, exports, stills).
+//! Supports single `Range` requests and `If-Range`, so an interrupted download
+//! over a flaky phone hotspot resumes instead of starting over.
+
+use chrono::{DateTime, Utc};
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use crate::http::server::{Body, Request, Response};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeOutcome {
+    /// No usable `Range` header: send everything
+    Full,
+    /// Inclusive byte range
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Interpret a `Range` header for a file of `len` bytes. Multi-range and
+/// malformed headers are ignored (full response), as RFC 9110 allows.
+pub fn parse_range(header: Option<&str>, len: u64) -> RangeOutcome {
+    let Some(spec) = header.and_then(|h| h.trim().strip_prefix("bytes=")) else {
+        return RangeOutcome::Full;
+    };
+    if spec.contains(',') {
+        return RangeOutcome::Full;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return RangeOutcome::Full;
+    };
+    let (start, end) = (start.trim(), end.trim());
+
+    let parsed = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // bytes=-N: the last N bytes
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 || len == 0 {
+                return RangeOutcome::Unsatisfiable;
+            }
+            (len.saturating_sub(suffix), len - 1)
+        }
+        // bytes=N-: from N to the end
+        (Ok(first), Err(_)) if end.is_empty() => (first, len.saturating_sub(1)),
+        (Ok(first), Ok(last)) if first <= last => (first, last.min(len.saturating_sub(1))),
+        _ => return RangeOutcome::Full,
+    };
+    if parsed.0 >= len {
+        return RangeOutcome::Unsatisfiable;
+    }
+    RangeOutcome::Partial(parsed.0, parsed.1)
+}
+
+/// `rel_path` under `root`, refusing anything that could climb out of it.
+fn resolve(root: &Path, rel_path: &str) -> Option<PathBuf> {
+    let rel = Path::new(rel_path);
+    if rel_path.is_empty() || rel_path.contains('\\') || !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let path = root.join(rel).canonicalize().ok()?;
+    path.starts_with(root.canonicalize().ok()?).then_some(path)
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).unwrap_or("") {
+        "ts" => "video/mp2t",
+        "mp4" => "video/mp4",
+        "mkv" => "video/x-matroska",
+        "m3u8" => "application/vnd.apple.mpegurl",
+        "jpg" | "jpeg" => "image/jpeg",
+        "pgm" => "image/x-portable-graymap",
+        "zip" => "application/zip",
+        "gpx" => "application/gpx+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Serve `<root>/<rel_path>` for a GET/HEAD request.
+pub fn serve_file(root: &Path, rel_path: &str, req: &Request) -> Response {
+    let Some(path) = resolve(root, rel_path) else {
+        return Response::error(404, "Not found");
+    };
+    let (mut file, meta) = match File::open(&path).and_then(|f| f.metadata().map(|m| (f, m))) {
+        Ok((file, meta)) if meta.is_file() => (file, meta),
+        _ => return Response::error(404, "Not found"),
+    };
+
+    let len = meta.len();
+    let modified: Option<DateTime<Utc>> = meta.modified().ok().map(DateTime::from);
+    let etag = format!("\"{:x}-{:x}\"", len, modified.map(|m| m.timestamp()).unwrap_or(0));
+
+    // A resumed download only gets the tail if the file is still the one it started on
+    let range_header = match req.header("if-range") {
+        Some(tag) if tag != etag => None,
+        _ => req.header("range"),
+    };
+
+    let (status, start, body_len) = match parse_range(range_header, len) {
+        RangeOutcome::Full => (200, 0, len),
+        RangeOutcome::Partial(start, end) => (206, start, end - start + 1),
+        RangeOutcome::Unsatisfiable => {
+            return Response::error(416, "Range not satisfiable").with_header("Content-Range", format!("bytes */{}", len));
+        }
+    };
+    if file.seek(SeekFrom::Start(start)).is_err() {
+        return Response::error(500, "Seek failed");
+    }
+
+    let mut response = Response {
+        status,
+        content_type: content_type(&path),
+        headers: Vec::new(),
+        body: Body::File { file, len: body_len },
+    }
+    .with_header("Accept-Ranges", "bytes")
+    .with_header("ETag", etag);
+    if let Some(modified) = modified {
+        response = response.with_header("Last-Modified", modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string());
+    }
+    if status == 206 {
+        response = response.with_header("Content-Range", format!("bytes {}-{}/{}", start, start + body_len - 1, len));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_forms() {
+        assert_eq!(parse_range(None, 100), RangeOutcome::Full);
+        assert_eq!(parse_range(Some("bytes=0-9"), 100), RangeOutcome::Partial(0, 9));
+        assert_eq!(parse_range(Some("bytes=90-"), 100), RangeOutcome::Partial(90, 99));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), RangeOutcome::Partial(90, 99));
+        assert_eq!(parse_range(Some("bytes=-500"), 100), RangeOutcome::Partial(0, 99));
+        assert_eq!(parse_range(Some("bytes=50-500"), 100), RangeOutcome::Partial(50, 99));
+        assert_eq!(parse_range(Some("bytes=100-"), 100), RangeOutcome::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-1,5-6"), 100), RangeOutcome::Full);
+        assert_eq!(parse_range(Some("bytes=9-0"), 100), RangeOutcome::Full);
+        assert_eq!(parse_range(Some("items=0-9"), 100), RangeOutcome::Full);
+    }
+
+    fn get(root: &Path, rel: &str, headers: &[(&str, &str)]) -> (u16, Vec<(&'static str, String)>, Vec<u8>) {
+        let req = Request {
+            method: "GET".to_string(),
+            path: format!("/files/{}", rel),
+            query: Default::default(),
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        let response = serve_file(root, rel, &req);
+        let (status, headers) = (response.status, response.headers.clone());
+        let mut raw = Vec::new();
+        response.write_to(&mut raw, true).unwrap();
+        let split = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        (status, headers, raw[split..].to_vec())
+    }
+
+    #[test]
+    fn serves_ranges_and_resumes() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("cam/0")).unwrap();
+        std::fs::write(dir.path().join("cam/0/output_0.ts"), b"0123456789").unwrap();
+
+        let (status, _, body) = get(dir.path(), "cam/0/output_0.ts", &[]);
+        assert_eq!((status, body.as_slice()), (200, &b"0123456789"[..]));
+
+        let (status, headers, body) = get(dir.path(), "cam/0/output_0.ts", &[("range", "bytes=4-")]);
+        assert_eq!((status, body.as_slice()), (206, &b"456789"[..]));
+        assert!(headers.contains(&("Content-Range", "bytes 4-9/10".to_string())));
+        let etag = headers.iter().find(|(k, _)| *k == "ETag").unwrap().1.clone();
+
+        // same file: resume; file changed since: start over
+        let (status, _, _) = get(dir.path(), "cam/0/output_0.ts", &[("range", "bytes=4-"), ("if-range", &etag)]);
+        assert_eq!(status, 206);
+        let (status, _, body) = get(dir.path(), "cam/0/output_0.ts", &[("range", "bytes=4-"), ("if-range", "\"old\"")]);
+        assert_eq!((status, body.len()), (200, 10));
+
+        let (status, _, _) = get(dir.path(), "cam/0/output_0.ts", &[("range", "bytes=20-")]);
+        assert_eq!(status, 416);
+    }
+
+    #[test]
+    fn refuses_paths_outside_root() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().join("recordings");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(dir.path().join("secret.db"), b"x").unwrap();
+
+        assert_eq!(get(&root, "../secret.db", &[]).0, 404);
+        assert_eq!(get(&root, "/etc/passwd", &[]).0, 404);
+        assert_eq!(get(&root, "missing.ts", &[]).0, 404);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod auth;
+pub mod files;
+pub mod server;
+pub mod tls;
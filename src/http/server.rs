@@ -0,0 +1,621 @@
+//! Minimal HTTP/1.1 server for the control API. One thread per connection,
+//! one request per connection; the traffic is a phone or tablet, not a crowd.
+//!
+//! Routes (role needed in brackets):
+//! - `GET  /api/status`                              [viewer]
+//! - `GET  /api/cameras/<key>/events?limit=N`        [viewer]
+//! - `POST /api/cameras/<key>/events/<kind>`         [operator]
+//! - `POST /api/cameras/<key>/sinks/<id>/enable`     [operator]
+//! - `POST /api/cameras/<key>/sinks/<id>/disable`    [operator]
+//! - `POST /api/cameras/<key>/clip`                  [operator] `[clips]` clip around now
+//! - `POST /api/cameras/<key>/privacy/on`, `/off`    [operator]
+//! - `POST /api/cameras/<key>/stop`, `/start`        [operator] held stopped until started again
+//! - `GET  /api/audit?limit=N`                       [admin]
+//! - `POST /api/standby/enter`, `/api/standby/exit`  [operator]
+//! - `GET  /api/rules`                              [viewer] armed, fire count, last firing
+//! - `POST /api/rules/<name>/arm`, `/disarm`         [operator]
+//! - `GET  /api/mode`                                [viewer] current mode, recent switches
+//! - `POST /api/mode/<name>`                         [operator]
+//! - `GET  /api/incidents?limit=N`                   [viewer]
+//! - `POST /api/incidents/<id>/bundle`               [operator] zip for handing over
+//! - `GET  /api/track?trip=N` or `?from=T&to=T`      [viewer] GPX, or GeoJSON with `&format=geojson`
+//! - `GET  /api/cameras/<key>/playlist.m3u8?from=T&to=T` [viewer] HLS playlist of the recordings
+//! - `GET  /api/cameras/<key>/rewind.m3u8?minutes=N` [viewer] HLS from N minutes back, on to live
+//! - `GET  /api/settings/<key>`                      [viewer]
+//! - `POST /api/settings/<key>?value=V`              [operator]
+//! - `GET  /files/<key>/<path>`                      [viewer] recordings/exports, with Range support
+//! - `GET  /healthz`                                 [none] ok | degraded | failing
+//! - `GET  /metrics`                                 [viewer] Prometheus latency histograms
+
+use anyhow::{Context, Result, bail};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::{info, trace, warn};
+
+use crate::bandwidth::RateLimits;
+use crate::cam_service::CamService;
+use crate::config::HttpConfig;
+use crate::control::{ControlCommand, ControlReply};
+use crate::events::EventKind;
+use crate::http::auth::TokenAuth;
+use crate::http::files;
+use crate::http::tls;
+use crate::metrics;
+use crate::playlist::RewindStart;
+use crate::track::{TrackFormat, TrackRange, parse_time};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_HEADER_LINES: usize = 100;
+/// Request line and headers together; the read timeout is per read, so this bounds a slow sender too
+const MAX_REQUEST_BYTES: u64 = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    /// Lower-cased names
+    pub headers: HashMap<String, String>,
+}
+
+impl Request {
+    /// Parse the request line and headers. Bodies aren't used by any route.
+    pub fn read_from(reader: &mut impl BufRead) -> Result<Self> {
+        let mut reader = reader.take(MAX_REQUEST_BYTES);
+        let mut line = String::new();
+        read_line(&mut reader, &mut line)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            bail!("Malformed request line {:?}", line.trim_end());
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter(|kv| !kv.is_empty())
+            .map(|kv| {
+                let (k, v) = kv.split_once('=').unwrap_or((kv, ""));
+                (percent_decode(k), percent_decode(v))
+            })
+            .collect();
+
+        let mut headers = HashMap::new();
+        for _ in 0..MAX_HEADER_LINES {
+            let mut header = String::new();
+            if read_line(&mut reader, &mut header)? == 0 || header.trim_end().is_empty() {
+                return Ok(Request {
+                    method: method.to_string(),
+                    path: path.to_string(),
+                    query,
+                    headers,
+                });
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+        }
+        bail!("Too many headers")
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    /// `?trip=N`, or `?from=T&to=T` in unix seconds or RFC 3339
+    fn track_range(&self) -> Result<TrackRange, Response> {
+        let time = |name: &str| -> Result<i64, Response> {
+            let value = self.query.get(name).ok_or_else(|| Response::error(400, "Need ?trip= or ?from=&to="))?;
+            parse_time(value).ok_or_else(|| Response::error(400, &format!("Bad {} '{}'", name, value)))
+        };
+        match self.query.get("trip") {
+            Some(id) => id
+                .parse()
+                .map(TrackRange::Trip)
+                .map_err(|_| Response::error(400, &format!("Bad trip id '{}'", id))),
+            None => Ok(TrackRange::Between { from_utc: time("from")?, to_utc: time("to")? }),
+        }
+    }
+
+    fn limit(&self) -> Result<i64, Response> {
+        match self.query.get("limit") {
+            None => Ok(DEFAULT_LIMIT),
+            Some(v) => v
+                .parse()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| Response::error(400, &format!("Bad limit '{}'", v))),
+        }
+    }
+}
+
+/// `BufRead::read_line`, failing once a line runs into `MAX_REQUEST_BYTES`.
+fn read_line<R: BufRead>(reader: &mut std::io::Take<R>, line: &mut String) -> Result<usize> {
+    let read = reader.read_line(line)?;
+    if reader.limit() == 0 && !line.ends_with('\n') {
+        bail!("Request headers over {} bytes", MAX_REQUEST_BYTES);
+    }
+    Ok(read)
+}
+
+/// Decode `%XX` escapes. `+` stays a `+`: it's the offset sign of an unencoded RFC 3339 time.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[derive(Debug)]
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// `len` bytes from the file's current position
+    File { file: File, len: u64 },
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Body,
+}
+
+impl Response {
+    pub fn json(status: u16, value: serde_json::Value) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            headers: Vec::new(),
+            body: Body::Bytes(value.to_string().into_bytes()),
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        Self::json(status, json!({ "error": message }))
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn content_length(&self) -> u64 {
+        match &self.body {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File { len, .. } => *len,
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            206 => "Partial Content",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            416 => "Range Not Satisfiable",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+
+    /// Send the response; `include_body = false` for HEAD.
+    pub fn write_to(self, w: &mut impl Write, include_body: bool) -> std::io::Result<()> {
+        write!(
+            w,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.content_length()
+        )?;
+        for (name, value) in &self.headers {
+            write!(w, "{}: {}\r\n", name, value)?;
+        }
+        w.write_all(b"\r\n")?;
+        if include_body {
+            match self.body {
+                Body::Bytes(bytes) => w.write_all(&bytes)?,
+                Body::File { file, len } => {
+                    std::io::copy(&mut file.take(len), w)?;
+                }
+            }
+        }
+        w.flush()
+    }
+}
+
+/// Map a request onto a control command.
+pub fn route(req: &Request) -> Result<ControlCommand, Response> {
+    let segments: Vec<&str> = req.path.trim_matches('/').split('/').collect();
+    let method = req.method.as_str();
+
+    match (method, segments.as_slice()) {
+        ("GET", ["api", "status"]) => Ok(ControlCommand::Status),
+        ("GET", ["api", "audit"]) => Ok(ControlCommand::AuditLog { limit: req.limit()? }),
+        ("GET", ["api", "cameras", key, "events"]) => Ok(ControlCommand::RecentEvents {
+            camera_key: key.to_string(),
+            limit: req.limit()?,
+        }),
+        ("POST", ["api", "cameras", key, "events", kind]) => {
+            let kind = match *kind {
+                "gsensor" => EventKind::GSensor,
+                "motion" => EventKind::Motion,
+                "manual" => EventKind::Manual,
+                "object" => EventKind::Object,
+                "reverse" => EventKind::Reverse,
+                other => return Err(Response::error(400, &format!("Unknown event kind '{}'", other))),
+            };
+            Ok(ControlCommand::TriggerEvent { camera_key: key.to_string(), kind })
+        }
+        ("POST", ["api", "cameras", key, "sinks", sink_id, action @ ("enable" | "disable")]) => {
+            let sink_id = sink_id
+                .parse()
+                .map_err(|_| Response::error(400, &format!("Bad sink id '{}'", sink_id)))?;
+            Ok(ControlCommand::SetSinkEnabled {
+                camera_key: key.to_string(),
+                sink_id,
+                enabled: *action == "enable",
+            })
+        }
+        ("GET", ["api", "cameras", key, "playlist.m3u8"]) => {
+            let time = |name: &str| -> Result<i64, Response> {
+                let value = req.query.get(name).ok_or_else(|| Response::error(400, "Need ?from=&to="))?;
+                parse_time(value).ok_or_else(|| Response::error(400, &format!("Bad {} '{}'", name, value)))
+            };
+            Ok(ControlCommand::Playlist { camera_key: key.to_string(), from_utc: time("from")?, to_utc: time("to")? })
+        }
+        ("GET", ["api", "cameras", key, "rewind.m3u8"]) => {
+            let start = match (req.query.get("minutes"), req.query.get("from")) {
+                (Some(minutes), None) => minutes
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|&m| m > 0)
+                    .map(RewindStart::MinutesAgo)
+                    .ok_or_else(|| Response::error(400, &format!("Bad minutes '{}'", minutes)))?,
+                (None, Some(from)) => parse_time(from)
+                    .map(RewindStart::At)
+                    .ok_or_else(|| Response::error(400, &format!("Bad from '{}'", from)))?,
+                _ => return Err(Response::error(400, "Need ?minutes=")),
+            };
+            Ok(ControlCommand::Rewind { camera_key: key.to_string(), start })
+        }
+        ("POST", ["api", "cameras", key, "split"]) => Ok(ControlCommand::SplitSegments { camera_key: key.to_string() }),
+        ("POST", ["api", "cameras", key, "clip"]) => {
+            Ok(ControlCommand::SaveClip { camera_key: key.to_string(), kind: EventKind::Manual })
+        }
+        ("POST", ["api", "cameras", key, "privacy", state @ ("on" | "off")]) => {
+            Ok(ControlCommand::SetPrivacy { camera_key: key.to_string(), private: *state == "on" })
+        }
+        ("POST", ["api", "cameras", key, "stop"]) => Ok(ControlCommand::StopCamera { camera_key: key.to_string() }),
+        ("POST", ["api", "cameras", key, "start"]) => Ok(ControlCommand::StartCamera { camera_key: key.to_string() }),
+        ("POST", ["api", "standby", action @ ("enter" | "exit")]) => {
+            Ok(ControlCommand::SetStandby { standby: *action == "enter" })
+        }
+        ("GET", ["api", "rules"]) => Ok(ControlCommand::Rules),
+        ("POST", ["api", "rules", name, action @ ("arm" | "disarm")]) => {
+            Ok(ControlCommand::SetRuleArmed { name: name.to_string(), armed: *action == "arm" })
+        }
+        ("GET", ["api", "mode"]) => Ok(ControlCommand::Mode),
+        ("POST", ["api", "mode", name]) => Ok(ControlCommand::SetMode { name: name.to_string() }),
+        ("GET", ["api", "incidents"]) => Ok(ControlCommand::RecentIncidents { limit: req.limit()? }),
+        ("POST", ["api", "incidents", id, "bundle"]) => {
+            let incident_id = id
+                .parse()
+                .map_err(|_| Response::error(400, &format!("Bad incident id '{}'", id)))?;
+            Ok(ControlCommand::BundleIncident { incident_id })
+        }
+        ("GET", ["api", "track"]) => {
+            let format = match req.query.get("format") {
+                None => TrackFormat::Gpx,
+                Some(f) => TrackFormat::from_arg(f).ok_or_else(|| Response::error(400, &format!("Unknown format '{}'", f)))?,
+            };
+            Ok(ControlCommand::Track { range: req.track_range()?, format })
+        }
+        ("GET", ["api", "settings", key]) => Ok(ControlCommand::GetSetting { key: key.to_string() }),
+        ("POST", ["api", "settings", key]) => {
+            let value = req
+                .query
+                .get("value")
+                .ok_or_else(|| Response::error(400, "Missing ?value="))?;
+            Ok(ControlCommand::SetSetting { key: key.to_string(), value: value.clone() })
+        }
+        (_, ["api", ..]) if method != "GET" && method != "POST" => Err(Response::error(405, "Method not allowed")),
+        _ => Err(Response::error(404, "Not found")),
+    }
+}
+
+/// JSON body of a reply; also what the control socket sends back.
+pub fn reply_json(reply: ControlReply) -> serde_json::Value {
+    match reply {
+        ControlReply::Done => json!({ "ok": true }),
+        ControlReply::PowerState(state) => json!({ "power_state": state.as_str() }),
+        ControlReply::EventRaised(raised) => json!({ "raised": raised }),
+        ControlReply::Events(events) => json!(events),
+        ControlReply::Audit(entries) => json!(entries),
+        ControlReply::Setting { key, value } => json!({ "key": key, "value": value }),
+        ControlReply::Incidents(incidents) => json!(incidents),
+        ControlReply::Rules(rules) => json!(rules),
+        ControlReply::Mode(mode) => json!(mode),
+        ControlReply::Bundle(bundle) => json!(bundle),
+        ControlReply::Protecting(info) => json!(info),
+        ControlReply::Track { body, .. } | ControlReply::Playlist(body) => json!(body),
+        ControlReply::Status(status) => json!(status),
+    }
+}
+
+/// What every connection thread needs.
+pub struct HttpState {
+    pub auth: TokenAuth,
+    pub service: Arc<Mutex<CamService>>,
+    /// `<recording_root>`, served read-only under `/files/<camera key>/`
+    pub recording_root: PathBuf,
+    pub rate_limits: RateLimits,
+}
+
+/// Authenticate, authorize and run one request.
+pub fn handle_request(req: &Request, state: &HttpState) -> Response {
+    // Orchestrators and uptime monitors don't carry tokens; nothing sensitive in it
+    if req.path == "/healthz" && (req.method == "GET" || req.method == "HEAD") {
+        let report = state.service.lock().unwrap().health();
+        return Response::json(report.status.http_status(), json!(report));
+    }
+
+    let Some(caller) = state.auth.authenticate(req.header("authorization")) else {
+        return Response::error(401, "Missing or unknown API token").with_header("WWW-Authenticate", "Bearer");
+    };
+
+    // Recordings and exports: plain downloads, not control commands
+    if let Some(rel_path) = req.path.strip_prefix("/files/") {
+        if req.method != "GET" && req.method != "HEAD" {
+            return Response::error(405, "Method not allowed");
+        }
+        return files::serve_file(&state.recording_root, rel_path, req);
+    }
+
+    // Scraped every few seconds; not worth an audit row each time
+    if req.path == "/metrics" {
+        if req.method != "GET" && req.method != "HEAD" {
+            return Response::error(405, "Method not allowed");
+        }
+        return Response {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            headers: Vec::new(),
+            body: Body::Bytes(metrics::render().into_bytes()),
+        };
+    }
+
+    let command = match route(req) {
+        Ok(command) => command,
+        Err(response) => return response,
+    };
+
+    let service = state.service.lock().unwrap();
+    if caller.role < command.required_role() {
+        let reason = format!(
+            "'{}' needs the {} role, token '{}' is {}",
+            command.action(),
+            command.required_role().as_str(),
+            caller.name,
+            caller.role.as_str()
+        );
+        service.audit_denied(&caller.name, &command, &reason);
+        return Response::error(403, &reason);
+    }
+    match service.execute(&caller.name, command) {
+        Ok(ControlReply::Track { format, body }) => Response {
+            status: 200,
+            content_type: format.content_type(),
+            headers: Vec::new(),
+            body: Body::Bytes(body.into_bytes()),
+        },
+        Ok(ControlReply::Playlist(body)) => Response {
+            status: 200,
+            content_type: "application/vnd.apple.mpegurl",
+            headers: Vec::new(),
+            body: Body::Bytes(body.into_bytes()),
+        },
+        Ok(reply) => Response::json(200, reply_json(reply)),
+        Err(e) => Response::error(400, &format!("{:#}", e)),
+    }
+}
+
+fn handle_connection(stream: impl Read + Write, state: &HttpState) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+    let (response, include_body) = match Request::read_from(&mut reader) {
+        Ok(req) => {
+            trace!("HTTP {} {}", req.method, req.path);
+            (handle_request(&req, state), req.method != "HEAD")
+        }
+        Err(e) => (Response::error(400, &format!("{:#}", e)), true),
+    };
+    response.write_to(&mut state.rate_limits.writer(reader.get_mut()), include_body)?;
+    Ok(())
+}
+
+/// Plain TCP, or TLS on top of it when `[http.tls]` is set.
+fn serve_stream(stream: TcpStream, tls: Option<&Arc<tls::ServerConfig>>, state: &HttpState) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    match tls {
+        #[cfg(feature = "tls")]
+        Some(config) => {
+            let connection = rustls::ServerConnection::new(config.clone())?;
+            handle_connection(rustls::StreamOwned::new(connection, stream), state)
+        }
+        #[cfg(not(feature = "tls"))]
+        Some(_) => bail!("TLS support not compiled in"),
+        None => handle_connection(stream, state),
+    }
+}
+
+/// Bind `[http] bind` and serve the control API on a background thread.
+pub fn spawn_http_server(cfg: HttpConfig, service: Arc<Mutex<CamService>>) -> Result<JoinHandle<()>> {
+    let (main_dir, recording_root, bandwidth) = {
+        let service = service.lock().unwrap();
+        let app = &service.app_config;
+        (app.main_dir(), app.recording_root(), app.bandwidth.clone())
+    };
+    let tls = match &cfg.tls {
+        Some(tls_cfg) => Some(tls::server_config(tls_cfg, &main_dir)?),
+        None => None,
+    };
+    let listener = TcpListener::bind(&cfg.bind).with_context(|| format!("Failed to bind HTTP API on {}", cfg.bind))?;
+    let state = Arc::new(HttpState {
+        auth: TokenAuth::new(&cfg.auth),
+        service,
+        recording_root: PathBuf::from(recording_root),
+        rate_limits: RateLimits::from_config(&bandwidth),
+    });
+    if state.auth.is_open() {
+        warn!("HTTP API on {} has no [http.auth] tokens: anyone on the network is admin", cfg.bind);
+    }
+    info!(
+        "HTTP API listening on {}://{}",
+        if tls.is_some() { "https" } else { "http" },
+        cfg.bind
+    );
+
+    Ok(std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("HTTP accept failed: {}", e);
+                    continue;
+                }
+            };
+            let state = state.clone();
+            let tls = tls.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = serve_stream(stream, tls.as_ref(), &state) {
+                    trace!("HTTP connection error: {:#}", e);
+                }
+            });
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(raw: &str) -> Request {
+        Request::read_from(&mut raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parses_request_line_query_and_headers() {
+        let req = request("GET /api/audit?limit=5 HTTP/1.1\r\nHost: cam\r\nAuthorization: Bearer abc\r\n\r\n");
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.path, "/api/audit");
+        assert_eq!(req.query.get("limit").map(String::as_str), Some("5"));
+        assert_eq!(req.header("authorization"), Some("Bearer abc"));
+    }
+
+    #[test]
+    fn decodes_query_values_and_caps_the_request_size() {
+        let req = request(
+            "GET /api/track?from=2026-03-01T10%3A00%3A00%2B01%3A00&to=2026-03-01T12:00:00+01:00 HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(req.query.get("from").map(String::as_str), Some("2026-03-01T10:00:00+01:00"));
+        assert_eq!(req.query.get("to").map(String::as_str), Some("2026-03-01T12:00:00+01:00"));
+        assert_eq!(percent_decode("100%"), "100%");
+
+        let endless = format!("GET / HTTP/1.1\r\nX-Junk: {}", "a".repeat(MAX_REQUEST_BYTES as usize));
+        assert!(Request::read_from(&mut endless.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn routes_to_commands() {
+        assert_eq!(
+            route(&request("GET /api/audit HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::AuditLog { limit: DEFAULT_LIMIT }
+        );
+        assert_eq!(
+            route(&request("POST /api/cameras/front/sinks/2/disable HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::SetSinkEnabled { camera_key: "front".to_string(), sink_id: 2, enabled: false }
+        );
+        assert_eq!(
+            route(&request("POST /api/cameras/front/events/manual HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::TriggerEvent { camera_key: "front".to_string(), kind: EventKind::Manual }
+        );
+        assert_eq!(
+            route(&request("POST /api/rules/away/disarm HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::SetRuleArmed { name: "away".to_string(), armed: false }
+        );
+        assert_eq!(
+            route(&request("POST /api/mode/night HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::SetMode { name: "night".to_string() }
+        );
+        assert_eq!(
+            route(&request("POST /api/cameras/rear/events/reverse HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::TriggerEvent { camera_key: "rear".to_string(), kind: EventKind::Reverse }
+        );
+        assert_eq!(
+            route(&request("POST /api/settings/overlay.enabled?value=false HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::SetSetting { key: "overlay.enabled".to_string(), value: "false".to_string() }
+        );
+        assert_eq!(
+            route(&request("POST /api/cameras/front/split HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::SplitSegments { camera_key: "front".to_string() }
+        );
+        assert_eq!(
+            route(&request("POST /api/incidents/12/bundle HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::BundleIncident { incident_id: 12 }
+        );
+        assert_eq!(route(&request("POST /api/incidents/x/bundle HTTP/1.1\r\n\r\n")).unwrap_err().status, 400);
+        assert_eq!(
+            route(&request("GET /api/cameras/front/playlist.m3u8?from=100&to=160 HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::Playlist { camera_key: "front".to_string(), from_utc: 100, to_utc: 160 }
+        );
+        assert_eq!(route(&request("GET /api/cameras/front/playlist.m3u8?from=100 HTTP/1.1\r\n\r\n")).unwrap_err().status, 400);
+        assert_eq!(
+            route(&request("GET /api/cameras/front/rewind.m3u8?minutes=10 HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::Rewind { camera_key: "front".to_string(), start: RewindStart::MinutesAgo(10) }
+        );
+        assert_eq!(
+            route(&request("GET /api/cameras/front/rewind.m3u8?from=100 HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::Rewind { camera_key: "front".to_string(), start: RewindStart::At(100) }
+        );
+        assert_eq!(route(&request("GET /api/cameras/front/rewind.m3u8?minutes=0 HTTP/1.1\r\n\r\n")).unwrap_err().status, 400);
+        assert_eq!(
+            route(&request("GET /api/track?trip=3&format=geojson HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::Track { range: TrackRange::Trip(3), format: TrackFormat::GeoJson }
+        );
+        assert_eq!(
+            route(&request("GET /api/track?from=100&to=2026-03-01T14:00:00Z HTTP/1.1\r\n\r\n")).unwrap(),
+            ControlCommand::Track {
+                range: TrackRange::Between { from_utc: 100, to_utc: 1_772_373_600 },
+                format: TrackFormat::Gpx
+            }
+        );
+        assert_eq!(route(&request("GET /api/track?from=100 HTTP/1.1\r\n\r\n")).unwrap_err().status, 400);
+        assert_eq!(route(&request("GET /api/track?trip=3&format=kml HTTP/1.1\r\n\r\n")).unwrap_err().status, 400);
+        assert_eq!(route(&request("POST /api/settings/overlay.enabled HTTP/1.1\r\n\r\n")).unwrap_err().status, 400);
+        assert_eq!(route(&request("GET /api/cameras/front/events?limit=x HTTP/1.1\r\n\r\n")).unwrap_err().status, 400);
+        assert_eq!(route(&request("GET /nope HTTP/1.1\r\n\r\n")).unwrap_err().status, 404);
+        assert_eq!(route(&request("DELETE /api/audit HTTP/1.1\r\n\r\n")).unwrap_err().status, 405);
+    }
+}
@@ -0,0 +1,115 @@
+//! HTTPS for the control API (`[http.tls]`, needs the `tls` feature).
+//!
+//! Certificates come from PEM files, or with `self_signed = true` one is generated
+//! on first start (under `<main_dir>/tls/` unless paths are given) and reused after.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::HttpTlsConfig;
+
+#[cfg(feature = "tls")]
+pub type ServerConfig = rustls::ServerConfig;
+#[cfg(not(feature = "tls"))]
+pub type ServerConfig = ();
+
+/// Where the certificate and key live (or will be generated).
+pub fn cert_and_key_paths(cfg: &HttpTlsConfig, main_dir: &str) -> (PathBuf, PathBuf) {
+    match (&cfg.cert_path, &cfg.key_path) {
+        (Some(cert), Some(key)) => (PathBuf::from(cert), PathBuf::from(key)),
+        _ => {
+            let dir = Path::new(main_dir).join("tls");
+            (dir.join("cert.pem"), dir.join("key.pem"))
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+pub fn server_config(cfg: &HttpTlsConfig, main_dir: &str) -> Result<Arc<ServerConfig>> {
+    use anyhow::Context;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let (cert_path, key_path) = cert_and_key_paths(cfg, main_dir);
+    if cfg.self_signed && !(cert_path.exists() && key_path.exists()) {
+        write_self_signed(&cfg.names, &cert_path, &key_path)?;
+    }
+
+    let certs = CertificateDer::pem_file_iter(&cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read TLS certificate {}", cert_path.display()))?;
+    let key = PrivateKeyDer::from_pem_file(&key_path)
+        .with_context(|| format!("Failed to read TLS key {}", key_path.display()))?;
+
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("TLS certificate and key don't match")?;
+    Ok(Arc::new(config))
+}
+
+#[cfg(feature = "tls")]
+fn write_self_signed(names: &[String], cert_path: &Path, key_path: &Path) -> Result<()> {
+    use anyhow::Context;
+    use std::fs;
+
+    let generated = rcgen::generate_simple_self_signed(names.to_vec())?;
+    for path in [cert_path, key_path] {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    fs::write(cert_path, generated.cert.pem())
+        .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+    fs::write(key_path, generated.key_pair.serialize_pem())
+        .with_context(|| format!("Failed to write {}", key_path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(key_path, fs::Permissions::from_mode(0o600))?;
+    }
+    tracing::info!("Generated self-signed TLS certificate {} for {:?}", cert_path.display(), names);
+    Ok(())
+}
+
+#[cfg(not(feature = "tls"))]
+pub fn server_config(_cfg: &HttpTlsConfig, _main_dir: &str) -> Result<Arc<ServerConfig>> {
+    anyhow::bail!("[http.tls] is set but dashcam_rs was built without the `tls` feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tls(self_signed: bool) -> HttpTlsConfig {
+        HttpTlsConfig {
+            cert_path: None,
+            key_path: None,
+            self_signed,
+            names: vec!["localhost".to_string()],
+        }
+    }
+
+    #[test]
+    fn self_signed_defaults_under_main_dir() {
+        let (cert, key) = cert_and_key_paths(&tls(true), "/var/lib/dashcam");
+        assert_eq!(cert, PathBuf::from("/var/lib/dashcam/tls/cert.pem"));
+        assert_eq!(key, PathBuf::from("/var/lib/dashcam/tls/key.pem"));
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn self_signed_is_generated_once_and_reused() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let main_dir = dir.path().to_str().unwrap();
+
+        server_config(&tls(true), main_dir).unwrap();
+        let (cert, _) = cert_and_key_paths(&tls(true), main_dir);
+        let first = std::fs::read(&cert).unwrap();
+
+        server_config(&tls(true), main_dir).unwrap();
+        assert_eq!(std::fs::read(&cert).unwrap(), first);
+    }
+}
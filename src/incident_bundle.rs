@@ -0,0 +1,278 @@
+//! Incident bundles: one zip per group incident (see `clips`) to hand to the
+//! police or an insurer. It holds, under `incident_<id>/`:
+//!
+//! - `<camera>.mp4` for every clip of the incident
+//! - `track.gpx` and `track.geojson`, the GPS fixes during the clips (if any)
+//! - `timeline.json`, the incident, its clips and every event of its cameras
+//!   during the clips
+//! - `SHA256SUMS` over all of the above, checkable with `sha256sum -c`
+//!
+//! The zip only stores (video doesn't compress), and clips are streamed into
+//! it without being held in memory.
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use sha2::{Digest, Sha256};
+use serde_json::json;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::db::db::{DashcamDb, EventRow, GpsPoint, IncidentClipRow, IncidentRow};
+use crate::track::{to_geojson, to_gpx};
+
+/// An incident with everything that goes into its bundle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncidentRecord {
+    pub incident: IncidentRow,
+    pub clips: Vec<IncidentClipRow>,
+    /// (camera key, event), oldest first
+    pub events: Vec<(String, EventRow)>,
+    pub track: Vec<GpsPoint>,
+}
+
+/// What `write_bundle` produced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleSummary {
+    pub path: PathBuf,
+    /// Entries in the zip
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// `incident_id` with its clips, and the events and GPS fixes during them.
+/// `None` if there is no such incident.
+pub fn load_incident(db: &DashcamDb, incident_id: i64) -> rusqlite::Result<Option<IncidentRecord>> {
+    let Some(incident) = db.get_incident(incident_id)? else {
+        return Ok(None);
+    };
+    let clips = db.get_incident_clips(incident_id)?;
+    let mut record = IncidentRecord { incident, clips, events: Vec::new(), track: Vec::new() };
+    if let Some((from_utc, to_utc)) = record.window() {
+        for clip in &record.clips {
+            let camera_id = db.get_camera_id_by_key(&clip.camera_key)?;
+            for event in db.get_events_between(camera_id, from_utc, to_utc + 1)? {
+                record.events.push((clip.camera_key.clone(), event));
+            }
+        }
+        record.events.sort_by_key(|(_, e)| (e.at_utc, e.id));
+        record.track = db.gps_points_between(from_utc, to_utc + 1)?;
+    }
+    Ok(Some(record))
+}
+
+impl IncidentRecord {
+    /// Span of the incident's clips, `None` until one is finished.
+    pub fn window(&self) -> Option<(i64, i64)> {
+        let from = self.clips.iter().map(|c| c.from_utc).min()?;
+        let to = self.clips.iter().map(|c| c.to_utc).max()?;
+        Some((from, to))
+    }
+
+    /// `<incident dir>.zip`, next to the incident folder
+    pub fn default_output(&self) -> PathBuf {
+        PathBuf::from(format!("{}.zip", self.incident.dir.trim_end_matches('/')))
+    }
+
+    pub fn timeline(&self) -> serde_json::Value {
+        let (from_utc, to_utc) = self.window().unzip();
+        json!({
+            "incident": {
+                "id": self.incident.id,
+                "group": self.incident.group_name,
+                "camera": self.incident.camera_key,
+                "kind": self.incident.kind,
+                "at": local_text(self.incident.at_utc),
+                "at_utc": self.incident.at_utc,
+            },
+            "from_utc": from_utc,
+            "to_utc": to_utc,
+            "clips": self.clips.iter().map(|c| json!({
+                "camera": c.camera_key,
+                "file": clip_name(c),
+                "from": local_text(c.from_utc),
+                "to": local_text(c.to_utc),
+            })).collect::<Vec<_>>(),
+            "events": self.events.iter().map(|(camera, e)| json!({
+                "camera": camera,
+                "kind": e.kind,
+                "label": e.label,
+                "score": e.score,
+                "at": local_text(e.at_utc),
+                "at_utc": e.at_utc,
+            })).collect::<Vec<_>>(),
+            "gps_points": self.track.len(),
+            "created_at": Local::now().to_rfc3339(),
+        })
+    }
+}
+
+fn local_text(at_utc: i64) -> String {
+    DateTime::<Utc>::from_timestamp(at_utc, 0)
+        .map(|t| t.with_timezone(&Local).to_rfc3339())
+        .unwrap_or_default()
+}
+
+fn clip_name(clip: &IncidentClipRow) -> String {
+    format!("{}.mp4", clip.camera_key)
+}
+
+/// Zip `record` into `output` (through a `.part` file).
+pub fn write_bundle(record: &IncidentRecord, output: &Path) -> Result<BundleSummary> {
+    let id = record.incident.id;
+    if record.clips.is_empty() {
+        bail!("Incident {} has no finished clips yet", id);
+    }
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let partial = output.with_extension("zip.part");
+    let file = File::create(&partial).with_context(|| format!("Can't create {}", partial.display()))?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    let options = entry_options(Local::now());
+    let folder = format!("incident_{}", id);
+    let (mut sums, mut files) = (String::new(), 0);
+
+    for clip in &record.clips {
+        let clip_file = File::open(&clip.path).with_context(|| format!("Clip {} of incident {} is gone", clip.path, id))?;
+        let sha = add(&mut zip, &format!("{}/{}", folder, clip_name(clip)), clip_file, options)?;
+        sums += &format!("{}  {}\n", hex(&sha), clip_name(clip));
+        files += 1;
+    }
+    let mut generated = vec![("timeline.json", serde_json::to_vec_pretty(&record.timeline())?)];
+    if !record.track.is_empty() {
+        let name = format!("incident {} ({})", id, record.incident.group_name);
+        generated.push(("track.gpx", to_gpx(&name, &record.track).into_bytes()));
+        generated.push(("track.geojson", serde_json::to_vec_pretty(&to_geojson(&name, &record.track))?));
+    }
+    for (name, data) in &generated {
+        let sha = add(&mut zip, &format!("{}/{}", folder, name), &data[..], options)?;
+        sums += &format!("{}  {}\n", hex(&sha), name);
+        files += 1;
+    }
+    add(&mut zip, &format!("{}/SHA256SUMS", folder), sums.as_bytes(), options)?;
+    files += 1;
+
+    zip.finish()?.flush()?;
+    let bytes = fs::metadata(&partial)?.len();
+    fs::rename(&partial, output)?;
+    info!("Incident {} bundle: {} files, {} bytes -> {}", id, files, bytes, output.display());
+    Ok(BundleSummary { path: output.to_path_buf(), files, bytes })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Stored, not deflated: video doesn't compress. No zip64, so entries and the
+/// whole archive stay under 4 GiB.
+fn entry_options(mtime: DateTime<Local>) -> SimpleFileOptions {
+    let mtime = zip::DateTime::from_date_and_time(
+        mtime.year() as u16,
+        mtime.month() as u8,
+        mtime.day() as u8,
+        mtime.hour() as u8,
+        mtime.minute() as u8,
+        mtime.second() as u8,
+    )
+    .unwrap_or_default();
+    SimpleFileOptions::default().compression_method(CompressionMethod::Stored).last_modified_time(mtime)
+}
+
+/// Store `data` as `name`; returns its SHA-256.
+fn add<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    name: &str,
+    mut data: impl Read,
+    options: SimpleFileOptions,
+) -> Result<[u8; 32]> {
+    zip.start_file(name, options)?;
+    let mut sha = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = data.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        sha.update(&buf[..n]);
+        zip.write_all(&buf[..n]).with_context(|| format!("Failed to add {}", name))?;
+    }
+    Ok(sha.finalize().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(dir: &Path, clips: &[(&str, &Path)]) -> IncidentRecord {
+        IncidentRecord {
+            incident: IncidentRow {
+                id: 3,
+                group_name: "car".into(),
+                camera_key: Some("front".into()),
+                kind: "gsensor".into(),
+                at_utc: 1_000,
+                dir: dir.join("14-00-05_gsensor_car").to_string_lossy().to_string(),
+            },
+            clips: clips
+                .iter()
+                .map(|(camera_key, path)| IncidentClipRow {
+                    camera_key: camera_key.to_string(),
+                    path: path.to_string_lossy().to_string(),
+                    from_utc: 980,
+                    to_utc: 1_010,
+                })
+                .collect(),
+            events: Vec::new(),
+            track: Vec::new(),
+        }
+    }
+
+    fn entry(archive: &mut zip::ZipArchive<File>, name: &str) -> String {
+        let mut text = String::new();
+        archive.by_name(name).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn bundles_clips_timeline_and_manifest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let clip_path = tmp.path().join("front.mp4");
+        fs::write(&clip_path, b"not really an mp4").unwrap();
+        let empty = record(tmp.path(), &[]);
+        assert!(write_bundle(&empty, &empty.default_output()).is_err(), "nothing to bundle yet");
+
+        let record = record(tmp.path(), &[("front", &clip_path)]);
+        let summary = write_bundle(&record, &record.default_output()).unwrap();
+        assert_eq!(summary.path, tmp.path().join("14-00-05_gsensor_car.zip"));
+        assert_eq!(summary.files, 3, "clip, timeline and SHA256SUMS; no track without GPS fixes");
+        assert_eq!(fs::metadata(&summary.path).unwrap().len(), summary.bytes);
+
+        let mut archive = zip::ZipArchive::new(File::open(&summary.path).unwrap()).unwrap();
+        assert_eq!(archive.len(), 3);
+        assert_eq!(entry(&mut archive, "incident_3/front.mp4"), "not really an mp4");
+        let timeline: serde_json::Value = serde_json::from_str(&entry(&mut archive, "incident_3/timeline.json")).unwrap();
+        assert_eq!(timeline["clips"][0]["file"], "front.mp4");
+        assert_eq!(archive.by_name("incident_3/front.mp4").unwrap().compression(), CompressionMethod::Stored);
+    }
+
+    #[test]
+    fn manifest_is_checkable_with_sha256sum() {
+        let tmp = tempfile::tempdir().unwrap();
+        let clip_path = tmp.path().join("rear.mp4");
+        fs::write(&clip_path, b"abc").unwrap();
+        let record = record(tmp.path(), &[("rear", &clip_path)]);
+        let summary = write_bundle(&record, &record.default_output()).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(&summary.path).unwrap()).unwrap();
+        let sums = entry(&mut archive, "incident_3/SHA256SUMS");
+        // FIPS 180-2 test vector
+        assert!(sums.starts_with("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  rear.mp4\n"));
+        let timeline = entry(&mut archive, "incident_3/timeline.json");
+        let line = format!("{}  timeline.json\n", hex(&Sha256::digest(timeline.as_bytes())));
+        assert!(sums.ends_with(&line), "{}", sums);
+    }
+}
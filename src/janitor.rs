@@ -0,0 +1,253 @@
+//! Ring janitor (`[janitor]` in config.toml).
+//!
+//! The `dashcamts` ring writes `<ring>/<index / 1000>/output_<index>.ts` (or
+//! `g<generation>_s<index>.ts`) and wraps at `max_segments`. Shrink
+//! `max_segments` (or switch profiles) and the files past the new end are never
+//! overwritten again; they just eat the card.
+//! On every `interval_sec` the janitor lists each ring, keeps what the ring can
+//! still reach and whatever the `segments` table references (imported
+//! footage), and removes the rest along with subdirectories left empty; rows
+//! the ring catalogued for removed files become `janitor` tombstones. With
+//! `dry_run` it only logs what it would remove.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::{AppConfig, SinkConfig};
+use crate::db::db::DeletionReason;
+use crate::db::db_worker::DBMessage;
+use crate::pipeline_sinks::ts_file_pipeline_sink::ring_slot;
+use crate::worker::Worker;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct JanitorConfig {
+    /// Time between sweeps; the first one runs at start-up
+    pub interval_sec: u64,
+    /// Only report what would be removed
+    pub dry_run: bool,
+}
+
+impl Default for JanitorConfig {
+    fn default() -> Self {
+        Self { interval_sec: 3600, dry_run: false }
+    }
+}
+
+/// One camera's ring for the worker.
+#[derive(Debug, Clone)]
+pub struct JanitorCamera {
+    pub key: String,
+    pub camera_id: i64,
+    /// Where the camera's `dashcamts` ring lives
+    pub ring_dir: PathBuf,
+    pub max_segments: i64,
+}
+
+/// The enabled cameras with a `dashcamts` ring; `camera_id` maps a key to its DB id.
+pub fn ring_cameras(cfg: &AppConfig, camera_id: impl Fn(&str) -> Option<i64>) -> Vec<JanitorCamera> {
+    let recording_root = PathBuf::from(cfg.recording_root());
+    let default_max = cfg.profile().segments_to_keep;
+    cfg.cameras
+        .iter()
+        .filter(|c| c.enabled)
+        .filter_map(|c| {
+            let max_segments = c
+                .sinks
+                .iter()
+                .filter_map(|s| match s {
+                    SinkConfig::DashcamTs { max_segments, .. } => Some(max_segments.unwrap_or(default_max)),
+                    _ => None,
+                })
+                .max()?;
+            Some(JanitorCamera {
+                key: c.key.clone(),
+                camera_id: camera_id(&c.key)?,
+                ring_dir: recording_root.join(&c.key),
+                max_segments,
+            })
+        })
+        .collect()
+}
+
+/// What a sweep found in one ring.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JanitorReport {
+    pub orphans: Vec<PathBuf>,
+    pub bytes: u64,
+    /// Subdirectories past the end of the ring that hold nothing but orphans
+    pub empty_dirs: Vec<PathBuf>,
+}
+
+/// Files in the numbered subdirs of `ring_dir` the ring won't write again
+/// (index at or past `max_segments`, or in the wrong subdir) and `referenced`
+/// (absolute paths) doesn't list. Anything not named like a ring segment is left alone.
+pub fn find_orphans(ring_dir: &Path, max_segments: i64, referenced: &HashSet<PathBuf>) -> Result<JanitorReport> {
+    let mut report = JanitorReport::default();
+    let last_subdir = (max_segments - 1).max(0) / 1000;
+    let mut subdirs: Vec<(i64, PathBuf)> = fs::read_dir(ring_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|entry| Some((entry.file_name().to_str()?.parse().ok()?, entry.path())))
+        .collect();
+    subdirs.sort();
+
+    for (subdir, dir) in subdirs {
+        let mut kept = 0;
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let index = entry.file_name().to_str().and_then(ring_slot);
+            let reachable = index.is_some_and(|i| i < max_segments && i / 1000 == subdir);
+            if index.is_none() || reachable || referenced.contains(&path) || !entry.file_type()?.is_file() {
+                kept += 1;
+                continue;
+            }
+            report.bytes += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            report.orphans.push(path);
+        }
+        // subdirs the ring still writes to are recreated on demand, but not atomically with the write
+        if kept == 0 && subdir > last_subdir {
+            report.empty_dirs.push(dir);
+        }
+    }
+    report.orphans.sort();
+    Ok(report)
+}
+
+/// Remove what `find_orphans` reported. Returns how many files went.
+pub fn sweep(report: &JanitorReport) -> usize {
+    let mut removed = 0;
+    for path in &report.orphans {
+        match fs::remove_file(path) {
+            Ok(()) => removed += 1,
+            Err(e) => warn!("Janitor couldn't remove {}: {}", path.display(), e),
+        }
+    }
+    for dir in &report.empty_dirs {
+        if let Err(e) = fs::remove_dir(dir) {
+            warn!("Janitor couldn't remove {}: {}", dir.display(), e);
+        }
+    }
+    removed
+}
+
+/// Orphans `sweep` got rid of, relative to the recording root. The ring
+/// catalogued them when it wrote them; their rows become tombstones.
+pub fn swept_rel_paths(report: &JanitorReport, recording_root: &Path) -> Vec<String> {
+    report
+        .orphans
+        .iter()
+        .filter(|path| !path.exists())
+        .filter_map(|path| path.strip_prefix(recording_root).ok())
+        .map(|rel| rel.to_string_lossy().to_string())
+        .collect()
+}
+
+/// The camera's catalogued files as absolute paths, `None` if the DB couldn't say.
+fn referenced_paths(db_sender: &Sender<DBMessage>, camera_id: i64, recording_root: &Path) -> Option<HashSet<PathBuf>> {
+    let (tx, rx) = mpsc::channel();
+    db_sender.send(DBMessage::GetSegmentPaths { camera_id, reply: tx }).ok()?;
+    let paths = rx.recv().ok()??;
+    Some(paths.iter().map(|p| recording_root.join(p)).collect())
+}
+
+/// Janitor thread, sweeping every `interval_sec` from start-up on.
+pub fn spawn_janitor(
+    cfg: &JanitorConfig,
+    cameras: Vec<JanitorCamera>,
+    recording_root: PathBuf,
+    db_sender: Arc<Sender<DBMessage>>,
+) -> Worker {
+    let cfg = cfg.clone();
+    Worker::spawn("janitor", Duration::from_secs(cfg.interval_sec), move |_| {
+        for cam in &cameras {
+            // without the catalogue imported footage would look orphaned
+            let Some(referenced) = referenced_paths(&db_sender, cam.camera_id, &recording_root) else {
+                warn!("Janitor skipping camera '{}': couldn't read its segments from the DB", cam.key);
+                continue;
+            };
+            let report = match find_orphans(&cam.ring_dir, cam.max_segments, &referenced) {
+                Ok(report) => report,
+                Err(e) => {
+                    warn!("Janitor couldn't list {}: {:#}", cam.ring_dir.display(), e);
+                    continue;
+                }
+            };
+            if report.orphans.is_empty() && report.empty_dirs.is_empty() {
+                continue;
+            }
+            if cfg.dry_run {
+                info!(
+                    "Janitor (dry run) would remove {} files ({} bytes) and {} dirs from camera '{}'",
+                    report.orphans.len(), report.bytes, report.empty_dirs.len(), cam.key
+                );
+                for path in &report.orphans {
+                    info!("  {}", path.display());
+                }
+            } else {
+                let removed = sweep(&report);
+                info!(
+                    "Janitor removed {} orphaned files ({} bytes) from camera '{}'",
+                    removed, report.bytes, cam.key
+                );
+                let _ = db_sender.send(DBMessage::ForgetSegments {
+                    camera_id: cam.camera_id,
+                    rel_paths: swept_rel_paths(&report, &recording_root),
+                    reason: DeletionReason::Janitor,
+                });
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_files_past_the_end_of_a_shrunk_ring() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let ring = tmp.path();
+        let touch = |rel: &str| {
+            let path = ring.join(rel);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, b"ts").unwrap();
+            path
+        };
+        touch("0/output_5.ts");
+        let past_end = touch("1/output_1500.ts");
+        let imported = touch("1/output_1501.ts");
+        let old_size = touch("2/output_2000.ts");
+        touch("2/notes.txt");
+        let gone_dir = touch("3/output_3000.ts").parent().unwrap().to_path_buf();
+        let misplaced = touch("0/output_1200.ts");
+        let generation_named = touch("1/g2_s1600.ts");
+
+        // shrunk from 4000 to 1200 segments
+        let referenced = HashSet::from([imported]);
+        let report = find_orphans(ring, 1200, &referenced).unwrap();
+        let mut expected = vec![misplaced, past_end, generation_named, old_size, gone_dir.join("output_3000.ts")];
+        expected.sort();
+        assert_eq!(report.orphans, expected);
+        assert_eq!(report.bytes, 10);
+        assert_eq!(report.empty_dirs, vec![gone_dir.clone()]);
+
+        assert_eq!(sweep(&report), 5);
+        assert_eq!(
+            swept_rel_paths(&report, ring),
+            vec!["0/output_1200.ts", "1/g2_s1600.ts", "1/output_1500.ts", "2/output_2000.ts", "3/output_3000.ts"]
+        );
+        assert!(ring.join("0/output_5.ts").exists());
+        assert!(ring.join("2/notes.txt").exists());
+        assert!(!gone_dir.exists());
+        assert_eq!(find_orphans(ring, 1200, &referenced).unwrap(), JanitorReport::default());
+    }
+}
@@ -0,0 +1,136 @@
+//! `dashcam_rs import-legacy <dir> <camera key>`: register footage from the old
+//! single-camera layout (`output_N.ts`, optionally in `<N / 1000>/` subdirs, with
+//! per-file `.m3u8` playlists) so it shows up in the new DB.
+//!
+//! The old recorder kept no per-file times, so each file is assumed to end at its
+//! mtime and last one segment duration. Files stay where they are; they are listed
+//! under `HISTORICAL_SINK_ID`, a read-only sink nothing records into.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+use crate::db::db::{DashcamDb, SegmentRecord};
+use crate::pipeline_sinks::ts_file_pipeline_sink::ring_slot;
+
+/// Sink id for imported footage. Negative so it can't clash with configured sinks.
+pub const HISTORICAL_SINK_ID: i64 = -1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegacySegment {
+    pub path: PathBuf,
+    /// N from `output_N.ts` / `g<generation>_sN.ts`
+    pub index: i64,
+    pub modified: SystemTime,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub found: usize,
+    pub imported: usize,
+    /// Already listed by an earlier import
+    pub skipped: usize,
+}
+
+/// Ring files (`output_N.ts`, or `g<generation>_sN.ts` from a ring with
+/// `generation_names`) in `dir` and its numeric subdirectories, oldest first.
+/// Playlists are ignored; they only point at the `.ts` files.
+pub fn scan_legacy_dir(dir: &Path) -> Result<Vec<LegacySegment>> {
+    let subdir_regex = Regex::new(r"^\d+$")?;
+
+    let mut dirs = vec![dir.to_path_buf()];
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && subdir_regex.is_match(&entry.file_name().to_string_lossy()) {
+            dirs.push(entry.path());
+        }
+    }
+
+    let mut segments = Vec::new();
+    for dir in dirs {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(index) = ring_slot(&name) else {
+                continue;
+            };
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            segments.push(LegacySegment {
+                path: entry.path(),
+                index,
+                modified: metadata.modified()?,
+                bytes: metadata.len(),
+            });
+        }
+    }
+    segments.sort_by_key(|s| (s.modified, s.index));
+    Ok(segments)
+}
+
+fn unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Import everything under `dir` for `camera_key`. Safe to re-run: files already
+/// listed are skipped.
+pub fn import_legacy(
+    db: &DashcamDb,
+    camera_key: &str,
+    dir: &Path,
+    recording_root: &Path,
+    segment_duration_sec: u64,
+) -> Result<ImportSummary> {
+    let camera_id = db
+        .get_camera_id_by_key(camera_key)
+        .with_context(|| format!("Camera '{}' is not in the DB (is it in config.toml?)", camera_key))?;
+    let segments = scan_legacy_dir(dir)?;
+    let mut summary = ImportSummary { found: segments.len(), ..Default::default() };
+
+    let tx = db.conn.unchecked_transaction()?;
+    db.register_historical_sink(camera_id, HISTORICAL_SINK_ID, &dir.to_string_lossy())?;
+
+    let mut previous_end = i64::MIN;
+    for (absolute_index, segment) in segments.iter().enumerate() {
+        let end_utc = unix_secs(segment.modified);
+        let start_utc = (end_utc - segment_duration_sec as i64).max(previous_end).min(end_utc);
+        previous_end = end_utc;
+
+        let rel_path = segment.path.strip_prefix(recording_root).unwrap_or(&segment.path);
+        let inserted = db.insert_segment(&SegmentRecord {
+            camera_id,
+            sink_id: HISTORICAL_SINK_ID,
+            segment_index: segment.index,
+            segment_gen: 0,
+            absolute_index: absolute_index as i64,
+            start_utc,
+            end_utc,
+            rel_path: rel_path.to_string_lossy().to_string(),
+            disk: None,
+            stream: Default::default(),
+            bytes: Some(segment.bytes as i64),
+            restarted: false,
+        })?;
+        if inserted {
+            summary.imported += 1;
+        } else {
+            summary.skipped += 1;
+        }
+    }
+    tx.commit()?;
+
+    info!(
+        "Imported {} legacy segments from {} into camera '{}' ({} already known)",
+        summary.imported,
+        dir.display(),
+        camera_key,
+        summary.skipped
+    );
+    Ok(summary)
+}
@@ -1,9 +1,60 @@
 pub mod constants;
+pub mod backfill;
 pub mod config;
+pub mod profiles;
 pub mod log;
+pub mod crash;
+pub mod clips;
+pub mod events;
+pub mod export;
+pub mod gps;
+pub mod gps_overlay;
+pub mod gsensor;
+pub mod health;
+pub mod incident_bundle;
+pub mod janitor;
+pub mod live;
+pub mod modes;
+pub mod motion;
+pub mod obd;
+pub mod playlist;
+pub mod protect;
+pub mod detection;
+pub mod roi;
+pub mod rollup;
+pub mod rules;
+pub mod self_test;
+pub mod simulation;
+pub mod smart;
+pub mod soak;
+pub mod speed_events;
+pub mod snapshot;
+pub mod standby;
+pub mod storage_wear;
+pub mod start_retry;
+pub mod storage_manager;
+pub mod tiering;
+pub mod time_sync;
+pub mod track;
+pub mod usb_sync;
+pub mod validation;
+pub mod worker;
 
+pub mod anonymize;
+pub mod bandwidth;
 pub mod utils;
+pub mod legacy_import;
 pub mod cam_service;
+pub mod control;
+pub mod control_socket;
+pub mod http;
+pub mod mdns;
+pub mod metrics;
+pub mod gpio;
+pub mod mqtt;
+pub mod power_loss;
+pub mod privacy;
+pub mod upload;
 pub mod recording_pipeline;
 pub mod recording_pipeline_factory;
 
@@ -0,0 +1,152 @@
+//! Which camera viewers should show full screen (`[live]` in config.toml).
+//!
+//! The primary stream is `primary` until one of the `switch_on` events (by
+//! default `reverse`) comes in, then `reverse_camera` until the gear is out of
+//! reverse: an event labelled "off", or `hold_sec` without a new event when the
+//! source only reports "in reverse" repeatedly. Clients read the current choice
+//! from `live` in `/api/status`.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::events::{CameraEvent, EventKind};
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LiveConfig {
+    /// Camera shown normally
+    pub primary: String,
+    /// Camera shown while reversing
+    pub reverse_camera: String,
+    /// Event kinds that switch to `reverse_camera`
+    #[serde(default = "default_switch_on")]
+    pub switch_on: Vec<EventKind>,
+    /// Back to `primary` this long after the last event unless an "off" came first
+    #[serde(default = "default_hold_sec")]
+    pub hold_sec: u64,
+}
+
+fn default_switch_on() -> Vec<EventKind> {
+    vec![EventKind::Reverse]
+}
+
+fn default_hold_sec() -> u64 {
+    3
+}
+
+impl LiveConfig {
+    pub fn is_valid(&self, camera_keys: &[&str]) -> bool {
+        camera_keys.contains(&self.primary.as_str())
+            && camera_keys.contains(&self.reverse_camera.as_str())
+            && !self.switch_on.is_empty()
+    }
+}
+
+/// The advertised primary stream.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LiveStatus {
+    pub camera: String,
+    /// "reverse" while switched, "default" otherwise
+    pub reason: &'static str,
+}
+
+/// Shared switch state; clones see the same state. Does nothing without `[live]`.
+#[derive(Debug, Clone, Default)]
+pub struct LiveView {
+    cfg: Option<LiveConfig>,
+    /// Some while switched: until when, or `None` until an "off" event
+    switched: Arc<Mutex<Option<Option<Instant>>>>,
+}
+
+impl LiveView {
+    pub fn new(cfg: Option<&LiveConfig>) -> Self {
+        Self { cfg: cfg.cloned(), switched: Arc::default() }
+    }
+
+    pub fn on_event(&self, event: &CameraEvent) {
+        self.on_event_at(event, Instant::now());
+    }
+
+    pub fn on_event_at(&self, event: &CameraEvent, now: Instant) {
+        let Some(cfg) = &self.cfg else {
+            return;
+        };
+        if !cfg.switch_on.contains(&event.kind) {
+            return;
+        }
+        let mut switched = self.switched.lock().unwrap();
+        let was_switched = switched.is_some_and(|until| until.is_none_or(|t| now < t));
+        *switched = match event.label.as_deref() {
+            Some("off") => None,
+            Some("on") => Some(None),
+            _ => Some(Some(now + Duration::from_secs(cfg.hold_sec))),
+        };
+        if was_switched != switched.is_some() {
+            let camera = if switched.is_some() { &cfg.reverse_camera } else { &cfg.primary };
+            info!("Live view switched to '{}' on {} event", camera, event.kind);
+        }
+    }
+
+    pub fn status(&self) -> Option<LiveStatus> {
+        self.status_at(Instant::now())
+    }
+
+    pub fn status_at(&self, now: Instant) -> Option<LiveStatus> {
+        let cfg = self.cfg.as_ref()?;
+        let reversing = self
+            .switched
+            .lock()
+            .unwrap()
+            .is_some_and(|until| until.is_none_or(|t| now < t));
+        Some(if reversing {
+            LiveStatus { camera: cfg.reverse_camera.clone(), reason: "reverse" }
+        } else {
+            LiveStatus { camera: cfg.primary.clone(), reason: "default" }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reverse(label: Option<&str>) -> CameraEvent {
+        CameraEvent { label: label.map(str::to_string), ..CameraEvent::now(EventKind::Reverse) }
+    }
+
+    #[test]
+    fn reverse_switches_to_the_rear_camera_and_back() {
+        let cfg = LiveConfig {
+            primary: "front".into(),
+            reverse_camera: "rear".into(),
+            switch_on: default_switch_on(),
+            hold_sec: 3,
+        };
+        assert!(cfg.is_valid(&["front", "rear"]));
+        assert!(!cfg.is_valid(&["front"]));
+
+        let live = LiveView::new(Some(&cfg));
+        let t0 = Instant::now();
+        let at = |s| t0 + Duration::from_secs(s);
+        assert_eq!(live.status_at(t0).unwrap().camera, "front");
+
+        live.on_event_at(&CameraEvent::now(EventKind::GSensor), t0);
+        assert_eq!(live.status_at(t0).unwrap().reason, "default");
+
+        // repeated "in reverse" pulses keep it switched, then it times out
+        live.on_event_at(&reverse(None), t0);
+        live.on_event_at(&reverse(None), at(2));
+        assert_eq!(live.status_at(at(4)).unwrap(), LiveStatus { camera: "rear".into(), reason: "reverse" });
+        assert_eq!(live.status_at(at(5)).unwrap().camera, "front");
+
+        // on/off from a gear switch: no timeout
+        live.on_event_at(&reverse(Some("on")), at(10));
+        assert_eq!(live.status_at(at(100)).unwrap().camera, "rear");
+        live.on_event_at(&reverse(Some("off")), at(100));
+        assert_eq!(live.status_at(at(100)).unwrap().camera, "front");
+
+        assert_eq!(LiveView::new(None).status(), None);
+    }
+}
@@ -1,10 +1,52 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
 
+/// Log lines kept in memory for crash reports.
+const RECENT_LINES: usize = 200;
+
+fn recent() -> &'static Mutex<VecDeque<String>> {
+    static RECENT: OnceLock<Mutex<VecDeque<String>>> = OnceLock::new();
+    RECENT.get_or_init(|| Mutex::new(VecDeque::with_capacity(RECENT_LINES)))
+}
+
+/// The last log lines, oldest first. Empty if the lock is poisoned or held
+/// (e.g. when called from a panic inside the logger).
+pub fn recent_lines() -> Vec<String> {
+    recent()
+        .try_lock()
+        .map(|lines| lines.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Stdout, plus a copy into the in-memory ring.
+struct RingWriter;
+
+impl Write for RingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Ok(mut lines) = recent().try_lock() {
+            for line in String::from_utf8_lossy(buf).lines() {
+                if lines.len() == RECENT_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back(line.to_string());
+            }
+        }
+        io::stdout().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
 pub fn setup_trace_logging() {
     let subscriber = FmtSubscriber::builder()
         .with_max_level(Level::INFO)
         .with_ansi(false)
+        .with_writer(|| RingWriter)
         .finish();
     tracing::subscriber::set_global_default(subscriber).unwrap();
 }
@@ -2,10 +2,40 @@ use anyhow::{Context, Result, anyhow};
 use signal_hook::consts::signal::*;
 use signal_hook::iterator::Signals;
 use std::fs;
-use tracing::info;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
 
 use dashcam_rs::cam_service::CamService;
-use dashcam_rs::config::{AppConfig, verify_app_config};
+use dashcam_rs::config::{AppConfig, SinkConfig, verify_app_config};
+use dashcam_rs::constants::SOCKET_PATH;
+use dashcam_rs::control::ControlCommand;
+use dashcam_rs::control_socket::spawn_control_socket;
+use dashcam_rs::gps::spawn_gps;
+use dashcam_rs::gsensor::spawn_gsensor;
+use dashcam_rs::power_loss::spawn_power_loss;
+use dashcam_rs::privacy::spawn_privacy;
+use dashcam_rs::modes::spawn_modes;
+use dashcam_rs::rules::spawn_rules;
+use dashcam_rs::crash;
+use dashcam_rs::anonymize::BlurPass;
+use dashcam_rs::export::{
+    ExportAudio, ExportOptions, benchmark_export, clock_accuracy_text, event_chapters, export_with, range_segments,
+    run_output, segment_start, split_by_stream,
+};
+use dashcam_rs::http::server::spawn_http_server;
+use dashcam_rs::mdns;
+use dashcam_rs::self_test::run_self_test;
+use dashcam_rs::simulation::{Simulation, spawn_simulation_reporter};
+use dashcam_rs::soak::{SoakLimits, run_soak};
+use dashcam_rs::db::db_worker::{DBWorker, start_db_worker};
+use dashcam_rs::recording_pipeline_factory::build_pipelines_from_config;
+use dashcam_rs::db::db::{DashcamDb, DeletionReason};
+use dashcam_rs::janitor::{find_orphans, ring_cameras, sweep, swept_rel_paths};
+use dashcam_rs::incident_bundle::{load_incident, write_bundle};
+use dashcam_rs::legacy_import::import_legacy;
+use dashcam_rs::track::{TrackFormat, TrackRange, load_track, parse_time, render};
 use dashcam_rs::log;
 
 pub const CONFIG_PATH: &str = "/var/lib/dashcam/config.toml";
@@ -20,32 +50,441 @@ fn load_app_config() -> Result<AppConfig> {
     let cfg: AppConfig = toml::from_str(&contents)
         .with_context(|| format!("Failed to parse TOML config at '{}'", path))?;
 
-    if verify_app_config(&cfg) {
-        Ok(cfg)
-    } else {
-        Err(anyhow!("Can't have more than 1 camera with the same source. Check your config.toml."))
-    }
+    verify_app_config(&cfg).with_context(|| format!("Invalid config at '{}'", path))?;
+    Ok(cfg)
 }
 
 fn main() -> Result<()> {
     log::setup_trace_logging();
 
-    let cfg = load_app_config()?;
+    let mut cfg = load_app_config()?;
+
+    if std::env::args().nth(1).as_deref() == Some("self-test") {
+        let report = run_self_test(&cfg)?;
+        println!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("import-legacy") {
+        let (Some(dir), Some(camera_key)) = (std::env::args().nth(2), std::env::args().nth(3)) else {
+            return Err(anyhow!("Usage: dashcam_rs import-legacy <legacy dir> <camera key>"));
+        };
+        let db = DashcamDb::setup_from_config(&cfg)?;
+        let summary = import_legacy(
+            &db,
+            &camera_key,
+            Path::new(&dir),
+            Path::new(&cfg.recording_root()),
+            cfg.profile().video_duration,
+        )?;
+        println!(
+            "{} files found, {} imported, {} already known",
+            summary.found, summary.imported, summary.skipped
+        );
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("janitor") {
+        let dry_run = std::env::args().any(|a| a == "--dry-run");
+        let db = DashcamDb::setup_from_config(&cfg)?;
+        let recording_root = PathBuf::from(cfg.recording_root());
+        for cam in ring_cameras(&cfg, |key| db.get_camera_id_by_key(key).ok()) {
+            let referenced = db
+                .segment_rel_paths(cam.camera_id)?
+                .iter()
+                .map(|p| recording_root.join(p))
+                .collect();
+            let report = find_orphans(&cam.ring_dir, cam.max_segments, &referenced)?;
+            for path in &report.orphans {
+                println!("{}", path.display());
+            }
+            let removed = if dry_run { 0 } else { sweep(&report) };
+            if removed > 0 {
+                let rel_paths = swept_rel_paths(&report, &recording_root);
+                db.forget_segments(cam.camera_id, &rel_paths, DeletionReason::Janitor, chrono::Utc::now().timestamp())?;
+            }
+            println!(
+                "{}: {} orphaned files ({} bytes), {} removed",
+                cam.key,
+                report.orphans.len(),
+                report.bytes,
+                removed
+            );
+        }
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("export") {
+        let mut args: Vec<String> = std::env::args().skip(2).collect();
+        let events_camera = match args.iter().position(|a| a == "--events") {
+            Some(i) if i + 1 < args.len() => Some(args.drain(i..=i + 1).nth(1).unwrap()),
+            Some(_) => return Err(anyhow!("--events needs a camera key")),
+            None => None,
+        };
+        let camera = match args.iter().position(|a| a == "--camera") {
+            Some(i) if i + 1 < args.len() => args.drain(i..=i + 1).nth(1),
+            Some(_) => return Err(anyhow!("--camera needs a camera key")),
+            None => events_camera.clone(),
+        };
+        let speed = match args.iter().position(|a| a == "--speed") {
+            Some(i) if i + 1 < args.len() => {
+                let speed = args.drain(i..=i + 1).nth(1).unwrap();
+                Some(speed.parse::<u32>().ok().filter(|s| *s > 0).with_context(|| format!("Bad --speed '{}'", speed))?)
+            }
+            Some(_) => return Err(anyhow!("--speed needs a factor, e.g. 288 for a day in five minutes")),
+            None => None,
+        };
+        let audio = match args.iter().position(|a| a == "--audio") {
+            Some(i) if i + 1 < args.len() => {
+                let audio = args.drain(i..=i + 1).nth(1).unwrap();
+                ExportAudio::from_arg(&audio).with_context(|| format!("--audio is keep, mute or strip, not '{}'", audio))?
+            }
+            Some(_) => return Err(anyhow!("--audio needs keep, mute or strip")),
+            None => cfg.export.audio,
+        };
+        let blur = match args.iter().position(|a| a == "--blur") {
+            Some(i) if i + 1 < args.len() => {
+                let labels = args.drain(i..=i + 1).nth(1).unwrap();
+                let cfg = cfg.export.anonymize.clone().context("--blur needs an [export.anonymize] model")?;
+                Some(BlurPass { cfg, labels: labels.split(',').map(|l| l.trim().to_string()).collect() })
+            }
+            Some(_) => return Err(anyhow!("--blur needs the labels to hide, e.g. face,license_plate")),
+            None => None,
+        };
+        let mut watermark = cfg.export.watermark(camera.as_deref().unwrap_or("unknown"));
+        let args: Vec<PathBuf> = args.into_iter().map(PathBuf::from).collect();
+        let Some((output, segments)) = args.split_first().filter(|(_, s)| !s.is_empty()) else {
+            return Err(anyhow!("Usage: dashcam_rs export <output.mp4|.mkv> <segment>... [--events <camera key>] [--camera <camera key>] [--speed <factor>] [--audio keep|mute|strip] [--blur <label>,...]"));
+        };
+        let duration = Duration::from_secs(cfg.profile().video_duration);
+        let range = segment_start(&segments[0], duration).zip(segment_start(&segments[segments.len() - 1], duration));
+        let range = range.map(|(start, last)| (start, last + chrono::Duration::seconds(duration.as_secs() as i64)));
+
+        // Clock corrections during the footage bound how far its timestamps can be trusted
+        if let Some((start, end)) = range.filter(|_| Path::new(&cfg.db_path()).exists()) {
+            let db = DashcamDb::setup_from_config(&cfg)?;
+            if let Some(ms) = db.clock_accuracy_between(start.timestamp(), end.timestamp())? {
+                println!("Clock was corrected during this footage: {}", clock_accuracy_text(ms));
+                if let Some(watermark) = watermark.as_mut() {
+                    watermark.clock_accuracy_ms = Some(ms);
+                }
+            }
+        }
+
+        let chapters;
+        let mut options = ExportOptions { watermark: watermark.as_ref(), speed, audio, blur: blur.as_ref(), ..Default::default() };
+        if let Some(camera_key) = events_camera {
+            let (start, end) = range.context("Can't tell when the segments start")?;
+            let db = DashcamDb::setup_from_config(&cfg)?;
+            let camera_id = db.get_camera_id_by_key(&camera_key)?;
+            let events = db.get_events_between(camera_id, start.timestamp(), end.timestamp())?;
+            chapters = event_chapters(&events, start.timestamp(), end.timestamp());
+            println!("{} events in range", chapters.len());
+            options.chapters = &chapters;
+            options.total = (end - start).to_std()?;
+        }
 
-    let mut cam_service = CamService::new(cfg)?;
+        // A file can't change codec, size or frame rate midway; split where the catalog says they do
+        let mut runs = vec![segments];
+        if Path::new(&cfg.db_path()).exists() {
+            let db = DashcamDb::setup_from_config(&cfg)?;
+            let root = fs::canonicalize(cfg.recording_root()).unwrap_or_else(|_| PathBuf::from(cfg.recording_root()));
+            let mut streams = Vec::new();
+            for file in segments {
+                let rel = fs::canonicalize(file).ok().and_then(|path| {
+                    path.strip_prefix(&root).ok().map(|rel| rel.to_string_lossy().to_string())
+                });
+                let stream = match rel {
+                    Some(rel) => db.segment_stream(&rel)?,
+                    None => None,
+                };
+                streams.push(stream.unwrap_or_default());
+            }
+            runs = split_by_stream(segments, &streams);
+        }
+        if runs.len() > 1 {
+            if !options.chapters.is_empty() {
+                return Err(anyhow!("The segments change codec, size or frame rate, so --events can't chapter them as one file; export each part on its own"));
+            }
+            println!("The segments change codec, size or frame rate; exporting {} files", runs.len());
+        }
+        for (n, run) in runs.iter().enumerate() {
+            let output = run_output(output, n);
+            let throughput = export_with(run, &output, &options)?;
+            println!("{} segments -> {} ({:.1} MB/s)", run.len(), output.display(), throughput.mb_per_sec());
+        }
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("export-incident") {
+        let Some(incident_id) = std::env::args().nth(2).and_then(|id| id.parse::<i64>().ok()) else {
+            return Err(anyhow!("Usage: dashcam_rs export-incident <incident id> [<output.zip>]"));
+        };
+        let db = DashcamDb::setup_from_config(&cfg)?;
+        let record = load_incident(&db, incident_id)?.with_context(|| format!("No incident {}", incident_id))?;
+        let output = std::env::args().nth(3).map(PathBuf::from).unwrap_or_else(|| record.default_output());
+        let summary = write_bundle(&record, &output)?;
+        println!(
+            "Incident {}: {} clips, {} events, {} GPS fixes -> {} ({} bytes)",
+            incident_id,
+            record.clips.len(),
+            record.events.len(),
+            record.track.len(),
+            summary.path.display(),
+            summary.bytes
+        );
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("export-range") {
+        const USAGE: &str = "Usage: dashcam_rs export-range <camera key> <from> <to> <output.mp4|.mkv>";
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let [camera_key, from, to, output] = args.as_slice() else {
+            return Err(anyhow!(USAGE));
+        };
+        let time = |value: &str| parse_time(value).with_context(|| format!("Bad time '{}', use unix seconds or RFC 3339", value));
+        let (from_utc, to_utc) = (time(from)?, time(to)?);
+        if to_utc <= from_utc {
+            return Err(anyhow!("<to> must come after <from>. {}", USAGE));
+        }
+        let db = DashcamDb::setup_from_config(&cfg)?;
+        let camera_id = db
+            .get_camera_id_by_key(camera_key)
+            .with_context(|| format!("No camera '{}'", camera_key))?;
+        let rows = db.segments_between(camera_id, from_utc, to_utc)?;
+        let (segments, streams) = range_segments(&rows, Path::new(&cfg.recording_root()));
+        if segments.is_empty() {
+            return Err(anyhow!("No recordings of {} between {} and {}", camera_key, from, to));
+        }
+        let watermark = cfg.export.watermark(camera_key);
+        let options = ExportOptions { watermark: watermark.as_ref(), audio: cfg.export.audio, ..Default::default() };
+        let runs = split_by_stream(&segments, &streams);
+        if runs.len() > 1 {
+            println!("The segments change codec, size or frame rate; exporting {} files", runs.len());
+        }
+        for (n, run) in runs.iter().enumerate() {
+            let output = run_output(Path::new(output), n);
+            let throughput = export_with(run, &output, &options)?;
+            println!("{} segments -> {} ({:.1} MB/s)", run.len(), output.display(), throughput.mb_per_sec());
+        }
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("export-track") {
+        const USAGE: &str = "Usage: dashcam_rs export-track (--trip <id> | --from <time> --to <time>) [--format gpx|geojson] [<output>]";
+        let mut args: Vec<String> = std::env::args().skip(2).collect();
+        let mut option = |name: &str| -> Result<Option<String>> {
+            match args.iter().position(|a| a == name) {
+                Some(i) if i + 1 < args.len() => Ok(args.drain(i..=i + 1).nth(1)),
+                Some(_) => Err(anyhow!("{} needs a value. {}", name, USAGE)),
+                None => Ok(None),
+            }
+        };
+        let time = |value: String| parse_time(&value).with_context(|| format!("Bad time '{}', use unix seconds or RFC 3339", value));
+        let range = match (option("--trip")?, option("--from")?, option("--to")?) {
+            (Some(id), None, None) => TrackRange::Trip(id.parse().with_context(|| format!("Bad trip id '{}'", id))?),
+            (None, Some(from), Some(to)) => TrackRange::Between { from_utc: time(from)?, to_utc: time(to)? },
+            _ => return Err(anyhow!(USAGE)),
+        };
+        let format = option("--format")?;
+        let output = args.first().map(PathBuf::from);
+        let format = match (format, &output) {
+            (Some(f), _) => TrackFormat::from_arg(&f).with_context(|| format!("--format is gpx or geojson, not '{}'", f))?,
+            (None, Some(output)) => TrackFormat::from_path(output),
+            (None, None) => TrackFormat::Gpx,
+        };
+        let db = DashcamDb::setup_from_config(&cfg)?;
+        let points = load_track(&db, range)?.with_context(|| format!("No {}", range.name()))?;
+        let document = render(format, range, &points);
+        match output {
+            Some(output) => {
+                fs::write(&output, document)?;
+                println!("{}: {} GPS fixes -> {}", range.name(), points.len(), output.display());
+            }
+            None => print!("{}", document),
+        }
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("soak") {
+        let args: Vec<String> = std::env::args().collect();
+        let cycles = match args.iter().position(|a| a == "--cycles") {
+            Some(i) => args.get(i + 1).and_then(|v| v.parse().ok()).context("--cycles needs a number")?,
+            None => 300,
+        };
+        // test sources and a scratch DB, like --simulate
+        Simulation { time_scale: 1 }.apply(&mut cfg);
+        let (db_sender, recvr) = std::sync::mpsc::channel();
+        let db_handle = start_db_worker(DBWorker::new(recvr, &cfg)?);
+        let mut pipelines = build_pipelines_from_config(&cfg, Arc::new(db_sender))?;
+        let report = run_soak(&mut pipelines, cycles, Duration::from_secs(2))?;
+        drop(pipelines);
+        let _ = db_handle.join();
+        println!("{}", report);
+        let problems = report.problems(&SoakLimits::default());
+        for problem in &problems {
+            println!("LEAK: {}", problem);
+        }
+        std::process::exit(if problems.is_empty() { 0 } else { 1 });
+    }
 
-    let running = cam_service.running.clone();
-    let mut signals = Signals::new(&[SIGINT, SIGTERM, SIGQUIT, SIGHUP])?;
+    if std::env::args().nth(1).as_deref() == Some("bench-export") {
+        let args: Vec<PathBuf> = std::env::args().skip(2).map(PathBuf::from).collect();
+        let Some((scratch_dir, segments)) = args.split_first().filter(|(_, s)| !s.is_empty()) else {
+            return Err(anyhow!("Usage: dashcam_rs bench-export <scratch dir> <segment>..."));
+        };
+        println!("{}", benchmark_export(segments, scratch_dir)?);
+        return Ok(());
+    }
+
+    let simulation = Simulation::from_args(&std::env::args().collect::<Vec<_>>())?;
+    if let Some(simulation) = &simulation {
+        simulation.apply(&mut cfg);
+        info!("Simulation mode: recording test video under {}", cfg.main_dir());
+    }
+
+    crash::install(&cfg.main_dir())?;
+
+    let http_config = cfg.http.clone();
+    let cam_service = Arc::new(Mutex::new(CamService::new(cfg)?));
+
+    let running = cam_service.lock().unwrap().running.clone();
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGQUIT, SIGHUP])?;
+
+    // Before the cameras start, so one switched private never records
+    let privacy_worker = {
+        let service = cam_service.lock().unwrap();
+        let switches: Vec<_> = service
+            .app_config
+            .cameras
+            .iter()
+            .filter(|c| c.enabled)
+            .filter_map(|c| Some((c.key.clone(), c.privacy_switch.clone()?)))
+            .collect();
+        let status_mqtt = service.app_config.status_mqtt.clone();
+        drop(service);
+        (!switches.is_empty()).then(|| {
+            let cam_service = cam_service.clone();
+            spawn_privacy(switches, status_mqtt, move |camera_key, private| {
+                if let Err(e) = cam_service.lock().unwrap().set_privacy(camera_key, private) {
+                    warn!("Privacy switch for '{}': {:#}", camera_key, e);
+                }
+            })
+        })
+    };
+
+    let rule_worker = {
+        let mut service = cam_service.lock().unwrap();
+        let rules = service.app_config.rules.clone();
+        let keys: Vec<String> = service.app_config.cameras.iter().filter(|c| c.enabled).map(|c| c.key.clone()).collect();
+        service.take_rule_inputs().map(|inputs| {
+            info!("Dispatching {} event rule(s)", rules.len());
+            let cam_service = cam_service.clone();
+            spawn_rules(rules, keys, inputs, service.db_sender.clone(), move |actor, command| {
+                let _ = cam_service.lock().unwrap().execute(actor, command);
+            })
+        })
+    };
+
+    let mode_worker = cam_service.lock().unwrap().app_config.mode_mqtt.clone().map(|cfg| {
+        let cam_service = cam_service.clone();
+        spawn_modes(cfg, move |mode| {
+            let command = ControlCommand::SetMode { name: mode.to_string() };
+            let _ = cam_service.lock().unwrap().execute("mqtt", command);
+        })
+    });
+
+    cam_service.lock().unwrap().main_loop()?;
+
+    // the shutdown runs on clones, without the service lock
+    let power_loss_worker = cam_service.lock().unwrap().power_loss_shutdown().map(|shutdown| {
+        let cfg = shutdown.config().clone();
+        spawn_power_loss(&cfg, move |lost_at| shutdown.run(lost_at))
+    });
+
+    let gps_worker = {
+        let service = cam_service.lock().unwrap();
+        let db_sender = service.db_sender.clone();
+        service.app_config.gps.clone().map(|cfg| {
+            let cam_service = cam_service.clone();
+            spawn_gps(&cfg, db_sender, move |point| cam_service.lock().unwrap().gps_fix(point))
+        })
+    };
+
+    let gsensor_worker = cam_service.lock().unwrap().app_config.gsensor.clone().map(|cfg| {
+        let cam_service = cam_service.clone();
+        spawn_gsensor(&cfg, move |g| cam_service.lock().unwrap().impact(g))
+    });
+
+    let simulation_reporter = simulation.map(|simulation| {
+        let service = cam_service.lock().unwrap();
+        let rings = service
+            .app_config
+            .cameras
+            .iter()
+            .filter(|c| c.enabled)
+            .map(|c| {
+                let sink_ids = c
+                    .sinks
+                    .iter()
+                    .filter(|s| matches!(s, SinkConfig::DashcamTs { .. }))
+                    .map(|s| s.sink_id())
+                    .collect();
+                (c.key.clone(), sink_ids)
+            })
+            .collect();
+        spawn_simulation_reporter(simulation, rings, service.db_sender.clone())
+    });
+
+    if let Some(http_config) = http_config {
+        spawn_http_server(http_config, cam_service.clone())?;
+    }
+
+    // Local tools only; the service runs fine without it
+    if let Err(e) = spawn_control_socket(Path::new(SOCKET_PATH), cam_service.clone(), load_app_config) {
+        warn!("No control socket: {:#}", e);
+    }
 
-    cam_service.main_loop()?;
+    let mdns_advertiser = {
+        let service = cam_service.lock().unwrap();
+        match &service.app_config.mdns {
+            Some(mdns_config) => Some(mdns::advertise(mdns_config, &service.app_config)?),
+            None => None,
+        }
+    };
 
-    for sig in signals.forever() {
+    if let Some(sig) = signals.forever().next() {
         info!("Exiting cleanly. Received signal {}", sig);
         running.store(false, std::sync::atomic::Ordering::SeqCst);
-        cam_service.kill_main_loop()?;
+        if let Some(worker) = privacy_worker {
+            worker.stop();
+        }
+        if let Some(worker) = power_loss_worker {
+            worker.stop();
+        }
+        if let Some(worker) = gps_worker {
+            worker.stop();
+        }
+        if let Some(worker) = gsensor_worker {
+            worker.stop();
+        }
+        if let Some(worker) = rule_worker {
+            worker.stop();
+        }
+        if let Some(worker) = mode_worker {
+            worker.stop();
+        }
+        if let Some(reporter) = simulation_reporter {
+            reporter.stop();
+        }
+        cam_service.lock().unwrap().kill_main_loop()?;
+        if let Some(advertiser) = mdns_advertiser {
+            advertiser.shutdown();
+        }
+        let _ = fs::remove_file(SOCKET_PATH);
+        crash::mark_clean_shutdown();
         std::process::exit(sig);
     }
 
-    #[allow(unreachable_code)]
     Ok(())
 }
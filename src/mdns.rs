@@ -0,0 +1,154 @@
+//! LAN service advertisement (`[mdns]`, needs the `mdns` feature), so companion
+//! apps and other recorders find the device without a static IP.
+//!
+//! - `_dashcam._tcp`: the HTTP control API, when `[http]` is configured
+//! - `_rtsp._tcp`: one entry per camera, when `rtsp_port` points at an RTSP server
+
+use anyhow::Result;
+use std::net::SocketAddr;
+use tracing::warn;
+
+use crate::config::{AppConfig, MdnsConfig};
+
+pub const DASHCAM_SERVICE: &str = "_dashcam._tcp.local.";
+pub const RTSP_SERVICE: &str = "_rtsp._tcp.local.";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceRecord {
+    pub service_type: &'static str,
+    pub instance: String,
+    pub port: u16,
+    pub txt: Vec<(String, String)>,
+}
+
+/// Everything to announce for this config.
+pub fn service_records(mdns: &MdnsConfig, app: &AppConfig) -> Vec<ServiceRecord> {
+    let mut records = Vec::new();
+    let cameras: Vec<_> = app.cameras.iter().filter(|c| c.enabled).collect();
+
+    if let Some(http) = &app.http {
+        match http.bind.parse::<SocketAddr>() {
+            Ok(addr) => records.push(ServiceRecord {
+                service_type: DASHCAM_SERVICE,
+                instance: mdns.instance.clone(),
+                port: addr.port(),
+                txt: vec![
+                    ("api".to_string(), "/api".to_string()),
+                    ("tls".to_string(), if http.tls.is_some() { "1" } else { "0" }.to_string()),
+                    (
+                        "cameras".to_string(),
+                        cameras.iter().map(|c| c.key.as_str()).collect::<Vec<_>>().join(","),
+                    ),
+                    ("version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+                ],
+            }),
+            Err(_) => warn!("mDNS: can't get a port from [http] bind '{}', not advertising the API", http.bind),
+        }
+    }
+
+    if let Some(port) = mdns.rtsp_port {
+        for cam in cameras {
+            records.push(ServiceRecord {
+                service_type: RTSP_SERVICE,
+                instance: format!("{} {}", mdns.instance, cam.name),
+                port,
+                txt: vec![("path".to_string(), format!("/{}", cam.key))],
+            });
+        }
+    }
+    records
+}
+
+/// Keeps the announcements up; withdraws them when dropped.
+#[cfg(feature = "mdns")]
+pub struct MdnsAdvertiser {
+    daemon: mdns_sd::ServiceDaemon,
+    fullnames: Vec<String>,
+}
+
+#[cfg(feature = "mdns")]
+pub fn advertise(mdns: &MdnsConfig, app: &AppConfig) -> Result<MdnsAdvertiser> {
+    use anyhow::Context;
+    use tracing::info;
+
+    let daemon = mdns_sd::ServiceDaemon::new().context("Failed to start mDNS daemon")?;
+    let mut fullnames = Vec::new();
+    for record in service_records(mdns, app) {
+        let info = mdns_sd::ServiceInfo::new(
+            record.service_type,
+            &record.instance,
+            &mdns.hostname,
+            "",
+            record.port,
+            record.txt.as_slice(),
+        )?
+        .enable_addr_auto();
+        info!("mDNS: advertising '{}' ({}) on port {}", record.instance, record.service_type, record.port);
+        fullnames.push(info.get_fullname().to_string());
+        daemon.register(info)?;
+    }
+    Ok(MdnsAdvertiser { daemon, fullnames })
+}
+
+impl MdnsAdvertiser {
+    /// Withdraw the announcements now (`process::exit` skips destructors).
+    pub fn shutdown(self) {}
+}
+
+#[cfg(feature = "mdns")]
+impl Drop for MdnsAdvertiser {
+    fn drop(&mut self) {
+        for fullname in &self.fullnames {
+            let _ = self.daemon.unregister(fullname);
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+#[cfg(not(feature = "mdns"))]
+pub struct MdnsAdvertiser;
+
+#[cfg(not(feature = "mdns"))]
+pub fn advertise(_mdns: &MdnsConfig, _app: &AppConfig) -> Result<MdnsAdvertiser> {
+    anyhow::bail!("[mdns] is set but dashcam_rs was built without the `mdns` feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_and_rtsp_records() {
+        let app: AppConfig = toml::from_str(
+            r#"
+            [http]
+            bind = "0.0.0.0:8443"
+            [http.tls]
+            self_signed = true
+
+            [mdns]
+            rtsp_port = 8554
+
+            [[cameras]]
+            key = "front"
+            name = "Front"
+            source = { kind = "v4l2", device = "/dev/video0" }
+
+            [[cameras]]
+            key = "rear"
+            name = "Rear"
+            source = { kind = "v4l2", device = "/dev/video2" }
+            "#,
+        )
+        .unwrap();
+        let records = service_records(app.mdns.as_ref().unwrap(), &app);
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].service_type, DASHCAM_SERVICE);
+        assert_eq!(records[0].port, 8443);
+        assert!(records[0].txt.contains(&("tls".to_string(), "1".to_string())));
+        assert!(records[0].txt.contains(&("cameras".to_string(), "front,rear".to_string())));
+        assert_eq!(records[2].instance, "Dashcam Rear");
+        assert_eq!(records[2].txt, vec![("path".to_string(), "/rear".to_string())]);
+    }
+}
@@ -0,0 +1,124 @@
+//! Latency histograms in Prometheus text format, served at `GET /metrics`.
+//!
+//! A dying SD card shows up as slow fsyncs and stalled writes well before
+//! segments start failing outright; averages hide that, the upper buckets don't.
+//! Histograms are process-wide and created on first observation.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Wall time from a segment being opened to splitmuxsink reporting it closed
+pub const SEGMENT_WRITE: &str = "dashcam_segment_write_seconds";
+/// Segment rollover to the ring index being committed to the DB
+pub const FRAGMENT_COMMIT: &str = "dashcam_fragment_commit_seconds";
+/// fsync of a freshly closed segment
+pub const SEGMENT_FSYNC: &str = "dashcam_segment_fsync_seconds";
+
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+/// Segments are 2-60s long; the interesting part is how far past that they run
+const SEGMENT_BUCKETS: &[f64] = &[1.0, 2.0, 2.5, 3.0, 5.0, 10.0, 15.0, 30.0, 60.0, 65.0, 90.0, 120.0];
+
+fn describe(name: &str) -> (&'static str, &'static [f64]) {
+    match name {
+        SEGMENT_WRITE => ("Time from opening a segment until it was closed", SEGMENT_BUCKETS),
+        FRAGMENT_COMMIT => ("Time from a segment rollover until its ring index was committed", LATENCY_BUCKETS),
+        SEGMENT_FSYNC => ("Time to fsync a closed segment", LATENCY_BUCKETS),
+        _ => ("", LATENCY_BUCKETS),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Per bucket, not cumulative; the last one is +Inf
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self { bounds, counts: vec![0; bounds.len() + 1], sum: 0.0 }
+    }
+
+    fn observe(&mut self, secs: f64) {
+        let bucket = self.bounds.iter().position(|b| secs <= *b).unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += secs;
+    }
+}
+
+/// (metric name, rendered labels) -> histogram
+type Registry = BTreeMap<(&'static str, String), Histogram>;
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Record one observation of `name` (one of the constants above).
+pub fn observe(name: &'static str, labels: &[(&str, &str)], value: Duration) {
+    let (_, bounds) = describe(name);
+    registry()
+        .lock()
+        .unwrap()
+        .entry((name, render_labels(labels)))
+        .or_insert_with(|| Histogram::new(bounds))
+        .observe(value.as_secs_f64());
+}
+
+/// Everything observed so far, in the Prometheus text exposition format.
+pub fn render() -> String {
+    let registry = registry().lock().unwrap();
+    let mut out = String::new();
+    let mut last_name = "";
+    for ((name, labels), histogram) in registry.iter() {
+        if *name != last_name {
+            let _ = writeln!(out, "# HELP {} {}", name, describe(name).0);
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            last_name = name;
+        }
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (i, count) in histogram.counts.iter().enumerate() {
+            cumulative += count;
+            let le = histogram.bounds.get(i).map_or("+Inf".to_string(), |b| b.to_string());
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, le, cumulative);
+        }
+        let braces = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, braces, histogram.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces, cumulative);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histograms_render_cumulative_buckets() {
+        let labels = [("camera", "metrics_test"), ("sink", "0")];
+        observe(SEGMENT_FSYNC, &labels, Duration::from_millis(3));
+        observe(SEGMENT_FSYNC, &labels, Duration::from_millis(40));
+        observe(SEGMENT_FSYNC, &labels, Duration::from_secs(20));
+
+        let text = render();
+        assert!(text.contains("# TYPE dashcam_segment_fsync_seconds histogram"));
+        let series = "dashcam_segment_fsync_seconds_bucket{camera=\"metrics_test\",sink=\"0\",";
+        assert!(text.contains(&format!("{}le=\"0.005\"}} 1\n", series)));
+        assert!(text.contains(&format!("{}le=\"0.05\"}} 2\n", series)));
+        assert!(text.contains(&format!("{}le=\"10\"}} 2\n", series)));
+        assert!(text.contains(&format!("{}le=\"+Inf\"}} 3\n", series)));
+        assert!(text.contains("dashcam_segment_fsync_seconds_count{camera=\"metrics_test\",sink=\"0\"} 3\n"));
+    }
+}
@@ -0,0 +1,268 @@
+//! System modes (`[[modes]]` in config.toml), like the home/away/night of
+//! commercial NVRs.
+//!
+//! A mode says which cameras record and which `[[rules]]` are armed: an
+//! interior camera can record only while `away`, a doorbell rule only fire at
+//! `night`. Cameras left out of a mode's `cameras` are kept stopped, like the
+//! privacy switch but without a `privacy` event; rules left out of its `rules`
+//! are disarmed. A mode without `cameras` records on all cameras, one without
+//! `rules` leaves the rules as they are.
+//!
+//! A mode can also record at other settings than the cameras' own, e.g.
+//! 1080p at 5 fps and a low bitrate while parked: `encoding = { framerate = 5,
+//! bitrate_kbps = 800 }`. Only cameras that encode themselves (not passthrough
+//! or RTSP) follow it. A new bitrate is set on the running encoder; a new size
+//! or frame rate rebuilds the pipeline, which costs a second or two of video.
+//! Modes without `encoding` record at the cameras' own settings.
+//!
+//! `warm_spare = true` keeps an encoder for the mode's encoding built and idle
+//! next to the one in use (see `encode_branches`), so switching into and out of
+//! it is instant, with only a segment split. It costs an encoder's memory per
+//! camera.
+//!
+//! Modes are switched over the control API, a rule's `mode` action, or an MQTT
+//! message carrying the mode name on `[mode_mqtt]`'s topic. Every switch is
+//! logged in `mode_history`; the last one is restored on startup, the first
+//! mode listed is used before any switch.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::db::db::ModeChangeRow;
+use crate::mqtt::MqttClient;
+use crate::recording_pipeline::EncodingSettings;
+use crate::worker::{Worker, sleep_unless_stopped};
+
+const MQTT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+const MQTT_RETRY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ModeConfig {
+    /// "home", "away", "night"...; used in URLs
+    pub name: String,
+    /// Cameras recording in this mode; all when left out
+    pub cameras: Option<Vec<String>>,
+    /// Rules armed in this mode, the others are disarmed; untouched when left out
+    pub rules: Option<Vec<String>>,
+    /// Overrides of the cameras' recording settings while in this mode
+    pub encoding: Option<ModeEncoding>,
+    /// Keep an encoder for `encoding` ready so switches don't rebuild the pipeline
+    #[serde(default)]
+    pub warm_spare: bool,
+}
+
+/// `encoding = { ... }` of a mode; fields left out keep the camera's own value.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModeEncoding {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub framerate: Option<i32>,
+    pub bitrate_kbps: Option<u32>,
+}
+
+impl ModeEncoding {
+    pub fn is_valid(&self) -> bool {
+        [self.width, self.height, self.framerate].iter().flatten().all(|v| *v > 0)
+            && self.bitrate_kbps.is_none_or(|kbps| kbps > 0)
+    }
+
+    /// `camera`'s settings with this mode's overrides.
+    pub fn apply(&self, camera: EncodingSettings) -> EncodingSettings {
+        EncodingSettings {
+            width: self.width.unwrap_or(camera.width),
+            height: self.height.unwrap_or(camera.height),
+            frame_rate: self.framerate.unwrap_or(camera.frame_rate),
+            bitrate_kbps: self.bitrate_kbps.unwrap_or(camera.bitrate_kbps),
+        }
+    }
+}
+
+impl ModeConfig {
+    /// A name usable in URLs, known cameras and rules.
+    pub fn is_valid(&self, camera_keys: &[&str], rule_names: &[&str]) -> bool {
+        !self.name.is_empty()
+            && !self.name.contains(|c: char| c == '/' || c == '?' || c.is_whitespace())
+            && self.cameras.iter().flatten().all(|key| camera_keys.contains(&key.as_str()))
+            && self.rules.iter().flatten().all(|name| rule_names.contains(&name.as_str()))
+            && self.encoding.is_none_or(|encoding| encoding.is_valid())
+    }
+
+    /// What a camera configured with `camera` records at in this mode.
+    pub fn encoding_for(&self, camera: EncodingSettings) -> EncodingSettings {
+        self.encoding.map_or(camera, |encoding| encoding.apply(camera))
+    }
+
+    pub fn records(&self, camera_key: &str) -> bool {
+        self.cameras.as_ref().is_none_or(|keys| keys.iter().any(|k| k == camera_key))
+    }
+
+    /// Whether `rule` should be armed, `None` if the mode leaves rules alone.
+    pub fn arms(&self, rule: &str) -> Option<bool> {
+        self.rules.as_ref().map(|names| names.iter().any(|n| n == rule))
+    }
+}
+
+/// `[mode_mqtt]`: a topic on which a message with a mode name switches to it.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ModeMqttConfig {
+    /// "host:port"
+    pub broker: String,
+    pub topic: String,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+}
+
+fn default_client_id() -> String {
+    "dashcam_rs_mode".to_string()
+}
+
+/// Encodings a camera configured with `camera` keeps spare encoders for: its
+/// own and those of the `warm_spare` modes, when there are any.
+pub fn spare_encodings(modes: &[ModeConfig], camera: EncodingSettings) -> Vec<EncodingSettings> {
+    let mut spares = Vec::new();
+    for mode in modes.iter().filter(|m| m.warm_spare) {
+        let encoding = mode.encoding_for(camera);
+        if encoding != camera && !spares.contains(&encoding) {
+            spares.push(encoding);
+        }
+    }
+    if !spares.is_empty() {
+        spares.insert(0, camera);
+    }
+    spares
+}
+
+/// The mode to start in: the last one switched to if it's still configured,
+/// else the first one listed.
+pub fn initial_mode<'a>(modes: &'a [ModeConfig], last: Option<&str>) -> Option<&'a ModeConfig> {
+    last.and_then(|name| modes.iter().find(|m| m.name == name))
+        .or_else(|| modes.first())
+}
+
+/// Current mode and recent switches, for `GET /api/mode`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModeStatus {
+    pub current: String,
+    pub modes: Vec<String>,
+    /// Newest first
+    pub history: Vec<ModeChangeRow>,
+}
+
+/// Listens on `[mode_mqtt]`; `on_request(mode)` runs for every message.
+pub fn spawn_modes<F>(cfg: ModeMqttConfig, on_request: F) -> Worker
+where
+    F: Fn(&str) + Send + 'static,
+{
+    let mut mqtt: Option<MqttClient> = None;
+    let mut retry_at = Instant::now();
+    Worker::spawn("modes", Duration::ZERO, move |stop| {
+        let Some(client) = mqtt.as_mut() else {
+            if Instant::now() < retry_at {
+                sleep_unless_stopped(retry_at - Instant::now(), stop);
+                return;
+            }
+            match MqttClient::subscribe(&cfg.broker, &cfg.client_id, &cfg.topic, MQTT_KEEP_ALIVE) {
+                Ok(client) => {
+                    info!("Listening for mode switches on {} ({})", cfg.topic, cfg.broker);
+                    mqtt = Some(client);
+                }
+                Err(e) => {
+                    warn!("{:#}; retrying in {:?}", e, MQTT_RETRY);
+                    retry_at = Instant::now() + MQTT_RETRY;
+                }
+            }
+            return;
+        };
+        match client.poll(Duration::from_millis(200)) {
+            Ok(Some(msg)) => {
+                let mode = String::from_utf8_lossy(&msg.payload).trim().to_string();
+                if !mode.is_empty() {
+                    on_request(&mode);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("MQTT connection lost: {:#}", e);
+                mqtt = None;
+                retry_at = Instant::now() + MQTT_RETRY;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode(name: &str, cameras: Option<&[&str]>, rules: Option<&[&str]>) -> ModeConfig {
+        let owned = |list: &[&str]| list.iter().map(|s| s.to_string()).collect();
+        ModeConfig { name: name.into(), cameras: cameras.map(owned), rules: rules.map(owned), encoding: None, warm_spare: false }
+    }
+
+    #[test]
+    fn modes_pick_cameras_and_rules() {
+        let home = mode("home", Some(&["front"]), Some(&[]));
+        let away = mode("away", None, Some(&["doorbell"]));
+        let night = mode("night", Some(&["front", "cab"]), None);
+
+        assert!(!home.records("cab"));
+        assert!(away.records("cab"));
+        assert_eq!(home.arms("doorbell"), Some(false));
+        assert_eq!(away.arms("doorbell"), Some(true));
+        assert_eq!(night.arms("doorbell"), None);
+
+        assert!(night.is_valid(&["front", "cab"], &[]));
+        assert!(!night.is_valid(&["front"], &[]), "unknown camera");
+        assert!(!away.is_valid(&["front"], &["other"]), "unknown rule");
+        assert!(!mode("at home", None, None).is_valid(&[], &[]));
+
+        let modes = vec![home, away, night];
+        assert_eq!(initial_mode(&modes, None).unwrap().name, "home");
+        assert_eq!(initial_mode(&modes, Some("night")).unwrap().name, "night");
+        assert_eq!(initial_mode(&modes, Some("vacation")).unwrap().name, "home", "no longer configured");
+        assert_eq!(initial_mode(&[], Some("home")), None);
+    }
+
+    #[test]
+    fn mode_encoding_overrides_camera_settings() {
+        let camera = EncodingSettings { width: 1920, height: 1080, frame_rate: 30, bitrate_kbps: 2000 };
+        let mut parked = mode("parked", None, None);
+        assert_eq!(parked.encoding_for(camera), camera);
+
+        parked.encoding = Some(ModeEncoding { framerate: Some(5), bitrate_kbps: Some(600), ..Default::default() });
+        assert_eq!(
+            parked.encoding_for(camera),
+            EncodingSettings { frame_rate: 5, bitrate_kbps: 600, ..camera }
+        );
+        assert!(parked.is_valid(&[], &[]));
+
+        parked.encoding = Some(ModeEncoding { framerate: Some(0), ..Default::default() });
+        assert!(!parked.is_valid(&[], &[]));
+    }
+
+    #[test]
+    fn warm_spare_modes_keep_their_encodings_ready() {
+        let camera = EncodingSettings { width: 1920, height: 1080, frame_rate: 30, bitrate_kbps: 2000 };
+        let slow = ModeEncoding { framerate: Some(5), ..Default::default() };
+        let mut parked = mode("parked", None, None);
+        parked.encoding = Some(slow);
+        let mut night = mode("night", None, None);
+        night.encoding = Some(slow);
+        let driving = mode("driving", None, None);
+
+        let mut modes = vec![driving, parked, night];
+        assert!(spare_encodings(&modes, camera).is_empty(), "no warm_spare mode");
+
+        modes[0].warm_spare = true;
+        assert!(spare_encodings(&modes, camera).is_empty(), "same encoding as the camera");
+
+        modes[1].warm_spare = true;
+        modes[2].warm_spare = true;
+        let slow = EncodingSettings { frame_rate: 5, ..camera };
+        assert_eq!(spare_encodings(&modes, camera), vec![camera, slow]);
+    }
+}
@@ -0,0 +1,197 @@
+//! Motion detection (`[cameras.motion]` in config.toml).
+//!
+//! Frame differencing on a small, low-rate frame tap: each frame is reduced to
+//! luma and compared with the previous one, and when enough of the watched
+//! (ROI) pixels changed, the camera gets a `motion` event. An NVR camera with
+//! `record_mode = "motion"` has its segment file sinks triggered by it, see
+//! `pipeline_sinks::pre_roll`.
+
+use serde::Deserialize;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tracing::info;
+
+use crate::pipeline_sinks::frame_tap_sink::Frame;
+use crate::roi::Roi;
+
+/// How often `on_tick` runs when no frames arrive
+const TICK: Duration = Duration::from_secs(1);
+/// Longest pre-roll kept per sink
+pub const MAX_PRE_ROLL_SEC: u64 = 60;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MotionConfig {
+    /// Width of the analysed frames; height follows the camera's aspect ratio
+    pub width: u32,
+    /// Frames per second compared
+    pub fps: u32,
+    /// Luma change (0-255) for a pixel to count as changed
+    pub pixel_delta: u8,
+    /// Fraction of the watched pixels that must change for motion
+    pub min_area: f32,
+    /// `record_mode = "motion"`: seconds of video from before the motion that get recorded
+    pub pre_roll_sec: u64,
+    /// `record_mode = "motion"`: seconds recording goes on after the last motion
+    pub post_roll_sec: u64,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self { width: 160, fps: 5, pixel_delta: 25, min_area: 0.01, pre_roll_sec: 5, post_roll_sec: 30 }
+    }
+}
+
+impl MotionConfig {
+    pub fn is_valid(&self) -> bool {
+        self.width >= 16
+            && self.fps > 0
+            && self.pixel_delta > 0
+            && self.min_area > 0.0
+            && self.min_area <= 1.0
+            // the pre-roll is held in RAM
+            && self.pre_roll_sec <= MAX_PRE_ROLL_SEC
+    }
+}
+
+/// Compares each frame with the one before it, inside the ROI.
+pub struct MotionDetector {
+    pixel_delta: u8,
+    min_area: f32,
+    roi: Roi,
+    /// ROI mask for the current frame size, rebuilt if the size changes
+    mask: Vec<u8>,
+    size: (i32, i32),
+    previous: Option<Vec<u8>>,
+}
+
+impl MotionDetector {
+    pub fn new(cfg: &MotionConfig, roi: Roi) -> Self {
+        Self {
+            pixel_delta: cfg.pixel_delta,
+            min_area: cfg.min_area,
+            roi,
+            mask: Vec::new(),
+            size: (0, 0),
+            previous: None,
+        }
+    }
+
+    /// Fraction of watched pixels that changed since the previous frame, if it's
+    /// at least `min_area`. The first frame (and one after a size change) only primes.
+    pub fn feed(&mut self, frame: &Frame) -> Option<f32> {
+        if (frame.width, frame.height) != self.size {
+            self.size = (frame.width, frame.height);
+            self.mask = self.roi.mask(frame.width as u32, frame.height as u32);
+            self.previous = None;
+        }
+        let luma = luma(frame);
+        let previous = self.previous.replace(luma)?;
+        let current = self.previous.as_ref()?;
+
+        let mut watched = 0usize;
+        let mut changed = 0usize;
+        for ((now, before), mask) in current.iter().zip(&previous).zip(&self.mask) {
+            if *mask == 0 {
+                continue;
+            }
+            watched += 1;
+            if now.abs_diff(*before) >= self.pixel_delta {
+                changed += 1;
+            }
+        }
+        if watched == 0 {
+            return None;
+        }
+        let area = changed as f32 / watched as f32;
+        (area >= self.min_area).then_some(area)
+    }
+}
+
+/// Packed RGB rows (with their stride padding) to one luma byte per pixel.
+fn luma(frame: &Frame) -> Vec<u8> {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let mut luma = Vec::with_capacity(width * height);
+    for row in frame.data.chunks(frame.stride).take(height) {
+        for px in row[..width * 3].chunks_exact(3) {
+            luma.push(((77 * px[0] as u32 + 150 * px[1] as u32 + 29 * px[2] as u32) >> 8) as u8);
+        }
+    }
+    luma
+}
+
+/// Compare frames from `frames` (the tap caps the rate) and call `on_motion`
+/// with the changed area of every frame with motion. `on_tick` runs after every
+/// frame, and at least every second without frames, for the post-roll to run
+/// out (see `pre_roll`). Exits when the tap goes away.
+pub fn spawn_motion<M, T>(
+    camera_key: String,
+    cfg: MotionConfig,
+    roi: Roi,
+    frames: Receiver<Frame>,
+    on_motion: M,
+    on_tick: T,
+) -> JoinHandle<()>
+where
+    M: Fn(f32) + Send + 'static,
+    T: Fn() + Send + 'static,
+{
+    info!(
+        "Motion detection on camera '{}' ({}px wide at {} fps, min area {})",
+        camera_key, cfg.width, cfg.fps, cfg.min_area
+    );
+    std::thread::spawn(move || {
+        let mut detector = MotionDetector::new(&cfg, roi);
+        loop {
+            match frames.recv_timeout(TICK) {
+                Ok(frame) => {
+                    if let Some(area) = detector.feed(&frame) {
+                        on_motion(area);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            on_tick();
+        }
+        info!("Motion detection for camera '{}' exiting", camera_key);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 8x4 gray frame, rows padded to a 28-byte stride, with `lit` pixels (x, y) white
+    fn frame(lit: &[(usize, usize)]) -> Frame {
+        let stride = 28;
+        let mut data = vec![40u8; stride * 4];
+        for (x, y) in lit {
+            data[y * stride + x * 3..][..3].copy_from_slice(&[255, 255, 255]);
+        }
+        Frame { camera_key: "front".to_string(), width: 8, height: 4, stride, pts: None, data }
+    }
+
+    #[test]
+    fn counts_changed_pixels_inside_the_roi() {
+        let cfg = MotionConfig { min_area: 0.1, ..MotionConfig::default() };
+        let mut detector = MotionDetector::new(&cfg, Roi::default());
+        assert_eq!(detector.feed(&frame(&[])), None);
+        // 2 of 32 pixels is under the minimum area; then those 2 go dark and 4 others light up
+        assert_eq!(detector.feed(&frame(&[(0, 0), (1, 0)])), None);
+        assert_eq!(detector.feed(&frame(&[(0, 3), (1, 3), (2, 3), (3, 3)])), Some(0.1875));
+
+        // with the left half excluded, only the right-hand changes count
+        use crate::roi::{RoiConfig, RoiMode};
+        let roi = Roi::from_config(&[RoiConfig {
+            name: "street".to_string(),
+            mode: RoiMode::Exclude,
+            points: vec![[0.0, 0.0], [0.5, 0.0], [0.5, 1.0], [0.0, 1.0]],
+        }]);
+        let mut detector = MotionDetector::new(&cfg, roi);
+        detector.feed(&frame(&[]));
+        assert_eq!(detector.feed(&frame(&[(0, 0), (1, 0), (2, 0), (3, 0)])), None);
+        assert_eq!(detector.feed(&frame(&[(4, 1), (5, 1)])), Some(0.125));
+    }
+}
@@ -0,0 +1,291 @@
+//! Just enough MQTT 3.1.1 to listen on one topic and publish state: CONNECT,
+//! SUBSCRIBE and PUBLISH at QoS 0, and keep-alive pings. Used for on-demand
+//! requests from a phone app or home automation while parked, and for retained
+//! state topics (`[status_mqtt]`) they can show.
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const PINGREQ: u8 = 0xc0;
+/// Once the first byte of a packet is in, the rest should follow promptly
+const PACKET_TIMEOUT: Duration = Duration::from_secs(5);
+const STATUS_KEEP_ALIVE: Duration = Duration::from_secs(60);
+const STATUS_RETRY: Duration = Duration::from_secs(30);
+
+/// `[status_mqtt]`: broker that state changes are published to, retained.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StatusMqttConfig {
+    /// "host:port"
+    pub broker: String,
+    #[serde(default = "default_status_client_id")]
+    pub client_id: String,
+    /// Topics are `<topic_prefix>/<camera>/<what>`
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+}
+
+fn default_status_client_id() -> String {
+    "dashcam_rs_status".to_string()
+}
+
+fn default_topic_prefix() -> String {
+    "dashcam".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Publish {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+fn encode_remaining_length(mut len: usize, out: &mut Vec<u8>) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+fn push_str(s: &str, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![header];
+    encode_remaining_length(body.len(), &mut out);
+    out.extend_from_slice(body);
+    out
+}
+
+/// CONNECT with a clean session and no credentials.
+pub fn connect_packet(client_id: &str, keep_alive: Duration) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_str("MQTT", &mut body);
+    body.push(4); // protocol level 3.1.1
+    body.push(0x02); // clean session
+    body.extend_from_slice(&(keep_alive.as_secs().min(u16::MAX as u64) as u16).to_be_bytes());
+    push_str(client_id, &mut body);
+    packet(CONNECT, &body)
+}
+
+pub fn subscribe_packet(packet_id: u16, topic: &str) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    push_str(topic, &mut body);
+    body.push(0); // QoS 0
+    packet(SUBSCRIBE, &body)
+}
+
+/// PUBLISH at QoS 0; a retained one is handed to every later subscriber.
+pub fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_str(topic, &mut body);
+    body.extend_from_slice(payload);
+    packet(PUBLISH | retain as u8, &body)
+}
+
+/// Topic and payload of a PUBLISH packet body (`flags` are the header's low bits).
+pub fn parse_publish(flags: u8, body: &[u8]) -> Result<Publish> {
+    if body.len() < 2 {
+        bail!("PUBLISH too short");
+    }
+    let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    let mut pos = 2 + topic_len;
+    let topic = std::str::from_utf8(body.get(2..pos).context("PUBLISH topic cut off")?)?.to_string();
+    if (flags >> 1) & 0x03 > 0 {
+        pos += 2; // packet id, only there for QoS 1/2
+    }
+    Ok(Publish {
+        topic,
+        payload: body.get(pos..).context("PUBLISH cut off")?.to_vec(),
+    })
+}
+
+pub struct MqttClient {
+    stream: TcpStream,
+    keep_alive: Duration,
+    last_sent: Instant,
+}
+
+impl MqttClient {
+    /// Connect to `broker` ("host:port") without subscribing to anything.
+    pub fn connect(broker: &str, client_id: &str, keep_alive: Duration) -> Result<Self> {
+        let stream = TcpStream::connect(broker).with_context(|| format!("Can't reach MQTT broker {}", broker))?;
+        stream.set_read_timeout(Some(PACKET_TIMEOUT))?;
+        let mut client = Self { stream, keep_alive, last_sent: Instant::now() };
+
+        client.send(&connect_packet(client_id, keep_alive))?;
+        let (header, body) = client.read_packet()?;
+        if header & 0xf0 != CONNACK || body.len() < 2 {
+            bail!("Expected CONNACK from {}", broker);
+        }
+        if body[1] != 0 {
+            bail!("MQTT broker {} refused the connection (code {})", broker, body[1]);
+        }
+        Ok(client)
+    }
+
+    /// Connect to `broker` ("host:port") and subscribe to `topic`.
+    pub fn subscribe(broker: &str, client_id: &str, topic: &str, keep_alive: Duration) -> Result<Self> {
+        let mut client = Self::connect(broker, client_id, keep_alive)?;
+        client.send(&subscribe_packet(1, topic))?;
+        loop {
+            let (header, body) = client.read_packet()?;
+            if header == SUBACK {
+                if body.get(2) == Some(&0x80) {
+                    bail!("MQTT broker {} refused the subscription to {}", broker, topic);
+                }
+                return Ok(client);
+            }
+        }
+    }
+
+    pub fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<()> {
+        self.send(&publish_packet(topic, payload, retain))
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        self.stream.write_all(bytes)?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    fn read_packet(&mut self) -> Result<(u8, Vec<u8>)> {
+        let mut header = [0u8; 1];
+        self.stream.read_exact(&mut header)?;
+        self.read_rest(header[0])
+    }
+
+    fn read_rest(&mut self, header: u8) -> Result<(u8, Vec<u8>)> {
+        let mut len = 0usize;
+        for shift in 0..4 {
+            let mut byte = [0u8; 1];
+            self.stream.read_exact(&mut byte)?;
+            len |= ((byte[0] & 0x7f) as usize) << (7 * shift);
+            if byte[0] & 0x80 == 0 {
+                let mut body = vec![0u8; len];
+                self.stream.read_exact(&mut body)?;
+                return Ok((header, body));
+            }
+        }
+        bail!("Malformed MQTT packet length")
+    }
+
+    /// Next message on the subscription, or `None` if nothing came within `timeout`.
+    /// Pings the broker as the keep-alive requires.
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<Publish>> {
+        if self.last_sent.elapsed() >= self.keep_alive / 2 {
+            self.send(&[PINGREQ, 0])?;
+        }
+        self.stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let mut header = [0u8; 1];
+        let read = self.stream.read(&mut header);
+        self.stream.set_read_timeout(Some(PACKET_TIMEOUT))?;
+        match read {
+            Ok(0) => bail!("MQTT broker closed the connection"),
+            Ok(_) => {
+                let (header, body) = self.read_rest(header[0])?;
+                if header & 0xf0 == PUBLISH {
+                    return Ok(Some(parse_publish(header & 0x0f, &body)?));
+                }
+                Ok(None)
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Publishes retained state topics under `[status_mqtt]`, connecting on demand.
+/// A failed publish is dropped; the next state change tries a fresh connection.
+pub struct StatusPublisher {
+    cfg: StatusMqttConfig,
+    client: Option<MqttClient>,
+    retry_at: Instant,
+}
+
+impl StatusPublisher {
+    pub fn new(cfg: StatusMqttConfig) -> Self {
+        Self { cfg, client: None, retry_at: Instant::now() }
+    }
+
+    /// `<topic_prefix>/<camera_key>/<what>`
+    pub fn topic(&self, camera_key: &str, what: &str) -> String {
+        format!("{}/{}/{}", self.cfg.topic_prefix, camera_key, what)
+    }
+
+    pub fn publish(&mut self, camera_key: &str, what: &str, value: &str) {
+        let topic = self.topic(camera_key, what);
+        if self.client.is_none() && Instant::now() >= self.retry_at {
+            match MqttClient::connect(&self.cfg.broker, &self.cfg.client_id, STATUS_KEEP_ALIVE) {
+                Ok(client) => {
+                    info!("Publishing state to MQTT broker {}", self.cfg.broker);
+                    self.client = Some(client);
+                }
+                Err(e) => {
+                    warn!("{:#}; retrying in {:?}", e, STATUS_RETRY);
+                    self.retry_at = Instant::now() + STATUS_RETRY;
+                }
+            }
+        }
+        let Some(client) = &mut self.client else {
+            warn!("Not published: {} = {}", topic, value);
+            return;
+        };
+        if let Err(e) = client.publish(&topic, value.as_bytes(), true) {
+            warn!("MQTT publish of {} failed: {:#}", topic, e);
+            self.client = None;
+        }
+    }
+
+    /// Keep the connection alive between state changes; call a few times a second.
+    pub fn tick(&mut self) {
+        if let Some(client) = &mut self.client
+            && let Err(e) = client.poll(Duration::from_millis(1))
+        {
+            warn!("MQTT connection lost: {:#}", e);
+            self.client = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_follow_the_3_1_1_layout() {
+        let connect = connect_packet("cam", Duration::from_secs(60));
+        assert_eq!(connect, [&[0x10, 15, 0, 4][..], b"MQTT", &[4, 0x02, 0, 60, 0, 3], b"cam"].concat());
+
+        let mut long = Vec::new();
+        encode_remaining_length(321, &mut long);
+        assert_eq!(long, vec![0xc1, 0x02]);
+
+        let body = [&[0, 5][..], b"a/b/c", b"front"].concat();
+        let publish = parse_publish(0, &body).unwrap();
+        assert_eq!((publish.topic.as_str(), publish.payload.as_slice()), ("a/b/c", &b"front"[..]));
+        let qos1 = [&[0, 1][..], b"t", &[0, 7], b"x"].concat();
+        assert_eq!(parse_publish(0x02, &qos1).unwrap().payload, b"x");
+
+        let retained = publish_packet("dashcam/cab/privacy", b"on", true);
+        assert_eq!(retained[..2], [0x31, 23]);
+        assert_eq!(parse_publish(retained[0] & 0x0f, &retained[2..]).unwrap().payload, b"on");
+    }
+}
@@ -0,0 +1,213 @@
+//! OBD-II telemetry through an ELM327 adapter (`[obd]` in config.toml).
+//!
+//! USB adapters show up as `/dev/ttyUSB*`, Bluetooth ones as `/dev/rfcomm*`
+//! once bound with `rfcomm`. Speed, RPM and throttle are polled every
+//! `poll_ms` and stored in the `telemetry` table on the same clock as the
+//! segments, so a segment's samples are the rows inside its start/end. In a
+//! tunnel, where the GPS has no fix, `DashcamDb::speed_at` falls back to them.
+//!
+//! The port is set up with `stty` (raw, `baud`, reads time out after 1 s) and
+//! reopened after a few seconds whenever the adapter stops answering.
+
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use serde::Deserialize;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::process::Command;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::db::db::TelemetrySample;
+use crate::db::db_worker::DBMessage;
+use crate::worker::{Worker, sleep_unless_stopped};
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+const REOPEN_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ObdConfig {
+    /// Serial device of the adapter
+    pub device: String,
+    /// Ignored by Bluetooth (rfcomm) ports
+    pub baud: u32,
+    pub poll_ms: u64,
+}
+
+impl Default for ObdConfig {
+    fn default() -> Self {
+        Self {
+            device: "/dev/rfcomm0".to_string(),
+            baud: 38400,
+            poll_ms: 1000,
+        }
+    }
+}
+
+impl ObdConfig {
+    pub fn is_valid(&self) -> bool {
+        self.device.starts_with('/') && self.baud > 0 && self.poll_ms > 0
+    }
+}
+
+/// Mode 01 PIDs that are polled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pid {
+    /// km/h
+    Speed,
+    /// revolutions per minute
+    Rpm,
+    /// percent
+    Throttle,
+}
+
+impl Pid {
+    pub fn code(&self) -> u8 {
+        match self {
+            Pid::Speed => 0x0D,
+            Pid::Rpm => 0x0C,
+            Pid::Throttle => 0x11,
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<f64> {
+        match (self, data) {
+            (Pid::Speed, [a, ..]) => Some(f64::from(*a)),
+            (Pid::Rpm, [a, b, ..]) => Some((256.0 * f64::from(*a) + f64::from(*b)) / 4.0),
+            (Pid::Throttle, [a, ..]) => Some(f64::from(*a) * 100.0 / 255.0),
+            _ => None,
+        }
+    }
+}
+
+/// Value of `pid` in an ELM327 reply to `01<pid>` (headers off). Replies can
+/// carry `SEARCHING...`, spaces and several ECUs' lines; the first line with
+/// the answer wins. `NO DATA` and the like give `None`.
+pub fn parse_pid_response(pid: Pid, reply: &str) -> Option<f64> {
+    let header = format!("41{:02X}", pid.code());
+    reply.split(['\r', '\n', '>']).find_map(|line| {
+        let hex = line
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_uppercase();
+        let data = hex.strip_prefix(&header)?;
+        let bytes: Vec<u8> = data
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+            .collect::<Option<_>>()?;
+        pid.decode(&bytes)
+    })
+}
+
+/// An open adapter.
+struct Elm327 {
+    port: File,
+}
+
+impl Elm327 {
+    fn open(cfg: &ObdConfig) -> Result<Self> {
+        let status = Command::new("stty")
+            .args(["-F", &cfg.device, &cfg.baud.to_string(), "raw", "-echo", "min", "0", "time", "10"])
+            .status()
+            .context("Can't run stty")?;
+        if !status.success() {
+            bail!("stty failed on {}", cfg.device);
+        }
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&cfg.device)
+            .with_context(|| format!("Can't open {}", cfg.device))?;
+        let mut elm = Self { port };
+        // reset, echo/linefeeds/headers off, automatic protocol
+        for command in ["ATZ", "ATE0", "ATL0", "ATH0", "ATSP0"] {
+            elm.command(command)?;
+        }
+        Ok(elm)
+    }
+
+    /// Send `command` and read up to the `>` prompt.
+    fn command(&mut self, command: &str) -> Result<String> {
+        self.port.write_all(format!("{}\r", command).as_bytes())?;
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        let mut reply = Vec::new();
+        let mut buf = [0u8; 64];
+        while Instant::now() < deadline {
+            let n = self.port.read(&mut buf)?;
+            reply.extend_from_slice(&buf[..n]);
+            if reply.contains(&b'>') {
+                return Ok(String::from_utf8_lossy(&reply).into_owned());
+            }
+        }
+        bail!("No reply to '{}' within {:?}", command, RESPONSE_TIMEOUT)
+    }
+
+    fn query(&mut self, pid: Pid) -> Result<Option<f64>> {
+        let reply = self.command(&format!("01{:02X}", pid.code()))?;
+        Ok(parse_pid_response(pid, &reply))
+    }
+
+    fn sample(&mut self) -> Result<TelemetrySample> {
+        Ok(TelemetrySample {
+            at_utc: Utc::now().timestamp(),
+            speed_kmh: self.query(Pid::Speed)?,
+            rpm: self.query(Pid::Rpm)?,
+            throttle_pct: self.query(Pid::Throttle)?,
+        })
+    }
+}
+
+/// Polls the adapter every `poll_ms` until stopped.
+pub fn spawn_obd(cfg: &ObdConfig, db_sender: Arc<Sender<DBMessage>>) -> Worker {
+    let cfg = cfg.clone();
+    let mut elm: Option<Elm327> = None;
+
+    Worker::spawn("obd", Duration::from_millis(cfg.poll_ms), move |stop| {
+        let Some(adapter) = elm.as_mut() else {
+            match Elm327::open(&cfg) {
+                Ok(adapter) => {
+                    info!("OBD adapter on {} ready", cfg.device);
+                    elm = Some(adapter);
+                }
+                Err(e) => {
+                    debug!("OBD adapter on {}: {:#}", cfg.device, e);
+                    sleep_unless_stopped(REOPEN_DELAY, stop);
+                }
+            }
+            return;
+        };
+        match adapter.sample() {
+            Ok(sample) => {
+                let _ = db_sender.send(DBMessage::InsertTelemetry { sample });
+            }
+            Err(e) => {
+                warn!("OBD adapter on {} stopped answering: {:#}", cfg.device, e);
+                elm = None;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_elm327_replies() {
+        assert_eq!(parse_pid_response(Pid::Speed, "41 0D 3C \r\r>"), Some(60.0));
+        assert_eq!(parse_pid_response(Pid::Speed, "SEARCHING...\r410D00\r\r>"), Some(0.0));
+        assert_eq!(parse_pid_response(Pid::Rpm, "41 0C 1A F8\r>"), Some(1726.0));
+        assert_eq!(parse_pid_response(Pid::Throttle, "41 11 FF\r>"), Some(100.0));
+        // a second ECU answering as well
+        assert_eq!(parse_pid_response(Pid::Speed, "7E8 NO\r41 0D 50\r41 0D 51\r>"), Some(80.0));
+
+        assert_eq!(parse_pid_response(Pid::Speed, "NO DATA\r\r>"), None);
+        assert_eq!(parse_pid_response(Pid::Rpm, "41 0C 1A\r>"), None, "RPM needs two bytes");
+        assert_eq!(parse_pid_response(Pid::Speed, "41 0C 1A F8\r>"), None, "answer to another PID");
+    }
+}
@@ -0,0 +1,231 @@
+use crate::config::VideoCodec;
+use crate::events::CameraEvent;
+use crate::pipeline_sources::encoder::{decoder_factory, parser_factory};
+use crate::pipeline_sources::pipeline_source::StreamFormat;
+use crate::recording_pipeline::RecordingConfig;
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use super::pipeline_sink::PipelineSink;
+
+/// Burst: on an event, dump `frames` full-resolution JPEGs spread over the last
+/// `window_sec` seconds into `<recording_dir>/burst_<sink_id>/<YYYY-MM-DD>/<HH-MM-SS>_<kind>/`.
+///
+/// queue(leaky, window_sec deep) -> parser -> decoder -> videorate -> capsfilter -> videoconvert -> jpegenc -> multifilesink
+///
+/// The queue's src pad is held by a blocking probe, so between events it just keeps
+/// the most recent `window_sec` of encoded video and nothing gets decoded.
+/// An event lifts the block, the pre-roll drains through the decoder, and the
+/// block goes back on once `frames` JPEGs are out.
+pub struct BurstPipelineSink {
+    config: RecordingConfig,
+    sink_id: i64,
+    frames: u32,
+    window_sec: u64,
+    codec: VideoCodec,
+    queue: Option<gst::Element>,
+    sink: Option<gst::Element>,
+    state: Arc<Mutex<BurstState>>,
+}
+
+#[derive(Default)]
+struct BurstState {
+    block_probe: Option<gst::PadProbeId>,
+    // frames left in the current burst; 0 = idle
+    remaining: u32,
+    // decoder needs a keyframe after the gap, drop deltas until one comes
+    waiting_for_keyframe: bool,
+}
+
+impl BurstPipelineSink {
+    pub fn new(config: RecordingConfig, sink_id: i64, frames: u32, window_sec: u64, codec: VideoCodec) -> Self {
+        BurstPipelineSink {
+            config,
+            sink_id,
+            frames: frames.max(1),
+            window_sec: window_sec.max(1),
+            codec,
+            queue: None,
+            sink: None,
+            state: Arc::new(Mutex::new(BurstState::default())),
+        }
+    }
+
+    fn output_dir(&self) -> PathBuf {
+        PathBuf::from(&self.config.recording_dir).join(format!("burst_{}", self.sink_id))
+    }
+}
+
+fn block(pad: &gst::Pad) -> Option<gst::PadProbeId> {
+    pad.add_probe(gst::PadProbeType::BLOCK | gst::PadProbeType::BUFFER, |_, _| {
+        gst::PadProbeReturn::Ok
+    })
+}
+
+impl PipelineSink for BurstPipelineSink {
+    fn get_sink_pad(&self) -> Result<gst::Pad> {
+        self.queue
+            .as_ref()
+            .context("Queue element not initialized")?
+            .static_pad("sink")
+            .context("Failed to get sink pad from queue")
+    }
+
+    fn get_sink_element(&self) -> Result<gst::Element> {
+        self.sink.clone().context("Sink element not initialized")
+    }
+
+    fn sink_id(&self) -> i64 {
+        self.sink_id
+    }
+
+    fn accepts(&self, format: StreamFormat) -> bool {
+        format == StreamFormat::Encoded(self.codec)
+    }
+
+    fn setup_sink(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
+        let sink_id = self.sink_id;
+        let make = |factory: &str, role: &str| {
+            gst::ElementFactory::make(factory)
+                .name(format!("burst_{}_{}", role, sink_id))
+                .build()
+                .with_context(|| format!("Failed to create {}", factory))
+        };
+
+        let queue = make("queue", "queue")?;
+        let parser = make(parser_factory(self.codec), "parser")?;
+        let decoder = make(decoder_factory(self.codec), "decoder")?;
+        let videorate = make("videorate", "videorate")?;
+        let capsfilter = make("capsfilter", "capsfilter")?;
+        let videoconvert = make("videoconvert", "videoconvert")?;
+        let jpegenc = make("jpegenc", "jpegenc")?;
+        let sink = make("multifilesink", "sink")?;
+
+        // Pre-roll: keep only the newest window_sec of video while blocked
+        queue.set_property("max-size-time", self.window_sec * 1_000_000_000u64);
+        queue.set_property("max-size-buffers", 0u32);
+        queue.set_property("max-size-bytes", 0u32);
+        queue.set_property_from_str("leaky", "downstream");
+
+        // `frames` evenly over the window
+        videorate.set_property("drop-only", true);
+        let caps = gst::Caps::builder("video/x-raw")
+            .field("framerate", gst::Fraction::new(self.frames as i32, self.window_sec as i32))
+            .build();
+        capsfilter.set_property("caps", &caps);
+
+        fs::create_dir_all(self.output_dir())?;
+        sink.set_property(
+            "location",
+            self.output_dir().join("frame_%02d.jpg").to_string_lossy().to_string(),
+        );
+
+        let elements = [&queue, &parser, &decoder, &videorate, &capsfilter, &videoconvert, &jpegenc, &sink];
+        pipeline
+            .add_many(elements)
+            .context("Failed to add burst elements to pipeline")?;
+        gst::Element::link_many(elements).context("Failed to link burst elements")?;
+
+        let queue_src = queue.static_pad("src").context("Failed to get src pad from burst queue")?;
+
+        // Skip to the first keyframe after the block is lifted
+        let state = self.state.clone();
+        queue_src.add_probe(gst::PadProbeType::BUFFER, move |_, probe_info| {
+            let mut state = state.lock().unwrap();
+            if !state.waiting_for_keyframe {
+                return gst::PadProbeReturn::Ok;
+            }
+            match probe_info.buffer() {
+                Some(buffer) if buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) => {
+                    gst::PadProbeReturn::Drop
+                }
+                _ => {
+                    state.waiting_for_keyframe = false;
+                    gst::PadProbeReturn::Ok
+                }
+            }
+        });
+
+        // Count JPEGs; once the burst is done drop the rest and block the queue again
+        let state = self.state.clone();
+        let block_pad = queue_src.clone();
+        jpegenc
+            .static_pad("src")
+            .context("Failed to get src pad from jpegenc")?
+            .add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                let mut state = state.lock().unwrap();
+                if state.remaining == 0 {
+                    return gst::PadProbeReturn::Drop;
+                }
+                state.remaining -= 1;
+                if state.remaining == 0 && state.block_probe.is_none() {
+                    state.block_probe = block(&block_pad);
+                }
+                gst::PadProbeReturn::Ok
+            });
+
+        // A blocked queue would hold EOS back forever and stop_pipeline() would hang
+        let state = self.state.clone();
+        let block_pad = queue_src.clone();
+        queue
+            .static_pad("sink")
+            .context("Failed to get sink pad from burst queue")?
+            .add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_, probe_info| {
+                if let Some(gst::EventView::Eos(..)) = probe_info.event().map(|e| e.view())
+                    && let Some(id) = state.lock().unwrap().block_probe.take()
+                {
+                    block_pad.remove_probe(id);
+                }
+                gst::PadProbeReturn::Ok
+            });
+
+        self.state.lock().unwrap().block_probe = block(&queue_src);
+
+        self.queue = Some(queue);
+        self.sink = Some(sink);
+
+        info!(
+            "Burst sink {} ready ({} frames over {}s on events)",
+            sink_id, self.frames, self.window_sec
+        );
+        Ok(())
+    }
+
+    fn on_event(&self, event: &CameraEvent) {
+        let (Some(queue), Some(sink)) = (&self.queue, &self.sink) else {
+            return;
+        };
+        let Some(queue_src) = queue.static_pad("src") else {
+            return;
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if state.remaining > 0 {
+            // already bursting; this event's frames are being captured anyway
+            return;
+        }
+
+        let dir = self
+            .output_dir()
+            .join(event.at.format("%Y-%m-%d").to_string())
+            .join(format!("{}_{}", event.at.format("%H-%M-%S"), event.kind));
+        if let Err(e) = fs::create_dir_all(&dir) {
+            warn!("Burst sink {}: can't create {}: {}", self.sink_id, dir.display(), e);
+            return;
+        }
+        sink.set_property("location", dir.join("frame_%02d.jpg").to_string_lossy().to_string());
+        sink.set_property("index", 0i32);
+
+        state.remaining = self.frames;
+        state.waiting_for_keyframe = true;
+        if let Some(id) = state.block_probe.take() {
+            queue_src.remove_probe(id);
+        }
+        info!("Burst sink {}: capturing {} frames into {}", self.sink_id, self.frames, dir.display());
+    }
+}
@@ -0,0 +1,203 @@
+//! `disks = { ... }` on an NvrTs sink: segments spread over several mounted
+//! disks instead of the recording root.
+//!
+//! With `spread = "fill"` each segment goes to the first disk in `mounts`
+//! with at least `min_free_mb` free; `round_robin` takes turns. A mount point
+//! that isn't mounted (same device as its parent directory) is skipped, so a
+//! disk missing at startup or pulled later doesn't end up filling the root
+//! filesystem, and is used again once it's back. When every disk is short
+//! on space the roomiest one is used; with none mounted, segments go to the
+//! recording root.
+//!
+//! A segment on a disk lives at `<mount>/<camera key>/<YYYY-MM-DD>/<HH-MM-SS>.ts`
+//! and is catalogued with its mount point in `segments.disk`.
+
+use serde::Deserialize;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+fn default_min_free_mb() -> u64 {
+    1024
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiskSpread {
+    /// First disk with room, in `mounts` order
+    #[default]
+    Fill,
+    RoundRobin,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DisksConfig {
+    /// Mount points of the disks
+    pub mounts: Vec<String>,
+    #[serde(default)]
+    pub spread: DiskSpread,
+    /// A disk with less free space is only used when all of them are
+    #[serde(default = "default_min_free_mb")]
+    pub min_free_mb: u64,
+}
+
+impl DisksConfig {
+    /// Why these disks can't be used, if they can't.
+    pub fn problem(&self) -> Option<String> {
+        if self.mounts.is_empty() {
+            return Some("disks needs at least one mount point".to_string());
+        }
+        if let Some(relative) = self.mounts.iter().find(|m| !Path::new(m).is_absolute()) {
+            return Some(format!("disk mount point '{}' must be absolute", relative));
+        }
+        if self.mounts.iter().enumerate().any(|(i, m)| self.mounts[..i].contains(m)) {
+            return Some("disk mount points must be distinct".to_string());
+        }
+        None
+    }
+}
+
+/// Whether something is mounted at `path`, rather than it being an empty
+/// directory on the filesystem above.
+pub fn is_mounted(path: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    match path.parent().map(fs::metadata) {
+        Some(Ok(parent)) => metadata.is_dir() && parent.dev() != metadata.dev(),
+        Some(Err(_)) => false,
+        None => metadata.is_dir(),
+    }
+}
+
+/// Space available to unprivileged writers at `path`, in MB.
+pub fn free_mb(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs only writes into the struct we hand it
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64 / 1_000_000)
+}
+
+/// Picks the disk for each segment of one sink.
+#[derive(Debug)]
+pub struct DiskSet {
+    config: DisksConfig,
+    state: Mutex<PickState>,
+}
+
+#[derive(Debug)]
+struct PickState {
+    /// Where round-robin continues
+    next: usize,
+    /// Whether each disk was mounted at the last pick, to log changes once
+    mounted: Vec<bool>,
+}
+
+impl DiskSet {
+    pub fn new(config: DisksConfig) -> Self {
+        let mounted = vec![true; config.mounts.len()];
+        Self { config, state: Mutex::new(PickState { next: 0, mounted }) }
+    }
+
+    /// Mount point for the next segment, `None` if no disk is mounted.
+    pub fn pick(&self) -> Option<String> {
+        self.pick_with(|mount| {
+            let mount = Path::new(mount);
+            if is_mounted(mount) { free_mb(mount) } else { None }
+        })
+    }
+
+    /// `pick` with `free_mb` giving each mount point's free space, `None` when it isn't mounted.
+    fn pick_with(&self, free_mb: impl Fn(&str) -> Option<u64>) -> Option<String> {
+        let mounts = &self.config.mounts;
+        let free: Vec<Option<u64>> = mounts.iter().map(|m| free_mb(m)).collect();
+        let mut state = self.state.lock().unwrap();
+        for (i, mount) in mounts.iter().enumerate() {
+            let mounted = free[i].is_some();
+            if mounted != state.mounted[i] {
+                if mounted {
+                    info!("Disk {} is mounted again, recording to it", mount);
+                } else {
+                    warn!("Disk {} isn't mounted, skipping it", mount);
+                }
+                state.mounted[i] = mounted;
+            }
+        }
+
+        let n = mounts.len();
+        let start = match self.config.spread {
+            DiskSpread::Fill => 0,
+            DiskSpread::RoundRobin => state.next,
+        };
+        let roomy = (0..n).map(|k| (start + k) % n).find(|&i| free[i].is_some_and(|f| f >= self.config.min_free_mb));
+        let chosen = roomy.or_else(|| (0..n).filter(|&i| free[i].is_some()).max_by_key(|&i| free[i]))?;
+        state.next = (chosen + 1) % n;
+        Some(mounts[chosen].clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn disks(spread: DiskSpread) -> DiskSet {
+        DiskSet::new(DisksConfig {
+            mounts: vec!["/mnt/a".into(), "/mnt/b".into(), "/mnt/c".into()],
+            spread,
+            min_free_mb: 100,
+        })
+    }
+
+    #[test]
+    fn fills_in_order_or_takes_turns_around_missing_disks() {
+        let free: HashMap<&str, u64> = [("/mnt/a", 50), ("/mnt/b", 500), ("/mnt/c", 900)].into();
+        let fill = disks(DiskSpread::Fill);
+        assert_eq!(fill.pick_with(|m| free.get(m).copied()).as_deref(), Some("/mnt/b"), "a is full");
+        assert_eq!(fill.pick_with(|m| free.get(m).copied()).as_deref(), Some("/mnt/b"));
+
+        // b missing at startup, back later
+        let round_robin = disks(DiskSpread::RoundRobin);
+        let without_b = |m: &str| (m != "/mnt/b").then_some(300);
+        let picks: Vec<_> = (0..3).filter_map(|_| round_robin.pick_with(without_b)).collect();
+        assert_eq!(picks, ["/mnt/a", "/mnt/c", "/mnt/a"]);
+        assert_eq!(round_robin.pick_with(|_| Some(300)).as_deref(), Some("/mnt/b"));
+        assert_eq!(round_robin.pick_with(|_| Some(300)).as_deref(), Some("/mnt/c"));
+
+        // everything short on space: the roomiest
+        assert_eq!(fill.pick_with(|m| Some(if m == "/mnt/c" { 80 } else { 10 })).as_deref(), Some("/mnt/c"));
+        assert_eq!(fill.pick_with(|_| None), None);
+    }
+
+    #[test]
+    fn an_empty_mount_point_is_not_mounted() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let mount = tmp.path().join("nvr1");
+        assert!(!is_mounted(&mount));
+        fs::create_dir(&mount).unwrap();
+        assert!(!is_mounted(&mount), "same filesystem as its parent");
+        assert!(is_mounted(Path::new("/")));
+        assert!(free_mb(tmp.path()).is_some());
+    }
+
+    #[test]
+    fn mount_points_must_be_absolute_and_distinct() {
+        let config = |mounts: &[&str]| DisksConfig {
+            mounts: mounts.iter().map(|m| m.to_string()).collect(),
+            spread: DiskSpread::Fill,
+            min_free_mb: 0,
+        };
+        assert_eq!(config(&["/mnt/a", "/mnt/b"]).problem(), None);
+        assert!(config(&[]).problem().is_some());
+        assert!(config(&["mnt/a"]).problem().is_some());
+        assert!(config(&["/mnt/a", "/mnt/a"]).problem().is_some());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::config::VideoCodec;
+use crate::pipeline_sources::encoder::{decoder_factory, parser_factory};
+use crate::pipeline_sources::pipeline_source::StreamFormat;
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use tracing::{info, trace};
+
+use super::pipeline_sink::PipelineSink;
+
+/// One decoded RGB frame handed to an analyzer.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub camera_key: String,
+    pub width: i32,
+    pub height: i32,
+    /// Packed RGB; each row is `stride` bytes (GStreamer pads rows to 4 bytes)
+    pub stride: usize,
+    pub pts: Option<gst::ClockTime>,
+    pub data: Vec<u8>,
+}
+
+pub type FrameHandler = Arc<dyn Fn(Frame) + Send + Sync>;
+
+/// What a tap wants: at most `max_fps` frames per second, optionally scaled.
+#[derive(Debug, Clone)]
+pub struct FrameTapConfig {
+    pub max_fps: u32,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+}
+
+impl Default for FrameTapConfig {
+    fn default() -> Self {
+        Self {
+            max_fps: 2,
+            width: None,
+            height: None,
+        }
+    }
+}
+
+/// Where frames go. Both are called on the streaming thread and never block it:
+/// a slow analyzer just misses frames.
+#[derive(Clone)]
+pub enum FrameTarget {
+    Callback(FrameHandler),
+    Channel(SyncSender<Frame>),
+}
+
+impl FrameTarget {
+    /// Bounded channel; frames arriving while it's full are dropped.
+    pub fn channel(capacity: usize) -> (Self, Receiver<Frame>) {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        (FrameTarget::Channel(tx), rx)
+    }
+
+    fn deliver(&self, frame: Frame) {
+        match self {
+            FrameTarget::Callback(handler) => handler(frame),
+            FrameTarget::Channel(tx) => match tx.try_send(frame) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => trace!("Frame tap receiver busy, dropping frame"),
+                Err(TrySendError::Disconnected(_)) => {}
+            },
+        }
+    }
+}
+
+/// Decoded-frame tap for external analyzers (plate readers, person detection, ...).
+///
+/// queue -> parser -> decoder -> queue(leaky) -> videorate -> videoscale -> videoconvert -> capsfilter(RGB) -> appsink
+///
+/// Registered through `RecordingPipeline::add_frame_tap`/`CamService::add_frame_tap`,
+/// not config.toml.
+pub struct FrameTapSink {
+    camera_key: String,
+    sink_id: i64,
+    codec: VideoCodec,
+    tap_config: FrameTapConfig,
+    target: FrameTarget,
+    queue: Option<gst::Element>,
+    sink: Option<gst::Element>,
+}
+
+impl FrameTapSink {
+    pub fn new(
+        camera_key: String,
+        sink_id: i64,
+        codec: VideoCodec,
+        tap_config: FrameTapConfig,
+        target: FrameTarget,
+    ) -> Self {
+        FrameTapSink {
+            camera_key,
+            sink_id,
+            codec,
+            tap_config,
+            target,
+            queue: None,
+            sink: None,
+        }
+    }
+}
+
+fn sample_to_frame(camera_key: &str, sample: &gst::Sample) -> Option<Frame> {
+    let buffer = sample.buffer()?;
+    let structure = sample.caps()?.structure(0)?;
+    let width = structure.get::<i32>("width").ok()?;
+    let height = structure.get::<i32>("height").ok()?;
+    let map = buffer.map_readable().ok()?;
+
+    Some(Frame {
+        camera_key: camera_key.to_string(),
+        width,
+        height,
+        stride: map.len() / height.max(1) as usize,
+        pts: buffer.pts(),
+        data: map.as_slice().to_vec(),
+    })
+}
+
+impl PipelineSink for FrameTapSink {
+    fn get_sink_pad(&self) -> Result<gst::Pad> {
+        self.queue
+            .as_ref()
+            .context("Queue element not initialized")?
+            .static_pad("sink")
+            .context("Failed to get sink pad from queue")
+    }
+
+    fn get_sink_element(&self) -> Result<gst::Element> {
+        self.sink.clone().context("Sink element not initialized")
+    }
+
+    fn sink_id(&self) -> i64 {
+        self.sink_id
+    }
+
+    fn accepts(&self, format: StreamFormat) -> bool {
+        format == StreamFormat::Encoded(self.codec)
+    }
+
+    fn setup_sink(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
+        let sink_id = self.sink_id;
+        let make = |factory: &str, role: &str| {
+            gst::ElementFactory::make(factory)
+                .name(format!("frame_tap_{}_{}", role, sink_id))
+                .build()
+                .with_context(|| format!("Failed to create {}", factory))
+        };
+
+        let queue = make("queue", "queue")?;
+        let parser = make(parser_factory(self.codec), "parser")?;
+        let decoder = make(decoder_factory(self.codec), "decoder")?;
+        let raw_queue = make("queue", "raw_queue")?;
+        let videorate = make("videorate", "videorate")?;
+        let videoscale = make("videoscale", "videoscale")?;
+        let videoconvert = make("videoconvert", "videoconvert")?;
+        let capsfilter = make("capsfilter", "capsfilter")?;
+        let sink = make("appsink", "sink")?;
+
+        // Decoded frames are independent, so this is where a slow consumer sheds load
+        raw_queue.set_property("max-size-buffers", 2u32);
+        raw_queue.set_property("max-size-time", 0u64);
+        raw_queue.set_property("max-size-bytes", 0u32);
+        raw_queue.set_property_from_str("leaky", "downstream");
+
+        videorate.set_property("drop-only", true);
+        videorate.set_property("max-rate", self.tap_config.max_fps.max(1) as i32);
+
+        let mut caps = gst::Caps::builder("video/x-raw").field("format", "RGB");
+        if let Some(width) = self.tap_config.width {
+            caps = caps.field("width", width);
+        }
+        if let Some(height) = self.tap_config.height {
+            caps = caps.field("height", height);
+        }
+        capsfilter.set_property("caps", caps.build());
+
+        sink.set_property("emit-signals", true);
+        sink.set_property("sync", false);
+        sink.set_property("max-buffers", 1u32);
+        sink.set_property("drop", true);
+
+        let camera_key = self.camera_key.clone();
+        let target = self.target.clone();
+        sink.connect("new-sample", false, move |args| {
+            let appsink = args[0].get::<gst::Element>().ok()?;
+            if let Some(sample) = appsink.emit_by_name::<Option<gst::Sample>>("pull-sample", &[])
+                && let Some(frame) = sample_to_frame(&camera_key, &sample)
+            {
+                target.deliver(frame);
+            }
+            Some(gst::FlowReturn::Ok.to_value())
+        });
+
+        let elements = [
+            &queue,
+            &parser,
+            &decoder,
+            &raw_queue,
+            &videorate,
+            &videoscale,
+            &videoconvert,
+            &capsfilter,
+            &sink,
+        ];
+        pipeline
+            .add_many(elements)
+            .context("Failed to add frame tap elements to pipeline")?;
+        gst::Element::link_many(elements).context("Failed to link frame tap elements")?;
+
+        self.queue = Some(queue);
+        self.sink = Some(sink);
+
+        info!(
+            "Frame tap {} ready on camera '{}' (max {} fps)",
+            sink_id, self.camera_key, self.tap_config.max_fps
+        );
+        Ok(())
+    }
+}
@@ -4,26 +4,30 @@ use gstreamer as gst;
 use gstreamer::prelude::*;
 use tracing::info;
 use super::pipeline_sink::PipelineSink;
+use crate::config::VideoCodec;
+use crate::pipeline_sources::pipeline_source::StreamFormat;
 
 pub struct HlsPipelineSink {
     config: RecordingConfig,
+    sink_id: i64,
     queue: Option<gst::Element>,
+    audio_queue: Option<gst::Element>,
     parser: Option<gst::Element>,
     mux: Option<gst::Element>,
     sink: Option<gst::Element>,
-    tee_pad: Option<gst::Pad>,
     webroot: String,
 }
 
 impl HlsPipelineSink {
-    pub fn new(config: RecordingConfig) -> Self {
+    pub fn new(config: RecordingConfig, sink_id: i64) -> Self {
         HlsPipelineSink {
             config,
+            sink_id,
             queue: None,
+            audio_queue: None,
             parser: None,
             mux: None,
             sink: None,
-            tee_pad: None,
             webroot: String::new(),
         }
     }
@@ -42,6 +46,19 @@ impl PipelineSink for HlsPipelineSink {
         self.sink.clone().context("Sink element not initialized")
     }
 
+    fn get_audio_pad(&self) -> Option<gst::Pad> {
+        self.audio_queue.as_ref()?.static_pad("sink")
+    }
+
+    fn sink_id(&self) -> i64 {
+        self.sink_id
+    }
+
+    // h264parse in the chain, and browsers only reliably play H.264 over HLS
+    fn accepts(&self, format: StreamFormat) -> bool {
+        format == StreamFormat::Encoded(VideoCodec::H264)
+    }
+
     fn setup_sink(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
         info!("Creating HlsPipelineSink");
 
@@ -108,13 +125,33 @@ impl PipelineSink for HlsPipelineSink {
 
         // Add elements to pipeline
         pipeline
-            .add_many(&[&queue, &parser, &mux, &sink])
+            .add_many([&queue, &parser, &mux, &sink])
             .context("Failed to add HLS elements to pipeline")?;
 
         // Link elements
-        gst::Element::link_many(&[&queue, &parser, &mux, &sink])
+        gst::Element::link_many([&queue, &parser, &mux, &sink])
             .context("Failed to link HLS elements")?;
 
+        // AAC, next to the video in the same transport stream
+        if self.config.audio.is_some() {
+            let audio_queue = gst::ElementFactory::make("queue")
+                .name("hls_audio_queue")
+                .build()
+                .context("Failed to create audio queue")?;
+            pipeline
+                .add(&audio_queue)
+                .context("Failed to add audio queue to pipeline")?;
+            let mux_pad = mux
+                .request_pad_simple("sink_%d")
+                .context("Failed to request audio pad from mpegtsmux")?;
+            audio_queue
+                .static_pad("src")
+                .context("Audio queue has no src pad")?
+                .link(&mux_pad)
+                .context("Failed to link audio queue to mpegtsmux")?;
+            self.audio_queue = Some(audio_queue);
+        }
+
         info!(
             "HLS elements setup successfully. Web root: {}",
             self.webroot
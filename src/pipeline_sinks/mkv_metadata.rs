@@ -0,0 +1,130 @@
+//! Tags, chapters and attachments written into each `.mkv` segment.
+//!
+//! matroskamux forgets its tags and TOC whenever splitmuxsink resets it for a new
+//! fragment, so everything is set again from `segment_started`, which runs from
+//! format-location while the muxer is stopped.
+
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{trace, warn};
+
+/// A point of interest in the current segment, shown as a chapter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChapterMarker {
+    /// From the start of the segment
+    pub offset: Duration,
+    pub title: String,
+}
+
+#[derive(Clone)]
+pub struct MkvMetadata {
+    camera_key: String,
+    markers: Arc<Mutex<Vec<ChapterMarker>>>,
+}
+
+impl MkvMetadata {
+    pub fn new(camera_key: &str) -> Self {
+        Self {
+            camera_key: camera_key.to_string(),
+            markers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// New segment: forget the last one's chapters and tag this one.
+    pub fn segment_started(&self, muxer: &gst::Element) {
+        self.markers.lock().unwrap().clear();
+        let Some(setter) = muxer.dynamic_cast_ref::<gst::TagSetter>() else {
+            warn!("{} doesn't take tags, segment metadata skipped", muxer.name());
+            return;
+        };
+        let mut tags = gst::TagList::new();
+        {
+            let tags = tags.get_mut().unwrap();
+            tags.add::<gst::tags::Title>(&self.camera_key.as_str(), gst::TagMergeMode::Replace);
+            tags.add::<gst::tags::DeviceModel>(&self.camera_key.as_str(), gst::TagMergeMode::Replace);
+            if let Some(now) = gst::DateTime::new_now_local_time() {
+                tags.add::<gst::tags::DateTime>(&now, gst::TagMergeMode::Replace);
+            }
+        }
+        setter.merge_tags(&tags, gst::TagMergeMode::Replace);
+        Self::set_toc(muxer, &[]);
+    }
+
+    /// Add a chapter at `offset` into the current segment.
+    pub fn add_marker(&self, muxer: &gst::Element, offset: Duration, title: &str) {
+        let mut markers = self.markers.lock().unwrap();
+        markers.push(ChapterMarker { offset, title: title.to_string() });
+        trace!("Chapter '{}' at {:?}", title, offset);
+        Self::set_toc(muxer, &markers);
+    }
+
+    /// Embed a file (e.g. a GPS track) in the current segment.
+    pub fn attach(&self, muxer: &gst::Element, filename: &str, mime_type: &str, data: Vec<u8>) {
+        let Some(setter) = muxer.dynamic_cast_ref::<gst::TagSetter>() else {
+            return;
+        };
+        let sample = gst::Sample::builder()
+            .buffer(&gst::Buffer::from_slice(data))
+            .caps(&gst::Caps::new_empty_simple(mime_type))
+            .info(gst::Structure::builder("GstTagAttachment").field("filename", filename).build())
+            .build();
+        setter.add_tag::<gst::tags::Attachment>(&sample, gst::TagMergeMode::Append);
+    }
+
+    fn set_toc(muxer: &gst::Element, markers: &[ChapterMarker]) {
+        if let Some(setter) = muxer.dynamic_cast_ref::<gst::TocSetter>() {
+            setter.set_toc(Some(&chapter_toc(markers)));
+        }
+    }
+}
+
+/// One edition with a chapter per marker, each lasting until the next one
+/// (the last one is open-ended).
+pub fn chapter_toc(markers: &[ChapterMarker]) -> gst::Toc {
+    let mut sorted = markers.to_vec();
+    sorted.sort_by_key(|m| m.offset);
+
+    let mut edition = gst::TocEntry::new(gst::TocEntryType::Edition, "events");
+    {
+        let edition = edition.get_mut().unwrap();
+        for (i, marker) in sorted.iter().enumerate() {
+            let start = marker.offset.as_nanos() as i64;
+            let stop = sorted.get(i + 1).map_or(-1, |next| next.offset.as_nanos() as i64);
+            let mut chapter = gst::TocEntry::new(gst::TocEntryType::Chapter, &format!("event-{}", i));
+            {
+                let chapter = chapter.get_mut().unwrap();
+                chapter.set_start_stop_times(start, stop);
+                let mut tags = gst::TagList::new();
+                tags.get_mut()
+                    .unwrap()
+                    .add::<gst::tags::Title>(&marker.title.as_str(), gst::TagMergeMode::Replace);
+                chapter.set_tags(tags);
+            }
+            edition.append_sub_entry(chapter);
+        }
+    }
+
+    let mut toc = gst::Toc::new(gst::TocScope::Global);
+    toc.get_mut().unwrap().append_entry(edition);
+    toc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chapters_are_sorted_and_run_until_the_next_one() {
+        gst::init().unwrap();
+        let marker = |secs, title: &str| ChapterMarker { offset: Duration::from_secs(secs), title: title.to_string() };
+        let toc = chapter_toc(&[marker(40, "motion"), marker(10, "gsensor")]);
+
+        let chapters = toc.entries()[0].sub_entries();
+        let times: Vec<_> = chapters.iter().map(|c| c.start_stop_times().unwrap()).collect();
+        assert_eq!(times, vec![(10_000_000_000, 40_000_000_000), (40_000_000_000, -1)]);
+        let title = chapters[0].tags().unwrap().get::<gst::tags::Title>().unwrap().get().to_string();
+        assert_eq!(title, "gsensor");
+    }
+}
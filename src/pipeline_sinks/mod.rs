@@ -1,3 +1,16 @@
 pub mod pipeline_sink;
 pub mod hls_pipeline_sink;
+pub mod segmented_file_sink;
+pub mod write_mode;
+pub mod segment_counters;
+pub mod staging;
+pub mod disk_span;
+pub mod throughput;
+pub mod mkv_metadata;
 pub mod ts_file_pipeline_sink;
+pub mod nvr_file_pipeline_sink;
+pub mod transcode_pipeline_sink;
+pub mod timelapse_pipeline_sink;
+pub mod burst_pipeline_sink;
+pub mod frame_tap_sink;
+pub mod pre_roll;
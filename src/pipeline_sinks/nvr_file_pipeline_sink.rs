@@ -0,0 +1,121 @@
+use crate::recording_pipeline::RecordingConfig;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use super::disk_span::{DiskSet, DisksConfig};
+use super::segment_counters::{SegmentCounterProvider, SegmentCounters};
+use super::segmented_file_sink::{ClosedSegment, ContainerFormat, SegmentPolicy, SegmentedFileSink};
+
+/// NvrTs / Mp4 / Mkv: continuous recording into `<recording_dir>/<YYYY-MM-DD>/<HH-MM-SS>.<ext>`.
+/// Nothing is overwritten; retention is left to whatever cleans up old days.
+pub type NvrFilePipelineSink = SegmentedFileSink<TimestampSegmentPolicy>;
+
+#[derive(Default)]
+pub struct TimestampSegmentPolicy {
+    spanning: Option<Spanning>,
+}
+
+/// An NvrTs sink with `disks`: its files are scattered, so each one is catalogued.
+struct Spanning {
+    disks: DiskSet,
+    counters: Box<dyn SegmentCounterProvider>,
+    // counters of the next segment; the index just counts up
+    next: Mutex<SegmentCounters>,
+    // segments being written, with their counters and disk
+    open: Mutex<HashMap<String, (SegmentCounters, Option<String>)>>,
+}
+
+impl NvrFilePipelineSink {
+    pub fn new(config: RecordingConfig, sink_id: i64, container: ContainerFormat) -> Self {
+        SegmentedFileSink::with_policy(config, sink_id, container, TimestampSegmentPolicy::default())
+    }
+
+    /// NvrTs spread over `disks`, see `disk_span`; `counters` catalogs every segment.
+    pub fn spanning(
+        config: RecordingConfig,
+        sink_id: i64,
+        disks: DisksConfig,
+        counters: impl SegmentCounterProvider,
+    ) -> Self {
+        let policy = TimestampSegmentPolicy {
+            spanning: Some(Spanning {
+                disks: DiskSet::new(disks),
+                next: Mutex::new(counters.start()),
+                counters: Box::new(counters),
+                open: Mutex::new(HashMap::new()),
+            }),
+        };
+        SegmentedFileSink::with_policy(config, sink_id, ContainerFormat::Ts, policy)
+    }
+}
+
+impl SegmentPolicy for TimestampSegmentPolicy {
+    fn next_location(&self, config: &RecordingConfig, extension: &str) -> String {
+        let now = chrono::Local::now();
+
+        let disk = self.spanning.as_ref().and_then(|spanning| spanning.disks.pick());
+        let dir = match &disk {
+            Some(mount) => PathBuf::from(mount).join(&config.camera_key),
+            None => PathBuf::from(&config.recording_dir),
+        };
+        let subdir = dir.join(now.format("%Y-%m-%d").to_string());
+        let _ = fs::create_dir_all(&subdir);
+
+        let filename = format!("{}.{}", now.format("%H-%M-%S"), extension);
+        let location = subdir.join(filename).to_string_lossy().to_string();
+
+        if let Some(spanning) = &self.spanning {
+            let mut next = spanning.next.lock().unwrap();
+            let current = *next;
+            // never wraps
+            *next = current.advanced(current.index + 1, i64::MAX);
+            spanning.counters.advance(&config.camera_key, current.index + 1, i64::MAX);
+            spanning.open.lock().unwrap().insert(location.clone(), (current, disk));
+        }
+        location
+    }
+
+    fn on_fragment_closed(&self, segment: &ClosedSegment) {
+        let Some(spanning) = &self.spanning else {
+            return;
+        };
+        if let Some((counters, disk)) = spanning.open.lock().unwrap().remove(&segment.location) {
+            spanning.counters.segment_closed(counters, &ClosedSegment { disk, ..segment.clone() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline_sinks::disk_span::DiskSpread;
+    use crate::pipeline_sinks::segment_counters::MemorySegmentCounters;
+    use std::sync::Arc;
+
+    #[test]
+    fn without_a_mounted_disk_segments_stay_under_the_recording_root() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let config = RecordingConfig {
+            camera_key: "gate".to_string(),
+            recording_dir: tmp.path().join("gate").to_string_lossy().to_string(),
+            ..RecordingConfig::default()
+        };
+        let unmounted = tmp.path().join("nvr1");
+        fs::create_dir(&unmounted).unwrap();
+        let disks = DisksConfig {
+            mounts: vec![unmounted.to_string_lossy().to_string()],
+            spread: DiskSpread::Fill,
+            min_free_mb: 0,
+        };
+        let counters = Arc::new(MemorySegmentCounters::new(SegmentCounters { index: 7, generation: 0, absolute: 7 }));
+        let sink = NvrFilePipelineSink::spanning(config.clone(), 1, disks, counters.clone());
+
+        let location = sink.policy().next_location(&config, "ts");
+        assert!(location.starts_with(&config.recording_dir), "{}", location);
+        assert_eq!(fs::read_dir(&unmounted).unwrap().count(), 0, "nothing written to the empty mount point");
+        sink.policy().next_location(&config, "ts");
+        assert_eq!(counters.counters(), SegmentCounters { index: 9, generation: 0, absolute: 9 });
+    }
+}
@@ -1,7 +1,115 @@
 use anyhow::{ Result};
+use chrono::{DateTime, Local};
 use gstreamer as gst;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::events::CameraEvent;
+use crate::pipeline_sources::pipeline_source::StreamFormat;
+
+/// Receives every element message from the pipeline bus; filter on `msg.src()`.
+pub type ElementMessageHandler = Arc<dyn Fn(&gst::Message) + Send + Sync>;
+
+/// The file a sink is writing right now.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrentSegmentInfo {
+    pub path: String,
+    /// Segments this sink has opened since its pipeline was built, counting from 0
+    pub index: u64,
+    pub started_at: DateTime<Local>,
+}
+
+/// Kept up to date from a sink's callbacks: set when a segment is opened, cleared
+/// once that same segment is closed (i.e. at EOS, when nothing follows it).
+#[derive(Debug, Clone, Default)]
+pub struct SegmentTracker {
+    inner: Arc<Mutex<TrackerState>>,
+}
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    current: Option<CurrentSegmentInfo>,
+    opened_count: u64,
+    /// Segments opened but not closed yet, with when they were opened
+    open: Vec<(String, Instant)>,
+}
+
+impl SegmentTracker {
+    pub fn opened(&self, path: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        let index = inner.opened_count;
+        inner.current = Some(CurrentSegmentInfo { path: path.to_string(), index, started_at: Local::now() });
+        inner.opened_count += 1;
+        inner.open.push((path.to_string(), Instant::now()));
+    }
+
+    /// The next segment is opened before the previous one is closed, so only
+    /// the last segment of a run clears the current one. Returns how long
+    /// `path` was open, if it was opened through this tracker.
+    pub fn closed(&self, path: &str) -> Option<Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.current.as_ref().is_some_and(|current| current.path == path) {
+            inner.current = None;
+        }
+        let pos = inner.open.iter().position(|(p, _)| p == path)?;
+        Some(inner.open.remove(pos).1.elapsed())
+    }
+
+    pub fn current(&self) -> Option<CurrentSegmentInfo> {
+        self.inner.lock().unwrap().current.clone()
+    }
+}
+
 pub trait PipelineSink: Send {
     fn setup_sink(&mut self, pipeline: &gst::Pipeline) -> Result<()>;
     fn get_sink_pad(&self) -> Result<gst::Pad>;
     fn get_sink_element(&self) -> Result<gst::Element>;
-}
\ No newline at end of file
+    /// Where the camera's audio goes, for sinks that mux it. Only called after `setup_sink`.
+    fn get_audio_pad(&self) -> Option<gst::Pad> {
+        None
+    }
+    /// `sink_id` from config, unique per camera.
+    fn sink_id(&self) -> i64;
+    /// Whether this sink can consume what the source puts on the tee.
+    /// Default: any parsed, encoded stream (muxers handle H.264 and H.265).
+    fn accepts(&self, format: StreamFormat) -> bool {
+        matches!(format, StreamFormat::Encoded(_))
+    }
+    /// Hook for bus messages posted by this sink's elements (e.g. splitmuxsink fragments).
+    /// Only called after `setup_sink`.
+    fn message_handler(&self) -> Option<ElementMessageHandler> {
+        None
+    }
+    /// A G-sensor/motion/manual event happened on this camera. Called from the
+    /// caller's thread, so don't block; most sinks don't care.
+    fn on_event(&self, _event: &CameraEvent) {}
+    /// File being written, for sinks that write files.
+    fn current_segment(&self) -> Option<CurrentSegmentInfo> {
+        None
+    }
+    /// Close the file being written now and carry on in a new one (power loss).
+    fn split_now(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracker_follows_splitmux_open_close_order() {
+        let tracker = SegmentTracker::default();
+        assert_eq!(tracker.current(), None);
+
+        tracker.opened("a.ts");
+        tracker.opened("b.ts");
+        // splitmuxsink reports a.ts closed after b.ts was opened
+        assert!(tracker.closed("a.ts").is_some());
+        let current = tracker.current().unwrap();
+        assert_eq!((current.path.as_str(), current.index), ("b.ts", 1));
+
+        // EOS: the last one closes and nothing replaces it
+        tracker.closed("b.ts");
+        assert_eq!(tracker.current(), None);
+        assert_eq!(tracker.closed("b.ts"), None, "already closed");
+    }
+}
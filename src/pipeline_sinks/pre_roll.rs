@@ -0,0 +1,143 @@
+//! Pre-roll for triggered sinks (`record_mode = "motion"`).
+//!
+//! A leaky queue between a sink's valve and the sink keeps the last `pre_roll`
+//! of encoded video in RAM. While the sink is idle a blocking probe on the
+//! queue's src pad holds that back, and the queue drops its oldest buffers as
+//! new ones come in. A trigger (motion, or any event on the camera) arms the
+//! sink: the backlog goes out first, from its first keyframe on, then live
+//! video, so the segments written for the trigger (and a protected clip of
+//! them) start before it. The sink stays armed until `post_roll` after the
+//! last trigger.
+
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::time::{Duration, Instant};
+
+pub struct PreRollBuffer {
+    pre_roll: Duration,
+    post_roll: Duration,
+    armed_until: Option<Instant>,
+    queue: Option<gst::Element>,
+    // the blocking probe while disarmed, or the one draining the queue for a stop
+    probe: Option<gst::PadProbeId>,
+}
+
+impl PreRollBuffer {
+    pub fn new(pre_roll: Duration, post_roll: Duration) -> Self {
+        Self { pre_roll, post_roll, armed_until: None, queue: None, probe: None }
+    }
+
+    /// Add the queue to `pipeline`, gated for the current state. Link its sink pad
+    /// to the valve and its src pad to the sink.
+    pub fn build(&mut self, pipeline: &gst::Pipeline, sink_id: i64) -> Result<gst::Element> {
+        let queue = gst::ElementFactory::make("queue")
+            .name(format!("sink_preroll_{}", sink_id))
+            .property_from_str("leaky", "downstream")
+            .property("max-size-time", self.pre_roll.as_nanos() as u64)
+            // a zero max-size-time means unlimited; without pre-roll keep just one buffer
+            .property("max-size-buffers", if self.pre_roll.is_zero() { 1u32 } else { 0u32 })
+            .property("max-size-bytes", 0u32)
+            .build()
+            .context("Failed to create pre-roll queue")?;
+        pipeline.add(&queue).context("Failed to add pre-roll queue to pipeline")?;
+        self.queue = Some(queue.clone());
+        self.probe = None;
+        self.gate();
+        Ok(queue)
+    }
+
+    /// The queue was taken out of the pipeline.
+    pub fn forget(&mut self) -> Option<gst::Element> {
+        self.probe = None;
+        self.queue.take()
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed_until.is_some()
+    }
+
+    /// A trigger at `now`: armed until `post_roll` from now. True if this armed an idle sink.
+    pub fn trigger(&mut self, now: Instant) -> bool {
+        let was_armed = self.armed_until.replace(now + self.post_roll).is_some();
+        if !was_armed {
+            self.gate();
+        }
+        !was_armed
+    }
+
+    /// True once, when `post_roll` has passed since the last trigger and the sink is disarmed.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        if self.armed_until.is_some_and(|until| now >= until) {
+            self.disarm();
+            return true;
+        }
+        false
+    }
+
+    /// Disarm right away, without waiting for the post-roll. True if it was armed.
+    pub fn disarm(&mut self) -> bool {
+        let was_armed = self.armed_until.take().is_some();
+        if was_armed {
+            self.gate();
+        }
+        was_armed
+    }
+
+    fn take_probe(&mut self) -> Option<gst::Pad> {
+        let pad = self.queue.as_ref()?.static_pad("src")?;
+        if let Some(probe) = self.probe.take() {
+            pad.remove_probe(probe);
+        }
+        Some(pad)
+    }
+
+    /// Block the queue while disarmed; once armed, let it through from its first keyframe.
+    pub fn gate(&mut self) {
+        let Some(pad) = self.take_probe() else {
+            return;
+        };
+        if self.is_armed() {
+            // leaking drops the oldest buffers, so the pre-roll likely starts mid-GOP
+            pad.add_probe(gst::PadProbeType::BUFFER, |_, info| match info.buffer() {
+                Some(buffer) if buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) => gst::PadProbeReturn::Drop,
+                _ => gst::PadProbeReturn::Remove,
+            });
+        } else {
+            self.probe =
+                pad.add_probe(gst::PadProbeType::BLOCK | gst::PadProbeType::BUFFER, |_, _| gst::PadProbeReturn::Ok);
+        }
+    }
+
+    /// Throw the held-back pre-roll away instead, so an EOS can get past the queue.
+    /// `gate` again once the pipeline is stopped.
+    pub fn drain(&mut self) {
+        if let Some(pad) = self.take_probe() {
+            self.probe = pad.add_probe(gst::PadProbeType::BUFFER, |_, _| gst::PadProbeReturn::Drop);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stays_armed_until_post_roll_after_the_last_trigger() {
+        let start = Instant::now();
+        let at = |sec| start + Duration::from_secs(sec);
+        let mut buffer = PreRollBuffer::new(Duration::from_secs(5), Duration::from_secs(30));
+
+        assert!(!buffer.expire(at(0)));
+        assert!(buffer.trigger(at(0)));
+        assert!(!buffer.trigger(at(20)));
+        assert!(!buffer.expire(at(49)));
+        assert!(buffer.is_armed());
+        assert!(buffer.expire(at(50)));
+        assert!(!buffer.is_armed());
+        assert!(!buffer.expire(at(90)));
+        assert!(buffer.trigger(at(91)));
+        assert!(buffer.disarm());
+        assert!(!buffer.disarm());
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::db::db::SegmentRecord;
+use crate::db::db_facade::SinkDb;
+use crate::db::db_worker::DBMessage;
+use super::segmented_file_sink::ClosedSegment;
+
+/// A ring sink's counters: the next index to write, how many times the ring
+/// wrapped and how many segments it has written in total.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SegmentCounters {
+    pub index: i64,
+    pub generation: i64,
+    pub absolute: i64,
+}
+
+impl SegmentCounters {
+    /// Move the ring to `new_index`. An index lower than the current one means
+    /// the ring wrapped at `max_segments`, which starts a new generation.
+    pub fn advanced(self, new_index: i64, max_segments: i64) -> Self {
+        if new_index == self.index {
+            return self;
+        }
+        let wrapped = new_index < self.index;
+        let diff = if wrapped { (max_segments - self.index) + new_index } else { new_index - self.index };
+        Self {
+            index: new_index,
+            generation: self.generation + i64::from(wrapped),
+            absolute: self.absolute + diff,
+        }
+    }
+}
+
+/// Where a ring sink keeps its counters between segments and restarts, and
+/// catalogs the files it wrote.
+pub trait SegmentCounterProvider: Send + Sync + 'static {
+    /// Counters to continue from
+    fn start(&self) -> SegmentCounters;
+
+    /// The ring moved on to `next_index`. Called on the streaming thread.
+    fn advance(&self, camera_key: &str, next_index: i64, max_segments: i64);
+
+    /// The segment opened at `counters` is finished. Called off the bus thread.
+    fn segment_closed(&self, _counters: SegmentCounters, _segment: &ClosedSegment) {}
+}
+
+/// Counters in `camera_state`, updated through the DB worker.
+pub struct DbSegmentCounters {
+    db: SinkDb,
+}
+
+impl DbSegmentCounters {
+    pub fn new(db: SinkDb) -> Self {
+        Self { db }
+    }
+}
+
+impl SegmentCounterProvider for DbSegmentCounters {
+    fn start(&self) -> SegmentCounters {
+        self.db.counters
+    }
+
+    fn advance(&self, camera_key: &str, next_index: i64, max_segments: i64) {
+        let _ = self.db.db_sender.send(DBMessage::SegmentUpdate {
+            camera_key: camera_key.to_string(),
+            camera_id: self.db.camera_id,
+            sink_id: self.db.sink_id,
+            segment_index: next_index,
+            max_segments,
+            queued_at: Instant::now(),
+        });
+    }
+
+    fn segment_closed(&self, counters: SegmentCounters, segment: &ClosedSegment) {
+        let _ = self.db.db_sender.send(DBMessage::RecordSegment {
+            segment: SegmentRecord {
+                camera_id: self.db.camera_id,
+                sink_id: self.db.sink_id,
+                segment_index: counters.index,
+                segment_gen: counters.generation,
+                absolute_index: counters.absolute,
+                start_utc: segment.start_utc,
+                end_utc: segment.end_utc,
+                rel_path: segment.rel_path.clone(),
+                disk: segment.disk.clone(),
+                stream: segment.stream.clone(),
+                bytes: segment.bytes.map(|b| b as i64),
+                restarted: segment.restarted,
+            },
+        });
+    }
+}
+
+/// Counters kept in memory, for sinks that don't need to survive a restart and for tests.
+#[derive(Debug, Default)]
+pub struct MemorySegmentCounters {
+    counters: Mutex<SegmentCounters>,
+}
+
+impl MemorySegmentCounters {
+    pub fn new(start: SegmentCounters) -> Self {
+        Self { counters: Mutex::new(start) }
+    }
+
+    pub fn counters(&self) -> SegmentCounters {
+        *self.counters.lock().unwrap()
+    }
+}
+
+impl SegmentCounterProvider for MemorySegmentCounters {
+    fn start(&self) -> SegmentCounters {
+        self.counters()
+    }
+
+    fn advance(&self, _camera_key: &str, next_index: i64, max_segments: i64) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters = counters.advanced(next_index, max_segments);
+    }
+}
+
+/// Lets a test keep a handle on counters it gave to a sink.
+impl<T: SegmentCounterProvider> SegmentCounterProvider for std::sync::Arc<T> {
+    fn start(&self) -> SegmentCounters {
+        (**self).start()
+    }
+
+    fn advance(&self, camera_key: &str, next_index: i64, max_segments: i64) {
+        (**self).advance(camera_key, next_index, max_segments)
+    }
+
+    fn segment_closed(&self, counters: SegmentCounters, segment: &ClosedSegment) {
+        (**self).segment_closed(counters, segment)
+    }
+}
@@ -0,0 +1,514 @@
+use crate::config::VideoCodec;
+use crate::db::db::SegmentStream;
+use crate::events::CameraEvent;
+use crate::metrics;
+use crate::pipeline_sources::encoder::media_type;
+use crate::pipeline_sources::pipeline_source::StreamFormat;
+use crate::recording_pipeline::RecordingConfig;
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use serde::Deserialize;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::{info, trace};
+
+use super::mkv_metadata::MkvMetadata;
+use super::pipeline_sink::{CurrentSegmentInfo, ElementMessageHandler, PipelineSink, SegmentTracker};
+use super::staging::{MoveRetry, Mover, Staging};
+use super::throughput::ThroughputMonitor;
+use super::write_mode::{WriteMode, drop_cached_pages};
+
+/// Container written by a SegmentedFileSink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerFormat {
+    Ts,
+    Mp4,
+    /// Tagged with the camera and chaptered at events, see `mkv_metadata`
+    Mkv,
+}
+
+impl ContainerFormat {
+    /// Default muxer element.
+    pub fn muxer_factory(&self) -> &'static str {
+        self.muxer_factories()[0]
+    }
+
+    /// Muxers that can be picked with `muxer.factory`, default first.
+    pub fn muxer_factories(&self) -> &'static [&'static str] {
+        match self {
+            ContainerFormat::Ts => &["mpegtsmux", "avmux_mpegts"],
+            ContainerFormat::Mp4 => &["mp4mux", "qtmux", "avmux_mp4"],
+            ContainerFormat::Mkv => &["matroskamux"],
+        }
+    }
+
+    /// MPEG-TS has no AV1 mapping; MP4 and Matroska take everything we produce.
+    pub fn supports(&self, codec: VideoCodec) -> bool {
+        match self {
+            ContainerFormat::Ts => matches!(codec, VideoCodec::H264 | VideoCodec::H265),
+            ContainerFormat::Mp4 | ContainerFormat::Mkv => true,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ContainerFormat::Ts => "ts",
+            ContainerFormat::Mp4 => "mp4",
+            ContainerFormat::Mkv => "mkv",
+        }
+    }
+}
+
+/// `[cameras.sinks.muxer]`: which muxer a file sink uses and how it's tuned.
+/// The tuning knobs are `mpegtsmux` properties; other muxers don't have them.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MuxerConfig {
+    /// Muxer element, e.g. `avmux_mpegts`; the container's default when unset
+    pub factory: Option<String>,
+    /// Packets (188 bytes) per output buffer; 7 fits a UDP datagram, -1 pushes them as produced
+    pub alignment: Option<i32>,
+    /// How often the PAT/PMT tables are repeated. Shorter helps players that join
+    /// mid-file (a cut-off ring segment, a truncated copy) find the stream sooner.
+    pub pat_interval_ms: Option<u64>,
+    pub pmt_interval_ms: Option<u64>,
+}
+
+impl MuxerConfig {
+    pub fn factory_for(&self, container: ContainerFormat) -> &str {
+        self.factory.as_deref().unwrap_or(container.muxer_factory())
+    }
+
+    /// Why this can't be used for `container`, if it can't.
+    pub fn problem(&self, container: ContainerFormat) -> Option<String> {
+        let factory = self.factory_for(container);
+        if !container.muxer_factories().contains(&factory) {
+            return Some(format!(
+                "muxer '{}' can't write .{} (use one of {})",
+                factory,
+                container.extension(),
+                container.muxer_factories().join(", ")
+            ));
+        }
+        let tuned = self.alignment.is_some() || self.pat_interval_ms.is_some() || self.pmt_interval_ms.is_some();
+        if tuned && factory != "mpegtsmux" {
+            return Some(format!("alignment and PAT/PMT intervals are mpegtsmux settings, not {}", factory));
+        }
+        if self.alignment.is_some_and(|a| a < -1 || a == 0) {
+            return Some("alignment must be -1 or at least 1".to_string());
+        }
+        if self.pat_interval_ms == Some(0) || self.pmt_interval_ms == Some(0) {
+            return Some("PAT/PMT intervals must be at least 1ms".to_string());
+        }
+        None
+    }
+
+    fn apply(&self, muxer: &gst::Element) {
+        if let Some(alignment) = self.alignment {
+            muxer.set_property("alignment", alignment);
+        }
+        // mpegtsmux counts in 90 kHz ticks
+        if let Some(ms) = self.pat_interval_ms {
+            muxer.set_property("pat-interval", (ms * 90) as u32);
+        }
+        if let Some(ms) = self.pmt_interval_ms {
+            muxer.set_property("pmt-interval", (ms * 90) as u32);
+        }
+    }
+}
+
+/// A segment splitmuxsink finished writing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosedSegment {
+    /// Full path, as handed out by `next_location`
+    pub location: String,
+    /// Relative to the recording root (absolute for files outside it)
+    pub rel_path: String,
+    /// Mount point of the disk it's on, for an NvrTs sink spread over `disks`
+    pub disk: Option<String>,
+    /// Wall clock, UTC seconds
+    pub start_utc: i64,
+    pub end_utc: i64,
+    pub bytes: Option<u64>,
+    /// Codec, size and frame rate it was written with
+    pub stream: SegmentStream,
+    /// First segment since the pipeline started; its timestamps start over
+    pub restarted: bool,
+}
+
+// What's known about a segment from when splitmuxsink opened it
+#[derive(Debug, Default)]
+struct OpenedSegment {
+    stream: SegmentStream,
+    restarted: bool,
+}
+
+/// Codec, size and frame rate from the caps of an encoded stream.
+pub fn stream_of(caps: &gst::CapsRef) -> SegmentStream {
+    let Some(structure) = caps.structure(0) else {
+        return SegmentStream::default();
+    };
+    let codec = [VideoCodec::H264, VideoCodec::H265, VideoCodec::Av1]
+        .into_iter()
+        .find(|codec| media_type(*codec) == structure.name().as_str())
+        .map(|codec| codec_name(codec).to_string());
+    SegmentStream {
+        codec,
+        width: structure.get::<i32>("width").ok().map(i64::from),
+        height: structure.get::<i32>("height").ok().map(i64::from),
+        fps: structure
+            .get::<gst::Fraction>("framerate")
+            .ok()
+            .filter(|f| f.denom() != 0 && f.numer() != 0)
+            .map(|f| f.numer() as f64 / f.denom() as f64),
+    }
+}
+
+/// `segments.codec` value for `codec`.
+pub fn codec_name(codec: VideoCodec) -> &'static str {
+    match codec {
+        VideoCodec::H264 => "H264",
+        VideoCodec::H265 => "H265",
+        VideoCodec::Av1 => "AV1",
+    }
+}
+
+/// What makes one segmented file sink different from another:
+/// where each segment goes and what happens once it's closed.
+pub trait SegmentPolicy: Send + Sync + 'static {
+    /// Full path of the next segment. Called from splitmuxsink's format-location,
+    /// i.e. on the streaming thread, so keep it cheap.
+    fn next_location(&self, config: &RecordingConfig, extension: &str) -> String;
+
+    /// Called once splitmuxsink has finished writing a segment and it was synced
+    /// to disk (and, with `[staging]`, moved there), on a short-lived thread of
+    /// its own or the sink's staging mover.
+    fn on_fragment_closed(&self, _segment: &ClosedSegment) {}
+}
+
+/// queue -> splitmuxsink(muxer) shared by the DashcamTs, NvrTs and Mp4 sinks.
+pub struct SegmentedFileSink<P: SegmentPolicy> {
+    config: RecordingConfig,
+    sink_id: i64,
+    container: ContainerFormat,
+    policy: Arc<P>,
+    muxer_config: MuxerConfig,
+    write_mode: WriteMode,
+    staging: Option<Staging>,
+    mover: Option<Mover>,
+    throughput: Option<ThroughputMonitor>,
+    segments: SegmentTracker,
+    // segments being written, by location
+    opened: Arc<Mutex<HashMap<String, OpenedSegment>>>,
+    mkv: Option<MkvMetadata>,
+    queue: Option<gst::Element>,
+    // in front of splitmuxsink's audio pad, when the camera records audio
+    audio_queue: Option<gst::Element>,
+    muxer: Option<gst::Element>,
+    sink: Option<gst::Element>,
+}
+
+impl<P: SegmentPolicy> SegmentedFileSink<P> {
+    pub fn with_policy(
+        config: RecordingConfig,
+        sink_id: i64,
+        container: ContainerFormat,
+        policy: P,
+    ) -> Self {
+        let mkv = (container == ContainerFormat::Mkv).then(|| MkvMetadata::new(&config.camera_key));
+        // recording_dir is <recording root>/<camera key>
+        let staging = config
+            .staging
+            .as_ref()
+            .zip(Path::new(&config.recording_dir).parent())
+            .map(|(staging, root)| Staging { dir: PathBuf::from(&staging.dir), recording_root: root.to_path_buf() });
+        SegmentedFileSink {
+            config,
+            sink_id,
+            container,
+            policy: Arc::new(policy),
+            muxer_config: MuxerConfig::default(),
+            write_mode: WriteMode::default(),
+            staging,
+            mover: None,
+            throughput: None,
+            segments: SegmentTracker::default(),
+            opened: Arc::new(Mutex::new(HashMap::new())),
+            mkv,
+            queue: None,
+            audio_queue: None,
+            muxer: None,
+            sink: None,
+        }
+    }
+
+    pub fn with_muxer(mut self, muxer_config: MuxerConfig) -> Self {
+        self.muxer_config = muxer_config;
+        self
+    }
+
+    pub fn with_write_mode(mut self, write_mode: WriteMode) -> Self {
+        self.write_mode = write_mode;
+        self
+    }
+
+    /// Report each closed segment's write throughput to `monitor`, if any.
+    pub fn with_throughput(mut self, monitor: Option<ThroughputMonitor>) -> Self {
+        self.throughput = monitor;
+        self
+    }
+
+    pub fn policy(&self) -> &Arc<P> {
+        &self.policy
+    }
+
+    /// The policy, for builders; `None` once the sink has handed it out.
+    pub fn policy_mut(&mut self) -> Option<&mut P> {
+        Arc::get_mut(&mut self.policy)
+    }
+}
+
+impl<P: SegmentPolicy> PipelineSink for SegmentedFileSink<P> {
+    fn get_sink_pad(&self) -> Result<gst::Pad> {
+        self.queue
+            .as_ref()
+            .context("Queue element not initialized")?
+            .static_pad("sink")
+            .context("Failed to get sink pad from queue")
+    }
+
+    fn get_sink_element(&self) -> Result<gst::Element> {
+        self.sink.clone().context("Sink element not initialized")
+    }
+
+    fn get_audio_pad(&self) -> Option<gst::Pad> {
+        self.audio_queue.as_ref()?.static_pad("sink")
+    }
+
+    fn sink_id(&self) -> i64 {
+        self.sink_id
+    }
+
+    fn accepts(&self, format: StreamFormat) -> bool {
+        match format {
+            StreamFormat::Encoded(codec) => self.container.supports(codec),
+            StreamFormat::Raw => false,
+        }
+    }
+
+    fn setup_sink(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
+        let segment_length = self.config.segment_length();
+        if let Some(problem) = self.muxer_config.problem(self.container) {
+            anyhow::bail!("sink_id={}: {}", self.sink_id, problem);
+        }
+        let muxer_factory = self.muxer_config.factory_for(self.container);
+
+        self.queue = Some(
+            gst::ElementFactory::make("queue")
+                .name(format!("file_sink_queue_{}", self.sink_id))
+                .build()
+                .context("Failed to create queue")?,
+        );
+
+        self.muxer = Some(
+            gst::ElementFactory::make(muxer_factory)
+                .name(format!("muxer_{}", self.sink_id))
+                .build()
+                .with_context(|| format!("Failed to create {} (is its plugin installed?)", muxer_factory))?,
+        );
+        self.muxer_config.apply(self.muxer.as_ref().unwrap());
+
+        self.sink = Some(
+            gst::ElementFactory::make("splitmuxsink")
+                .name(format!("sink_{}", self.sink_id))
+                .build()
+                .context("Failed to create splitmuxsink")?,
+        );
+
+        let queue = self.queue.clone().unwrap();
+        let muxer = self.muxer.clone().unwrap();
+        let sink = self.sink.clone().unwrap();
+
+        sink.set_property("muxer", &muxer);
+        sink.set_property("max-size-time", segment_length.as_nanos() as u64);
+        self.write_mode.apply(&sink, &queue, self.sink_id)?;
+        if self.mover.is_none()
+            && let (Some(staging), Some(cfg)) = (&self.staging, &self.config.staging)
+        {
+            let retry = MoveRetry::for_root(cfg, &staging.recording_root);
+            self.mover = Some(Mover::spawn(format!("mover_{}_{}", self.config.camera_key, self.sink_id), retry));
+        }
+
+        let config = self.config.clone();
+        let policy = self.policy.clone();
+        let extension = self.container.extension();
+        let segments = self.segments.clone();
+        let mkv = self.mkv.clone();
+        let format_muxer = muxer.clone();
+        let opened = self.opened.clone();
+        let format_queue = queue.clone();
+        let staging = self.staging.clone();
+
+        sink.connect("format-location", false, move |args| {
+            let mut filename = policy.next_location(&config, extension);
+            if let Some(staged) = staging.as_ref().and_then(|s| s.staged_location(Path::new(&filename))) {
+                if let Some(dir) = staged.parent() {
+                    let _ = std::fs::create_dir_all(dir);
+                }
+                filename = staged.to_string_lossy().to_string();
+            }
+            segments.opened(&filename);
+            // the new fragment starts with what's flowing now
+            let caps = format_queue.static_pad("src").and_then(|pad| pad.current_caps());
+            // splitmuxsink numbers fragments from 0 again every time it starts
+            let restarted = args.get(1).and_then(|id| id.get::<u32>().ok()) == Some(0);
+            let stream = caps.map(|caps| stream_of(&caps)).unwrap_or_default();
+            opened.lock().unwrap().insert(filename.clone(), OpenedSegment { stream, restarted });
+            if let Some(mkv) = &mkv {
+                mkv.segment_started(&format_muxer);
+            }
+            Some(filename.to_value())
+        });
+
+        pipeline
+            .add_many([&queue, &sink])
+            .context("Failed to add sink elements to pipeline")?;
+
+        queue
+            .link(&sink)
+            .context("Failed to link queue to splitmuxsink")?;
+
+        if self.config.audio.is_some() {
+            let audio_queue = gst::ElementFactory::make("queue")
+                .name(format!("file_sink_audio_queue_{}", self.sink_id))
+                .build()
+                .context("Failed to create audio queue")?;
+            // as much headroom as the video queue, which write_mode may have raised
+            for property in ["max-size-buffers", "max-size-bytes", "max-size-time"] {
+                audio_queue.set_property_from_value(property, &queue.property_value(property));
+            }
+            pipeline.add(&audio_queue).context("Failed to add audio queue to pipeline")?;
+            let audio_pad = sink.request_pad_simple("audio_%u").context("splitmuxsink has no audio pad")?;
+            audio_queue
+                .static_pad("src")
+                .context("Audio queue has no src pad")?
+                .link(&audio_pad)
+                .context("Failed to link audio queue to splitmuxsink")?;
+            self.audio_queue = Some(audio_queue);
+        }
+
+        info!(
+            "Segmented file sink {} ready ({} segments of {:?}, {}, {:?} writes)",
+            self.sink_id, extension, segment_length, muxer_factory, self.write_mode
+        );
+        Ok(())
+    }
+
+    fn message_handler(&self) -> Option<ElementMessageHandler> {
+        let sink = self.sink.clone()?;
+        let policy = self.policy.clone();
+        let segments = self.segments.clone();
+        let opened = self.opened.clone();
+        let camera_key = self.config.camera_key.clone();
+        let sink_id = self.sink_id.to_string();
+        let throughput = self.throughput.clone().map(|monitor| (monitor, self.sink_id));
+        let drops_cache = self.write_mode.drops_cache();
+        let staging = self.staging.clone();
+        let mover = self.mover.clone();
+        // recording_dir is <recording root>/<camera key>
+        let recording_root = Path::new(&self.config.recording_dir).parent().map(Path::to_path_buf);
+
+        Some(Arc::new(move |msg: &gst::Message| {
+            if msg.src() != Some(sink.upcast_ref::<gst::Object>()) {
+                return;
+            }
+            if let gst::MessageView::Element(element) = msg.view()
+                && let Some(structure) = element.structure()
+                && structure.name() == "splitmuxsink-fragment-closed"
+                && let Ok(location) = structure.get::<String>("location")
+            {
+                trace!("Fragment closed: {}", location);
+                let closed_at = Instant::now();
+                let labels = [("camera", camera_key.as_str()), ("sink", sink_id.as_str())];
+                let open_for = segments.closed(&location);
+                if let Some(open_for) = open_for {
+                    metrics::observe(metrics::SEGMENT_WRITE, &labels, open_for);
+                }
+                let OpenedSegment { stream, restarted } = opened.lock().unwrap().remove(&location).unwrap_or_default();
+                let end_utc = Utc::now().timestamp();
+                let start_utc = end_utc - open_for.map_or(0, |d| d.as_secs() as i64);
+                let staged = staging
+                    .as_ref()
+                    .and_then(|s| s.final_location(Path::new(&location)))
+                    .map(|destination| (PathBuf::from(&location), destination));
+                let location = staged.as_ref().map_or(location, |(_, d)| d.to_string_lossy().to_string());
+                let rel_path = recording_root
+                    .as_deref()
+                    .and_then(|root| Path::new(&location).strip_prefix(root).ok())
+                    .map_or_else(|| location.clone(), |rel| rel.to_string_lossy().to_string());
+                // off the bus thread: on a dying card this is exactly what stalls
+                let (camera_key, sink_id, policy) = (camera_key.clone(), sink_id.clone(), policy.clone());
+                let throughput = throughput.clone();
+                let finish = move || {
+                    let started = Instant::now();
+                    if let Ok(file) = File::open(&location)
+                        && file.sync_all().is_ok()
+                    {
+                        let labels = [("camera", camera_key.as_str()), ("sink", sink_id.as_str())];
+                        metrics::observe(metrics::SEGMENT_FSYNC, &labels, started.elapsed());
+                        if drops_cache && let Err(e) = drop_cached_pages(&file) {
+                            trace!("Couldn't drop {} from the page cache: {}", location, e);
+                        }
+                    }
+                    let bytes = std::fs::metadata(&location).ok().map(|m| m.len());
+                    if let (Some((monitor, id)), Some(bytes), Some(open_for)) = (&throughput, bytes, open_for) {
+                        monitor.segment_written(*id, bytes, open_for, closed_at.elapsed());
+                    }
+                    policy.on_fragment_closed(&ClosedSegment {
+                        location,
+                        rel_path,
+                        disk: None,
+                        start_utc,
+                        end_utc,
+                        bytes,
+                        stream,
+                        restarted,
+                    });
+                };
+                match (staged, &mover) {
+                    (Some((staged, destination)), Some(mover)) => mover.queue(staged, destination, finish),
+                    _ => {
+                        std::thread::spawn(finish);
+                    }
+                }
+            }
+        }))
+    }
+
+    fn current_segment(&self) -> Option<CurrentSegmentInfo> {
+        self.segments.current()
+    }
+
+    fn split_now(&self) {
+        if let Some(sink) = &self.sink {
+            sink.emit_by_name::<()>("split-now", &[]);
+        }
+    }
+
+    fn on_event(&self, event: &CameraEvent) {
+        let (Some(mkv), Some(muxer), Some(current)) = (&self.mkv, &self.muxer, self.segments.current()) else {
+            return;
+        };
+        let offset = (event.at - current.started_at).to_std().unwrap_or_default();
+        let title = match &event.label {
+            Some(label) => format!("{}: {}", event.kind, label),
+            None => event.kind.to_string(),
+        };
+        mkv.add_marker(muxer, offset, &title);
+    }
+}
@@ -0,0 +1,425 @@
+//! `[staging]`: segments are written to a tmpfs and moved to the recording
+//! root once closed, so an SD card that stalls for seconds on a write (erase
+//! blocks, wear leveling) holds up a mover thread instead of the muxer and,
+//! through it, the encoder.
+//!
+//! A segment headed for `<recording root>/<rel path>` is written to
+//! `<staging dir>/<rel path>`. When it's closed, one mover thread per sink
+//! copies it next to its destination as `<name>.part`, fsyncs it and renames
+//! it over the destination, so a reader never sees half a segment and a saved
+//! hard link (see `protect`) keeps the old file. Only then is the segment
+//! catalogued. Staged files left over from a crash are moved at startup by
+//! `move_leftovers`, but not catalogued.
+//!
+//! The staging dir needs room for a couple of segments per file sink, more if
+//! the card falls behind for longer; the mover warns when it does. Up to
+//! `spool_mb` of segments per sink wait for it; past that the oldest is deleted
+//! so the tmpfs doesn't fill up. A move that fails (a full or read-only card)
+//! is tried again every `retry_sec`, three times, then the staged segment is
+//! deleted too.
+//!
+//! When the recording root is an NFS or SMB share, a dropped connection
+//! (ESTALE, EIO, a timeout, or the share no longer being mounted) doesn't lose
+//! the segment: it stays staged and the mover tries again every `retry_sec`,
+//! keeping the order, for as long as the spool has room.
+
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Segments waiting to be moved before the mover warns that the card can't keep up
+const BACKLOG_WARNING: usize = 3;
+/// Tries of a move failing for another reason than a share going away
+const MOVE_ATTEMPTS: u32 = 3;
+
+/// `statfs` magic numbers of network file systems
+const NFS_SUPER_MAGIC: u32 = 0x6969;
+const SMB_SUPER_MAGIC: u32 = 0x517B;
+const CIFS_SUPER_MAGIC: u32 = 0xFF53_4D42;
+const SMB2_SUPER_MAGIC: u32 = 0xFE53_4D42;
+
+fn default_retry_sec() -> u64 {
+    10
+}
+
+fn default_spool_mb() -> u64 {
+    256
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StagingConfig {
+    /// On a tmpfs, e.g. `/dev/shm/dashcam`
+    pub dir: String,
+    /// How often a failed move is retried, e.g. while a network share is unreachable
+    #[serde(default = "default_retry_sec")]
+    pub retry_sec: u64,
+    /// Segments each sink keeps staged, waiting for storage, before dropping the oldest
+    #[serde(default = "default_spool_mb")]
+    pub spool_mb: u64,
+}
+
+/// Whether `path` is on NFS or SMB, where writes can fail while the server is away.
+pub fn is_network_fs(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: statfs only writes into the struct we hand it
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return false;
+    }
+    // f_type is 32 or 64 bits wide depending on the target; the magic numbers fit in 32
+    matches!(stat.f_type as u32, NFS_SUPER_MAGIC | SMB_SUPER_MAGIC | CIFS_SUPER_MAGIC | SMB2_SUPER_MAGIC)
+}
+
+/// Whether a failed move means the share went away, rather than e.g. a full disk.
+fn is_disconnect(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::NotConnected
+        || matches!(
+            e.raw_os_error(),
+            Some(
+                libc::ESTALE
+                    | libc::EIO
+                    | libc::ENOTCONN
+                    | libc::EHOSTDOWN
+                    | libc::EHOSTUNREACH
+                    | libc::ETIMEDOUT
+                    | libc::ENETDOWN
+                    | libc::ENETUNREACH
+                    | libc::ECONNRESET
+                    | libc::ECONNABORTED
+            )
+        )
+}
+
+/// Where segments are staged for one recording root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Staging {
+    pub dir: PathBuf,
+    pub recording_root: PathBuf,
+}
+
+impl Staging {
+    /// Where to write the segment headed for `location`; `None` for files
+    /// outside the recording root, which are written in place.
+    pub fn staged_location(&self, location: &Path) -> Option<PathBuf> {
+        location.strip_prefix(&self.recording_root).ok().map(|rel| self.dir.join(rel))
+    }
+
+    /// Where a staged segment is headed.
+    pub fn final_location(&self, staged: &Path) -> Option<PathBuf> {
+        staged.strip_prefix(&self.dir).ok().map(|rel| self.recording_root.join(rel))
+    }
+
+    /// Move staged files a crash or kill left behind to the recording root.
+    pub fn move_leftovers(&self) -> usize {
+        let mut moved = 0;
+        let mut dirs = vec![self.dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                    dirs.push(path);
+                } else if let Some(destination) = self.final_location(&path) {
+                    match move_to_storage(&path, &destination) {
+                        Ok(_) => moved += 1,
+                        Err(e) => warn!("Couldn't move leftover {}: {}", path.display(), e),
+                    }
+                }
+            }
+        }
+        if moved > 0 {
+            info!("Moved {} leftover staged segments to {}", moved, self.recording_root.display());
+        }
+        moved
+    }
+}
+
+/// Move `staged` to `destination` so that it appears there whole: a rename on
+/// the same file system, else a copy to `<destination>.part`, fsync and rename.
+pub fn move_to_storage(staged: &Path, destination: &Path) -> io::Result<u64> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(staged, destination).is_ok() {
+        return Ok(fs::metadata(destination)?.len());
+    }
+    let mut part = destination.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+    let bytes = fs::copy(staged, &part)?;
+    File::open(&part)?.sync_all()?;
+    fs::rename(&part, destination)?;
+    fs::remove_file(staged)?;
+    Ok(bytes)
+}
+
+/// A closed segment waiting for the mover, and what to do once it's moved.
+struct MoveJob {
+    staged: PathBuf,
+    destination: PathBuf,
+    then: Box<dyn FnOnce() + Send>,
+    failures: u32,
+}
+
+/// How a mover retries failed moves, and how much it keeps staged meanwhile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveRetry {
+    /// The recording root when it's on a network share, whose disconnects are waited out
+    pub share_root: Option<PathBuf>,
+    pub retry: Duration,
+    pub spool_bytes: u64,
+}
+
+impl MoveRetry {
+    pub fn for_root(config: &StagingConfig, recording_root: &Path) -> Self {
+        Self {
+            share_root: is_network_fs(recording_root).then(|| recording_root.to_path_buf()),
+            retry: Duration::from_secs(config.retry_sec),
+            spool_bytes: config.spool_mb * 1_000_000,
+        }
+    }
+}
+
+/// One thread that runs a sink's moves in order.
+#[derive(Clone)]
+pub struct Mover {
+    jobs: Sender<MoveJob>,
+    backlog: Arc<AtomicUsize>,
+    // the share is away; the backlog is expected to grow
+    disconnected: Arc<AtomicBool>,
+}
+
+impl Mover {
+    /// The thread ends once every clone is dropped and the queued moves are
+    /// done, or given up on while they keep failing.
+    pub fn spawn(name: String, retry: MoveRetry) -> Self {
+        let Some(root) = retry.share_root.clone() else {
+            return Self::spawn_with(name, retry, move_to_storage);
+        };
+        Self::spawn_with(name, retry, move |staged, destination| {
+            // an unmounted share leaves an empty directory on the local disk
+            if !is_network_fs(&root) {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "share isn't mounted"));
+            }
+            move_to_storage(staged, destination)
+        })
+    }
+
+    /// `spawn` with `move_file` doing the moves.
+    fn spawn_with(
+        name: String,
+        retry: MoveRetry,
+        move_file: impl Fn(&Path, &Path) -> io::Result<u64> + Send + 'static,
+    ) -> Self {
+        let (jobs, queue) = mpsc::channel::<MoveJob>();
+        let backlog = Arc::new(AtomicUsize::new(0));
+        let disconnected = Arc::new(AtomicBool::new(false));
+        let (pending, away) = (backlog.clone(), disconnected.clone());
+        let spawned = std::thread::Builder::new().name(name).spawn(move || {
+            let mut waiting = VecDeque::new();
+            loop {
+                if waiting.is_empty() {
+                    match queue.recv() {
+                        Ok(job) => waiting.push_back(job),
+                        Err(_) => break,
+                    }
+                }
+                waiting.extend(queue.try_iter());
+                drop_over_spool(&mut waiting, retry.spool_bytes, &pending);
+                move_waiting(&mut waiting, &retry, &move_file, &pending, &away);
+                if waiting.is_empty() {
+                    continue;
+                }
+                match queue.recv_timeout(retry.retry) {
+                    Ok(job) => waiting.push_back(job),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => {
+                        warn!("{} segments stay staged until the next start, moving them still fails", waiting.len());
+                        break;
+                    }
+                }
+            }
+        });
+        if let Err(e) = spawned {
+            error!("Failed to start the staging mover: {}", e);
+        }
+        Self { jobs, backlog, disconnected }
+    }
+
+    /// Queue the move of `staged` to `destination`, then `then`, which reports it.
+    pub fn queue(&self, staged: PathBuf, destination: PathBuf, then: impl FnOnce() + Send + 'static) {
+        let backlog = self.backlog.fetch_add(1, Ordering::SeqCst) + 1;
+        if backlog >= BACKLOG_WARNING && !self.disconnected.load(Ordering::SeqCst) {
+            warn!("Storage is {} segments behind the staging dir ({} waiting)", backlog, staged.display());
+        }
+        let staged_name = staged.display().to_string();
+        if self.jobs.send(MoveJob { staged, destination, then: Box::new(then), failures: 0 }).is_err() {
+            self.backlog.fetch_sub(1, Ordering::SeqCst);
+            error!("Staging mover has stopped; {} stays staged", staged_name);
+        }
+    }
+}
+
+/// Move what's waiting, in order, until a move fails and is to be tried again.
+fn move_waiting(
+    waiting: &mut VecDeque<MoveJob>,
+    retry: &MoveRetry,
+    move_file: &impl Fn(&Path, &Path) -> io::Result<u64>,
+    pending: &AtomicUsize,
+    away: &AtomicBool,
+) {
+    while let Some(mut job) = waiting.pop_front() {
+        match move_file(&job.staged, &job.destination) {
+            Ok(_) => {
+                if away.swap(false, Ordering::SeqCst) {
+                    info!("Recording share is back, moving {} staged segments", waiting.len() + 1);
+                }
+                pending.fetch_sub(1, Ordering::SeqCst);
+                (job.then)();
+            }
+            Err(e) if retry.share_root.is_some() && is_disconnect(&e) => {
+                if !away.swap(true, Ordering::SeqCst) {
+                    warn!("Recording share is unreachable ({}), keeping segments staged until it's back", e);
+                }
+                waiting.push_front(job);
+                return;
+            }
+            Err(e) => {
+                job.failures += 1;
+                if job.failures < MOVE_ATTEMPTS {
+                    warn!("Couldn't move {} ({}), trying again in {:?}", job.staged.display(), e, retry.retry);
+                    waiting.push_front(job);
+                    return;
+                }
+                pending.fetch_sub(1, Ordering::SeqCst);
+                error!("Couldn't move {} to {}: {}; deleting it", job.staged.display(), job.destination.display(), e);
+                if let Err(e) = fs::remove_file(&job.staged) {
+                    warn!("Couldn't remove {}: {}", job.staged.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// Delete the oldest staged segments until those waiting fit in `spool_bytes`.
+fn drop_over_spool(waiting: &mut VecDeque<MoveJob>, spool_bytes: u64, pending: &AtomicUsize) {
+    let size = |job: &MoveJob| fs::metadata(&job.staged).map_or(0, |m| m.len());
+    let mut spooled: u64 = waiting.iter().map(size).sum();
+    while spooled > spool_bytes
+        && let Some(oldest) = waiting.pop_front()
+    {
+        spooled -= size(&oldest);
+        pending.fetch_sub(1, Ordering::SeqCst);
+        error!("Staging spool is full, dropping {}", oldest.staged.display());
+        if let Err(e) = fs::remove_file(&oldest.staged) {
+            warn!("Couldn't remove {}: {}", oldest.staged.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn staged_segments_mirror_the_recording_root_and_move_whole() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let staging = Staging { dir: tmp.path().join("shm"), recording_root: tmp.path().join("recordings") };
+        let destination = staging.recording_root.join("front/0/output_3.ts");
+        let staged = staging.staged_location(&destination).unwrap();
+        assert_eq!(staged, tmp.path().join("shm/front/0/output_3.ts"));
+        assert_eq!(staging.final_location(&staged).unwrap(), destination);
+        assert_eq!(staging.staged_location(Path::new("/media/usb/front.ts")), None);
+
+        // a saved hard link keeps the old footage
+        fs::create_dir_all(destination.parent().unwrap()).unwrap();
+        fs::write(&destination, b"old").unwrap();
+        let saved = tmp.path().join("saved.ts");
+        fs::hard_link(&destination, &saved).unwrap();
+        fs::create_dir_all(staged.parent().unwrap()).unwrap();
+        fs::write(&staged, b"new segment").unwrap();
+        assert_eq!(move_to_storage(&staged, &destination).unwrap(), 11);
+        assert_eq!(fs::read(&destination).unwrap(), b"new segment");
+        assert_eq!(fs::read(&saved).unwrap(), b"old");
+        assert!(!staged.exists());
+
+        fs::write(&staged, b"left behind").unwrap();
+        assert_eq!(staging.move_leftovers(), 1);
+        assert_eq!(fs::read(&destination).unwrap(), b"left behind");
+    }
+
+    #[test]
+    fn while_the_share_is_away_segments_stay_staged_up_to_the_spool_size() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let share_up = Arc::new(AtomicBool::new(false));
+        let up = share_up.clone();
+        let retry = MoveRetry {
+            share_root: Some(tmp.path().join("share")),
+            retry: Duration::from_millis(10),
+            spool_bytes: 1000,
+        };
+        let mover = Mover::spawn_with("mover_test".to_string(), retry, move |staged, destination| {
+            if !up.load(Ordering::SeqCst) {
+                return Err(io::Error::from_raw_os_error(libc::ESTALE));
+            }
+            move_to_storage(staged, destination)
+        });
+
+        let (moved, done) = mpsc::channel();
+        for n in 1..=3 {
+            let staged = tmp.path().join(format!("shm/{}.ts", n));
+            fs::create_dir_all(staged.parent().unwrap()).unwrap();
+            fs::write(&staged, [0u8; 400]).unwrap();
+            let moved = moved.clone();
+            mover.queue(staged, tmp.path().join(format!("share/{}.ts", n)), move || moved.send(n).unwrap());
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!tmp.path().join("shm/1.ts").exists(), "oldest dropped past 1000 bytes");
+        assert!(tmp.path().join("shm/2.ts").exists());
+
+        share_up.store(true, Ordering::SeqCst);
+        let order: Vec<i32> = (0..2).map(|_| done.recv_timeout(Duration::from_secs(2)).unwrap()).collect();
+        assert_eq!(order, [2, 3]);
+        assert!(tmp.path().join("share/3.ts").exists());
+        assert!(is_disconnect(&io::Error::from_raw_os_error(libc::EIO)));
+        assert!(!is_disconnect(&io::Error::from_raw_os_error(libc::ENOSPC)));
+    }
+
+    #[test]
+    fn a_move_that_keeps_failing_is_given_up_and_its_staged_file_deleted() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let retry = MoveRetry { share_root: None, retry: Duration::from_millis(10), spool_bytes: 1000 };
+        let mover = Mover::spawn_with("mover_test".to_string(), retry, |staged, destination| {
+            if staged.ends_with("1.ts") {
+                return Err(io::Error::from_raw_os_error(libc::ENOSPC));
+            }
+            move_to_storage(staged, destination)
+        });
+
+        let (moved, done) = mpsc::channel();
+        for n in 1..=2 {
+            let staged = tmp.path().join(format!("shm/{}.ts", n));
+            fs::create_dir_all(staged.parent().unwrap()).unwrap();
+            fs::write(&staged, [0u8; 400]).unwrap();
+            let moved = moved.clone();
+            mover.queue(staged, tmp.path().join(format!("card/{}.ts", n)), move || moved.send(n).unwrap());
+        }
+        assert_eq!(done.recv_timeout(Duration::from_secs(2)).unwrap(), 2);
+        assert!(!tmp.path().join("shm/1.ts").exists(), "not left on the tmpfs");
+        assert!(!tmp.path().join("card/1.ts").exists());
+    }
+}
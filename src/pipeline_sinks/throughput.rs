@@ -0,0 +1,183 @@
+//! `[throughput]`: effective write throughput per file sink.
+//!
+//! A card on its way out gets slow long before writes start failing: the muxer
+//! blocks, segments run long and fsyncs take seconds. Every closed segment's
+//! size is divided by the time the card took for it (how long the segment was
+//! open, or until it was fsynced and, with `[staging]`, moved, if that took
+//! longer) and compared with the encoder's bitrate. Once a sink writes below
+//! `min_ratio` of it for `sustained_sec` of footage in a row, it's logged and
+//! shows up degraded in `/healthz` and `/api/status`, until a segment is
+//! written at full speed again.
+//!
+//! Only cameras whose source encodes are watched; a passthrough stream's
+//! bitrate isn't known.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::health::{HealthState, SubsystemHealth};
+
+/// Shorter segments (the last one before a stop) say more about keyframe
+/// timing than about the card
+const MIN_SEGMENT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThroughputConfig {
+    /// Share of the encoder bitrate below which a segment counts as slow; 0 turns the check off
+    pub min_ratio: f64,
+    /// Footage in a row that has to be slow before it's reported
+    pub sustained_sec: u64,
+}
+
+impl Default for ThroughputConfig {
+    fn default() -> Self {
+        Self { min_ratio: 0.75, sustained_sec: 120 }
+    }
+}
+
+impl ThroughputConfig {
+    pub fn enabled(&self) -> bool {
+        self.min_ratio > 0.0
+    }
+}
+
+/// How fast one sink's segments reach the card.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SinkThroughput {
+    pub sink_id: i64,
+    /// Of the newest closed segment
+    pub kbps: f64,
+    pub expected_kbps: u32,
+    /// Footage written below `min_ratio` in a row
+    pub slow_sec: f64,
+    /// `slow_sec` has reached `sustained_sec`
+    pub alert: bool,
+}
+
+impl SinkThroughput {
+    pub fn health(&self, camera_key: &str) -> SubsystemHealth {
+        let name = format!("throughput:{}:{}", camera_key, self.sink_id);
+        let detail = format!("{:.0} of {} kbit/s", self.kbps, self.expected_kbps);
+        if self.alert {
+            let detail = format!("{} for {:.0}s: the card can't keep up", detail, self.slow_sec);
+            SubsystemHealth::new(name, HealthState::Degraded, detail)
+        } else {
+            SubsystemHealth::new(name, HealthState::Ok, detail)
+        }
+    }
+}
+
+/// Shared by a camera's file sinks, which report their closed segments, and its
+/// pipeline, which keeps the expected bitrate in step with `[[modes]]`.
+#[derive(Debug, Clone)]
+pub struct ThroughputMonitor {
+    camera_key: String,
+    config: ThroughputConfig,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    expected_kbps: u32,
+    sinks: BTreeMap<i64, SinkThroughput>,
+}
+
+impl ThroughputMonitor {
+    pub fn new(camera_key: &str, expected_kbps: u32, config: ThroughputConfig) -> Self {
+        Self {
+            camera_key: camera_key.to_string(),
+            config,
+            inner: Arc::new(Mutex::new(Inner { expected_kbps, sinks: BTreeMap::new() })),
+        }
+    }
+
+    pub fn set_expected_kbps(&self, kbps: u32) {
+        self.inner.lock().unwrap().expected_kbps = kbps;
+    }
+
+    /// Count a closed segment of `sink_id`: `bytes` of footage that was open for
+    /// `open_for`, and reached the card `took` after it was closed.
+    pub fn segment_written(&self, sink_id: i64, bytes: u64, open_for: Duration, took: Duration) {
+        if open_for < MIN_SEGMENT {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let expected_kbps = inner.expected_kbps;
+        let kbps = bytes as f64 * 8.0 / 1000.0 / open_for.max(took).as_secs_f64();
+        let sink = inner.sinks.entry(sink_id).or_insert(SinkThroughput {
+            sink_id,
+            kbps,
+            expected_kbps,
+            slow_sec: 0.0,
+            alert: false,
+        });
+        sink.kbps = kbps;
+        sink.expected_kbps = expected_kbps;
+
+        if kbps >= expected_kbps as f64 * self.config.min_ratio {
+            if sink.alert {
+                info!(
+                    "Camera '{}' sink_id={} writes at {:.0} kbit/s again after {:.0}s below {} kbit/s",
+                    self.camera_key, sink_id, kbps, sink.slow_sec, expected_kbps
+                );
+            }
+            sink.slow_sec = 0.0;
+            sink.alert = false;
+            return;
+        }
+        sink.slow_sec += open_for.as_secs_f64();
+        if !sink.alert && sink.slow_sec >= self.config.sustained_sec as f64 {
+            sink.alert = true;
+            warn!(
+                "Camera '{}' sink_id={} has written at {:.0} kbit/s, below the encoder's {} kbit/s, for {:.0}s: the card may be failing",
+                self.camera_key, sink_id, kbps, expected_kbps, sink.slow_sec
+            );
+        }
+    }
+
+    /// Every sink that has closed a segment so far.
+    pub fn sinks(&self) -> Vec<SinkThroughput> {
+        self.inner.lock().unwrap().sinks.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_once_slow_for_long_enough_and_clears_on_a_fast_segment() {
+        let monitor = ThroughputMonitor::new("front", 2000, ThroughputConfig { min_ratio: 0.75, sustained_sec: 90 });
+        let minute = Duration::from_secs(60);
+        let at = |kbps: u64| kbps * 1000 / 8 * 60;
+
+        // 2000 kbit/s over a minute, fsynced quickly
+        monitor.segment_written(0, at(2000), minute, Duration::from_millis(50));
+        assert_eq!(monitor.sinks()[0].kbps, 2000.0);
+        assert!(!monitor.sinks()[0].alert);
+
+        // the segment was fine, but took two minutes to reach the card
+        monitor.segment_written(0, at(2000), minute, 2 * minute);
+        assert_eq!(monitor.sinks()[0].kbps, 1000.0);
+        assert!(!monitor.sinks()[0].alert);
+        monitor.segment_written(0, at(1000), minute, Duration::ZERO);
+        let slow = monitor.sinks()[0].clone();
+        assert!(slow.alert && slow.slow_sec == 120.0);
+        assert_eq!(slow.health("front").state, HealthState::Degraded);
+
+        monitor.segment_written(0, at(1800), minute, Duration::ZERO);
+        assert_eq!(monitor.sinks()[0].slow_sec, 0.0);
+        assert_eq!(monitor.sinks()[0].health("front").state, HealthState::Ok);
+
+        // a mode halved the bitrate
+        monitor.set_expected_kbps(1000);
+        monitor.segment_written(1, at(900), minute, Duration::ZERO);
+        assert_eq!(monitor.sinks()[1].slow_sec, 0.0);
+        monitor.segment_written(1, 10, Duration::from_millis(300), Duration::ZERO);
+        assert_eq!(monitor.sinks()[1].kbps, 900.0, "too short to count");
+    }
+}
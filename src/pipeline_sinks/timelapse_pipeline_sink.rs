@@ -0,0 +1,151 @@
+use crate::config::VideoCodec;
+use crate::pipeline_sources::encoder::{decoder_factory, parser_factory};
+use crate::pipeline_sources::pipeline_source::StreamFormat;
+use crate::recording_pipeline::RecordingConfig;
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, trace, warn};
+
+use super::pipeline_sink::{ElementMessageHandler, PipelineSink};
+
+/// Timelapse: one JPEG every `interval_sec`, written to
+/// `<recording_dir>/timelapse_<sink_id>/<YYYY-MM-DD>/<HH-MM-SS>.jpg`.
+///
+/// queue -> parser -> decoder -> videorate -> capsfilter(1/N fps) -> videoconvert -> jpegenc -> multifilesink
+///
+/// multifilesink can only number its files, so each frame is renamed to its
+/// timestamped path once the sink reports it written.
+pub struct TimelapsePipelineSink {
+    config: RecordingConfig,
+    sink_id: i64,
+    interval_sec: u64,
+    codec: VideoCodec,
+    queue: Option<gst::Element>,
+    sink: Option<gst::Element>,
+}
+
+impl TimelapsePipelineSink {
+    pub fn new(config: RecordingConfig, sink_id: i64, interval_sec: u64, codec: VideoCodec) -> Self {
+        TimelapsePipelineSink {
+            config,
+            sink_id,
+            interval_sec: interval_sec.max(1),
+            codec,
+            queue: None,
+            sink: None,
+        }
+    }
+
+    fn output_dir(&self) -> PathBuf {
+        PathBuf::from(&self.config.recording_dir).join(format!("timelapse_{}", self.sink_id))
+    }
+}
+
+/// Move a freshly written frame to `<dir>/<YYYY-MM-DD>/<HH-MM-SS>.jpg`.
+fn file_frame(dir: &Path, written: &Path) -> std::io::Result<PathBuf> {
+    let now = chrono::Local::now();
+    let day_dir = dir.join(now.format("%Y-%m-%d").to_string());
+    fs::create_dir_all(&day_dir)?;
+
+    let dest = day_dir.join(format!("{}.jpg", now.format("%H-%M-%S")));
+    fs::rename(written, &dest)?;
+    Ok(dest)
+}
+
+impl PipelineSink for TimelapsePipelineSink {
+    fn get_sink_pad(&self) -> Result<gst::Pad> {
+        self.queue
+            .as_ref()
+            .context("Queue element not initialized")?
+            .static_pad("sink")
+            .context("Failed to get sink pad from queue")
+    }
+
+    fn get_sink_element(&self) -> Result<gst::Element> {
+        self.sink.clone().context("Sink element not initialized")
+    }
+
+    fn sink_id(&self) -> i64 {
+        self.sink_id
+    }
+
+    fn accepts(&self, format: StreamFormat) -> bool {
+        format == StreamFormat::Encoded(self.codec)
+    }
+
+    fn setup_sink(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
+        let sink_id = self.sink_id;
+        let make = |factory: &str, role: &str| {
+            gst::ElementFactory::make(factory)
+                .name(format!("timelapse_{}_{}", role, sink_id))
+                .build()
+                .with_context(|| format!("Failed to create {}", factory))
+        };
+
+        let queue = make("queue", "queue")?;
+        let parser = make(parser_factory(self.codec), "parser")?;
+        let decoder = make(decoder_factory(self.codec), "decoder")?;
+        let videorate = make("videorate", "videorate")?;
+        let capsfilter = make("capsfilter", "capsfilter")?;
+        let videoconvert = make("videoconvert", "videoconvert")?;
+        let jpegenc = make("jpegenc", "jpegenc")?;
+        let sink = make("multifilesink", "sink")?;
+
+        videorate.set_property("drop-only", true);
+        let caps = gst::Caps::builder("video/x-raw")
+            .field("framerate", gst::Fraction::new(1, self.interval_sec as i32))
+            .build();
+        capsfilter.set_property("caps", &caps);
+
+        let dir = self.output_dir();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create timelapse dir {}", dir.display()))?;
+        sink.set_property(
+            "location",
+            dir.join("frame_%05d.jpg").to_string_lossy().to_string(),
+        );
+        sink.set_property("post-messages", true);
+
+        let elements = [&queue, &parser, &decoder, &videorate, &capsfilter, &videoconvert, &jpegenc, &sink];
+        pipeline
+            .add_many(elements)
+            .context("Failed to add timelapse elements to pipeline")?;
+        gst::Element::link_many(elements).context("Failed to link timelapse elements")?;
+
+        self.queue = Some(queue);
+        self.sink = Some(sink);
+
+        info!(
+            "Timelapse sink {} ready (1 frame every {}s into {})",
+            sink_id,
+            self.interval_sec,
+            dir.display()
+        );
+        Ok(())
+    }
+
+    fn message_handler(&self) -> Option<ElementMessageHandler> {
+        let sink = self.sink.clone()?;
+        let dir = self.output_dir();
+
+        Some(Arc::new(move |msg: &gst::Message| {
+            if msg.src() != Some(sink.upcast_ref::<gst::Object>()) {
+                return;
+            }
+            if let gst::MessageView::Element(element) = msg.view()
+                && let Some(structure) = element.structure()
+                && structure.name() == "GstMultiFileSink"
+                && let Ok(filename) = structure.get::<String>("filename")
+            {
+                match file_frame(&dir, Path::new(&filename)) {
+                    Ok(dest) => trace!("Timelapse frame {}", dest.display()),
+                    Err(e) => warn!("Failed to file timelapse frame {}: {}", filename, e),
+                }
+            }
+        }))
+    }
+}
@@ -0,0 +1,138 @@
+use crate::config::VideoCodec;
+use crate::events::CameraEvent;
+use crate::pipeline_sources::encoder::{decoder_factory, parser_factory};
+use crate::pipeline_sources::pipeline_source::StreamFormat;
+use crate::recording_pipeline::RecordingConfig;
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use tracing::info;
+
+use super::pipeline_sink::{ElementMessageHandler, PipelineSink};
+
+/// Wraps another sink behind a decode -> encode branch:
+/// queue -> parser -> decoder -> videoconvert -> encoder -> parser -> inner sink.
+///
+/// Only inserted by the factory when a sink opts in with `transcode = true`,
+/// since it costs a full software decode + encode per frame.
+pub struct TranscodePipelineSink {
+    config: RecordingConfig,
+    inner: Box<dyn PipelineSink>,
+    from: VideoCodec,
+    to: VideoCodec,
+    queue: Option<gst::Element>,
+}
+
+impl TranscodePipelineSink {
+    pub fn new(
+        config: RecordingConfig,
+        inner: Box<dyn PipelineSink>,
+        from: VideoCodec,
+        to: VideoCodec,
+    ) -> Self {
+        TranscodePipelineSink {
+            config,
+            inner,
+            from,
+            to,
+            queue: None,
+        }
+    }
+}
+
+fn encoder_factory(codec: VideoCodec) -> &'static str {
+    match codec {
+        VideoCodec::H264 => "x264enc",
+        VideoCodec::H265 => "x265enc",
+        VideoCodec::Av1 => "svtav1enc",
+    }
+}
+
+impl PipelineSink for TranscodePipelineSink {
+    fn get_sink_pad(&self) -> Result<gst::Pad> {
+        self.queue
+            .as_ref()
+            .context("Queue element not initialized")?
+            .static_pad("sink")
+            .context("Failed to get sink pad from queue")
+    }
+
+    fn get_sink_element(&self) -> Result<gst::Element> {
+        self.inner.get_sink_element()
+    }
+
+    // audio isn't transcoded, it goes straight to the inner sink
+    fn get_audio_pad(&self) -> Option<gst::Pad> {
+        self.inner.get_audio_pad()
+    }
+
+    fn sink_id(&self) -> i64 {
+        self.inner.sink_id()
+    }
+
+    fn accepts(&self, format: StreamFormat) -> bool {
+        format == StreamFormat::Encoded(self.from)
+    }
+
+    fn setup_sink(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
+        let sink_id = self.inner.sink_id();
+        let make = |factory: &str, role: &str| {
+            gst::ElementFactory::make(factory)
+                .name(format!("transcode_{}_{}", role, sink_id))
+                .build()
+                .with_context(|| format!("Failed to create {}", factory))
+        };
+
+        let queue = make("queue", "queue")?;
+        let in_parser = make(parser_factory(self.from), "in_parser")?;
+        let decoder = make(decoder_factory(self.from), "decoder")?;
+        let videoconvert = make("videoconvert", "videoconvert")?;
+        let encoder = make(encoder_factory(self.to), "encoder")?;
+        let out_parser = make(parser_factory(self.to), "out_parser")?;
+
+        // Keyframe at least once per segment so HLS/splitmuxsink can cut on time
+        let key_int = ((self.config.frame_rate.max(1) as u64 * self.config.video_duration.max(1)) as u32
+            / self.config.time_scale.max(1))
+        .max(1);
+        // x264enc/x265enc share these; key-int-max is guint on one and gint on the other
+        for (property, value) in [("tune", "zerolatency".to_string()), ("key-int-max", key_int.to_string())] {
+            if encoder.find_property(property).is_some() {
+                encoder.set_property_from_str(property, &value);
+            }
+        }
+
+        let elements = [&queue, &in_parser, &decoder, &videoconvert, &encoder, &out_parser];
+        pipeline
+            .add_many(elements)
+            .context("Failed to add transcode elements to pipeline")?;
+        gst::Element::link_many(elements)
+            .map_err(|_| anyhow::anyhow!("Failed to link transcode elements (sink_id={})", sink_id))?;
+
+        self.inner.setup_sink(pipeline)?;
+
+        let inner_pad = self.inner.get_sink_pad()?;
+        out_parser
+            .static_pad("src")
+            .context("Failed to get src pad from transcode parser")?
+            .link(&inner_pad)
+            .with_context(|| format!("Failed to link transcode branch to sink_id={}", sink_id))?;
+
+        self.queue = Some(queue);
+
+        info!(
+            "Transcode branch ready for sink {} ({} -> {})",
+            sink_id,
+            StreamFormat::Encoded(self.from),
+            StreamFormat::Encoded(self.to)
+        );
+        Ok(())
+    }
+
+    fn message_handler(&self) -> Option<ElementMessageHandler> {
+        self.inner.message_handler()
+    }
+
+    fn on_event(&self, event: &CameraEvent) {
+        self.inner.on_event(event)
+    }
+}
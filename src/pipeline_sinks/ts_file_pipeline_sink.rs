@@ -1,152 +1,106 @@
 use crate::recording_pipeline::{ RecordingConfig};
-use anyhow::{Context, Result};
-use gstreamer as gst;
-use gstreamer::prelude::*;
+use std::collections::HashMap;
 use std::fs::{self};
+use std::os::unix::fs::MetadataExt;
 use std::path::PathBuf;
-use std::sync::{Arc, mpsc};
-use std::sync::atomic::{AtomicI64, Ordering};
-use std::sync::mpsc::{Sender, channel};
-use std::thread::JoinHandle;
-use crate::db::db::{DashcamDb };
-use crate::db::db_worker::{DBMessage,DBWorker,start_db_worker};
-use super::pipeline_sink::PipelineSink;
-
-pub struct TsFilePipelineSink {
-    config: RecordingConfig,
-    db_worker_handle: Option<JoinHandle<()>>,
-    db_sender: Arc<Sender<DBMessage>>,
-    camera_id: i64,
-    sink_id: i64,
-    segment_index: Arc<AtomicI64>,
+use std::sync::Mutex;
+use super::segment_counters::{SegmentCounterProvider, SegmentCounters};
+use super::segmented_file_sink::{ClosedSegment, ContainerFormat, SegmentPolicy, SegmentedFileSink};
+
+/// DashcamTs: fixed-size ring of `output_<index>.ts` files, index persisted in
+/// camera_state; every finished file is catalogued in `segments`. With
+/// `generation_names` the files are `g<generation>_s<index>.ts` instead, so a
+/// link to one never starts pointing at a later lap of the ring.
+pub type TsFilePipelineSink = SegmentedFileSink<RingSegmentPolicy>;
+
+pub struct RingSegmentPolicy {
+    counters: Box<dyn SegmentCounterProvider>,
+    // counters of the next segment
+    next: Mutex<SegmentCounters>,
+    // segments being written, with the counters they were opened at
+    open: Mutex<HashMap<String, SegmentCounters>>,
     max_segments: i64,
-    queue: Option<gst::Element>,
-    muxer: Option<gst::Element>,
-    sink: Option<gst::Element>,
+    generation_names: bool,
 }
 
 impl TsFilePipelineSink {
-    pub fn new(config: RecordingConfig, camera_id: i64, sink_id: i64, max_segments: i64, db_sender: Arc<Sender<DBMessage>>) -> Result<Self> {
-        //
-        let (reply_tx, reply_rx) = mpsc::channel();
-        db_sender.send(DBMessage::GetSegmentIndex { camera_id: camera_id, sink_id, reply: reply_tx })?;
-        let segment_index = reply_rx.recv()?;
-        //
-
-        Ok(TsFilePipelineSink {
-            config,
-            db_worker_handle: None,
-            db_sender: db_sender,
-            camera_id,
-            sink_id,
-            segment_index: Arc::new(AtomicI64::new(segment_index)),
+    /// `counters` says where the ring continues and hears about every step;
+    /// `DbSegmentCounters` in production.
+    pub fn new(
+        config: RecordingConfig,
+        sink_id: i64,
+        max_segments: i64,
+        counters: impl SegmentCounterProvider,
+    ) -> Self {
+        let policy = RingSegmentPolicy {
+            next: Mutex::new(counters.start()),
+            open: Mutex::new(HashMap::new()),
+            counters: Box::new(counters),
             max_segments,
-            queue: None,
-            muxer: None,
-            sink: None,
-        })
-    }
-}
+            generation_names: false,
+        };
 
-////////////////////////////////////////////
-impl PipelineSink for TsFilePipelineSink {
-    fn get_sink_pad(&self) -> Result<gst::Pad> {
-        self.queue
-            .as_ref()
-            .context("Queue element not initialized")?
-            .static_pad("sink")
-            .context("Failed to get sink pad from queue")
+        SegmentedFileSink::with_policy(config, sink_id, ContainerFormat::Ts, policy)
     }
 
-    fn get_sink_element(&self) -> Result<gst::Element> {
-        self.sink.clone().context("Sink element not initialized")
+    /// Name files `g<generation>_s<index>.ts`; whatever an earlier lap left in
+    /// the slot is removed when it comes round.
+    pub fn with_generation_names(mut self, generation_names: bool) -> Self {
+        if let Some(policy) = self.policy_mut() {
+            policy.generation_names = generation_names;
+        }
+        self
     }
+}
 
-    fn setup_sink(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
-        let video_duration = self.config.video_duration;
+impl SegmentPolicy for RingSegmentPolicy {
+    // TODO rethink this format-location callback ?
+    fn next_location(&self, config: &RecordingConfig, _extension: &str) -> String {
+        let mut next = self.next.lock().unwrap();
+        let current = *next;
 
-        self.queue = Some(
-            gst::ElementFactory::make("queue")
-                .name("file_sink_queue")
-                .build()
-                .context("Failed to create queue")?,
-        );
+        let filename = make_filename_closure(config, current, self.generation_names);
 
-        self.muxer = Some(
-            gst::ElementFactory::make("mpegtsmux")
-                .name("muxer")
-                .build()
-                .context("Failed to create mpegtsmux")?,
-        );
+        // wrap next_index if necessary
+        let next_index = if current.index + 1 >= self.max_segments {
+            0
+        } else {
+            current.index + 1
+        };
 
-        self.sink = Some(
-            gst::ElementFactory::make("splitmuxsink")
-                .name("sink")
-                .build()
-                .context("Failed to create splitmuxsink")?,
-        );
+        *next = current.advanced(next_index, self.max_segments);
+        self.counters.advance(&config.camera_key, next_index, self.max_segments);
+        self.open.lock().unwrap().insert(filename.clone(), current);
 
-        let queue = self.queue.clone().unwrap();
-        let muxer = self.muxer.clone().unwrap();
-        let sink = self.sink.clone().unwrap();
-
-        sink.set_property("muxer", &muxer);
-        sink.set_property("max-size-time", video_duration * 1_000_000_000u64);
-
-        let config = self.config.clone();
-        let camera_id = self.camera_id;
-        let sink_id = self.sink_id;
-        let segment_index = self.segment_index.clone();
-        let max_segments = self.max_segments;
-        let db_sender = self.db_sender.clone();
-
-        // TODO rethink this format-location callback ?
-        sink.connect("format-location", false, move |_args| {
-            let current_index = segment_index.load(Ordering::SeqCst);
-
-            let filename = make_filename_closure(&config, current_index);
-
-            // wrap next_index if necessary
-            let next_index = if current_index + 1 >= max_segments {
-                0
-            } else {
-                current_index + 1
-            };
-
-            segment_index.store(next_index, Ordering::SeqCst);
-            let _ = db_sender.send(DBMessage::SegmentUpdate {
-                camera_id: camera_id,
-                sink_id: sink_id,
-                segment_index: next_index,
-                max_segments: max_segments,
-            });
-            
-            Some(filename.to_value())
-        });
-
-        pipeline
-            .add_many(&[&queue, &sink])
-            .context("Failed to add sink elements to pipeline")?;
-
-        queue
-            .link(&sink)
-            .context("Failed to link queue to splitmuxsink")?;
-
-        Ok(())
+        filename
+    }
+
+    fn on_fragment_closed(&self, segment: &ClosedSegment) {
+        if let Some(counters) = self.open.lock().unwrap().remove(&segment.location) {
+            self.counters.segment_closed(counters, segment);
+        }
     }
 }
 
-impl Drop for TsFilePipelineSink {
-    fn drop(&mut self) {
-        // if let Some(handle) = self.db_worker_handle.take() {
-        //     let _ = handle.join();
-        // }
-        // don't need this because an mpsc::channel() close will produce Err on recv() and exit db worker thread loop
+/// Ring slot of a `dashcamts` file name: N from `output_N.ts` or `g<generation>_sN.ts`.
+pub fn ring_slot(file_name: &str) -> Option<i64> {
+    let stem = file_name.strip_suffix(".ts")?;
+    let index = match stem.strip_prefix("output_") {
+        Some(index) => index,
+        None => {
+            let (generation, index) = stem.strip_prefix('g')?.split_once("_s")?;
+            generation.parse::<i64>().ok()?;
+            index
+        }
+    };
+    if !index.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
     }
+    index.parse().ok()
 }
 
-fn make_filename_closure(config: &RecordingConfig, segment_index: i64) -> String {
-    let current_index = segment_index;
+fn make_filename_closure(config: &RecordingConfig, counters: SegmentCounters, generation_names: bool) -> String {
+    let current_index = counters.index;
 
     let subdir = {
         let subdir_digits = current_index / 1000;
@@ -155,9 +109,167 @@ fn make_filename_closure(config: &RecordingConfig, segment_index: i64) -> String
 
     let _ = fs::create_dir_all(&subdir);
 
-    let ts_filename = format!("output_{}.ts", current_index);
+    let ts_filename = if generation_names {
+        format!("g{}_s{}.ts", counters.generation, current_index)
+    } else {
+        format!("output_{}.ts", current_index)
+    };
     let ts_filepath = PathBuf::from(&subdir).join(&ts_filename);
-    let ts_filepath_str = ts_filepath.to_string_lossy().to_string();
 
-    ts_filepath_str
+    // the slot's file from the last lap (or from before generation names) has another name
+    if generation_names && let Ok(entries) = fs::read_dir(&subdir) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name != ts_filename && ring_slot(&name) == Some(current_index) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    // a saved copy (see `protect`) may be a hard link to it; start a new file instead of truncating both
+    if fs::metadata(&ts_filepath).is_ok_and(|m| m.nlink() > 1) {
+        let _ = fs::remove_file(&ts_filepath);
+    }
+
+    ts_filepath.to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline_sinks::segment_counters::{MemorySegmentCounters, SegmentCounters};
+    use std::sync::Arc;
+
+    #[test]
+    fn ring_wraps_and_starts_a_new_generation() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let config = RecordingConfig {
+            recording_dir: tmp.path().to_string_lossy().to_string(),
+            ..RecordingConfig::default()
+        };
+        let counters = Arc::new(MemorySegmentCounters::new(SegmentCounters { index: 1998, generation: 3, absolute: 50 }));
+        let sink = TsFilePipelineSink::new(config.clone(), 0, 2000, counters.clone());
+
+        let files: Vec<String> = (0..4).map(|_| sink.policy().next_location(&config, "ts")).collect();
+        let expected: Vec<String> = ["1/output_1998.ts", "1/output_1999.ts", "0/output_0.ts", "0/output_1.ts"]
+            .iter()
+            .map(|rel| tmp.path().join(rel).to_string_lossy().to_string())
+            .collect();
+        assert_eq!(files, expected);
+        assert_eq!(counters.counters(), SegmentCounters { index: 2, generation: 4, absolute: 54 });
+        assert!(tmp.path().join("1").is_dir());
+    }
+
+    #[test]
+    fn saved_segments_are_unlinked_not_overwritten() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let config = RecordingConfig {
+            recording_dir: tmp.path().to_string_lossy().to_string(),
+            ..RecordingConfig::default()
+        };
+        let counters = Arc::new(MemorySegmentCounters::new(SegmentCounters::default()));
+        let sink = TsFilePipelineSink::new(config.clone(), 0, 1, counters);
+
+        let ring_file = sink.policy().next_location(&config, "ts");
+        fs::write(&ring_file, b"impact").unwrap();
+        let saved = tmp.path().join("saved.ts");
+        fs::hard_link(&ring_file, &saved).unwrap();
+
+        assert_eq!(sink.policy().next_location(&config, "ts"), ring_file, "a ring of one");
+        assert!(!std::path::Path::new(&ring_file).exists());
+        assert_eq!(fs::read(&saved).unwrap(), b"impact");
+    }
+
+    #[test]
+    fn generation_names_replace_whatever_held_the_slot() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let config = RecordingConfig {
+            recording_dir: tmp.path().to_string_lossy().to_string(),
+            ..RecordingConfig::default()
+        };
+        let counters = Arc::new(MemorySegmentCounters::new(SegmentCounters { index: 1, generation: 0, absolute: 1 }));
+        let sink = TsFilePipelineSink::new(config.clone(), 0, 2, counters).with_generation_names(true);
+        fs::create_dir_all(tmp.path().join("0")).unwrap();
+        fs::write(tmp.path().join("0/output_0.ts"), b"before generation names").unwrap();
+        fs::write(tmp.path().join("0/notes.txt"), b"not a segment").unwrap();
+
+        // the muxer writes each file before asking for the next
+        let files: Vec<String> = (0..3)
+            .map(|_| {
+                let file = sink.policy().next_location(&config, "ts");
+                fs::write(&file, b"segment").unwrap();
+                file
+            })
+            .collect();
+        let expected: Vec<String> = ["0/g0_s1.ts", "0/g1_s0.ts", "0/g1_s1.ts"]
+            .iter()
+            .map(|rel| tmp.path().join(rel).to_string_lossy().to_string())
+            .collect();
+        assert_eq!(files, expected);
+
+        let mut left: Vec<String> = fs::read_dir(tmp.path().join("0"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, vec!["g1_s0.ts", "g1_s1.ts", "notes.txt"]);
+
+        assert_eq!(ring_slot("output_12.ts"), Some(12));
+        assert_eq!(ring_slot("g3_s1999.ts"), Some(1999));
+        assert_eq!(ring_slot("gx_s1.ts"), None);
+        assert_eq!(ring_slot("output_1.m3u8"), None);
+    }
+
+    #[derive(Default)]
+    struct Catalog {
+        closed: Mutex<Vec<(SegmentCounters, String)>>,
+    }
+
+    impl SegmentCounterProvider for Catalog {
+        fn start(&self) -> SegmentCounters {
+            SegmentCounters { index: 1, generation: 5, absolute: 21 }
+        }
+
+        fn advance(&self, _camera_key: &str, _next_index: i64, _max_segments: i64) {}
+
+        fn segment_closed(&self, counters: SegmentCounters, segment: &ClosedSegment) {
+            self.closed.lock().unwrap().push((counters, segment.rel_path.clone()));
+        }
+    }
+
+    #[test]
+    fn closed_segments_are_catalogued_with_the_counters_they_opened_at() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let config = RecordingConfig {
+            recording_dir: tmp.path().to_string_lossy().to_string(),
+            ..RecordingConfig::default()
+        };
+        let catalog = Arc::new(Catalog::default());
+        let sink = TsFilePipelineSink::new(config.clone(), 0, 2, catalog.clone());
+
+        let first = sink.policy().next_location(&config, "ts");
+        let second = sink.policy().next_location(&config, "ts");
+        let closed = |location: &str, rel: &str| ClosedSegment {
+            location: location.to_string(),
+            rel_path: rel.to_string(),
+            disk: None,
+            start_utc: 0,
+            end_utc: 60,
+            bytes: Some(1),
+            stream: Default::default(),
+            restarted: false,
+        };
+        sink.policy().on_fragment_closed(&closed(&first, "cam/0/output_1.ts"));
+        sink.policy().on_fragment_closed(&closed(&first, "cam/0/output_1.ts"));
+        sink.policy().on_fragment_closed(&closed(&second, "cam/0/output_0.ts"));
+
+        assert_eq!(
+            *catalog.closed.lock().unwrap(),
+            vec![
+                (SegmentCounters { index: 1, generation: 5, absolute: 21 }, "cam/0/output_1.ts".to_string()),
+                (SegmentCounters { index: 0, generation: 6, absolute: 22 }, "cam/0/output_0.ts".to_string()),
+            ],
+            "once per file, with the wrap counted"
+        );
+    }
 }
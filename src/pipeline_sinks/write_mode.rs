@@ -0,0 +1,157 @@
+//! How segment files reach the card: `write_mode` on file sinks.
+//!
+//! With the default `buffered`, a segment sits in the page cache until
+//! writeback, and its clean pages stay cached after that. On a 512 MB Pi Zero
+//! that's most of RAM: dirty pages pile up to `vm.dirty_ratio`, writeback then
+//! stalls every writer at once (muxer, DB, upload), and cached footage nobody
+//! will read again evicts pages the pipeline needs.
+//!
+//! - `drop_cache`: after the fsync on close, `posix_fadvise(DONTNEED)` drops the
+//!   segment from the page cache. Free while writing, but dirty pages still
+//!   build up within a segment.
+//! - `sync`: filesink opens the file `O_SYNC`, so a write returns once it is on
+//!   the card and nothing dirty is left behind; the segment is dropped from the
+//!   cache on close too. Every write now waits for the card, so writes are
+//!   batched into `SYNC_BUFFER_BYTES` and the queue in front of the sink may
+//!   hold `SYNC_QUEUE` of stream while the card catches up. Sustained bitrate is
+//!   then bounded by the card's write latency instead of by RAM; run
+//!   `cargo test -- --ignored write_mode_bench` with `DASHCAM_BENCH_DIR` on the
+//!   card to see what it manages before picking this.
+//!
+//! `O_DIRECT` isn't offered: it needs block-aligned buffers, sizes and offsets,
+//! and muxers write whatever sizes they like (mp4mux also seeks back to patch
+//! its header), so filesink can't open files that way.
+
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use serde::Deserialize;
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+/// filesink buffer in `sync` mode: one synchronous write per this much stream
+pub const SYNC_BUFFER_BYTES: u32 = 512 * 1024;
+/// Stream the queue before the sink may hold in `sync` mode while a write blocks
+pub const SYNC_QUEUE: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteMode {
+    #[default]
+    Buffered,
+    DropCache,
+    Sync,
+}
+
+impl WriteMode {
+    /// Set up splitmuxsink (and the queue feeding it) for this mode.
+    pub fn apply(self, splitmuxsink: &gst::Element, queue: &gst::Element, sink_id: i64) -> Result<()> {
+        if self != WriteMode::Sync {
+            return Ok(());
+        }
+        let filesink = gst::ElementFactory::make("filesink")
+            .name(format!("file_{}", sink_id))
+            .property("o-sync", true)
+            .property_from_str("buffer-mode", "full")
+            .property("buffer-size", SYNC_BUFFER_BYTES)
+            .build()
+            .context("Failed to create filesink")?;
+        splitmuxsink.set_property("sink", &filesink);
+        queue.set_property("max-size-buffers", 0u32);
+        queue.set_property("max-size-bytes", 0u32);
+        queue.set_property("max-size-time", SYNC_QUEUE.as_nanos() as u64);
+        Ok(())
+    }
+
+    /// Whether a closed segment is dropped from the page cache.
+    pub fn drops_cache(self) -> bool {
+        self != WriteMode::Buffered
+    }
+}
+
+/// Tell the kernel `file`'s pages won't be read again. Only clean pages go, so
+/// call it after the fsync.
+pub fn drop_cached_pages(file: &File) -> io::Result<()> {
+    // SAFETY: plain syscall on a descriptor `file` keeps open
+    match unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) } {
+        0 => Ok(()),
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::time::Instant;
+
+    #[test]
+    fn closed_segments_can_be_dropped_from_the_cache() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("output_0.ts");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(&[0x47; 188 * 7 * 100]).unwrap();
+        file.sync_all().unwrap();
+        drop_cached_pages(&file).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap().len(), 188 * 7 * 100);
+
+        assert!(!WriteMode::Buffered.drops_cache());
+        assert!(WriteMode::DropCache.drops_cache() && WriteMode::Sync.drops_cache());
+    }
+
+    fn meminfo_kb(field: &str) -> i64 {
+        std::fs::read_to_string("/proc/meminfo")
+            .unwrap_or_default()
+            .lines()
+            .find_map(|l| l.strip_prefix(field)?.strip_prefix(':')?.trim().strip_suffix(" kB")?.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Writes 64 MB per mode in mpegtsmux-sized chunks and prints the time taken
+    /// and what was left in the page cache. Point `DASHCAM_BENCH_DIR` at the card.
+    #[test]
+    #[ignore]
+    fn write_mode_bench() {
+        let dir = std::env::var("DASHCAM_BENCH_DIR")
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|_| std::env::temp_dir());
+        let chunk = [0x47u8; 188 * 7];
+        let total = 64 * 1024 * 1024;
+        for mode in [WriteMode::Buffered, WriteMode::DropCache, WriteMode::Sync] {
+            let path = dir.join(format!("write_mode_bench_{:?}.ts", mode));
+            let (cached, dirty) = (meminfo_kb("Cached"), meminfo_kb("Dirty"));
+            let started = Instant::now();
+            let mut options = OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            if mode == WriteMode::Sync {
+                options.custom_flags(libc::O_SYNC);
+            }
+            let file = options.open(&path).unwrap();
+            let capacity = if mode == WriteMode::Sync { SYNC_BUFFER_BYTES as usize } else { 64 * 1024 };
+            let mut writer = io::BufWriter::with_capacity(capacity, &file);
+            for _ in 0..total / chunk.len() {
+                writer.write_all(&chunk).unwrap();
+            }
+            writer.flush().unwrap();
+            let dirty_peak = meminfo_kb("Dirty") - dirty;
+            drop(writer);
+            file.sync_all().unwrap();
+            if mode.drops_cache() {
+                drop_cached_pages(&file).unwrap();
+            }
+            let elapsed = started.elapsed();
+            println!(
+                "{:?}: {:.1} MB/s, {} kB dirty before the fsync, {} kB left cached",
+                mode,
+                total as f64 / 1e6 / elapsed.as_secs_f64(),
+                dirty_peak,
+                meminfo_kb("Cached") - cached
+            );
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! `audio = { ... }` on a camera: a microphone recorded with the video.
+//!
+//! alsasrc (autoaudiosrc without a `device`) -> audioconvert -> audioresample
+//! -> encoder -> parser -> tee, built next to the video source. Every sink that
+//! muxes audio (the file sinks and HLS) gets a pad of that tee behind a valve of
+//! its own, so switching a sink off drops its audio along with its video.
+
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use serde::Deserialize;
+use tracing::info;
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioCodec {
+    #[default]
+    Aac,
+    Opus,
+}
+
+impl AudioCodec {
+    pub fn encoder_factory(self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "avenc_aac",
+            AudioCodec::Opus => "opusenc",
+        }
+    }
+
+    pub fn parser_factory(self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aacparse",
+            AudioCodec::Opus => "opusparse",
+        }
+    }
+}
+
+fn default_audio_bitrate() -> u32 {
+    64
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AudioConfig {
+    /// ALSA device, e.g. `hw:1,0` or `plughw:CARD=Device`; autoaudiosrc picks one when unset
+    pub device: Option<String>,
+    #[serde(default)]
+    pub codec: AudioCodec,
+    #[serde(default = "default_audio_bitrate")]
+    pub bitrate_kbps: u32,
+}
+
+impl AudioConfig {
+    /// Why this can't be recorded, if it can't. `hls`: the camera has an HLS sink.
+    pub fn problem(&self, hls: bool) -> Option<String> {
+        if self.device.as_ref().is_some_and(|d| d.is_empty()) {
+            return Some("audio device can't be empty (leave it out for autoaudiosrc)".to_string());
+        }
+        // opusenc takes 6 to 510 kbit/s; AAC is fine in the same range
+        if !(6..=510).contains(&self.bitrate_kbps) {
+            return Some(format!("audio bitrate_kbps {} is outside 6..=510", self.bitrate_kbps));
+        }
+        // browsers play AAC over HLS, not Opus in MPEG-TS
+        if hls && self.codec == AudioCodec::Opus {
+            return Some("HLS needs codec = \"aac\" for audio".to_string());
+        }
+        None
+    }
+}
+
+/// Add the audio branch to `pipeline` and return its tee.
+pub fn build_audio_branch(pipeline: &gst::Pipeline, camera_key: &str, config: &AudioConfig) -> Result<gst::Element> {
+    let make = |factory: &str, name: &str| {
+        gst::ElementFactory::make(factory)
+            .name(name)
+            .build()
+            .with_context(|| format!("Failed to create {} (is its plugin installed?)", factory))
+    };
+
+    let src = match &config.device {
+        Some(device) => {
+            let src = make("alsasrc", "audio_src")?;
+            src.set_property("device", device);
+            src
+        }
+        None => make("autoaudiosrc", "audio_src")?,
+    };
+    // a stalled encoder mustn't hold up the capture thread
+    let queue = make("queue", "audio_queue")?;
+    let convert = make("audioconvert", "audio_convert")?;
+    let resample = make("audioresample", "audio_resample")?;
+    let encoder = make(config.codec.encoder_factory(), "audio_encoder")?;
+    // bitrate is a gint on opusenc and a gint64 on avenc_aac
+    encoder.set_property_from_str("bitrate", &(config.bitrate_kbps * 1000).to_string());
+    let parser = make(config.codec.parser_factory(), "audio_parser")?;
+    let tee = make("tee", "audio_tee")?;
+
+    let elements = [&src, &queue, &convert, &resample, &encoder, &parser, &tee];
+    pipeline.add_many(elements).context("Failed to add audio elements to pipeline")?;
+    gst::Element::link_many(elements).context("Failed to link audio elements")?;
+
+    info!(
+        "Camera '{}' audio from {} ({:?}, {} kbit/s)",
+        camera_key,
+        config.device.as_deref().unwrap_or("autoaudiosrc"),
+        config.codec,
+        config.bitrate_kbps
+    );
+    Ok(tee)
+}
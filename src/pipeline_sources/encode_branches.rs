@@ -0,0 +1,146 @@
+//! Warm-spare encoders for `[[modes]]` with `warm_spare = true`. Raw frames go
+//! through a tee to one encoder per prepared encoding, and an input-selector
+//! picks the one that reaches the parser:
+//!
+//! `tee -> queue -> valve -> videoscale -> videorate -> capsfilter -> encoder -> input-selector`
+//!
+//! Idle branches sit behind a closed valve, so they cost memory but no CPU, and
+//! switching between prepared encodings is a selector flip instead of a
+//! pipeline rebuild. Without spares this is just the one encoder, as before.
+
+use anyhow::{Context, Result, anyhow};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use gstreamer_video as gst_video;
+use tracing::info;
+
+use crate::config::VideoCodec;
+use crate::recording_pipeline::{EncodingSettings, RecordingConfig};
+use super::encoder::make_encoder;
+
+struct Branch {
+    encoding: EncodingSettings,
+    valve: gst::Element,
+    encoder: gst::Element,
+    // request pad on the selector
+    selector_pad: gst::Pad,
+}
+
+pub struct EncodeBranches {
+    // the encoder, or the bin holding every branch
+    element: gst::Element,
+    selector: Option<gst::Element>,
+    branches: Vec<Branch>,
+    active: usize,
+}
+
+impl EncodeBranches {
+    /// Encoder for `config`'s encoding, plus a spare branch for each of its
+    /// `spare_encodings`.
+    pub fn build(codec: VideoCodec, config: &RecordingConfig) -> Result<Self> {
+        let active = config.encoding();
+        let mut encodings = vec![active];
+        for spare in &config.spare_encodings {
+            if !encodings.contains(spare) {
+                encodings.push(*spare);
+            }
+        }
+        if encodings.len() == 1 {
+            return Ok(Self {
+                element: make_encoder(codec, config)?,
+                selector: None,
+                branches: Vec::new(),
+                active: 0,
+            });
+        }
+
+        let make = |factory: &str, name: String| {
+            gst::ElementFactory::make(factory)
+                .name(name)
+                .build()
+                .with_context(|| format!("Failed to create {}", factory))
+        };
+        let bin = gst::Bin::with_name("encoders");
+        let tee = make("tee", "raw_tee".to_string())?;
+        let selector = make("input-selector", "encoder_selector".to_string())?;
+        bin.add_many([&tee, &selector]).context("Failed to add encoder tee/selector")?;
+
+        let mut branches = Vec::new();
+        for (i, encoding) in encodings.into_iter().enumerate() {
+            let mut branch_config = config.clone();
+            branch_config.set_encoding(encoding);
+
+            let queue = make("queue", format!("encoder_queue_{}", i))?;
+            let valve = make("valve", format!("encoder_valve_{}", i))?;
+            valve.set_property("drop", i != 0);
+            let scale = make("videoscale", format!("encoder_scale_{}", i))?;
+            let rate = make("videorate", format!("encoder_rate_{}", i))?;
+            let capsfilter = make("capsfilter", format!("encoder_caps_{}", i))?;
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("width", encoding.width)
+                .field("height", encoding.height)
+                .field("framerate", gst::Fraction::new(encoding.frame_rate, 1))
+                .build();
+            capsfilter.set_property("caps", &caps);
+            let encoder = make_encoder(codec, &branch_config)?;
+            encoder.set_property("name", format!("encoder_{}", i));
+
+            let elements = [&queue, &valve, &scale, &rate, &capsfilter, &encoder];
+            bin.add_many(elements).context("Failed to add encoder branch")?;
+            gst::Element::link_many(elements).map_err(|_| anyhow!("Failed to link encoder branch {}", i))?;
+            tee.link(&queue).map_err(|_| anyhow!("Failed to link encoder branch {} to the tee", i))?;
+
+            let selector_pad = selector
+                .request_pad_simple("sink_%u")
+                .context("input-selector gave no sink pad")?;
+            encoder
+                .static_pad("src")
+                .context("Encoder has no src pad")?
+                .link(&selector_pad)
+                .map_err(|e| anyhow!("Failed to link encoder branch {} to the selector: {:?}", i, e))?;
+            branches.push(Branch { encoding, valve, encoder, selector_pad });
+        }
+        selector.set_property("active-pad", &branches[0].selector_pad);
+
+        let sink = gst::GhostPad::with_target(&tee.static_pad("sink").context("tee has no sink pad")?)?;
+        let src = gst::GhostPad::with_target(&selector.static_pad("src").context("input-selector has no src pad")?)?;
+        bin.add_pad(&sink)?;
+        bin.add_pad(&src)?;
+
+        Ok(Self {
+            element: bin.upcast(),
+            selector: Some(selector),
+            branches,
+            active: 0,
+        })
+    }
+
+    /// What goes between the raw frames and the parser.
+    pub fn element(&self) -> &gst::Element {
+        &self.element
+    }
+
+    /// Flip to the branch prepared for `encoding`; false if there is none.
+    pub fn switch_to(&mut self, encoding: EncodingSettings) -> bool {
+        let (Some(selector), Some(next)) = (&self.selector, self.branches.iter().position(|b| b.encoding == encoding))
+        else {
+            return false;
+        };
+        if next == self.active {
+            return true;
+        }
+        let (old, new) = (&self.branches[self.active], &self.branches[next]);
+        new.valve.set_property("drop", false);
+        // the spare's last frames are stale; start the switched-to stream on a keyframe
+        new.encoder
+            .send_event(gst_video::UpstreamForceKeyUnitEvent::builder().all_headers(true).build());
+        selector.set_property("active-pad", &new.selector_pad);
+        old.valve.set_property("drop", true);
+        self.active = next;
+        info!(
+            "Switched to spare encoder {}x{} at {} fps, {} kbit/s",
+            encoding.width, encoding.height, encoding.frame_rate, encoding.bitrate_kbps
+        );
+        true
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{Context, Result, anyhow};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+
+use crate::config::{EncoderKind, VideoCodec};
+use crate::recording_pipeline::RecordingConfig;
+
+/// AV1 encoders in order of preference: hardware first, then SVT-AV1, then libaom.
+#[cfg(feature = "av1")]
+const AV1_ENCODERS: [&str; 5] = ["vaav1enc", "qsvav1enc", "nvav1enc", "svtav1enc", "av1enc"];
+
+/// Parser that goes between an encoder (or a passthrough source) and the tee.
+pub fn parser_factory(codec: VideoCodec) -> &'static str {
+    match codec {
+        VideoCodec::H264 => "h264parse",
+        VideoCodec::H265 => "h265parse",
+        VideoCodec::Av1 => "av1parse",
+    }
+}
+
+/// Software decoder for sinks that need raw frames back (transcode, timelapse).
+pub fn decoder_factory(codec: VideoCodec) -> &'static str {
+    match codec {
+        VideoCodec::H264 => "avdec_h264",
+        VideoCodec::H265 => "avdec_h265",
+        VideoCodec::Av1 => "dav1ddec",
+    }
+}
+
+/// Caps media type for an encoded `codec` stream.
+pub fn media_type(codec: VideoCodec) -> &'static str {
+    match codec {
+        VideoCodec::H264 => "video/x-h264",
+        VideoCodec::H265 => "video/x-h265",
+        VideoCodec::Av1 => "video/x-av1",
+    }
+}
+
+/// Software/hardware encoder for raw sources, configured for live recording.
+/// H.264 and H.265 use `config.encoder`, x264enc/x265enc by default.
+pub fn make_encoder(codec: VideoCodec, config: &RecordingConfig) -> Result<gst::Element> {
+    // a keyframe per second unless configured; more often when segments are
+    // shorter (`--simulate`)
+    let key_int = config.keyframe_interval.unwrap_or(config.frame_rate.max(1) as u32);
+    let key_int = (key_int / config.time_scale.max(1)).max(1);
+
+    match config.encoder.or(EncoderKind::software(codec)) {
+        Some(kind) => make_kind_encoder(kind, config, key_int),
+        None => make_av1_encoder(key_int),
+    }
+}
+
+/// The same settings, under each encoder's own property names.
+fn make_kind_encoder(kind: EncoderKind, config: &RecordingConfig, key_int: u32) -> Result<gst::Element> {
+    let factory = kind.factory();
+    let encoder = gst::ElementFactory::make(factory)
+        .name("encoder")
+        .build()
+        .with_context(|| format!("Failed to create {} (is its plugin installed?)", factory))?;
+    match kind {
+        // same knobs, but key-int-max is a guint on one and a gint on the other
+        EncoderKind::X264enc | EncoderKind::X265enc => {
+            encoder.set_property_from_str("tune", config.tune.as_deref().unwrap_or("zerolatency"));
+            encoder.set_property_from_str("speed-preset", config.speed_preset.as_deref().unwrap_or("ultrafast"));
+            encoder.set_property("bitrate", config.bitrate_kbps);
+            if kind == EncoderKind::X264enc {
+                encoder.set_property("key-int-max", key_int);
+            } else {
+                encoder.set_property("key-int-max", key_int as i32);
+            }
+        }
+        // Driver controls instead of properties; the bitrate is in bit/s. Headers
+        // go with every keyframe so each segment can be decoded on its own.
+        EncoderKind::V4l2h264enc | EncoderKind::V4l2h265enc => {
+            let period = if kind == EncoderKind::V4l2h264enc { "h264_i_frame_period" } else { "video_gop_size" };
+            let controls = gst::Structure::builder("controls")
+                .field("video_bitrate", (config.bitrate_kbps * 1000) as i32)
+                .field(period, key_int as i32)
+                .field("repeat_sequence_header", 1i32)
+                .build();
+            encoder.set_property("extra-controls", &controls);
+        }
+        EncoderKind::Vaapih264enc | EncoderKind::Vaapih265enc => {
+            encoder.set_property_from_str("rate-control", "cbr");
+            encoder.set_property("bitrate", config.bitrate_kbps);
+            encoder.set_property("keyframe-period", key_int);
+        }
+    }
+    tracing::info!("Using {} for {:?}", factory, kind.codec());
+    Ok(encoder)
+}
+
+#[cfg(feature = "av1")]
+fn make_av1_encoder(key_int: u32) -> Result<gst::Element> {
+    let factory = AV1_ENCODERS
+        .iter()
+        .find(|name| gst::ElementFactory::find(name).is_some())
+        .ok_or_else(|| anyhow!("No AV1 encoder available (tried {})", AV1_ENCODERS.join(", ")))?;
+
+    let encoder = gst::ElementFactory::make(factory)
+        .name("encoder")
+        .build()
+        .with_context(|| format!("Failed to create {}", factory))?;
+
+    // Property names differ per encoder; set whichever keyframe interval it has.
+    for property in ["key-int-max", "intra-period-length", "gop-size", "keyframe-max-dist"] {
+        if encoder.find_property(property).is_some() {
+            encoder.set_property_from_str(property, &key_int.to_string());
+            break;
+        }
+    }
+    // SVT-AV1: higher preset = faster. Anything below ~8 won't keep up on an SBC.
+    if *factory == "svtav1enc" {
+        encoder.set_property_from_str("preset", "10");
+    }
+
+    tracing::info!("Using {} for AV1", factory);
+    Ok(encoder)
+}
+
+#[cfg(not(feature = "av1"))]
+fn make_av1_encoder(_key_int: u32) -> Result<gst::Element> {
+    Err(anyhow!("AV1 encoding needs dashcam_rs built with the `av1` feature"))
+}
@@ -1,22 +1,28 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 #[allow(dead_code)]
 use gstreamer as gst;
 use gstreamer::prelude::*;
 use tracing::info;
 
-use crate::recording_pipeline::{ RecordingConfig};
-use super::pipeline_source::PipelineSource;
+use crate::config::VideoCodec;
+use crate::recording_pipeline::{EncodingSettings, RecordingConfig};
+use super::encode_branches::EncodeBranches;
+use super::encoder::parser_factory;
+use super::pipeline_source::{PipelineSource, StreamFormat};
+use super::processing::ProcessingChain;
 
 ///
 /// def
 ///
 pub struct LibcameraPipelineSource {
     config: RecordingConfig,
+    codec: VideoCodec,
     source: Option<gst::Element>,
-    encoder: Option<gst::Element>,
+    encoder: Option<EncodeBranches>,
     queue: Option<gst::Element>,
     capsfilter: Option<gst::Element>,
     videoconvert: Option<gst::Element>,
+    processing: ProcessingChain,
     videoflip: Option<gst::Element>,
     parser: Option<gst::Element>,
     tee: Option<gst::Element>,
@@ -28,17 +34,25 @@ pub struct LibcameraPipelineSource {
 impl LibcameraPipelineSource {
     pub fn new(config: RecordingConfig) -> Self {
         LibcameraPipelineSource {
-            config: config,
+            config,
+            codec: VideoCodec::H264,
             source: None,
             encoder: None,
             queue: None,
             capsfilter: None,
             videoconvert: None,
+            processing: ProcessingChain::default(),
             videoflip: None,
             parser: None,
             tee: None,
         }
     }
+
+    /// Encode to `codec` instead of H.264.
+    pub fn with_codec(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self
+    }
 }
 
 impl Default for LibcameraPipelineSource {
@@ -59,6 +73,30 @@ impl PipelineSource for LibcameraPipelineSource {
         self.tee.clone().context("Tee element not initialized")
     }
 
+    fn output_format(&self) -> StreamFormat {
+        StreamFormat::Encoded(self.codec)
+    }
+
+    fn check_ready(&self) -> Result<()> {
+        // Without libcamera's device provider there's nobody to ask; libcamerasrc will say
+        let Some(provider) = gst::DeviceProviderFactory::by_name("libcameraprovider") else {
+            return Ok(());
+        };
+        if provider.devices().is_empty() {
+            bail!("libcamera doesn't list any camera yet");
+        }
+        Ok(())
+    }
+
+    fn switch_encoding(&mut self, encoding: EncodingSettings) -> bool {
+        self.encoder.as_mut().is_some_and(|encoder| encoder.switch_to(encoding))
+    }
+
+    fn set_encoding(&mut self, encoding: EncodingSettings) -> bool {
+        self.config.set_encoding(encoding);
+        true
+    }
+
     fn setup_source(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
         info!("Creating gstreamer libcamera source");
 
@@ -69,12 +107,8 @@ impl PipelineSource for LibcameraPipelineSource {
                 .context("Failed to create libcamerasrc")?,
         );
 
-        self.encoder = Some(
-            gst::ElementFactory::make("x264enc")
-                .name("encoder")
-                .build()
-                .context("Failed to create x264enc")?,
-        );
+        self.processing = ProcessingChain::build(&self.config.processing, self.config.overlay_gps)?;
+        self.encoder = Some(EncodeBranches::build(self.codec, &self.config)?);
 
         self.queue = Some(
             gst::ElementFactory::make("queue")
@@ -105,11 +139,12 @@ impl PipelineSource for LibcameraPipelineSource {
                 .context("Failed to create videoflip")?,
         );
 
+        let parser = parser_factory(self.codec);
         self.parser = Some(
-            gst::ElementFactory::make("h264parse")
-                .name("h264parser")
+            gst::ElementFactory::make(parser)
+                .name("parser")
                 .build()
-                .context("Failed to create h264parse")?,
+                .with_context(|| format!("Failed to create {}", parser))?,
         );
 
         self.tee = Some(
@@ -119,13 +154,6 @@ impl PipelineSource for LibcameraPipelineSource {
                 .context("Failed to create tee")?,
         );
 
-        // Configure encoder
-        let encoder = self.encoder.as_ref().unwrap();
-        encoder.set_property_from_str("tune", "zerolatency"); // Use string instead of int
-        encoder.set_property_from_str("speed-preset", "ultrafast"); // Use string instead of int
-        encoder.set_property("bitrate", 2000u32);
-        encoder.set_property("key-int-max", self.config.frame_rate as u32);
-
         // Configure videoflip
         // let videoflip = self.videoflip.as_ref().unwrap();
         // videoflip.set_property("method", 2u32); // rotate-180
@@ -141,31 +169,26 @@ impl PipelineSource for LibcameraPipelineSource {
 
         capsfilter.set_property("caps", &caps);
 
-        // Add all elements to pipeline
-        pipeline
-            .add_many(&[
-                self.source.as_ref().unwrap(),
-                self.queue.as_ref().unwrap(),
-                self.capsfilter.as_ref().unwrap(),
-                self.videoflip.as_ref().unwrap(),
-                self.videoconvert.as_ref().unwrap(),
-                self.encoder.as_ref().unwrap(),
-                self.parser.as_ref().unwrap(),
-                self.tee.as_ref().unwrap(),
-            ])
-            .context("Failed to add elements to pipeline")?;
-
-        // Link all elements
-        gst::Element::link_many(&[
+        let mut elements = vec![
             self.source.as_ref().unwrap(),
             self.queue.as_ref().unwrap(),
             self.capsfilter.as_ref().unwrap(),
             self.videoflip.as_ref().unwrap(),
-            self.videoconvert.as_ref().unwrap(),
-            self.encoder.as_ref().unwrap(),
+        ];
+        elements.extend(self.processing.around(self.videoconvert.as_ref().unwrap()));
+        elements.extend([
+            self.encoder.as_ref().unwrap().element(),
             self.parser.as_ref().unwrap(),
             self.tee.as_ref().unwrap(),
-        ])
+        ]);
+
+        // Add all elements to pipeline
+        pipeline
+            .add_many(&elements)
+            .context("Failed to add elements to pipeline")?;
+
+        // Link all elements
+        gst::Element::link_many(&elements)
         .map_err(|_| anyhow::anyhow!("Failed to link gstreamer elements"))?;
 
         info!("Finished setup of gstreamer libcamera src");
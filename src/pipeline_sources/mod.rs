@@ -1,3 +1,9 @@
 pub mod pipeline_source;
+pub mod encoder;
+pub mod encode_branches;
 pub mod v4l2_pipeline_source;
 pub mod libcamera_pipeline_source;
+pub mod rtsp_pipeline_source;
+pub mod test_pipeline_source;
+pub mod processing;
+pub mod audio_source;
@@ -1,7 +1,51 @@
 use anyhow::{ Result};
 use gstreamer as gst;
+
+use crate::config::VideoCodec;
+use crate::recording_pipeline::EncodingSettings;
+
+/// What a source's tee hands to the sinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// Decoded video frames
+    Raw,
+    /// Parsed elementary stream (h264parse/h265parse output)
+    Encoded(VideoCodec),
+}
+
+impl std::fmt::Display for StreamFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamFormat::Raw => write!(f, "raw video"),
+            StreamFormat::Encoded(VideoCodec::H264) => write!(f, "H.264"),
+            StreamFormat::Encoded(VideoCodec::H265) => write!(f, "H.265"),
+            StreamFormat::Encoded(VideoCodec::Av1) => write!(f, "AV1"),
+        }
+    }
+}
+
 pub trait PipelineSource: Send {
     fn setup_source(&mut self, pipeline: &gst::Pipeline) -> Result<()>;
     fn get_tee(&self) -> Result<gst::Element>;
     fn get_source_pad(&self) -> Result<gst::Pad>;
+    /// Format on the tee. Sources that encode themselves produce H.264;
+    /// passthrough sources produce whatever the camera delivers.
+    fn output_format(&self) -> StreamFormat {
+        StreamFormat::Encoded(VideoCodec::H264)
+    }
+    /// Cheap check that the device is there to be opened; the error says what's
+    /// missing. Polled before `setup_source` and by background start retries.
+    fn check_ready(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Flip a built source to `encoding` if it has a spare encoder prepared for
+    /// it (`warm_spare` modes). False if not, leaving a rebuild to the caller.
+    fn switch_encoding(&mut self, _encoding: EncodingSettings) -> bool {
+        false
+    }
+    /// Size, frame rate and bitrate for the next `setup_source`. Returns false
+    /// for sources that don't encode themselves, which ignore them.
+    fn set_encoding(&mut self, _encoding: EncodingSettings) -> bool {
+        false
+    }
 }
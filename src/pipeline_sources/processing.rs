@@ -0,0 +1,122 @@
+//! Optional raw-frame processing in a source chain (`processing` per camera):
+//! `deinterlace` and `videocrop` go in front of the source's videoconvert, a
+//! colorimetry capsfilter after it so videoconvert does the conversion. With
+//! `overlay_gps`, a textoverlay after the crop takes the text `gps_overlay`
+//! keeps setting.
+
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+
+use crate::config::ProcessingConfig;
+
+/// Name of the textoverlay `overlay_gps` adds
+pub const GPS_OVERLAY: &str = "gps_overlay";
+
+/// GStreamer's named colorimetries, or its `range:matrix:transfer:primaries`
+/// numeric form. Checked here so we don't need libgstvideo for it.
+pub fn is_known_colorimetry(colorimetry: &str) -> bool {
+    const NAMED: &[&str] = &["bt601", "bt709", "bt2020", "bt2020-10", "bt2100-pq", "bt2100-hlg", "smpte240m", "sRGB"];
+    let parts: Vec<_> = colorimetry.split(':').collect();
+    NAMED.contains(&colorimetry)
+        || (parts.len() == 4 && parts.iter().all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit())))
+}
+
+/// Factories in front of and behind videoconvert, in link order.
+pub fn chain_factories(cfg: &ProcessingConfig, overlay_gps: bool) -> (Vec<&'static str>, Vec<&'static str>) {
+    let mut before = Vec::new();
+    if cfg.deinterlace {
+        before.push("deinterlace");
+    }
+    if cfg.crop.is_some() {
+        before.push("videocrop");
+    }
+    if overlay_gps {
+        before.push("textoverlay");
+    }
+    let after = if cfg.colorimetry.is_some() { vec!["capsfilter"] } else { Vec::new() };
+    (before, after)
+}
+
+#[derive(Default)]
+pub struct ProcessingChain {
+    before_convert: Vec<gst::Element>,
+    after_convert: Vec<gst::Element>,
+}
+
+impl ProcessingChain {
+    pub fn build(cfg: &ProcessingConfig, overlay_gps: bool) -> Result<Self> {
+        let (before, after) = chain_factories(cfg, overlay_gps);
+        let make = |factory: &str, name: &str| {
+            gst::ElementFactory::make(factory)
+                .name(name)
+                .build()
+                .with_context(|| format!("Failed to create {}", factory))
+        };
+
+        let mut before_convert = Vec::new();
+        for factory in before {
+            let element = make(factory, if factory == "textoverlay" { GPS_OVERLAY } else { factory })?;
+            if factory == "videocrop"
+                && let Some(crop) = cfg.crop
+            {
+                element.set_property("top", crop.top as i32);
+                element.set_property("bottom", crop.bottom as i32);
+                element.set_property("left", crop.left as i32);
+                element.set_property("right", crop.right as i32);
+            }
+            if factory == "textoverlay" {
+                element.set_property("text", "");
+                element.set_property_from_str("valignment", "top");
+                element.set_property_from_str("halignment", "left");
+                element.set_property("shaded-background", true);
+                element.set_property("font-desc", "Sans 14");
+            }
+            before_convert.push(element);
+        }
+
+        let mut after_convert = Vec::new();
+        if let (Some(colorimetry), Some(_)) = (&cfg.colorimetry, after.first()) {
+            let capsfilter = make("capsfilter", "colorimetry")?;
+            let caps = gst::Caps::builder("video/x-raw").field("colorimetry", colorimetry).build();
+            capsfilter.set_property("caps", &caps);
+            after_convert.push(capsfilter);
+        }
+        Ok(Self { before_convert, after_convert })
+    }
+
+    /// `videoconvert` with the processing elements around it, in link order.
+    pub fn around<'a>(&'a self, videoconvert: &'a gst::Element) -> Vec<&'a gst::Element> {
+        self.before_convert
+            .iter()
+            .chain(std::iter::once(videoconvert))
+            .chain(self.after_convert.iter())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CropConfig;
+
+    #[test]
+    fn deinterlace_and_crop_go_before_videoconvert() {
+        assert_eq!(chain_factories(&ProcessingConfig::default(), false), (vec![], vec![]));
+
+        let cfg = ProcessingConfig {
+            deinterlace: true,
+            colorimetry: Some("bt709".to_string()),
+            crop: Some(CropConfig { bottom: 8, ..Default::default() }),
+        };
+        assert_eq!(chain_factories(&cfg, false), (vec!["deinterlace", "videocrop"], vec!["capsfilter"]));
+        assert_eq!(
+            chain_factories(&cfg, true),
+            (vec!["deinterlace", "videocrop", "textoverlay"], vec!["capsfilter"]),
+            "the overlay is drawn on the cropped frame"
+        );
+
+        assert!(is_known_colorimetry("bt601") && is_known_colorimetry("1:4:0:0"));
+        assert!(!is_known_colorimetry("bt-709") && !is_known_colorimetry("1:4:0"));
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result, bail};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use tracing::{info, warn};
+
+use crate::config::VideoCodec;
+use super::encoder::parser_factory;
+use super::pipeline_source::{PipelineSource, StreamFormat};
+
+/// Jitter buffer of rtspsrc; enough for a camera on the same LAN
+const LATENCY_MS: u32 = 200;
+
+/// RTP depayloader for an encoded `codec` stream.
+pub fn depayloader_factory(codec: VideoCodec) -> &'static str {
+    match codec {
+        VideoCodec::H264 => "rtph264depay",
+        VideoCodec::H265 => "rtph265depay",
+        VideoCodec::Av1 => "rtpav1depay",
+    }
+}
+
+/// Network camera: rtspsrc -> depay -> parser -> tee. The camera encodes, so
+/// the stream is teed as delivered (`codec` has to match what it sends).
+pub struct RtspPipelineSource {
+    url: String,
+    codec: VideoCodec,
+    source: Option<gst::Element>,
+    depay: Option<gst::Element>,
+    parser: Option<gst::Element>,
+    tee: Option<gst::Element>,
+}
+
+impl RtspPipelineSource {
+    pub fn new(url: String, codec: VideoCodec) -> Self {
+        RtspPipelineSource {
+            url,
+            codec,
+            source: None,
+            depay: None,
+            parser: None,
+            tee: None,
+        }
+    }
+}
+
+impl PipelineSource for RtspPipelineSource {
+    fn get_source_pad(&self) -> Result<gst::Pad> {
+        let tee = self.tee.as_ref().context("Tee element not initialized")?;
+
+        tee.static_pad("src")
+            .context("Failed to get static pad 'src' from tee")
+    }
+
+    fn get_tee(&self) -> Result<gst::Element> {
+        self.tee.clone().context("Tee element not initialized")
+    }
+
+    fn output_format(&self) -> StreamFormat {
+        StreamFormat::Encoded(self.codec)
+    }
+
+    fn check_ready(&self) -> Result<()> {
+        if !self.url.starts_with("rtsp://") && !self.url.starts_with("rtsps://") {
+            bail!("'{}' isn't an rtsp:// URL", self.url);
+        }
+        Ok(())
+    }
+
+    fn setup_source(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
+        info!("Creating gstreamer rtsp source for {}", self.url);
+
+        let source = gst::ElementFactory::make("rtspsrc")
+            .name("source")
+            .build()
+            .context("Failed to create rtspsrc")?;
+        source.set_property("location", &self.url);
+        source.set_property("latency", LATENCY_MS);
+        // TCP gets through NAT and doesn't drop packets on a busy Wi-Fi
+        source.set_property_from_str("protocols", "tcp");
+
+        let depayloader = depayloader_factory(self.codec);
+        let depay = gst::ElementFactory::make(depayloader)
+            .name("depay")
+            .build()
+            .with_context(|| format!("Failed to create {}", depayloader))?;
+
+        let parser = parser_factory(self.codec);
+        let parser_element = gst::ElementFactory::make(parser)
+            .name("parser")
+            .build()
+            .with_context(|| format!("Failed to create {}", parser))?;
+        // SPS/PPS with every keyframe, so each segment decodes on its own
+        if self.codec != VideoCodec::Av1 {
+            parser_element.set_property("config-interval", -1i32);
+        }
+
+        let tee = gst::ElementFactory::make("tee")
+            .name("tee")
+            .build()
+            .context("Failed to create tee")?;
+
+        pipeline
+            .add_many([&source, &depay, &parser_element, &tee])
+            .context("Failed to add elements to rtsp pipeline source")?;
+        gst::Element::link_many([&depay, &parser_element, &tee])
+            .map_err(|_| anyhow::anyhow!("Failed to link gstreamer elements"))?;
+
+        // rtspsrc creates its pads once the stream is described; link the video one
+        let depay_weak = depay.downgrade();
+        source.connect_pad_added(move |_, pad| {
+            let Some(depay) = depay_weak.upgrade() else {
+                return;
+            };
+            let is_video = pad
+                .current_caps()
+                .and_then(|caps| caps.structure(0).and_then(|s| s.get::<String>("media").ok()))
+                .is_some_and(|media| media == "video");
+            let Some(sink) = depay.static_pad("sink") else {
+                return;
+            };
+            if !is_video || sink.is_linked() {
+                return;
+            }
+            if let Err(e) = pad.link(&sink) {
+                warn!("Failed to link rtspsrc pad {} to the depayloader: {:?}", pad.name(), e);
+            }
+        });
+
+        self.source = Some(source);
+        self.depay = Some(depay);
+        self.parser = Some(parser_element);
+        self.tee = Some(tee);
+
+        info!("Finished setup of gstreamer rtsp src ({})", self.output_format());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_rtsp_urls_are_ready() {
+        assert!(RtspPipelineSource::new("rtsp://10.0.0.5:554/stream1".into(), VideoCodec::H264).check_ready().is_ok());
+        assert!(RtspPipelineSource::new("http://10.0.0.5/stream1".into(), VideoCodec::H264).check_ready().is_err());
+        assert_eq!(depayloader_factory(VideoCodec::H265), "rtph265depay");
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use tracing::info;
+
+use crate::config::VideoCodec;
+use crate::recording_pipeline::{EncodingSettings, RecordingConfig};
+use super::encode_branches::EncodeBranches;
+use super::encoder::parser_factory;
+use super::pipeline_source::{PipelineSource, StreamFormat};
+
+/// `videotestsrc` at the camera's size and frame rate, encoded like a raw
+/// camera would be. Stands in for every camera under `--simulate`.
+pub struct TestPipelineSource {
+    config: RecordingConfig,
+    codec: VideoCodec,
+    encoder: Option<EncodeBranches>,
+    tee: Option<gst::Element>,
+}
+
+impl TestPipelineSource {
+    pub fn new(config: RecordingConfig, codec: VideoCodec) -> Self {
+        TestPipelineSource { config, codec, encoder: None, tee: None }
+    }
+}
+
+impl PipelineSource for TestPipelineSource {
+    fn get_source_pad(&self) -> Result<gst::Pad> {
+        let tee = self.tee.as_ref().context("Tee element not initialized")?;
+
+        tee.static_pad("src")
+            .context("Failed to get static pad 'src' from tee")
+    }
+
+    fn get_tee(&self) -> Result<gst::Element> {
+        self.tee.clone().context("Tee element not initialized")
+    }
+
+    fn output_format(&self) -> StreamFormat {
+        StreamFormat::Encoded(self.codec)
+    }
+
+    fn switch_encoding(&mut self, encoding: EncodingSettings) -> bool {
+        self.encoder.as_mut().is_some_and(|encoder| encoder.switch_to(encoding))
+    }
+
+    fn set_encoding(&mut self, encoding: EncodingSettings) -> bool {
+        self.config.set_encoding(encoding);
+        true
+    }
+
+    fn setup_source(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
+        info!("Creating gstreamer test source for camera '{}'", self.config.camera_key);
+
+        let source = gst::ElementFactory::make("videotestsrc")
+            .name("source")
+            .property("is-live", true)
+            .property_from_str("pattern", "ball")
+            .build()
+            .context("Failed to create videotestsrc")?;
+        let caps = gst::Caps::builder("video/x-raw")
+            .field("width", self.config.video_width)
+            .field("height", self.config.video_height)
+            .field("framerate", gst::Fraction::new(self.config.frame_rate, 1))
+            .build();
+        let capsfilter = gst::ElementFactory::make("capsfilter")
+            .name("capsfilter")
+            .property("caps", &caps)
+            .build()
+            .context("Failed to create capsfilter")?;
+        let videoconvert = gst::ElementFactory::make("videoconvert")
+            .name("videoconvert")
+            .build()
+            .context("Failed to create videoconvert")?;
+        let encoder = EncodeBranches::build(self.codec, &self.config)?;
+        let parser = parser_factory(self.codec);
+        let parser = gst::ElementFactory::make(parser)
+            .name("parser")
+            .build()
+            .with_context(|| format!("Failed to create {}", parser))?;
+        let tee = gst::ElementFactory::make("tee")
+            .name("tee")
+            .build()
+            .context("Failed to create tee")?;
+
+        let elements = [&source, &capsfilter, &videoconvert, encoder.element(), &parser, &tee];
+        pipeline
+            .add_many(elements)
+            .context("Failed to add elements to pipeline")?;
+        gst::Element::link_many(elements)
+            .map_err(|_| anyhow::anyhow!("Failed to link gstreamer elements"))?;
+
+        self.encoder = Some(encoder);
+        self.tee = Some(tee);
+        Ok(())
+    }
+}
@@ -1,22 +1,31 @@
-use std::{fs, thread, time::Duration};
+use std::fs;
+use std::path::Path;
 
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, bail};
 #[allow(dead_code)]
 use gstreamer as gst;
 use gstreamer::prelude::*;
 use tracing::info;
 
-use crate::{recording_pipeline::RecordingConfig};
-use super::pipeline_source::PipelineSource;
+use crate::{config::VideoCodec, recording_pipeline::{EncodingSettings, RecordingConfig}};
+use super::encode_branches::EncodeBranches;
+use super::encoder::{media_type, parser_factory};
+use super::pipeline_source::{PipelineSource, StreamFormat};
+use super::processing::ProcessingChain;
 
 pub struct V4l2PipelineSource {
     device: String,
     config: RecordingConfig,
+    // codec on the tee; encoded here unless the device already does it
+    codec: VideoCodec,
+    // the device encodes itself (e.g. UVC H.264 webcams), skip the encoder
+    passthrough: bool,
     source: Option<gst::Element>,
     queue: Option<gst::Element>,
     capsfilter: Option<gst::Element>,
     videoconvert: Option<gst::Element>,
-    encoder: Option<gst::Element>,
+    processing: ProcessingChain,
+    encoder: Option<EncodeBranches>,
     parser: Option<gst::Element>,
     tee: Option<gst::Element>,
 }
@@ -24,37 +33,71 @@ pub struct V4l2PipelineSource {
 impl V4l2PipelineSource {
     pub fn new(config: RecordingConfig, device: Option<String>) -> Self {
         let device = match device {
-            None => "dev/video0".to_string(),
+            None => "/dev/video0".to_string(),
             Some(dev) => dev.clone()
         };
 
         V4l2PipelineSource {
             device,
-            config: config,
+            config,
+            codec: VideoCodec::H264,
+            passthrough: false,
             source: None,
             queue: None,
             capsfilter: None,
             videoconvert: None,
+            processing: ProcessingChain::default(),
             encoder: None,
             parser: None,
             tee: None,
         }
     }
 
-    fn wait_for_video_device(device_path: &str) -> Result<()> {
-        let mut i = 0;
-        while !fs::exists(device_path)? {
-            if i >= 10 {
-                return Err(anyhow!("Device not found after 10 seconds"));
-            }
+    /// Encode to `codec` instead of H.264.
+    pub fn with_codec(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self
+    }
 
-            info!("Waiting for {}...", device_path);
-            thread::sleep(Duration::from_secs(1));
-            i = i + 1;
-        }
+    /// Take the device's own `codec` stream instead of raw frames.
+    pub fn with_passthrough(mut self, codec: VideoCodec) -> Self {
+        self.codec = codec;
+        self.passthrough = true;
+        self
+    }
+}
 
-        Ok(())
+/// `(/dev/videoN, card name)` for every V4L2 node the kernel lists under `sysfs`
+/// (normally `/sys/class/video4linux`), sorted by device.
+pub fn video_devices_in(sysfs: &Path) -> Vec<(String, String)> {
+    let Ok(entries) = fs::read_dir(sysfs) else {
+        return Vec::new();
+    };
+    let mut devices: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let node = entry.file_name().to_str()?.to_string();
+            node.starts_with("video").then_some(())?;
+            let card = fs::read_to_string(entry.path().join("name")).unwrap_or_default();
+            Some((format!("/dev/{}", node), card.trim().to_string()))
+        })
+        .collect();
+    // video10 after video9
+    devices.sort_by_key(|(dev, _)| (dev.len(), dev.clone()));
+    devices
+}
+
+/// One line listing the V4L2 devices present, to point a wrong `device =` at the right one.
+pub fn describe_video_devices() -> String {
+    let devices = video_devices_in(Path::new("/sys/class/video4linux"));
+    if devices.is_empty() {
+        return "no /dev/video* devices present".to_string();
     }
+    let listed: Vec<_> = devices
+        .iter()
+        .map(|(dev, card)| if card.is_empty() { dev.clone() } else { format!("{} ({})", dev, card) })
+        .collect();
+    format!("available: {}", listed.join(", "))
 }
 
 impl Default for V4l2PipelineSource {
@@ -75,9 +118,32 @@ impl PipelineSource for V4l2PipelineSource {
         self.tee.clone().context("Tee element not initialized")
     }
 
+    fn output_format(&self) -> StreamFormat {
+        StreamFormat::Encoded(self.codec)
+    }
+
+    fn check_ready(&self) -> Result<()> {
+        if !Path::new(&self.device).exists() {
+            bail!("{} doesn't exist; {}", self.device, describe_video_devices());
+        }
+        Ok(())
+    }
+
+    fn switch_encoding(&mut self, encoding: EncodingSettings) -> bool {
+        self.encoder.as_mut().is_some_and(|encoder| encoder.switch_to(encoding))
+    }
+
+    fn set_encoding(&mut self, encoding: EncodingSettings) -> bool {
+        // a passthrough device records in whatever it was set up for
+        if self.passthrough {
+            return false;
+        }
+        self.config.set_encoding(encoding);
+        true
+    }
+
     fn setup_source(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
         info!("Creating gstreamer v4l2 source");
-        Self::wait_for_video_device(&self.device)?;
 
         self.source = Some(
             gst::ElementFactory::make("v4l2src")
@@ -100,6 +166,54 @@ impl PipelineSource for V4l2PipelineSource {
                 .context("Failed to create capsfilter")?,
         );
 
+        let source = self.source.as_ref().unwrap();
+        source.set_property_from_str("device", &self.device);
+
+        let capsfilter = self.capsfilter.as_ref().unwrap();
+
+        let parser = parser_factory(self.codec);
+        self.parser = Some(
+            gst::ElementFactory::make(parser)
+                .name("parser")
+                .build()
+                .with_context(|| format!("Failed to create {}", parser))?,
+        );
+
+        if self.passthrough {
+            // device already encodes: v4l2src -> queue -> capsfilter -> parser -> tee
+            self.tee = Some(
+                gst::ElementFactory::make("tee")
+                    .name("tee")
+                    .build()
+                    .context("Failed to create tee")?,
+            );
+
+            let caps = gst::Caps::builder(media_type(self.codec))
+                .field("width", self.config.video_width)
+                .field("height", self.config.video_height)
+                .field("framerate", gst::Fraction::new(self.config.frame_rate, 1))
+                .build();
+            capsfilter.set_property("caps", &caps);
+
+            let elements = [
+                self.source.as_ref().unwrap(),
+                self.queue.as_ref().unwrap(),
+                self.capsfilter.as_ref().unwrap(),
+                self.parser.as_ref().unwrap(),
+                self.tee.as_ref().unwrap(),
+            ];
+
+            pipeline
+                .add_many(elements)
+                .context("Failed to add elements to v4l2 pipeline source")?;
+
+            gst::Element::link_many(elements)
+                .map_err(|_| anyhow::anyhow!("Failed to link gstreamer elements"))?;
+
+            info!("Finished setup of gstreamer v4l2 src ({} passthrough)", self.output_format());
+            return Ok(());
+        }
+
         self.videoconvert = Some(
             gst::ElementFactory::make("videoconvert")
                 .name("videoconvert")
@@ -107,19 +221,8 @@ impl PipelineSource for V4l2PipelineSource {
                 .context("Failed to create videoconvert")?,
         );
 
-        self.encoder = Some(
-            gst::ElementFactory::make("x264enc")
-                .name("encoder")
-                .build()
-                .context("Failed to create x264enc")?,
-        );
-
-        self.parser = Some(
-            gst::ElementFactory::make("h264parse")
-                .name("h264parser")
-                .build()
-                .context("Failed to create h264parse")?,
-        );
+        self.processing = ProcessingChain::build(&self.config.processing, self.config.overlay_gps)?;
+        self.encoder = Some(EncodeBranches::build(self.codec, &self.config)?);
 
         self.tee = Some(
             gst::ElementFactory::make("tee")
@@ -128,10 +231,6 @@ impl PipelineSource for V4l2PipelineSource {
                 .context("Failed to create tee")?,
         );
 
-        let source = self.source.as_ref().unwrap();
-        source.set_property_from_str("device", &self.device);
-
-        let capsfilter = self.capsfilter.as_ref().unwrap();
         let caps = gst::Caps::builder("video/x-raw")
             .field("format", "YUY2")
             .field("width", self.config.video_width)
@@ -141,27 +240,23 @@ impl PipelineSource for V4l2PipelineSource {
 
         capsfilter.set_property("caps", &caps);
 
-        pipeline
-            .add_many(&[
-                self.source.as_ref().unwrap(),
-                self.queue.as_ref().unwrap(),
-                self.capsfilter.as_ref().unwrap(),
-                self.videoconvert.as_ref().unwrap(),
-                self.encoder.as_ref().unwrap(),
-                self.parser.as_ref().unwrap(),
-                self.tee.as_ref().unwrap(),
-            ])
-            .context("Failed to add elements to v4l2 pipeline source")?;
-
-        gst::Element::link_many(&[
+        let mut elements = vec![
             self.source.as_ref().unwrap(),
             self.queue.as_ref().unwrap(),
             self.capsfilter.as_ref().unwrap(),
-            self.videoconvert.as_ref().unwrap(),
-            self.encoder.as_ref().unwrap(),
+        ];
+        elements.extend(self.processing.around(self.videoconvert.as_ref().unwrap()));
+        elements.extend([
+            self.encoder.as_ref().unwrap().element(),
             self.parser.as_ref().unwrap(),
             self.tee.as_ref().unwrap(),
-        ])
+        ]);
+
+        pipeline
+            .add_many(&elements)
+            .context("Failed to add elements to v4l2 pipeline source")?;
+
+        gst::Element::link_many(&elements)
         .map_err(|_| anyhow::anyhow!("Failed to link gstreamer elements"))?;
 
         info!("Finished setup of gstreamer v4l2 src");
@@ -169,3 +264,30 @@ impl PipelineSource for V4l2PipelineSource {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_video_nodes_with_card_names() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        for (node, card) in [("video10", "bcm2835-isp\n"), ("video2", "HD Pro Webcam C920\n"), ("video0", "")] {
+            fs::create_dir_all(tmp.path().join(node)).unwrap();
+            if !card.is_empty() {
+                fs::write(tmp.path().join(node).join("name"), card).unwrap();
+            }
+        }
+        fs::create_dir_all(tmp.path().join("v4l-subdev0")).unwrap();
+
+        assert_eq!(
+            video_devices_in(tmp.path()),
+            vec![
+                ("/dev/video0".to_string(), String::new()),
+                ("/dev/video2".to_string(), "HD Pro Webcam C920".to_string()),
+                ("/dev/video10".to_string(), "bcm2835-isp".to_string()),
+            ]
+        );
+        assert!(video_devices_in(&tmp.path().join("missing")).is_empty());
+    }
+}
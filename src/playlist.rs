@@ -0,0 +1,210 @@
+//! HLS playlists over archived segments, from the `segments` catalog, so a
+//! stretch of recordings plays in any HLS player straight from `/files/`.
+//!
+//! Players assume timestamps, codec and picture size carry on from one segment
+//! to the next and lose audio/video sync, or stall, where they don't. Before
+//! such a segment the playlist has an `EXT-X-DISCONTINUITY` tag:
+//! - the pipeline (re)started, so timestamps start over (`segments.restarted`)
+//! - codec, size or frame rate changed (a `[[modes]]` encoding switch)
+//! - there is a gap in the recordings (privacy, a stopped camera, a crash)
+//! - the wall clock was stepped in between (`clock_corrections`), which
+//!   `EXT-X-PROGRAM-DATE-TIME` would otherwise show as a jump
+//!
+//! The live HLS sink (`hlssink`) writes its own playlist and can't take the
+//! tags; it starts a new playlist whenever the pipeline starts.
+//!
+//! `rewind.m3u8` is live view started some minutes back: a master playlist
+//! pins the start to a wall time, and the media playlist it points to keeps
+//! growing from there as segments are catalogued, without an end, so a player
+//! plays on towards live (a segment behind) and can scrub back to the start.
+
+use chrono::{DateTime, Utc};
+use std::fmt::Write;
+
+use crate::db::db::{SegmentRow, busiest_sink};
+
+/// Seconds between one segment's end and the next one's start still taken as back to back
+pub const MAX_GAP_SEC: i64 = 2;
+/// `BANDWIDTH` of a rewind when no segment has a size yet
+const DEFAULT_BANDWIDTH: i64 = 2_000_000;
+
+/// Where a rewind playlist starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RewindStart {
+    /// Now; answered with a master playlist pointing to `At` that time
+    MinutesAgo(u64),
+    At(i64),
+}
+
+/// Whether `next` can't be played on from `prev` without a discontinuity.
+pub fn discontinuity_between(prev: &SegmentRow, next: &SegmentRow) -> bool {
+    next.restarted
+        || !prev.stream.continues_into(&next.stream)
+        || next.start_utc - prev.end_utc > MAX_GAP_SEC
+        || prev.clock_offset_ms != next.clock_offset_ms
+}
+
+fn program_date_time(at_utc: i64) -> String {
+    DateTime::<Utc>::from_timestamp(at_utc, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string()
+}
+
+/// VOD playlist of `rows` (from `segments_between`) of one sink (see
+/// `busiest_sink`), each under `/files/<rel_path>`. Files outside the
+/// recording root can't be served and are left out.
+pub fn archive_playlist(rows: &[SegmentRow]) -> String {
+    let rows = servable(rows);
+    let mut m3u8 = String::from("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-PLAYLIST-TYPE:VOD\n");
+    let _ = writeln!(m3u8, "#EXT-X-TARGETDURATION:{}", target_duration(&rows));
+    m3u8.push_str("#EXT-X-MEDIA-SEQUENCE:0\n");
+    push_segments(&mut m3u8, &rows);
+    m3u8.push_str("#EXT-X-ENDLIST\n");
+    m3u8
+}
+
+/// Master playlist of a rewind from `from_utc`: one variant, the media
+/// playlist `rewind_playlist` serves, so a player reloading it keeps the start.
+pub fn rewind_master(camera_key: &str, from_utc: i64, rows: &[SegmentRow]) -> String {
+    // peak bitrate of the segments, as HLS wants it
+    let bandwidth = servable(rows)
+        .iter()
+        .filter_map(|r| Some(r.bytes? * 8 / duration(r)))
+        .max()
+        .unwrap_or(DEFAULT_BANDWIDTH);
+    format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-STREAM-INF:BANDWIDTH={}\n/api/cameras/{}/rewind.m3u8?from={}\n",
+        bandwidth, camera_key, from_utc
+    )
+}
+
+/// Live playlist of `rows` (from `segments_between`, from the rewind's start
+/// up to now): no end, so players reload it for the segments still to come,
+/// and `EXT-X-START` so they begin at the start rather than at live.
+/// `segment_sec` is the camera's configured segment length.
+pub fn rewind_playlist(rows: &[SegmentRow], segment_sec: u64) -> String {
+    let rows = servable(rows);
+    // The configured length rather than the rows at hand, so reloads agree; only
+    // a segment that ran long (HLS wants none above the target) raises it
+    let target = target_duration(&rows).max(segment_sec.max(1) as i64);
+    // Segments overwritten by the ring move the first one; the catalogue's count
+    // of the camera's segments keeps each one's sequence number
+    let sequence = rows.first().map_or(0, |first| first.absolute_index);
+
+    let mut m3u8 = String::from("#EXTM3U\n#EXT-X-VERSION:3\n");
+    let _ = writeln!(m3u8, "#EXT-X-TARGETDURATION:{}", target);
+    let _ = writeln!(m3u8, "#EXT-X-MEDIA-SEQUENCE:{}", sequence);
+    m3u8.push_str("#EXT-X-START:TIME-OFFSET=0\n");
+    push_segments(&mut m3u8, &rows);
+    m3u8
+}
+
+/// The rows of the busiest sink that `/files/` can serve.
+fn servable(rows: &[SegmentRow]) -> Vec<&SegmentRow> {
+    busiest_sink(rows).into_iter().filter(|r| !r.rel_path.starts_with('/')).collect()
+}
+
+fn duration(row: &SegmentRow) -> i64 {
+    (row.end_utc - row.start_utc).max(1)
+}
+
+fn target_duration(rows: &[&SegmentRow]) -> i64 {
+    rows.iter().map(|r| duration(r)).max().unwrap_or(1)
+}
+
+/// `EXTINF` lines of `rows`, with discontinuities and program date-times.
+fn push_segments(m3u8: &mut String, rows: &[&SegmentRow]) {
+    for (i, row) in rows.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|p| rows[p]);
+        if let Some(prev) = prev
+            && discontinuity_between(prev, row)
+        {
+            m3u8.push_str("#EXT-X-DISCONTINUITY\n");
+        }
+        if prev.is_none_or(|prev| discontinuity_between(prev, row)) {
+            let _ = writeln!(m3u8, "#EXT-X-PROGRAM-DATE-TIME:{}", program_date_time(row.start_utc));
+        }
+        let _ = writeln!(m3u8, "#EXTINF:{}.000,\n/files/{}", duration(row), row.rel_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db::SegmentStream;
+
+    fn row(start_utc: i64, rel_path: &str) -> SegmentRow {
+        SegmentRow {
+            id: 0,
+            sink_id: 0,
+            start_utc,
+            end_utc: start_utc + 60,
+            absolute_index: 0,
+            rel_path: rel_path.to_string(),
+            stream: SegmentStream { codec: Some("H264".to_string()), width: Some(1920), height: Some(1080), fps: Some(30.0) },
+            bytes: None,
+            restarted: false,
+            clock_offset_ms: None,
+        }
+    }
+
+    #[test]
+    fn discontinuities_go_before_restarts_changes_gaps_and_clock_steps() {
+        let mut rows = vec![
+            row(1_772_373_600, "front/0/output_0.ts"),
+            row(1_772_373_660, "front/0/output_1.ts"),
+            row(1_772_373_720, "front/0/output_2.ts"),
+            row(1_772_373_780, "front/0/output_3.ts"),
+            row(1_772_373_900, "front/0/output_4.ts"),
+            row(1_772_373_960, "front/0/output_5.ts"),
+            row(1_772_374_020, "front/0/output_6.ts"),
+        ];
+        rows[2].restarted = true;
+        rows[3].stream.width = Some(1280);
+        // output_4 follows a two-minute gap
+        rows[6].clock_offset_ms = Some(-1_500);
+
+        let m3u8 = archive_playlist(&rows);
+        assert!(m3u8.starts_with("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-TARGETDURATION:60\n"));
+        assert!(m3u8.ends_with("/files/front/0/output_6.ts\n#EXT-X-ENDLIST\n"));
+        assert_eq!(m3u8.matches("#EXT-X-DISCONTINUITY\n").count(), 4, "{}", m3u8);
+        assert!(m3u8.contains(
+            "#EXT-X-PROGRAM-DATE-TIME:2026-03-01T14:00:00.000Z\n#EXTINF:60.000,\n/files/front/0/output_0.ts\n\
+             #EXTINF:60.000,\n/files/front/0/output_1.ts\n#EXT-X-DISCONTINUITY\n"
+        ));
+        assert!(m3u8.contains("/files/front/0/output_4.ts\n#EXTINF:60.000,\n/files/front/0/output_5.ts\n#EXT-X-DISCONTINUITY\n"));
+
+        assert_eq!(archive_playlist(&[]).matches("#EXTINF").count(), 0);
+    }
+
+    #[test]
+    fn a_rewind_is_pinned_to_its_start_and_left_open() {
+        let from_utc = 1_772_373_600;
+        let mut rows: Vec<SegmentRow> = (0..4)
+            .map(|i| SegmentRow { absolute_index: 40 + i, ..row(from_utc + 60 * i, &format!("front/0/output_{}.ts", i)) })
+            .collect();
+        rows[1].bytes = Some(30_000_000);
+
+        let master = rewind_master("front", from_utc, &rows);
+        assert_eq!(
+            master,
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-STREAM-INF:BANDWIDTH=4000000\n\
+             /api/cameras/front/rewind.m3u8?from=1772373600\n"
+        );
+
+        let m3u8 = rewind_playlist(&rows, 60);
+        assert!(m3u8.contains("#EXT-X-TARGETDURATION:60\n#EXT-X-MEDIA-SEQUENCE:40\n#EXT-X-START:TIME-OFFSET=0\n"), "{}", m3u8);
+        assert!(!m3u8.contains("#EXT-X-ENDLIST") && !m3u8.contains("#EXT-X-PLAYLIST-TYPE"));
+        assert!(m3u8.ends_with("/files/front/0/output_3.ts\n"));
+
+        // the ring overwrote the first two: the others keep their numbers
+        let m3u8 = rewind_playlist(&rows[2..], 60);
+        assert!(m3u8.contains("#EXT-X-MEDIA-SEQUENCE:42\n"), "{}", m3u8);
+
+        // a shorter last segment doesn't move the target
+        rows[3].end_utc = rows[3].start_utc + 20;
+        let m3u8 = rewind_playlist(&rows[3..], 60);
+        assert!(m3u8.contains("#EXT-X-TARGETDURATION:60\n"), "{}", m3u8);
+    }
+}
@@ -0,0 +1,186 @@
+//! Two-stage shutdown on power loss (`[power_loss]` in config.toml).
+//!
+//! A UPS HAT or supercapacitor keeps the Pi up for a few seconds after the
+//! supply drops and signals it on a GPIO pin or as a power supply going
+//! offline. Within `budget_ms` of that signal: every segment being written is
+//! closed (splitmuxsink `split-now`, then EOS), the DB WAL is checkpointed,
+//! filesystems are synced and `shutdown_command` runs. Stages that would
+//! overrun the budget are cut short, but the sync and shutdown always happen.
+//! Once triggered there's no going back, even if power returns.
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::gpio::{self, SwitchDebounce};
+use crate::worker::Worker;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PowerLossConfig {
+    /// sysfs GPIO number of the HAT's power-fail output
+    pub gpio: Option<u32>,
+    /// The GPIO reads 0 on power loss
+    pub active_low: bool,
+    /// Name under /sys/class/power_supply; power is lost when its `online` reads 0
+    pub power_supply: Option<String>,
+    /// How long the signal has to hold, so a brownout blip doesn't halt the car's camera
+    pub debounce_ms: u64,
+    /// Everything up to the shutdown command has to fit in here
+    pub budget_ms: u64,
+    pub shutdown_command: Vec<String>,
+}
+
+impl Default for PowerLossConfig {
+    fn default() -> Self {
+        Self {
+            gpio: None,
+            active_low: false,
+            power_supply: None,
+            debounce_ms: 20,
+            budget_ms: 8000,
+            shutdown_command: vec!["systemctl".to_string(), "poweroff".to_string()],
+        }
+    }
+}
+
+impl PowerLossConfig {
+    /// Exactly one signal source, a budget and a command.
+    pub fn is_valid(&self) -> bool {
+        self.gpio.is_some() != self.power_supply.is_some() && self.budget_ms > 0 && !self.shutdown_command.is_empty()
+    }
+
+    fn online_path(&self) -> Option<PathBuf> {
+        self.power_supply.as_ref().map(|name| Path::new("/sys/class/power_supply").join(name).join("online"))
+    }
+
+    /// Whether the supply is gone right now.
+    fn power_lost(&self) -> Result<bool> {
+        match (self.gpio, self.online_path()) {
+            (Some(gpio), _) => gpio::read_input(gpio, self.active_low),
+            (None, Some(online)) => supply_offline(&online),
+            (None, None) => bail!("[power_loss] has neither gpio nor power_supply"),
+        }
+    }
+}
+
+/// A power_supply `online` attribute reading 0.
+pub fn supply_offline(online: &Path) -> Result<bool> {
+    Ok(fs::read_to_string(online)?.trim() == "0")
+}
+
+/// Time left of the power budget.
+#[derive(Debug, Clone, Copy)]
+pub struct PowerBudget {
+    deadline: Instant,
+}
+
+impl PowerBudget {
+    pub fn starting(at: Instant, budget: Duration) -> Self {
+        Self { deadline: at + budget }
+    }
+
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.deadline.saturating_duration_since(now)
+    }
+
+    /// What a stage may take: its `share` of what's left, leaving the rest for later stages.
+    pub fn stage(&self, now: Instant, share: f64) -> Duration {
+        self.remaining(now).mul_f64(share.clamp(0.0, 1.0))
+    }
+}
+
+/// Flush dirty pages of every filesystem; `sync(1)` so no libc binding is needed.
+pub fn sync_filesystems() {
+    match Command::new("sync").status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("sync exited with {}", status),
+        Err(e) => warn!("Couldn't run sync: {}", e),
+    }
+}
+
+/// Start the configured shutdown command without waiting for it.
+pub fn run_shutdown_command(cfg: &PowerLossConfig) {
+    let Some((program, args)) = cfg.shutdown_command.split_first() else {
+        return;
+    };
+    info!("Running shutdown command: {}", cfg.shutdown_command.join(" "));
+    if let Err(e) = Command::new(program).args(args).spawn() {
+        error!("Shutdown command '{}' failed to start: {}", program, e);
+    }
+}
+
+/// Watches the power-fail signal and calls `on_power_loss` once, when it holds.
+pub fn spawn_power_loss<F>(cfg: &PowerLossConfig, on_power_loss: F) -> Worker
+where
+    F: FnOnce(Instant) + Send + 'static,
+{
+    let cfg = cfg.clone();
+    if let Some(gpio) = cfg.gpio
+        && let Err(e) = gpio::export_input(gpio)
+    {
+        warn!("Power-loss input: {:#}", e);
+    }
+
+    let mut on_power_loss = Some(on_power_loss);
+    let mut debounce = SwitchDebounce::new(Duration::from_millis(cfg.debounce_ms));
+    let mut first_seen = None;
+    let mut warned = false;
+    Worker::spawn("power_loss", POLL_INTERVAL, move |stop| match cfg.power_lost() {
+        Ok(lost) => {
+            let now = Instant::now();
+            if lost && first_seen.is_none() {
+                first_seen = Some(now);
+            } else if !lost {
+                first_seen = None;
+            }
+            // the budget runs from the first reading, not the end of the debounce
+            if debounce.update(lost, now) == Some(true)
+                && let Some(on_power_loss) = on_power_loss.take()
+            {
+                warn!("Power lost, shutting down within {}ms", cfg.budget_ms);
+                stop.store(true, Ordering::SeqCst);
+                on_power_loss(first_seen.unwrap_or(now));
+            }
+        }
+        Err(e) if !warned => {
+            warn!("Can't read the power-loss signal: {:#}", e);
+            warned = true;
+        }
+        Err(_) => {}
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_is_shared_between_stages() {
+        let t0 = Instant::now();
+        let budget = PowerBudget::starting(t0, Duration::from_secs(8));
+        assert_eq!(budget.stage(t0, 0.5), Duration::from_secs(4));
+        assert_eq!(budget.remaining(t0 + Duration::from_secs(6)), Duration::from_secs(2));
+        assert_eq!(budget.remaining(t0 + Duration::from_secs(9)), Duration::ZERO);
+
+        let cfg = PowerLossConfig { gpio: Some(6), ..Default::default() };
+        assert!(cfg.is_valid());
+        assert!(!PowerLossConfig::default().is_valid());
+        assert!(!PowerLossConfig { power_supply: Some("ups".to_string()), ..cfg.clone() }.is_valid());
+        assert!(!PowerLossConfig { shutdown_command: vec![], ..cfg }.is_valid());
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let online = tmp.path().join("online");
+        fs::write(&online, "1\n").unwrap();
+        assert!(!supply_offline(&online).unwrap());
+        fs::write(&online, "0\n").unwrap();
+        assert!(supply_offline(&online).unwrap());
+    }
+}
@@ -0,0 +1,82 @@
+//! Privacy switches (`[cameras.privacy_switch]` in config.toml).
+//!
+//! A GPIO input (a toggle on the dash, say) puts one camera, typically the
+//! interior-facing cab camera, into privacy mode: its pipeline is stopped
+//! and stays stopped until the switch goes back. Each transition is stored as a
+//! `privacy` event (label "on"/"off"), shown in `/api/status` and, with
+//! `[status_mqtt]`, published retained on `<topic_prefix>/<camera>/privacy`.
+
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::gpio::{self, SwitchDebounce};
+use crate::mqtt::{StatusMqttConfig, StatusPublisher};
+use crate::worker::Worker;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PrivacySwitchConfig {
+    /// sysfs GPIO number; on recent Pi kernels that's the BCM pin + 512
+    pub gpio: u32,
+    /// Privacy is on while the pin reads 0 (switch to ground, pull-up enabled)
+    #[serde(default)]
+    pub active_low: bool,
+    /// How long a new level has to hold before it counts
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+}
+
+fn default_debounce_ms() -> u64 {
+    100
+}
+
+/// Polls the switches; `on_change(camera_key, private)` runs for every transition.
+/// `switches` is (camera key, switch). The first reading of each is applied
+/// before this returns, so a camera switched private never starts recording.
+pub fn spawn_privacy<F>(
+    switches: Vec<(String, PrivacySwitchConfig)>,
+    status_mqtt: Option<StatusMqttConfig>,
+    on_change: F,
+) -> Worker
+where
+    F: Fn(&str, bool) + Send + 'static,
+{
+    let mut publisher = status_mqtt.map(StatusPublisher::new);
+    let mut watched: Vec<(String, PrivacySwitchConfig, SwitchDebounce)> = Vec::new();
+    for (key, switch) in switches {
+        if let Err(e) = gpio::export_input(switch.gpio) {
+            warn!("Privacy switch for '{}': {:#}", key, e);
+        }
+        let debounce = SwitchDebounce::new(Duration::from_millis(switch.debounce_ms));
+        watched.push((key, switch, debounce));
+    }
+    let mut poll = move |publisher: &mut Option<StatusPublisher>| {
+        for (key, switch, debounce) in watched.iter_mut() {
+            let raw = match gpio::read_input(switch.gpio, switch.active_low) {
+                Ok(raw) => raw,
+                Err(e) => {
+                    warn!("Privacy switch for '{}': {:#}", key, e);
+                    continue;
+                }
+            };
+            if let Some(private) = debounce.update(raw, Instant::now()) {
+                info!("Privacy switch for '{}' is {}", key, if private { "on" } else { "off" });
+                on_change(key, private);
+                if let Some(publisher) = publisher.as_mut() {
+                    publisher.publish(key, "privacy", if private { "on" } else { "off" });
+                }
+            }
+        }
+    };
+    poll(&mut publisher);
+
+    Worker::spawn("privacy", POLL_INTERVAL, move |_| {
+        poll(&mut publisher);
+        if let Some(publisher) = publisher.as_mut() {
+            publisher.tick();
+        }
+    })
+}
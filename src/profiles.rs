@@ -0,0 +1,150 @@
+use serde::Deserialize;
+
+use crate::constants::DEFAULT_PROFILE;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileName {
+    Dev,
+    Production,
+}
+
+impl Default for ProfileName {
+    fn default() -> Self {
+        DEFAULT_PROFILE
+    }
+}
+
+/// Values that used to be baked in at compile time via cfg(debug_assertions).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub video_duration: u64,
+    pub video_width: i32,
+    pub video_height: i32,
+    pub video_framerate: i32,
+    pub main_dir: String,
+    pub db_path: String,
+    pub recording_dir: String,
+    pub recording_save_dir: String,
+    pub segments_to_keep: i64,
+}
+
+impl Profile {
+    pub fn dev() -> Self {
+        Self {
+            video_duration: 2,
+            video_width: 640,
+            video_height: 480,
+            video_framerate: 10,
+            main_dir: "./".to_string(),
+            db_path: "./dashcam.db".to_string(),
+            recording_dir: "./recordings/".to_string(),
+            recording_save_dir: "./recordings/save/".to_string(),
+            segments_to_keep: 86400 / 2 * 2, // 2 days worth
+        }
+    }
+
+    pub fn production() -> Self {
+        Self {
+            video_duration: 2,
+            video_width: 1920,
+            video_height: 1080,
+            video_framerate: 30,
+            main_dir: "/var/lib/dashcam/".to_string(),
+            db_path: "/var/lib/dashcam/dashcam.db".to_string(),
+            recording_dir: "/var/lib/dashcam/recordings/".to_string(),
+            recording_save_dir: "/var/lib/dashcam/recordings/save/".to_string(),
+            segments_to_keep: 86400 / 2 * 2, // 2 days worth
+        }
+    }
+
+    pub fn with_overrides(mut self, overrides: &ProfileOverrides) -> Self {
+        if let Some(v) = overrides.video_duration {
+            self.video_duration = v;
+        }
+        if let Some(v) = overrides.video_width {
+            self.video_width = v;
+        }
+        if let Some(v) = overrides.video_height {
+            self.video_height = v;
+        }
+        if let Some(v) = overrides.video_framerate {
+            self.video_framerate = v;
+        }
+        if let Some(v) = &overrides.main_dir {
+            self.main_dir = v.clone();
+        }
+        if let Some(v) = &overrides.db_path {
+            self.db_path = v.clone();
+        }
+        if let Some(v) = &overrides.recording_dir {
+            self.recording_dir = v.clone();
+        }
+        if let Some(v) = &overrides.recording_save_dir {
+            self.recording_save_dir = v.clone();
+        }
+        if let Some(v) = overrides.segments_to_keep {
+            self.segments_to_keep = v;
+        }
+        self
+    }
+}
+
+/// The built-in profile matching how the binary was compiled.
+impl Default for Profile {
+    fn default() -> Self {
+        Profiles::default().get(ProfileName::default()).clone()
+    }
+}
+
+/// Partial profile from a `[profiles.<name>]` table; unset fields keep the built-in value.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileOverrides {
+    pub video_duration: Option<u64>,
+    pub video_width: Option<i32>,
+    pub video_height: Option<i32>,
+    pub video_framerate: Option<i32>,
+    pub main_dir: Option<String>,
+    pub db_path: Option<String>,
+    pub recording_dir: Option<String>,
+    pub recording_save_dir: Option<String>,
+    pub segments_to_keep: Option<i64>,
+}
+
+/// `[profiles.dev]` / `[profiles.production]` sections of config.toml.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfilesConfig {
+    pub dev: ProfileOverrides,
+    pub production: ProfileOverrides,
+}
+
+/// Every named profile, with config overrides applied.
+#[derive(Debug, Clone)]
+pub struct Profiles {
+    pub dev: Profile,
+    pub production: Profile,
+}
+
+impl Profiles {
+    pub fn from_config(cfg: &ProfilesConfig) -> Self {
+        Self {
+            dev: Profile::dev().with_overrides(&cfg.dev),
+            production: Profile::production().with_overrides(&cfg.production),
+        }
+    }
+
+    pub fn get(&self, name: ProfileName) -> &Profile {
+        match name {
+            ProfileName::Dev => &self.dev,
+            ProfileName::Production => &self.production,
+        }
+    }
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self::from_config(&ProfilesConfig::default())
+    }
+}
@@ -0,0 +1,152 @@
+//! "Save this moment": keep the ring segments of a time window from being
+//! overwritten, without cutting a clip.
+//!
+//! Every catalogued segment of the camera overlapping the window is hard-linked
+//! (copied when the save dir is on another filesystem) into
+//! `<recording_save_dir>/<camera>/<YYYY-MM-DD>/<HH-MM-SS>_<kind>_segments/`
+//! and listed in `protected_segments`. When the ring comes back to a linked
+//! file it unlinks it and starts a new one instead of truncating it, so the
+//! saved link keeps the old footage.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::db::db::{DashcamDb, SegmentRow};
+use crate::events::EventKind;
+
+/// Window before now saved when the request doesn't say
+pub const DEFAULT_BEFORE_SEC: u64 = 60;
+/// How long to give the segment closed by the save to reach the catalog
+pub const CATALOG_DELAY: Duration = Duration::from_secs(3);
+
+/// What to save and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtectRequest {
+    pub camera_id: i64,
+    pub from_utc: i64,
+    pub to_utc: i64,
+    pub kind: EventKind,
+    pub recording_root: PathBuf,
+    /// From `protected_dir`
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProtectSummary {
+    pub linked: usize,
+    pub copied: usize,
+    /// Saved by an earlier request already
+    pub already: usize,
+    /// Catalogued but gone from disk
+    pub missing: usize,
+}
+
+/// `<save_dir>/<camera>/<YYYY-MM-DD>/<HH-MM-SS>_<kind>_segments`
+pub fn protected_dir(save_dir: &Path, camera_key: &str, kind: EventKind, at: DateTime<Local>) -> PathBuf {
+    save_dir
+        .join(camera_key)
+        .join(at.format("%Y-%m-%d").to_string())
+        .join(format!("{}_{}_segments", at.format("%H-%M-%S"), kind))
+}
+
+/// Hard-link `from` to `to`, or copy it if that's not possible. True if linked.
+fn link_or_copy(from: &Path, to: &Path) -> Result<bool> {
+    match fs::hard_link(from, to) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            warn!("Can't hard-link {} ({}), copying it", from.display(), e);
+            fs::copy(from, to).with_context(|| format!("Failed to copy {}", from.display()))?;
+            Ok(false)
+        }
+    }
+}
+
+/// The catalogued segments of a window, as the DB worker finds them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProtectPlan {
+    /// Not saved yet, oldest first
+    pub segments: Vec<SegmentRow>,
+    /// Saved by an earlier request already
+    pub already: usize,
+}
+
+/// The segments of `camera_id` overlapping `[from_utc, to_utc]`, less the saved ones.
+pub fn plan_window(db: &DashcamDb, camera_id: i64, from_utc: i64, to_utc: i64) -> rusqlite::Result<ProtectPlan> {
+    let mut plan = ProtectPlan::default();
+    for segment in db.segments_between(camera_id, from_utc, to_utc)? {
+        if db.is_segment_protected(segment.id)? {
+            plan.already += 1;
+        } else {
+            plan.segments.push(segment);
+        }
+    }
+    Ok(plan)
+}
+
+/// Link (or copy) the segments of `plan` into `request.dir`, off the DB worker.
+/// Returns what was done and `(segment, saved path)` of every saved one, for
+/// `record_saved`.
+pub fn save_segments(
+    request: &ProtectRequest,
+    plan: ProtectPlan,
+) -> Result<(ProtectSummary, Vec<(SegmentRow, String)>)> {
+    let mut summary = ProtectSummary { already: plan.already, ..ProtectSummary::default() };
+    let mut saved_segments = Vec::new();
+    for segment in plan.segments {
+        let source = request.recording_root.join(&segment.rel_path);
+        if !source.exists() {
+            summary.missing += 1;
+            continue;
+        }
+        fs::create_dir_all(&request.dir).with_context(|| format!("Failed to create {}", request.dir.display()))?;
+        let start = DateTime::<Utc>::from_timestamp(segment.start_utc, 0).unwrap_or_default().with_timezone(&Local);
+        let extension = source.extension().map_or(String::new(), |e| format!(".{}", e.to_string_lossy()));
+        let saved = request
+            .dir
+            .join(format!("{}_sink{}{}", start.format("%H-%M-%S"), segment.sink_id, extension));
+        if link_or_copy(&source, &saved)? {
+            summary.linked += 1;
+        } else {
+            summary.copied += 1;
+        }
+        saved_segments.push((segment, saved.to_string_lossy().to_string()));
+    }
+    info!(
+        "Saved {} segments to {} ({} linked, {} copied), {} already saved, {} gone",
+        summary.linked + summary.copied,
+        request.dir.display(),
+        summary.linked,
+        summary.copied,
+        summary.already,
+        summary.missing
+    );
+    Ok((summary, saved_segments))
+}
+
+/// List what `save_segments` saved in `protected_segments`.
+pub fn record_saved(
+    db: &DashcamDb,
+    camera_id: i64,
+    kind: EventKind,
+    saved: &[(SegmentRow, String)],
+    saved_at_utc: i64,
+) -> rusqlite::Result<()> {
+    for (segment, saved_path) in saved {
+        db.insert_protected_segment(camera_id, segment, saved_path, kind, saved_at_utc)?;
+    }
+    Ok(())
+}
+
+/// Save every segment of `request.camera_id` overlapping the window, all on
+/// the caller's thread; for tools that have the DB open themselves.
+pub fn protect_window(db: &DashcamDb, request: &ProtectRequest) -> Result<ProtectSummary> {
+    let plan = plan_window(db, request.camera_id, request.from_utc, request.to_utc)?;
+    let (summary, saved) = save_segments(request, plan)?;
+    record_saved(db, request.camera_id, request.kind, &saved, Utc::now().timestamp())?;
+    Ok(summary)
+}
@@ -2,34 +2,199 @@
 use anyhow::{Context, Result, bail};
 use gstreamer as gst;
 use gstreamer::prelude::*;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use tracing::info;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
 
-use crate::constants::*;
-use crate::pipeline_sinks::pipeline_sink::PipelineSink;
-use crate::pipeline_sources::pipeline_source::PipelineSource;
+use crate::config::{EncoderKind, ProcessingConfig};
+use crate::events::CameraEvent;
+use crate::pipeline_sinks::frame_tap_sink::{FrameTapConfig, FrameTapSink, FrameTarget};
+use crate::pipeline_sinks::pipeline_sink::{CurrentSegmentInfo, ElementMessageHandler, PipelineSink};
+use crate::pipeline_sinks::pre_roll::PreRollBuffer;
+use crate::pipeline_sinks::staging::StagingConfig;
+use crate::pipeline_sources::processing::GPS_OVERLAY;
+use crate::pipeline_sinks::throughput::{SinkThroughput, ThroughputMonitor};
+use crate::profiles::Profile;
+use crate::pipeline_sources::audio_source::{AudioConfig, build_audio_branch};
+use crate::pipeline_sources::pipeline_source::{PipelineSource, StreamFormat};
+
+/// How long a stop waits for the EOS to reach every sink before forcing NULL
+const EOS_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often a source that isn't ready yet is checked again during a start
+const SOURCE_POLL: Duration = Duration::from_millis(250);
+/// Encoder bitrate for sources that encode themselves, unless the camera or a mode sets one
+const DEFAULT_BITRATE_KBPS: u32 = 2000;
 
 #[derive(Clone)]
 pub struct RecordingConfig {
+    pub camera_key: String,
     pub recording_dir: String,
     pub video_duration: u64, // in seconds
     pub video_width: i32,
     pub video_height: i32,
     pub frame_rate: i32,
+    /// Target bitrate of the source's encoder, in kbit/s
+    pub bitrate_kbps: u32,
+    /// Encoder element; the codec's software encoder when `None`
+    pub encoder: Option<EncoderKind>,
+    /// x264enc/x265enc `speed-preset` and `tune`; ultrafast/zerolatency when `None`
+    pub speed_preset: Option<String>,
+    pub tune: Option<String>,
+    /// Frames between keyframes; one a second when `None`
+    pub keyframe_interval: Option<u32>,
+    /// Encodings kept ready next to this one (`warm_spare` modes), switched to without a rebuild
+    pub spare_encodings: Vec<EncodingSettings>,
+    pub processing: ProcessingConfig,
+    /// Speed and position burnt into the frames, see `gps_overlay`
+    pub overlay_gps: bool,
+    /// Segments are this many times shorter than `video_duration` (`--simulate`)
+    pub time_scale: u32,
+    /// `[staging]`: file sinks write to its dir and move closed segments to `recording_dir`
+    pub staging: Option<StagingConfig>,
+    /// Microphone muxed into the sinks that take audio
+    pub audio: Option<AudioConfig>,
+}
+
+impl RecordingConfig {
+    pub fn from_profile(profile: &Profile) -> Self {
+        Self {
+            camera_key: "dashcam".to_string(),
+            recording_dir: profile.recording_dir.clone(),
+            video_duration: profile.video_duration,
+            video_width: profile.video_width,
+            video_height: profile.video_height,
+            frame_rate: profile.video_framerate,
+            bitrate_kbps: DEFAULT_BITRATE_KBPS,
+            encoder: None,
+            speed_preset: None,
+            tune: None,
+            keyframe_interval: None,
+            spare_encodings: Vec::new(),
+            processing: ProcessingConfig::default(),
+            overlay_gps: false,
+            time_scale: 1,
+            staging: None,
+            audio: None,
+        }
+    }
+
+    /// Wall time of one segment.
+    pub fn segment_length(&self) -> Duration {
+        Duration::from_secs(self.video_duration) / self.time_scale.max(1)
+    }
+
+    pub fn encoding(&self) -> EncodingSettings {
+        EncodingSettings {
+            width: self.video_width,
+            height: self.video_height,
+            frame_rate: self.frame_rate,
+            bitrate_kbps: self.bitrate_kbps,
+        }
+    }
+
+    pub fn set_encoding(&mut self, encoding: EncodingSettings) {
+        self.video_width = encoding.width;
+        self.video_height = encoding.height;
+        self.frame_rate = encoding.frame_rate;
+        self.bitrate_kbps = encoding.bitrate_kbps;
+    }
+}
+
+/// What a source that encodes itself records at. `[[modes]]` can change it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodingSettings {
+    pub width: i32,
+    pub height: i32,
+    pub frame_rate: i32,
+    pub bitrate_kbps: u32,
+}
+
+/// How `RecordingPipeline::set_encoding` got new settings in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodingChange {
+    /// Nothing to do: same settings, or a source that doesn't encode
+    Unchanged,
+    /// Bitrate set on the running encoder
+    Live,
+    /// Flipped to the spare encoder prepared for it; segments split at the switch
+    Switched,
+    /// Graph torn down; it rebuilds with the new settings on the next start,
+    /// which is up to the caller when `was_running`
+    Rebuilt { was_running: bool },
 }
 
 impl Default for RecordingConfig {
     fn default() -> Self {
-        Self {
-            recording_dir: RECORDING_DIR.to_string(),
-            video_duration: VIDEO_DURATION,
-            video_width: VIDEO_WIDTH,
-            video_height: VIDEO_HEIGHT,
-            frame_rate: VIDEO_FRAMERATE,
+        Self::from_profile(&Profile::default())
+    }
+}
+/// How far a pipeline got towards PLAYING. Shown per camera in `/api/status`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StartState {
+    Stopped,
+    /// PLAYING requested, state change still ASYNC (prerolling, RTSP connecting, ...)
+    Starting,
+    /// Didn't reach PLAYING within the start timeout; still trying in the background
+    TimedOut,
+    Playing,
+    /// Live source: PLAYING without preroll (NO_PREROLL)
+    PlayingLive,
+    Failed(String),
+}
+
+impl StartState {
+    pub fn as_str(&self) -> &str {
+        match self {
+            StartState::Stopped => "stopped",
+            StartState::Starting => "starting",
+            StartState::TimedOut => "timed out",
+            StartState::Playing => "playing",
+            StartState::PlayingLive => "playing (live)",
+            StartState::Failed(reason) => reason,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self, StartState::Playing | StartState::PlayingLive)
+    }
+}
+
+/// Waits for a started pipeline to reach PLAYING without borrowing the
+/// `RecordingPipeline`, so callers can release their lock meanwhile.
+pub struct StartWaiter {
+    camera_key: String,
+    state: Arc<Mutex<StartState>>,
+}
+
+impl StartWaiter {
+    /// `Ok` once PLAYING; an error if the pipeline failed or `timeout` passed first
+    /// (in which case it keeps trying and the state becomes `TimedOut`).
+    pub fn wait(&self, timeout: Duration) -> Result<StartState> {
+        let started = Instant::now();
+        loop {
+            let mut state = self.state.lock().unwrap();
+            match &*state {
+                s if s.is_playing() => return Ok(s.clone()),
+                StartState::Failed(reason) => bail!("Camera '{}' failed to start: {}", self.camera_key, reason),
+                StartState::Stopped => bail!("Camera '{}' stopped while starting", self.camera_key),
+                _ if started.elapsed() >= timeout => {
+                    *state = StartState::TimedOut;
+                    bail!(
+                        "Camera '{}' didn't reach PLAYING within {}s (state change still async)",
+                        self.camera_key,
+                        timeout.as_secs()
+                    );
+                }
+                _ => {}
+            }
+            drop(state);
+            std::thread::sleep(Duration::from_millis(50));
         }
     }
 }
+
 ////////////////////////////////////////////////////////////
 /// Main recording pipeline that orchestrates sources and sinks
 #[allow(dead_code)]
@@ -40,9 +205,43 @@ pub struct RecordingPipeline {
 
     source: Option<Box<dyn PipelineSource>>,
     sinks: Vec<Box<dyn PipelineSink>>,
+    // sink_id -> enabled; each sink branch is gated by a valve so it can be toggled live
+    sink_enabled: HashMap<i64, bool>,
+    sink_valves: HashMap<i64, gst::Element>,
+    // tee request pads, requested on every start and released on stop
+    sink_tee_pads: HashMap<i64, gst::Pad>,
+    // what each sink's setup_sink added to the pipeline, for remove_sink
+    sink_elements: HashMap<i64, Vec<gst::Element>>,
+    // audio branch, if the camera records audio, and the valve in front of each sink that muxes it;
+    // unlike the video tee pads these stay linked across stops
+    audio_tee: Option<gst::Element>,
+    sink_audio_valves: HashMap<i64, gst::Element>,
+    // sinks that only record while armed; their pre-roll queues sit between valve and sink
+    triggers: HashMap<i64, PreRollBuffer>,
 
-    pub current_video_name: Arc<Mutex<String>>,
     pipeline_thread: Option<std::thread::JoinHandle<()>>,
+    // graph already built by an earlier start; a restart (e.g. after standby) reuses it
+    built: bool,
+    start_state: Arc<Mutex<StartState>>,
+    start_timeout: Duration,
+    // how long a build waits for `check_ready`, and how often a failed start is retried
+    source_startup_timeout: Duration,
+    start_retry_interval: Option<Duration>,
+    // hot-standby source and what it is; taken once the primary failed `failover_after` starts in a row
+    backup_source: Option<(Box<dyn PipelineSource>, String)>,
+    failover_after: u32,
+    start_failures: u32,
+    on_backup: bool,
+    // privacy switch on: kept stopped, nothing starts it
+    private: bool,
+    // not recording in the current [[modes]] mode; kept stopped the same way
+    off_in_mode: bool,
+    // stopped over the control API until started there again
+    halted: bool,
+    // encoding the camera was configured with, before any [[modes]] override
+    default_encoding: EncodingSettings,
+    // what the file sinks report their write throughput to, for sources that encode
+    throughput: Option<ThroughputMonitor>,
 }
 
 #[allow(dead_code)]
@@ -52,14 +251,35 @@ impl RecordingPipeline {
 
         std::fs::create_dir_all(&config.recording_dir)?;
         let pipeline = gst::Pipeline::with_name("dashcam_pipeline");
+        let default_encoding = config.encoding();
         Ok(Self {
-            pipeline: pipeline,
+            pipeline,
             source: None,
             sinks: Vec::new(),
+            sink_enabled: HashMap::new(),
+            sink_valves: HashMap::new(),
+            sink_tee_pads: HashMap::new(),
+            sink_elements: HashMap::new(),
+            audio_tee: None,
+            sink_audio_valves: HashMap::new(),
+            triggers: HashMap::new(),
             config,
             pipeline_running: Arc::new(AtomicBool::new(false)),
-            current_video_name: Arc::new(Mutex::new("None".to_string())),
             pipeline_thread: None,
+            built: false,
+            start_state: Arc::new(Mutex::new(StartState::Stopped)),
+            start_timeout: Duration::from_secs(20),
+            source_startup_timeout: Duration::from_secs(10),
+            start_retry_interval: None,
+            backup_source: None,
+            failover_after: 0,
+            start_failures: 0,
+            on_backup: false,
+            private: false,
+            off_in_mode: false,
+            halted: false,
+            default_encoding,
+            throughput: None,
         })
     }
 
@@ -67,23 +287,426 @@ impl RecordingPipeline {
         self.source = Some(source);
     }
 
+    /// Source to switch to after `after_failures` failed starts of the current one in a row.
+    pub fn set_backup_source(&mut self, source: Box<dyn PipelineSource>, description: String, after_failures: u32) {
+        self.backup_source = Some((source, description));
+        self.failover_after = after_failures;
+    }
+
+    pub fn has_backup_source(&self) -> bool {
+        self.backup_source.is_some()
+    }
+
+    /// Whether the camera runs on its backup source after a failover.
+    pub fn is_on_backup(&self) -> bool {
+        self.on_backup
+    }
+
+    /// Count a start that didn't get to PLAYING. Returns whether it's time to fail over.
+    pub fn note_start_failure(&mut self) -> bool {
+        self.start_failures += 1;
+        self.backup_source.is_some() && self.start_failures >= self.failover_after
+    }
+
+    pub fn start_failures(&self) -> u32 {
+        self.start_failures
+    }
+
+    pub fn reset_start_failures(&mut self) {
+        self.start_failures = 0;
+    }
+
+    /// Tear the graph down and put the backup source in place of the failing one.
+    /// Returns the backup's description; the next start builds on it.
+    pub fn fail_over(&mut self) -> Result<String> {
+        let (backup, description) = self
+            .backup_source
+            .take()
+            .with_context(|| format!("Camera '{}' has no backup source", self.config.camera_key))?;
+        self.rebuild_pipeline()?;
+        self.source = Some(backup);
+        self.on_backup = true;
+        self.start_failures = 0;
+        warn!("Camera '{}' failed over to its backup source ({})", self.config.camera_key, description);
+        Ok(description)
+    }
+
+    /// Privacy mode: stops the pipeline when turned on. Starting is up to the
+    /// caller when it's turned off; `begin_start` refuses while it's on.
+    pub fn set_private(&mut self, private: bool) -> Result<()> {
+        self.private = private;
+        if private && self.is_started() {
+            self.stop_pipeline()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+
+    /// Same as `set_private`, for a `[[modes]]` mode the camera doesn't record in.
+    pub fn set_off_in_mode(&mut self, off: bool) -> Result<()> {
+        self.off_in_mode = off;
+        if off && self.is_started() {
+            self.stop_pipeline()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_off_in_mode(&self) -> bool {
+        self.off_in_mode
+    }
+
+    /// Same as `set_private`, for a camera stopped over the control API.
+    pub fn set_halted(&mut self, halted: bool) -> Result<()> {
+        self.halted = halted;
+        if halted && self.is_started() {
+            self.stop_pipeline()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Kept stopped on purpose: privacy switch, current mode or control API.
+    pub fn is_held(&self) -> bool {
+        self.private || self.off_in_mode || self.halted
+    }
+
+    /// Settings from the camera's own config, before any mode changed them.
+    pub fn default_encoding(&self) -> EncodingSettings {
+        self.default_encoding
+    }
+
+    /// Record with `encoding` from now on (primary and backup source). A spare
+    /// encoder prepared for it is switched to in place; otherwise a new bitrate
+    /// goes straight to the running encoder when it takes one while PLAYING, and
+    /// a new size or frame rate needs the graph rebuilt, which this does,
+    /// leaving the restart to the caller.
+    pub fn set_encoding(&mut self, encoding: EncodingSettings) -> Result<EncodingChange> {
+        let current = self.config.encoding();
+        if encoding == current {
+            return Ok(EncodingChange::Unchanged);
+        }
+        if self.built && self.source.as_mut().is_some_and(|source| source.switch_encoding(encoding)) {
+            // a later rebuild starts on the encoding in use
+            if let Some(source) = self.source.as_mut() {
+                source.set_encoding(encoding);
+            }
+            if let Some((backup, _)) = self.backup_source.as_mut() {
+                backup.set_encoding(encoding);
+            }
+            self.config.set_encoding(encoding);
+            self.expect_bitrate(encoding.bitrate_kbps);
+            // one encoding per file
+            if self.is_running() {
+                for sink in &self.sinks {
+                    if self.is_sink_enabled(sink.sink_id()) == Some(true) {
+                        sink.split_now();
+                    }
+                }
+            }
+            return Ok(EncodingChange::Switched);
+        }
+
+        let encodes = self.source.as_mut().is_some_and(|source| source.set_encoding(encoding));
+        if let Some((backup, _)) = self.backup_source.as_mut() {
+            backup.set_encoding(encoding);
+        }
+        if !encodes {
+            return Ok(EncodingChange::Unchanged);
+        }
+        self.config.set_encoding(encoding);
+        self.expect_bitrate(encoding.bitrate_kbps);
+        if !self.built {
+            return Ok(EncodingChange::Unchanged);
+        }
+
+        let bitrate_only = EncodingSettings { bitrate_kbps: encoding.bitrate_kbps, ..current } == encoding;
+        if bitrate_only && self.is_running() && self.set_live_bitrate(encoding.bitrate_kbps) {
+            return Ok(EncodingChange::Live);
+        }
+        let was_running = self.is_started();
+        self.rebuild_pipeline()?;
+        Ok(EncodingChange::Rebuilt { was_running })
+    }
+
+    fn expect_bitrate(&self, kbps: u32) {
+        if let Some(monitor) = &self.throughput {
+            monitor.set_expected_kbps(kbps);
+        }
+    }
+
+    /// Set the "encoder" element's bitrate (kbit/s) if it can change while PLAYING.
+    fn set_live_bitrate(&self, kbps: u32) -> bool {
+        let Some(encoder) = self.pipeline.by_name("encoder") else {
+            return false;
+        };
+        let mutable = encoder.find_property("bitrate").is_some_and(|pspec| {
+            pspec.value_type() == u32::static_type() && pspec.flags().contains(gst::PARAM_FLAG_MUTABLE_PLAYING)
+        });
+        if !mutable {
+            return false;
+        }
+        encoder.set_property("bitrate", kbps);
+        info!("Camera '{}' encoder bitrate now {} kbit/s", self.config.camera_key, kbps);
+        true
+    }
+
+    /// Set the text of the `overlay_gps` textoverlay, if the source has one.
+    pub fn set_gps_overlay(&self, text: &str) {
+        if let Some(overlay) = self.pipeline.by_name(GPS_OVERLAY) {
+            overlay.set_property("text", text);
+        }
+    }
+
+    /// Watch the sinks' write throughput against the encoder bitrate; the sinks
+    /// must have been built `with_throughput` the same monitor.
+    pub fn set_throughput_monitor(&mut self, monitor: ThroughputMonitor) {
+        self.throughput = Some(monitor);
+    }
+
+    /// Write throughput of each file sink that has closed a segment.
+    pub fn throughput(&self) -> Vec<SinkThroughput> {
+        self.throughput.as_ref().map(ThroughputMonitor::sinks).unwrap_or_default()
+    }
+
     pub fn get_source_tee(&self) -> Result<gst::Element> {
         self.source.as_ref().context("No source set")?.get_tee()
     }
 
-    pub fn add_sink(&mut self, sink: Box<dyn PipelineSink>) {
+    pub fn add_sink(&mut self, sink: Box<dyn PipelineSink>, enabled: bool) {
+        self.sink_enabled.insert(sink.sink_id(), enabled);
         self.sinks.push(sink);
     }
 
+    /// Attach a decoded-frame tap for an external analyzer. Must be called before
+    /// `start_pipeline`; returns the sink_id it got, usable with `set_sink_enabled`.
+    pub fn add_frame_tap(&mut self, tap_config: FrameTapConfig, target: FrameTarget) -> Result<i64> {
+        if self.is_running() || self.built {
+            bail!("Frame taps must be added before camera '{}' starts", self.config.camera_key);
+        }
+        let StreamFormat::Encoded(codec) = self.source.as_ref().context("No source set")?.output_format() else {
+            bail!("Camera '{}' source isn't encoded, can't tap it", self.config.camera_key);
+        };
+
+        let sink_id = self.sinks.iter().map(|s| s.sink_id()).max().map_or(0, |id| id + 1);
+        let tap = FrameTapSink::new(self.config.camera_key.clone(), sink_id, codec, tap_config, target);
+        self.add_sink(Box::new(tap), true);
+        Ok(sink_id)
+    }
+
+    /// Open/close the valve in front of a sink. Works before and while the pipeline is running.
+    pub fn set_sink_enabled(&mut self, sink_id: i64, enabled: bool) -> Result<()> {
+        let state = self
+            .sink_enabled
+            .get_mut(&sink_id)
+            .with_context(|| format!("No sink with sink_id={} on camera '{}'", sink_id, self.config.camera_key))?;
+        *state = enabled;
+
+        if let Some(valve) = self.sink_audio_valves.get(&sink_id) {
+            valve.set_property("drop", !(enabled && self.is_sink_armed(sink_id)));
+        }
+        if let Some(valve) = self.sink_valves.get(&sink_id) {
+            valve.set_property("drop", !enabled);
+            if enabled {
+                // Don't make the sink wait a whole GOP before its first decodable frame
+                request_keyframe(valve);
+            }
+        }
+        info!(
+            "Camera '{}' sink_id={} {}",
+            self.config.camera_key,
+            sink_id,
+            if enabled { "enabled" } else { "disabled" }
+        );
+        Ok(())
+    }
+
+    /// Make a sink record only while triggered (`trigger`), starting with up to
+    /// `pre_roll` of the video from before the trigger and going on until `post_roll`
+    /// after the last one. Call before `start_pipeline`. Audio isn't pre-rolled, it
+    /// joins when the sink is armed.
+    pub fn set_sink_triggered(&mut self, sink_id: i64, pre_roll: Duration, post_roll: Duration) -> Result<()> {
+        if self.built {
+            bail!("Camera '{}' sink_id={}: triggers must be set before the first start", self.config.camera_key, sink_id);
+        }
+        if !self.sink_enabled.contains_key(&sink_id) {
+            bail!("No sink with sink_id={} on camera '{}'", sink_id, self.config.camera_key);
+        }
+        self.triggers.insert(sink_id, PreRollBuffer::new(pre_roll, post_roll));
+        Ok(())
+    }
+
+    /// Motion, or an event, at `now`: arm the triggered sinks, or keep them armed
+    /// for another post-roll. Works before and while the pipeline is running.
+    pub fn trigger(&mut self, now: Instant) {
+        let armed: Vec<i64> =
+            self.triggers.iter_mut().filter_map(|(sink_id, buffer)| buffer.trigger(now).then_some(*sink_id)).collect();
+        for sink_id in armed {
+            self.armed_changed(sink_id, true);
+        }
+    }
+
+    /// Disarm the triggered sinks whose post-roll is over.
+    pub fn expire_triggers(&mut self, now: Instant) {
+        let disarmed: Vec<i64> =
+            self.triggers.iter_mut().filter_map(|(sink_id, buffer)| buffer.expire(now).then_some(*sink_id)).collect();
+        for sink_id in disarmed {
+            self.armed_changed(sink_id, false);
+        }
+    }
+
+    fn armed_changed(&self, sink_id: i64, armed: bool) {
+        if let Some(valve) = self.sink_audio_valves.get(&sink_id) {
+            valve.set_property("drop", !(armed && self.is_sink_enabled(sink_id) == Some(true)));
+        }
+        info!(
+            "Camera '{}' sink_id={} {}",
+            self.config.camera_key,
+            sink_id,
+            if armed { "armed" } else { "disarmed" }
+        );
+    }
+
+    /// False only for a triggered sink that isn't armed.
+    pub fn is_sink_armed(&self, sink_id: i64) -> bool {
+        self.triggers.get(&sink_id).is_none_or(PreRollBuffer::is_armed)
+    }
+
+    /// Hand an event to every enabled sink (burst capture, clip protection, ...).
+    /// It triggers the triggered sinks too, so the event's footage gets recorded.
+    pub fn notify_event(&mut self, event: &CameraEvent) {
+        info!("Camera '{}' event: {}", self.config.camera_key, event.kind);
+        self.trigger(Instant::now());
+        for sink in &self.sinks {
+            if self.is_sink_enabled(sink.sink_id()) == Some(true) {
+                sink.on_event(event);
+            }
+        }
+    }
+
+    /// Close every segment being written right away (splitmuxsink `split-now`)
+    /// instead of at the end of the segment duration; recording carries on in new files.
+    pub fn split_segments(&self) -> Result<()> {
+        if !self.is_running() {
+            bail!("Camera '{}' isn't recording", self.config.camera_key);
+        }
+        for sink in &self.sinks {
+            if self.is_sink_enabled(sink.sink_id()) == Some(true) {
+                sink.split_now();
+            }
+        }
+        info!("Camera '{}' segments split", self.config.camera_key);
+        Ok(())
+    }
+
+    pub fn sink_ids(&self) -> Vec<i64> {
+        let mut ids: Vec<i64> = self.sink_enabled.keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Elements currently in the GStreamer pipeline.
+    pub fn element_count(&self) -> usize {
+        self.pipeline.children().len()
+    }
+
+    pub fn is_sink_enabled(&self, sink_id: i64) -> Option<bool> {
+        self.sink_enabled.get(&sink_id).copied()
+    }
+
+    pub fn camera_key(&self) -> &str {
+        &self.config.camera_key
+    }
+
     pub fn is_running(&self) -> bool {
         self.pipeline_running.load(Ordering::SeqCst)
     }
 
+    /// GStreamer state the pipeline has actually reached (not the pending one).
+    pub fn current_state(&self) -> gst::State {
+        self.pipeline.current_state()
+    }
+
+    pub fn start_state(&self) -> StartState {
+        self.start_state.lock().unwrap().clone()
+    }
+
+    /// How long `start_pipeline` waits for PLAYING.
+    pub fn set_start_timeout(&mut self, timeout: Duration) {
+        self.start_timeout = timeout;
+    }
+
+    /// How long a start waits for the source device to appear, and how often a
+    /// camera that didn't come up is retried in the background (`None`: never).
+    pub fn set_source_startup(&mut self, timeout: Duration, retry_interval: Option<Duration>) {
+        self.source_startup_timeout = timeout;
+        self.start_retry_interval = retry_interval;
+    }
+
+    pub fn start_retry_interval(&self) -> Option<Duration> {
+        self.start_retry_interval
+    }
+
+    /// Whether the source device is there right now, without waiting for it.
+    pub fn check_source_ready(&self) -> Result<()> {
+        self.source.as_ref().context("No source set")?.check_ready()
+    }
+
+    pub fn start_waiter(&self) -> StartWaiter {
+        StartWaiter {
+            camera_key: self.config.camera_key.clone(),
+            state: self.start_state.clone(),
+        }
+    }
+
+    /// (sink_id, file) for every sink that is writing a file right now.
+    pub fn current_segments(&self) -> Vec<(i64, CurrentSegmentInfo)> {
+        self.sinks
+            .iter()
+            .filter_map(|s| s.current_segment().map(|info| (s.sink_id(), info)))
+            .collect()
+    }
+
+    /// Path of the most recently opened file across all sinks.
+    pub fn current_video_name(&self) -> Option<String> {
+        self.current_segments()
+            .into_iter()
+            .max_by_key(|(_, info)| info.started_at)
+            .map(|(_, info)| info.path)
+    }
+
     pub fn config(&self) -> &RecordingConfig {
         &self.config
     }
 
+    /// Start and wait (up to the start timeout) until the pipeline is PLAYING.
     pub fn start_pipeline(&mut self) -> Result<()> {
+        self.begin_start()?;
+        self.start_waiter().wait(self.start_timeout).map(|_| ())
+    }
+
+    /// Build if needed and request PLAYING on the pipeline thread, without waiting.
+    /// Follow up with `start_waiter().wait(..)`.
+    pub fn begin_start(&mut self) -> Result<()> {
+        if self.private {
+            bail!("Camera '{}' is in privacy mode", self.config.camera_key);
+        }
+        if self.off_in_mode {
+            bail!("Camera '{}' doesn't record in the current mode", self.config.camera_key);
+        }
+        if self.halted {
+            bail!("Camera '{}' was stopped over the control API", self.config.camera_key);
+        }
+        // The runner exits on its own after an error or EOS; clean up after it first
+        if self.pipeline_thread.as_ref().is_some_and(|h| h.is_finished()) {
+            info!("Camera '{}' pipeline thread has exited, resetting before start", self.config.camera_key);
+            self.stop_pipeline()?;
+        }
         if self.pipeline_thread.is_none() {
             info!(
                 "Starting pipeline at {}",
@@ -92,12 +715,29 @@ impl RecordingPipeline {
 
             let pipeline = self.pipeline.clone();
             let pipeline_running = self.pipeline_running.clone();
+            let start_state = self.start_state.clone();
+            *start_state.lock().unwrap() = StartState::Starting;
 
-            self.build_pipeline()?;
+            let prepared = if self.built {
+                self.link_branches().and_then(|_| self.validate_pipeline())
+            } else {
+                self.build_pipeline()
+            };
+            if let Err(e) = prepared {
+                *start_state.lock().unwrap() = StartState::Failed(format!("{:#}", e));
+                if !self.built {
+                    // whatever got added would clash with the next attempt's elements
+                    self.remove_all_elements()?;
+                }
+                return Err(e);
+            }
+            self.built = true;
+            let handlers: Vec<ElementMessageHandler> =
+                self.sinks.iter().filter_map(|s| s.message_handler()).collect();
             pipeline_running.store(true, Ordering::SeqCst);
 
             let handle = std::thread::spawn(move || {
-                Self::pipeline_runner(pipeline, pipeline_running);
+                Self::pipeline_runner(pipeline, pipeline_running, start_state, handlers);
             });
             self.pipeline_thread = Some(handle);
 
@@ -107,26 +747,142 @@ impl RecordingPipeline {
         }
     }
 
+    /// Whether a pipeline thread exists, even one that has since stopped on an error.
+    pub fn is_started(&self) -> bool {
+        self.pipeline_thread.is_some()
+    }
+
+    /// Stop and put the pipeline back to NULL. Safe to call at any time, including
+    /// after the pipeline stopped itself on an error; `start_pipeline` works again after.
     pub fn stop_pipeline(&mut self) -> Result<()> {
         info!("Stopping pipeline");
 
-        if self.pipeline_running.load(Ordering::SeqCst) {
-            self.pipeline_running.store(false, Ordering::SeqCst);
+        // Short of PLAYING nothing is being written, and sending EOS can block on a
+        // source that is stuck prerolling
+        let playing = self.pipeline.current_state() == gst::State::Playing;
+        if self.pipeline_running.swap(false, Ordering::SeqCst) {
+            if playing {
+                // A blocked pre-roll queue would hold the EOS back too
+                for buffer in self.triggers.values_mut().filter(|b| !b.is_armed()) {
+                    buffer.drain();
+                }
+                self.pipeline.send_event(gst::event::Eos::new());
+            } else if let Some(bus) = self.pipeline.bus() {
+                // Just end the bus loop
+                let _ = bus.post(gst::message::Eos::builder().src(&self.pipeline).build());
+            }
+        }
+        if let Some(handle) = self.pipeline_thread.take() {
+            let _ = handle.join();
+        }
+        self.pipeline.set_state(gst::State::Null)?;
+        for buffer in self.triggers.values_mut().filter(|b| !b.is_armed()) {
+            buffer.gate();
+        }
+        // Leftover EOS/error messages would end the next run as soon as it starts
+        if let Some(bus) = self.pipeline.bus() {
+            bus.set_flushing(true);
+            bus.set_flushing(false);
+        }
+        *self.start_state.lock().unwrap() = StartState::Stopped;
+        self.release_tee_pads()
+    }
 
-            self.pipeline.send_event(gst::event::Eos::new());
+    /// Stop, then start again with the same elements.
+    pub fn restart_pipeline(&mut self) -> Result<()> {
+        self.stop_pipeline()?;
+        self.start_pipeline()
+    }
 
-            if let Some(handle) = self.pipeline_thread.take() {
-                let _ = handle.join();
+    /// Throw away every element so the next start sets source and sinks up from
+    /// scratch, e.g. after their config changed or an element got stuck. Stops first.
+    pub fn rebuild_pipeline(&mut self) -> Result<()> {
+        self.stop_pipeline()?;
+        self.remove_all_elements()?;
+        self.built = false;
+        info!("Camera '{}' pipeline torn down, rebuilds on next start", self.config.camera_key);
+        Ok(())
+    }
+
+    fn remove_all_elements(&mut self) -> Result<()> {
+        for element in self.pipeline.children() {
+            let _ = element.set_state(gst::State::Null);
+            self.pipeline
+                .remove(&element)
+                .with_context(|| format!("Failed to remove {} from the pipeline", element.name()))?;
+        }
+        self.sink_valves.clear();
+        self.sink_elements.clear();
+        self.sink_tee_pads.clear();
+        self.sink_audio_valves.clear();
+        for buffer in self.triggers.values_mut() {
+            buffer.forget();
+        }
+        self.audio_tee = None;
+        Ok(())
+    }
+
+    /// Unlink and give back every tee request pad. The next start requests fresh ones.
+    fn release_tee_pads(&mut self) -> Result<()> {
+        let Some(tee) = self.source.as_ref().and_then(|s| s.get_tee().ok()) else {
+            return Ok(());
+        };
+        for (sink_id, tee_pad) in self.sink_tee_pads.drain() {
+            if let Some(peer) = tee_pad.peer() {
+                tee_pad
+                    .unlink(&peer)
+                    .with_context(|| format!("sink_id={}: failed to unlink tee pad", sink_id))?;
             }
-            self.pipeline.set_state(gst::State::Null)?;
+            tee.release_request_pad(&tee_pad);
         }
         Ok(())
     }
 
+    /// Take a sink out of a stopped pipeline, removing its valve and elements.
+    pub fn remove_sink(&mut self, sink_id: i64) -> Result<()> {
+        if self.is_running() {
+            bail!("Camera '{}' must be stopped to remove sink_id={}", self.config.camera_key, sink_id);
+        }
+        let idx = self
+            .sinks
+            .iter()
+            .position(|s| s.sink_id() == sink_id)
+            .with_context(|| format!("No sink with sink_id={} on camera '{}'", sink_id, self.config.camera_key))?;
+
+        if let (Some(tee_pad), Ok(tee)) = (self.sink_tee_pads.remove(&sink_id), self.get_source_tee()) {
+            if let Some(peer) = tee_pad.peer() {
+                let _ = tee_pad.unlink(&peer);
+            }
+            tee.release_request_pad(&tee_pad);
+        }
+        let audio_valve = self.sink_audio_valves.remove(&sink_id);
+        if let (Some(valve_pad), Some(tee)) = (audio_valve.as_ref().and_then(|v| v.static_pad("sink")), &self.audio_tee)
+            && let Some(tee_pad) = valve_pad.peer()
+        {
+            let _ = tee_pad.unlink(&valve_pad);
+            tee.release_request_pad(&tee_pad);
+        }
+        let valve = self.sink_valves.remove(&sink_id);
+        let queue = self.triggers.remove(&sink_id).and_then(|mut b| b.forget());
+        let elements = self.sink_elements.remove(&sink_id).unwrap_or_default();
+        for element in valve.iter().chain(queue.iter()).chain(audio_valve.iter()).chain(elements.iter()) {
+            element.set_state(gst::State::Null)?;
+            self.pipeline
+                .remove(element)
+                .with_context(|| format!("sink_id={}: failed to remove {}", sink_id, element.name()))?;
+        }
+
+        self.sinks.remove(idx);
+        self.sink_enabled.remove(&sink_id);
+        info!("Camera '{}' sink_id={} removed", self.config.camera_key, sink_id);
+        Ok(())
+    }
+
     /// Setup the GStreamer Pipeline
     /// - Setup 1 Source
     /// - Setup multiple Sinks
-    /// - Connect Source Tee to each Sink via Pads
+    /// - Connect Source Tee to each Sink via a valve (closed while the sink is disabled)
+    /// - Same for the audio branch and the sinks that mux audio, if the camera records audio
     fn build_pipeline(&mut self) -> Result<()> {
         let source = self.source.as_mut().context("No source set for pipeline")?;
 
@@ -134,46 +890,239 @@ impl RecordingPipeline {
             anyhow::bail!("No sinks added to pipeline");
         }
 
+        let format = source.output_format();
+        for sink in &self.sinks {
+            if !sink.accepts(format) {
+                bail!(
+                    "Camera '{}' sink_id={} can't consume {} from the source (set `transcode = true` on the sink if it supports it)",
+                    self.config.camera_key,
+                    sink.sink_id(),
+                    format
+                );
+            }
+        }
+
+        wait_for_source(source.as_ref(), &self.config.camera_key, self.source_startup_timeout)?;
         source.setup_source(&self.pipeline)?;
 
         for sink in &mut self.sinks {
+            let before = self.pipeline.children();
             sink.setup_sink(&self.pipeline)?;
+            let added = self
+                .pipeline
+                .children()
+                .into_iter()
+                .filter(|e| !before.contains(e))
+                .collect();
+            self.sink_elements.insert(sink.sink_id(), added);
+        }
+
+        for sink in &self.sinks {
+            let sink_id = sink.sink_id();
+            let enabled = self.sink_enabled.get(&sink_id).copied().unwrap_or(true);
+
+            let valve = gst::ElementFactory::make("valve")
+                .name(format!("sink_valve_{}", sink_id))
+                .property("drop", !enabled)
+                .build()
+                .context("Failed to create valve")?;
+            self.pipeline
+                .add(&valve)
+                .context("Failed to add valve to pipeline")?;
+
+            let mut valve_src_pad = valve.static_pad("src").context("Valve has no src pad")?;
+            if let Some(buffer) = self.triggers.get_mut(&sink_id) {
+                let queue = buffer.build(&self.pipeline, sink_id)?;
+                valve_src_pad
+                    .link(&queue.static_pad("sink").context("Queue has no sink pad")?)
+                    .with_context(|| format!("sink_id={}: failed to link valve to pre-roll queue", sink_id))?;
+                valve_src_pad = queue.static_pad("src").context("Queue has no src pad")?;
+            }
+            valve_src_pad
+                .link(&sink.get_sink_pad()?)
+                .with_context(|| format!("sink_id={}: failed to link valve to sink", sink_id))?;
+
+            if !enabled {
+                info!("Camera '{}' sink_id={} is disabled, valve closed", self.config.camera_key, sink_id);
+            }
+            self.sink_valves.insert(sink_id, valve);
         }
 
-        let source_tee = source.get_tee()?;
+        self.build_audio()?;
+        self.link_branches()?;
+        self.validate_pipeline()?;
+
+        Ok(())
+    }
+
+    /// Build the audio branch and link it to every sink that muxes audio, each
+    /// through a valve of its own. Nothing to do without `audio` in the config.
+    fn build_audio(&mut self) -> Result<()> {
+        let Some(audio) = &self.config.audio else {
+            return Ok(());
+        };
+        let audio_pads: Vec<(i64, gst::Pad)> = self
+            .sinks
+            .iter()
+            .filter_map(|sink| Some((sink.sink_id(), sink.get_audio_pad()?)))
+            .collect();
+        if audio_pads.is_empty() {
+            info!("Camera '{}' has audio configured but no sink that muxes it", self.config.camera_key);
+            return Ok(());
+        }
+
+        let tee = build_audio_branch(&self.pipeline, &self.config.camera_key, audio)?;
+        for (sink_id, audio_pad) in audio_pads {
+            let enabled = self.sink_enabled.get(&sink_id).copied().unwrap_or(true) && self.is_sink_armed(sink_id);
+            let valve = gst::ElementFactory::make("valve")
+                .name(format!("sink_audio_valve_{}", sink_id))
+                .property("drop", !enabled)
+                .build()
+                .context("Failed to create valve")?;
+            self.pipeline.add(&valve).context("Failed to add audio valve to pipeline")?;
+            valve
+                .static_pad("src")
+                .context("Valve has no src pad")?
+                .link(&audio_pad)
+                .with_context(|| format!("sink_id={}: failed to link audio valve to sink", sink_id))?;
+            tee.request_pad_simple("src_%u")
+                .context("Failed to request pad from audio tee")?
+                .link(&valve.static_pad("sink").context("Valve has no sink pad")?)
+                .with_context(|| format!("sink_id={}: failed to link audio tee to valve", sink_id))?;
+            self.sink_audio_valves.insert(sink_id, valve);
+        }
+        self.audio_tee = Some(tee);
+        Ok(())
+    }
+
+    /// Request a tee pad per sink and link it to the sink's valve.
+    fn link_branches(&mut self) -> Result<()> {
+        let source_tee = self.get_source_tee()?;
         for sink in &self.sinks {
+            let sink_id = sink.sink_id();
+            let valve = self
+                .sink_valves
+                .get(&sink_id)
+                .with_context(|| format!("sink_id={}: no valve", sink_id))?;
+            let valve_sink_pad = valve.static_pad("sink").context("Valve has no sink pad")?;
+
             let tee_src_pad = source_tee
                 .request_pad_simple("src_%u")
                 .context("Failed to request pad from tee")?;
+            // Check before linking: a failed link only says "not negotiated"
+            check_branch_caps(&self.config.camera_key, sink.as_ref(), &tee_src_pad, &sink.get_sink_pad()?)?;
+            tee_src_pad
+                .link(&valve_sink_pad)
+                .with_context(|| format!("sink_id={}: failed to link tee to valve", sink_id))?;
+            self.sink_tee_pads.insert(sink_id, tee_src_pad);
+        }
+        Ok(())
+    }
+
+    /// Pre-flight check of the built graph so problems surface as a readable error
+    /// naming the broken branch, not a generic not-negotiated error once PLAYING.
+    /// - every tee src pad is linked, and its caps can intersect the sink branch's caps
+    /// - every pad of every top-level element is linked
+    fn validate_pipeline(&self) -> Result<()> {
+        for sink in &self.sinks {
+            let sink_id = sink.sink_id();
+            let tee_pad = self
+                .sink_tee_pads
+                .get(&sink_id)
+                .with_context(|| format!("sink_id={}: no tee pad was requested", sink_id))?;
             let sink_pad = sink.get_sink_pad()?;
+            let sink_name = sink
+                .get_sink_element()
+                .map(|e| e.name().to_string())
+                .unwrap_or_else(|_| "<unknown>".to_string());
 
-            tee_src_pad
-                .link(&sink_pad)
-                .context("Failed to link tee to sink")?;
+            if !tee_pad.is_linked() {
+                bail!(
+                    "Camera '{}' sink_id={} ({}): tee pad {} is not linked",
+                    self.config.camera_key, sink_id, sink_name, tee_pad.name()
+                );
+            }
+            if !sink_pad.is_linked() {
+                bail!(
+                    "Camera '{}' sink_id={} ({}): sink pad {} is not linked",
+                    self.config.camera_key, sink_id, sink_name, sink_pad.name()
+                );
+            }
+
+            check_branch_caps(&self.config.camera_key, sink.as_ref(), tee_pad, &sink_pad)?;
+        }
+
+        for element in self.pipeline.children() {
+            for pad in element.pads() {
+                if !pad.is_linked() {
+                    bail!(
+                        "Camera '{}': pad {}:{} is not linked",
+                        self.config.camera_key,
+                        element.name(),
+                        pad.name()
+                    );
+                }
+            }
         }
 
         Ok(())
     }
 
-    fn pipeline_runner(pipeline: gst::Pipeline, pipeline_running: Arc<AtomicBool>) {
-        match pipeline.set_state(gst::State::Playing) {
-            Ok(_) => info!("Pipeline state successfully set to PLAYING"),
+    fn pipeline_runner(
+        pipeline: gst::Pipeline,
+        pipeline_running: Arc<AtomicBool>,
+        start_state: Arc<Mutex<StartState>>,
+        handlers: Vec<ElementMessageHandler>,
+    ) {
+        // Go through PAUSED on its own: only that step says NO_PREROLL for live sources
+        let next_state = match pipeline
+            .set_state(gst::State::Paused)
+            .and_then(|paused| pipeline.set_state(gst::State::Playing).map(|playing| (paused, playing)))
+        {
+            Ok((gst::StateChangeSuccess::NoPreroll, _)) => StartState::PlayingLive,
+            // completes later; the StateChanged message on the bus says when
+            Ok((_, gst::StateChangeSuccess::Async)) | Ok((gst::StateChangeSuccess::Async, _)) => StartState::Starting,
+            Ok(_) => StartState::Playing,
             Err(e) => {
                 eprintln!("❌ Failed to start pipeline: {}", e);
+                *start_state.lock().unwrap() = StartState::Failed(format!("set_state(PLAYING) failed: {}", e));
                 pipeline_running.store(false, Ordering::SeqCst);
                 return;
             }
+        };
+        info!("Pipeline PLAYING requested: {}", next_state.as_str());
+        {
+            // NO_PREROLL: a live source reports PLAYING via the bus too; don't lose the distinction
+            let mut state = start_state.lock().unwrap();
+            if *state == StartState::Starting {
+                *state = next_state;
+            }
         }
 
         let bus = pipeline.bus().expect("Pipeline has no bus");
-        
-        while Self::handle_gstreamer_bus_message(&bus) {}
+
+        // After a stop request, give the EOS a bounded time to drain through the muxers
+        let mut eos_deadline: Option<Instant> = None;
+        while Self::handle_gstreamer_bus_message(&pipeline, &bus, &start_state, &handlers) {
+            if !pipeline_running.load(Ordering::SeqCst) {
+                let deadline = *eos_deadline.get_or_insert_with(|| Instant::now() + EOS_TIMEOUT);
+                if Instant::now() >= deadline {
+                    warn!("No EOS within {:?} of the stop request, stopping anyway", EOS_TIMEOUT);
+                    break;
+                }
+            }
+        }
 
         pipeline_running.store(false, Ordering::SeqCst);
         info!("Pipeline thread exiting");
     }
 
-    fn handle_gstreamer_bus_message(bus: &gst::Bus) -> bool {
+    fn handle_gstreamer_bus_message(
+        pipeline: &gst::Pipeline,
+        bus: &gst::Bus,
+        start_state: &Mutex<StartState>,
+        handlers: &[ElementMessageHandler],
+    ) -> bool {
         use gst::MessageView;
 
         let msg = bus.timed_pop_filtered(
@@ -182,6 +1131,7 @@ impl RecordingPipeline {
                 gst::MessageType::Error,
                 gst::MessageType::Element,
                 gst::MessageType::Eos,
+                gst::MessageType::StateChanged,
             ],
         );
 
@@ -195,14 +1145,28 @@ impl RecordingPipeline {
                 }
                 MessageView::Error(err) => {
                     eprintln!("Error: {} ({:?})", err.error(), err.debug());
+                    *start_state.lock().unwrap() = StartState::Failed(err.error().to_string());
                     continue_flag = false;
                 }
-                MessageView::Element(element) => {
-                    if let Some(structure) = element.structure() {
-                        if structure.name() == "splitmuxsink-fragment-closed" {
-                            // info!("Fragment closed");
+                MessageView::StateChanged(change) => {
+                    // Only the pipeline's own transition; elements report theirs too
+                    if change.current() == gst::State::Playing
+                        && msg.src().is_some_and(|src| src == pipeline.upcast_ref::<gst::Object>())
+                    {
+                        let mut state = start_state.lock().unwrap();
+                        if matches!(*state, StartState::Starting | StartState::TimedOut) {
+                            if *state == StartState::TimedOut {
+                                warn!("Pipeline reached PLAYING after its start timeout");
+                            }
+                            *state = StartState::Playing;
                         }
                     }
+                }
+                MessageView::Element(..) => {
+                    // e.g. splitmuxsink-fragment-closed; each sink picks out its own
+                    for handler in handlers {
+                        handler(&msg);
+                    }
                     continue_flag = true;
                 }
                 _ => {
@@ -210,7 +1174,65 @@ impl RecordingPipeline {
                 }
             }
         }
-        return continue_flag;
+        continue_flag
+    }
+}
+
+/// Error naming the sink branch if what the tee can produce and what the sink accepts don't overlap.
+fn check_branch_caps(
+    camera_key: &str,
+    sink: &dyn PipelineSink,
+    tee_pad: &gst::Pad,
+    sink_pad: &gst::Pad,
+) -> Result<()> {
+    let upstream_caps = tee_pad.query_caps(None);
+    let downstream_caps = sink_pad.query_caps(None);
+    if !upstream_caps.can_intersect(&downstream_caps) {
+        let sink_name = sink
+            .get_sink_element()
+            .map(|e| e.name().to_string())
+            .unwrap_or_else(|_| "<unknown>".to_string());
+        bail!(
+            "Camera '{}' sink_id={} ({}): source produces {} but the sink accepts {}",
+            camera_key,
+            sink.sink_id(),
+            sink_name,
+            upstream_caps,
+            downstream_caps
+        );
+    }
+    Ok(())
+}
+
+/// Poll `check_ready` until the source's device shows up, for at most `timeout`.
+fn wait_for_source(source: &dyn PipelineSource, camera_key: &str, timeout: Duration) -> Result<()> {
+    let started = Instant::now();
+    let mut last_log: Option<Instant> = None;
+    loop {
+        let Err(e) = source.check_ready() else {
+            return Ok(());
+        };
+        if started.elapsed() >= timeout {
+            bail!("Camera '{}' source not ready after {}s: {:#}", camera_key, timeout.as_secs(), e);
+        }
+        if last_log.is_none_or(|at| at.elapsed() >= Duration::from_secs(1)) {
+            info!("Waiting for camera '{}' source: {:#}", camera_key, e);
+            last_log = Some(Instant::now());
+        }
+        std::thread::sleep(SOURCE_POLL);
+    }
+}
+
+/// Ask upstream encoders for a keyframe (with SPS/PPS headers) as soon as possible.
+/// Sent as the raw `GstForceKeyUnit` structure so we don't need libgstvideo.
+pub fn request_keyframe(element: &gst::Element) {
+    let structure = gst::Structure::builder("GstForceKeyUnit")
+        .field("all-headers", true)
+        .field("count", 0u32)
+        .build();
+    let event = gst::event::CustomUpstream::new(structure);
+    if let Some(src_pad) = element.static_pad("src") {
+        src_pad.send_event(event);
     }
 }
 
@@ -227,6 +1249,115 @@ impl Drop for RecordingPipeline {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::VideoCodec;
+    use crate::pipeline_sinks::hls_pipeline_sink::HlsPipelineSink;
+    use crate::pipeline_sinks::nvr_file_pipeline_sink::NvrFilePipelineSink;
+    use crate::pipeline_sinks::segmented_file_sink::ContainerFormat;
+
+    /// fakesrc -> capsfilter -> tee, using only coreelements
+    struct TestSource {
+        caps: &'static str,
+        format: StreamFormat,
+        tee: Option<gst::Element>,
+        ready: bool,
+    }
+
+    impl PipelineSource for TestSource {
+        fn setup_source(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
+            let src = gst::ElementFactory::make("fakesrc").property("is-live", true).build()?;
+            let capsfilter = gst::ElementFactory::make("capsfilter")
+                .property("caps", self.caps.parse::<gst::Caps>()?)
+                .build()?;
+            let tee = gst::ElementFactory::make("tee").build()?;
+            pipeline.add_many([&src, &capsfilter, &tee])?;
+            gst::Element::link_many([&src, &capsfilter, &tee])?;
+            self.tee = Some(tee);
+            Ok(())
+        }
+
+        fn get_tee(&self) -> Result<gst::Element> {
+            self.tee.clone().context("Tee element not initialized")
+        }
+
+        fn get_source_pad(&self) -> Result<gst::Pad> {
+            self.get_tee()?.static_pad("sink").context("no pad")
+        }
+
+        fn output_format(&self) -> StreamFormat {
+            self.format
+        }
+
+        fn check_ready(&self) -> Result<()> {
+            if !self.ready {
+                bail!("unplugged");
+            }
+            Ok(())
+        }
+    }
+
+    /// capsfilter -> queue -> fakesink; `link_internal = false` leaves the queue dangling.
+    /// The queue matters with several sinks: without one per branch, the tee's
+    /// streaming thread blocks in the first sink's preroll and the rest never get data.
+    struct TestSink {
+        sink_id: i64,
+        caps: &'static str,
+        link_internal: bool,
+        entry: Option<gst::Element>,
+        sink: Option<gst::Element>,
+    }
+
+    impl PipelineSink for TestSink {
+        fn setup_sink(&mut self, pipeline: &gst::Pipeline) -> Result<()> {
+            let entry = gst::ElementFactory::make("capsfilter")
+                .property("caps", self.caps.parse::<gst::Caps>()?)
+                .build()?;
+            let queue = gst::ElementFactory::make("queue").build()?;
+            let sink = gst::ElementFactory::make("fakesink").build()?;
+            pipeline.add_many([&entry, &queue, &sink])?;
+            queue.link(&sink)?;
+            if self.link_internal {
+                entry.link(&queue)?;
+            }
+            self.entry = Some(entry);
+            self.sink = Some(sink);
+            Ok(())
+        }
+
+        fn get_sink_pad(&self) -> Result<gst::Pad> {
+            self.entry.as_ref().context("no entry")?.static_pad("sink").context("no pad")
+        }
+
+        fn get_sink_element(&self) -> Result<gst::Element> {
+            self.sink.clone().context("no sink")
+        }
+
+        fn sink_id(&self) -> i64 {
+            self.sink_id
+        }
+    }
+
+    fn test_pipeline(source_caps: &'static str, sinks: Vec<TestSink>) -> RecordingPipeline {
+        let tmp = std::env::temp_dir().join("dashcam_rs_pipeline_tests");
+        let config = RecordingConfig {
+            recording_dir: tmp.to_string_lossy().to_string(),
+            ..RecordingConfig::default()
+        };
+        let mut pipeline = RecordingPipeline::new(config).unwrap();
+        pipeline.set_source(Box::new(TestSource {
+            caps: source_caps,
+            format: StreamFormat::Encoded(VideoCodec::H264),
+            tee: None,
+            ready: true,
+        }));
+        for sink in sinks {
+            pipeline.add_sink(Box::new(sink), true);
+        }
+        pipeline
+    }
+
+    fn test_sink(sink_id: i64, caps: &'static str, link_internal: bool) -> TestSink {
+        TestSink { sink_id, caps, link_internal, entry: None, sink: None }
+    }
 
     #[test]
     fn test_pipeline_creation() {
@@ -234,4 +1365,279 @@ mod tests {
         let pipeline = RecordingPipeline::new(config);
         assert!(pipeline.is_ok());
     }
+
+    #[test]
+    fn validation_accepts_well_formed_graph() {
+        let mut pipeline = test_pipeline(
+            "video/x-h264",
+            vec![test_sink(0, "video/x-h264", true), test_sink(1, "video/x-h264", true)],
+        );
+        pipeline.build_pipeline().unwrap();
+    }
+
+    #[test]
+    fn restarts_after_stop_reusing_the_graph() {
+        let mut pipeline = test_pipeline("video/x-h264", vec![test_sink(0, "video/x-h264", true)]);
+        pipeline.start_pipeline().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        pipeline.stop_pipeline().unwrap();
+
+        pipeline.start_pipeline().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(pipeline.is_running());
+        pipeline.stop_pipeline().unwrap();
+    }
+
+    #[test]
+    fn start_stop_cycles_release_tee_pads() {
+        let mut pipeline = test_pipeline(
+            "video/x-h264",
+            vec![test_sink(0, "video/x-h264", true), test_sink(1, "video/x-h264", true)],
+        );
+        pipeline.start_pipeline().unwrap();
+        let tee = pipeline.get_source_tee().unwrap();
+        let elements = pipeline.pipeline.children().len();
+        assert_eq!(tee.src_pads().len(), 2);
+
+        for _ in 0..5 {
+            pipeline.stop_pipeline().unwrap();
+            assert_eq!(tee.src_pads().len(), 0, "request pads released on stop");
+            pipeline.start_pipeline().unwrap();
+            assert_eq!(tee.src_pads().len(), 2);
+            assert_eq!(pipeline.pipeline.children().len(), elements);
+        }
+        pipeline.stop_pipeline().unwrap();
+    }
+
+    #[test]
+    fn restarts_after_the_pipeline_failed_on_its_own() {
+        let mut pipeline = test_pipeline("video/x-h264", vec![test_sink(0, "video/x-h264", true)]);
+        pipeline.start_pipeline().unwrap();
+
+        let tee = pipeline.get_source_tee().unwrap();
+        let error = gst::message::Error::builder(gst::CoreError::Failed, "camera unplugged").src(&tee).build();
+        pipeline.pipeline.bus().unwrap().post(error).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pipeline.is_running() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(matches!(pipeline.start_state(), StartState::Failed(_)));
+        assert!(pipeline.is_started(), "thread not reaped yet");
+
+        // error recovery: no stop in between
+        pipeline.start_pipeline().unwrap();
+        assert!(pipeline.start_state().is_playing());
+        pipeline.restart_pipeline().unwrap();
+        assert!(pipeline.start_state().is_playing());
+        pipeline.stop_pipeline().unwrap();
+        pipeline.stop_pipeline().unwrap();
+        assert!(!pipeline.is_started());
+    }
+
+    #[test]
+    fn fails_over_to_the_backup_after_repeated_failures() {
+        let mut pipeline = test_pipeline("video/x-h264", vec![test_sink(0, "video/x-h264", true)]);
+        pipeline.set_source(Box::new(TestSource {
+            caps: "video/x-h264",
+            format: StreamFormat::Encoded(VideoCodec::H264),
+            tee: None,
+            ready: false,
+        }));
+        pipeline.set_source_startup(Duration::ZERO, Some(Duration::from_secs(1)));
+        let backup = TestSource {
+            caps: "video/x-h264",
+            format: StreamFormat::Encoded(VideoCodec::H264),
+            tee: None,
+            ready: true,
+        };
+        pipeline.set_backup_source(Box::new(backup), "test backup".to_string(), 2);
+
+        let err = pipeline.start_pipeline().unwrap_err();
+        assert!(format!("{:#}", err).contains("unplugged"), "{:#}", err);
+        assert!(matches!(pipeline.start_state(), StartState::Failed(_)));
+        assert!(!pipeline.note_start_failure());
+        assert!(pipeline.note_start_failure());
+
+        assert_eq!(pipeline.fail_over().unwrap(), "test backup");
+        assert!(pipeline.is_on_backup() && !pipeline.has_backup_source());
+        pipeline.start_pipeline().unwrap();
+        assert!(pipeline.start_state().is_playing());
+        pipeline.stop_pipeline().unwrap();
+    }
+
+    #[test]
+    fn rebuild_sets_everything_up_again() {
+        let mut pipeline = test_pipeline(
+            "video/x-h264",
+            vec![test_sink(0, "video/x-h264", true), test_sink(1, "video/x-h264", true)],
+        );
+        pipeline.start_pipeline().unwrap();
+        pipeline.set_sink_enabled(1, false).unwrap();
+        let elements = pipeline.pipeline.children().len();
+        let old_tee = pipeline.get_source_tee().unwrap();
+
+        pipeline.rebuild_pipeline().unwrap();
+        assert!(pipeline.pipeline.children().is_empty());
+
+        pipeline.start_pipeline().unwrap();
+        assert_eq!(pipeline.pipeline.children().len(), elements);
+        assert_ne!(pipeline.get_source_tee().unwrap(), old_tee);
+        assert_eq!(pipeline.is_sink_enabled(1), Some(false), "sink switches survive a rebuild");
+        pipeline.stop_pipeline().unwrap();
+    }
+
+    #[test]
+    fn remove_sink_drops_its_branch() {
+        let mut pipeline = test_pipeline(
+            "video/x-h264",
+            vec![test_sink(0, "video/x-h264", true), test_sink(1, "video/x-h264", true)],
+        );
+        pipeline.start_pipeline().unwrap();
+        assert!(pipeline.remove_sink(1).is_err(), "only while stopped");
+        let elements = pipeline.pipeline.children().len();
+        pipeline.stop_pipeline().unwrap();
+
+        pipeline.remove_sink(1).unwrap();
+        // valve + capsfilter + queue + fakesink
+        assert_eq!(pipeline.pipeline.children().len(), elements - 4);
+        assert_eq!(pipeline.is_sink_enabled(1), None);
+
+        pipeline.start_pipeline().unwrap();
+        assert_eq!(pipeline.get_source_tee().unwrap().src_pads().len(), 1);
+        pipeline.stop_pipeline().unwrap();
+    }
+
+    #[test]
+    fn triggered_sink_gets_a_pre_roll_queue_and_arms_live() {
+        let mut pipeline = test_pipeline(
+            "video/x-h264",
+            vec![test_sink(0, "video/x-h264", true), test_sink(1, "video/x-h264", true)],
+        );
+        pipeline.set_sink_triggered(1, Duration::from_secs(5), Duration::from_secs(30)).unwrap();
+        assert!(pipeline.is_sink_armed(0));
+        assert!(!pipeline.is_sink_armed(1));
+
+        pipeline.start_pipeline().unwrap();
+        assert!(
+            pipeline.set_sink_triggered(0, Duration::from_secs(5), Duration::from_secs(30)).is_err(),
+            "only before the first start"
+        );
+        assert!(pipeline.pipeline.by_name("sink_preroll_1").is_some());
+        assert!(pipeline.pipeline.by_name("sink_preroll_0").is_none());
+
+        let now = Instant::now();
+        pipeline.trigger(now);
+        assert!(pipeline.is_sink_armed(1));
+        pipeline.expire_triggers(now + Duration::from_secs(30));
+        assert!(!pipeline.is_sink_armed(1));
+        // a disarmed, blocked queue mustn't hold up the stop
+        pipeline.restart_pipeline().unwrap();
+        assert!(pipeline.start_state().is_playing());
+        pipeline.stop_pipeline().unwrap();
+
+        pipeline.remove_sink(1).unwrap();
+        assert!(pipeline.pipeline.by_name("sink_preroll_1").is_none());
+        assert!(pipeline.is_sink_armed(1));
+    }
+
+    #[test]
+    fn start_waits_for_playing_and_reports_live_sources() {
+        let mut pipeline = test_pipeline("video/x-h264", vec![test_sink(0, "video/x-h264", true)]);
+        assert_eq!(pipeline.start_state(), StartState::Stopped);
+        pipeline.start_pipeline().unwrap();
+        // fakesrc is-live=true: PLAYING without preroll
+        assert_eq!(pipeline.start_state(), StartState::PlayingLive);
+        pipeline.stop_pipeline().unwrap();
+        assert_eq!(pipeline.start_state(), StartState::Stopped);
+    }
+
+    /// Long-running: `cargo test -- --ignored soak`
+    #[test]
+    #[ignore]
+    fn soak_restarts_do_not_leak() {
+        use crate::soak::{SoakLimits, run_soak};
+
+        let sinks = vec![test_sink(0, "video/x-h264", true), test_sink(1, "video/x-h264", true)];
+        let mut pipelines = vec![test_pipeline("video/x-h264", sinks)];
+        let report = run_soak(&mut pipelines, 300, Duration::from_millis(20)).unwrap();
+        let problems = report.problems(&SoakLimits::default());
+        assert!(problems.is_empty(), "{}{}", report, problems.join("\n"));
+    }
+
+    #[test]
+    fn waiter_times_out_while_still_async() {
+        let state = Arc::new(Mutex::new(StartState::Starting));
+        let waiter = StartWaiter { camera_key: "cam".to_string(), state: state.clone() };
+        let err = waiter.wait(Duration::from_millis(100)).unwrap_err().to_string();
+        assert!(err.contains("didn't reach PLAYING"), "{}", err);
+        assert_eq!(*state.lock().unwrap(), StartState::TimedOut);
+
+        *state.lock().unwrap() = StartState::Failed("no such device".to_string());
+        assert!(waiter.wait(Duration::from_secs(1)).unwrap_err().to_string().contains("no such device"));
+    }
+
+    #[test]
+    fn validation_names_branch_with_incompatible_caps() {
+        let mut pipeline = test_pipeline(
+            "video/x-h264",
+            vec![test_sink(0, "video/x-h264", true), test_sink(7, "audio/x-raw", true)],
+        );
+        let err = pipeline.build_pipeline().unwrap_err().to_string();
+        assert!(err.contains("sink_id=7"), "{}", err);
+        assert!(err.contains("audio/x-raw"), "{}", err);
+    }
+
+    #[test]
+    fn validation_reports_unlinked_pads() {
+        let mut pipeline = test_pipeline("video/x-h264", vec![test_sink(0, "video/x-h264", false)]);
+        let err = pipeline.build_pipeline().unwrap_err().to_string();
+        assert!(err.contains("is not linked"), "{}", err);
+    }
+
+    #[test]
+    fn build_rejects_sink_that_cannot_take_source_format() {
+        let mut pipeline = RecordingPipeline::new(RecordingConfig::default()).unwrap();
+        pipeline.set_source(Box::new(TestSource {
+            caps: "video/x-h265",
+            format: StreamFormat::Encoded(VideoCodec::H265),
+            tee: None,
+            ready: true,
+        }));
+        pipeline.add_sink(Box::new(HlsPipelineSink::new(RecordingConfig::default(), 3)), true);
+
+        let err = pipeline.build_pipeline().unwrap_err().to_string();
+        assert!(err.contains("sink_id=3"), "{}", err);
+        assert!(err.contains("H.265"), "{}", err);
+    }
+
+    #[test]
+    fn build_rejects_av1_into_ts() {
+        let mut pipeline = RecordingPipeline::new(RecordingConfig::default()).unwrap();
+        pipeline.set_source(Box::new(TestSource {
+            caps: "video/x-av1",
+            format: StreamFormat::Encoded(VideoCodec::Av1),
+            tee: None,
+            ready: true,
+        }));
+        pipeline.add_sink(
+            Box::new(NvrFilePipelineSink::new(RecordingConfig::default(), 4, ContainerFormat::Ts)),
+            true,
+        );
+
+        let err = pipeline.build_pipeline().unwrap_err().to_string();
+        assert!(err.contains("sink_id=4"), "{}", err);
+        assert!(err.contains("AV1"), "{}", err);
+    }
+
+    #[test]
+    fn frame_tap_gets_next_free_sink_id() {
+        let mut pipeline = test_pipeline(
+            "video/x-h264",
+            vec![test_sink(0, "video/x-h264", true), test_sink(5, "video/x-h264", true)],
+        );
+        let (target, _rx) = FrameTarget::channel(1);
+        let sink_id = pipeline.add_frame_tap(FrameTapConfig::default(), target).unwrap();
+        assert_eq!(sink_id, 6);
+        assert_eq!(pipeline.is_sink_enabled(6), Some(true));
+    }
 }
@@ -1,57 +1,52 @@
-use anyhow::{anyhow, Result};
-use crate::db::db::{DashcamDb };
-use crate::db::db_worker::{DBMessage,DBWorker,start_db_worker};
+use anyhow::{anyhow, Context, Result};
+use crate::db::db_facade::DbFacade;
+use crate::db::db_worker::DBMessage;
+use crate::pipeline_sinks::burst_pipeline_sink::BurstPipelineSink;
 use crate::pipeline_sinks::hls_pipeline_sink::HlsPipelineSink;
+use crate::pipeline_sinks::nvr_file_pipeline_sink::NvrFilePipelineSink;
+use crate::pipeline_sinks::segment_counters::DbSegmentCounters;
+use crate::pipeline_sinks::segmented_file_sink::ContainerFormat;
+use crate::pipeline_sinks::throughput::ThroughputMonitor;
+use crate::pipeline_sinks::timelapse_pipeline_sink::TimelapsePipelineSink;
+use crate::pipeline_sinks::transcode_pipeline_sink::TranscodePipelineSink;
 use crate::pipeline_sinks::ts_file_pipeline_sink::TsFilePipelineSink;
 use crate::pipeline_sinks::pipeline_sink::PipelineSink;
 use crate::pipeline_sources::v4l2_pipeline_source::V4l2PipelineSource;
 use crate::pipeline_sources::libcamera_pipeline_source::LibcameraPipelineSource;
-use crate::pipeline_sources::pipeline_source::PipelineSource;
+use crate::pipeline_sources::rtsp_pipeline_source::RtspPipelineSource;
+use crate::pipeline_sources::test_pipeline_source::TestPipelineSource;
+use crate::pipeline_sources::pipeline_source::{PipelineSource, StreamFormat};
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::sync::mpsc;
 use std::sync::mpsc::Sender;
+use std::time::Duration;
+use tracing::info;
 
-use crate::config::{AppConfig, CameraConfig, GlobalConfig, SourceKind, SinkConfig, CameraRole};
+use crate::config::{AppConfig, CameraConfig, SourceConfig, SourceKind, SinkConfig, CameraRole, VideoCodec};
+use crate::modes;
+use crate::profiles::Profile;
 use crate::recording_pipeline::{RecordingConfig, RecordingPipeline};
 
 
-fn get_camera_id_for_camera(
-    cam: &CameraConfig,
-    db_sender: &Arc<Sender<DBMessage>>,
-) -> Result<i64> {
-    let (tx, rx) = mpsc::channel();
-
-    db_sender.send(DBMessage::GetCameraIdByKey {
+/// Build a RecordingConfig for a specific camera.
+///
+/// - recording_dir: recording_root / camera.key
+/// - video_*: from the camera if set, otherwise from the active profile.
+/// - bitrate and encoder tuning: from the camera's `encoding`.
+fn build_recording_config(app_cfg: &AppConfig, cam: &CameraConfig) -> RecordingConfig {
+    // Base from the active profile, then override
+    let mut cfg = RecordingConfig {
         camera_key: cam.key.clone(),
-        reply: tx,
-    })?;
+        ..RecordingConfig::from_profile(&app_cfg.profile())
+    };
 
-    match rx.recv() {
-        Ok(Some(id)) => Ok(id),
-        Ok(None) => Err(anyhow!(
-            "DBWorker could not find camera_id for key '{}'",
-            cam.key
-        )),
-        Err(e) => Err(anyhow!(
-            "DBWorker channel closed while waiting for camera_id for key '{}': {}",
-            cam.key,
-            e
-        )),
+    if let Some(simulation) = &app_cfg.simulation {
+        cfg.time_scale = simulation.time_scale;
     }
-}
-
-
-/// Build a RecordingConfig for a specific camera.
-///
-/// - recording_dir: global.recording_root / camera.key
-/// - video_*: from global if set, otherwise from constants.
-fn build_recording_config(global: &GlobalConfig, cam: &CameraConfig) -> RecordingConfig {
-    // Base from Default/Constants, then override
-    let mut cfg = RecordingConfig::default();
+    cfg.staging = app_cfg.staging.clone();
 
     // put recordings per-camera under recording_root/key
-    let mut dir = PathBuf::from(&global.recording_root);
+    let mut dir = PathBuf::from(app_cfg.recording_root());
     dir.push(&cam.key);
     cfg.recording_dir = dir.to_string_lossy().to_string();
 
@@ -64,95 +59,179 @@ fn build_recording_config(global: &GlobalConfig, cam: &CameraConfig) -> Recordin
     if let Some(fps) = cam.video_framerate {
         cfg.frame_rate = fps as i32;
     }
+    if let Some(kbps) = cam.encoding.bitrate_kbps {
+        cfg.bitrate_kbps = kbps;
+    }
+    cfg.speed_preset = cam.encoding.speed_preset.clone();
+    cfg.tune = cam.encoding.tune.clone();
+    cfg.keyframe_interval = cam.encoding.keyframe_interval;
+    cfg.processing = cam.processing.clone();
+    cfg.overlay_gps = cam.overlay_gps;
+    cfg.audio = cam.audio.clone();
+    cfg.spare_encodings = modes::spare_encodings(&app_cfg.modes, cfg.encoding());
 
     // segment duration comes from sinks:
-    // Pick dashcam_ts duration if present, else NvrTs, else Hls, else default.
-    if let Some(dash_ts) = cam.sinks.iter().find_map(|s| {
-        if let SinkConfig::DashcamTs { segment_duration_sec, .. } = s {
-            Some(*segment_duration_sec)
-        } else {
-            None
-        }
-    }) {
-        cfg.video_duration = dash_ts;
-    } else if let Some(nvr_ts) = cam.sinks.iter().find_map(|s| {
-        if let SinkConfig::NvrTs { segment_duration_sec, .. } = s {
-            Some(*segment_duration_sec)
-        } else {
-            None
-        }
-    }) {
-        cfg.video_duration = nvr_ts;
-    } else if let Some(hls) = cam.sinks.iter().find_map(|s| {
-        if let SinkConfig::Hls { segment_duration_sec, .. } = s {
-            Some(*segment_duration_sec)
-        } else {
-            None
-        }
-    }) {
-        cfg.video_duration = hls;
-    } else {
-        // fallback to whatever RecordingConfig::default() gave us
-        // cfg.video_duration already set
+    // Pick dashcam_ts duration if present, else NvrTs, else Mp4/Mkv, else Hls, else default.
+    let by_priority = |s: &&SinkConfig| match s {
+        SinkConfig::DashcamTs { .. } => 0,
+        SinkConfig::NvrTs { .. } => 1,
+        SinkConfig::Mp4 { .. } | SinkConfig::Mkv { .. } => 2,
+        SinkConfig::Hls { .. } => 3,
+        SinkConfig::Timelapse { .. } | SinkConfig::Burst { .. } => 4,
+    };
+    let mut candidates: Vec<&SinkConfig> = cam.sinks.iter().collect();
+    candidates.sort_by_key(by_priority);
+    if let Some(duration) = candidates.iter().find_map(|s| s.segment_duration_sec()) {
+        cfg.video_duration = duration;
     }
+    // otherwise keep whatever the profile gave us
 
     cfg
 }
 
-/// Build a PipelineSource from a camera's source config.
-fn build_source_for_camera(
-    cam: &CameraConfig,
+/// Build a PipelineSource from a camera's (primary or backup) source config.
+fn build_source(
+    source_cfg: &SourceConfig,
     rec_cfg: &RecordingConfig,
 ) -> Result<Box<dyn PipelineSource>> {
-    match cam.source.kind {
+    // a backup source may encode with something else than the primary
+    let rec_cfg = &RecordingConfig { encoder: source_cfg.encoder, ..rec_cfg.clone() };
+    match source_cfg.kind {
         SourceKind::Libcamera => {
-            Ok(Box::new(LibcameraPipelineSource::new(rec_cfg.clone())))
+            Ok(Box::new(LibcameraPipelineSource::new(rec_cfg.clone()).with_codec(source_cfg.codec)))
         }
         SourceKind::V4l2 => {
-            Ok(Box::new(V4l2PipelineSource::new(rec_cfg.clone(), cam.source.device.clone())))
+            let source = V4l2PipelineSource::new(rec_cfg.clone(), source_cfg.device.clone());
+            let source = if source_cfg.passthrough {
+                source.with_passthrough(source_cfg.codec)
+            } else {
+                source.with_codec(source_cfg.codec)
+            };
+            Ok(Box::new(source))
         }
         SourceKind::Rtsp => {
-            // TODO: implement an RtspPipelineSource
-            Err(anyhow!("Rtsp source not implemented yet"))
+            let url = source_cfg.rtsp_url.clone().context("rtsp source without rtsp_url")?;
+            Ok(Box::new(RtspPipelineSource::new(url, source_cfg.codec)))
         }
     }
 }
 
+/// Codec the source puts on the tee, for sinks that decode it themselves.
+fn source_codec(cam: &CameraConfig, sink_id: i64, source_format: StreamFormat) -> Result<VideoCodec> {
+    match source_format {
+        StreamFormat::Encoded(codec) => Ok(codec),
+        StreamFormat::Raw => Err(anyhow!(
+            "Camera '{}' sink_id={} needs an encoded source, got {}",
+            cam.key,
+            sink_id,
+            source_format
+        )),
+    }
+}
+
+/// Build every configured sink, paired with its effective enabled state.
+/// Disabled sinks are still built so they can be switched on at runtime.
+/// `source_format` is what the camera's source puts on the tee; sinks that opted
+/// in to transcoding get a decode/encode branch when it doesn't match. File
+/// sinks report their write throughput to `throughput`, if given.
 fn build_sinks_for_camera(
     cam: &CameraConfig,
     rec_cfg: &RecordingConfig,
-    db_sender: Arc<Sender<DBMessage>>,
-) -> Result<Vec<Box<dyn PipelineSink>>> {
-    let mut sinks: Vec<Box<dyn PipelineSink>> = Vec::new();
-
-    // Resolve camera_id once per camera through DBWorker
-    let camera_id = get_camera_id_for_camera(cam, &db_sender)?;
+    profile: &Profile,
+    source_format: StreamFormat,
+    throughput: Option<&ThroughputMonitor>,
+    db: &DbFacade,
+) -> Result<Vec<(Box<dyn PipelineSink>, bool)>> {
+    let mut sinks: Vec<(Box<dyn PipelineSink>, bool)> = Vec::new();
 
     for sink_cfg in &cam.sinks {
+        let enabled = db.sink_enabled(cam, sink_cfg)?;
+
         match sink_cfg {
             SinkConfig::DashcamTs {
                 max_segments,
-                segment_duration_sec: _,
-                sink_id
+                sink_id,
+                muxer,
+                write_mode,
+                generation_names,
+                ..
             } => {
-                // TsFilePipelineSink now needs camera_id and max_segments
                 let ts_sink = TsFilePipelineSink::new(
                     rec_cfg.clone(),
-                    camera_id,
                     *sink_id,
-                    *max_segments,
-                    db_sender.clone(),
-                )?;
-                sinks.push(Box::new(ts_sink) as Box<dyn PipelineSink>);
+                    max_segments.unwrap_or(profile.segments_to_keep),
+                    DbSegmentCounters::new(db.sink(cam, *sink_id)?),
+                )
+                .with_muxer(muxer.clone())
+                .with_write_mode(*write_mode)
+                .with_throughput(throughput.cloned())
+                .with_generation_names(*generation_names);
+                sinks.push((Box::new(ts_sink) as Box<dyn PipelineSink>, enabled));
+            }
+
+            SinkConfig::Hls { sink_id, transcode, .. } => {
+                let hls_sink: Box<dyn PipelineSink> = Box::new(HlsPipelineSink::new(rec_cfg.clone(), *sink_id));
+                let hls_sink = match source_format {
+                    StreamFormat::Encoded(codec) if *transcode && !hls_sink.accepts(source_format) => {
+                        info!(
+                            "Camera '{}' sink_id={}: transcoding {} to H.264 for HLS",
+                            cam.key, sink_id, source_format
+                        );
+                        Box::new(TranscodePipelineSink::new(
+                            rec_cfg.clone(),
+                            hls_sink,
+                            codec,
+                            VideoCodec::H264,
+                        ))
+                    }
+                    _ => hls_sink,
+                };
+                sinks.push((hls_sink, enabled));
+            }
+
+            SinkConfig::NvrTs { sink_id, muxer, write_mode, disks, .. } => {
+                let nvr_sink = match disks {
+                    Some(disks) => NvrFilePipelineSink::spanning(
+                        rec_cfg.clone(),
+                        *sink_id,
+                        disks.clone(),
+                        DbSegmentCounters::new(db.sink(cam, *sink_id)?),
+                    ),
+                    None => NvrFilePipelineSink::new(rec_cfg.clone(), *sink_id, ContainerFormat::Ts),
+                };
+                let nvr_sink = nvr_sink
+                    .with_muxer(muxer.clone())
+                    .with_write_mode(*write_mode)
+                    .with_throughput(throughput.cloned());
+                sinks.push((Box::new(nvr_sink) as Box<dyn PipelineSink>, enabled));
             }
 
-            SinkConfig::Hls { segment_duration_sec: _ , sink_id: _} => {
-                let hls_sink = HlsPipelineSink::new(rec_cfg.clone());
-                sinks.push(Box::new(hls_sink) as Box<dyn PipelineSink>);
+            SinkConfig::Mp4 { sink_id, muxer, write_mode, .. } => {
+                let mp4_sink = NvrFilePipelineSink::new(rec_cfg.clone(), *sink_id, ContainerFormat::Mp4)
+                    .with_muxer(muxer.clone())
+                    .with_write_mode(*write_mode)
+                    .with_throughput(throughput.cloned());
+                sinks.push((Box::new(mp4_sink) as Box<dyn PipelineSink>, enabled));
             }
 
-            SinkConfig::NvrTs { segment_duration_sec: _ , sink_id: _} => {
-                return Err(anyhow!("NvrTs sink not implemented yet"));
+            SinkConfig::Mkv { sink_id, muxer, write_mode, .. } => {
+                let mkv_sink = NvrFilePipelineSink::new(rec_cfg.clone(), *sink_id, ContainerFormat::Mkv)
+                    .with_muxer(muxer.clone())
+                    .with_write_mode(*write_mode)
+                    .with_throughput(throughput.cloned());
+                sinks.push((Box::new(mkv_sink) as Box<dyn PipelineSink>, enabled));
+            }
+
+            SinkConfig::Timelapse { interval_sec, sink_id, .. } => {
+                let codec = source_codec(cam, *sink_id, source_format)?;
+                let timelapse_sink = TimelapsePipelineSink::new(rec_cfg.clone(), *sink_id, *interval_sec, codec);
+                sinks.push((Box::new(timelapse_sink) as Box<dyn PipelineSink>, enabled));
+            }
+
+            SinkConfig::Burst { frames, window_sec, sink_id, .. } => {
+                let codec = source_codec(cam, *sink_id, source_format)?;
+                let burst_sink = BurstPipelineSink::new(rec_cfg.clone(), *sink_id, *frames, *window_sec, codec);
+                sinks.push((Box::new(burst_sink) as Box<dyn PipelineSink>, enabled));
             }
         }
     }
@@ -170,9 +249,9 @@ fn build_sinks_for_camera(
 
 /// Build a single RecordingPipeline for a camera.
 pub fn build_pipeline_for_camera(
-    global: &GlobalConfig,
+    app_cfg: &AppConfig,
     cam: &CameraConfig,
-    db_sender: Arc<Sender<DBMessage>>,
+    db: &DbFacade,
 ) -> Result<RecordingPipeline> {
     if !cam.enabled {
         return Err(anyhow!("Camera '{}' is disabled", cam.key));
@@ -185,38 +264,99 @@ pub fn build_pipeline_for_camera(
         }
     }
 
-    let rec_cfg = build_recording_config(global, cam);
+    let rec_cfg = build_recording_config(app_cfg, cam);
 
     // Create the RecordingPipeline
     let mut pipeline = RecordingPipeline::new(rec_cfg.clone())?;
+    pipeline.set_start_timeout(app_cfg.camera_start_timeout());
+    pipeline.set_source_startup(
+        Duration::from_secs(cam.source.startup_timeout_sec),
+        (cam.source.retry_interval_sec > 0).then(|| Duration::from_secs(cam.source.retry_interval_sec)),
+    );
 
     // Source
-    let source = build_source_for_camera(cam, &rec_cfg)?;
+    let source: Box<dyn PipelineSource> = if app_cfg.simulation.is_some() {
+        Box::new(TestPipelineSource::new(rec_cfg.clone(), cam.source.codec))
+    } else {
+        build_source(&cam.source, &rec_cfg)?
+    };
+    let source_format = source.output_format();
     pipeline.set_source(source);
+    if let Some(failover) = &cam.failover {
+        pipeline.set_backup_source(
+            build_source(&failover.source, &rec_cfg)?,
+            failover.source.describe(),
+            failover.after_failures,
+        );
+    }
 
-    // Sinks
-    let sinks = build_sinks_for_camera(cam, &rec_cfg, db_sender)?;
-    for sink in sinks {
-        pipeline.add_sink(sink);
+    // Sinks, their write throughput watched against the bitrate of a source that encodes
+    let encodes = app_cfg.simulation.is_some() || !cam.source.delivers_encoded();
+    let throughput = (encodes && app_cfg.throughput.enabled())
+        .then(|| ThroughputMonitor::new(&cam.key, rec_cfg.bitrate_kbps, app_cfg.throughput.clone()));
+    let sinks = build_sinks_for_camera(cam, &rec_cfg, &app_cfg.profile(), source_format, throughput.as_ref(), db)?;
+    for (sink, enabled) in sinks {
+        pipeline.add_sink(sink, enabled);
+    }
+    if let Some(monitor) = throughput {
+        pipeline.set_throughput_monitor(monitor);
     }
 
     Ok(pipeline)
 }
 
+/// Pipeline for `dashcam self-test`: the camera's real source/encoder, recording a
+/// single `duration_sec` segment into `dir`. No DB involved.
+pub fn build_self_test_pipeline(
+    app_cfg: &AppConfig,
+    cam: &CameraConfig,
+    dir: &std::path::Path,
+    duration_sec: u64,
+) -> Result<RecordingPipeline> {
+    let rec_cfg = RecordingConfig {
+        recording_dir: dir.to_string_lossy().to_string(),
+        video_duration: duration_sec,
+        spare_encodings: Vec::new(),
+        staging: None,
+        ..build_recording_config(app_cfg, cam)
+    };
+    let mut pipeline = RecordingPipeline::new(rec_cfg.clone())?;
+
+    let source = build_source(&cam.source, &rec_cfg)?;
+    let container = match source.output_format() {
+        StreamFormat::Encoded(codec) if !ContainerFormat::Ts.supports(codec) => ContainerFormat::Mp4,
+        _ => ContainerFormat::Ts,
+    };
+    pipeline.set_source(source);
+    pipeline.add_sink(Box::new(NvrFilePipelineSink::new(rec_cfg, 0, container)), true);
+    Ok(pipeline)
+}
+
 /// Helper: build all pipelines for all enabled cameras in AppConfig.
 pub fn build_pipelines_from_config(
     cfg: &AppConfig,
     db_sender: Arc<Sender<DBMessage>>,
 ) -> Result<Vec<RecordingPipeline>> {
-    let mut pipelines = Vec::new();
+    let db = DbFacade::new(db_sender);
+    db.prefetch(cfg)?;
 
-    for cam in &cfg.cameras {
-        if !cam.enabled {
-            continue;
-        }
-        let p = build_pipeline_for_camera(&cfg.global, cam, db_sender.clone())?;
-        pipelines.push(p);
-    }
+    // One thread per camera: a source that is slow to set up doesn't hold up the rest
+    std::thread::scope(|scope| {
+        let db = &db;
+        let builds: Vec<_> = cfg
+            .cameras
+            .iter()
+            .filter(|cam| cam.enabled)
+            .map(|cam| (cam, scope.spawn(move || build_pipeline_for_camera(cfg, cam, db))))
+            .collect();
 
-    Ok(pipelines)
+        builds
+            .into_iter()
+            .map(|(cam, build)| {
+                build
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow!("Building the pipeline for camera '{}' panicked", cam.key)))
+            })
+            .collect()
+    })
 }
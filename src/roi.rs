@@ -0,0 +1,174 @@
+//! Per-camera regions of interest for motion and object detection.
+//!
+//! Polygons use normalized coordinates (0..1, origin top-left), so they survive
+//! resolution changes. With no `include` polygons the whole frame counts.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RoiMode {
+    #[default]
+    Include,
+    Exclude,
+}
+
+/// `[[cameras.roi]]` in config.toml.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct RoiConfig {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub mode: RoiMode,
+    /// `[[x, y], ...]`, at least 3 points
+    pub points: Vec<[f32; 2]>,
+}
+
+impl RoiConfig {
+    pub fn is_valid(&self) -> bool {
+        self.points.len() >= 3
+            && self
+                .points
+                .iter()
+                .all(|[x, y]| (0.0..=1.0).contains(x) && (0.0..=1.0).contains(y))
+    }
+}
+
+/// Effective region for one camera.
+#[derive(Debug, Clone, Default)]
+pub struct Roi {
+    include: Vec<Vec<[f32; 2]>>,
+    exclude: Vec<Vec<[f32; 2]>>,
+}
+
+/// Even-odd ray cast.
+fn polygon_contains(polygon: &[[f32; 2]], x: f32, y: f32) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+    for i in 0..polygon.len() {
+        let [xi, yi] = polygon[i];
+        let [xj, yj] = polygon[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+impl Roi {
+    pub fn from_config(regions: &[RoiConfig]) -> Self {
+        let mut roi = Roi::default();
+        for region in regions {
+            match region.mode {
+                RoiMode::Include => roi.include.push(region.points.clone()),
+                RoiMode::Exclude => roi.exclude.push(region.points.clone()),
+            }
+        }
+        roi
+    }
+
+    /// True if nothing is configured, i.e. the whole frame is watched.
+    pub fn is_full_frame(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether a normalized point is watched.
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|p| polygon_contains(p, x, y));
+        included && !self.exclude.iter().any(|p| polygon_contains(p, x, y))
+    }
+
+    /// Row-major mask, 255 = watched, 0 = ignored. Sampled at pixel centers.
+    pub fn mask(&self, width: u32, height: u32) -> Vec<u8> {
+        let mut mask = Vec::with_capacity((width * height) as usize);
+        for py in 0..height {
+            for px in 0..width {
+                let x = (px as f32 + 0.5) / width as f32;
+                let y = (py as f32 + 0.5) / height as f32;
+                mask.push(if self.contains(x, y) { 255 } else { 0 });
+            }
+        }
+        mask
+    }
+
+    /// Coarse text rendering for the log: `#` watched, `.` ignored.
+    pub fn ascii(&self, cols: u32, rows: u32) -> String {
+        self.mask(cols, rows)
+            .chunks(cols as usize)
+            .map(|row| row.iter().map(|&v| if v > 0 { '#' } else { '.' }).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Write the mask as a binary PGM so a UI can overlay it on the live view.
+    pub fn write_pgm(&self, path: &Path, width: u32, height: u32) -> Result<()> {
+        let mut data = format!("P5\n{} {}\n255\n", width, height).into_bytes();
+        data.extend(self.mask(width, height));
+        fs::write(path, data).with_context(|| format!("Failed to write ROI mask {}", path.display()))
+    }
+
+    /// Log the effective mask and export it next to the camera's recordings.
+    pub fn log_and_export(&self, camera_key: &str, dir: &Path, width: u32, height: u32) -> Result<()> {
+        if self.is_full_frame() {
+            info!("Camera '{}' ROI: full frame", camera_key);
+            return Ok(());
+        }
+        info!(
+            "Camera '{}' ROI ({} include, {} exclude):\n{}",
+            camera_key,
+            self.include.len(),
+            self.exclude.len(),
+            self.ascii(48, 18)
+        );
+        fs::create_dir_all(dir)?;
+        self.write_pgm(&dir.join("roi_mask.pgm"), width, height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(mode: RoiMode, points: &[[f32; 2]]) -> RoiConfig {
+        RoiConfig { name: String::new(), mode, points: points.to_vec() }
+    }
+
+    #[test]
+    fn empty_roi_watches_everything() {
+        let roi = Roi::from_config(&[]);
+        assert!(roi.is_full_frame());
+        assert!(roi.contains(0.0, 0.0));
+        assert!(roi.contains(0.99, 0.99));
+    }
+
+    #[test]
+    fn exclude_cuts_out_of_include() {
+        // Watch the bottom half (driveway), but not the strip of road at the bottom-left
+        let roi = Roi::from_config(&[
+            region(RoiMode::Include, &[[0.0, 0.5], [1.0, 0.5], [1.0, 1.0], [0.0, 1.0]]),
+            region(RoiMode::Exclude, &[[0.0, 0.8], [0.3, 0.8], [0.3, 1.0], [0.0, 1.0]]),
+        ]);
+        assert!(!roi.contains(0.5, 0.2), "top half is ignored");
+        assert!(roi.contains(0.5, 0.7));
+        assert!(!roi.contains(0.1, 0.9), "excluded strip");
+        assert!(roi.contains(0.6, 0.9));
+    }
+
+    #[test]
+    fn mask_matches_contains() {
+        let roi = Roi::from_config(&[region(RoiMode::Include, &[[0.0, 0.0], [0.5, 0.0], [0.5, 1.0], [0.0, 1.0]])]);
+        assert_eq!(roi.ascii(4, 2), "##..\n##..");
+    }
+
+    #[test]
+    fn validates_points() {
+        assert!(!region(RoiMode::Include, &[[0.0, 0.0], [1.0, 1.0]]).is_valid());
+        assert!(!region(RoiMode::Include, &[[0.0, 0.0], [1.5, 0.0], [1.0, 1.0]]).is_valid());
+        assert!(region(RoiMode::Include, &[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0]]).is_valid());
+    }
+}
@@ -0,0 +1,233 @@
+//! Daily rollup (`[rollup]` in config.toml).
+//!
+//! Once a day the previous day's ring segments of the selected cameras are
+//! remuxed (no re-encode) into hour-long `.mkv` files on secondary storage,
+//! before the ring wraps around and overwrites them. The ring keeps giving
+//! quick dashcam-style access to the last hours; the archive keeps the days.
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, Timelike};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::VideoCodec;
+use crate::legacy_import::{LegacySegment, scan_legacy_dir};
+use crate::pipeline_sources::encoder::parser_factory;
+use crate::worker::Worker;
+
+/// An hour of segments at the usual 2-60s lengths remuxes in well under this
+const REMUX_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RollupConfig {
+    /// Secondary storage; files go to `<archive_dir>/<camera>/<YYYY-MM-DD>/<HH>.mkv`
+    pub archive_dir: String,
+    /// Camera keys to roll up; empty means all
+    #[serde(default)]
+    pub cameras: Vec<String>,
+    /// Local time of day to run, "HH:MM"
+    #[serde(default = "default_run_at")]
+    pub run_at: String,
+}
+
+fn default_run_at() -> String {
+    "03:00".to_string()
+}
+
+impl RollupConfig {
+    pub fn run_at_time(&self) -> Option<NaiveTime> {
+        NaiveTime::parse_from_str(&self.run_at, "%H:%M").ok()
+    }
+
+    pub fn includes(&self, camera_key: &str) -> bool {
+        self.cameras.is_empty() || self.cameras.iter().any(|c| c == camera_key)
+    }
+}
+
+/// One camera's view for the worker.
+#[derive(Debug, Clone)]
+pub struct RolledCamera {
+    pub key: String,
+    /// Where the camera's `dashcamts` ring lives
+    pub ring_dir: PathBuf,
+    pub codec: VideoCodec,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RollupSummary {
+    pub hours_written: usize,
+    /// Archive file already there from an earlier run
+    pub hours_skipped: usize,
+    pub segments: usize,
+}
+
+/// Ring segments that finished on `day`, by local hour, oldest first.
+pub fn segments_by_hour(segments: &[LegacySegment], day: NaiveDate) -> BTreeMap<u32, Vec<PathBuf>> {
+    let mut hours: BTreeMap<u32, Vec<PathBuf>> = BTreeMap::new();
+    for segment in segments {
+        let ended = DateTime::<Local>::from(segment.modified);
+        if ended.date_naive() == day {
+            hours.entry(ended.hour()).or_default().push(segment.path.clone());
+        }
+    }
+    hours
+}
+
+/// `gst-launch` line that demuxes each file, joins them with `concat` and muxes
+/// the result into one Matroska file. Going through the demuxer rebuilds the TS
+/// timing instead of gluing files together byte by byte.
+pub fn remux_description(files: &[PathBuf], codec: VideoCodec, output: &Path) -> String {
+    let parser = parser_factory(codec);
+    let mut line = format!(
+        "concat name=c ! {} ! matroskamux ! filesink location=\"{}\"",
+        parser,
+        output.display()
+    );
+    for file in files {
+        line.push_str(&format!(" filesrc location=\"{}\" ! tsdemux ! {} ! c.", file.display(), parser));
+    }
+    line
+}
+
+/// Remux `files` into `output`, via a `.part` file so a half-written hour is never
+/// mistaken for a finished one.
+pub fn remux_files(files: &[PathBuf], codec: VideoCodec, output: &Path) -> Result<()> {
+    if files.is_empty() {
+        bail!("Nothing to remux into {}", output.display());
+    }
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let partial = output.with_extension("mkv.part");
+    let pipeline = gst::parse::launch(&remux_description(files, codec, &partial))?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Not a pipeline"))?;
+    let bus = pipeline.bus().context("No bus")?;
+
+    pipeline.set_state(gst::State::Playing)?;
+    let msg = bus.timed_pop_filtered(
+        gst::ClockTime::from_seconds(REMUX_TIMEOUT.as_secs()),
+        &[gst::MessageType::Eos, gst::MessageType::Error],
+    );
+    pipeline.set_state(gst::State::Null)?;
+    match msg.as_ref().map(|m| m.view()) {
+        Some(gst::MessageView::Eos(..)) => {}
+        Some(gst::MessageView::Error(err)) => {
+            let _ = fs::remove_file(&partial);
+            bail!("Remux into {} failed: {}", output.display(), err.error());
+        }
+        _ => {
+            let _ = fs::remove_file(&partial);
+            bail!("Remux into {} timed out", output.display());
+        }
+    }
+    fs::rename(&partial, output).with_context(|| format!("Failed to move {} into place", output.display()))?;
+    Ok(())
+}
+
+/// Roll up `day` for one camera. Hours already in the archive are left alone,
+/// so a rerun after a crash only does what's missing.
+pub fn rollup_day(cam: &RolledCamera, archive_dir: &Path, day: NaiveDate, stop: &AtomicBool) -> Result<RollupSummary> {
+    let segments = scan_legacy_dir(&cam.ring_dir)?;
+    let hours = segments_by_hour(&segments, day);
+    let day_dir = archive_dir.join(&cam.key).join(day.format("%Y-%m-%d").to_string());
+    let mut summary = RollupSummary::default();
+
+    if let Some(oldest) = segments.first().map(|s| DateTime::<Local>::from(s.modified))
+        && oldest.date_naive() == day
+    {
+        warn!(
+            "Camera '{}': ring only reaches back to {}, the start of {} is already overwritten",
+            cam.key,
+            oldest.format("%H:%M"),
+            day
+        );
+    }
+
+    for (hour, files) in hours {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let output = day_dir.join(format!("{:02}.mkv", hour));
+        if output.exists() {
+            summary.hours_skipped += 1;
+            continue;
+        }
+        remux_files(&files, cam.codec, &output)?;
+        info!("Camera '{}': {} segments -> {}", cam.key, files.len(), output.display());
+        summary.hours_written += 1;
+        summary.segments += files.len();
+    }
+    Ok(summary)
+}
+
+/// Rollup thread. Runs for yesterday once `run_at` has passed today, including
+/// right after start-up if it's later than that (the archive skips hours it already has).
+pub fn spawn_rollup(cfg: &RollupConfig, cameras: Vec<RolledCamera>) -> Worker {
+    let archive_dir = PathBuf::from(&cfg.archive_dir);
+    let run_at = cfg.run_at_time().unwrap_or_default();
+    let mut done_on: Option<NaiveDate> = None;
+
+    Worker::spawn("rollup", Duration::from_secs(1), move |stop| {
+        let now = Local::now();
+        let today = now.date_naive();
+        if now.time() < run_at || done_on == Some(today) {
+            return;
+        }
+        done_on = Some(today);
+        let Some(yesterday) = today.pred_opt() else {
+            return;
+        };
+
+        for cam in &cameras {
+            match rollup_day(cam, &archive_dir, yesterday, stop) {
+                Ok(summary) => info!(
+                    "Camera '{}' rollup of {}: {} hours written ({} segments), {} already archived",
+                    cam.key, yesterday, summary.hours_written, summary.segments, summary.hours_skipped
+                ),
+                Err(e) => warn!("Camera '{}' rollup of {} failed: {:#}", cam.key, yesterday, e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn groups_the_days_segments_by_hour() {
+        let at = |h, m| -> std::time::SystemTime { Local.with_ymd_and_hms(2026, 3, 1, h, m, 0).unwrap().into() };
+        let segment = |index: i64, modified| LegacySegment {
+            path: PathBuf::from(format!("0/output_{}.ts", index)),
+            index,
+            modified,
+            bytes: 1,
+        };
+        let day = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        let next_day: std::time::SystemTime = Local.with_ymd_and_hms(2026, 3, 2, 0, 1, 0).unwrap().into();
+        let segments = vec![segment(0, at(9, 58)), segment(1, at(9, 59)), segment(2, at(10, 0)), segment(3, next_day)];
+
+        let hours = segments_by_hour(&segments, day);
+        assert_eq!(hours.keys().copied().collect::<Vec<_>>(), vec![9, 10]);
+        assert_eq!(hours[&9].len(), 2);
+        assert_eq!(hours[&10], vec![PathBuf::from("0/output_2.ts")]);
+    }
+
+    #[test]
+    fn remux_goes_through_the_demuxer() {
+        let files = vec![PathBuf::from("/r/0/output_1.ts"), PathBuf::from("/r/0/output_2.ts")];
+        let line = remux_description(&files, VideoCodec::H265, Path::new("/a/09.mkv"));
+        assert!(line.starts_with("concat name=c ! h265parse ! matroskamux ! filesink location=\"/a/09.mkv\""));
+        assert_eq!(line.matches("tsdemux ! h265parse ! c.").count(), 2);
+    }
+}
@@ -0,0 +1,581 @@
+//! Event → action rules (`[[rules]]` in config.toml), evaluated by one dispatcher
+//! thread instead of wiring subsystems to each other.
+//!
+//! A rule has one trigger: an event kind (optionally only from some cameras or
+//! with a label), a GPIO input turning active, or a local time of day. When it
+//! fires, its actions run in order. Actions on the service (clip, split, pause,
+//! standby, mode, setting, event) go through the control API as actor `rule:<name>`,
+//! so they land in the audit log like any other command; MQTT publishes and
+//! webhooks are sent from the dispatcher itself.
+//!
+//! Payload templates may use `{rule}`, `{kind}`, `{camera}`, `{label}` and `{at}`
+//! (RFC 3339). A rule raising the event it listens to would loop; `cooldown_sec`
+//! bounds that.
+//!
+//! Whether a rule is armed, how often and when it last fired are kept in the
+//! `rule_state` table, so a restart neither repeats a firing inside its cooldown
+//! or a time trigger of the same day, nor re-arms a rule disarmed over the API.
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Local, NaiveTime, Timelike};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::bandwidth::RateLimits;
+use crate::control::ControlCommand;
+use crate::db::db::RuleStateRow;
+use crate::db::db_worker::DBMessage;
+use crate::events::{CameraEvent, EventKind};
+use crate::gpio::{self, SwitchDebounce};
+use crate::mqtt::MqttClient;
+use crate::upload::http_post;
+use crate::worker::Worker;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const GPIO_DEBOUNCE: Duration = Duration::from_millis(100);
+const MQTT_KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    /// Shown in logs and as `rule:<name>` in the audit log
+    pub name: String,
+    /// Trigger: an event of this kind
+    pub event: Option<EventKind>,
+    /// Only events of these cameras, all when empty
+    #[serde(default)]
+    pub cameras: Vec<String>,
+    /// Only events with this label (detector class, "on"/"off" switches)
+    pub label: Option<String>,
+    /// Trigger: this sysfs GPIO turning active
+    pub gpio: Option<u32>,
+    #[serde(default)]
+    pub active_low: bool,
+    /// Trigger: every day at this local time ("HH:MM")
+    pub at: Option<String>,
+    /// Minimum time between two firings, also across restarts
+    #[serde(default)]
+    pub cooldown_sec: u64,
+    /// Armed at first start; after that the stored state (`/api/rules/<name>/arm`) wins
+    #[serde(default = "default_true")]
+    pub armed: bool,
+    pub actions: Vec<RuleAction>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(tag = "do", rename_all = "snake_case", deny_unknown_fields)]
+pub enum RuleAction {
+    /// Cut a clip around now through `[clips]`
+    Clip { camera: Option<String> },
+    /// Close the current segments
+    Split { camera: Option<String> },
+    /// Stop recording like the privacy switch, for `sec` or until resumed
+    Pause { camera: Option<String>, sec: Option<u64> },
+    /// Resume a paused camera
+    Resume { camera: Option<String> },
+    /// Enter (true) or leave (false) standby
+    Standby { on: bool },
+    /// Switch to a `[[modes]]` mode
+    Mode { name: String },
+    /// Runtime setting, as `POST /api/settings/<key>`
+    Setting { key: String, value: String },
+    /// Raise another event
+    Event { kind: EventKind, camera: Option<String> },
+    Mqtt {
+        /// "host:port"
+        broker: String,
+        topic: String,
+        #[serde(default = "default_payload")]
+        payload: String,
+        #[serde(default)]
+        retain: bool,
+    },
+    /// POST a JSON description of the firing; a token needs `https://` unless it's to this machine
+    Webhook { url: String, token: Option<String> },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_payload() -> String {
+    "{kind} on {camera}".to_string()
+}
+
+impl RuleConfig {
+    /// A name usable in URLs, one trigger, at least one action, known cameras.
+    pub fn is_valid(&self, camera_keys: &[&str]) -> bool {
+        let triggers = [self.event.is_some(), self.gpio.is_some(), self.at.is_some()];
+        let known = |key: &Option<String>| key.as_deref().is_none_or(|k| camera_keys.contains(&k));
+        !self.name.is_empty()
+            && !self.name.contains(|c: char| c == '/' || c == '?' || c.is_whitespace())
+            && triggers.iter().filter(|t| **t).count() == 1
+            && self.at.as_deref().is_none_or(|at| parse_time_of_day(at).is_some())
+            && !self.actions.is_empty()
+            && self.cameras.iter().all(|key| camera_keys.contains(&key.as_str()))
+            && self.actions.iter().all(|action| match action {
+                RuleAction::Clip { camera }
+                | RuleAction::Split { camera }
+                | RuleAction::Pause { camera, .. }
+                | RuleAction::Resume { camera }
+                | RuleAction::Event { camera, .. } => known(camera),
+                RuleAction::Mqtt { broker, topic, .. } => !broker.is_empty() && !topic.is_empty(),
+                RuleAction::Webhook { url, token } => crate::upload::check_endpoint(url, token.as_deref()).is_ok(),
+                RuleAction::Standby { .. } | RuleAction::Setting { .. } | RuleAction::Mode { .. } => true,
+            })
+    }
+
+    /// Whether an event of `camera_key` triggers the rule.
+    pub fn matches(&self, camera_key: &str, event: &CameraEvent) -> bool {
+        self.event == Some(event.kind)
+            && (self.cameras.is_empty() || self.cameras.iter().any(|c| c == camera_key))
+            && self.label.as_ref().is_none_or(|label| event.label.as_ref() == Some(label))
+    }
+}
+
+/// "HH:MM"
+pub fn parse_time_of_day(text: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(text, "%H:%M").ok()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Event(EventKind),
+    Gpio,
+    Schedule,
+}
+
+impl Trigger {
+    /// The event kind, or "gpio"/"schedule"
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Trigger::Event(kind) => kind.as_str(),
+            Trigger::Gpio => "gpio",
+            Trigger::Schedule => "schedule",
+        }
+    }
+}
+
+/// What set a rule off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Firing {
+    pub rule: String,
+    pub trigger: Trigger,
+    /// Camera of the event; `None` for GPIO and time triggers
+    pub camera: Option<String>,
+    pub label: Option<String>,
+    pub at: DateTime<Local>,
+}
+
+impl Firing {
+    pub fn expand(&self, template: &str) -> String {
+        template
+            .replace("{rule}", &self.rule)
+            .replace("{kind}", self.trigger.as_str())
+            .replace("{camera}", self.camera.as_deref().unwrap_or("all"))
+            .replace("{label}", self.label.as_deref().unwrap_or(""))
+            .replace("{at}", &self.at.to_rfc3339())
+    }
+
+    /// Kind a clip of the firing is filed under
+    fn clip_kind(&self) -> EventKind {
+        match self.trigger {
+            Trigger::Event(kind) => kind,
+            Trigger::Gpio | Trigger::Schedule => EventKind::Manual,
+        }
+    }
+}
+
+/// One thing to do for a firing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Command(ControlCommand),
+    /// Resume `camera_key` after a timed pause
+    ResumeAfter { camera_key: String, after: Duration },
+    Mqtt { broker: String, topic: String, payload: String, retain: bool },
+    Webhook { url: String, token: Option<String>, body: String },
+}
+
+/// The steps of `actions` for `firing`. Camera actions without a camera act on
+/// the event's camera, or on every camera for GPIO and time triggers.
+pub fn plan(actions: &[RuleAction], firing: &Firing, camera_keys: &[String]) -> Vec<Step> {
+    let targets = |camera: &Option<String>| -> Vec<String> {
+        match (camera, &firing.camera) {
+            (Some(key), _) | (None, Some(key)) => vec![key.clone()],
+            (None, None) => camera_keys.to_vec(),
+        }
+    };
+    let mut steps = Vec::new();
+    for action in actions {
+        match action {
+            RuleAction::Clip { camera } => {
+                for camera_key in targets(camera) {
+                    steps.push(Step::Command(ControlCommand::SaveClip { camera_key, kind: firing.clip_kind() }));
+                }
+            }
+            RuleAction::Split { camera } => {
+                for camera_key in targets(camera) {
+                    steps.push(Step::Command(ControlCommand::SplitSegments { camera_key }));
+                }
+            }
+            RuleAction::Pause { camera, sec } => {
+                for camera_key in targets(camera) {
+                    steps.push(Step::Command(ControlCommand::SetPrivacy { camera_key: camera_key.clone(), private: true }));
+                    if let Some(sec) = sec {
+                        steps.push(Step::ResumeAfter { camera_key, after: Duration::from_secs(*sec) });
+                    }
+                }
+            }
+            RuleAction::Resume { camera } => {
+                for camera_key in targets(camera) {
+                    steps.push(Step::Command(ControlCommand::SetPrivacy { camera_key, private: false }));
+                }
+            }
+            RuleAction::Standby { on } => steps.push(Step::Command(ControlCommand::SetStandby { standby: *on })),
+            RuleAction::Mode { name } => steps.push(Step::Command(ControlCommand::SetMode { name: name.clone() })),
+            RuleAction::Setting { key, value } => {
+                steps.push(Step::Command(ControlCommand::SetSetting { key: key.clone(), value: firing.expand(value) }))
+            }
+            RuleAction::Event { kind, camera } => {
+                for camera_key in targets(camera) {
+                    steps.push(Step::Command(ControlCommand::TriggerEvent { camera_key, kind: *kind }));
+                }
+            }
+            RuleAction::Mqtt { broker, topic, payload, retain } => steps.push(Step::Mqtt {
+                broker: broker.clone(),
+                topic: firing.expand(topic),
+                payload: firing.expand(payload),
+                retain: *retain,
+            }),
+            RuleAction::Webhook { url, token } => {
+                let body = json!({
+                    "rule": firing.rule,
+                    "kind": firing.trigger.as_str(),
+                    "camera": firing.camera,
+                    "label": firing.label,
+                    "at": firing.at.to_rfc3339(),
+                });
+                steps.push(Step::Webhook { url: url.clone(), token: token.clone(), body: body.to_string() });
+            }
+        }
+    }
+    steps
+}
+
+/// What the dispatcher is fed.
+#[derive(Debug, Clone)]
+pub enum RuleInput {
+    /// An admitted event of a camera
+    Event { camera_key: String, event: CameraEvent },
+    /// Arm or disarm a rule by name
+    SetArmed { name: String, armed: bool },
+}
+
+pub type RuleInputs = Receiver<RuleInput>;
+
+/// Handle for feeding the rules; cheap to clone into event callbacks. The
+/// default one does nothing.
+#[derive(Clone, Default)]
+pub struct RuleQueue {
+    sender: Option<Sender<RuleInput>>,
+}
+
+impl RuleQueue {
+    /// A queue and the end `RuleWorker::spawn` reads it from.
+    pub fn channel() -> (Self, RuleInputs) {
+        let (sender, receiver) = channel();
+        (Self { sender: Some(sender) }, receiver)
+    }
+
+    pub fn submit(&self, camera_key: &str, event: &CameraEvent) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(RuleInput::Event { camera_key: camera_key.to_string(), event: event.clone() });
+        }
+    }
+
+    /// False without `[[rules]]`.
+    pub fn set_armed(&self, name: &str, armed: bool) -> bool {
+        match &self.sender {
+            Some(sender) => sender.send(RuleInput::SetArmed { name: name.to_string(), armed }).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// State of `rule` when nothing is stored for it yet.
+pub fn initial_state(rule: &RuleConfig) -> RuleStateRow {
+    RuleStateRow { name: rule.name.clone(), armed: rule.armed, fire_count: 0, last_fired_utc: None, fired_on: None }
+}
+
+/// Whether an armed rule is out of its cooldown at `now_utc`. Works across
+/// restarts since the last firing is stored.
+pub fn ready(rule: &RuleConfig, state: &RuleStateRow, now_utc: i64) -> bool {
+    state.armed && state.last_fired_utc.is_none_or(|at| now_utc - at >= rule.cooldown_sec as i64)
+}
+
+struct RuleState {
+    rule: RuleConfig,
+    /// What's kept in the `rule_state` table
+    saved: RuleStateRow,
+    /// GPIO triggers
+    debounce: Option<SwitchDebounce>,
+}
+
+/// Dispatcher thread. `execute(actor, command)` runs the control commands, from
+/// this thread; it must not call back into `stop`. Picks up the state stored by
+/// the previous run, so cooldowns, once-a-day triggers and disarmed rules carry
+/// over a restart.
+pub fn spawn_rules<F>(
+    rules: Vec<RuleConfig>,
+    camera_keys: Vec<String>,
+    inputs: RuleInputs,
+    db_sender: Arc<Sender<DBMessage>>,
+    execute: F,
+) -> Worker
+where
+    F: Fn(&str, ControlCommand) + Send + 'static,
+{
+    let (tx, rx) = channel();
+    let stored = match db_sender.send(DBMessage::GetRuleStates { reply: tx }) {
+        Ok(()) => rx.recv().ok().flatten().unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+    let mut states: Vec<RuleState> = rules
+        .into_iter()
+        .map(|rule| {
+            let debounce = rule.gpio.map(|pin| {
+                if let Err(e) = gpio::export_input(pin) {
+                    warn!("Rule '{}': {:#}", rule.name, e);
+                }
+                SwitchDebounce::new(GPIO_DEBOUNCE)
+            });
+            let saved = stored.iter().find(|s| s.name == rule.name).cloned().unwrap_or_else(|| initial_state(&rule));
+            if !saved.armed {
+                info!("Rule '{}' is disarmed", rule.name);
+            }
+            RuleState { rule, saved, debounce }
+        })
+        .collect();
+    let mut resumes: Vec<(Instant, String, String)> = Vec::new();
+    Worker::spawn("rules", Duration::ZERO, move |stop| {
+        tick(&mut states, &mut resumes, &camera_keys, &inputs, &db_sender, &execute, stop)
+    })
+}
+
+/// Take in the next input, if one comes within `POLL_INTERVAL`, then fire the
+/// rules that are due and resume the cameras whose privacy ran out.
+fn tick<F>(
+    states: &mut [RuleState],
+    resumes: &mut Vec<(Instant, String, String)>,
+    camera_keys: &[String],
+    inputs: &RuleInputs,
+    db_sender: &Sender<DBMessage>,
+    execute: &F,
+    stop: &AtomicBool,
+) where
+    F: Fn(&str, ControlCommand),
+{
+    let save = |state: &RuleStateRow| {
+        let _ = db_sender.send(DBMessage::SaveRuleState { state: state.clone() });
+    };
+    let mut firings = Vec::new();
+    match inputs.recv_timeout(POLL_INTERVAL) {
+        Ok(RuleInput::Event { camera_key, event }) => {
+            for (i, state) in states.iter().enumerate() {
+                if state.rule.matches(&camera_key, &event) {
+                    firings.push((i, Firing {
+                        rule: state.rule.name.clone(),
+                        trigger: Trigger::Event(event.kind),
+                        camera: Some(camera_key.clone()),
+                        label: event.label.clone(),
+                        at: event.at,
+                    }));
+                }
+            }
+        }
+        Ok(RuleInput::SetArmed { name, armed }) => {
+            if let Some(state) = states.iter_mut().find(|s| s.rule.name == name)
+                && state.saved.armed != armed
+            {
+                info!("Rule '{}' {}", name, if armed { "armed" } else { "disarmed" });
+                state.saved.armed = armed;
+                save(&state.saved);
+            }
+        }
+        Err(RecvTimeoutError::Timeout) => {}
+        Err(RecvTimeoutError::Disconnected) => {
+            stop.store(true, Ordering::SeqCst);
+            return;
+        }
+    }
+
+    let now = Local::now();
+    for (i, state) in states.iter_mut().enumerate() {
+        let triggered = if let (Some(pin), Some(debounce)) = (state.rule.gpio, state.debounce.as_mut()) {
+            match gpio::read_input(pin, state.rule.active_low) {
+                Ok(raw) => debounce.update(raw, Instant::now()) == Some(true),
+                Err(_) => false,
+            }
+        } else if let Some(at) = state.rule.at.as_deref().and_then(parse_time_of_day) {
+            let today = now.format("%Y-%m-%d").to_string();
+            let due = now.hour() == at.hour() && now.minute() == at.minute();
+            let fresh = state.saved.fired_on.as_deref() != Some(today.as_str());
+            if due && fresh {
+                state.saved.fired_on = Some(today);
+                save(&state.saved);
+            }
+            due && fresh
+        } else {
+            false
+        };
+        if triggered {
+            let trigger = if state.rule.gpio.is_some() { Trigger::Gpio } else { Trigger::Schedule };
+            firings.push((i, Firing { rule: state.rule.name.clone(), trigger, camera: None, label: None, at: now }));
+        }
+    }
+
+    for (i, firing) in firings {
+        let state = &mut states[i];
+        let now_utc = firing.at.timestamp();
+        if !ready(&state.rule, &state.saved, now_utc) {
+            continue;
+        }
+        state.saved.last_fired_utc = Some(now_utc);
+        state.saved.fire_count += 1;
+        save(&state.saved);
+        info!("Rule '{}' fired on {}", firing.rule, firing.trigger.as_str());
+        let actor = format!("rule:{}", firing.rule);
+        for step in plan(&state.rule.actions, &firing, camera_keys) {
+            match step {
+                Step::Command(command) => execute(&actor, command),
+                Step::ResumeAfter { camera_key, after } => resumes.push((Instant::now() + after, actor.clone(), camera_key)),
+                Step::Mqtt { broker, topic, payload, retain } => {
+                    if let Err(e) = publish(&broker, &topic, &payload, retain) {
+                        warn!("Rule '{}': MQTT publish to {} failed: {:#}", firing.rule, broker, e);
+                    }
+                }
+                Step::Webhook { url, token, body } => {
+                    if let Err(e) = http_post(&url, token.as_deref(), "application/json", body.as_bytes(), &RateLimits::default()) {
+                        warn!("Rule '{}': webhook {} failed: {:#}", firing.rule, url, e);
+                    }
+                }
+            }
+        }
+    }
+
+    let due_now = Instant::now();
+    resumes.retain(|(due, actor, camera_key)| {
+        if *due > due_now {
+            return true;
+        }
+        execute(actor, ControlCommand::SetPrivacy { camera_key: camera_key.clone(), private: false });
+        false
+    });
+}
+
+fn publish(broker: &str, topic: &str, payload: &str, retain: bool) -> Result<()> {
+    if topic.is_empty() {
+        bail!("Empty topic");
+    }
+    let mut client = MqttClient::connect(broker, "dashcam_rs_rules", MQTT_KEEP_ALIVE)?;
+    client.publish(topic, payload.as_bytes(), retain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(text: &str) -> Vec<RuleConfig> {
+        #[derive(Deserialize)]
+        struct File {
+            rules: Vec<RuleConfig>,
+        }
+        toml::from_str::<File>(text).unwrap().rules
+    }
+
+    #[test]
+    fn rules_match_events_and_plan_their_actions() {
+        let rules = rules(
+            r#"
+            [[rules]]
+            name = "crash"
+            event = "gsensor"
+            cameras = ["front"]
+            actions = [
+              { do = "clip" },
+              { do = "split", camera = "rear" },
+              { do = "mqtt", broker = "hub:1883", topic = "dashcam/{camera}/alert" },
+            ]
+
+            [[rules]]
+            name = "night"
+            at = "23:30"
+            actions = [{ do = "pause", sec = 60 }]
+            "#,
+        );
+        let keys = ["front", "rear"];
+        assert!(rules.iter().all(|r| r.is_valid(&keys)));
+        assert!(!RuleConfig { gpio: Some(17), ..rules[0].clone() }.is_valid(&keys), "two triggers");
+        assert!(!RuleConfig { cameras: vec!["cab".into()], ..rules[0].clone() }.is_valid(&keys));
+        assert!(!RuleConfig { at: Some("25:00".into()), ..rules[1].clone() }.is_valid(&keys));
+
+        let bump = CameraEvent::now(EventKind::GSensor);
+        assert!(rules[0].matches("front", &bump));
+        assert!(!rules[0].matches("rear", &bump));
+        assert!(!rules[0].matches("front", &CameraEvent::now(EventKind::Motion)));
+
+        let firing = Firing {
+            rule: "crash".into(),
+            trigger: Trigger::Event(EventKind::GSensor),
+            camera: Some("front".into()),
+            label: None,
+            at: bump.at,
+        };
+        assert_eq!(
+            plan(&rules[0].actions, &firing, &["front".into(), "rear".into()]),
+            vec![
+                Step::Command(ControlCommand::SaveClip { camera_key: "front".into(), kind: EventKind::GSensor }),
+                Step::Command(ControlCommand::SplitSegments { camera_key: "rear".into() }),
+                Step::Mqtt {
+                    broker: "hub:1883".into(),
+                    topic: "dashcam/front/alert".into(),
+                    payload: "gsensor on front".into(),
+                    retain: false,
+                },
+            ]
+        );
+
+        // no event camera: every camera
+        let firing = Firing { rule: "night".into(), trigger: Trigger::Schedule, camera: None, ..firing };
+        let steps = plan(&rules[1].actions, &firing, &["front".into(), "rear".into()]);
+        assert_eq!(steps.len(), 4);
+        assert_eq!(
+            steps[3],
+            Step::ResumeAfter { camera_key: "rear".into(), after: Duration::from_secs(60) }
+        );
+    }
+
+    #[test]
+    fn stored_state_keeps_cooldowns_and_disarming() {
+        let rule = rules("[[rules]]\nname = \"away\"\nevent = \"motion\"\ncooldown_sec = 300\nactions = [{ do = \"clip\" }]")
+            .remove(0);
+        assert!(!RuleConfig { name: "away mode".into(), ..rule.clone() }.is_valid(&[]), "not usable in a URL");
+
+        let mut state = initial_state(&rule);
+        assert!(state.armed && state.last_fired_utc.is_none());
+        assert!(ready(&rule, &state, 1_000));
+
+        // fired just before a restart: still cooling down afterwards
+        state.last_fired_utc = Some(1_000);
+        assert!(!ready(&rule, &state, 1_299));
+        assert!(ready(&rule, &state, 1_300));
+
+        state.armed = false;
+        assert!(!ready(&rule, &state, 5_000));
+    }
+}
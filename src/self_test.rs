@@ -0,0 +1,248 @@
+//! `dashcam_rs self-test`: installer check that each camera records, the result
+//! decodes, the frame rate holds up, and the recording disk keeps up.
+//!
+//! Per enabled camera: record `RECORD_SECS` through the real source/encoder into a
+//! scratch dir on the recording disk, count frames at the tee, then decode the
+//! file back. Finally write a test file to measure disk throughput.
+
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::config::AppConfig;
+use crate::recording_pipeline_factory::build_self_test_pipeline;
+use crate::validation::probe_segment;
+
+const RECORD_SECS: u64 = 5;
+/// Achieved FPS must be at least this share of the configured rate
+const MIN_FPS_RATIO: f64 = 0.9;
+/// Disk must write at least this many times the combined recording rate
+const WRITE_HEADROOM: f64 = 2.0;
+const WRITE_TEST_BYTES: usize = 64 * 1024 * 1024;
+const DECODE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default)]
+pub struct CameraReport {
+    pub key: String,
+    pub expected_fps: f64,
+    pub fps: f64,
+    /// Bytes recorded during the run
+    pub bytes: u64,
+    pub decoded_frames: u64,
+    pub errors: Vec<String>,
+}
+
+impl CameraReport {
+    pub fn passed(&self) -> bool {
+        self.errors.is_empty() && self.decoded_frames > 0 && self.fps >= self.expected_fps * MIN_FPS_RATIO
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SelfTestReport {
+    pub cameras: Vec<CameraReport>,
+    pub write_mb_per_sec: f64,
+    pub write_error: Option<String>,
+}
+
+impl SelfTestReport {
+    /// What the cameras produced together, MB/s
+    pub fn recording_mb_per_sec(&self) -> f64 {
+        self.cameras.iter().map(|c| c.bytes as f64).sum::<f64>() / RECORD_SECS as f64 / 1e6
+    }
+
+    pub fn disk_passed(&self) -> bool {
+        self.write_error.is_none() && self.write_mb_per_sec >= self.recording_mb_per_sec() * WRITE_HEADROOM
+    }
+
+    pub fn passed(&self) -> bool {
+        !self.cameras.is_empty() && self.cameras.iter().all(CameraReport::passed) && self.disk_passed()
+    }
+}
+
+fn verdict(ok: bool) -> &'static str {
+    if ok { "PASS" } else { "FAIL" }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "dashcam self-test")?;
+        for cam in &self.cameras {
+            writeln!(
+                f,
+                "  [{}] camera '{}': {:.1}/{:.0} fps, {} KB recorded, {} frames decoded",
+                verdict(cam.passed()),
+                cam.key,
+                cam.fps,
+                cam.expected_fps,
+                cam.bytes / 1024,
+                cam.decoded_frames
+            )?;
+            for error in &cam.errors {
+                writeln!(f, "         {}", error)?;
+            }
+        }
+        write!(
+            f,
+            "  [{}] disk: {:.1} MB/s write, recording needs {:.2} MB/s",
+            verdict(self.disk_passed()),
+            self.write_mb_per_sec,
+            self.recording_mb_per_sec()
+        )?;
+        if let Some(error) = &self.write_error {
+            write!(f, " ({})", error)?;
+        }
+        writeln!(f)?;
+        write!(f, "RESULT: {}", verdict(self.passed()))
+    }
+}
+
+fn recorded_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return files;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(recorded_files(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+fn test_camera(app: &AppConfig, cam: &crate::config::CameraConfig, scratch: &Path) -> CameraReport {
+    let mut report = CameraReport {
+        key: cam.key.clone(),
+        expected_fps: cam.video_framerate.unwrap_or(app.profile().video_framerate as i64) as f64,
+        ..Default::default()
+    };
+    let dir = scratch.join(&cam.key);
+
+    let mut pipeline = match build_self_test_pipeline(app, cam, &dir, RECORD_SECS) {
+        Ok(p) => p,
+        Err(e) => {
+            report.errors.push(format!("build failed: {:#}", e));
+            return report;
+        }
+    };
+    if let Err(e) = pipeline.start_pipeline() {
+        report.errors.push(format!("start failed: {:#}", e));
+        return report;
+    }
+
+    let frames = Arc::new(AtomicU64::new(0));
+    if let Some(pad) = pipeline.get_source_tee().ok().and_then(|tee| tee.static_pad("sink")) {
+        let counter = frames.clone();
+        pad.add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            gst::PadProbeReturn::Ok
+        });
+    }
+    let started = Instant::now();
+    std::thread::sleep(Duration::from_secs(RECORD_SECS));
+    let elapsed = started.elapsed().as_secs_f64();
+    let running = pipeline.is_running();
+    let _ = pipeline.stop_pipeline();
+
+    report.fps = frames.load(Ordering::Relaxed) as f64 / elapsed;
+    if !running {
+        report.errors.push("pipeline stopped on its own (see log)".to_string());
+    }
+
+    let files = recorded_files(&dir);
+    report.bytes = files.iter().filter_map(|f| f.metadata().ok()).map(|m| m.len()).sum();
+    if files.is_empty() {
+        report.errors.push("no file written".to_string());
+    }
+    for file in &files {
+        match probe_segment(file, DECODE_TIMEOUT) {
+            Ok(probe) => report.decoded_frames += probe.frames,
+            Err(e) => report.errors.push(format!("{}: {:#}", file.display(), e)),
+        }
+    }
+    report
+}
+
+/// Write and fsync a test file, return MB/s.
+fn measure_write(dir: &Path) -> Result<f64> {
+    let path = dir.join("write_test.bin");
+    let chunk = vec![0x5au8; 1024 * 1024];
+    let started = Instant::now();
+    let mut file = fs::File::create(&path)?;
+    for _ in 0..WRITE_TEST_BYTES / chunk.len() {
+        file.write_all(&chunk)?;
+    }
+    file.sync_all()?;
+    let secs = started.elapsed().as_secs_f64();
+    fs::remove_file(&path)?;
+    Ok(WRITE_TEST_BYTES as f64 / secs / 1e6)
+}
+
+/// Run every check. Scratch files go under `<recording_root>/.self_test/` (the
+/// disk being tested) and are removed afterwards.
+pub fn run_self_test(app: &AppConfig) -> Result<SelfTestReport> {
+    gst::init()?;
+    let scratch = PathBuf::from(app.recording_root()).join(".self_test");
+    fs::create_dir_all(&scratch).with_context(|| format!("Failed to create {}", scratch.display()))?;
+
+    let mut report = SelfTestReport::default();
+    for cam in app.cameras.iter().filter(|c| c.enabled) {
+        report.cameras.push(test_camera(app, cam, &scratch));
+    }
+    match measure_write(&scratch) {
+        Ok(rate) => report.write_mb_per_sec = rate,
+        Err(e) => report.write_error = Some(format!("{:#}", e)),
+    }
+
+    let _ = fs::remove_dir_all(&scratch);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(fps: f64, decoded: u64) -> CameraReport {
+        CameraReport {
+            key: "front".to_string(),
+            expected_fps: 30.0,
+            fps,
+            bytes: 5_000_000,
+            decoded_frames: decoded,
+            errors: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn pass_needs_frames_rate_and_disk() {
+        let mut report = SelfTestReport {
+            cameras: vec![camera(29.5, 150)],
+            write_mb_per_sec: 50.0,
+            write_error: None,
+        };
+        assert!(report.passed());
+        assert!(report.to_string().ends_with("RESULT: PASS"));
+
+        report.cameras[0].fps = 20.0;
+        assert!(!report.passed(), "fps too low");
+
+        report.cameras[0] = camera(30.0, 0);
+        assert!(!report.passed(), "nothing decoded");
+
+        report.cameras[0] = camera(30.0, 150);
+        report.write_mb_per_sec = 1.0;
+        assert!(!report.disk_passed(), "1 MB/s can't keep up with 1 MB/s x2");
+        assert!(report.to_string().contains("[FAIL] disk"));
+    }
+}
@@ -0,0 +1,326 @@
+//! Drive health polling (`[smart]` in config.toml) for recording drives that
+//! report it, typically a USB SSD.
+//!
+//! Every `interval_sec` each configured device is sampled with
+//! `smartctl --json -H -A` (overall self-assessment, temperature, reallocated
+//! and pending sectors) and the sample stored in `drive_health`. Without
+//! smartctl, or for a bridge it can't talk through, the temperature is read
+//! from sysfs instead (the `drivetemp` hwmon driver, or NVMe's own).
+//!
+//! A drive is degraded in `/healthz` while its self-assessment fails, it runs
+//! at `max_temperature_c` or hotter, or its reallocated or pending sector count
+//! grew within the last `trend_days`: a few remapped sectors are normal, a
+//! count that keeps climbing is a drive on its way out. Every change for the
+//! worse is logged as a warning.
+
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::db::db::DriveSample;
+use crate::db::db_worker::DBMessage;
+use crate::health::{HealthState, SubsystemHealth};
+use crate::worker::Worker;
+
+/// ATA attribute IDs
+const REALLOCATED_SECTORS: i64 = 5;
+const PENDING_SECTORS: i64 = 197;
+/// smartctl exit status bits for "couldn't parse the command line" and "couldn't open the device"
+const SMARTCTL_FATAL: i32 = 0b11;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmartConfig {
+    /// Block devices to poll, e.g. `/dev/sda`
+    pub devices: Vec<String>,
+    pub interval_sec: u64,
+    /// smartctl `-d` type for USB bridges it doesn't detect, e.g. `sat`
+    pub device_type: Option<String>,
+    pub max_temperature_c: f64,
+    /// How far back sector counts are compared
+    pub trend_days: u64,
+    pub smartctl: String,
+}
+
+impl Default for SmartConfig {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            interval_sec: 3600,
+            device_type: None,
+            max_temperature_c: 60.0,
+            trend_days: 7,
+            smartctl: "smartctl".to_string(),
+        }
+    }
+}
+
+impl SmartConfig {
+    pub fn is_valid(&self) -> bool {
+        !self.devices.is_empty()
+            && self.devices.iter().all(|d| Path::new(d).is_absolute())
+            && self.interval_sec > 0
+            && self.trend_days > 0
+            && !self.smartctl.is_empty()
+    }
+}
+
+/// A sample out of `smartctl --json -H -A` output. Missing values are left `None`.
+pub fn parse_smartctl(device: &str, at_utc: i64, json: &str) -> Result<DriveSample> {
+    let report: Value = serde_json::from_str(json).context("smartctl didn't print JSON")?;
+    let attribute = |id: i64| {
+        report["ata_smart_attributes"]["table"]
+            .as_array()?
+            .iter()
+            .find(|a| a["id"].as_i64() == Some(id))?["raw"]["value"]
+            .as_i64()
+    };
+    Ok(DriveSample {
+        device: device.to_string(),
+        at_utc,
+        passed: report["smart_status"]["passed"].as_bool(),
+        temperature_c: report["temperature"]["current"].as_f64(),
+        // NVMe has no sector remapping attributes; media errors are the closest thing
+        reallocated_sectors: attribute(REALLOCATED_SECTORS)
+            .or_else(|| report["nvme_smart_health_information_log"]["media_errors"].as_i64()),
+        pending_sectors: attribute(PENDING_SECTORS),
+    })
+}
+
+/// Drive temperature from sysfs: `drivetemp` puts a hwmon under the SCSI
+/// device, NVMe controllers have their own. In °C.
+pub fn sysfs_temperature(sys_block: &Path, device: &str) -> Option<f64> {
+    let name = Path::new(device).file_name()?;
+    let device_dir = sys_block.join(name).join("device");
+    let mut hwmons: Vec<_> = fs::read_dir(device_dir.join("hwmon"))
+        .into_iter()
+        .chain(fs::read_dir(&device_dir))
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().is_some_and(|n| n.to_string_lossy().starts_with("hwmon")))
+        .collect();
+    hwmons.sort();
+    hwmons.iter().find_map(|hwmon| {
+        let millidegrees: f64 = fs::read_to_string(hwmon.join("temp1_input")).ok()?.trim().parse().ok()?;
+        Some(millidegrees / 1000.0)
+    })
+}
+
+/// Sample `device` with smartctl, falling back to the sysfs temperature.
+pub fn sample_drive(config: &SmartConfig, device: &str) -> Result<DriveSample> {
+    let at_utc = Utc::now().timestamp();
+    let mut command = Command::new(&config.smartctl);
+    command.args(["--json", "-H", "-A"]);
+    if let Some(device_type) = &config.device_type {
+        command.args(["-d", device_type]);
+    }
+    let smartctl = match command.arg(device).output() {
+        Ok(output) if output.status.code().is_some_and(|code| code & SMARTCTL_FATAL == 0) => {
+            parse_smartctl(device, at_utc, &String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(output) => Err(anyhow::anyhow!("smartctl couldn't read {} ({})", device, output.status)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(anyhow::anyhow!("{} isn't installed", config.smartctl)),
+        Err(e) => Err(e).with_context(|| format!("Failed to run {}", config.smartctl)),
+    };
+    match smartctl {
+        Ok(sample) => Ok(sample),
+        Err(e) => {
+            let Some(temperature_c) = sysfs_temperature(Path::new("/sys/block"), device) else {
+                bail!("{:#}, and sysfs has no temperature for it", e);
+            };
+            Ok(DriveSample {
+                device: device.to_string(),
+                at_utc,
+                passed: None,
+                temperature_c: Some(temperature_c),
+                reallocated_sectors: None,
+                pending_sectors: None,
+            })
+        }
+    }
+}
+
+/// The newest sample of a drive, judged against the oldest one of the trend window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DriveHealth {
+    pub latest: DriveSample,
+    /// Growth since the start of the trend window
+    pub reallocated_growth: i64,
+    pub pending_growth: i64,
+    pub state: HealthState,
+    pub problems: Vec<String>,
+}
+
+impl DriveHealth {
+    pub fn assess(latest: DriveSample, baseline: Option<&DriveSample>, config: &SmartConfig) -> Self {
+        let growth = |now: Option<i64>, then: Option<i64>| now.zip(then).map_or(0, |(now, then)| (now - then).max(0));
+        let reallocated_growth = growth(latest.reallocated_sectors, baseline.and_then(|b| b.reallocated_sectors));
+        let pending_growth = growth(latest.pending_sectors, baseline.and_then(|b| b.pending_sectors));
+
+        let mut problems = Vec::new();
+        if latest.passed == Some(false) {
+            problems.push("self-assessment failed".to_string());
+        }
+        if latest.temperature_c.is_some_and(|t| t >= config.max_temperature_c) {
+            problems.push(format!("at or over {:.0}°C", config.max_temperature_c));
+        }
+        if reallocated_growth > 0 {
+            problems.push(format!("{} more reallocated sectors in {}d", reallocated_growth, config.trend_days));
+        }
+        if pending_growth > 0 {
+            problems.push(format!("{} more pending sectors in {}d", pending_growth, config.trend_days));
+        }
+        let state = if problems.is_empty() { HealthState::Ok } else { HealthState::Degraded };
+        Self { latest, reallocated_growth, pending_growth, state, problems }
+    }
+
+    pub fn health(&self) -> SubsystemHealth {
+        let name = format!("drive:{}", self.latest.device);
+        if !self.problems.is_empty() {
+            return SubsystemHealth::new(name, self.state, self.problems.join(", "));
+        }
+        let mut detail = Vec::new();
+        if let Some(temperature) = self.latest.temperature_c {
+            detail.push(format!("{:.0}°C", temperature));
+        }
+        if let Some(reallocated) = self.latest.reallocated_sectors {
+            detail.push(format!("{} reallocated sectors", reallocated));
+        }
+        let detail = if detail.is_empty() { "no attributes reported".to_string() } else { detail.join(", ") };
+        SubsystemHealth::new(name, self.state, detail)
+    }
+}
+
+/// Oldest stored sample of `device` since `since_utc`, `None` if there's none or the DB didn't answer.
+fn baseline(db_sender: &Sender<DBMessage>, device: &str, since_utc: i64) -> Option<DriveSample> {
+    let (tx, rx) = mpsc::channel();
+    db_sender.send(DBMessage::GetDriveBaseline { device: device.to_string(), since_utc, reply: tx }).ok()?;
+    rx.recv().ok()?
+}
+
+/// Polling thread, with the latest health of every drive.
+pub struct SmartWorker {
+    worker: Worker,
+    drives: Arc<Mutex<Vec<DriveHealth>>>,
+}
+
+impl SmartWorker {
+    pub fn spawn(cfg: &SmartConfig, db_sender: Arc<Sender<DBMessage>>) -> Self {
+        let drives: Arc<Mutex<Vec<DriveHealth>>> = Arc::new(Mutex::new(Vec::new()));
+        let thread_drives = drives.clone();
+        let cfg = cfg.clone();
+
+        let worker = Worker::spawn("smart", Duration::from_secs(cfg.interval_sec), move |_| {
+            for device in &cfg.devices {
+                let sample = match sample_drive(&cfg, device) {
+                    Ok(sample) => sample,
+                    Err(e) => {
+                        warn!("Couldn't read the health of {}: {:#}", device, e);
+                        continue;
+                    }
+                };
+                let since_utc = sample.at_utc - (cfg.trend_days * 86_400) as i64;
+                let baseline = baseline(&db_sender, device, since_utc);
+                let _ = db_sender.send(DBMessage::InsertDriveSample { sample: sample.clone() });
+                let drive = DriveHealth::assess(sample, baseline.as_ref(), &cfg);
+
+                let mut drives = thread_drives.lock().unwrap();
+                let previous = drives.iter().position(|d| &d.latest.device == device).map(|i| drives.remove(i));
+                let known = |problem: &String| previous.as_ref().is_some_and(|p| p.problems.contains(problem));
+                if !drive.problems.iter().all(known) {
+                    warn!("Drive {} is degrading: {}", device, drive.problems.join(", "));
+                } else if drive.problems.is_empty() && previous.is_some_and(|p| !p.problems.is_empty()) {
+                    info!("Drive {} is healthy again", device);
+                }
+                drives.push(drive);
+            }
+        });
+        Self { worker, drives }
+    }
+
+    /// Health of every drive sampled so far.
+    pub fn drives(&self) -> Vec<DriveHealth> {
+        self.drives.lock().unwrap().clone()
+    }
+
+    pub fn stop(self) {
+        self.worker.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SATA_SSD: &str = r#"{
+        "smart_status": { "passed": true },
+        "temperature": { "current": 41 },
+        "ata_smart_attributes": { "table": [
+            { "id": 5, "name": "Reallocated_Sector_Ct", "raw": { "value": 12, "string": "12" } },
+            { "id": 9, "name": "Power_On_Hours", "raw": { "value": 8812, "string": "8812" } },
+            { "id": 197, "name": "Current_Pending_Sector", "raw": { "value": 0, "string": "0" } }
+        ] }
+    }"#;
+
+    #[test]
+    fn climbing_sector_counts_degrade_a_drive() {
+        let config = SmartConfig { devices: vec!["/dev/sda".into()], ..SmartConfig::default() };
+        let then = parse_smartctl("/dev/sda", 1_000, SATA_SSD).unwrap();
+        assert_eq!(
+            then,
+            DriveSample {
+                device: "/dev/sda".into(),
+                at_utc: 1_000,
+                passed: Some(true),
+                temperature_c: Some(41.0),
+                reallocated_sectors: Some(12),
+                pending_sectors: Some(0),
+            }
+        );
+        // a dozen sectors remapped long ago is fine
+        let steady = DriveHealth::assess(then.clone(), None, &config);
+        assert_eq!(steady.health().state, HealthState::Ok);
+        assert_eq!(steady.health().detail, "41°C, 12 reallocated sectors");
+
+        let now = DriveSample { reallocated_sectors: Some(40), temperature_c: Some(63.0), ..then.clone() };
+        let worn = DriveHealth::assess(now, Some(&then), &config);
+        assert_eq!(worn.reallocated_growth, 28);
+        assert_eq!(worn.health().state, HealthState::Degraded);
+        assert_eq!(worn.health().detail, "at or over 60°C, 28 more reallocated sectors in 7d");
+
+        let nvme = parse_smartctl(
+            "/dev/nvme0n1",
+            1_000,
+            r#"{ "smart_status": { "passed": false }, "nvme_smart_health_information_log": { "media_errors": 3 } }"#,
+        )
+        .unwrap();
+        assert_eq!((nvme.reallocated_sectors, nvme.temperature_c), (Some(3), None));
+        assert_eq!(DriveHealth::assess(nvme, None, &config).problems, vec!["self-assessment failed"]);
+        assert!(parse_smartctl("/dev/sda", 0, "smartctl: command not found").is_err());
+    }
+
+    #[test]
+    fn temperature_falls_back_to_sysfs() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let hwmon = tmp.path().join("sda/device/hwmon/hwmon3");
+        fs::create_dir_all(&hwmon).unwrap();
+        fs::write(hwmon.join("temp1_input"), "38000\n").unwrap();
+        assert_eq!(sysfs_temperature(tmp.path(), "/dev/sda"), Some(38.0));
+        // NVMe: hwmon right under the controller
+        let nvme = tmp.path().join("nvme0n1/device/hwmon1");
+        fs::create_dir_all(&nvme).unwrap();
+        fs::write(nvme.join("temp1_input"), "45850").unwrap();
+        assert_eq!(sysfs_temperature(tmp.path(), "/dev/nvme0n1"), Some(45.85));
+        assert_eq!(sysfs_temperature(tmp.path(), "/dev/sdb"), None);
+    }
+}
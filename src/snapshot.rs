@@ -0,0 +1,86 @@
+//! One-shot JPEG grabs straight from a camera's source, without its recording
+//! pipeline (which may be stopped, e.g. in standby).
+
+use anyhow::{Context, Result, anyhow, bail};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::{SourceConfig, SourceKind};
+
+/// `gst-launch` description of a pipeline that writes one JPEG to `location` and stops.
+pub fn snapshot_launch_line(source: &SourceConfig, location: &Path) -> Result<String> {
+    let src = match source.kind {
+        SourceKind::Libcamera => "libcamerasrc".to_string(),
+        SourceKind::V4l2 => format!(
+            "v4l2src device=\"{}\"",
+            source.device.as_deref().context("v4l2 source without device")?
+        ),
+        SourceKind::Rtsp => format!(
+            "rtspsrc location=\"{}\" protocols=tcp",
+            source.rtsp_url.as_deref().context("rtsp source without rtsp_url")?
+        ),
+    };
+    Ok(format!(
+        "{} ! decodebin ! videoconvert ! jpegenc snapshot=true ! filesink location=\"{}\"",
+        src,
+        location.display()
+    ))
+}
+
+/// Grab one frame from `source` into `location`, giving up after `timeout`.
+pub fn take_snapshot(source: &SourceConfig, location: &Path, timeout: Duration) -> Result<()> {
+    gst::init()?;
+    if let Some(dir) = location.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let line = snapshot_launch_line(source, location)?;
+    let pipeline = gst::parse::launch(&line).with_context(|| format!("Failed to build snapshot pipeline '{}'", line))?;
+    let bus = pipeline.bus().context("Snapshot pipeline has no bus")?;
+
+    pipeline.set_state(gst::State::Playing)?;
+    let msg = bus.timed_pop_filtered(
+        gst::ClockTime::from_mseconds(timeout.as_millis() as u64),
+        &[gst::MessageType::Eos, gst::MessageType::Error],
+    );
+    pipeline.set_state(gst::State::Null)?;
+
+    match msg.as_ref().map(|m| m.view()) {
+        Some(gst::MessageView::Eos(..)) => Ok(()),
+        Some(gst::MessageView::Error(err)) => Err(anyhow!("Snapshot failed: {} ({:?})", err.error(), err.debug())),
+        _ => bail!("Snapshot timed out after {:?}", timeout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VideoCodec;
+
+    #[test]
+    fn launch_line_per_source_kind() {
+        let source = |kind, device: Option<&str>, url: Option<&str>| SourceConfig {
+            kind,
+            rtsp_url: url.map(str::to_string),
+            device: device.map(str::to_string),
+            passthrough: false,
+            codec: VideoCodec::H264,
+            encoder: None,
+            startup_timeout_sec: 10,
+            retry_interval_sec: 5,
+        };
+        let out = Path::new("/tmp/snap.jpg");
+
+        assert_eq!(
+            snapshot_launch_line(&source(SourceKind::V4l2, Some("/dev/video0"), None), out).unwrap(),
+            "v4l2src device=\"/dev/video0\" ! decodebin ! videoconvert ! jpegenc snapshot=true ! filesink location=\"/tmp/snap.jpg\""
+        );
+        assert!(
+            snapshot_launch_line(&source(SourceKind::Rtsp, None, Some("rtsp://cam/stream")), out)
+                .unwrap()
+                .starts_with("rtspsrc location=\"rtsp://cam/stream\"")
+        );
+        assert!(snapshot_launch_line(&source(SourceKind::V4l2, None, None), out).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Restart soak test: `dashcam_rs soak`, or `cargo test -- --ignored soak` for
+//! the coreelements-only version.
+//!
+//! Starts and stops pipelines hundreds of times, toggling sinks and now and
+//! then rebuilding from scratch like a config change would, and watches the
+//! process's open FDs, threads and RSS plus each pipeline's element count and
+//! leftover tee pads. GStreamer allocates thread pools and caches lazily, so
+//! the baseline is taken after a few warm-up cycles; anything that keeps
+//! growing after that is a leak in a restart path.
+
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use std::fmt;
+use std::fs;
+use std::time::Duration;
+
+use crate::recording_pipeline::RecordingPipeline;
+
+/// Cycles before the baseline sample
+const WARMUP_CYCLES: usize = 5;
+/// Every this many cycles the pipelines are torn down and rebuilt
+const REBUILD_EVERY: usize = 10;
+
+/// What /proc says about this process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessStats {
+    pub fds: usize,
+    pub threads: usize,
+    pub rss_kb: u64,
+}
+
+impl ProcessStats {
+    pub fn sample() -> Result<Self> {
+        let fds = fs::read_dir("/proc/self/fd").context("Can't list /proc/self/fd")?.count();
+        let status = fs::read_to_string("/proc/self/status").context("Can't read /proc/self/status")?;
+        let (threads, rss_kb) = parse_status(&status);
+        Ok(Self { fds, threads, rss_kb })
+    }
+}
+
+/// `Threads:` and `VmRSS:` (kB) of a /proc/<pid>/status.
+pub fn parse_status(status: &str) -> (usize, u64) {
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0)
+    };
+    (field("Threads:") as usize, field("VmRSS:"))
+}
+
+/// Growth over the baseline that still counts as noise.
+#[derive(Debug, Clone, Copy)]
+pub struct SoakLimits {
+    pub fds: usize,
+    pub threads: usize,
+    pub rss_kb: u64,
+}
+
+impl Default for SoakLimits {
+    fn default() -> Self {
+        Self { fds: 8, threads: 4, rss_kb: 32 * 1024 }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SoakReport {
+    pub cycles: usize,
+    pub baseline: ProcessStats,
+    pub last: ProcessStats,
+    /// Highest of each field over the measured cycles
+    pub peak: ProcessStats,
+    /// Elements per pipeline, after the first build and at the end
+    pub elements: Vec<(usize, usize)>,
+    /// Tee request pads still there after a stop, summed over all cycles
+    pub leftover_tee_pads: usize,
+}
+
+impl SoakReport {
+    /// One line per leak, empty if there's none.
+    pub fn problems(&self, limits: &SoakLimits) -> Vec<String> {
+        let mut problems = Vec::new();
+        let grew = |last: u64, base: u64, limit: u64| last.saturating_sub(base) > limit;
+        if grew(self.last.fds as u64, self.baseline.fds as u64, limits.fds as u64) {
+            problems.push(format!("open FDs grew from {} to {}", self.baseline.fds, self.last.fds));
+        }
+        if grew(self.last.threads as u64, self.baseline.threads as u64, limits.threads as u64) {
+            problems.push(format!("threads grew from {} to {}", self.baseline.threads, self.last.threads));
+        }
+        if grew(self.last.rss_kb, self.baseline.rss_kb, limits.rss_kb) {
+            problems.push(format!("RSS grew from {} kB to {} kB", self.baseline.rss_kb, self.last.rss_kb));
+        }
+        for (i, (first, last)) in self.elements.iter().enumerate() {
+            if last != first {
+                problems.push(format!("pipeline {} went from {} to {} elements", i, first, last));
+            }
+        }
+        if self.leftover_tee_pads > 0 {
+            problems.push(format!("{} tee pads left behind after stops", self.leftover_tee_pads));
+        }
+        problems
+    }
+}
+
+impl fmt::Display for SoakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} cycles", self.cycles)?;
+        let row = |f: &mut fmt::Formatter<'_>, name: &str, base: u64, last: u64, peak: u64| {
+            writeln!(f, "  {:<8} baseline {:>8}  last {:>8}  peak {:>8}", name, base, last, peak)
+        };
+        row(f, "fds", self.baseline.fds as u64, self.last.fds as u64, self.peak.fds as u64)?;
+        row(f, "threads", self.baseline.threads as u64, self.last.threads as u64, self.peak.threads as u64)?;
+        row(f, "rss kB", self.baseline.rss_kb, self.last.rss_kb, self.peak.rss_kb)
+    }
+}
+
+/// Element count of a pipeline and how many tee src pads its source still has.
+fn pipeline_shape(pipeline: &RecordingPipeline) -> (usize, usize) {
+    let tee_pads = pipeline.get_source_tee().map_or(0, |tee| tee.src_pads().len());
+    (pipeline.element_count(), tee_pads)
+}
+
+/// Run `cycles` start/stop cycles over `pipelines`, keeping each running for `hold`.
+pub fn run_soak(pipelines: &mut [RecordingPipeline], cycles: usize, hold: Duration) -> Result<SoakReport> {
+    let mut report = SoakReport { cycles, ..Default::default() };
+
+    for cycle in 0..cycles.max(WARMUP_CYCLES + 1) {
+        for pipeline in pipelines.iter_mut() {
+            pipeline.start_pipeline()?;
+        }
+        std::thread::sleep(hold);
+        for pipeline in pipelines.iter_mut() {
+            // closing and reopening the valves is part of what a restart exercises
+            let sink_ids: Vec<i64> = pipeline.sink_ids();
+            for sink_id in &sink_ids {
+                pipeline.set_sink_enabled(*sink_id, false)?;
+                pipeline.set_sink_enabled(*sink_id, true)?;
+            }
+            pipeline.stop_pipeline()?;
+            let (_, tee_pads) = pipeline_shape(pipeline);
+            report.leftover_tee_pads += tee_pads;
+        }
+
+        if cycle == 0 {
+            report.elements = pipelines.iter().map(|p| (p.element_count(), 0)).collect();
+        }
+        if cycle > 0 && cycle % REBUILD_EVERY == 0 {
+            for pipeline in pipelines.iter_mut() {
+                pipeline.rebuild_pipeline()?;
+            }
+        }
+
+        let stats = ProcessStats::sample()?;
+        if cycle + 1 == WARMUP_CYCLES {
+            report.baseline = stats;
+            report.peak = stats;
+        } else if cycle + 1 > WARMUP_CYCLES {
+            report.peak = ProcessStats {
+                fds: report.peak.fds.max(stats.fds),
+                threads: report.peak.threads.max(stats.threads),
+                rss_kb: report.peak.rss_kb.max(stats.rss_kb),
+            };
+        }
+        report.last = stats;
+    }
+
+    // a rebuilt pipeline only has its elements again once it has started
+    for (pipeline, (_, last)) in pipelines.iter_mut().zip(report.elements.iter_mut()) {
+        pipeline.start_pipeline()?;
+        pipeline.stop_pipeline()?;
+        *last = pipeline.element_count();
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_threads_and_rss_from_status() {
+        let status = "Name:\tdashcam_rs\nVmRSS:\t   52344 kB\nThreads:\t17\n";
+        assert_eq!(parse_status(status), (17, 52344));
+        assert_eq!(parse_status(""), (0, 0));
+
+        let baseline = ProcessStats { fds: 20, threads: 10, rss_kb: 50_000 };
+        let report = SoakReport {
+            cycles: 100,
+            baseline,
+            last: ProcessStats { fds: 40, threads: 11, rss_kb: 60_000 },
+            peak: baseline,
+            elements: vec![(12, 12), (9, 11)],
+            leftover_tee_pads: 0,
+        };
+        let problems = report.problems(&SoakLimits::default());
+        assert_eq!(problems, vec!["open FDs grew from 20 to 40", "pipeline 1 went from 9 to 11 elements"]);
+    }
+}
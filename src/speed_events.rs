@@ -0,0 +1,119 @@
+//! Events from GPS speed (`[speed_events]` in config.toml): `speeding` for
+//! every fix above a limit, and `hard_brake` when the speed drops faster than
+//! a deceleration threshold between two fixes, for cars without a G-sensor.
+//! The events go through the usual `[events]` filter, so a long stretch over
+//! the limit is one event per cooldown.
+//!
+//! Fixes without a reported speed get one from the distance to the previous fix.
+
+use serde::Deserialize;
+
+use crate::db::db::GpsPoint;
+use crate::events::EventKind;
+
+/// Fixes further apart than this (a tunnel, a GPS restart) aren't compared
+const MAX_FIX_GAP_SEC: i64 = 5;
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpeedEventsConfig {
+    /// `speeding` above this
+    pub over_kmh: Option<f64>,
+    /// `hard_brake` when slowing down faster than this (m/s²; ~4 is an emergency stop in the dry)
+    pub hard_brake_mps2: Option<f64>,
+    /// Cameras the events are raised on, every camera when empty
+    pub cameras: Vec<String>,
+}
+
+impl SpeedEventsConfig {
+    /// Needs a rule, positive thresholds and known cameras.
+    pub fn is_valid(&self, camera_keys: &[&str]) -> bool {
+        (self.over_kmh.is_some() || self.hard_brake_mps2.is_some())
+            && self.over_kmh.is_none_or(|v| v > 0.0)
+            && self.hard_brake_mps2.is_none_or(|v| v > 0.0)
+            && self.cameras.iter().all(|key| camera_keys.contains(&key.as_str()))
+    }
+}
+
+/// Great-circle distance between two fixes.
+pub fn distance_m(a: &GpsPoint, b: &GpsPoint) -> f64 {
+    let (lat_a, lat_b) = (a.lat.to_radians(), b.lat.to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_lon = (b.lon - a.lon).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * h.sqrt().asin()
+}
+
+/// Applies the speed rules to fixes as they come in.
+#[derive(Debug, Clone)]
+pub struct SpeedRules {
+    cfg: SpeedEventsConfig,
+    /// Previous fix and its speed (m/s) if known
+    last: Option<(GpsPoint, Option<f64>)>,
+}
+
+impl SpeedRules {
+    pub fn new(cfg: &SpeedEventsConfig) -> Self {
+        Self { cfg: cfg.clone(), last: None }
+    }
+
+    /// Event kinds `point` triggers. Fixes must come oldest first.
+    pub fn feed(&mut self, point: &GpsPoint) -> Vec<EventKind> {
+        let previous = self
+            .last
+            .take()
+            .filter(|(last, _)| (1..=MAX_FIX_GAP_SEC).contains(&(point.at_utc - last.at_utc)));
+        let speed = point.speed_mps.or_else(|| {
+            previous
+                .as_ref()
+                .map(|(last, _)| distance_m(last, point) / (point.at_utc - last.at_utc) as f64)
+        });
+
+        let mut kinds = Vec::new();
+        if let (Some(limit), Some(speed)) = (self.cfg.over_kmh, speed)
+            && speed * 3.6 > limit
+        {
+            kinds.push(EventKind::Speeding);
+        }
+        if let (Some(threshold), Some((last, Some(last_speed))), Some(speed)) =
+            (self.cfg.hard_brake_mps2, &previous, speed)
+            && (last_speed - speed) / (point.at_utc - last.at_utc) as f64 > threshold
+        {
+            kinds.push(EventKind::HardBrake);
+        }
+        self.last = Some((point.clone(), speed));
+        kinds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fix(at_utc: i64, lat: f64, speed_mps: Option<f64>) -> GpsPoint {
+        GpsPoint { at_utc, lat, lon: 13.4, alt_m: None, speed_mps, heading_deg: None }
+    }
+
+    #[test]
+    fn speeding_and_hard_braking_from_fixes() {
+        let cfg = SpeedEventsConfig { over_kmh: Some(100.0), hard_brake_mps2: Some(4.0), cameras: Vec::new() };
+        assert!(cfg.is_valid(&[]));
+        assert!(!SpeedEventsConfig::default().is_valid(&[]));
+        assert!(!SpeedEventsConfig { cameras: vec!["side".into()], ..cfg.clone() }.is_valid(&["front"]));
+
+        let mut rules = SpeedRules::new(&cfg);
+        assert_eq!(rules.feed(&fix(0, 52.0, Some(30.0))), vec![EventKind::Speeding], "108 km/h");
+        assert!(rules.feed(&fix(1, 52.0, Some(27.0))).is_empty(), "3 m/s² is ordinary braking");
+        assert_eq!(rules.feed(&fix(2, 52.0, Some(20.0))), vec![EventKind::HardBrake]);
+        // a dropout in between: no deceleration across it
+        assert!(rules.feed(&fix(20, 52.0, Some(0.0))).is_empty());
+
+        // no reported speed: 0.0003° of latitude (~33 m) per second is ~120 km/h
+        let mut rules = SpeedRules::new(&cfg);
+        assert!(rules.feed(&fix(0, 52.0, None)).is_empty());
+        assert_eq!(rules.feed(&fix(1, 52.0003, None)), vec![EventKind::Speeding]);
+        assert_eq!(rules.feed(&fix(2, 52.0003, None)), vec![EventKind::HardBrake], "33 m/s to standstill");
+        assert!((distance_m(&fix(0, 52.0, None), &fix(0, 53.0, None)) - 111_195.0).abs() < 1.0);
+    }
+}
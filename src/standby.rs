@@ -0,0 +1,142 @@
+//! Low-power standby (`[standby]` in config.toml): pipelines stopped, DB
+//! checkpointed, and one snapshot per camera every `snapshot_interval_sec`.
+//! Entered via the control API (or ignition off), left on the API, ignition on,
+//! or any event of a `wake_on` kind.
+//!
+//! With `[snapshot_upload]`, each snapshot is also uploaded, and MQTT requests
+//! trigger extra ones (see `upload`).
+
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::SourceConfig;
+use crate::events::EventKind;
+use crate::mqtt::MqttClient;
+use crate::snapshot::take_snapshot;
+use crate::upload::SnapshotUploader;
+use crate::worker::Worker;
+
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(15);
+const MQTT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+const MQTT_RETRY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StandbyConfig {
+    /// 0 = no snapshots while in standby
+    pub snapshot_interval_sec: u64,
+    /// Events that bring the cameras back immediately
+    pub wake_on: Vec<EventKind>,
+}
+
+impl Default for StandbyConfig {
+    fn default() -> Self {
+        Self {
+            snapshot_interval_sec: 900,
+            wake_on: vec![EventKind::Motion, EventKind::GSensor],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    Active,
+    Standby,
+}
+
+impl PowerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PowerState::Active => "active",
+            PowerState::Standby => "standby",
+        }
+    }
+}
+
+/// Snapshot thread running while in standby. `cameras` is (key, source, camera
+/// dir); snapshots go to `<dir>/standby/<date>/<time>.jpg`.
+pub fn spawn_standby(
+    cfg: &StandbyConfig,
+    cameras: Vec<(String, SourceConfig, PathBuf)>,
+    uploader: Option<SnapshotUploader>,
+) -> Worker {
+    let interval = Duration::from_secs(cfg.snapshot_interval_sec);
+    let mqtt_cfg = uploader.as_ref().and_then(|u| u.cfg.mqtt.clone());
+    let mut mqtt: Option<MqttClient> = None;
+    let mut mqtt_retry_at = Instant::now();
+    let mut next = Instant::now() + interval;
+
+    Worker::spawn("standby", Duration::ZERO, move |stop| {
+        if interval.is_zero() && mqtt_cfg.is_none() {
+            stop.store(true, Ordering::SeqCst);
+            return;
+        }
+        // None = every camera
+        let mut requested: Option<Option<String>> = None;
+        if let Some(mqtt_cfg) = &mqtt_cfg {
+            if mqtt.is_none() && Instant::now() >= mqtt_retry_at {
+                match MqttClient::subscribe(&mqtt_cfg.broker, &mqtt_cfg.client_id, &mqtt_cfg.topic, MQTT_KEEP_ALIVE) {
+                    Ok(client) => {
+                        info!("Listening for snapshot requests on {} ({})", mqtt_cfg.topic, mqtt_cfg.broker);
+                        mqtt = Some(client);
+                    }
+                    Err(e) => {
+                        warn!("{:#}; retrying in {:?}", e, MQTT_RETRY);
+                        mqtt_retry_at = Instant::now() + MQTT_RETRY;
+                    }
+                }
+            }
+            if let Some(client) = &mut mqtt {
+                match client.poll(Duration::from_millis(200)) {
+                    Ok(Some(msg)) => {
+                        let key = String::from_utf8_lossy(&msg.payload).trim().to_string();
+                        requested = Some((!key.is_empty()).then_some(key));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("MQTT connection lost: {:#}", e);
+                        mqtt = None;
+                        mqtt_retry_at = Instant::now() + MQTT_RETRY;
+                    }
+                }
+            }
+        }
+
+        let due = !interval.is_zero() && Instant::now() >= next;
+        if !due && requested.is_none() {
+            if mqtt.is_none() {
+                std::thread::sleep(Duration::from_millis(200));
+            }
+            return;
+        }
+        if due {
+            next += interval;
+        }
+        let only = requested.flatten();
+        for (key, source, dir) in &cameras {
+            if !due && only.as_ref().is_some_and(|only| only != key) {
+                continue;
+            }
+            let now = chrono::Local::now();
+            let location = dir
+                .join("standby")
+                .join(now.format("%Y-%m-%d").to_string())
+                .join(format!("{}.jpg", now.format("%H-%M-%S")));
+            match take_snapshot(source, &location, SNAPSHOT_TIMEOUT) {
+                Ok(()) => info!("Standby snapshot for '{}': {}", key, location.display()),
+                Err(e) => {
+                    warn!("Standby snapshot for '{}' failed: {:#}", key, e);
+                    continue;
+                }
+            }
+            if let Some(uploader) = &uploader
+                && let Err(e) = uploader.upload(key, now, &location)
+            {
+                warn!("Snapshot upload for '{}' failed: {:#}", key, e);
+            }
+        }
+    })
+}
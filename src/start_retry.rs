@@ -0,0 +1,106 @@
+//! Background starts for cameras that didn't come up with the service.
+//!
+//! A USB hub that enumerates slowly, or an RTSP camera that boots after the Pi,
+//! shouldn't keep the other cameras from recording. Startup moves on without
+//! them and this worker tries again every `retry_interval_sec` (per source)
+//! until each one plays. Once a camera has played it is left alone, unless it
+//! has a backup source: those stay watched, and after `after_failures` failed
+//! starts in a row the pipeline is rebuilt on the backup (see `[cameras.failover]`).
+
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::crash;
+use crate::events::{CameraEvent, EventKind};
+use crate::recording_pipeline::{RecordingPipeline, StartState};
+use crate::worker::Worker;
+
+/// Keep retrying `pipelines` (with their retry interval) until they play or the worker is stopped.
+/// `on_failover(camera_key, event)` is called, without the pipeline locked, after a switch.
+pub fn spawn_start_retry<F>(pipelines: Vec<Arc<Mutex<RecordingPipeline>>>, on_failover: F) -> Worker
+where
+    F: Fn(&str, CameraEvent) + Send + 'static,
+{
+    let mut pipelines = Some(pipelines);
+    let mut pending: Vec<(Arc<Mutex<RecordingPipeline>>, Instant)> = Vec::new();
+    Worker::spawn("start_retry", Duration::from_millis(200), move |stop| {
+        if let Some(pipelines) = pipelines.take() {
+            let now = Instant::now();
+            pending = pipelines
+                .into_iter()
+                .filter_map(|p| {
+                    let interval = p.lock().unwrap().start_retry_interval()?;
+                    Some((p, now + interval))
+                })
+                .collect();
+        }
+        pending.retain_mut(|(pipeline_arc, next_attempt)| {
+            if Instant::now() < *next_attempt {
+                return true;
+            }
+            let mut pipeline = pipeline_arc.lock().unwrap();
+            let Some(interval) = pipeline.start_retry_interval() else {
+                return false;
+            };
+            *next_attempt = Instant::now() + interval;
+            let (keep, failover) = retry_start(&mut pipeline);
+            let key = pipeline.camera_key().to_string();
+            drop(pipeline);
+            if let Some(event) = failover {
+                on_failover(&key, event);
+            }
+            keep
+        });
+        if pending.is_empty() {
+            stop.store(true, Ordering::SeqCst);
+        }
+    })
+}
+
+/// One attempt. Returns whether the camera still needs watching, and the
+/// event to raise if it just failed over.
+fn retry_start(pipeline: &mut RecordingPipeline) -> (bool, Option<CameraEvent>) {
+    let key = pipeline.camera_key().to_string();
+    // stopped on purpose; picked up again once the switch, mode or operator says so
+    if pipeline.is_held() {
+        return (true, None);
+    }
+    match pipeline.start_state() {
+        state if state.is_playing() => {
+            if pipeline.start_failures() > 0 || !pipeline.has_backup_source() {
+                info!("Camera '{}' is up ({})", key, state.as_str());
+                crash::note_pipeline_state(&key, "running");
+            }
+            pipeline.reset_start_failures();
+            (pipeline.has_backup_source(), None)
+        }
+        // GStreamer is still at it (RTSP connecting, ...)
+        StartState::Starting | StartState::TimedOut if pipeline.is_running() => (true, None),
+        _ => {
+            let mut failover = None;
+            if pipeline.note_start_failure() {
+                match pipeline.fail_over() {
+                    Ok(description) => {
+                        crash::note_pipeline_state(&key, "failed over");
+                        failover = Some(CameraEvent {
+                            label: Some(format!("backup: {}", description)),
+                            ..CameraEvent::now(EventKind::Failover)
+                        });
+                    }
+                    Err(e) => warn!("Camera '{}' failover failed: {:#}", key, e),
+                }
+            }
+            if let Err(e) = pipeline.check_source_ready() {
+                debug!("Camera '{}' still not ready: {:#}", key, e);
+                return (true, failover);
+            }
+            info!("Retrying start of camera '{}'", key);
+            if let Err(e) = pipeline.begin_start() {
+                warn!("Camera '{}' failed to start again: {:#}", key, e);
+            }
+            (true, failover)
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Storage manager (`[storage_manager]` in config.toml).
+//!
+//! The ring only bounds what one sink writes: several cameras, imports, saved
+//! clips and exports share the card, and a `max_segments` picked for a bigger
+//! card fills a smaller one. Every `interval_sec` the free space under the
+//! recording root is checked; once it drops below `low_water_mb`, the oldest
+//! catalogued segments of any camera are deleted until `high_water_mb` is free
+//! again. Segments an event saved (`protected_segments`), imported footage,
+//! files on spanned disks and the ring slot being written (or opened next) are
+//! never touched. Every deleted file leaves a `low_space` tombstone.
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::db::db::{DeletionReason, PrunableSegment};
+use crate::db::db_worker::DBMessage;
+use crate::pipeline_sinks::disk_span::free_mb;
+use crate::worker::Worker;
+
+/// Segments deleted between two looks at the free space
+const BATCH: i64 = 20;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageManagerConfig {
+    /// Free space (MB) under the recording root below which pruning starts
+    pub low_water_mb: u64,
+    /// Free space (MB) pruning goes on until
+    pub high_water_mb: u64,
+    /// Time between checks; the first one runs at start-up
+    pub interval_sec: u64,
+}
+
+impl Default for StorageManagerConfig {
+    fn default() -> Self {
+        Self { low_water_mb: 1000, high_water_mb: 2000, interval_sec: 30 }
+    }
+}
+
+impl StorageManagerConfig {
+    pub fn is_valid(&self) -> bool {
+        self.low_water_mb > 0 && self.high_water_mb >= self.low_water_mb && self.interval_sec > 0
+    }
+}
+
+/// What one round of pruning did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PruneReport {
+    pub files: usize,
+    pub bytes: u64,
+    /// Rel paths whose file is gone now (deleted, or already missing), by camera id
+    pub forgotten: BTreeMap<i64, Vec<String>>,
+}
+
+/// Delete the files of `segments`. Ones that were already gone are forgotten too;
+/// ones that can't be deleted stay catalogued.
+pub fn delete_segments(segments: &[PrunableSegment], recording_root: &Path) -> PruneReport {
+    let mut report = PruneReport::default();
+    for segment in segments {
+        let path = recording_root.join(&segment.rel_path);
+        let bytes = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                report.files += 1;
+                report.bytes += bytes;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => {
+                warn!("Storage manager couldn't delete {}: {}", path.display(), e);
+                continue;
+            }
+        }
+        report.forgotten.entry(segment.camera_id).or_default().push(segment.rel_path.clone());
+    }
+    report
+}
+
+fn prunable_segments(db_sender: &Sender<DBMessage>) -> Option<Vec<PrunableSegment>> {
+    let (tx, rx) = mpsc::channel();
+    db_sender.send(DBMessage::GetPrunableSegments { limit: BATCH, reply: tx }).ok()?;
+    rx.recv().ok()?
+}
+
+/// Delete the oldest prunable segments until `high_water_mb` is free or nothing
+/// is left to delete. The catalogue learns of each batch before the next is listed.
+pub fn prune(
+    cfg: &StorageManagerConfig,
+    recording_root: &Path,
+    db_sender: &Sender<DBMessage>,
+    stop: &AtomicBool,
+) -> Result<PruneReport> {
+    let mut total = PruneReport::default();
+    while free_mb(recording_root).is_some_and(|free| free < cfg.high_water_mb) && !stop.load(Ordering::SeqCst) {
+        let Some(segments) = prunable_segments(db_sender) else {
+            bail!("couldn't read the segments from the DB");
+        };
+        if segments.is_empty() {
+            warn!("Storage manager: nothing left to delete under {}", recording_root.display());
+            break;
+        }
+        let report = delete_segments(&segments, recording_root);
+        if report.forgotten.is_empty() {
+            // none of them could be deleted; the next batch would be the same
+            break;
+        }
+        total.files += report.files;
+        total.bytes += report.bytes;
+        for (camera_id, rel_paths) in report.forgotten {
+            total.forgotten.entry(camera_id).or_default().extend(rel_paths.iter().cloned());
+            let _ =
+                db_sender.send(DBMessage::ForgetSegments { camera_id, rel_paths, reason: DeletionReason::LowSpace });
+        }
+    }
+    Ok(total)
+}
+
+/// Storage manager thread, checking the free space every `interval_sec`.
+pub fn spawn_storage_manager(
+    cfg: &StorageManagerConfig,
+    recording_root: PathBuf,
+    db_sender: Arc<Sender<DBMessage>>,
+) -> Worker {
+    let cfg = cfg.clone();
+    Worker::spawn("storage_manager", Duration::from_secs(cfg.interval_sec), move |stop| {
+        let Some(free) = free_mb(&recording_root) else {
+            warn!("Storage manager can't read the free space of {}", recording_root.display());
+            return;
+        };
+        if free >= cfg.low_water_mb {
+            return;
+        }
+        warn!(
+            "{} MB free under {}, below {} MB: deleting the oldest segments",
+            free,
+            recording_root.display(),
+            cfg.low_water_mb
+        );
+        match prune(&cfg, &recording_root, &db_sender, stop) {
+            Ok(report) => info!(
+                "Storage manager deleted {} segments ({} bytes), {} MB free",
+                report.files,
+                report.bytes,
+                free_mb(&recording_root).unwrap_or(0)
+            ),
+            Err(e) => warn!("Storage manager stopped pruning: {:#}", e),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deletes_files_and_forgets_the_ones_already_gone() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("front/0")).unwrap();
+        fs::write(root.join("front/0/output_1.ts"), vec![0u8; 100]).unwrap();
+        fs::create_dir_all(root.join("rear/0")).unwrap();
+        fs::write(root.join("rear/0/output_7.ts"), vec![0u8; 50]).unwrap();
+        let segment =
+            |camera_id, rel_path: &str| PrunableSegment { camera_id, rel_path: rel_path.to_string(), end_utc: 0 };
+
+        let report = delete_segments(
+            &[segment(1, "front/0/output_1.ts"), segment(1, "front/0/output_2.ts"), segment(2, "rear/0/output_7.ts")],
+            root,
+        );
+        assert_eq!((report.files, report.bytes), (2, 150));
+        assert_eq!(report.forgotten[&1], vec!["front/0/output_1.ts", "front/0/output_2.ts"]);
+        assert_eq!(report.forgotten[&2], vec!["rear/0/output_7.ts"]);
+        assert!(!root.join("front/0/output_1.ts").exists());
+        assert!(root.join("front/0").exists(), "the ring writes there again");
+    }
+}
@@ -0,0 +1,99 @@
+//! Estimated SD card wear. Every catalogued segment adds its size to a
+//! `bytes_written:<recording root>` counter; set the card's endurance rating
+//! (terabytes written, TBW, from its datasheet) and status and `/healthz` show
+//! how much of it is used, so a card is replaced before it starts corrupting
+//! footage.
+//!
+//! Only segment files count, so this is a lower bound: the file system, the DB
+//! and the card's own write amplification add to it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::health::{HealthState, SubsystemHealth};
+
+const TB: f64 = 1e12;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageWearConfig {
+    /// Rated endurance of the card under the recording root, in TB written; no estimate without it
+    pub tbw_rating_tb: Option<f64>,
+    /// Health turns degraded past this share of the rating
+    pub warn_pct: f64,
+}
+
+impl Default for StorageWearConfig {
+    fn default() -> Self {
+        Self { tbw_rating_tb: None, warn_pct: 80.0 }
+    }
+}
+
+/// Counter key in the `counters` table
+pub fn counter_name(storage_root: &str) -> String {
+    format!("bytes_written:{}", storage_root)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StorageWear {
+    pub root: String,
+    pub bytes_written: u64,
+    /// With `tbw_rating_tb` set
+    pub rated_bytes: Option<u64>,
+    /// Share of the rating written so far
+    pub used_pct: Option<f64>,
+}
+
+impl StorageWear {
+    pub fn new(root: &str, bytes_written: u64, config: &StorageWearConfig) -> Self {
+        let rated_bytes = config.tbw_rating_tb.filter(|tb| *tb > 0.0).map(|tb| (tb * TB) as u64);
+        Self {
+            root: root.to_string(),
+            bytes_written,
+            rated_bytes,
+            used_pct: rated_bytes.map(|rated| bytes_written as f64 * 100.0 / rated as f64),
+        }
+    }
+
+    /// Degraded once `warn_pct` of the rating is used: the card still records,
+    /// but should be replaced.
+    pub fn health(&self, config: &StorageWearConfig) -> SubsystemHealth {
+        let written = format!("{:.2} TB written", self.bytes_written as f64 / TB);
+        let (Some(rated), Some(used_pct)) = (self.rated_bytes, self.used_pct) else {
+            return SubsystemHealth::new("storage_wear", HealthState::Ok, format!("{}, no tbw_rating_tb set", written));
+        };
+        let detail = format!("{} of {:.0} TB rated ({:.0}%)", written, rated as f64 / TB, used_pct);
+        if used_pct >= 100.0 {
+            let detail = format!("{}: past its rated endurance, replace the card", detail);
+            SubsystemHealth::new("storage_wear", HealthState::Degraded, detail)
+        } else if used_pct >= config.warn_pct {
+            SubsystemHealth::new("storage_wear", HealthState::Degraded, format!("{}: replace the card soon", detail))
+        } else {
+            SubsystemHealth::new("storage_wear", HealthState::Ok, detail)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wear_degrades_health_past_the_warning_share() {
+        let config = StorageWearConfig { tbw_rating_tb: Some(40.0), warn_pct: 80.0 };
+        let fresh = StorageWear::new("/mnt/sd", 4_000_000_000_000, &config);
+        assert_eq!(fresh.rated_bytes, Some(40_000_000_000_000));
+        assert_eq!(fresh.used_pct, Some(10.0));
+        let health = fresh.health(&config);
+        assert_eq!(health.state, HealthState::Ok);
+        assert_eq!(health.detail, "4.00 TB written of 40 TB rated (10%)");
+
+        let worn = StorageWear::new("/mnt/sd", 34_000_000_000_000, &config);
+        assert_eq!(worn.health(&config).state, HealthState::Degraded);
+        let spent = StorageWear::new("/mnt/sd", 41_000_000_000_000, &config);
+        assert!(spent.health(&config).detail.ends_with("past its rated endurance, replace the card"));
+
+        let unrated = StorageWear::new("/mnt/sd", 34_000_000_000_000, &StorageWearConfig::default());
+        assert_eq!(unrated.used_pct, None);
+        assert_eq!(unrated.health(&StorageWearConfig::default()).state, HealthState::Ok);
+    }
+}
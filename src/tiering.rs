@@ -0,0 +1,313 @@
+//! Tiered storage (`[tiering]` in config.toml).
+//!
+//! `nvrts` archives grow by a day dir (`<YYYY-MM-DD>/`) a day and are never
+//! overwritten. Once a day dir is `after_days` old, its `.ts` segments are
+//! re-encoded one at a time at `width` (height follows the camera's aspect
+//! ratio) and `bitrate_kbps`, video only: the old tier keeps a visual record
+//! for a fraction of the space. Each file is replaced in place with its
+//! modification time kept, so paths, playlists and exports still line up, and
+//! catalogued rows get the new size and resolution. Done files are listed in
+//! the day dir's `.tiered`, so a restart picks up where it stopped and nothing
+//! is re-encoded twice.
+
+use anyhow::{Context, Result, bail};
+use chrono::{Local, NaiveDate};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::config::{EncoderKind, VideoCodec};
+use crate::db::db_worker::DBMessage;
+use crate::pipeline_sources::encoder::{decoder_factory, media_type, parser_factory};
+use crate::worker::Worker;
+
+/// One segment (a minute or so of video) re-encodes in well under this
+const RETRANSCODE_TIMEOUT: Duration = Duration::from_secs(600);
+/// Names of a day dir's files already re-encoded, one per line
+const DONE_LIST: &str = ".tiered";
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TieringConfig {
+    /// Age in days a day dir reaches before its segments are re-encoded
+    pub after_days: u32,
+    /// Width of the re-encoded video; height follows the camera's aspect ratio
+    pub width: u32,
+    pub bitrate_kbps: u32,
+    /// Camera keys; empty means every camera with an `nvrts` sink
+    pub cameras: Vec<String>,
+    /// Time between passes; the first one runs at start-up
+    pub interval_sec: u64,
+}
+
+impl Default for TieringConfig {
+    fn default() -> Self {
+        Self { after_days: 7, width: 640, bitrate_kbps: 400, cameras: Vec::new(), interval_sec: 3600 }
+    }
+}
+
+impl TieringConfig {
+    pub fn is_valid(&self) -> bool {
+        self.after_days > 0 && self.width >= 16 && self.bitrate_kbps > 0 && self.interval_sec > 0
+    }
+
+    pub fn includes(&self, camera_key: &str) -> bool {
+        self.cameras.is_empty() || self.cameras.iter().any(|c| c == camera_key)
+    }
+}
+
+/// One camera's archive for the worker.
+#[derive(Debug, Clone)]
+pub struct TieredCamera {
+    pub key: String,
+    /// `<recording_root>/<key>`, and `<mount>/<key>` for every disk an `nvrts` sink spans
+    pub dirs: Vec<PathBuf>,
+    pub codec: VideoCodec,
+    /// Size of the re-encoded video
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TierSummary {
+    pub files: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Day dirs in `camera_dir` at least `after_days` before `today`, oldest first.
+/// Anything not named `<YYYY-MM-DD>` (the `dashcamts` ring's numbered subdirs) is left alone.
+pub fn due_days(camera_dir: &Path, today: NaiveDate, after_days: u32) -> Result<Vec<PathBuf>> {
+    let Some(cutoff) = today.checked_sub_days(chrono::Days::new(after_days as u64)) else {
+        return Ok(Vec::new());
+    };
+    let mut days: Vec<(NaiveDate, PathBuf)> = fs::read_dir(camera_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|entry| {
+            let day = NaiveDate::parse_from_str(entry.file_name().to_str()?, "%Y-%m-%d").ok()?;
+            (day <= cutoff).then(|| (day, entry.path()))
+        })
+        .collect();
+    days.sort();
+    Ok(days.into_iter().map(|(_, path)| path).collect())
+}
+
+/// `.ts` segments of `day_dir` its `.tiered` list doesn't name yet, by name (= time).
+pub fn pending_segments(day_dir: &Path) -> Result<Vec<PathBuf>> {
+    let done: HashSet<String> =
+        fs::read_to_string(day_dir.join(DONE_LIST)).unwrap_or_default().lines().map(str::to_string).collect();
+    let mut pending: Vec<PathBuf> = fs::read_dir(day_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "ts"))
+        .filter(|path| path.file_name().and_then(|n| n.to_str()).is_some_and(|n| !done.contains(n)))
+        .collect();
+    pending.sort();
+    Ok(pending)
+}
+
+fn mark_done(day_dir: &Path, segment: &Path) -> Result<()> {
+    let name = segment.file_name().context("Segment without a file name")?.to_string_lossy();
+    let mut list = OpenOptions::new().create(true).append(true).open(day_dir.join(DONE_LIST))?;
+    writeln!(list, "{}", name)?;
+    Ok(())
+}
+
+/// `gst-launch` line that decodes the video of one TS segment, scales it and
+/// encodes it again with the codec's software encoder. Other streams are dropped.
+pub fn retranscode_description(input: &Path, cam: &TieredCamera, bitrate_kbps: u32, output: &Path) -> Result<String> {
+    let Some(encoder) = EncoderKind::software(cam.codec) else {
+        bail!("No software encoder for {:?}", cam.codec);
+    };
+    let parser = parser_factory(cam.codec);
+    Ok(format!(
+        "filesrc location=\"{}\" ! tsdemux name=d d. ! {} ! queue ! {} ! {} ! videoconvert ! videoscale ! \
+         video/x-raw,width={},height={} ! {} bitrate={} speed-preset=veryfast ! {} ! mpegtsmux ! filesink location=\"{}\"",
+        input.display(),
+        media_type(cam.codec),
+        parser,
+        decoder_factory(cam.codec),
+        cam.width,
+        cam.height,
+        encoder.factory(),
+        bitrate_kbps,
+        parser,
+        output.display()
+    ))
+}
+
+/// Re-encode `segment` in place, keeping its modification time. Returns the new
+/// size, or `None` if the re-encode came out no smaller and the original was kept.
+pub fn retranscode_file(segment: &Path, cam: &TieredCamera, bitrate_kbps: u32) -> Result<Option<u64>> {
+    let metadata = fs::metadata(segment).with_context(|| format!("Failed to stat {}", segment.display()))?;
+    let partial = segment.with_extension("ts.part");
+    let pipeline = gst::parse::launch(&retranscode_description(segment, cam, bitrate_kbps, &partial)?)?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Not a pipeline"))?;
+    let bus = pipeline.bus().context("No bus")?;
+
+    pipeline.set_state(gst::State::Playing)?;
+    let msg = bus.timed_pop_filtered(
+        gst::ClockTime::from_seconds(RETRANSCODE_TIMEOUT.as_secs()),
+        &[gst::MessageType::Eos, gst::MessageType::Error],
+    );
+    pipeline.set_state(gst::State::Null)?;
+    match msg.as_ref().map(|m| m.view()) {
+        Some(gst::MessageView::Eos(..)) => {}
+        Some(gst::MessageView::Error(err)) => {
+            let _ = fs::remove_file(&partial);
+            bail!("Re-encoding {} failed: {}", segment.display(), err.error());
+        }
+        _ => {
+            let _ = fs::remove_file(&partial);
+            bail!("Re-encoding {} timed out", segment.display());
+        }
+    }
+
+    let bytes = fs::metadata(&partial)?.len();
+    if bytes == 0 || bytes >= metadata.len() {
+        fs::remove_file(&partial)?;
+        return Ok(None);
+    }
+    // exports and imports date segments by their modification time
+    File::options().write(true).open(&partial)?.set_modified(metadata.modified()?)?;
+    fs::rename(&partial, segment).with_context(|| format!("Failed to move {} into place", partial.display()))?;
+    Ok(Some(bytes))
+}
+
+/// Re-encode what's left of one day dir. Catalogued rows are updated through `db_sender`;
+/// a segment that fails is logged and tried again on the next pass.
+pub fn tier_day(
+    cam: &TieredCamera,
+    day_dir: &Path,
+    bitrate_kbps: u32,
+    recording_root: &Path,
+    db_sender: &Sender<DBMessage>,
+    stop: &AtomicBool,
+) -> Result<TierSummary> {
+    let mut summary = TierSummary::default();
+    for segment in pending_segments(day_dir)? {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let before = fs::metadata(&segment).map(|m| m.len()).unwrap_or(0);
+        let retranscoded = match retranscode_file(&segment, cam, bitrate_kbps) {
+            Ok(retranscoded) => retranscoded,
+            Err(e) => {
+                // left as it is; the next pass tries again
+                warn!("Camera '{}': {:#}", cam.key, e);
+                continue;
+            }
+        };
+        if let Some(bytes) = retranscoded {
+            summary.files += 1;
+            summary.bytes_before += before;
+            summary.bytes_after += bytes;
+            // files on a spanned disk are catalogued by their absolute path
+            let rel_path = segment.strip_prefix(recording_root).unwrap_or(&segment).to_string_lossy().to_string();
+            let _ = db_sender.send(DBMessage::SetSegmentTiered {
+                rel_path,
+                width: cam.width as i64,
+                height: cam.height as i64,
+                bytes: bytes as i64,
+            });
+        }
+        mark_done(day_dir, &segment)?;
+    }
+    Ok(summary)
+}
+
+/// Tiering thread, a pass over every camera's day dirs each `interval_sec`.
+pub fn spawn_tiering(
+    cfg: &TieringConfig,
+    cameras: Vec<TieredCamera>,
+    recording_root: PathBuf,
+    db_sender: Arc<Sender<DBMessage>>,
+) -> Worker {
+    let cfg = cfg.clone();
+    Worker::spawn("tiering", Duration::from_secs(cfg.interval_sec), move |stop| {
+        let today = Local::now().date_naive();
+        for cam in &cameras {
+            for dir in &cam.dirs {
+                let days = match due_days(dir, today, cfg.after_days) {
+                    Ok(days) => days,
+                    Err(e) => {
+                        warn!("Tiering couldn't list {}: {:#}", dir.display(), e);
+                        continue;
+                    }
+                };
+                for day_dir in days {
+                    if stop.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    match tier_day(cam, &day_dir, cfg.bitrate_kbps, &recording_root, &db_sender, stop) {
+                        Ok(summary) if summary.files > 0 => info!(
+                            "Camera '{}' {}: {} segments re-encoded, {} -> {} bytes",
+                            cam.key,
+                            day_dir.display(),
+                            summary.files,
+                            summary.bytes_before,
+                            summary.bytes_after
+                        ),
+                        Ok(_) => {}
+                        Err(e) => warn!("Camera '{}' tiering of {} failed: {:#}", cam.key, day_dir.display(), e),
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_old_day_dirs_and_the_segments_not_done_yet() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let camera_dir = tmp.path();
+        for dir in ["2026-03-01", "2026-03-05", "2026-03-09", "0", "notes"] {
+            fs::create_dir(camera_dir.join(dir)).unwrap();
+        }
+        let today = NaiveDate::from_ymd_opt(2026, 3, 12).unwrap();
+        let days = due_days(camera_dir, today, 7).unwrap();
+        assert_eq!(days, vec![camera_dir.join("2026-03-01"), camera_dir.join("2026-03-05")]);
+
+        let day = &days[1];
+        for name in ["10-00-00.ts", "10-01-00.ts", "10-02-00.mp4", "10-03-00.ts.part"] {
+            fs::write(day.join(name), b"ts").unwrap();
+        }
+        mark_done(day, &day.join("10-00-00.ts")).unwrap();
+        assert_eq!(pending_segments(day).unwrap(), vec![day.join("10-01-00.ts")]);
+    }
+
+    #[test]
+    fn retranscode_scales_and_reencodes_the_video_only() {
+        let cam = TieredCamera {
+            key: "gate".to_string(),
+            dirs: Vec::new(),
+            codec: VideoCodec::H265,
+            width: 640,
+            height: 360,
+        };
+        let line =
+            retranscode_description(Path::new("/r/gate/2026-03-01/10-00-00.ts"), &cam, 400, Path::new("/out.part"))
+                .unwrap();
+        assert!(line.contains("tsdemux name=d d. ! video/x-h265 ! queue ! h265parse ! avdec_h265"));
+        assert!(line.contains("video/x-raw,width=640,height=360 ! x265enc bitrate=400"));
+        assert!(line.ends_with("mpegtsmux ! filesink location=\"/out.part\""));
+
+        let av1 = TieredCamera { codec: VideoCodec::Av1, ..cam };
+        assert!(retranscode_description(Path::new("/in.ts"), &av1, 400, Path::new("/out.part")).is_err());
+    }
+}
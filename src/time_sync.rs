@@ -0,0 +1,131 @@
+//! Clock step detection (`[time_sync]` in config.toml).
+//!
+//! A Pi has no RTC, so it boots with a stale clock until NTP (or GPS) steps it.
+//! Everything recorded before that step carries timestamps that are off by the
+//! step. CLOCK_MONOTONIC isn't stepped, so comparing how far the wall clock
+//! moved against how far the monotonic clock moved between two polls gives the
+//! size of each correction. Corrections are stored in the DB and the segment
+//! rows they affect are annotated, so an export can say how accurate its
+//! timestamps are.
+
+use serde::Deserialize;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::db::db_worker::DBMessage;
+use crate::worker::Worker;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeSyncConfig {
+    /// Smaller jumps are poll jitter, not corrections
+    pub step_threshold_ms: u64,
+    pub poll_interval_sec: u64,
+}
+
+impl Default for TimeSyncConfig {
+    fn default() -> Self {
+        Self { step_threshold_ms: 500, poll_interval_sec: 2 }
+    }
+}
+
+/// One step of the wall clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockCorrection {
+    /// When it happened, on the old clock (the one the affected segments were stamped with)
+    pub at_utc: i64,
+    /// New minus old clock; positive when the clock was behind
+    pub offset_ms: i64,
+    /// Since when the old clock was in use (service start or the previous correction), UTC
+    pub since_utc: i64,
+}
+
+fn unix_ms(t: SystemTime) -> i64 {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
+    }
+}
+
+/// Compares successive (wall, monotonic) readings.
+#[derive(Debug, Clone)]
+pub struct ClockWatch {
+    threshold: Duration,
+    wall_ms: i64,
+    mono: Instant,
+    since_utc: i64,
+}
+
+impl ClockWatch {
+    pub fn new(threshold: Duration, wall: SystemTime, mono: Instant) -> Self {
+        let wall_ms = unix_ms(wall);
+        Self { threshold, wall_ms, mono, since_utc: wall_ms.div_euclid(1000) }
+    }
+
+    /// Take a reading; returns the correction if the wall clock jumped since the last one.
+    pub fn check(&mut self, wall: SystemTime, mono: Instant) -> Option<ClockCorrection> {
+        let expected_ms = self.wall_ms + mono.duration_since(self.mono).as_millis() as i64;
+        let wall_ms = unix_ms(wall);
+        let offset_ms = wall_ms - expected_ms;
+        self.wall_ms = wall_ms;
+        self.mono = mono;
+        if offset_ms.unsigned_abs() < self.threshold.as_millis() as u64 {
+            return None;
+        }
+        let correction = ClockCorrection {
+            at_utc: expected_ms.div_euclid(1000),
+            offset_ms,
+            since_utc: self.since_utc,
+        };
+        self.since_utc = wall_ms.div_euclid(1000);
+        Some(correction)
+    }
+}
+
+/// Polls the clocks and hands every correction to the DB worker.
+pub fn spawn_time_sync(cfg: &TimeSyncConfig, db_sender: Arc<Sender<DBMessage>>) -> Worker {
+    let mut watch =
+        ClockWatch::new(Duration::from_millis(cfg.step_threshold_ms), SystemTime::now(), Instant::now());
+    Worker::spawn("time_sync", Duration::from_secs(cfg.poll_interval_sec.max(1)), move |_| {
+        if let Some(correction) = watch.check(SystemTime::now(), Instant::now()) {
+            warn!(
+                "Wall clock stepped by {:+.3}s; timestamps since {} were off by up to that much",
+                correction.offset_ms as f64 / 1000.0,
+                chrono::DateTime::from_timestamp(correction.since_utc, 0)
+                    .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default()
+            );
+            let _ = db_sender.send(DBMessage::ClockCorrection { correction });
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_show_up_against_the_monotonic_clock() {
+        let boot = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mono = Instant::now();
+        let mut watch = ClockWatch::new(Duration::from_millis(500), boot, mono);
+
+        // 2s apart on both clocks, plus a bit of jitter
+        assert_eq!(watch.check(boot + Duration::from_millis(2100), mono + Duration::from_secs(2)), None);
+
+        // NTP sets the clock 2 hours ahead
+        let stepped = boot + Duration::from_secs(4 + 7200);
+        let correction = watch.check(stepped, mono + Duration::from_secs(4)).unwrap();
+        assert_eq!(correction.since_utc, 1_000_000);
+        assert_eq!(correction.at_utc, 1_000_004);
+        assert_eq!(correction.offset_ms, 7_199_900);
+
+        // the next correction covers only what came after the first one
+        let back = stepped + Duration::from_secs(2) - Duration::from_secs(3);
+        let correction = watch.check(back, mono + Duration::from_secs(6)).unwrap();
+        assert_eq!(correction.offset_ms, -3000);
+        assert_eq!(correction.since_utc, 1_007_204);
+    }
+}
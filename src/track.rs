@@ -0,0 +1,185 @@
+//! GPS tracks as GPX 1.1 and GeoJSON, from `gps_points` rows. Both are plain
+//! text any mapping tool opens; GeoJSON keeps the fix times in a `times`
+//! property since coordinates have no slot for them.
+//!
+//! `dashcam_rs export-track` and `GET /api/track` cut a track for a trip or a
+//! time range, independent of any video.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::fmt::Write;
+
+use crate::db::db::{DashcamDb, GpsPoint};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackFormat {
+    Gpx,
+    GeoJson,
+}
+
+impl TrackFormat {
+    /// `gpx` or `geojson`
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg {
+            "gpx" => Some(TrackFormat::Gpx),
+            "geojson" => Some(TrackFormat::GeoJson),
+            _ => None,
+        }
+    }
+
+    /// GeoJSON for `.geojson`/`.json` files, GPX otherwise
+    pub fn from_path(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("geojson" | "json") => TrackFormat::GeoJson,
+            _ => TrackFormat::Gpx,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TrackFormat::Gpx => "application/gpx+xml",
+            TrackFormat::GeoJson => "application/geo+json",
+        }
+    }
+}
+
+/// What a track covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackRange {
+    /// A row of `trips`; an open trip runs until now
+    Trip(i64),
+    /// `from_utc <= at_utc < to_utc`
+    Between { from_utc: i64, to_utc: i64 },
+}
+
+impl TrackRange {
+    /// Track name in the output
+    pub fn name(&self) -> String {
+        match self {
+            TrackRange::Trip(id) => format!("trip {}", id),
+            TrackRange::Between { from_utc, to_utc } => format!("{} to {}", utc_text(*from_utc), utc_text(*to_utc)),
+        }
+    }
+}
+
+/// Unix seconds or an RFC 3339 time.
+pub fn parse_time(arg: &str) -> Option<i64> {
+    arg.parse()
+        .ok()
+        .or_else(|| DateTime::parse_from_rfc3339(arg).ok().map(|t| t.timestamp()))
+}
+
+/// Fixes covered by `range`, oldest first; `None` if the trip doesn't exist.
+pub fn load_track(db: &DashcamDb, range: TrackRange) -> rusqlite::Result<Option<Vec<GpsPoint>>> {
+    let (from_utc, to_utc) = match range {
+        TrackRange::Trip(id) => match db.get_trip_span(id)? {
+            Some((start, end)) => (start, end.unwrap_or_else(|| Utc::now().timestamp()) + 1),
+            None => return Ok(None),
+        },
+        TrackRange::Between { from_utc, to_utc } => (from_utc, to_utc),
+    };
+    db.gps_points_between(from_utc, to_utc).map(Some)
+}
+
+/// `points` as a GPX or GeoJSON document named after `range`.
+pub fn render(format: TrackFormat, range: TrackRange, points: &[GpsPoint]) -> String {
+    match format {
+        TrackFormat::Gpx => to_gpx(&range.name(), points),
+        TrackFormat::GeoJson => format!("{:#}\n", to_geojson(&range.name(), points)),
+    }
+}
+
+fn utc_text(at_utc: i64) -> String {
+    DateTime::<Utc>::from_timestamp(at_utc, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// One `<trk>` named `name` holding `points` as a single segment.
+pub fn to_gpx(name: &str, points: &[GpsPoint]) -> String {
+    let mut gpx = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <gpx version=\"1.1\" creator=\"dashcam_rs\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+    );
+    let _ = writeln!(gpx, "  <trk>\n    <name>{}</name>\n    <trkseg>", xml_escape(name));
+    for point in points {
+        let _ = write!(gpx, "      <trkpt lat=\"{:.7}\" lon=\"{:.7}\">", point.lat, point.lon);
+        if let Some(alt) = point.alt_m {
+            let _ = write!(gpx, "<ele>{:.1}</ele>", alt);
+        }
+        let _ = writeln!(gpx, "<time>{}</time></trkpt>", utc_text(point.at_utc));
+    }
+    gpx.push_str("    </trkseg>\n  </trk>\n</gpx>\n");
+    gpx
+}
+
+/// A FeatureCollection with one LineString feature named `name`.
+pub fn to_geojson(name: &str, points: &[GpsPoint]) -> serde_json::Value {
+    let coordinates: Vec<Vec<f64>> = points
+        .iter()
+        .map(|p| match p.alt_m {
+            Some(alt) => vec![p.lon, p.lat, alt],
+            None => vec![p.lon, p.lat],
+        })
+        .collect();
+    json!({
+        "type": "FeatureCollection",
+        "features": [{
+            "type": "Feature",
+            "geometry": { "type": "LineString", "coordinates": coordinates },
+            "properties": {
+                "name": name,
+                "times": points.iter().map(|p| utc_text(p.at_utc)).collect::<Vec<_>>(),
+                "speeds_mps": points.iter().map(|p| p.speed_mps).collect::<Vec<_>>(),
+            },
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_points_in_order_with_times() {
+        let points = vec![
+            GpsPoint { at_utc: 1_772_373_600, lat: 52.52, lon: 13.405, alt_m: Some(34.0), speed_mps: Some(12.5), heading_deg: None },
+            GpsPoint { at_utc: 1_772_373_601, lat: 52.5201, lon: 13.4051, alt_m: None, speed_mps: None, heading_deg: None },
+        ];
+        let gpx = to_gpx("front & rear", &points);
+        assert!(gpx.contains("<name>front &amp; rear</name>"));
+        assert!(gpx.contains(
+            "<trkpt lat=\"52.5200000\" lon=\"13.4050000\"><ele>34.0</ele><time>2026-03-01T14:00:00Z</time></trkpt>"
+        ));
+        assert!(gpx.contains("<trkpt lat=\"52.5201000\" lon=\"13.4051000\"><time>2026-03-01T14:00:01Z</time></trkpt>"));
+
+        let geojson = to_geojson("trip", &points);
+        let feature = &geojson["features"][0];
+        assert_eq!(feature["geometry"]["coordinates"], json!([[13.405, 52.52, 34.0], [13.4051, 52.5201]]));
+        assert_eq!(feature["properties"]["times"][1], "2026-03-01T14:00:01Z");
+    }
+
+    #[test]
+    fn ranges_and_formats_from_arguments() {
+        assert_eq!(parse_time("1772373600"), Some(1_772_373_600));
+        assert_eq!(parse_time("2026-03-01T15:00:00+01:00"), Some(1_772_373_600));
+        assert_eq!(parse_time("yesterday"), None);
+
+        assert_eq!(TrackFormat::from_arg("geojson"), Some(TrackFormat::GeoJson));
+        assert_eq!(TrackFormat::from_arg("kml"), None);
+        assert_eq!(TrackFormat::from_path(std::path::Path::new("out/trip.json")), TrackFormat::GeoJson);
+        assert_eq!(TrackFormat::from_path(std::path::Path::new("trip")), TrackFormat::Gpx);
+
+        let range = TrackRange::Between { from_utc: 1_772_373_600, to_utc: 1_772_377_200 };
+        assert_eq!(range.name(), "2026-03-01T14:00:00Z to 2026-03-01T15:00:00Z");
+        assert!(render(TrackFormat::Gpx, TrackRange::Trip(4), &[]).contains("<name>trip 4</name>"));
+    }
+}
@@ -0,0 +1,255 @@
+//! Snapshot upload for remote "is my car okay" checks (`[snapshot_upload]` in config.toml).
+//!
+//! Every standby snapshot is POSTed to `url` as `image/jpeg`, with `camera` and
+//! `taken_at` (Unix seconds) in the query string, instead of streaming video
+//! over LTE. With `mqtt` set, a message on its topic (payload: a camera key, or
+//! empty for all) takes and uploads a fresh snapshot right away. `https://` needs
+//! the `tls` feature (servers are checked against the bundled webpki roots); a
+//! `token` is only ever sent over it, or over plain `http://` to this machine.
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+use crate::bandwidth::RateLimits;
+
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotUploadConfig {
+    /// `http[s]://host[:port]/path` the JPEGs are POSTed to
+    pub url: String,
+    /// Sent as `Authorization: Bearer <token>`
+    pub token: Option<String>,
+    /// Snapshot requests while parked
+    pub mqtt: Option<MqttRequestConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MqttRequestConfig {
+    /// "host:port"
+    pub broker: String,
+    #[serde(default = "default_topic")]
+    pub topic: String,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+}
+
+fn default_topic() -> String {
+    "dashcam/snapshot".to_string()
+}
+
+fn default_client_id() -> String {
+    "dashcam_rs".to_string()
+}
+
+/// Where an upload or webhook is POSTed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpUrl {
+    pub tls: bool,
+    /// Without the brackets of an IPv6 literal
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl HttpUrl {
+    /// Whether a bearer token may go there: encrypted, or not off this machine.
+    pub fn may_carry_token(&self) -> bool {
+        self.tls || self.host == "localhost" || self.host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+    }
+}
+
+/// Parts of an `http://` URL, or of an `https://` one with the `tls` feature.
+pub fn parse_http_url(url: &str) -> Result<HttpUrl> {
+    let (tls, rest) = match (url.strip_prefix("http://"), url.strip_prefix("https://")) {
+        (Some(rest), _) => (false, rest),
+        (None, Some(_)) if !cfg!(feature = "tls") => bail!("https:// URLs need the tls feature, got '{}'", url),
+        (None, Some(rest)) => (true, rest),
+        (None, None) => bail!("Only http:// and https:// URLs are supported, got '{}'", url),
+    };
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    // an IPv6 literal's colons are inside the brackets
+    let port_colon = authority.rfind(':').filter(|&i| !authority[i..].contains(']'));
+    let (host, port) = match port_colon {
+        Some(i) => (&authority[..i], authority[i + 1..].parse().with_context(|| format!("Bad port in '{}'", url))?),
+        None => (authority, if tls { 443 } else { 80 }),
+    };
+    let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    if host.is_empty() {
+        bail!("No host in '{}'", url);
+    }
+    Ok(HttpUrl {
+        tls,
+        host: host.to_string(),
+        port,
+        path: if path.is_empty() { "/".to_string() } else { path.to_string() },
+    })
+}
+
+/// `url` parsed, refused if `token` would cross the network in the clear.
+pub fn check_endpoint(url: &str, token: Option<&str>) -> Result<HttpUrl> {
+    let endpoint = parse_http_url(url)?;
+    if token.is_some() && !endpoint.may_carry_token() {
+        bail!("Refusing to send a token to '{}' over plain http; use https://", url);
+    }
+    Ok(endpoint)
+}
+
+/// Sends JPEGs to the configured endpoint, throttled by `[bandwidth]`.
+#[derive(Debug, Clone)]
+pub struct SnapshotUploader {
+    pub cfg: SnapshotUploadConfig,
+    limits: RateLimits,
+}
+
+impl SnapshotUploader {
+    pub fn new(cfg: SnapshotUploadConfig, limits: RateLimits) -> Self {
+        Self { cfg, limits }
+    }
+
+    /// POST `jpeg` for `camera_key`; fails unless the server answers 2xx.
+    pub fn upload(&self, camera_key: &str, taken_at: chrono::DateTime<chrono::Local>, jpeg: &Path) -> Result<()> {
+        let body = fs::read(jpeg).with_context(|| format!("Can't read {}", jpeg.display()))?;
+        let separator = if self.cfg.url.contains('?') { '&' } else { '?' };
+        let url = format!(
+            "{}{}camera={}&taken_at={}",
+            self.cfg.url,
+            separator,
+            camera_key,
+            taken_at.timestamp()
+        );
+        http_post(&url, self.cfg.token.as_deref(), "image/jpeg", &body, &self.limits)
+            .with_context(|| format!("Upload of {}", jpeg.display()))?;
+        info!("Uploaded snapshot of '{}' ({} KB)", camera_key, body.len() / 1024);
+        Ok(())
+    }
+}
+
+/// POST `body` to an `http://` or `https://` URL; fails unless the server answers 2xx.
+pub fn http_post(url: &str, token: Option<&str>, content_type: &str, body: &[u8], limits: &RateLimits) -> Result<()> {
+    let endpoint = check_endpoint(url, token)?;
+    let (host, port) = (endpoint.host.as_str(), endpoint.port);
+    let stream = TcpStream::connect((host, port)).with_context(|| format!("Can't reach {}:{}", host, port))?;
+    stream.set_read_timeout(Some(UPLOAD_TIMEOUT))?;
+    stream.set_write_timeout(Some(UPLOAD_TIMEOUT))?;
+    let mut head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        endpoint.path,
+        host,
+        content_type,
+        body.len()
+    );
+    if let Some(token) = token {
+        head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+    }
+    head.push_str("\r\n");
+
+    let status = match endpoint.tls {
+        #[cfg(feature = "tls")]
+        true => {
+            let server_name = rustls::pki_types::ServerName::try_from(host.to_string())?;
+            let connection = rustls::ClientConnection::new(client_config()?, server_name)?;
+            exchange(rustls::StreamOwned::new(connection, stream), &head, body, limits)
+        }
+        _ => exchange(stream, &head, body, limits),
+    }
+    .with_context(|| format!("POST to {}:{}", host, port))?;
+    if !(200..300).contains(&status) {
+        bail!("{} answered {}", url, status);
+    }
+    Ok(())
+}
+
+/// Sends the request and reads the status code of the answer.
+fn exchange<S: Read + Write>(mut stream: S, head: &str, body: &[u8], limits: &RateLimits) -> Result<u16> {
+    let mut writer = limits.writer(&mut stream);
+    writer.write_all(head.as_bytes())?;
+    writer.write_all(body)?;
+    writer.flush()?;
+
+    let mut status_line = String::new();
+    BufReader::new(&mut stream).read_line(&mut status_line)?;
+    status_line.split_whitespace().nth(1).and_then(|s| s.parse().ok()).context("No HTTP status in the answer")
+}
+
+/// Trusts the Mozilla roots bundled by `webpki-roots`; a car has no CA store to keep current.
+#[cfg(feature = "tls")]
+fn client_config() -> Result<std::sync::Arc<rustls::ClientConfig>> {
+    use std::sync::Arc;
+
+    let roots = rustls::RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn tokens_only_go_out_encrypted_or_to_loopback() {
+        let url = parse_http_url("http://relay.example:8080/up").unwrap();
+        assert_eq!((url.tls, url.host.as_str(), url.port, url.path.as_str()), (false, "relay.example", 8080, "/up"));
+        assert_eq!(parse_http_url("http://relay.example").unwrap().path, "/");
+        assert_eq!(parse_http_url("http://[::1]:8080/up").unwrap().host, "::1");
+        assert_eq!(
+            parse_http_url("https://relay.example/up").map(|u| u.port).ok(),
+            cfg!(feature = "tls").then_some(443)
+        );
+        assert!(parse_http_url("ftp://relay.example/up").is_err());
+
+        assert!(check_endpoint("http://relay.example/up", None).is_ok());
+        assert!(check_endpoint("http://relay.example/up", Some("s3cret")).is_err());
+        for local in ["http://127.0.0.1:9000/up", "http://localhost/up", "http://[::1]/up"] {
+            assert!(check_endpoint(local, Some("s3cret")).is_ok(), "{}", local);
+        }
+        assert_eq!(check_endpoint("https://relay.example/up", Some("s3cret")).is_ok(), cfg!(feature = "tls"));
+    }
+
+    #[test]
+    fn posts_the_jpeg_with_camera_and_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\xff\xd9") {
+                let n = conn.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            conn.write_all(b"HTTP/1.1 204 No Content\r\n\r\n").unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let jpeg = tmp.path().join("snap.jpg");
+        fs::write(&jpeg, b"\xff\xd8fake\xff\xd9").unwrap();
+        let cfg = SnapshotUploadConfig {
+            url: format!("http://127.0.0.1:{}/snapshots", port),
+            token: Some("s3cret".to_string()),
+            mqtt: None,
+        };
+        SnapshotUploader::new(cfg, RateLimits::default())
+            .upload("front", chrono::Local::now(), &jpeg)
+            .unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /snapshots?camera=front&taken_at="), "{}", request);
+        assert!(request.contains("Authorization: Bearer s3cret\r\n"));
+        assert!(request.contains("Content-Length: 8\r\n"));
+    }
+}
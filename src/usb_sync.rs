@@ -0,0 +1,268 @@
+//! Incident folder sync to USB sticks (`[usb_sync]` in config.toml).
+//!
+//! When a removable drive gets mounted under `mount_root` (udisks/udev automount
+//! does that on most images), the save directory is mirrored into
+//! `<stick>/<folder>/`. Files already on the stick with the same size and
+//! modification time (to FAT's two seconds) are skipped; everything else is
+//! copied to a `.part` file, flushed, read back from the stick and compared byte
+//! for byte with the original, and only then renamed into place, so a stick
+//! pulled out early never holds a half-copied clip under its real name. A `SYNC_DONE.txt` on the stick says when it's safe to unplug.
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::pipeline_sinks::write_mode::drop_cached_pages;
+use crate::worker::Worker;
+
+const DONE_FILE: &str = "SYNC_DONE.txt";
+/// vfat keeps modification times in two-second steps, and exFAT drivers often do too
+const MTIME_TOLERANCE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsbSyncConfig {
+    /// Where removable drives get mounted
+    pub mount_root: String,
+    /// Folder on the stick the save directory is mirrored into
+    pub folder: String,
+    pub poll_interval_sec: u64,
+}
+
+impl Default for UsbSyncConfig {
+    fn default() -> Self {
+        Self {
+            mount_root: "/media".to_string(),
+            folder: "dashcam_incidents".to_string(),
+            poll_interval_sec: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncSummary {
+    pub copied: usize,
+    /// Already on the stick, unchanged
+    pub skipped: usize,
+    pub bytes: u64,
+}
+
+/// `(device, mount point)` of the `/proc/mounts` entries under `mount_root`.
+/// Octal escapes (`\040` for a space in a label) are decoded.
+pub fn mounts_under(proc_mounts: &str, mount_root: &Path) -> Vec<(String, PathBuf)> {
+    proc_mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = fields.next()?;
+            let mount_point = PathBuf::from(unescape_mount(fields.next()?));
+            (device.starts_with("/dev/") && mount_point.starts_with(mount_root) && mount_point != mount_root)
+                .then(|| (device.to_string(), mount_point))
+        })
+        .collect()
+}
+
+fn unescape_mount(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && i + 3 < bytes.len()
+            && let Ok(code) = u8::from_str_radix(&field[i + 1..i + 4], 8)
+        {
+            out.push(code);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// Whether the kernel flags `device` (or the disk it's a partition of) as removable.
+pub fn is_removable(device: &str) -> bool {
+    let Some(name) = Path::new(device).file_name() else {
+        return false;
+    };
+    let block = Path::new("/sys/class/block").join(name);
+    [block.join("removable"), block.join("../removable")]
+        .iter()
+        .any(|flag| fs::read_to_string(flag).is_ok_and(|v| v.trim() == "1"))
+}
+
+fn same_mtime(a: SystemTime, b: SystemTime) -> bool {
+    a.duration_since(b).unwrap_or_else(|e| e.duration()) <= MTIME_TOLERANCE
+}
+
+fn same_contents(a: &Path, b: &Path) -> Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    let (mut buf_a, mut buf_b) = (vec![0u8; 64 * 1024], vec![0u8; 64 * 1024]);
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(b.read(&mut buf_b)? == 0);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Copy `src` to `dst` through `<dst>.part`, verified, with `src`'s mtime.
+fn copy_verified(src: &Path, dst: &Path) -> Result<u64> {
+    let partial = PathBuf::from(format!("{}.part", dst.display()));
+    let bytes = {
+        let mut out = File::create(&partial)?;
+        let bytes = io::copy(&mut File::open(src)?, &mut out)?;
+        out.set_modified(fs::metadata(src)?.modified()?)?;
+        out.sync_all()?;
+        // read it back from the stick, not from what's still cached
+        drop_cached_pages(&out)?;
+        bytes
+    };
+    if !same_contents(src, &partial)? {
+        let _ = fs::remove_file(&partial);
+        bail!("{} doesn't match the original after copying", partial.display());
+    }
+    fs::rename(&partial, dst)?;
+    Ok(bytes)
+}
+
+/// Mirror every file under `src_dir` into `dst_dir`. Nothing is deleted from `dst_dir`.
+pub fn mirror_dir(src_dir: &Path, dst_dir: &Path, stop: &AtomicBool) -> Result<SyncSummary> {
+    let mut summary = SyncSummary::default();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(rel) = dirs.pop() {
+        fs::create_dir_all(dst_dir.join(&rel))?;
+        for entry in fs::read_dir(src_dir.join(&rel))? {
+            if stop.load(Ordering::SeqCst) {
+                return Ok(summary);
+            }
+            let entry = entry?;
+            let rel_path = rel.join(entry.file_name());
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                dirs.push(rel_path);
+                continue;
+            }
+            // clips still being assembled
+            if !metadata.is_file() || rel_path.extension().is_some_and(|e| e == "part") {
+                continue;
+            }
+            let dst = dst_dir.join(&rel_path);
+            if let Ok(existing) = fs::metadata(&dst)
+                && existing.len() == metadata.len()
+                && same_mtime(existing.modified()?, metadata.modified()?)
+            {
+                summary.skipped += 1;
+                continue;
+            }
+            summary.bytes += copy_verified(&entry.path(), &dst)
+                .with_context(|| format!("Failed to copy {}", rel_path.display()))?;
+            summary.copied += 1;
+        }
+    }
+    Ok(summary)
+}
+
+fn sync_stick(save_dir: &Path, mount_point: &Path, folder: &str, stop: &AtomicBool) -> Result<SyncSummary> {
+    let done = mount_point.join(DONE_FILE);
+    let _ = fs::remove_file(&done);
+    let summary = mirror_dir(save_dir, &mount_point.join(folder), stop)?;
+    if stop.load(Ordering::SeqCst) {
+        return Ok(summary);
+    }
+    fs::write(
+        &done,
+        format!(
+            "{} new files, {} already there. Safe to unplug.\n{}\n",
+            summary.copied,
+            summary.skipped,
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+        ),
+    )?;
+    File::open(&done)?.sync_all()?;
+    Ok(summary)
+}
+
+/// Polls the mount table; each stick is synced once per insertion.
+pub fn spawn_usb_sync(cfg: &UsbSyncConfig, save_dir: PathBuf) -> Worker {
+    let cfg = cfg.clone();
+    let mount_root = PathBuf::from(&cfg.mount_root);
+    let mut synced: HashSet<PathBuf> = HashSet::new();
+    Worker::spawn("usb_sync", Duration::from_secs(cfg.poll_interval_sec.max(1)), move |stop| {
+        let mounts = fs::read_to_string("/proc/mounts").unwrap_or_default();
+        let sticks: Vec<_> =
+            mounts_under(&mounts, &mount_root).into_iter().filter(|(device, _)| is_removable(device)).collect();
+        // forget unplugged sticks so they sync again next time
+        synced.retain(|m| sticks.iter().any(|(_, mount)| mount == m));
+
+        for (device, mount_point) in sticks {
+            if synced.contains(&mount_point) || stop.load(Ordering::SeqCst) {
+                continue;
+            }
+            info!("USB stick {} at {}, copying incidents", device, mount_point.display());
+            match sync_stick(&save_dir, &mount_point, &cfg.folder, stop) {
+                Ok(summary) => info!(
+                    "USB sync to {} done: {} files copied ({} MB), {} already there",
+                    mount_point.display(),
+                    summary.copied,
+                    summary.bytes / (1024 * 1024),
+                    summary.skipped
+                ),
+                Err(e) => warn!("USB sync to {} failed: {:#}", mount_point.display(), e),
+            }
+            synced.insert(mount_point);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_sticks_under_the_mount_root() {
+        let mounts = "/dev/mmcblk0p2 / ext4 rw 0 0\n\
+                      tmpfs /media tmpfs rw 0 0\n\
+                      /dev/sda1 /media/pi/MY\\040STICK vfat rw 0 0\n";
+        assert_eq!(
+            mounts_under(mounts, Path::new("/media")),
+            vec![("/dev/sda1".to_string(), PathBuf::from("/media/pi/MY STICK"))]
+        );
+    }
+
+    #[test]
+    fn mirror_copies_new_and_changed_files_only() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let (save, stick) = (tmp.path().join("save"), tmp.path().join("stick"));
+        fs::create_dir_all(save.join("front/2026-03-01")).unwrap();
+        fs::write(save.join("front/2026-03-01/14-00-05_gsensor.mp4"), b"clip one").unwrap();
+        fs::write(save.join("front/2026-03-01/14-10-00_gsensor.mp4.part"), b"half").unwrap();
+        let stop = AtomicBool::new(false);
+
+        let first = mirror_dir(&save, &stick, &stop).unwrap();
+        assert_eq!((first.copied, first.skipped, first.bytes), (1, 0, 8));
+        let copied = stick.join("front/2026-03-01/14-00-05_gsensor.mp4");
+        assert_eq!(fs::read(&copied).unwrap(), b"clip one");
+        assert!(!stick.join("front/2026-03-01/14-10-00_gsensor.mp4.part").exists());
+
+        assert_eq!(mirror_dir(&save, &stick, &stop).unwrap().skipped, 1);
+        // FAT rounds the copy's mtime
+        let rounded = fs::metadata(&copied).unwrap().modified().unwrap() + Duration::from_secs(1);
+        File::options().write(true).open(&copied).unwrap().set_modified(rounded).unwrap();
+        assert_eq!(mirror_dir(&save, &stick, &stop).unwrap().skipped, 1);
+        fs::write(save.join("front/2026-03-01/14-00-05_gsensor.mp4"), b"clip one, longer").unwrap();
+        assert_eq!(mirror_dir(&save, &stick, &stop).unwrap().copied, 1);
+        assert_eq!(fs::read(&copied).unwrap(), b"clip one, longer");
+    }
+}
@@ -0,0 +1,255 @@
+//! Background segment validation (`[validation]` in config.toml).
+//!
+//! Every `interval_sec` the worker decodes the newest few finished segments of
+//! each camera and checks they play and last about as long as configured. A
+//! failing sensor or encoder tends to degrade gradually (short, truncated or
+//! undecodable files) long before it dies, so the first bad sample after a
+//! healthy one raises a `corrupt` event.
+
+use anyhow::{Context, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+use crate::events::{CameraEvent, EventKind};
+use crate::worker::Worker;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Files touched more recently than this may still be written to
+const SETTLE_TIME: Duration = Duration::from_secs(10);
+const SEGMENT_EXTENSIONS: [&str; 3] = ["ts", "mp4", "mkv"];
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    pub interval_sec: u64,
+    /// Newest finished segments checked per camera and round
+    pub sample: usize,
+    /// Shortest acceptable segment, as a share of the configured segment duration
+    pub min_duration_ratio: f64,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            interval_sec: 600,
+            sample: 2,
+            min_duration_ratio: 0.5,
+        }
+    }
+}
+
+/// What decoding a segment end to end found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentProbe {
+    pub frames: u64,
+    /// First to last decoded timestamp
+    pub duration: Duration,
+}
+
+/// Decode `file` to the end, counting frames and the timestamp span.
+pub fn probe_segment(file: &Path, timeout: Duration) -> Result<SegmentProbe> {
+    let line = format!("filesrc location=\"{}\" ! decodebin ! fakesink name=sink", file.display());
+    let pipeline = gst::parse::launch(&line)?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Not a pipeline"))?;
+    let sink_pad = pipeline
+        .by_name("sink")
+        .and_then(|s| s.static_pad("sink"))
+        .context("No fakesink pad")?;
+
+    let frames = Arc::new(AtomicU64::new(0));
+    let span: Arc<Mutex<Option<(gst::ClockTime, gst::ClockTime)>>> = Arc::new(Mutex::new(None));
+    let (counter, probe_span) = (frames.clone(), span.clone());
+    sink_pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(pts) = info.buffer().and_then(|b| b.pts()) {
+            let mut span = probe_span.lock().unwrap();
+            *span = Some(match *span {
+                Some((first, last)) => (first.min(pts), last.max(pts)),
+                None => (pts, pts),
+            });
+        }
+        gst::PadProbeReturn::Ok
+    });
+
+    let bus = pipeline.bus().context("No bus")?;
+    pipeline.set_state(gst::State::Playing)?;
+    let msg = bus.timed_pop_filtered(
+        gst::ClockTime::from_nseconds(timeout.as_nanos() as u64),
+        &[gst::MessageType::Eos, gst::MessageType::Error],
+    );
+    pipeline.set_state(gst::State::Null)?;
+    match msg.as_ref().map(|m| m.view()) {
+        Some(gst::MessageView::Eos(..)) => {}
+        Some(gst::MessageView::Error(err)) => anyhow::bail!("decode error: {}", err.error()),
+        _ => anyhow::bail!("decode timed out"),
+    }
+
+    let duration = span
+        .lock()
+        .unwrap()
+        .map(|(first, last)| Duration::from_nanos((last - first).nseconds()))
+        .unwrap_or_default();
+    Ok(SegmentProbe { frames: frames.load(Ordering::Relaxed), duration })
+}
+
+/// Why a probed segment doesn't pass, if it doesn't.
+pub fn segment_problem(probe: &SegmentProbe, expected: Duration, min_duration_ratio: f64) -> Option<String> {
+    if probe.frames == 0 {
+        return Some("no frames decoded".to_string());
+    }
+    let min = expected.mul_f64(min_duration_ratio);
+    if probe.duration < min {
+        return Some(format!(
+            "{:.1}s long, expected at least {:.1}s",
+            probe.duration.as_secs_f64(),
+            min.as_secs_f64()
+        ));
+    }
+    None
+}
+
+/// Finished recordings in the camera's dated/numbered subdirectories, newest first.
+/// HLS segments sit directly in the camera dir and are skipped.
+pub fn recent_segments(camera_dir: &Path, now: SystemTime) -> Vec<PathBuf> {
+    let mut files: Vec<(SystemTime, PathBuf)> = Vec::new();
+    let Ok(subdirs) = fs::read_dir(camera_dir) else {
+        return Vec::new();
+    };
+    for subdir in subdirs.flatten().map(|e| e.path()).filter(|p| p.is_dir()) {
+        let Ok(entries) = fs::read_dir(&subdir) else {
+            continue;
+        };
+        for path in entries.flatten().map(|e| e.path()) {
+            let is_segment = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| SEGMENT_EXTENSIONS.contains(&e));
+            let Some(modified) = path.metadata().and_then(|m| m.modified()).ok() else {
+                continue;
+            };
+            let settled = now.duration_since(modified).is_ok_and(|age| age >= SETTLE_TIME);
+            if is_segment && settled {
+                files.push((modified, path));
+            }
+        }
+    }
+    files.sort_by_key(|f| std::cmp::Reverse(f.0));
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+/// One camera's view for the worker.
+#[derive(Debug, Clone)]
+pub struct ValidatedCamera {
+    pub key: String,
+    pub dir: PathBuf,
+    /// Configured segment length
+    pub segment_duration: Duration,
+}
+
+/// Healthy/corrupt state of one camera; only the change to corrupt is reported.
+#[derive(Debug, Default)]
+pub struct CameraHealth {
+    corrupt: bool,
+}
+
+impl CameraHealth {
+    /// Record a round's outcome. True when the camera just went bad.
+    pub fn update(&mut self, bad: bool) -> bool {
+        let started = bad && !self.corrupt;
+        self.corrupt = bad;
+        started
+    }
+}
+
+/// Validator thread. `on_corrupt(camera_key, event)` is called once each time a
+/// camera goes from passing to failing.
+pub fn spawn_validation<F>(cfg: &ValidationConfig, cameras: Vec<ValidatedCamera>, on_corrupt: F) -> Worker
+where
+    F: Fn(&str, CameraEvent) + Send + 'static,
+{
+    let cfg = cfg.clone();
+    let mut checked: HashSet<PathBuf> = HashSet::new();
+    let mut health: Vec<CameraHealth> = cameras.iter().map(|_| CameraHealth::default()).collect();
+
+    Worker::spawn("validation", Duration::from_secs(cfg.interval_sec.max(1)), move |_| {
+        for (cam, health) in cameras.iter().zip(health.iter_mut()) {
+            let sample: Vec<PathBuf> = recent_segments(&cam.dir, SystemTime::now())
+                .into_iter()
+                .take(cfg.sample)
+                .filter(|p| !checked.contains(p))
+                .collect();
+            if sample.is_empty() {
+                continue;
+            }
+
+            let mut problems = Vec::new();
+            for file in sample {
+                let problem = match probe_segment(&file, PROBE_TIMEOUT) {
+                    Ok(probe) => segment_problem(&probe, cam.segment_duration, cfg.min_duration_ratio),
+                    Err(e) => Some(format!("{:#}", e)),
+                };
+                if let Some(problem) = problem {
+                    warn!("Camera '{}' segment {} failed validation: {}", cam.key, file.display(), problem);
+                    problems.push(problem);
+                }
+                checked.insert(file);
+            }
+
+            if health.update(!problems.is_empty()) {
+                on_corrupt(&cam.key, CameraEvent::now(EventKind::Corrupt));
+            } else if problems.is_empty() {
+                info!("Camera '{}' recent segments validated OK", cam.key);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_or_empty_segments_fail() {
+        let expected = Duration::from_secs(60);
+        let probe = |frames, secs| SegmentProbe { frames, duration: Duration::from_secs(secs) };
+
+        assert_eq!(segment_problem(&probe(1800, 59), expected, 0.5), None);
+        assert!(segment_problem(&probe(0, 0), expected, 0.5).is_some());
+        assert!(segment_problem(&probe(300, 10), expected, 0.5).unwrap().contains("10.0s long"));
+    }
+
+    #[test]
+    fn health_reports_only_the_change_to_corrupt() {
+        let mut health = CameraHealth::default();
+        assert!(!health.update(false));
+        assert!(health.update(true));
+        assert!(!health.update(true), "still bad, already reported");
+        assert!(!health.update(false));
+        assert!(health.update(true));
+    }
+
+    #[test]
+    fn recent_segments_skips_hls_and_unsettled_files() {
+        let dir = std::env::temp_dir().join(format!("dashcam_validation_{}", std::process::id()));
+        let day = dir.join("2026-01-01");
+        fs::create_dir_all(&day).unwrap();
+        fs::write(dir.join("segment00001.ts"), b"hls").unwrap();
+        fs::write(day.join("10-00-00.ts"), b"a").unwrap();
+        fs::write(day.join("10-00-00.jpg"), b"b").unwrap();
+
+        let later = SystemTime::now() + Duration::from_secs(60);
+        assert_eq!(recent_segments(&dir, later), vec![day.join("10-00-00.ts")]);
+        assert!(recent_segments(&dir, SystemTime::now()).is_empty(), "just written");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+//! Background threads of the service: a tick run every so often until stopped.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Longest a waiting worker goes without looking at its stop flag
+const STOP_POLL: Duration = Duration::from_millis(200);
+
+/// A background thread. Dropping it doesn't stop it; call `stop`.
+pub struct Worker {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Worker {
+    /// Run `tick` on a thread named `name` right away, then `interval` after the
+    /// start of each run. With a zero interval it runs back to back, for ticks that
+    /// wait on something themselves (a socket, a channel, a sensor). `tick` gets the
+    /// stop flag to cut long work short; setting it ends the worker.
+    pub fn spawn<F>(name: &str, interval: Duration, mut tick: F) -> Self
+    where
+        F: FnMut(&AtomicBool) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let mut next = Instant::now();
+                while !thread_stop.load(Ordering::SeqCst) {
+                    let now = Instant::now();
+                    if now < next {
+                        std::thread::sleep((next - now).min(STOP_POLL));
+                        continue;
+                    }
+                    next = now + interval;
+                    tick(&thread_stop);
+                }
+            })
+            .expect("failed to spawn a worker thread");
+        Self { stop, handle }
+    }
+
+    /// Ask the thread to stop and wait for it; a tick under way finishes first.
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let _ = self.handle.join();
+    }
+}
+
+/// Sleep for `duration`, or until `stop` is set.
+pub fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline && !stop.load(Ordering::SeqCst) {
+        std::thread::sleep(STOP_POLL.min(deadline - Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn ticks_right_away_until_stopped_or_it_stops_itself() {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let counted = ticks.clone();
+        let worker = Worker::spawn("test", Duration::from_secs(3600), move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        worker.stop();
+        assert_eq!(ticks.load(Ordering::SeqCst), 1);
+
+        let counted = ticks.clone();
+        let worker = Worker::spawn("test", Duration::ZERO, move |stop| {
+            if counted.fetch_add(1, Ordering::SeqCst) == 3 {
+                stop.store(true, Ordering::SeqCst);
+            }
+        });
+        while ticks.load(Ordering::SeqCst) < 4 {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        worker.stop();
+        assert_eq!(ticks.load(Ordering::SeqCst), 4);
+    }
+}
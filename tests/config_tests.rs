@@ -0,0 +1,1144 @@
+use dashcam_rs::config::{AppConfig, CameraEncoding, CameraRole, EncoderKind, RecordMode, SinkConfig, SourceKind, VideoCodec, verify_app_config};
+use dashcam_rs::control::Role;
+use dashcam_rs::events::{EventKind, EventPolicy};
+use dashcam_rs::export::ExportAudio;
+use dashcam_rs::gps::GpsConfig;
+use dashcam_rs::gsensor::GSensorConfig;
+use dashcam_rs::motion::MotionConfig;
+use dashcam_rs::pipeline_sinks::segmented_file_sink::{ContainerFormat, MuxerConfig};
+use dashcam_rs::pipeline_sinks::throughput::ThroughputConfig;
+use dashcam_rs::pipeline_sinks::write_mode::WriteMode;
+use dashcam_rs::pipeline_sources::audio_source::{AudioCodec, AudioConfig};
+use dashcam_rs::profiles::{Profile, ProfileName};
+use dashcam_rs::roi::RoiMode;
+use dashcam_rs::smart::SmartConfig;
+
+// The shipped config should always parse.
+const SHIPPED_CONFIG: &str = include_str!("../config.toml");
+
+#[test]
+fn minimal_config_fills_in_defaults() {
+    let toml_str = r#"
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+    "#;
+    let cfg: AppConfig = toml::from_str(toml_str).unwrap();
+
+    let profile = Profile::default();
+    assert_eq!(cfg.recording_root(), profile.recording_dir);
+    assert_eq!(cfg.db_path(), profile.db_path);
+    assert!(cfg.global.schema_path.is_none(), "schema should default to the embedded one");
+
+    let cam = &cfg.cameras[0];
+    assert_eq!(cam.key, "dashcam");
+    assert!(cam.enabled);
+    assert!(matches!(cam.role, CameraRole::Dashcam));
+    assert_eq!(cam.source.kind, SourceKind::V4l2);
+    assert_eq!(cam.sinks.len(), 1);
+    match &cam.sinks[0] {
+        SinkConfig::DashcamTs {
+            max_segments,
+            segment_duration_sec,
+            sink_id,
+            enabled,
+            muxer,
+            write_mode,
+            generation_names,
+        } => {
+            assert_eq!(*max_segments, None, "ring size comes from the profile");
+            assert!(!*generation_names, "output_<index>.ts names unless asked");
+            assert_eq!(*muxer, MuxerConfig::default());
+            assert_eq!(*write_mode, WriteMode::Buffered);
+            assert_eq!(*segment_duration_sec, None);
+            assert_eq!(*sink_id, 0);
+            assert!(*enabled);
+        }
+        other => panic!("unexpected default sink {:?}", other),
+    }
+    assert!(verify_app_config(&cfg).is_ok());
+}
+
+#[test]
+fn sink_fields_default_individually() {
+    let toml_str = r#"
+        [[cameras]]
+        key = "front"
+        source = { kind = "libcamera" }
+
+        [[cameras.sinks]]
+        kind = "hls"
+        sink_id = 3
+    "#;
+    let cfg: AppConfig = toml::from_str(toml_str).unwrap();
+    match &cfg.cameras[0].sinks[0] {
+        SinkConfig::Hls { segment_duration_sec, sink_id, .. } => {
+            assert_eq!(*segment_duration_sec, None);
+            assert_eq!(*sink_id, 3);
+        }
+        other => panic!("unexpected sink {:?}", other),
+    }
+}
+
+#[test]
+fn duplicate_default_keys_are_rejected() {
+    let toml_str = r#"
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video2" }
+    "#;
+    let cfg: AppConfig = toml::from_str(toml_str).unwrap();
+    let err = verify_app_config(&cfg).unwrap_err();
+    assert!(err.to_string().contains("More than one camera with key"), "{}", err);
+}
+
+#[test]
+fn shipped_config_parses() {
+    let cfg: AppConfig = toml::from_str(SHIPPED_CONFIG).unwrap();
+    assert!(verify_app_config(&cfg).is_ok());
+}
+
+#[test]
+fn sinks_can_be_disabled_individually() {
+    let toml_str = r#"
+        [[cameras]]
+        source = { kind = "libcamera" }
+
+        [[cameras.sinks]]
+        kind = "dashcamts"
+        sink_id = 0
+
+        [[cameras.sinks]]
+        kind = "hls"
+        sink_id = 1
+        enabled = false
+    "#;
+    let cfg: AppConfig = toml::from_str(toml_str).unwrap();
+    let sinks = &cfg.cameras[0].sinks;
+    assert!(sinks[0].enabled());
+    assert!(!sinks[1].enabled());
+    assert_eq!(sinks[1].sink_id(), 1);
+    assert!(verify_app_config(&cfg).is_ok());
+
+    // the switch is persisted per sink_id, so two sinks can't share one
+    let dup: AppConfig = toml::from_str(&toml_str.replace("sink_id = 1", "sink_id = 0")).unwrap();
+    let err = verify_app_config(&dup).unwrap_err();
+    assert!(err.to_string().contains("more than one sink with sink_id=0"), "{}", err);
+}
+
+#[test]
+fn profile_is_selected_at_runtime_with_overrides() {
+    let toml_str = r#"
+        [global]
+        profile = "dev"
+        db_path = "/tmp/explicit.db"
+
+        [profiles.dev]
+        video_width = 800
+        segments_to_keep = 100
+
+        [[cameras]]
+        source = { kind = "libcamera" }
+    "#;
+    let cfg: AppConfig = toml::from_str(toml_str).unwrap();
+    assert_eq!(cfg.global.profile, ProfileName::Dev);
+
+    let profile = cfg.profile();
+    assert_eq!(profile.video_width, 800, "override applies");
+    assert_eq!(profile.video_height, Profile::dev().video_height, "rest stays built-in");
+    assert_eq!(profile.segments_to_keep, 100);
+
+    // explicit [global] paths win over the profile
+    assert_eq!(cfg.db_path(), "/tmp/explicit.db");
+    assert_eq!(cfg.recording_root(), Profile::dev().recording_dir);
+}
+
+#[test]
+fn production_profile_can_be_used_from_any_build() {
+    let toml_str = r#"
+        [global]
+        profile = "production"
+
+        [[cameras]]
+        source = { kind = "libcamera" }
+    "#;
+    let cfg: AppConfig = toml::from_str(toml_str).unwrap();
+    assert_eq!(cfg.profile(), Profile::production());
+}
+
+#[test]
+fn passthrough_source_parses_codec() {
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video2", passthrough = true, codec = "h265" }
+        "#,
+    )
+    .unwrap();
+    assert!(cfg.cameras[0].source.passthrough);
+    assert_eq!(cfg.cameras[0].source.codec, VideoCodec::H265);
+    assert!(verify_app_config(&cfg).is_ok());
+}
+
+#[test]
+fn libcamera_passthrough_rejected() {
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [[cameras]]
+        source = { kind = "libcamera", passthrough = true }
+        "#,
+    )
+    .unwrap();
+    assert!(verify_app_config(&cfg).is_err());
+}
+
+#[test]
+fn hardware_encoder_is_picked_per_source() {
+    let parse = |source: &str| -> AppConfig { toml::from_str(&format!("[[cameras]]\nsource = {}", source)).unwrap() };
+
+    let cfg = parse(r#"{ kind = "libcamera", encoder = "v4l2h264enc" }"#);
+    assert_eq!(cfg.cameras[0].source.encoder, Some(EncoderKind::V4l2h264enc));
+    assert!(verify_app_config(&cfg).is_ok());
+    assert_eq!(parse(r#"{ kind = "libcamera" }"#).cameras[0].source.encoder, None);
+    assert!(verify_app_config(&parse(r#"{ kind = "v4l2", device = "/dev/video0", encoder = "vaapih264enc" }"#)).is_ok());
+    let hevc = parse(r#"{ kind = "v4l2", device = "/dev/video0", codec = "h265", encoder = "v4l2h265enc" }"#);
+    assert_eq!(hevc.cameras[0].source.encoder.map(|e| e.codec()), Some(VideoCodec::H265));
+    assert!(verify_app_config(&hevc).is_ok());
+    assert_eq!(EncoderKind::software(VideoCodec::H265), Some(EncoderKind::X265enc));
+
+    // nothing to encode, or the wrong codec
+    assert!(verify_app_config(&parse(r#"{ kind = "v4l2", device = "/dev/video0", passthrough = true, encoder = "x264enc" }"#)).is_err());
+    assert!(verify_app_config(&parse(r#"{ kind = "rtsp", rtsp_url = "rtsp://cam/main", encoder = "x264enc" }"#)).is_err());
+    assert!(verify_app_config(&parse(r#"{ kind = "libcamera", codec = "h265", encoder = "v4l2h264enc" }"#)).is_err());
+    assert!(verify_app_config(&parse(r#"{ kind = "libcamera", encoder = "x265enc" }"#)).is_err());
+    assert!(toml::from_str::<AppConfig>("[[cameras]]\nsource = { kind = \"libcamera\", encoder = \"nvh264enc\" }").is_err());
+}
+
+#[test]
+fn encoder_settings_are_set_per_camera() {
+    let parse = |source: &str, encoding: &str| -> AppConfig {
+        toml::from_str(&format!("[[cameras]]\nsource = {}\nencoding = {}", source, encoding)).unwrap()
+    };
+    let libcamera = r#"{ kind = "libcamera" }"#;
+
+    let cfg = parse(
+        libcamera,
+        r#"{ bitrate_kbps = 4000, speed_preset = "veryfast", tune = "zerolatency", keyframe_interval = 15 }"#,
+    );
+    assert_eq!(
+        cfg.cameras[0].encoding,
+        CameraEncoding {
+            bitrate_kbps: Some(4000),
+            speed_preset: Some("veryfast".into()),
+            tune: Some("zerolatency".into()),
+            keyframe_interval: Some(15),
+        }
+    );
+    assert!(verify_app_config(&cfg).is_ok());
+    assert!(verify_app_config(&parse(libcamera, r#"{ tune = "zerolatency+fastdecode" }"#)).is_ok());
+    assert!(verify_app_config(&parse(r#"{ kind = "libcamera", codec = "h265" }"#, r#"{ tune = "grain" }"#)).is_ok());
+    assert!(verify_app_config(&parse(r#"{ kind = "libcamera", encoder = "v4l2h264enc" }"#, "{ bitrate_kbps = 3000 }")).is_ok());
+
+    // GStreamer would panic on these
+    assert!(verify_app_config(&parse(libcamera, r#"{ speed_preset = "ludicrous" }"#)).is_err());
+    assert!(verify_app_config(&parse(libcamera, r#"{ tune = "grain" }"#)).is_err());
+    // no preset on hardware encoders, nothing to encode on passthrough
+    assert!(verify_app_config(&parse(r#"{ kind = "libcamera", encoder = "v4l2h264enc" }"#, r#"{ tune = "zerolatency" }"#)).is_err());
+    assert!(verify_app_config(&parse(r#"{ kind = "rtsp", rtsp_url = "rtsp://cam/main" }"#, "{ bitrate_kbps = 3000 }")).is_err());
+    assert!(verify_app_config(&parse(libcamera, "{ keyframe_interval = 0 }")).is_err());
+}
+
+#[test]
+fn hls_transcode_is_opt_in() {
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video2", passthrough = true, codec = "h265" }
+
+        [[cameras.sinks]]
+        kind = "hls"
+        sink_id = 1
+
+        [[cameras.sinks]]
+        kind = "hls"
+        sink_id = 2
+        transcode = true
+        "#,
+    )
+    .unwrap();
+    let transcode: Vec<bool> = cfg.cameras[0]
+        .sinks
+        .iter()
+        .map(|s| matches!(s, SinkConfig::Hls { transcode: true, .. }))
+        .collect();
+    assert_eq!(transcode, vec![false, true]);
+}
+
+#[test]
+fn av1_encode_needs_feature() {
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0", codec = "av1" }
+
+        [[cameras.sinks]]
+        kind = "mp4"
+        sink_id = 0
+        "#,
+    )
+    .unwrap();
+    assert_eq!(cfg.cameras[0].source.codec, VideoCodec::Av1);
+    assert_eq!(verify_app_config(&cfg).is_ok(), cfg!(feature = "av1"));
+}
+
+#[test]
+fn timelapse_sink_defaults_interval() {
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+
+        [[cameras.sinks]]
+        kind = "timelapse"
+        sink_id = 3
+        "#,
+    )
+    .unwrap();
+    match &cfg.cameras[0].sinks[0] {
+        SinkConfig::Timelapse { interval_sec, sink_id, enabled } => {
+            assert_eq!(*interval_sec, 60);
+            assert_eq!(*sink_id, 3);
+            assert!(*enabled);
+        }
+        other => panic!("expected timelapse sink, got {:?}", other),
+    }
+    assert_eq!(cfg.cameras[0].sinks[0].segment_duration_sec(), None);
+}
+
+#[test]
+fn burst_sink_defaults() {
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+
+        [[cameras.sinks]]
+        kind = "burst"
+        sink_id = 4
+        "#,
+    )
+    .unwrap();
+    match &cfg.cameras[0].sinks[0] {
+        SinkConfig::Burst { frames, window_sec, sink_id, enabled } => {
+            assert_eq!((*frames, *window_sec, *sink_id, *enabled), (5, 2, 4, true));
+        }
+        other => panic!("expected burst sink, got {:?}", other),
+    }
+}
+
+#[test]
+fn roi_polygons_parse_and_validate() {
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+
+        [[cameras.roi]]
+        name   = "driveway"
+        points = [[0.0, 0.5], [1.0, 0.5], [1.0, 1.0], [0.0, 1.0]]
+
+        [[cameras.roi]]
+        name   = "road"
+        mode   = "exclude"
+        points = [[0.0, 0.9], [1.0, 0.9], [1.0, 1.0]]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(cfg.cameras[0].roi.len(), 2);
+    assert_eq!(cfg.cameras[0].roi[1].mode, RoiMode::Exclude);
+    assert!(verify_app_config(&cfg).is_ok());
+
+    let bad: AppConfig = toml::from_str(
+        r#"
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+
+        [[cameras.roi]]
+        points = [[0.0, 0.5], [1.0, 0.5]]
+        "#,
+    )
+    .unwrap();
+    assert!(verify_app_config(&bad).is_err(), "a 2-point polygon isn't a region");
+}
+
+#[test]
+fn event_policies_parse_per_kind() {
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [events.object]
+        cooldown_sec = 60
+        consecutive  = 3
+
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        cfg.events.get(&EventKind::Object),
+        Some(&EventPolicy { cooldown_sec: 60, consecutive: 3, max_gap_sec: 5 })
+    );
+    assert!(!cfg.events.contains_key(&EventKind::Motion));
+}
+
+#[test]
+fn http_tokens_parse_and_must_be_unique() {
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [http]
+        bind = "127.0.0.1:9000"
+
+        [[http.auth.tokens]]
+        name  = "hallway-tablet"
+        token = "view-secret"
+        role  = "viewer"
+
+        [[http.auth.tokens]]
+        name  = "me"
+        token = "admin-secret"
+        role  = "admin"
+
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+        "#,
+    )
+    .unwrap();
+    let http = cfg.http.as_ref().unwrap();
+    assert_eq!(http.bind, "127.0.0.1:9000");
+    assert_eq!(http.auth.tokens[0].role, Role::Viewer);
+    assert!(verify_app_config(&cfg).is_ok());
+
+    let dup: AppConfig = toml::from_str(
+        r#"
+        [[http.auth.tokens]]
+        name  = "a"
+        token = "same"
+        role  = "viewer"
+
+        [[http.auth.tokens]]
+        name  = "b"
+        token = "same"
+        role  = "admin"
+
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(dup.http.as_ref().unwrap().bind, "0.0.0.0:8080");
+    assert!(verify_app_config(&dup).is_err(), "two tokens with the same secret");
+}
+
+#[test]
+fn http_tls_needs_paths_or_self_signed() {
+    let parse = |tls: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[http.tls]\n{}\n[[cameras]]\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            tls
+        ))
+        .unwrap()
+    };
+
+    let self_signed = parse("self_signed = true");
+    assert_eq!(self_signed.http.as_ref().unwrap().tls.as_ref().unwrap().names, ["dashcam.local", "localhost"]);
+    assert_eq!(verify_app_config(&self_signed).is_ok(), cfg!(feature = "tls"));
+
+    let pem = parse("cert_path = \"/etc/dashcam/cert.pem\"\nkey_path = \"/etc/dashcam/key.pem\"");
+    assert_eq!(verify_app_config(&pem).is_ok(), cfg!(feature = "tls"));
+
+    assert!(verify_app_config(&parse("cert_path = \"/etc/dashcam/cert.pem\"")).is_err(), "key missing");
+    assert!(verify_app_config(&parse("")).is_err(), "nothing to serve with");
+}
+
+#[test]
+fn standby_defaults_and_overrides() {
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(cfg.standby.snapshot_interval_sec, 900);
+    assert_eq!(cfg.standby.wake_on, vec![EventKind::Motion, EventKind::GSensor]);
+
+    let cfg: AppConfig = toml::from_str(
+        r#"
+        [standby]
+        snapshot_interval_sec = 0
+        wake_on = ["gsensor"]
+
+        [[cameras]]
+        source = { kind = "v4l2", device = "/dev/video0" }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(cfg.standby.snapshot_interval_sec, 0);
+    assert_eq!(cfg.standby.wake_on, vec![EventKind::GSensor]);
+}
+
+#[test]
+fn validation_is_optional_and_checked() {
+    let parse = |extra: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "{}\n[[cameras]]\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            extra
+        ))
+        .unwrap()
+    };
+    assert!(parse("").validation.is_none());
+
+    let cfg = parse("[validation]\nsample = 3");
+    let validation = cfg.validation.as_ref().unwrap();
+    assert_eq!((validation.interval_sec, validation.sample), (600, 3));
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(verify_app_config(&parse("[validation]\nsample = 0")).is_err());
+    assert!(verify_app_config(&parse("[validation]\nmin_duration_ratio = 1.5")).is_err());
+}
+
+#[test]
+fn camera_start_timeout_defaults_to_20s() {
+    let parse = |global: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[global]\n{}\n[[cameras]]\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            global
+        ))
+        .unwrap()
+    };
+    assert_eq!(parse("").camera_start_timeout().as_secs(), 20);
+    assert_eq!(parse("camera_start_timeout_sec = 5").camera_start_timeout().as_secs(), 5);
+}
+
+#[test]
+fn muxer_is_configurable_per_file_sink() {
+    let parse = |sink: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[[cameras]]\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n[[cameras.sinks]]\n{}\n",
+            sink
+        ))
+        .unwrap()
+    };
+
+    let cfg = parse("kind = \"nvrts\"\nsink_id = 0\nmuxer = { alignment = 7, pat_interval_ms = 50 }");
+    let (container, muxer) = cfg.cameras[0].sinks[0].muxer().unwrap();
+    assert_eq!(container, ContainerFormat::Ts);
+    assert_eq!(muxer.factory_for(container), "mpegtsmux");
+    assert_eq!((muxer.alignment, muxer.pat_interval_ms), (Some(7), Some(50)));
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(verify_app_config(&parse("kind = \"dashcamts\"\nsink_id = 0\nmuxer = { factory = \"avmux_mpegts\" }")).is_ok());
+    assert!(verify_app_config(&parse("kind = \"mp4\"\nsink_id = 0\nmuxer = { factory = \"qtmux\" }")).is_ok());
+    // wrong container
+    assert!(verify_app_config(&parse("kind = \"mp4\"\nsink_id = 0\nmuxer = { factory = \"mpegtsmux\" }")).is_err());
+    // mpegtsmux-only tuning
+    assert!(verify_app_config(&parse(
+        "kind = \"nvrts\"\nsink_id = 0\nmuxer = { factory = \"avmux_mpegts\", alignment = 7 }"
+    )).is_err());
+    assert!(verify_app_config(&parse("kind = \"nvrts\"\nsink_id = 0\nmuxer = { alignment = 0 }")).is_err());
+    assert!(parse("kind = \"hls\"\nsink_id = 0").cameras[0].sinks[0].muxer().is_none());
+
+    let mkv = parse("kind = \"mkv\"\nsink_id = 0");
+    assert_eq!(mkv.cameras[0].sinks[0].muxer().unwrap().0, ContainerFormat::Mkv);
+    assert!(verify_app_config(&mkv).is_ok());
+    assert!(verify_app_config(&parse("kind = \"mkv\"\nsink_id = 0\nmuxer = { factory = \"mp4mux\" }")).is_err());
+}
+
+#[test]
+fn nvrts_disks_need_distinct_absolute_mount_points() {
+    use dashcam_rs::pipeline_sinks::disk_span::DiskSpread;
+
+    let parse = |disks: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[[cameras]]\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n[[cameras.sinks]]\nkind = \"nvrts\"\nsink_id = 0\ndisks = {}\n",
+            disks
+        ))
+        .unwrap()
+    };
+
+    let cfg = parse(r#"{ mounts = ["/mnt/nvr1", "/mnt/nvr2"], spread = "round_robin" }"#);
+    assert!(verify_app_config(&cfg).is_ok());
+    let SinkConfig::NvrTs { disks: Some(disks), .. } = &cfg.cameras[0].sinks[0] else {
+        panic!("expected an nvrts sink with disks");
+    };
+    assert_eq!((disks.spread, disks.min_free_mb), (DiskSpread::RoundRobin, 1024));
+    assert!(verify_app_config(&parse("{ mounts = [] }")).is_err());
+    assert!(verify_app_config(&parse(r#"{ mounts = ["nvr1"] }"#)).is_err());
+    assert!(verify_app_config(&parse(r#"{ mounts = ["/mnt/nvr1", "/mnt/nvr1"] }"#)).is_err());
+}
+
+#[test]
+fn write_mode_is_set_per_file_sink() {
+    let parse = |sink: &str| {
+        toml::from_str::<AppConfig>(&format!(
+            "[[cameras]]\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n[[cameras.sinks]]\n{}\n",
+            sink
+        ))
+        .map(|cfg| cfg.cameras[0].sinks[0].write_mode())
+    };
+
+    assert_eq!(parse("kind = \"dashcamts\"\nsink_id = 0\nwrite_mode = \"sync\"").unwrap(), WriteMode::Sync);
+    assert_eq!(parse("kind = \"mp4\"\nsink_id = 0\nwrite_mode = \"drop_cache\"").unwrap(), WriteMode::DropCache);
+    assert_eq!(parse("kind = \"nvrts\"\nsink_id = 0").unwrap(), WriteMode::Buffered);
+    // filesink can't do O_DIRECT
+    assert!(parse("kind = \"nvrts\"\nsink_id = 0\nwrite_mode = \"direct\"").is_err());
+}
+
+#[test]
+fn audio_is_muxed_with_a_codec_the_sinks_can_carry() {
+    let parse = |audio: &str, sink: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[[cameras]]\nsource = {{ kind = \"libcamera\" }}\naudio = {}\n[[cameras.sinks]]\n{}\nsink_id = 0\n",
+            audio, sink
+        ))
+        .unwrap()
+    };
+
+    let cfg = parse(r#"{ device = "hw:1,0" }"#, r#"kind = "hls""#);
+    assert_eq!(
+        cfg.cameras[0].audio,
+        Some(AudioConfig { device: Some("hw:1,0".into()), codec: AudioCodec::Aac, bitrate_kbps: 64 })
+    );
+    assert!(verify_app_config(&cfg).is_ok());
+    assert!(verify_app_config(&parse(r#"{ codec = "opus", bitrate_kbps = 32 }"#, r#"kind = "dashcamts""#)).is_ok());
+    assert!(toml::from_str::<AppConfig>("[[cameras]]\nsource = { kind = \"libcamera\" }\n").unwrap().cameras[0].audio.is_none());
+
+    // browsers don't play Opus over HLS
+    assert!(verify_app_config(&parse(r#"{ codec = "opus" }"#, r#"kind = "hls""#)).is_err());
+    assert!(verify_app_config(&parse("{ bitrate_kbps = 0 }", r#"kind = "nvrts""#)).is_err());
+    assert!(verify_app_config(&parse(r#"{ device = "" }"#, r#"kind = "nvrts""#)).is_err());
+}
+
+#[test]
+fn staging_dir_must_be_absolute_and_outside_the_recording_root() {
+    let parse = |dir: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[global]\nrecording_root = \"/var/lib/dashcam/recordings\"\n[staging]\ndir = \"{}\"\n\
+             [[cameras]]\nsource = {{ kind = \"libcamera\" }}\n",
+            dir
+        ))
+        .unwrap()
+    };
+
+    let cfg = parse("/dev/shm/dashcam");
+    assert_eq!(cfg.staging.as_ref().unwrap().dir, "/dev/shm/dashcam");
+    assert!(verify_app_config(&cfg).is_ok());
+    assert!(verify_app_config(&parse("shm/dashcam")).is_err());
+    assert!(verify_app_config(&parse("/var/lib/dashcam/recordings/staging")).is_err());
+}
+
+#[test]
+fn staging_retries_a_network_share_within_a_bounded_spool() {
+    let parse = |extra: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[staging]\ndir = \"/dev/shm/dashcam\"\n{}\n[[cameras]]\nsource = {{ kind = \"libcamera\" }}\n",
+            extra
+        ))
+        .unwrap()
+    };
+
+    let staging = parse("").staging.unwrap();
+    assert_eq!((staging.retry_sec, staging.spool_mb), (10, 256));
+    assert_eq!(parse("retry_sec = 30\nspool_mb = 512").staging.unwrap().spool_mb, 512);
+    assert!(verify_app_config(&parse("retry_sec = 0")).is_err());
+    assert!(verify_app_config(&parse("spool_mb = 0")).is_err());
+}
+
+#[test]
+fn throughput_check_is_on_by_default_and_bounded() {
+    let parse = |section: &str| -> AppConfig {
+        toml::from_str(&format!("{}\n[[cameras]]\nsource = {{ kind = \"libcamera\" }}\n", section)).unwrap()
+    };
+
+    let cfg = parse("");
+    assert_eq!(cfg.throughput, ThroughputConfig { min_ratio: 0.75, sustained_sec: 120 });
+    assert!(cfg.throughput.enabled());
+    let off = parse("[throughput]\nmin_ratio = 0");
+    assert!(!off.throughput.enabled() && verify_app_config(&off).is_ok());
+    assert!(verify_app_config(&parse("[throughput]\nmin_ratio = 1.5")).is_err());
+    assert!(verify_app_config(&parse("[throughput]\nsustained_sec = 0")).is_err());
+}
+
+#[test]
+fn gps_reads_gpsd_unless_a_device_is_set() {
+    let parse = |section: &str| -> AppConfig {
+        toml::from_str(&format!("{}\n[[cameras]]\nsource = {{ kind = \"libcamera\" }}\n", section)).unwrap()
+    };
+
+    let gpsd = parse("[gps]").gps.unwrap();
+    assert_eq!(gpsd, GpsConfig::default());
+    assert_eq!(gpsd.source(), "127.0.0.1:2947");
+    let serial = parse("[gps]\ndevice = \"/dev/ttyACM0\"\nbaud = 4800");
+    assert!(verify_app_config(&serial).is_ok());
+    assert_eq!(serial.gps.unwrap().source(), "/dev/ttyACM0");
+    assert!(verify_app_config(&parse("[gps]\ndevice = \"ttyACM0\"")).is_err());
+    assert!(verify_app_config(&parse("[gps]\ngpsd = \"\"")).is_err());
+    assert!(verify_app_config(&parse("[gps]\ninterval_ms = 0")).is_err());
+}
+
+#[test]
+fn gsensor_reads_iio_unless_an_i2c_bus_is_set() {
+    let parse = |section: &str| -> AppConfig {
+        toml::from_str(&format!("{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"libcamera\" }}\n", section))
+            .unwrap()
+    };
+
+    let iio = parse("[gsensor]").gsensor.unwrap();
+    assert_eq!(iio, GSensorConfig::default());
+    assert_eq!(iio.source(), "/sys/bus/iio/devices/iio:device0");
+    let i2c = parse("[gsensor]\ni2c_bus = \"/dev/i2c-1\"\ni2c_address = 0x1D\ncameras = [\"front\"]");
+    assert!(verify_app_config(&i2c).is_ok());
+    assert_eq!(i2c.gsensor.unwrap().i2c_address, 0x1D);
+    assert!(verify_app_config(&parse("[gsensor]\ni2c_bus = \"i2c-1\"")).is_err());
+    assert!(verify_app_config(&parse("[gsensor]\nthreshold_g = 0.0")).is_err());
+    assert!(verify_app_config(&parse("[gsensor]\nsample_hz = 0")).is_err());
+    assert!(verify_app_config(&parse("[gsensor]\ncameras = [\"rear\"]")).is_err());
+}
+
+#[test]
+fn gps_overlay_needs_gps_and_raw_frames() {
+    let parse = |gps: &str, source: &str| -> AppConfig {
+        toml::from_str(&format!("{}\n[[cameras]]\noverlay_gps = true\nsource = {{ {} }}\n", gps, source)).unwrap()
+    };
+
+    let cfg = parse("[gps]", r#"kind = "libcamera""#);
+    assert!(cfg.cameras[0].overlay_gps);
+    assert!(verify_app_config(&cfg).is_ok());
+    assert!(verify_app_config(&parse("", r#"kind = "libcamera""#)).is_err());
+    assert!(verify_app_config(&parse("[gps]", r#"kind = "rtsp", rtsp_url = "rtsp://10.0.0.2/stream""#)).is_err());
+}
+
+#[test]
+fn smart_needs_absolute_device_paths() {
+    let parse = |section: &str| -> AppConfig {
+        toml::from_str(&format!("{}\n[[cameras]]\nsource = {{ kind = \"libcamera\" }}\n", section)).unwrap()
+    };
+
+    assert_eq!(parse("").smart, None);
+    let cfg = parse("[smart]\ndevices = [\"/dev/sda\"]\ndevice_type = \"sat\"");
+    assert!(verify_app_config(&cfg).is_ok());
+    assert_eq!(
+        cfg.smart,
+        Some(SmartConfig {
+            devices: vec!["/dev/sda".to_string()],
+            device_type: Some("sat".to_string()),
+            ..SmartConfig::default()
+        })
+    );
+    assert!(verify_app_config(&parse("[smart]\ndevices = []")).is_err());
+    assert!(verify_app_config(&parse("[smart]\ndevices = [\"sda\"]")).is_err());
+    assert!(verify_app_config(&parse("[smart]\ndevices = [\"/dev/sda\"]\ntrend_days = 0")).is_err());
+}
+
+#[test]
+fn rollup_needs_archive_dir_valid_time_and_known_cameras() {
+    let parse = |rollup: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            rollup
+        ))
+        .unwrap()
+    };
+    assert!(parse("").rollup.is_none());
+
+    let cfg = parse("[rollup]\narchive_dir = \"/mnt/usb/archive\"");
+    let rollup = cfg.rollup.as_ref().unwrap();
+    assert_eq!(rollup.run_at, "03:00");
+    assert!(rollup.includes("front"), "no list means every camera");
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(verify_app_config(&parse("[rollup]\narchive_dir = \"/a\"\ncameras = [\"front\"]\nrun_at = \"23:15\"")).is_ok());
+    assert!(verify_app_config(&parse("[rollup]\narchive_dir = \"/a\"\nrun_at = \"25:00\"")).is_err());
+    assert!(verify_app_config(&parse("[rollup]\narchive_dir = \"/a\"\ncameras = [\"rear\"]")).is_err());
+    assert!(verify_app_config(&parse("[rollup]\narchive_dir = \"\"")).is_err());
+}
+
+#[test]
+fn janitor_defaults_to_hourly_sweeps() {
+    let parse = |janitor: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[janitor]\n{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            janitor
+        ))
+        .unwrap()
+    };
+    let cfg = parse("");
+    let janitor = cfg.janitor.as_ref().unwrap();
+    assert_eq!(janitor.interval_sec, 3600);
+    assert!(!janitor.dry_run);
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(parse("dry_run = true").janitor.unwrap().dry_run);
+    assert!(verify_app_config(&parse("interval_sec = 0")).is_err());
+}
+
+#[test]
+fn tiering_defaults_to_a_week_and_needs_a_real_target() {
+    let parse = |tiering: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[tiering]\n{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            tiering
+        ))
+        .unwrap()
+    };
+    let cfg = parse("");
+    let tiering = cfg.tiering.as_ref().unwrap();
+    assert_eq!((tiering.after_days, tiering.width, tiering.bitrate_kbps), (7, 640, 400));
+    assert!(tiering.includes("front"));
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(!parse("cameras = [\"rear\"]").tiering.unwrap().includes("front"));
+    assert!(verify_app_config(&parse("after_days = 0")).is_err());
+    assert!(verify_app_config(&parse("bitrate_kbps = 0")).is_err());
+    assert!(verify_app_config(&parse("width = 8")).is_err());
+}
+
+#[test]
+fn storage_manager_high_water_mark_is_not_below_the_low_one() {
+    let parse = |storage_manager: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[storage_manager]\n{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            storage_manager
+        ))
+        .unwrap()
+    };
+    let cfg = parse("");
+    let storage_manager = cfg.storage_manager.as_ref().unwrap();
+    assert_eq!((storage_manager.low_water_mb, storage_manager.high_water_mb), (1000, 2000));
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(verify_app_config(&parse("low_water_mb = 500\nhigh_water_mb = 500")).is_ok());
+    assert!(verify_app_config(&parse("low_water_mb = 3000")).is_err());
+    assert!(verify_app_config(&parse("low_water_mb = 0")).is_err());
+    assert!(verify_app_config(&parse("interval_sec = 0")).is_err());
+}
+
+#[test]
+fn export_watermark_is_off_by_default_and_needs_a_real_serial() {
+    let parse = |export: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            export
+        ))
+        .unwrap()
+    };
+    assert!(!parse("").export.watermarks());
+
+    let cfg = parse("[export]\ndevice_serial = \"DC-0042\"\noverlay = true");
+    assert!(cfg.export.watermarks());
+    assert_eq!(cfg.export.device_serial(), "DC-0042");
+    assert_eq!(cfg.export.audio, ExportAudio::Keep);
+    assert_eq!(parse("[export]\naudio = \"strip\"").export.audio, ExportAudio::Strip);
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(verify_app_config(&parse("[export]\ndevice_serial = \" \"\nmetadata = true")).is_err());
+}
+
+#[test]
+fn clips_default_to_gsensor_events_with_pre_and_post_roll() {
+    let parse = |clips: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            clips
+        ))
+        .unwrap()
+    };
+    assert!(parse("").clips.is_none());
+
+    let cfg = parse("[clips]");
+    let clips = cfg.clips.as_ref().unwrap();
+    assert_eq!(clips.kinds, vec![EventKind::GSensor]);
+    assert_eq!((clips.pre_roll_sec, clips.post_roll_sec), (20, 10));
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(verify_app_config(&parse("[clips]\nkinds = [\"gsensor\", \"manual\"]\npost_roll_sec = 0")).is_ok());
+    assert!(verify_app_config(&parse("[clips]\nkinds = []")).is_err());
+    assert!(verify_app_config(&parse("[clips]\npre_roll_sec = 0\npost_roll_sec = 0")).is_err());
+}
+
+#[test]
+fn clip_groups_need_known_cameras_each_in_one_group() {
+    let parse = |groups: &str| -> AppConfig {
+        let mut text = format!("[clips]\n{}\n", groups);
+        for (i, key) in ["front", "rear", "cabin"].iter().enumerate() {
+            text += &format!("[[cameras]]\nkey = \"{}\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video{}\" }}\n", key, i);
+        }
+        toml::from_str(&text).unwrap()
+    };
+    let cfg = parse("[[clips.groups]]\nname = \"car\"\ncameras = [\"front\", \"rear\", \"cabin\"]");
+    let clips = cfg.clips.as_ref().unwrap();
+    assert_eq!(clips.group_of("rear").map(|g| g.name.as_str()), Some("car"));
+    assert!(clips.group_of("side").is_none());
+    assert!(verify_app_config(&cfg).is_ok());
+    assert!(parse("").clips.unwrap().groups.is_empty());
+
+    assert!(verify_app_config(&parse("[[clips.groups]]\nname = \"car\"\ncameras = [\"front\", \"side\"]")).is_err());
+    assert!(verify_app_config(&parse("[[clips.groups]]\nname = \"\"\ncameras = [\"front\"]")).is_err());
+    assert!(verify_app_config(&parse("[[clips.groups]]\nname = \"a/b\"\ncameras = [\"front\"]")).is_err());
+    assert!(verify_app_config(&parse(
+        "[[clips.groups]]\nname = \"a\"\ncameras = [\"front\", \"rear\"]\n[[clips.groups]]\nname = \"b\"\ncameras = [\"rear\", \"cabin\"]"
+    )).is_err());
+}
+
+#[test]
+fn speed_events_need_a_rule_and_known_cameras() {
+    let parse = |speed: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[speed_events]\n{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            speed
+        ))
+        .unwrap()
+    };
+    let cfg = parse("over_kmh = 130\ncameras = [\"front\"]");
+    assert_eq!(cfg.speed_events.as_ref().unwrap().over_kmh, Some(130.0));
+    assert!(verify_app_config(&cfg).is_ok());
+    assert!(verify_app_config(&parse("hard_brake_mps2 = 4.0")).is_ok());
+
+    assert!(verify_app_config(&parse("")).is_err());
+    assert!(verify_app_config(&parse("over_kmh = 0")).is_err());
+    assert!(verify_app_config(&parse("over_kmh = 130\ncameras = [\"rear\"]")).is_err());
+
+    let events: AppConfig = toml::from_str(
+        "[events.hard_brake]\ncooldown_sec = 30\n[[cameras]]\nkey = \"front\"\nsource = { kind = \"v4l2\", device = \"/dev/video0\" }\n",
+    )
+    .unwrap();
+    assert_eq!(events.events.get(&EventKind::HardBrake).map(|p| p.cooldown_sec), Some(30));
+}
+
+#[test]
+fn rules_need_one_trigger_and_unique_names() {
+    let parse = |rules: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            rules
+        ))
+        .unwrap()
+    };
+    let crash = "[[rules]]\nname = \"crash\"\nevent = \"gsensor\"\nactions = [{ do = \"clip\" }, { do = \"split\" }]\n";
+    let cfg = parse(crash);
+    assert_eq!(cfg.rules.len(), 1);
+    assert!(verify_app_config(&cfg).is_ok());
+    assert!(verify_app_config(&parse(
+        "[[rules]]\nname = \"night\"\nat = \"23:30\"\nactions = [{ do = \"standby\", on = true }]"
+    )).is_ok());
+
+    assert!(verify_app_config(&parse(&format!("{}{}", crash, crash))).is_err(), "duplicate name");
+    assert!(verify_app_config(&parse("[[rules]]\nname = \"none\"\nactions = [{ do = \"split\" }]")).is_err());
+    assert!(verify_app_config(&parse(
+        "[[rules]]\nname = \"x\"\nevent = \"motion\"\nactions = [{ do = \"pause\", camera = \"rear\" }]"
+    )).is_err());
+    let unknown_action = "[[rules]]\nname = \"x\"\nevent = \"motion\"\nactions = [{ do = \"explode\" }]\n\
+                          [[cameras]]\nkey = \"front\"\nsource = { kind = \"v4l2\", device = \"/dev/video0\" }\n";
+    assert!(toml::from_str::<AppConfig>(unknown_action).is_err());
+}
+
+#[test]
+fn modes_name_known_cameras_rules_and_modes() {
+    let parse = |modes: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            modes
+        ))
+        .unwrap()
+    };
+    let modes = "[[modes]]\nname = \"home\"\ncameras = []\n[[modes]]\nname = \"away\"\nrules = [\"crash\"]\n\
+                 [[rules]]\nname = \"crash\"\nevent = \"gsensor\"\nactions = [{ do = \"clip\" }]\n\
+                 [[rules]]\nname = \"bedtime\"\nat = \"22:00\"\nactions = [{ do = \"mode\", name = \"away\" }]\n";
+    let cfg = parse(modes);
+    assert_eq!(cfg.modes[0].cameras.as_deref(), Some(&[][..]));
+    assert!(verify_app_config(&cfg).is_ok());
+    assert!(verify_app_config(&parse(&format!("{}[mode_mqtt]\nbroker = \"broker.lan:1883\"\ntopic = \"dashcam/mode/set\"\n", modes))).is_ok());
+
+    assert!(verify_app_config(&parse("[[modes]]\nname = \"home\"\n[[modes]]\nname = \"home\"")).is_err(), "duplicate name");
+    assert!(verify_app_config(&parse("[[modes]]\nname = \"away\"\ncameras = [\"cabin\"]")).is_err(), "unknown camera");
+    assert!(verify_app_config(&parse("[[modes]]\nname = \"away\"\nrules = [\"crash\"]")).is_err(), "unknown rule");
+    assert!(verify_app_config(&parse(
+        "[[rules]]\nname = \"bedtime\"\nat = \"22:00\"\nactions = [{ do = \"mode\", name = \"night\" }]"
+    )).is_err(), "unknown mode");
+    assert!(verify_app_config(&parse("[mode_mqtt]\nbroker = \"broker.lan:1883\"\ntopic = \"dashcam/mode/set\"")).is_err());
+}
+
+#[test]
+fn usb_sync_defaults_to_media_automounts() {
+    let cfg: AppConfig = toml::from_str(
+        "[usb_sync]\n[[cameras]]\nkey = \"front\"\nsource = { kind = \"v4l2\", device = \"/dev/video0\" }\n",
+    )
+    .unwrap();
+    let usb_sync = cfg.usb_sync.as_ref().unwrap();
+    assert_eq!((usb_sync.mount_root.as_str(), usb_sync.folder.as_str()), ("/media", "dashcam_incidents"));
+    assert!(verify_app_config(&cfg).is_ok());
+}
+
+#[test]
+fn snapshot_upload_needs_an_http_url() {
+    let parse = |upload: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            upload
+        ))
+        .unwrap()
+    };
+    let cfg = parse("[snapshot_upload]\nurl = \"http://relay.lan:8080/snapshots\"\nmqtt = { broker = \"broker.lan:1883\" }");
+    assert_eq!(cfg.snapshot_upload.as_ref().unwrap().mqtt.as_ref().unwrap().topic, "dashcam/snapshot");
+    assert!(verify_app_config(&cfg).is_ok());
+
+    let https = parse("[snapshot_upload]\nurl = \"https://relay.example/snapshots\"\ntoken = \"s3cret\"");
+    assert_eq!(verify_app_config(&https).is_ok(), cfg!(feature = "tls"));
+    // the bearer token would cross the network in the clear
+    assert!(verify_app_config(&parse("[snapshot_upload]\nurl = \"http://relay.lan\"\ntoken = \"s3cret\"")).is_err());
+    assert!(verify_app_config(&parse("[snapshot_upload]\nurl = \"http://relay.lan\"\nmqtt = { broker = \"\" }")).is_err());
+}
+
+#[test]
+fn sources_wait_and_retry_by_default() {
+    let parse = |source: &str| -> AppConfig {
+        toml::from_str(&format!("[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", {} }}\n", source)).unwrap()
+    };
+    let cfg = parse("device = \"/dev/video0\"");
+    let source = &cfg.cameras[0].source;
+    assert_eq!((source.startup_timeout_sec, source.retry_interval_sec), (10, 5));
+
+    let cfg = parse("device = \"/dev/video0\", startup_timeout_sec = 30, retry_interval_sec = 0");
+    let source = &cfg.cameras[0].source;
+    assert_eq!((source.startup_timeout_sec, source.retry_interval_sec), (30, 0));
+    assert!(verify_app_config(&cfg).is_ok());
+}
+
+#[test]
+fn v4l2_device_must_be_an_absolute_path() {
+    let parse = |device: &str| -> AppConfig {
+        toml::from_str(&format!("[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"{}\" }}\n", device))
+            .unwrap()
+    };
+    assert!(verify_app_config(&parse("/dev/video0")).is_ok());
+    assert!(verify_app_config(&parse("dev/video0")).is_err());
+    assert!(verify_app_config(&parse("video0")).is_err());
+}
+
+#[test]
+fn processing_needs_raw_frames_and_a_known_colorimetry() {
+    let parse = |camera: &str| -> AppConfig { toml::from_str(&format!("[[cameras]]\nkey = \"front\"\n{}\n", camera)).unwrap() };
+    let cfg = parse(
+        "source = { kind = \"v4l2\", device = \"/dev/video0\" }\n\
+         processing = { deinterlace = true, colorimetry = \"bt601\", crop = { bottom = 8 } }",
+    );
+    let processing = &cfg.cameras[0].processing;
+    assert!(processing.deinterlace);
+    assert_eq!(processing.crop.unwrap().bottom, 8);
+    assert!(verify_app_config(&cfg).is_ok());
+    assert!(parse("source = { kind = \"v4l2\", device = \"/dev/video0\" }").cameras[0].processing.is_empty());
+
+    assert!(verify_app_config(&parse(
+        "source = { kind = \"v4l2\", device = \"/dev/video0\", passthrough = true }\nprocessing = { deinterlace = true }"
+    )).is_err());
+    assert!(verify_app_config(&parse(
+        "source = { kind = \"v4l2\", device = \"/dev/video0\" }\nprocessing = { colorimetry = \"rec709\" }"
+    )).is_err());
+}
+
+#[test]
+fn failover_source_must_match_the_primary_codec() {
+    let parse = |failover: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n[cameras.failover]\n{}\n",
+            failover
+        ))
+        .unwrap()
+    };
+    let cfg = parse("source = { kind = \"v4l2\", device = \"/dev/video1\" }");
+    let failover = cfg.cameras[0].failover.as_ref().unwrap();
+    assert_eq!(failover.after_failures, 3);
+    assert_eq!(failover.source.describe(), "v4l2 /dev/video1");
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(verify_app_config(&parse("source = { kind = \"v4l2\", device = \"/dev/video0\" }")).is_err());
+    assert!(verify_app_config(&parse("source = { kind = \"v4l2\", device = \"/dev/video1\", codec = \"h265\" }")).is_err());
+    assert!(verify_app_config(&parse("source = { kind = \"v4l2\", device = \"dev/video1\" }")).is_err());
+}
+
+#[test]
+fn privacy_switch_maps_a_gpio_to_one_camera() {
+    let parse = |extra: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[[cameras]]\nkey = \"cab\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n{}",
+            extra
+        ))
+        .unwrap()
+    };
+    let cfg = parse("[cameras.privacy_switch]\ngpio = 529\nactive_low = true\n");
+    let switch = cfg.cameras[0].privacy_switch.as_ref().unwrap();
+    assert_eq!((switch.gpio, switch.active_low, switch.debounce_ms), (529, true, 100));
+    assert!(parse("").cameras[0].privacy_switch.is_none());
+
+    let cfg: AppConfig = toml::from_str(
+        "[status_mqtt]\nbroker = \"10.0.0.2:1883\"\n[[cameras]]\nkey = \"cab\"\nsource = { kind = \"v4l2\", device = \"/dev/video0\" }\n",
+    )
+    .unwrap();
+    assert_eq!(cfg.status_mqtt.as_ref().unwrap().topic_prefix, "dashcam");
+    assert!(verify_app_config(&cfg).is_ok());
+    let blank: AppConfig = toml::from_str(
+        "[status_mqtt]\nbroker = \"\"\n[[cameras]]\nkey = \"cab\"\nsource = { kind = \"v4l2\", device = \"/dev/video0\" }\n",
+    )
+    .unwrap();
+    assert!(verify_app_config(&blank).is_err());
+}
+
+#[test]
+fn power_loss_needs_one_signal_source() {
+    let parse = |power_loss: &str| -> AppConfig {
+        toml::from_str(&format!(
+            "[power_loss]\n{}\n[[cameras]]\nkey = \"front\"\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n",
+            power_loss
+        ))
+        .unwrap()
+    };
+    let cfg = parse("gpio = 518");
+    let power_loss = cfg.power_loss.as_ref().unwrap();
+    assert_eq!(power_loss.budget_ms, 8000);
+    assert_eq!(power_loss.shutdown_command, vec!["systemctl", "poweroff"]);
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(verify_app_config(&parse("power_supply = \"ups\"")).is_ok());
+    assert!(verify_app_config(&parse("")).is_err());
+    assert!(verify_app_config(&parse("gpio = 518\npower_supply = \"ups\"")).is_err());
+    assert!(verify_app_config(&parse("gpio = 518\nbudget_ms = 0")).is_err());
+}
+
+#[test]
+fn record_mode_motion_needs_an_nvr_camera_with_motion() {
+    let parse = |camera: &str, motion: Option<&str>| -> AppConfig {
+        let motion = motion.map(|m| format!("[cameras.motion]\n{}\n", m)).unwrap_or_default();
+        toml::from_str(&format!(
+            "[[cameras]]\nkey = \"yard\"\n{}\nsource = {{ kind = \"v4l2\", device = \"/dev/video0\" }}\n{}",
+            camera, motion
+        ))
+        .unwrap()
+    };
+    let cfg = parse("role = \"dashcam\"", Some(""));
+    assert_eq!(cfg.cameras[0].motion, Some(MotionConfig::default()));
+    assert_eq!(cfg.cameras[0].record_mode, RecordMode::Continuous);
+    assert!(verify_app_config(&cfg).is_ok());
+
+    let cfg = parse("role = \"nvr\"\nrecord_mode = \"motion\"", Some("pre_roll_sec = 10\npost_roll_sec = 60"));
+    assert_eq!(cfg.cameras[0].record_mode, RecordMode::Motion);
+    let motion = cfg.cameras[0].motion.as_ref().unwrap();
+    assert_eq!((motion.pre_roll_sec, motion.post_roll_sec, motion.width), (10, 60, 160));
+    assert!(verify_app_config(&cfg).is_ok());
+
+    assert!(verify_app_config(&parse("role = \"dashcam\"\nrecord_mode = \"motion\"", Some(""))).is_err());
+    assert!(verify_app_config(&parse("role = \"nvr\"\nrecord_mode = \"motion\"", None)).is_err());
+    assert!(verify_app_config(&parse("role = \"nvr\"", Some("pre_roll_sec = 600"))).is_err());
+    assert!(verify_app_config(&parse("role = \"nvr\"", Some("min_area = 0.0"))).is_err());
+    assert!(verify_app_config(&parse("role = \"nvr\"", Some("fps = 0"))).is_err());
+}
@@ -3,7 +3,8 @@ use tempfile::TempDir;
 use dashcam_rs::config::{
     CameraConfig, CameraRole, SourceConfig, SourceKind, SinkConfig, GlobalConfig, AppConfig,
 };
-use dashcam_rs::db::db::DashcamDb;
+use dashcam_rs::db::db::{DashcamDb, DriveSample};
+use dashcam_rs::events::{CameraEvent, EventKind};
 
 
 // Inline the real schema so tests don't depend on disk at runtime.
@@ -21,6 +22,7 @@ fn make_test_camera(
         name: format!("Camera {}", key),
         enabled: true,
         role: CameraRole::Dashcam,
+        record_mode: Default::default(),
         video_width: None,
         video_height: None,
         video_framerate: None,
@@ -28,12 +30,30 @@ fn make_test_camera(
             kind: SourceKind::V4l2,
             rtsp_url: None,
             device: Some("/dev/video0".to_string()),
+            passthrough: false,
+            codec: Default::default(),
+            encoder: None,
+            startup_timeout_sec: 10,
+            retry_interval_sec: 5,
         },
+        failover: None,
+        privacy_switch: None,
+        processing: Default::default(),
+        overlay_gps: false,
+        encoding: Default::default(),
+        audio: None,
         sinks: vec![SinkConfig::DashcamTs {
             sink_id,
-            segment_duration_sec,
-            max_segments,
+            segment_duration_sec: Some(segment_duration_sec),
+            max_segments: Some(max_segments),
+            enabled: true,
+            muxer: Default::default(),
+            write_mode: Default::default(),
+            generation_names: false,
         }],
+        detection: None,
+        motion: None,
+        roi: Vec::new(),
     }
 }
 
@@ -41,12 +61,44 @@ fn make_test_camera(
 fn make_test_app_config(db_path: &str, schema_path: &str) -> AppConfig {
     AppConfig {
         global: GlobalConfig {
-            main_dir: ".".to_string(),
-            recording_root: "./recordings".to_string(),
-            db_path: db_path.to_string(),
-            schema_path: schema_path.to_string(),
-            log_level: None
+            main_dir: Some(".".to_string()),
+            recording_root: Some("./recordings".to_string()),
+            db_path: Some(db_path.to_string()),
+            schema_path: Some(schema_path.to_string()),
+            ..Default::default()
         },
+        profiles: Default::default(),
+        events: Default::default(),
+        http: None,
+        mdns: None,
+        bandwidth: Default::default(),
+        standby: Default::default(),
+        validation: None,
+        rollup: None,
+        clips: None,
+        speed_events: None,
+        gsensor: None,
+        gps: None,
+        obd: None,
+        live: None,
+        rules: Vec::new(),
+        modes: Vec::new(),
+        mode_mqtt: None,
+        janitor: None,
+        tiering: None,
+        storage_manager: None,
+        backfill: None,
+        usb_sync: None,
+        snapshot_upload: None,
+        export: Default::default(),
+        time_sync: Default::default(),
+        storage_wear: Default::default(),
+        throughput: Default::default(),
+        smart: None,
+        staging: None,
+        power_loss: None,
+        status_mqtt: None,
+        simulation: None,
         cameras: vec![make_test_camera("cam1", 0, 2, 10)],
     }
 }
@@ -195,3 +247,888 @@ fn setup_from_config_works_with_appconfig() {
     let idx = db.get_segment_index(camera_id, 0).unwrap();
     assert_eq!(idx, 0);
 }
+
+#[test]
+fn setup_from_config_uses_embedded_schema_by_default() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("db.sqlite");
+
+    let mut cfg = make_test_app_config(db_path.to_string_lossy().as_ref(), "unused");
+    cfg.global.schema_path = None;
+
+    let db = DashcamDb::setup_from_config(&cfg).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    assert_eq!(db.get_segment_index(camera_id, 0).unwrap(), 0);
+}
+
+#[test]
+fn sink_override_roundtrips_and_defaults_to_none() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("db.sqlite");
+
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(&db_path, SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+
+    assert_eq!(db.get_sink_enabled(camera_id, 0).unwrap(), None, "no override -> config decides");
+
+    db.set_sink_enabled(camera_id, 0, false).unwrap();
+    assert_eq!(db.get_sink_enabled(camera_id, 0).unwrap(), Some(false));
+
+    db.set_sink_enabled(camera_id, 0, true).unwrap();
+    assert_eq!(db.get_sink_enabled(camera_id, 0).unwrap(), Some(true));
+}
+
+#[test]
+fn events_are_recorded_newest_first() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("db.sqlite");
+
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(&db_path, SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+
+    db.insert_event("cam1", &CameraEvent::now(EventKind::GSensor)).unwrap();
+    db.insert_event("cam1", &CameraEvent::object("person", 0.8)).unwrap();
+
+    let events = db.get_recent_events(camera_id, 10).unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].kind, "object");
+    assert_eq!(events[0].label.as_deref(), Some("person"));
+    assert!((events[0].score.unwrap() - 0.8).abs() < 1e-6);
+    assert_eq!(events[1].kind, "gsensor");
+    assert_eq!(events[1].label, None);
+}
+
+#[test]
+fn audit_log_records_outcome_newest_first() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("db.sqlite");
+
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(&db_path, SCHEMA_SQL, &cameras).unwrap();
+
+    db.insert_audit("tablet", "set_sink_enabled", Some("cam1/sink 0 enabled=false"), true, None)
+        .unwrap();
+    db.insert_audit("admin", "trigger_event", Some("nope/manual"), false, Some("No running pipeline"))
+        .unwrap();
+
+    let entries = db.get_recent_audit(10).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].actor, "admin");
+    assert!(!entries[0].ok);
+    assert_eq!(entries[0].result.as_deref(), Some("No running pipeline"));
+    assert_eq!(entries[1].action, "set_sink_enabled");
+    assert!(entries[1].ok);
+    assert_eq!(db.get_recent_audit(1).unwrap().len(), 1);
+}
+
+#[test]
+fn settings_persist_and_report_changes() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("db.sqlite");
+
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(&db_path, SCHEMA_SQL, &cameras).unwrap();
+
+    assert_eq!(db.get_setting("overlay.enabled").unwrap(), None);
+    assert!(db.set_setting("overlay.enabled", "false").unwrap());
+    assert!(!db.set_setting("overlay.enabled", "false").unwrap(), "same value is not a change");
+    assert!(db.set_setting("overlay.enabled", "true").unwrap());
+    drop(db);
+
+    let db = DashcamDb::setup_with_paths_and_schema(&db_path, SCHEMA_SQL, &cameras).unwrap();
+    assert_eq!(db.get_setting("overlay.enabled").unwrap().as_deref(), Some("true"));
+}
+
+#[test]
+fn db_worker_notifies_setting_subscribers() {
+    use dashcam_rs::db::db_worker::{DBMessage, DBWorker, SettingChange, start_db_worker};
+    use std::sync::mpsc::channel;
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let dbconn = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let (sender, recvr) = channel();
+    let handle = start_db_worker(DBWorker { recvr, dbconn, recording_root: String::new(), setting_subscribers: Vec::new() });
+
+    let (sub_tx, sub_rx) = channel();
+    sender.send(DBMessage::SubscribeSettings { subscriber: sub_tx }).unwrap();
+    let set = |value: &str| {
+        sender
+            .send(DBMessage::SetSetting { key: "motion.sensitivity".to_string(), value: value.to_string() })
+            .unwrap()
+    };
+    set("0.4");
+    set("0.4");
+    set("0.6");
+    let (reply, value) = channel();
+    sender.send(DBMessage::GetSetting { key: "motion.sensitivity".to_string(), reply }).unwrap();
+    assert_eq!(value.recv().unwrap().as_deref(), Some("0.6"));
+
+    drop(sender);
+    handle.join().unwrap();
+    let changes: Vec<SettingChange> = sub_rx.iter().collect();
+    let values: Vec<&str> = changes.iter().map(|c| c.value.as_str()).collect();
+    assert_eq!(values, vec!["0.4", "0.6"], "unchanged writes aren't announced");
+}
+
+#[test]
+fn db_facade_serves_sink_state_from_one_batched_lookup() {
+    use dashcam_rs::db::db_facade::DbFacade;
+    use dashcam_rs::db::db_worker::{DBWorker, start_db_worker};
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10), make_test_camera("cam2", 3, 2, 10)];
+    let dbconn = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let cam1_id = dbconn.get_camera_id_by_key("cam1").unwrap();
+    dbconn.set_segment_index(cam1_id, 0, 7).unwrap();
+    dbconn.set_sink_enabled(cam1_id, 0, false).unwrap();
+    let (sender, recvr) = channel();
+    let handle = start_db_worker(DBWorker { recvr, dbconn, recording_root: String::new(), setting_subscribers: Vec::new() });
+
+    let mut cfg = make_test_app_config("unused", "unused");
+    cfg.cameras = cameras;
+    let db = DbFacade::new(Arc::new(sender));
+    db.prefetch(&cfg).unwrap();
+    let cameras = &cfg.cameras;
+
+    assert_eq!(db.camera_id(&cameras[0]).unwrap(), cam1_id);
+    assert!(!db.sink_enabled(&cameras[0], &cameras[0].sinks[0]).unwrap(), "override wins over config");
+    assert!(db.sink_enabled(&cameras[1], &cameras[1].sinks[0]).unwrap());
+    let sink = db.sink(&cameras[0], 0).unwrap();
+    assert_eq!((sink.camera_id, sink.sink_id, sink.counters.index), (cam1_id, 0, 7));
+    assert!(db.camera_id(&make_test_camera("ghost", 0, 2, 10)).is_err());
+
+    drop((db, sink));
+    handle.join().unwrap();
+}
+
+#[test]
+fn legacy_import_registers_historical_segments_once() {
+    use dashcam_rs::legacy_import::{HISTORICAL_SINK_ID, import_legacy};
+    use std::time::{Duration, SystemTime};
+
+    let tmp = TempDir::new().unwrap();
+    let legacy = tmp.path().join("old_recordings");
+    std::fs::create_dir_all(legacy.join("1")).unwrap();
+    let t0 = SystemTime::now() - Duration::from_secs(3600);
+    for (rel, index) in [("output_7.ts", 7u64), ("1/output_1000.ts", 1000), ("output_3.m3u8", 3)] {
+        let file = std::fs::File::create(legacy.join(rel)).unwrap();
+        file.set_modified(t0 + Duration::from_secs(index)).unwrap();
+    }
+
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+
+    let summary = import_legacy(&db, "cam1", &legacy, tmp.path(), 60).unwrap();
+    assert_eq!((summary.found, summary.imported, summary.skipped), (2, 2, 0));
+    assert_eq!(db.count_segments(camera_id, HISTORICAL_SINK_ID).unwrap(), 2);
+
+    let (rel_path, start, end): (String, i64, i64) = db
+        .conn
+        .query_row(
+            "SELECT rel_path, start_utc, end_utc FROM segments WHERE segment_index = 1000;",
+            [],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(rel_path, "old_recordings/1/output_1000.ts");
+    assert_eq!(end - start, 60);
+    let mut listed = db.segment_rel_paths(camera_id).unwrap();
+    listed.sort();
+    assert_eq!(listed, vec!["old_recordings/1/output_1000.ts", "old_recordings/output_7.ts"]);
+
+    let again = import_legacy(&db, "cam1", &legacy, tmp.path(), 60).unwrap();
+    assert_eq!((again.imported, again.skipped), (0, 2));
+    assert!(import_legacy(&db, "nope", &legacy, tmp.path(), 60).is_err());
+}
+
+#[test]
+fn events_between_is_half_open_and_oldest_first() {
+    let tmp = TempDir::new().unwrap();
+    let db_path = tmp.path().join("db.sqlite");
+
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(&db_path, SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+
+    let event = CameraEvent::now(EventKind::Motion);
+    let t0 = event.at.timestamp();
+    for secs in [0, 10, 20] {
+        let at = event.at + chrono::Duration::seconds(secs);
+        db.insert_event("cam1", &CameraEvent { at, ..event.clone() }).unwrap();
+    }
+
+    let events = db.get_events_between(camera_id, t0, t0 + 20).unwrap();
+    let times: Vec<i64> = events.iter().map(|e| e.at_utc - t0).collect();
+    assert_eq!(times, vec![0, 10]);
+}
+
+#[test]
+fn clock_corrections_annotate_the_segments_they_cover() {
+    use dashcam_rs::db::db::SegmentRecord;
+    use dashcam_rs::time_sync::ClockCorrection;
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    for (index, start_utc) in [(0, 1_000), (1, 1_060), (2, 9_000)] {
+        db.insert_segment(&SegmentRecord {
+            camera_id,
+            sink_id: 0,
+            segment_index: index,
+            segment_gen: 0,
+            absolute_index: index,
+            start_utc,
+            end_utc: start_utc + 60,
+            rel_path: format!("cam1/{}.ts", index),
+            disk: None,
+            stream: Default::default(),
+            bytes: None,
+            restarted: false,
+        })
+        .unwrap();
+    }
+
+    let step = ClockCorrection { at_utc: 1_100, offset_ms: -2_500, since_utc: 900 };
+    assert_eq!(db.insert_clock_correction(&step).unwrap(), 2);
+    let offsets: Vec<Option<i64>> = db
+        .conn
+        .prepare("SELECT clock_offset_ms FROM segments ORDER BY segment_index;")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(offsets, vec![Some(2_500), Some(2_500), None]);
+
+    assert_eq!(db.clock_accuracy_between(1_050, 1_200).unwrap(), Some(2_500));
+    assert_eq!(db.clock_accuracy_between(8_000, 9_060).unwrap(), None);
+}
+
+#[test]
+fn incident_clips_share_the_incident_id() {
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("front", 0, 2, 10), make_test_camera("rear", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+
+    let event = CameraEvent::now(EventKind::GSensor);
+    let first = db.insert_incident("car", "rear", &event, "/save/incidents/a").unwrap();
+    let second = db.insert_incident("car", "front", &event, "/save/incidents/b").unwrap();
+    assert_ne!(first, second);
+
+    db.insert_incident_clip(first, "rear", "/save/incidents/a/rear.mp4", 100, 130).unwrap();
+    db.insert_incident_clip(first, "front", "/save/incidents/a/front.mp4", 100, 130).unwrap();
+    db.insert_incident_clip(second, "front", "/save/incidents/b/front.mp4", 200, 230).unwrap();
+
+    let clips = db.get_incident_clips(first).unwrap();
+    assert_eq!(clips.iter().map(|c| c.camera_key.as_str()).collect::<Vec<_>>(), vec!["front", "rear"]);
+    assert!(clips.iter().all(|c| (c.from_utc, c.to_utc) == (100, 130)));
+    assert_eq!(db.get_incident_clips(second).unwrap().len(), 1);
+
+    let incident = db.get_incident(first).unwrap().unwrap();
+    assert_eq!((incident.group_name.as_str(), incident.camera_key.as_deref()), ("car", Some("rear")));
+    assert!(db.get_incident(second + 1).unwrap().is_none());
+    assert_eq!(db.get_recent_incidents(1).unwrap()[0].id, second);
+
+    let kind: String = db
+        .conn
+        .query_row("SELECT kind FROM incidents WHERE id = ?1;", [first], |r| r.get(0))
+        .unwrap();
+    assert_eq!(kind, "gsensor");
+}
+
+#[test]
+fn gps_points_come_back_in_time_order_within_the_range() {
+    use dashcam_rs::db::db::GpsPoint;
+
+    let tmp = TempDir::new().unwrap();
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &[]).unwrap();
+    for at_utc in [105, 100, 110, 99] {
+        db.insert_gps_point(&GpsPoint {
+            at_utc,
+            lat: 52.5,
+            lon: 13.4,
+            alt_m: None,
+            speed_mps: Some(10.0),
+            heading_deg: None,
+        })
+        .unwrap();
+    }
+    let points = db.gps_points_between(100, 110).unwrap();
+    assert_eq!(points.iter().map(|p| p.at_utc).collect::<Vec<_>>(), vec![100, 105]);
+    assert_eq!(points[0].speed_mps, Some(10.0));
+}
+
+#[test]
+fn trip_tracks_cover_the_trip_and_open_trips_run_until_now() {
+    use dashcam_rs::db::db::GpsPoint;
+    use dashcam_rs::track::{TrackRange, load_track};
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    db.conn
+        .execute(
+            "INSERT INTO trips (id, camera_id, boot_id, start_time_utc, end_time_utc, start_segment)
+             VALUES (1, ?1, 'boot', 100, 200, 0), (2, ?1, 'boot', 300, NULL, 0);",
+            [camera_id],
+        )
+        .unwrap();
+    for at_utc in [50, 100, 200, 250, 300, 400] {
+        db.insert_gps_point(&GpsPoint { at_utc, lat: 1.0, lon: 2.0, alt_m: None, speed_mps: None, heading_deg: None })
+            .unwrap();
+    }
+    let times = |range| load_track(&db, range).unwrap().map(|points| points.iter().map(|p| p.at_utc).collect::<Vec<_>>());
+
+    assert_eq!(db.get_trip_span(2).unwrap(), Some((300, None)));
+    assert_eq!(times(TrackRange::Trip(1)), Some(vec![100, 200]));
+    assert_eq!(times(TrackRange::Trip(2)), Some(vec![300, 400]));
+    assert_eq!(times(TrackRange::Trip(9)), None);
+    assert_eq!(times(TrackRange::Between { from_utc: 200, to_utc: 300 }), Some(vec![200, 250]));
+}
+
+#[test]
+fn segments_without_thumbnail_or_checksum_are_found_for_backfill() {
+    use dashcam_rs::db::db::SegmentRecord;
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    for index in 0..3 {
+        db.insert_segment(&SegmentRecord {
+            camera_id,
+            sink_id: 0,
+            segment_index: index,
+            segment_gen: 0,
+            absolute_index: index,
+            start_utc: 1_000 + index * 60,
+            end_utc: 1_060 + index * 60,
+            rel_path: format!("cam1/{}.ts", index),
+            disk: None,
+            stream: Default::default(),
+            bytes: None,
+            restarted: false,
+        })
+        .unwrap();
+    }
+    let pending = db.segments_missing_derived(0, 10, true, true).unwrap();
+    assert_eq!(pending.len(), 3);
+    assert!(pending.iter().all(|s| s.needs_thumbnail && s.needs_checksum));
+    let (first, second) = (pending[0].id, pending[1].id);
+
+    db.set_segment_derived(first, None, Some("cbf43926")).unwrap();
+    db.set_segment_derived(second, Some("/thumbs/2.jpg"), Some("00000000")).unwrap();
+    // setting only the thumbnail later keeps the checksum
+    db.set_segment_derived(first, Some("/thumbs/1.jpg"), None).unwrap();
+
+    let pending = db.segments_missing_derived(0, 10, true, true).unwrap();
+    assert_eq!(pending.iter().map(|s| s.rel_path.as_str()).collect::<Vec<_>>(), vec!["cam1/2.ts"]);
+    assert!(db.segments_missing_derived(pending[0].id, 10, true, true).unwrap().is_empty());
+    assert!(db.segments_missing_derived(0, 10, false, false).unwrap().is_empty());
+
+    let checksum: Option<String> = db
+        .conn
+        .query_row("SELECT checksum FROM segments WHERE id = ?1;", [first], |r| r.get(0))
+        .unwrap();
+    assert_eq!(checksum.as_deref(), Some("cbf43926"));
+}
+
+#[test]
+fn tiered_segments_get_their_new_size_and_lose_the_checksum() {
+    use dashcam_rs::db::db::{SegmentRecord, SegmentStream};
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 60, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    db.insert_segment(&SegmentRecord {
+        camera_id,
+        sink_id: 1,
+        segment_index: 0,
+        segment_gen: 0,
+        absolute_index: 0,
+        start_utc: 1_000,
+        end_utc: 1_060,
+        rel_path: "/mnt/nvr1/cam1/2026-03-01/10-00-00.ts".to_string(),
+        disk: Some("/mnt/nvr1".to_string()),
+        stream: SegmentStream { codec: Some("H264".to_string()), width: Some(1920), height: Some(1080), fps: Some(30.0) },
+        bytes: Some(30_000_000),
+        restarted: false,
+    })
+    .unwrap();
+    let id = db.segments_between(camera_id, 1_000, 1_060).unwrap()[0].id;
+    db.set_segment_derived(id, None, Some("cbf43926")).unwrap();
+
+    assert_eq!(db.set_segment_tiered("/mnt/nvr1/cam1/2026-03-01/10-00-00.ts", 640, 360, 3_000_000).unwrap(), 1);
+    assert_eq!(db.set_segment_tiered("cam1/2026-03-01/10-00-00.ts", 640, 360, 3_000_000).unwrap(), 0);
+
+    let row = &db.segments_between(camera_id, 1_000, 1_060).unwrap()[0];
+    assert_eq!((row.stream.width, row.stream.height, row.bytes), (Some(640), Some(360), Some(3_000_000)));
+    assert_eq!(row.stream.codec.as_deref(), Some("H264"));
+    assert_eq!(db.segments_missing_derived(0, 10, false, true).unwrap().len(), 1, "checksum read again");
+}
+
+#[test]
+fn ring_segments_are_replaced_when_rewritten_and_found_by_time() {
+    use dashcam_rs::db::db::SegmentRecord;
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 60, 2)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    // a ring of two: the third segment overwrites the first file
+    for absolute in 0..3 {
+        db.record_segment(&SegmentRecord {
+            camera_id,
+            sink_id: 0,
+            segment_index: absolute % 2,
+            segment_gen: absolute / 2,
+            absolute_index: absolute,
+            start_utc: 1_000 + absolute * 60,
+            end_utc: 1_060 + absolute * 60,
+            rel_path: format!("cam1/0/output_{}.ts", absolute % 2),
+            disk: None,
+            stream: Default::default(),
+            bytes: Some(4096),
+            restarted: false,
+        })
+        .unwrap();
+    }
+    assert_eq!(db.count_segments(camera_id, 0).unwrap(), 2);
+
+    let found = db.segments_between(camera_id, 1_000, 1_200).unwrap();
+    let spans: Vec<(i64, &str)> = found.iter().map(|s| (s.start_utc, s.rel_path.as_str())).collect();
+    assert_eq!(spans, vec![(1_060, "cam1/0/output_1.ts"), (1_120, "cam1/0/output_0.ts")]);
+    assert_eq!(db.segments_between(camera_id, 1_130, 1_140).unwrap().len(), 1);
+    assert!(db.segments_between(camera_id, 1_000, 1_059).unwrap().is_empty(), "overwritten");
+    // only imported footage keeps the janitor away
+    assert!(db.segment_rel_paths(camera_id).unwrap().is_empty());
+}
+
+#[test]
+fn generation_named_segments_replace_the_slot_they_reuse() {
+    use dashcam_rs::db::db::SegmentRecord;
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 60, 2)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    for absolute in 0..5 {
+        let (index, generation) = (absolute % 2, absolute / 2);
+        db.record_segment(&SegmentRecord {
+            camera_id,
+            sink_id: 0,
+            segment_index: index,
+            segment_gen: generation,
+            absolute_index: absolute,
+            start_utc: 1_000 + absolute * 60,
+            end_utc: 1_060 + absolute * 60,
+            rel_path: format!("cam1/0/g{}_s{}.ts", generation, index),
+            disk: None,
+            stream: Default::default(),
+            bytes: Some(4096),
+            restarted: false,
+        })
+        .unwrap();
+    }
+
+    let found = db.segments_between(camera_id, 0, 2_000).unwrap();
+    let paths: Vec<&str> = found.iter().map(|s| s.rel_path.as_str()).collect();
+    assert_eq!(paths, vec!["cam1/0/g1_s1.ts", "cam1/0/g2_s0.ts"]);
+}
+
+#[test]
+fn deleted_segments_leave_tombstones() {
+    use dashcam_rs::db::db::{DeletionReason, SegmentRecord};
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 60, 2)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    let record = |absolute: i64| SegmentRecord {
+        camera_id,
+        sink_id: 0,
+        segment_index: absolute % 2,
+        segment_gen: absolute / 2,
+        absolute_index: absolute,
+        start_utc: 1_000 + absolute * 60,
+        end_utc: 1_060 + absolute * 60,
+        rel_path: format!("cam1/0/output_{}.ts", absolute % 2),
+        disk: None,
+        stream: Default::default(),
+        bytes: Some(4096),
+        restarted: false,
+    };
+    for absolute in [0, 1, 2, 2] {
+        db.record_segment(&record(absolute)).unwrap();
+    }
+
+    // the third segment overwrote the first; cataloguing it twice is no deletion
+    let tombstones = db.tombstones_between(camera_id, 0, 2_000).unwrap();
+    assert_eq!(tombstones.len(), 1);
+    assert_eq!((tombstones[0].start_utc, tombstones[0].end_utc), (1_000, 1_060));
+    assert_eq!((tombstones[0].reason.as_str(), tombstones[0].deleted_at_utc), ("overwritten", 1_120));
+
+    let forgotten = db
+        .forget_segments(camera_id, &["cam1/0/output_1.ts".to_string()], DeletionReason::Janitor, 5_000)
+        .unwrap();
+    assert_eq!(forgotten, 1);
+    let reasons: Vec<(i64, String)> =
+        db.tombstones_between(camera_id, 0, 2_000).unwrap().into_iter().map(|t| (t.start_utc, t.reason)).collect();
+    assert_eq!(reasons, vec![(1_000, "overwritten".to_string()), (1_060, "janitor".to_string())]);
+    assert!(db.tombstones_between(camera_id, 1_130, 1_140).unwrap().is_empty(), "still recorded");
+    assert_eq!(db.segments_between(camera_id, 0, 2_000).unwrap().len(), 1);
+}
+
+#[test]
+fn oldest_prunable_segments_skip_saved_ones_and_spanned_disks() {
+    use dashcam_rs::db::db::SegmentRecord;
+    use dashcam_rs::protect::{ProtectRequest, protect_window};
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 60, 4), make_test_camera("cam2", 0, 60, 4)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let cam1 = db.get_camera_id_by_key("cam1").unwrap();
+    let cam2 = db.get_camera_id_by_key("cam2").unwrap();
+    let root = tmp.path().join("recordings");
+    std::fs::create_dir_all(root.join("cam1/0")).unwrap();
+    let record = |camera_id, rel_path: &str, end_utc, disk: Option<&str>| SegmentRecord {
+        camera_id,
+        sink_id: 0,
+        segment_index: end_utc,
+        segment_gen: 0,
+        absolute_index: end_utc,
+        start_utc: end_utc - 60,
+        end_utc,
+        rel_path: rel_path.to_string(),
+        disk: disk.map(str::to_string),
+        stream: Default::default(),
+        bytes: Some(4096),
+        restarted: false,
+    };
+    db.record_segment(&record(cam1, "cam1/0/output_0.ts", 1_060, None)).unwrap();
+    db.record_segment(&record(cam2, "cam2/0/output_0.ts", 1_030, None)).unwrap();
+    db.record_segment(&record(cam1, "/mnt/nvr1/cam1/0/output_1.ts", 1_000, Some("/mnt/nvr1"))).unwrap();
+    db.record_segment(&record(cam1, "cam1/0/output_2.ts", 1_120, None)).unwrap();
+    db.record_segment(&record(cam2, "cam2/0/output_1.ts", 1_200, None)).unwrap();
+
+    std::fs::write(root.join("cam1/0/output_2.ts"), "saved").unwrap();
+    let request = ProtectRequest {
+        camera_id: cam1,
+        from_utc: 1_100,
+        to_utc: 1_110,
+        kind: EventKind::GSensor,
+        recording_root: root.clone(),
+        dir: tmp.path().join("save/cam1/moment"),
+    };
+    assert_eq!(protect_window(&db, &request).unwrap().linked, 1);
+
+    let oldest: Vec<String> = db.oldest_prunable_segments(10).unwrap().into_iter().map(|s| s.rel_path).collect();
+    assert_eq!(oldest, vec!["cam2/0/output_0.ts", "cam1/0/output_0.ts", "cam2/0/output_1.ts"]);
+    let first = db.oldest_prunable_segments(1).unwrap();
+    assert_eq!((first[0].camera_id, first[0].end_utc), (cam2, 1_030));
+}
+
+#[test]
+fn oldest_prunable_segments_skip_the_open_and_next_ring_slots() {
+    use dashcam_rs::db::db::SegmentRecord;
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 60, 4)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    // segment `absolute` closes and the sink opens the next one, so camera_state
+    // points one slot further
+    let close = |absolute: i64, max_segments: i64| {
+        let (slot, generation) = (absolute % max_segments, absolute / max_segments);
+        db.record_segment(&SegmentRecord {
+            camera_id,
+            sink_id: 0,
+            segment_index: slot,
+            segment_gen: generation,
+            absolute_index: absolute,
+            start_utc: 1_000 + absolute * 60,
+            end_utc: 1_060 + absolute * 60,
+            rel_path: format!("cam1/0/output_{}.ts", slot),
+            disk: None,
+            stream: Default::default(),
+            bytes: Some(4096),
+            restarted: false,
+        })
+        .unwrap();
+        db.update_segment_counters(camera_id, 0, (absolute + 2) % max_segments, max_segments).unwrap();
+    };
+    let prunable =
+        || -> Vec<String> { db.oldest_prunable_segments(10).unwrap().into_iter().map(|s| s.rel_path).collect() };
+    db.update_segment_counters(camera_id, 0, 1, 4).unwrap();
+
+    // second lap, slot 2 open: its old row and slot 3 (the oldest) stay
+    for absolute in 0..6 {
+        close(absolute, 4);
+    }
+    assert_eq!(prunable(), vec!["cam1/0/output_0.ts", "cam1/0/output_1.ts"]);
+
+    // slot 3 open and the ring wrapped: lap 0's slot 3 and slot 0 stay
+    close(6, 4);
+    assert_eq!(prunable(), vec!["cam1/0/output_1.ts", "cam1/0/output_2.ts"]);
+}
+
+#[test]
+fn saved_segments_are_linked_once_and_listed() {
+    use dashcam_rs::db::db::SegmentRecord;
+    use dashcam_rs::protect::{ProtectRequest, protect_window};
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 60, 4)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    let root = tmp.path().join("recordings");
+    std::fs::create_dir_all(root.join("cam1/0")).unwrap();
+    for index in 0..3 {
+        let rel_path = format!("cam1/0/output_{}.ts", index);
+        // output_2.ts is catalogued but already gone
+        if index < 2 {
+            std::fs::write(root.join(&rel_path), format!("segment {}", index)).unwrap();
+        }
+        db.record_segment(&SegmentRecord {
+            camera_id,
+            sink_id: 0,
+            segment_index: index,
+            segment_gen: 0,
+            absolute_index: index,
+            start_utc: 1_000 + index * 60,
+            end_utc: 1_060 + index * 60,
+            rel_path,
+            disk: None,
+            stream: Default::default(),
+            bytes: None,
+            restarted: false,
+        })
+        .unwrap();
+    }
+
+    let request = ProtectRequest {
+        camera_id,
+        from_utc: 1_030,
+        to_utc: 1_200,
+        kind: EventKind::GSensor,
+        recording_root: root.clone(),
+        dir: tmp.path().join("save/cam1/moment"),
+    };
+    let summary = protect_window(&db, &request).unwrap();
+    assert_eq!((summary.linked, summary.copied, summary.missing), (2, 0, 1));
+    let saved: Vec<String> = std::fs::read_dir(&request.dir)
+        .unwrap()
+        .map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap())
+        .collect();
+    assert_eq!(saved.len(), 2);
+    assert!(saved.contains(&"segment 1".to_string()));
+
+    let again = protect_window(&db, &request).unwrap();
+    assert_eq!((again.linked, again.already), (0, 2));
+    let kinds: Vec<String> = db
+        .conn
+        .prepare("SELECT kind FROM protected_segments;")
+        .unwrap()
+        .query_map([], |r| r.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(kinds, vec!["gsensor", "gsensor"]);
+}
+
+#[test]
+fn segments_keep_the_stream_they_were_encoded_with() {
+    use dashcam_rs::db::db::{SegmentRecord, SegmentStream};
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 60, 2)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    let stream = SegmentStream { codec: Some("H264".to_string()), width: Some(1280), height: Some(720), fps: Some(30.0) };
+    db.record_segment(&SegmentRecord {
+        camera_id,
+        sink_id: 0,
+        segment_index: 0,
+        segment_gen: 0,
+        absolute_index: 0,
+        start_utc: 1_000,
+        end_utc: 1_060,
+        rel_path: "cam1/0/output_0.ts".to_string(),
+        disk: None,
+        stream: stream.clone(),
+        bytes: None,
+        restarted: false,
+    })
+    .unwrap();
+
+    assert_eq!(db.segment_stream("cam1/0/output_0.ts").unwrap(), Some(stream.clone()));
+    assert_eq!(db.segments_between(camera_id, 1_000, 1_060).unwrap()[0].stream, stream);
+    assert_eq!(db.segment_stream("cam1/0/output_1.ts").unwrap(), None);
+}
+
+#[test]
+fn telemetry_lines_up_with_segments_and_backs_up_gps_speed() {
+    use dashcam_rs::db::db::{GpsPoint, SegmentRecord, TelemetrySample};
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 2, 10)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    db.insert_segment(&SegmentRecord {
+        camera_id,
+        sink_id: 0,
+        segment_index: 0,
+        segment_gen: 0,
+        absolute_index: 0,
+        start_utc: 1_000,
+        end_utc: 1_060,
+        rel_path: "cam1/0.ts".to_string(),
+        disk: None,
+        stream: Default::default(),
+        bytes: None,
+        restarted: false,
+    })
+    .unwrap();
+    let segment_id: i64 = db.conn.query_row("SELECT id FROM segments;", [], |r| r.get(0)).unwrap();
+
+    for at_utc in [990, 1_000, 1_030, 1_061] {
+        let sample = TelemetrySample { at_utc, speed_kmh: Some(50.0), rpm: Some(2000.0), throttle_pct: None };
+        db.insert_telemetry(&sample).unwrap();
+    }
+    let samples = db.telemetry_for_segment(segment_id).unwrap();
+    assert_eq!(samples.iter().map(|s| s.at_utc).collect::<Vec<_>>(), vec![1_000, 1_030]);
+    assert_eq!(samples[0].throttle_pct, None);
+
+    // no fix: OBD speed
+    assert_eq!(db.speed_at(1_031, 5).unwrap(), Some(50.0));
+    db.insert_gps_point(&GpsPoint { at_utc: 1_029, lat: 52.0, lon: 13.0, alt_m: None, speed_mps: Some(10.0), heading_deg: None })
+        .unwrap();
+    assert_eq!(db.speed_at(1_031, 5).unwrap(), Some(36.0));
+    db.insert_gps_point(&GpsPoint { at_utc: 1_070, lat: 52.1, lon: 13.0, alt_m: None, speed_mps: None, heading_deg: None })
+        .unwrap();
+    let fixes = db.gps_points_for_segment(segment_id).unwrap();
+    assert_eq!(fixes.iter().map(|p| p.at_utc).collect::<Vec<_>>(), vec![1_029]);
+    // fix and sample both too old
+    assert_eq!(db.speed_at(1_050, 5).unwrap(), None);
+}
+
+#[test]
+fn mode_history_is_newest_first() {
+    let tmp = TempDir::new().unwrap();
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &[]).unwrap();
+    assert!(db.get_mode_history(1).unwrap().is_empty());
+
+    db.insert_mode_change("away", "phone").unwrap();
+    db.insert_mode_change("night", "rule:bedtime").unwrap();
+    db.insert_mode_change("home", "mqtt").unwrap();
+
+    let history = db.get_mode_history(2).unwrap();
+    let modes: Vec<(&str, &str)> = history.iter().map(|row| (row.mode.as_str(), row.actor.as_str())).collect();
+    assert_eq!(modes, vec![("home", "mqtt"), ("night", "rule:bedtime")]);
+}
+
+#[test]
+fn rule_state_is_upserted_by_name() {
+    use dashcam_rs::db::db::RuleStateRow;
+
+    let tmp = TempDir::new().unwrap();
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &[]).unwrap();
+    assert!(db.get_rule_states().unwrap().is_empty());
+
+    let mut away = RuleStateRow { name: "away".into(), armed: true, fire_count: 1, last_fired_utc: Some(1_000), fired_on: None };
+    db.save_rule_state(&away).unwrap();
+    away.armed = false;
+    away.fire_count = 2;
+    db.save_rule_state(&away).unwrap();
+    let nightly = RuleStateRow {
+        name: "nightly".into(),
+        armed: true,
+        fire_count: 0,
+        last_fired_utc: None,
+        fired_on: Some("2026-03-01".into()),
+    };
+    db.save_rule_state(&nightly).unwrap();
+
+    assert_eq!(db.get_rule_states().unwrap(), vec![away, nightly]);
+}
+
+#[test]
+fn bytes_written_add_up_per_storage_root() {
+    let tmp = TempDir::new().unwrap();
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &[]).unwrap();
+    assert_eq!(db.bytes_written("/mnt/sd").unwrap(), 0);
+
+    db.add_bytes_written("/mnt/sd", 30_000_000).unwrap();
+    db.add_bytes_written("/mnt/sd", 12_000_000).unwrap();
+    db.add_bytes_written("/mnt/usb", 5_000_000).unwrap();
+    assert_eq!(db.bytes_written("/mnt/sd").unwrap(), 42_000_000);
+    assert_eq!(db.bytes_written("/mnt/usb").unwrap(), 5_000_000);
+}
+
+#[test]
+fn drive_baseline_is_the_oldest_sample_in_the_window() {
+    let tmp = TempDir::new().unwrap();
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &[]).unwrap();
+    let sample = |device: &str, at_utc: i64, reallocated: i64| DriveSample {
+        device: device.to_string(),
+        at_utc,
+        passed: Some(true),
+        temperature_c: Some(40.0),
+        reallocated_sectors: Some(reallocated),
+        pending_sectors: None,
+    };
+    for s in [sample("/dev/sda", 100, 2), sample("/dev/sda", 200, 5), sample("/dev/sdb", 150, 9), sample("/dev/sda", 300, 8)] {
+        db.insert_drive_sample(&s).unwrap();
+    }
+
+    assert_eq!(db.drive_sample_since("/dev/sda", 0).unwrap(), Some(sample("/dev/sda", 100, 2)));
+    assert_eq!(db.drive_sample_since("/dev/sda", 101).unwrap(), Some(sample("/dev/sda", 200, 5)));
+    assert_eq!(db.drive_sample_since("/dev/sda", 301).unwrap(), None);
+    assert_eq!(db.drive_sample_since("/dev/sdc", 0).unwrap(), None);
+}
+
+#[test]
+fn segments_remember_the_disk_they_were_written_to() {
+    use dashcam_rs::db::db::SegmentRecord;
+
+    let tmp = TempDir::new().unwrap();
+    let cameras = vec![make_test_camera("cam1", 0, 60, 2)];
+    let db = DashcamDb::setup_with_paths_and_schema(tmp.path().join("db.sqlite"), SCHEMA_SQL, &cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    for (index, disk) in [(0, Some("/mnt/nvr1")), (1, Some("/mnt/nvr2")), (2, None)] {
+        let rel_path = match disk {
+            Some(mount) => format!("{}/cam1/2026-10-17/12-00-0{}.ts", mount, index),
+            None => format!("cam1/2026-10-17/12-00-0{}.ts", index),
+        };
+        db.record_segment(&SegmentRecord {
+            camera_id,
+            sink_id: 1,
+            segment_index: index,
+            segment_gen: 0,
+            absolute_index: index,
+            start_utc: 1_000 + index * 60,
+            end_utc: 1_060 + index * 60,
+            rel_path,
+            disk: disk.map(str::to_string),
+            stream: Default::default(),
+            bytes: None,
+            restarted: false,
+        })
+        .unwrap();
+    }
+
+    let mut stmt = db.conn.prepare("SELECT disk FROM segments ORDER BY absolute_index;").unwrap();
+    let disks: Vec<Option<String>> = stmt.query_map([], |r| r.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(disks, vec![Some("/mnt/nvr1".to_string()), Some("/mnt/nvr2".to_string()), None]);
+    let on_disk = db.segments_between(camera_id, 1_000, 1_060).unwrap();
+    assert!(on_disk[0].rel_path.starts_with("/mnt/nvr1/"), "absolute outside the recording root");
+}
@@ -0,0 +1,108 @@
+use proptest::prelude::*;
+
+use dashcam_rs::config::AppConfig;
+use dashcam_rs::db::db::DashcamDb;
+use dashcam_rs::pipeline_sinks::segment_counters::SegmentCounters;
+
+const SCHEMA_SQL: &str = include_str!("../migrations/0001_init.sql");
+
+/// In-memory DB with one camera and a single dashcamts sink (sink_id 0).
+fn ring_db() -> (DashcamDb, i64) {
+    let cfg: AppConfig = toml::from_str(
+        "[[cameras]]\nkey = \"cam1\"\nsource = { kind = \"v4l2\", device = \"/dev/video0\" }\n\
+         sinks = [{ kind = \"dashcamts\", sink_id = 0 }]\n",
+    )
+    .unwrap();
+    let db = DashcamDb::open(":memory:").unwrap();
+    db.run_schema(SCHEMA_SQL).unwrap();
+    db.ensure_cameras_initialized(&cfg.cameras).unwrap();
+    let camera_id = db.get_camera_id_by_key("cam1").unwrap();
+    (db, camera_id)
+}
+
+fn counters(db: &DashcamDb, camera_id: i64) -> SegmentCounters {
+    SegmentCounters {
+        index: db.get_segment_index(camera_id, 0).unwrap(),
+        generation: db.get_segment_generation(camera_id, 0).unwrap(),
+        absolute: db.get_absolute_segments(camera_id, 0).unwrap(),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum RingOp {
+    /// `increment_segment_index`
+    Increment,
+    /// `update_segment_counters` this many segments ahead (less than a full ring)
+    Advance(i64),
+}
+
+fn ring_ops(max_segments: i64) -> impl Strategy<Value = Vec<RingOp>> {
+    let op = prop_oneof![Just(RingOp::Increment), (1..max_segments).prop_map(RingOp::Advance)];
+    prop::collection::vec(op, 0..200)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn ring_counters_stay_consistent((max_segments, ops) in (2i64..50).prop_flat_map(|max| (Just(max), ring_ops(max)))) {
+        let (db, camera_id) = ring_db();
+        let mut model = SegmentCounters::default();
+
+        for op in ops {
+            let before = counters(&db, camera_id);
+            match op {
+                RingOp::Increment => {
+                    let next = db.increment_segment_index(camera_id, 0, max_segments).unwrap();
+                    prop_assert_eq!(next, (before.index + 1) % max_segments);
+                    model = model.advanced(next, max_segments);
+                }
+                RingOp::Advance(step) => {
+                    let next = (before.index + step) % max_segments;
+                    db.update_segment_counters(camera_id, 0, next, max_segments).unwrap();
+                    model = model.advanced(next, max_segments);
+                }
+            }
+            let after = counters(&db, camera_id);
+            prop_assert!((0..max_segments).contains(&after.index));
+            prop_assert!(after.absolute > before.absolute, "every op writes at least one segment");
+            prop_assert_eq!(after.generation, after.absolute / max_segments);
+            prop_assert_eq!(after.index, after.absolute % max_segments);
+            prop_assert_eq!(after, model, "DB and SegmentCounters::advanced agree");
+        }
+    }
+
+    #[test]
+    fn clamping_keeps_the_index_in_the_smaller_ring(
+        max_segments in 2i64..50,
+        steps in prop::collection::vec(1i64..10, 0..40),
+        shrink_to in 1i64..50,
+    ) {
+        let (db, camera_id) = ring_db();
+        let mut last_absolute = 0;
+        let mut max = max_segments;
+        for (i, step) in steps.iter().enumerate() {
+            // shrink the ring half way through, like a config change between runs
+            if i == steps.len() / 2 && shrink_to < max {
+                db.clamp_segment_index(camera_id, 0, shrink_to).unwrap();
+                max = shrink_to;
+                prop_assert!(counters(&db, camera_id).index < max);
+            }
+            for _ in 0..*step {
+                db.increment_segment_index(camera_id, 0, max).unwrap();
+            }
+            let now = counters(&db, camera_id);
+            prop_assert!((0..max).contains(&now.index));
+            prop_assert_eq!(now.absolute, last_absolute + step);
+            last_absolute = now.absolute;
+        }
+    }
+
+    #[test]
+    fn advancing_is_the_same_as_stepping_one_by_one(start in 0i64..100, step in 0i64..100, max_segments in 100i64..200) {
+        let start = SegmentCounters { index: start, generation: 0, absolute: start };
+        let jumped = start.advanced((start.index + step) % max_segments, max_segments);
+        let stepped = (0..step).fold(start, |c, _| c.advanced((c.index + 1) % max_segments, max_segments));
+        prop_assert_eq!(jumped, stepped);
+    }
+}